@@ -0,0 +1,359 @@
+//! Tracking of the guest's memory mappings
+//!
+//! [`AddressSpace`] is built from the `mmap()` and `munmap()` callbacks, and
+//! can answer what's mapped at a given address. Code executing from mappings
+//! which are anonymous and executable was generated at runtime (eg. by a JIT
+//! in the guest), these mappings are also tracked in a separate sub-map so
+//! analyses can tell interpreter/JIT frames apart from static code.
+//!
+//! The jitter keeps the same address space, and tags the exec hooks of
+//! dynamic code as it lifts them, so they arrive through
+//! [`crate::Cannoli::exec_dynamic`] rather than [`crate::Cannoli::exec`],
+//! without a race with the mapping events processed on other threads. With
+//! [`crate::Cannoli::CAPTURE_DYNAMIC_CODE`] they come with the generated
+//! code, see [`crate::code`].
+//!
+//! Mappings are only reported to us as they are created, so code generated
+//! in memory which is later `mprotect()`ed to be executable is not detected
+//! as dynamic code.
+//!
+//! Anonymous mappings which the jitter recognized as heap growth (see
+//! [`crate::heap`]) are marked with their [`HeapKind`] once the matching
+//! [`crate::Cannoli::heap`] callback is applied with [`AddressSpace::heap`].
+
+use std::sync::Arc;
+use std::collections::BTreeMap;
+use serde::Serialize;
+use crate::heap::HeapEvent;
+
+/// What a heap mapping is used for
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HeapKind {
+    /// Memory between the start of the heap and the program break
+    Brk,
+
+    /// An allocator arena
+    Arena,
+}
+
+/// A single mapping in the guest's address space
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mapping {
+    /// Base address of the mapping
+    pub base: u64,
+
+    /// Length of the mapping in bytes
+    pub len: u64,
+
+    /// Readable
+    pub read: bool,
+
+    /// Writable
+    pub write: bool,
+
+    /// Executable
+    pub exec: bool,
+
+    /// Anonymous (not backed by a file)
+    pub anon: bool,
+
+    /// Path of the file backing this mapping, empty for anonymous mappings
+    pub path: Arc<str>,
+
+    /// Offset into the file that `base` maps
+    pub offset: u64,
+
+    /// Set if the mapping was recognized as part of the heap
+    pub heap: Option<HeapKind>,
+}
+
+impl Mapping {
+    /// Address one past the end of the mapping
+    pub fn end(&self) -> u64 {
+        self.base.saturating_add(self.len)
+    }
+
+    /// Check if this mapping contains `addr`
+    pub fn contains(&self, addr: u64) -> bool {
+        addr >= self.base && addr < self.end()
+    }
+
+    /// Check if this mapping holds code generated at runtime
+    pub fn is_dynamic_code(&self) -> bool {
+        self.anon && self.exec
+    }
+}
+
+/// Where code at a given PC came from
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CodeOrigin {
+    /// Code from a file mapping, eg. the main binary or a shared library
+    Static(Arc<str>),
+
+    /// Code from an anonymous executable mapping, generated at runtime
+    Dynamic,
+
+    /// No mapping is known for the PC
+    Unknown,
+}
+
+/// The set of mappings in a guest process
+#[derive(Clone, Debug, Default)]
+pub struct AddressSpace {
+    /// All mappings, keyed by base address
+    mappings: BTreeMap<u64, Mapping>,
+
+    /// Sub-map of `mappings` which hold dynamically generated code, keyed by
+    /// base address
+    dynamic: BTreeMap<u64, Mapping>,
+
+    /// Program break, as of the last [`HeapEvent::Brk`]
+    brk: Option<u64>,
+
+    /// Path of the first file mapped executable, the main binary
+    main: Option<Arc<str>>,
+}
+
+impl AddressSpace {
+    /// Create a new, empty, address space
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a mapping, as reported by the [`crate::Cannoli::mmap`] callback.
+    /// Any existing mappings in the range are replaced
+    #[allow(clippy::too_many_arguments)]
+    pub fn mmap(&mut self, base: u64, len: u64, anon: bool, read: bool,
+            write: bool, exec: bool, path: &str, offset: u64) {
+        // Mapping over existing memory replaces it
+        self.munmap(base, len);
+
+        let mapping = Mapping {
+            path: path.into(),
+            heap: None,
+            base, len, read, write, exec, anon, offset,
+        };
+
+        if mapping.is_dynamic_code() {
+            self.dynamic.insert(base, mapping.clone());
+        }
+        if exec && !anon && self.main.is_none() {
+            self.main = Some(mapping.path.clone());
+        }
+        self.mappings.insert(base, mapping);
+    }
+
+    /// Remove a range of memory, as reported by the [`crate::Cannoli::munmap`]
+    /// callback. Mappings which partially overlap the range are split
+    pub fn munmap(&mut self, base: u64, len: u64) {
+        Self::remove_range(&mut self.mappings, base, len);
+        Self::remove_range(&mut self.dynamic,  base, len);
+    }
+
+    /// Change the protection of a range of memory, as reported by the
+    /// [`crate::Cannoli::mprotect`] callback. Mappings which partially
+    /// overlap the range are split, and memory which isn't mapped stays
+    /// unmapped
+    pub fn mprotect(&mut self, base: u64, len: u64, read: bool, write: bool,
+            exec: bool) {
+        let end = base.saturating_add(len);
+        let changed = self.mappings.range(..end).rev()
+            .take_while(|(_, x)| x.end() > base)
+            .map(|(_, x)| {
+                let start = x.base.max(base);
+                let mut mapping = x.clone();
+                if !mapping.anon {
+                    mapping.offset += start - x.base;
+                }
+                mapping.base  = start;
+                mapping.len   = x.end().min(end) - start;
+                mapping.read  = read;
+                mapping.write = write;
+                mapping.exec  = exec;
+                mapping
+            })
+            .collect::<Vec<_>>();
+
+        self.munmap(base, len);
+        for mapping in changed {
+            if mapping.is_dynamic_code() {
+                self.dynamic.insert(mapping.base, mapping.clone());
+            }
+            self.mappings.insert(mapping.base, mapping);
+        }
+    }
+
+    /// Remove `base..base + len` from `map`, splitting mappings which
+    /// straddle the edges of the range
+    fn remove_range(map: &mut BTreeMap<u64, Mapping>, base: u64, len: u64) {
+        let end = base.saturating_add(len);
+
+        // Find all mappings which overlap the range. Only the mapping starting
+        // before `base` can overlap from below as mappings don't overlap
+        let overlapping = map.range(..end).rev()
+            .take_while(|(_, x)| x.end() > base)
+            .map(|(&x, _)| x)
+            .collect::<Vec<_>>();
+
+        for key in overlapping {
+            let mapping = map.remove(&key).unwrap();
+
+            // Keep the part below the range
+            if mapping.base < base {
+                let mut below = mapping.clone();
+                below.len = base - mapping.base;
+                map.insert(below.base, below);
+            }
+
+            // Keep the part above the range
+            if mapping.end() > end {
+                let mut above = mapping.clone();
+                above.base = end;
+                above.len  = mapping.end() - end;
+                if !above.anon {
+                    above.offset += end - mapping.base;
+                }
+                map.insert(above.base, above);
+            }
+        }
+    }
+
+    /// Get the mapping containing `addr`
+    pub fn lookup(&self, addr: u64) -> Option<&Mapping> {
+        self.mappings.range(..=addr).next_back()
+            .map(|(_, x)| x)
+            .filter(|x| x.contains(addr))
+    }
+
+    /// Get the path of the file mapped at `addr` and the offset of `addr`
+    /// from the base of that file's image. The base is where offset 0 of the
+    /// file is mapped, so for PIEs and shared libraries the offset is the
+    /// same in every run regardless of ASLR
+    pub fn module_offset(&self, addr: u64) -> Option<(Arc<str>, u64)> {
+        let mapping = self.lookup(addr).filter(|x| !x.anon)?;
+
+        // Use the mapping of the start of the file if it's still there,
+        // otherwise assume the file is mapped contiguously
+        let base = self.mappings.values()
+            .find(|x| x.path == mapping.path && x.offset == 0)
+            .filter(|x| x.base <= addr)
+            .map_or(mapping.base.wrapping_sub(mapping.offset), |x| x.base);
+
+        Some((mapping.path.clone(), addr - base))
+    }
+
+    /// Mark the mappings changed by a heap event, as reported by the
+    /// [`crate::Cannoli::heap`] callback after the mapping itself
+    pub fn heap(&mut self, event: &HeapEvent) {
+        let (base, len, kind) = match *event {
+            HeapEvent::Brk { old, new } => {
+                self.brk = Some(new);
+                (old, new.saturating_sub(old), HeapKind::Brk)
+            }
+            HeapEvent::Arena { base, len } => (base, len, HeapKind::Arena),
+        };
+
+        let end = base.saturating_add(len);
+        for mapping in self.mappings.range_mut(..end).rev()
+                .map(|(_, x)| x)
+                .take_while(|x| x.end() > base) {
+            mapping.heap = Some(kind);
+        }
+    }
+
+    /// Get the current program break, if the heap grew at all
+    pub fn program_break(&self) -> Option<u64> {
+        self.brk
+    }
+
+    /// Get the kind of heap memory `addr` is in, `None` if it's not in the
+    /// heap
+    pub fn heap_kind(&self, addr: u64) -> Option<HeapKind> {
+        self.lookup(addr).and_then(|x| x.heap)
+    }
+
+    /// Get the mappings which are part of the heap, sorted by base address
+    pub fn heap_mappings(&self) -> impl Iterator<Item = &Mapping> {
+        self.mappings.values().filter(|x| x.heap.is_some())
+    }
+
+    /// Get all mappings, sorted by base address
+    pub fn mappings(&self) -> impl Iterator<Item = &Mapping> {
+        self.mappings.values()
+    }
+
+    /// Get the mappings which hold dynamically generated code, sorted by base
+    /// address
+    pub fn dynamic_code(&self) -> impl Iterator<Item = &Mapping> {
+        self.dynamic.values()
+    }
+
+    /// Check if `pc` is in dynamically generated code
+    pub fn is_dynamic_code(&self, pc: u64) -> bool {
+        self.dynamic.range(..=pc).next_back()
+            .is_some_and(|(_, x)| x.contains(pc))
+    }
+
+    /// Determine where the code at `pc` came from
+    pub fn classify(&self, pc: u64) -> CodeOrigin {
+        match self.lookup(pc) {
+            Some(x) if x.is_dynamic_code() => CodeOrigin::Dynamic,
+            Some(x) if !x.anon => CodeOrigin::Static(x.path.clone()),
+            _ => CodeOrigin::Unknown,
+        }
+    }
+
+    /// Get the path of the main binary. QEMU maps the main binary before
+    /// the dynamic loader, so this is the first file which was mapped
+    /// executable
+    pub fn main_module(&self) -> Option<&Arc<str>> {
+        self.main.as_ref()
+    }
+
+    /// Guess if the process is statically linked: all the code mapped from
+    /// files comes from a single file. The dynamic loader is mapped along
+    /// with the main binary, so this is reliable as soon as the main binary
+    /// is mapped. Statically linked targets have no libraries to take symbols
+    /// from, see [`crate::symbols::signatures`]
+    pub fn is_statically_linked(&self) -> bool {
+        let mut paths = self.mappings.values()
+            .filter(|x| x.exec && !x.anon)
+            .map(|x| &x.path);
+        paths.next().is_some_and(|first| paths.all(|x| x == first))
+    }
+}
+
+#[test]
+fn split_and_classify() {
+    let mut space = AddressSpace::new();
+    space.mmap(0x10000, 0x3000, false, true, false, true, "/bin/app", 0);
+    space.mmap(0x20000, 0x1000, true, true, true, true, "", 0);
+
+    // Punch a hole in the middle of the file mapping
+    space.munmap(0x11000, 0x1000);
+    assert_eq!(space.lookup(0x11800), None);
+    assert_eq!(space.lookup(0x12800).map(|x| (x.base, x.offset)),
+        Some((0x12000, 0x2000)));
+
+    assert_eq!(space.classify(0x10000), CodeOrigin::Static("/bin/app".into()));
+    assert_eq!(space.classify(0x20010), CodeOrigin::Dynamic);
+    assert_eq!(space.classify(0x30000), CodeOrigin::Unknown);
+    assert_eq!(space.dynamic_code().count(), 1);
+    assert!(space.is_statically_linked());
+    assert_eq!(space.main_module().map(|x| &**x), Some("/bin/app"));
+
+    // Heap growth is an anonymous mapping followed by the heap event
+    space.mmap(0x14000, 0x2000, true, true, true, false, "", 0);
+    space.heap(&HeapEvent::Brk { old: 0x14000, new: 0x16000 });
+    assert_eq!(space.heap_kind(0x15000), Some(HeapKind::Brk));
+    assert_eq!(space.heap_kind(0x20000), None);
+    assert_eq!(space.program_break(), Some(0x16000));
+
+    // JIT-ed code made executable in the middle of a heap mapping
+    space.mprotect(0x15000, 0x800, true, false, true);
+    assert_eq!(space.classify(0x15400), CodeOrigin::Dynamic);
+    assert_eq!(space.lookup(0x15800).map(|x| (x.base, x.exec)),
+        Some((0x15800, false)));
+    assert_eq!(space.heap_kind(0x15400), Some(HeapKind::Brk));
+}
@@ -0,0 +1,504 @@
+//! Control flow graphs and dominators of functions, from the control flow
+//! observed in traces
+//!
+//! A [`CfgBuilder`] records which instruction followed which in a thread,
+//! either from every executed PC ([`CfgBuilder::observe`]) or from the
+//! edges of [`crate::control::HookKind::Edge`] ([`CfgBuilder::edge`]), and
+//! which way conditional branches went. [`CfgBuilder::build`] then cuts the
+//! instructions of one function into basic blocks, connects them with the
+//! edges which were taken, and computes the dominator tree of the blocks
+//! reachable from the entry (Cooper, Harvey, Kennedy, "A Simple, Fast
+//! Dominance Algorithm").
+//!
+//! The graph only holds what executed, so it's the CFG of the exercised
+//! paths. That's what [`Cfg::cyclomatic`] measures, and the branches which
+//! only ever went one way are in [`Cfg::unreached`], which is where the
+//! paths the trace missed start.
+//!
+//! Functions are given as an address range, eg. from
+//! [`crate::analysis::functions`] or a symbol table. Control leaving the
+//! range is a call (or a tail call or a return), and a call landing back in
+//! the range just after where it left continues the block it left from, as
+//! calls don't end blocks. Recursion looks like a loop back to the entry.
+
+use std::io::Write;
+use std::ops::Range;
+use std::collections::{BTreeMap, BTreeSet};
+use crate::analysis::functions::FunctionInference;
+use crate::symbols::overlay::Region;
+use crate::event::Event;
+
+/// Maximum distance between two PCs for them to be considered sequential
+/// execution rather than a transfer of control. This is the largest
+/// instruction size we expect to see
+const MAX_INSN_LEN: u64 = 16;
+
+/// Maximum distance between the instruction which transferred control and
+/// the address that a return lands on, see
+/// [`crate::analysis::functions`]
+const MAX_RETURN_GAP: u64 = 16;
+
+/// Records the control flow of a thread to build [`Cfg`]s from
+///
+/// Use a separate instance per thread and [`CfgBuilder::merge`] them
+/// together, and feed an instance either PCs or edges, not both.
+#[derive(Default)]
+pub struct CfgBuilder {
+    /// Last PC given to [`CfgBuilder::observe`]
+    prev: Option<u64>,
+
+    /// Last target given to [`CfgBuilder::edge`]
+    target: Option<u64>,
+
+    /// Every instruction which was seen
+    insns: BTreeSet<u64>,
+
+    /// Transfers between instructions, with whether control went straight
+    /// from one to the other without branching
+    edges: BTreeMap<(u64, u64), bool>,
+
+    /// Directions conditional branches went, keyed by their PC, as whether
+    /// they were seen not taken and taken
+    branches: BTreeMap<u64, [bool; 2]>,
+}
+
+impl CfgBuilder {
+    /// Create an empty builder
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Observe the next executed PC
+    pub fn observe(&mut self, pc: u64) {
+        self.insns.insert(pc);
+        if let Some(prev) = self.prev.replace(pc) {
+            // Sequential if `0 < pc - prev <= max`
+            let straight =
+                pc.wrapping_sub(prev).wrapping_sub(1) < MAX_INSN_LEN;
+            self.edges.insert((prev, pc), straight);
+        }
+    }
+
+    /// Observe that the conditional branch at `pc` was `taken` or not
+    pub fn branch(&mut self, pc: u64, taken: bool) {
+        self.branches.entry(pc).or_default()[taken as usize] = true;
+    }
+
+    /// Observe an event of the thread, [`Event::Exec`] and [`Event::Regs`]
+    /// as executed PCs, [`Event::Branch`] as an executed conditional branch
+    pub fn event(&mut self, event: &Event) {
+        match *event {
+            Event::Exec { pc } | Event::Regs { pc, .. } => self.observe(pc),
+            Event::Branch { pc, taken, .. } => {
+                self.observe(pc);
+                self.branch(pc, taken);
+            }
+            _ => {}
+        }
+    }
+
+    /// Observe the next edge of the thread, as reported to
+    /// [`crate::Cannoli::edge`]. Control went straight from the previous
+    /// target to `pc`, as edge sources end blocks
+    pub fn edge(&mut self, pc: u64, target: u64, taken: bool) {
+        self.insns.insert(pc);
+        self.insns.insert(target);
+        if let Some(prev) = self.target.replace(target) {
+            if prev < pc {
+                self.edges.insert((prev, pc), true);
+            }
+        }
+        self.edges.entry((pc, target)).or_insert(false);
+        self.branch(pc, taken);
+    }
+
+    /// Merge the control flow recorded by `other` into `self`
+    pub fn merge(&mut self, other: &CfgBuilder) {
+        self.insns.extend(&other.insns);
+        for (&edge, &straight) in &other.edges {
+            *self.edges.entry(edge).or_default() |= straight;
+        }
+        for (&pc, seen) in &other.branches {
+            let ours = self.branches.entry(pc).or_default();
+            ours[0] |= seen[0];
+            ours[1] |= seen[1];
+        }
+    }
+
+    /// Build the CFG of the function at `entry` spanning `range`, `None` if
+    /// its entry never executed
+    pub fn build(&self, entry: u64, range: Range<u64>) -> Option<Cfg> {
+        if !range.contains(&entry) || !self.insns.contains(&entry) {
+            return None;
+        }
+
+        // Successors within the function, control leaving it is a call
+        let mut succs: BTreeMap<u64, Vec<(u64, bool)>> = BTreeMap::new();
+        let mut preds: BTreeMap<u64, usize> = BTreeMap::new();
+        let mut calls   = BTreeSet::new();
+        let mut entered = BTreeSet::new();
+        for (&(from, to), &straight) in &self.edges {
+            match (range.contains(&from), range.contains(&to)) {
+                (true, true) => {
+                    succs.entry(from).or_default().push((to, straight));
+                    *preds.entry(to).or_default() += 1;
+                }
+                (true, false) => { calls.insert(from); }
+                (false, true) => { entered.insert(to); }
+                _ => {}
+            }
+        }
+
+        // A call returns to the next instruction control entered at
+        for &site in &calls {
+            let ret = self.insns.range(site + 1..range.end).next()
+                .filter(|&&x| x - site <= MAX_RETURN_GAP &&
+                    entered.contains(&x));
+            if let Some(&ret) = ret {
+                let succ = succs.entry(site).or_default();
+                if !succ.iter().any(|x| x.0 == ret) {
+                    succ.push((ret, true));
+                    *preds.entry(ret).or_default() += 1;
+                }
+            }
+        }
+
+        // Blocks start at the entry, at targets of branches and joins, and
+        // after conditional branches
+        let mut leaders = BTreeSet::from([entry]);
+        for (&from, succ) in &succs {
+            let branches =
+                succ.len() > 1 || self.branches.contains_key(&from);
+            for &(to, straight) in succ {
+                if branches || !straight || preds[&to] > 1 {
+                    leaders.insert(to);
+                }
+            }
+        }
+
+        let mut blocks = BTreeMap::new();
+        let mut todo   = vec![entry];
+        while let Some(start) = todo.pop() {
+            if blocks.contains_key(&start) {
+                continue;
+            }
+            let mut insns = vec![start];
+            let succ = loop {
+                let last = insns[insns.len() - 1];
+                let succ = succs.get(&last).map_or(&[][..], |x| &x[..]);
+                match succ {
+                    [(next, true)] if !leaders.contains(next) =>
+                        insns.push(*next),
+                    _ => break succ,
+                }
+            };
+            let succs = succ.iter().map(|x| x.0).collect::<Vec<_>>();
+            todo.extend(&succs);
+            let last = insns[insns.len() - 1];
+            blocks.insert(start, Block {
+                start,
+                last,
+                insns,
+                succs,
+                branch: self.branches.get(&last).copied(),
+            });
+        }
+
+        let idom = dominators(entry, &blocks);
+        Some(Cfg { entry, blocks, idom })
+    }
+
+    /// Build the CFG of every function found by `functions`
+    pub fn build_inferred(&self, functions: &FunctionInference) -> Vec<Cfg> {
+        functions.functions().filter_map(|x| {
+            self.build(x.entry, x.entry..x.last_pc.saturating_add(1))
+        }).collect()
+    }
+}
+
+/// Compute the immediate dominator of every block reachable from `entry`,
+/// the entry being its own
+fn dominators(entry: u64, blocks: &BTreeMap<u64, Block>)
+        -> BTreeMap<u64, u64> {
+    // Reverse postorder of the blocks, with an explicit stack
+    let mut order   = Vec::new();
+    let mut visited = BTreeSet::from([entry]);
+    let mut stack   = vec![(entry, 0)];
+    while let Some((block, next)) = stack.last_mut() {
+        match blocks[block].succs.get(*next) {
+            Some(&succ) => {
+                *next += 1;
+                if visited.insert(succ) {
+                    stack.push((succ, 0));
+                }
+            }
+            None => {
+                order.push(*block);
+                stack.pop();
+            }
+        }
+    }
+    order.reverse();
+    let index = order.iter().enumerate().map(|(i, &x)| (x, i))
+        .collect::<BTreeMap<_, _>>();
+
+    let mut preds: BTreeMap<u64, Vec<u64>> = BTreeMap::new();
+    for block in blocks.values() {
+        for &succ in &block.succs {
+            preds.entry(succ).or_default().push(block.start);
+        }
+    }
+
+    let mut idom = BTreeMap::from([(entry, entry)]);
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &block in &order[1..] {
+            let mut new = None;
+            for &pred in preds.get(&block).into_iter().flatten() {
+                if !idom.contains_key(&pred) {
+                    continue;
+                }
+                new = Some(match new {
+                    None => pred,
+                    Some(mut a) => {
+                        // Walk both up the tree until they meet
+                        let mut b = pred;
+                        while a != b {
+                            while index[&a] > index[&b] { a = idom[&a]; }
+                            while index[&b] > index[&a] { b = idom[&b]; }
+                        }
+                        a
+                    }
+                });
+            }
+            if let Some(new) = new {
+                if idom.insert(block, new) != Some(new) {
+                    changed = true;
+                }
+            }
+        }
+    }
+    idom
+}
+
+/// A basic block of a [`Cfg`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Block {
+    /// PC of the first instruction
+    pub start: u64,
+
+    /// PC of the last instruction
+    pub last: u64,
+
+    /// PCs of the instructions, in the order they execute in
+    pub insns: Vec<u64>,
+
+    /// Start of the blocks control went to from this one
+    pub succs: Vec<u64>,
+
+    /// If the block ends with a conditional branch, whether it was seen not
+    /// taken and taken
+    pub branch: Option<[bool; 2]>,
+}
+
+/// A direction of a conditional branch which was never taken
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UnreachedEdge {
+    /// Start of the block ending with the branch
+    pub block: u64,
+
+    /// PC of the branch
+    pub pc: u64,
+
+    /// Whether the branch was never taken, rather than never not taken
+    pub taken: bool,
+}
+
+/// The CFG of the exercised paths of a function, see the module
+/// documentation
+#[derive(Clone, Debug)]
+pub struct Cfg {
+    /// Entry point of the function, and start of its first block
+    entry: u64,
+
+    /// Blocks reachable from the entry, keyed by their start
+    blocks: BTreeMap<u64, Block>,
+
+    /// Immediate dominator of every block, the entry being its own
+    idom: BTreeMap<u64, u64>,
+}
+
+impl Cfg {
+    /// Get the entry point of the function
+    pub fn entry(&self) -> u64 {
+        self.entry
+    }
+
+    /// Get the blocks, sorted by address
+    pub fn blocks(&self) -> impl Iterator<Item = &Block> {
+        self.blocks.values()
+    }
+
+    /// Get the block starting at `start`
+    pub fn block(&self, start: u64) -> Option<&Block> {
+        self.blocks.get(&start)
+    }
+
+    /// Get the number of edges between blocks
+    pub fn edges(&self) -> usize {
+        self.blocks.values().map(|x| x.succs.len()).sum()
+    }
+
+    /// Get the immediate dominator of the block starting at `start`, `None`
+    /// for the entry
+    pub fn idom(&self, start: u64) -> Option<u64> {
+        self.idom.get(&start).copied().filter(|_| start != self.entry)
+    }
+
+    /// Check if every path from the entry to the block `b` goes through the
+    /// block `a`
+    pub fn dominates(&self, a: u64, mut b: u64) -> bool {
+        loop {
+            if a == b {
+                return true;
+            }
+            match self.idom(b) {
+                Some(x) => b = x,
+                None => return false,
+            }
+        }
+    }
+
+    /// Get the edges going back to a block dominating their source, which
+    /// is what loops are made of
+    pub fn back_edges(&self) -> Vec<(u64, u64)> {
+        self.blocks.values().flat_map(|block| {
+            block.succs.iter().filter(|&&x| self.dominates(x, block.start))
+                .map(|&x| (block.start, x))
+        }).collect()
+    }
+
+    /// Get the cyclomatic complexity of the exercised paths, the number of
+    /// edges minus the number of blocks plus two
+    pub fn cyclomatic(&self) -> usize {
+        (self.edges() + 2).saturating_sub(self.blocks.len())
+    }
+
+    /// Get the directions of conditional branches which were never taken
+    pub fn unreached(&self) -> Vec<UnreachedEdge> {
+        self.blocks.values().flat_map(|block| {
+            let seen = block.branch.unwrap_or([true; 2]);
+            [false, true].into_iter().filter(move |&x| !seen[x as usize])
+                .map(|taken| UnreachedEdge {
+                    block: block.start,
+                    pc:    block.last,
+                    taken,
+                })
+        }).collect()
+    }
+
+    /// Write the CFG in the Graphviz DOT format, with the dominator tree as
+    /// dashed edges and unreached branch directions as dotted edges
+    pub fn write_dot(&self, out: impl Write) -> std::io::Result<()> {
+        self.write_dot_overlay(out, |_| None)
+    }
+
+    /// Write the CFG like [`Cfg::write_dot`], with every block labelled and
+    /// filled with the color of its region of an overlay, as found by
+    /// `region` from the start of the block (eg. with
+    /// [`crate::symbols::Overlay::resolve`])
+    pub fn write_dot_overlay<'a>(&self, mut out: impl Write,
+            region: impl Fn(u64) -> Option<&'a Region>)
+            -> std::io::Result<()> {
+        writeln!(out, "digraph sub_{:x} {{", self.entry)?;
+        writeln!(out, "    node [shape=box, fontname=monospace];")?;
+        for block in self.blocks.values() {
+            let range = format!("{:#x}..={:#x}", block.start, block.last);
+            match region(block.start) {
+                Some(Region { label, color, .. }) => {
+                    let fill = color.as_ref().map_or(String::new(),
+                        |x| format!(", style=filled, fillcolor=\"{x}\""));
+                    writeln!(out, "    b{:x} [label=\"{range}\\n{label}\"\
+                        {fill}];", block.start)?;
+                }
+                None => writeln!(out, "    b{:x} [label=\"{range}\"];",
+                    block.start)?,
+            }
+            for succ in &block.succs {
+                writeln!(out, "    b{:x} -> b{succ:x};", block.start)?;
+            }
+            if let Some(idom) = self.idom(block.start) {
+                writeln!(out, "    b{idom:x} -> b{:x} [style=dashed, \
+                    color=gray, constraint=false];", block.start)?;
+            }
+        }
+        for edge in self.unreached() {
+            let label = if edge.taken { "taken" } else { "not taken" };
+            writeln!(out, "    u{:x}_{} [label=\"?\", shape=plaintext];",
+                edge.pc, edge.taken as u8)?;
+            writeln!(out, "    b{:x} -> u{:x}_{} [style=dotted, \
+                label=\"{label}\"];", edge.block, edge.pc, edge.taken as u8)?;
+        }
+        writeln!(out, "}}")
+    }
+}
+
+#[test]
+fn build_cfg() {
+    let mut builder = CfgBuilder::new();
+
+    // `main` calls a function at 0x1000 twice, which branches at 0x1004 to
+    // either side of an if/else, and joins at 0x1014
+    for pc in [0x100, 0x104, 0x1000, 0x1004, 0x1010, 0x1014, 0x108, 0x1000,
+               0x1004, 0x1008, 0x100c, 0x1014, 0x10c] {
+        builder.observe(pc);
+    }
+    builder.branch(0x1004, true);
+    builder.branch(0x1004, false);
+
+    let cfg = builder.build(0x1000, 0x1000..0x1018).unwrap();
+    let starts = cfg.blocks().map(|x| (x.start, x.last)).collect::<Vec<_>>();
+    assert_eq!(starts, [(0x1000, 0x1004), (0x1008, 0x100c), (0x1010, 0x1010),
+        (0x1014, 0x1014)]);
+    assert_eq!(cfg.idom(0x1014), Some(0x1000));
+    assert_eq!(cfg.idom(0x1000), None);
+    assert!(cfg.dominates(0x1000, 0x1010) && !cfg.dominates(0x1008, 0x1014));
+    assert_eq!(cfg.cyclomatic(), 2);
+    assert!(cfg.unreached().is_empty() && cfg.back_edges().is_empty());
+
+    // Calls don't end blocks
+    let main = builder.build(0x100, 0x100..0x110).unwrap();
+    assert_eq!(main.blocks().map(|x| (x.start, x.last)).collect::<Vec<_>>(),
+        [(0x100, 0x10c)]);
+
+    // A loop at 0x2000 whose exit at 0x2008 was never seen
+    let mut builder = CfgBuilder::new();
+    for pc in [0x2000, 0x2004, 0x2008, 0x2004, 0x2008] {
+        builder.observe(pc);
+    }
+    builder.branch(0x2008, true);
+    let cfg = builder.build(0x2000, 0x2000..0x2010).unwrap();
+    assert_eq!(cfg.back_edges(), [(0x2004, 0x2004)]);
+    assert_eq!(cfg.unreached(), [UnreachedEdge {
+        block: 0x2004, pc: 0x2008, taken: false }]);
+    let mut dot = Vec::new();
+    cfg.write_dot(&mut dot).unwrap();
+    assert!(String::from_utf8(dot).unwrap().contains("b2000 -> b2004;"));
+
+    // Blocks in a region of an overlay are labelled with it
+    let region = Region {
+        module: None,
+        start:  0x2000,
+        end:    0x2100,
+        label:  "main".into(),
+        color:  Some("red".into()),
+    };
+    let mut dot = Vec::new();
+    cfg.write_dot_overlay(&mut dot, |x| Some(&region)
+        .filter(|r| (r.start..r.end).contains(&x))).unwrap();
+    assert!(String::from_utf8(dot).unwrap().contains(
+        "b2000 [label=\"0x2000..=0x2000\\nmain\", style=filled, \
+        fillcolor=\"red\"];"));
+    assert!(builder.build(0x3000, 0x3000..0x3010).is_none());
+}
@@ -0,0 +1,265 @@
+//! Value profiles of comparison instructions
+//!
+//! Comparisons against magic values (file signatures, command names,
+//! checksums) are what random mutation has the hardest time getting past.
+//! With the operands of every comparison in the trace, as
+//! [`crate::event::Event::Cmp`] / [`crate::Cannoli::cmp`], a [`ValueProfile`]
+//! collects for every comparison which values were compared, and which
+//! operand stayed constant while the other one varied: the value the input
+//! is compared against. The jitter only sends comparisons with
+//! [`crate::control::Filters::cmp`] set.
+//!
+//! This is the information input-to-state fuzzing (RedQueen, AFL++'s
+//! cmplog) works from. [`ValueProfile::replacements`] looks for the varying
+//! operands in an input, in either byte order, and suggests replacing them
+//! with the constant they were compared against.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+/// Default number of distinct values kept per operand
+const MAX_VALUES: usize = 32;
+
+/// Values seen for one operand of a comparison
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Operand {
+    /// Distinct values, at most [`ValueProfile::max_values`] of them
+    pub values: BTreeSet<u64>,
+
+    /// Set if there were more distinct values than are kept
+    pub overflowed: bool,
+}
+
+impl Operand {
+    /// Record `val`
+    fn add(&mut self, val: u64, max: usize) {
+        if self.values.len() < max || self.values.contains(&val) {
+            self.values.insert(val);
+        } else {
+            self.overflowed = true;
+        }
+    }
+
+    /// Check if more than one value was seen
+    pub fn varies(&self) -> bool {
+        self.overflowed || self.values.len() > 1
+    }
+
+    /// Get the only value seen, if the operand is constant
+    pub fn constant(&self) -> Option<u64> {
+        match (self.varies(), self.values.first()) {
+            (false, Some(val)) => Some(*val),
+            _ => None,
+        }
+    }
+}
+
+/// Profile of a single comparison instruction
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CmpSite {
+    /// PC of the comparison
+    pub pc: u64,
+
+    /// Size of the operands in bytes, the largest seen
+    pub sz: u8,
+
+    /// Number of times the comparison executed
+    pub execs: u64,
+
+    /// Number of times the operands were equal
+    pub equal: u64,
+
+    /// Left-hand operand
+    pub lhs: Operand,
+
+    /// Right-hand operand
+    pub rhs: Operand,
+}
+
+impl CmpSite {
+    /// Get the value the other operand is compared against: the operand
+    /// which stayed constant while the other one varied, or the right-hand
+    /// one (where architectures put immediates) if neither varied. `None`
+    /// if both varied
+    pub fn constant(&self) -> Option<u64> {
+        match (self.lhs.varies(), self.rhs.varies()) {
+            (true, false) => self.rhs.constant(),
+            (false, true) => self.lhs.constant(),
+            (false, false) => self.rhs.constant(),
+            (true, true) => None,
+        }
+    }
+
+    /// Get the values compared against [`CmpSite::constant`]
+    pub fn inputs(&self) -> &Operand {
+        if !self.lhs.varies() && self.rhs.varies() {
+            &self.rhs
+        } else {
+            &self.lhs
+        }
+    }
+
+    /// Check if the operands were never equal, the comparison against the
+    /// constant was never passed
+    pub fn unsolved(&self) -> bool {
+        self.equal == 0
+    }
+}
+
+/// A suggested change to an input, see [`ValueProfile::replacements`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Replacement {
+    /// PC of the comparison this would pass
+    pub pc: u64,
+
+    /// Offset in the input
+    pub offset: usize,
+
+    /// Bytes to put at `offset`
+    pub bytes: Vec<u8>,
+}
+
+/// Collects the value profile of comparisons, see the module documentation
+///
+/// Unlike most analyses the order of comparisons doesn't matter, so a
+/// profile can be fed from the parallel callbacks of several threads and
+/// [`ValueProfile::merge`]d.
+#[derive(Clone, Debug)]
+pub struct ValueProfile {
+    /// Distinct values kept per operand
+    pub max_values: usize,
+
+    /// Comparisons, keyed by PC
+    sites: HashMap<u64, CmpSite>,
+}
+
+impl Default for ValueProfile {
+    fn default() -> Self {
+        Self { max_values: MAX_VALUES, sites: HashMap::new() }
+    }
+}
+
+impl ValueProfile {
+    /// Create an empty profile
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the comparison at `pc` of the `sz` byte operands `lhs` and
+    /// `rhs`
+    pub fn cmp(&mut self, pc: u64, lhs: u64, rhs: u64, sz: u8) {
+        let site = self.sites.entry(pc)
+            .or_insert_with(|| CmpSite { pc, ..Default::default() });
+        site.sz     = site.sz.max(sz);
+        site.execs += 1;
+        site.equal += (lhs == rhs) as u64;
+        site.lhs.add(lhs, self.max_values);
+        site.rhs.add(rhs, self.max_values);
+    }
+
+    /// Merge the profile `other` into this one
+    pub fn merge(&mut self, other: &ValueProfile) {
+        for (pc, theirs) in &other.sites {
+            let site = self.sites.entry(*pc)
+                .or_insert_with(|| CmpSite { pc: *pc, ..Default::default() });
+            site.sz     = site.sz.max(theirs.sz);
+            site.execs += theirs.execs;
+            site.equal += theirs.equal;
+            for (ours, theirs) in [(&mut site.lhs, &theirs.lhs),
+                                   (&mut site.rhs, &theirs.rhs)] {
+                ours.overflowed |= theirs.overflowed;
+                for val in &theirs.values {
+                    ours.add(*val, self.max_values);
+                }
+            }
+        }
+    }
+
+    /// Get the profile of every comparison, sorted by PC
+    pub fn sites(&self) -> Vec<&CmpSite> {
+        let mut ret = self.sites.values().collect::<Vec<_>>();
+        ret.sort_by_key(|x| x.pc);
+        ret
+    }
+
+    /// Get the profile of the comparison at `pc`
+    pub fn site(&self, pc: u64) -> Option<&CmpSite> {
+        self.sites.get(&pc)
+    }
+
+    /// Suggest changes to `input` which would make it pass comparisons it
+    /// failed: every place where the value compared against a constant
+    /// appears in the input, in either byte order, is replaced by the
+    /// constant in the same byte order. Single byte comparisons match too
+    /// much of a typical input and are skipped
+    pub fn replacements(&self, input: &[u8]) -> Vec<Replacement> {
+        let mut ret  = Vec::new();
+        let mut seen = HashSet::new();
+        for site in self.sites().into_iter().filter(|x| x.unsolved()) {
+            let (Some(constant), sz) = (site.constant(), site.sz as usize)
+            else {
+                continue;
+            };
+            if !(2..=8).contains(&sz) {
+                continue;
+            }
+
+            for val in &site.inputs().values {
+                for big in [false, true] {
+                    let encode = |x: u64| if big {
+                        x.to_be_bytes()[8 - sz..].to_vec()
+                    } else {
+                        x.to_le_bytes()[..sz].to_vec()
+                    };
+                    let (needle, bytes) = (encode(*val), encode(constant));
+                    for offset in 0..input.len().saturating_sub(sz - 1) {
+                        // Values which read the same in both byte orders
+                        // are only replaced once
+                        if input[offset..offset + sz] == needle[..] &&
+                                seen.insert((site.pc, offset)) {
+                            ret.push(Replacement {
+                                pc: site.pc,
+                                offset,
+                                bytes: bytes.clone(),
+                            });
+                        }
+                    }
+                }
+            }
+        }
+        ret
+    }
+}
+
+#[test]
+fn input_to_state() {
+    // A parser compares the first 4 bytes against a magic, little-endian,
+    // and a length field against a limit
+    let mut profile = ValueProfile::new();
+    for input in [b"AAAA\x00\x20", b"BBBB\x00\x30"] {
+        let magic = u32::from_le_bytes(input[..4].try_into().unwrap());
+        profile.cmp(0x1000, magic as u64, 0x464c457f, 4);
+        let len = u16::from_be_bytes(input[4..].try_into().unwrap());
+        profile.cmp(0x1010, 0x10, len as u64, 2);
+    }
+
+    let magic = profile.site(0x1000).unwrap();
+    assert_eq!((magic.execs, magic.constant()), (2, Some(0x464c457f)));
+    assert!(magic.unsolved() && magic.lhs.varies());
+    assert_eq!(profile.site(0x1010).unwrap().constant(), Some(0x10));
+
+    let replacements = profile.replacements(b"AAAA\x00\x10");
+    assert_eq!(replacements, [
+        Replacement { pc: 0x1000, offset: 0, bytes: b"\x7fELF".to_vec() },
+    ]);
+    let replacements = profile.replacements(b"xBBBB\x00\x20");
+    assert_eq!(replacements[0].offset, 1);
+    assert_eq!(replacements[1], Replacement {
+        pc: 0x1010, offset: 5, bytes: vec![0x00, 0x10] });
+
+    // Merging keeps the operands apart
+    let mut merged = ValueProfile::new();
+    merged.merge(&profile);
+    merged.merge(&profile);
+    assert_eq!(merged.site(0x1000).unwrap().execs, 4);
+    assert_eq!(merged.site(0x1000).unwrap().lhs, magic.lhs);
+}
@@ -0,0 +1,257 @@
+//! Handing a concrete path over to symbolic execution
+//!
+//! Hybrid (concolic) workflows run the target concretely until it gets
+//! stuck, then have a symbolic executor (angr, Triton, or a fork of either)
+//! solve for inputs which take the other side of a branch. The executor
+//! needs to follow the exact path the concrete run took to get there, which
+//! is what a [`PathRecorder`] extracts from a thread's events as a
+//! [`PathSeed`]:
+//!
+//! - The branch sequence: every instruction which ended a basic block and
+//!   the PC execution continued at, from branch hooks
+//! - The operands of every comparison (`cmp` events), placed in the branch
+//!   sequence
+//! - Where comparison operands came from in the input. Cannoli doesn't track
+//!   data flow, so this is matched by value: the offsets at which an operand
+//!   of 2 or more bytes appears in the input, in either byte order, the same
+//!   input-to-state correspondence [`crate::analysis::cmp`] uses
+//! - The file mappings the addresses refer to, so the executor can load the
+//!   binaries at the same addresses
+//!
+//! The seed is written as JSON, meant to be read by a small loader script on
+//! the executor's side which drives a simulation manager (or Triton's
+//! emulation loop) along `branches`, checking `cmps` along the way.
+
+use std::io::{Read, Write};
+use serde::{Serialize, Deserialize};
+use crate::event::Event;
+
+/// Version of the seed format
+pub const SEED_VERSION: u32 = 1;
+
+/// Largest number of input offsets kept per operand
+const MAX_OFFSETS: usize = 16;
+
+/// A branch the concrete run took
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PathBranch {
+    /// PC of the instruction ending the basic block
+    pub pc: u64,
+
+    /// PC execution continued at
+    pub target: u64,
+}
+
+/// A comparison on the path
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PathCmp {
+    /// Number of branches taken before the comparison
+    pub step: usize,
+
+    /// PC of the comparison
+    pub pc: u64,
+
+    /// Size of the operands in bytes
+    pub sz: u8,
+
+    /// Left-hand operand
+    pub lhs: u64,
+
+    /// Right-hand operand
+    pub rhs: u64,
+
+    /// Offsets in the input the left-hand operand appears at
+    pub lhs_input: Vec<usize>,
+
+    /// Offsets in the input the right-hand operand appears at
+    pub rhs_input: Vec<usize>,
+}
+
+/// A file mapping of the traced process
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PathMapping {
+    /// Address it was mapped at
+    pub base: u64,
+
+    /// Length in bytes
+    pub len: u64,
+
+    /// Path of the file
+    pub path: String,
+
+    /// Offset in the file
+    pub offset: u64,
+
+    /// Set if the mapping is executable
+    pub exec: bool,
+}
+
+/// A concrete path, packaged for a symbolic executor, see the module
+/// documentation
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PathSeed {
+    /// Version of the format, [`SEED_VERSION`]
+    pub version: u32,
+
+    /// Architecture of the target as QEMU names it (eg. `x86_64`), if known
+    pub arch: Option<String>,
+
+    /// The input of the run, as hex
+    pub input: String,
+
+    /// File mappings, in the order they were made
+    pub mappings: Vec<PathMapping>,
+
+    /// Branches in the order they were taken
+    pub branches: Vec<PathBranch>,
+
+    /// Comparisons in the order they were made
+    pub cmps: Vec<PathCmp>,
+}
+
+impl PathSeed {
+    /// Write the seed as JSON to `out`
+    pub fn write(&self, out: impl Write) -> std::io::Result<()> {
+        serde_json::to_writer(out, self).map_err(Into::into)
+    }
+
+    /// Read a seed written by [`PathSeed::write`]
+    pub fn read(input: impl Read) -> std::io::Result<Self> {
+        serde_json::from_reader(input).map_err(Into::into)
+    }
+
+    /// Get the input of the run
+    pub fn input(&self) -> Option<Vec<u8>> {
+        (0..self.input.len()).step_by(2)
+            .map(|x| u8::from_str_radix(self.input.get(x..x + 2)?, 16).ok())
+            .collect()
+    }
+}
+
+/// Find the offsets `val` appears at in `input`, as an `sz` byte integer of
+/// either byte order
+fn input_offsets(input: &[u8], val: u64, sz: u8) -> Vec<usize> {
+    let sz = sz as usize;
+    if !(2..=8).contains(&sz) {
+        return Vec::new();
+    }
+
+    let little = val.to_le_bytes()[..sz].to_vec();
+    let big    = val.to_be_bytes()[8 - sz..].to_vec();
+    input.windows(sz).enumerate()
+        .filter(|(_, x)| *x == little || *x == big)
+        .map(|(ii, _)| ii)
+        .take(MAX_OFFSETS)
+        .collect()
+}
+
+/// Records the path of a single thread, see the module documentation
+pub struct PathRecorder {
+    /// The seed being built
+    seed: PathSeed,
+
+    /// The input of the run
+    input: Vec<u8>,
+
+    /// PC of a branch waiting for the PC execution continues at
+    pending: Option<u64>,
+}
+
+impl PathRecorder {
+    /// Create a recorder for a run of the target with `input`
+    pub fn new(arch: Option<String>, input: Vec<u8>) -> Self {
+        let seed = PathSeed {
+            version: SEED_VERSION,
+            arch,
+            input: input.iter().map(|x| format!("{x:02x}")).collect(),
+            ..Default::default()
+        };
+        Self { seed, input, pending: None }
+    }
+
+    /// Observe the next `event` of the thread
+    pub fn observe(&mut self, event: &Event) {
+        let pc = match *event {
+            Event::Exec { pc } | Event::Regs { pc, .. } => pc,
+            Event::Branch { pc, taken, .. } => {
+                if let Some(from) = self.pending.take() {
+                    self.seed.branches.push(PathBranch {
+                        pc: from, target: pc });
+                }
+                if taken {
+                    self.pending = Some(pc);
+                }
+                return;
+            }
+            Event::Cmp { pc, lhs, rhs, sz } => {
+                self.seed.cmps.push(PathCmp {
+                    step:      self.seed.branches.len(),
+                    pc, sz, lhs, rhs,
+                    lhs_input: input_offsets(&self.input, lhs, sz),
+                    rhs_input: input_offsets(&self.input, rhs, sz),
+                });
+                return;
+            }
+            Event::Mmap { base, len, anon: false, exec, ref path, offset,
+                    .. } => {
+                self.seed.mappings.push(PathMapping {
+                    base, len, path: path.to_string(), offset, exec,
+                });
+                return;
+            }
+            _ => return,
+        };
+
+        if let Some(from) = self.pending.take() {
+            self.seed.branches.push(PathBranch { pc: from, target: pc });
+        }
+    }
+
+    /// Get the seed of the path so far. A branch at the very end of the
+    /// thread has no target and is left out
+    pub fn seed(&self) -> &PathSeed {
+        &self.seed
+    }
+
+    /// Finish recording and get the seed
+    pub fn finish(self) -> PathSeed {
+        self.seed
+    }
+}
+
+#[test]
+fn record_path() {
+    let mut recorder = PathRecorder::new(Some("x86_64".into()),
+        b"\x00\x10MAGC".to_vec());
+    for event in [
+        Event::Mmap {
+            base: 0x400000, len: 0x1000, anon: false, read: true,
+            write: false, exec: true, path: "/bin/parser".into(), offset: 0,
+        },
+        Event::Branch { pc: 0x401000, taken: false, regs: Vec::new() },
+        Event::Cmp    { pc: 0x401004, lhs: 0x1000, rhs: 0x20, sz: 2 },
+        Event::Branch { pc: 0x401008, taken: true,  regs: Vec::new() },
+        Event::Branch { pc: 0x401040, taken: false, regs: Vec::new() },
+        Event::Cmp    { pc: 0x401044, lhs: 0x4347414d, rhs: 0x46464c45,
+            sz: 4 },
+        Event::Branch { pc: 0x401048, taken: true,  regs: Vec::new() },
+    ] {
+        recorder.observe(&event);
+    }
+
+    let seed = recorder.finish();
+    assert_eq!(seed.branches, [
+        PathBranch { pc: 0x401008, target: 0x401040 },
+    ]);
+    assert_eq!(seed.cmps.len(), 2);
+    assert_eq!((seed.cmps[0].step, &seed.cmps[0].lhs_input[..]), (0, &[0][..]));
+    assert!(seed.cmps[0].rhs_input.is_empty());
+    assert_eq!((seed.cmps[1].step, &seed.cmps[1].lhs_input[..]), (1, &[2][..]));
+    assert_eq!(seed.mappings[0].path, "/bin/parser");
+
+    let mut json = Vec::new();
+    seed.write(&mut json).unwrap();
+    let loaded = PathSeed::read(&json[..]).unwrap();
+    assert_eq!(loaded, seed);
+    assert_eq!(loaded.input().unwrap(), b"\x00\x10MAGC");
+}
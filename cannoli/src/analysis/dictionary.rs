@@ -0,0 +1,186 @@
+//! Fuzzing dictionaries from observed comparisons and constant data
+//!
+//! Fuzzers get past magic values and keywords much faster with a dictionary
+//! of tokens to splice into inputs. A [`Dictionary`] builds one for the
+//! target from what it was seen doing with its input:
+//!
+//! - The constants comparisons were made against, from a
+//!   [`crate::analysis::cmp::ValueProfile`], encoded in the target's byte
+//!   order
+//! - Strings in read-only file mappings (`.rodata` and friends) which the
+//!   target read, eg. keywords a parser matches its input against with
+//!   `strcmp()`. Strings are read from the mapped file, so reads by code
+//!   which only touches the first byte still produce the whole string
+//!
+//! The result is written in the dictionary format shared by AFL and
+//! libFuzzer, one `name="token"` per line.
+
+use std::io::Write;
+use std::sync::Arc;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use crate::address_space::AddressSpace;
+use crate::analysis::cmp::ValueProfile;
+use crate::event::Event;
+
+/// Longest string taken from constant data
+const MAX_STRING: usize = 128;
+
+/// Check if `byte` can be part of a string
+fn is_text(byte: u8) -> bool {
+    byte.is_ascii_graphic() || byte == b' ' || byte == b'\t'
+}
+
+/// Builds a fuzzing dictionary, see the module documentation
+pub struct Dictionary {
+    /// Encode constants as big-endian
+    pub big_endian: bool,
+
+    /// Shortest string which is kept
+    pub min_len: usize,
+
+    /// Comparisons seen
+    profile: ValueProfile,
+
+    /// Locations in files read from read-only mappings, as path and offset
+    reads: HashSet<(Arc<str>, u64)>,
+}
+
+impl Default for Dictionary {
+    fn default() -> Self {
+        Self {
+            big_endian: false,
+            min_len:    3,
+            profile:    ValueProfile::new(),
+            reads:      HashSet::new(),
+        }
+    }
+}
+
+impl Dictionary {
+    /// Create an empty dictionary for a little-endian target
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Observe `event` of a process with the mappings `space`
+    pub fn observe(&mut self, space: &AddressSpace, event: &Event) {
+        let addr = match *event {
+            Event::Cmp { pc, lhs, rhs, sz } => {
+                self.profile.cmp(pc, lhs, rhs, sz);
+                return;
+            }
+            Event::Read { addr, .. } | Event::ReadAddr { addr, .. } => addr,
+            _ => return,
+        };
+
+        let Some(mapping) = space.lookup(addr) else { return };
+        if !mapping.anon && !mapping.write && !mapping.exec {
+            let offset = mapping.offset + (addr - mapping.base);
+            self.reads.insert((mapping.path.clone(), offset));
+        }
+    }
+
+    /// Merge the observations of `other` into this one
+    pub fn merge(&mut self, other: &Dictionary) {
+        self.profile.merge(&other.profile);
+        self.reads.extend(other.reads.iter().cloned());
+    }
+
+    /// Get the string in `data` around `offset`, if there is one
+    fn string_at(&self, data: &[u8], offset: usize) -> Option<Vec<u8>> {
+        if !is_text(*data.get(offset)?) {
+            return None;
+        }
+        let start = data[..offset].iter().rposition(|x| !is_text(*x))
+            .map_or(0, |x| x + 1);
+        let end = data[offset..].iter().position(|x| !is_text(*x))
+            .map_or(data.len(), |x| offset + x);
+        (end - start >= self.min_len && end - start <= MAX_STRING)
+            .then(|| data[start..end].to_vec())
+    }
+
+    /// Get the tokens of the dictionary, sorted. Files which can't be read
+    /// are skipped
+    pub fn tokens(&self) -> Vec<Vec<u8>> {
+        let mut ret = BTreeSet::new();
+
+        for site in self.profile.sites() {
+            let Some(constant) = site.constant() else { continue };
+            let sz = site.sz.clamp(1, 8) as usize;
+            let bytes = if self.big_endian {
+                constant.to_be_bytes()[8 - sz..].to_vec()
+            } else {
+                constant.to_le_bytes()[..sz].to_vec()
+            };
+
+            // Zeroes, all-ones and small integers are everywhere already
+            let trivial = bytes.iter().all(|&x| x == 0 || x == 0xff) ||
+                (sz > 1 && constant < 0x100);
+            if !trivial {
+                ret.insert(bytes);
+            }
+        }
+
+        let mut files: HashMap<&Arc<str>, Option<Vec<u8>>> = HashMap::new();
+        for (path, offset) in &self.reads {
+            let data = files.entry(path)
+                .or_insert_with(|| std::fs::read(&**path).ok());
+            if let Some(string) = data.as_ref()
+                    .and_then(|x| self.string_at(x, *offset as usize)) {
+                ret.insert(string);
+            }
+        }
+
+        ret.into_iter().collect()
+    }
+
+    /// Write the dictionary in the AFL/libFuzzer format to `out`
+    pub fn write(&self, mut out: impl Write) -> std::io::Result<()> {
+        for (ii, token) in self.tokens().iter().enumerate() {
+            let mut escaped = String::new();
+            for &byte in token {
+                match byte {
+                    b'"' | b'\\' => {
+                        escaped.push('\\');
+                        escaped.push(byte as char);
+                    }
+                    _ if is_text(byte) && byte != b'\t' =>
+                        escaped.push(byte as char),
+                    _ => escaped += &format!("\\x{byte:02x}"),
+                }
+            }
+            writeln!(out, "kw{ii}=\"{escaped}\"")?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn dictionary_from_trace() {
+    let path = std::env::temp_dir()
+        .join(format!("cannoli_dictionary_{}", std::process::id()));
+    std::fs::write(&path, b"\x00\x00GET\x00POST\x00a\x00").unwrap();
+
+    let mut space = AddressSpace::new();
+    space.mmap(0x4000, 0x1000, false, true, false, false,
+        path.to_str().unwrap(), 0);
+
+    let mut dictionary = Dictionary::new();
+    for event in [
+        // `strcmp()` against "POST" reads its first byte, the one byte read
+        // of "a" is too short to be kept
+        Event::Read { pc: 0x1000, addr: 0x4006, val: 0x50, sz: 1 },
+        Event::Read { pc: 0x1000, addr: 0x400b, val: 0x61, sz: 1 },
+        Event::Cmp  { pc: 0x1010, lhs: 0x41414141, rhs: 0x464c457f, sz: 4 },
+        Event::Cmp  { pc: 0x1010, lhs: 0x42424242, rhs: 0x464c457f, sz: 4 },
+        Event::Cmp  { pc: 0x1020, lhs: 5, rhs: 0, sz: 8 },
+    ] {
+        dictionary.observe(&space, &event);
+    }
+
+    let mut out = Vec::new();
+    dictionary.write(&mut out).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(),
+        "kw0=\"POST\"\nkw1=\"\\x7fELF\"\n");
+}
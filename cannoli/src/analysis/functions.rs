@@ -0,0 +1,215 @@
+//! Inference of function entry points and boundaries from a PC trace
+//!
+//! Stripped binaries give the symbolizer nothing to work with. We can still
+//! get a pretty good idea of where the functions are by watching control
+//! flow: a transfer of control which later "returns" to just after the place
+//! it came from was a call, its target is a function entry, and everything
+//! executed while that call was active (not counting nested calls) is part of
+//! the function body.
+//!
+//! This only needs the sequence of executed PCs, no instruction decoding, so
+//! it works the same for every architecture QEMU supports.
+
+use std::io::Write;
+use std::collections::BTreeMap;
+
+/// Maximum distance between two PCs for them to be considered sequential
+/// execution rather than a transfer of control. This is the largest
+/// instruction size we expect to see
+const MAX_INSN_LEN: u64 = 16;
+
+/// Maximum distance between the instruction which transferred control and
+/// the address that a return lands on. This covers the size of call
+/// instructions on variable-length ISAs, and delay slots on MIPS and friends
+const MAX_RETURN_GAP: u64 = 16;
+
+/// Maximum number of tentative frames we track before we start folding them
+/// into their parents. Jumps which never return (loops, tail calls, etc) look
+/// like calls until proven otherwise, so this bounds the memory we use
+const MAX_DEPTH: usize = 4096;
+
+/// A tentative call frame
+struct Frame {
+    /// Address control was transferred to
+    entry: u64,
+
+    /// Address of the last instruction executed before the transfer, `None`
+    /// for the first frame of a thread, which can never return
+    call_site: Option<u64>,
+
+    /// Lowest PC executed in this frame
+    lo: u64,
+
+    /// Highest PC executed in this frame
+    hi: u64,
+}
+
+/// A function discovered by [`FunctionInference`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InferredFunction {
+    /// Entry point of the function
+    pub entry: u64,
+
+    /// Address of the highest instruction observed executing as part of this
+    /// function. The function spans `entry..=last_pc` plus the size of the
+    /// instruction at `last_pc`
+    pub last_pc: u64,
+
+    /// Number of times a call to this function was observed returning
+    pub returns: u64,
+}
+
+/// Infers function entry points and boundaries from the order PCs execute in
+///
+/// Feed this every executed PC of a single thread, in order, with
+/// [`FunctionInference::observe`]. Use a separate instance per thread and
+/// [`FunctionInference::merge`] them together at the end.
+#[derive(Default)]
+pub struct FunctionInference {
+    /// Last PC we observed
+    prev: Option<u64>,
+
+    /// Stack of tentative frames, the top of the stack is the frame we're
+    /// currently executing in
+    stack: Vec<Frame>,
+
+    /// Functions which have been confirmed by observing a return, keyed by
+    /// entry point
+    functions: BTreeMap<u64, InferredFunction>,
+}
+
+impl FunctionInference {
+    /// Create a new, empty, inference state
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Observe the next executed PC
+    pub fn observe(&mut self, pc: u64) {
+        // Get the previous PC, if this is the first PC, it's the root frame
+        let prev = match self.prev.replace(pc) {
+            Some(prev) => prev,
+            None => {
+                self.stack.push(Frame {
+                    entry:     pc,
+                    call_site: None,
+                    lo:        pc,
+                    hi:        pc,
+                });
+                return;
+            }
+        };
+
+        // Check if this is sequential execution (`0 < pc - prev <= max`)
+        let sequential = pc.wrapping_sub(prev).wrapping_sub(1) < MAX_INSN_LEN;
+
+        if !sequential {
+            // Look for a frame which this transfer returns from, searching
+            // from the top of the stack so we handle `longjmp()` and friends
+            let returned = self.stack.iter().rposition(|frame| {
+                frame.call_site.is_some_and(|site| {
+                    pc.wrapping_sub(site).wrapping_sub(1) < MAX_RETURN_GAP
+                })
+            });
+
+            if let Some(idx) = returned {
+                // Everything above the returning frame was a jump rather than
+                // a call, fold it into the frame that returned
+                self.fold(idx);
+
+                // The frame returned, it was a call and thus a function!
+                let frame = self.stack.pop().unwrap();
+                let func = self.functions.entry(frame.entry)
+                    .or_insert(InferredFunction {
+                        entry:   frame.entry,
+                        last_pc: frame.hi,
+                        returns: 0,
+                    });
+                func.last_pc  = func.last_pc.max(frame.hi);
+                func.returns += 1;
+            } else if self.stack.last().is_some_and(|top| {
+                (top.lo..=top.hi).contains(&pc)
+            }) {
+                // Transfer to code we've already executed in this frame, this
+                // is a loop, not a call
+            } else {
+                // Anything else might be a call, we'll know if it returns
+                if self.stack.len() >= MAX_DEPTH {
+                    self.fold(self.stack.len() - 2);
+                }
+
+                self.stack.push(Frame {
+                    entry:     pc,
+                    call_site: Some(prev),
+                    lo:        pc,
+                    hi:        pc,
+                });
+            }
+        }
+
+        // Account this PC to the frame we're in
+        if let Some(top) = self.stack.last_mut() {
+            top.lo = top.lo.min(pc);
+            top.hi = top.hi.max(pc);
+        }
+    }
+
+    /// Fold all frames above `idx` into the frame at `idx`
+    fn fold(&mut self, idx: usize) {
+        let (lo, hi) = self.stack.drain(idx + 1..)
+            .fold((u64::MAX, 0), |(lo, hi), frame| {
+                (lo.min(frame.lo), hi.max(frame.hi))
+            });
+
+        let frame = &mut self.stack[idx];
+        frame.lo = frame.lo.min(lo);
+        frame.hi = frame.hi.max(hi);
+    }
+
+    /// Merge the functions discovered by `other` into `self`
+    pub fn merge(&mut self, other: &FunctionInference) {
+        for func in other.functions.values() {
+            self.functions.entry(func.entry)
+                .and_modify(|ours| {
+                    ours.last_pc  = ours.last_pc.max(func.last_pc);
+                    ours.returns += func.returns;
+                })
+                .or_insert(*func);
+        }
+    }
+
+    /// Get all the functions discovered so far, sorted by entry point
+    pub fn functions(&self) -> impl Iterator<Item = &InferredFunction> {
+        self.functions.values()
+    }
+
+    /// Write the discovered functions as a symbol map in `nm` format, naming
+    /// each function `sub_<entry>`. This is the same format the examples load
+    /// from `symbols.txt`
+    pub fn write_symbol_map(&self, mut out: impl Write)
+            -> std::io::Result<()> {
+        for func in self.functions() {
+            writeln!(out, "{:016x} T sub_{:x}", func.entry, func.entry)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn infer_call_and_loop() {
+    let mut inference = FunctionInference::new();
+
+    // `main` calls a function at 0x2000 which loops once before returning
+    for pc in [0x1000, 0x1004, 0x1008, 0x2000, 0x2004, 0x2008, 0x2000,
+               0x2004, 0x2008, 0x200c, 0x100c, 0x1010] {
+        inference.observe(pc);
+    }
+
+    let functions = inference.functions().copied().collect::<Vec<_>>();
+    assert_eq!(functions, [InferredFunction {
+        entry:   0x2000,
+        last_pc: 0x200c,
+        returns: 1,
+    }]);
+}
@@ -0,0 +1,271 @@
+//! Detecting modifications of the guest's code
+//!
+//! Anti-debugging and self-patching targets rewrite their own code, which is
+//! easy to miss in a trace. [`IntegrityWatch`] keeps a copy of every
+//! executable mapping and hashes it page by page. Writes from the trace are
+//! applied to the copy, and [`IntegrityWatch::check`] (called periodically,
+//! eg. every so many batches of the trace) re-hashes the pages that were
+//! written to and reports those whose contents actually changed, along with
+//! the PCs of the writes.
+//!
+//! The client has no access to guest memory, so the initial contents are
+//! reconstructed instead: file mappings are read from the file at the path
+//! QEMU reported, and anonymous mappings start out zeroed. Mappings whose file
+//! can't be read (eg. a guest path under QEMU's `-L` prefix) aren't watched.
+//!
+//! Only writes done by guest instructions are seen, memory written by the
+//! kernel on behalf of the guest (eg. `read()` into a code page) is not.
+//! Writes logged without their value (see [`crate::control::Filters`]) still
+//! mark a page as modified, but its new hash is unknown. Mappings are only
+//! reported to us as they are created, so code made executable later with
+//! `mprotect()` is not watched.
+
+use std::sync::Arc;
+use std::collections::BTreeMap;
+
+/// Size of the pages hashes are kept for
+const PAGE_SIZE: u64 = 4096;
+
+/// Largest mapping to keep a copy of, this bounds memory usage
+const MAX_REGION: u64 = 256 << 20;
+
+/// Maximum number of writers recorded for a single page between checks
+const MAX_WRITERS: usize = 16;
+
+/// Hash a page with 64-bit FNV-1a
+fn hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, x| {
+        (hash ^ *x as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// A write to watched code
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Writer {
+    /// PC of the instruction which wrote
+    pub pc: u64,
+
+    /// Address written to
+    pub addr: u64,
+
+    /// Size of the write in bytes
+    pub sz: u8,
+}
+
+/// A page of code whose contents changed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Modification {
+    /// Address of the page
+    pub page: u64,
+
+    /// Path of the file the page was mapped from, empty for anonymous
+    /// mappings
+    pub path: Arc<str>,
+
+    /// Offset of the page in the file
+    pub offset: u64,
+
+    /// Hash of the page as of the previous check
+    pub before: u64,
+
+    /// Hash of the page now, `None` if a write without a value made its
+    /// contents unknown
+    pub after: Option<u64>,
+
+    /// Writes to the page since the previous check, up to a limit
+    pub writers: Vec<Writer>,
+}
+
+/// Copy of an executable mapping
+struct Region {
+    /// Path of the file backing the mapping, empty for anonymous mappings
+    path: Arc<str>,
+
+    /// Offset into the file that the mapping starts at
+    offset: u64,
+
+    /// Current contents of the mapping
+    contents: Vec<u8>,
+
+    /// Hash of every page as of the last check, `None` once the contents of
+    /// the page are unknown
+    hashes: Vec<Option<u64>>,
+}
+
+/// Pages written to since the last check
+#[derive(Default)]
+struct Dirty {
+    /// Writes to the page
+    writers: Vec<Writer>,
+
+    /// Set if a write without a value hit the page
+    unknown: bool,
+}
+
+/// Watches the code of a process for modifications, see the module
+/// documentation
+///
+/// Feed this every mapping and write of a process. Since code is shared
+/// between threads, this should be shared between all threads of a process.
+#[derive(Default)]
+pub struct IntegrityWatch {
+    /// Watched mappings, keyed by base address
+    regions: BTreeMap<u64, Region>,
+
+    /// Pages written to since the last check, keyed by address
+    dirty: BTreeMap<u64, Dirty>,
+}
+
+impl IntegrityWatch {
+    /// Create a new watch, with nothing mapped
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a new mapping, as reported by [`crate::Cannoli::mmap`]. Only
+    /// executable mappings are watched, but any mapping replaces the watched
+    /// ones it overlaps
+    pub fn mmap(&mut self, base: u64, len: u64, anon: bool, exec: bool,
+            path: &str, offset: u64) {
+        self.munmap(base, len);
+        if !exec || len > MAX_REGION {
+            return;
+        }
+
+        let contents = if anon {
+            vec![0; len as usize]
+        } else {
+            // Past the end of the file the mapping is zeroed
+            let Ok(file) = std::fs::read(path) else { return };
+            let start = (offset as usize).min(file.len());
+            let end   = start.saturating_add(len as usize).min(file.len());
+            let mut contents = file[start..end].to_vec();
+            contents.resize(len as usize, 0);
+            contents
+        };
+
+        let hashes = contents.chunks(PAGE_SIZE as usize)
+            .map(|x| Some(hash(x)))
+            .collect();
+        self.regions.insert(base, Region {
+            path: path.into(),
+            offset, contents, hashes,
+        });
+    }
+
+    /// Stop watching the code in `base..base + len`, as reported by
+    /// [`crate::Cannoli::munmap`]. Partially unmapped mappings are dropped
+    /// altogether
+    pub fn munmap(&mut self, base: u64, len: u64) {
+        let end = base.saturating_add(len);
+        self.regions.retain(|x, region| {
+            x.saturating_add(region.contents.len() as u64) <= base ||
+                *x >= end
+        });
+        self.dirty.retain(|x, _| x + PAGE_SIZE <= base || *x >= end);
+    }
+
+    /// Find the watched mapping containing `addr`
+    fn region(&mut self, addr: u64) -> Option<(u64, &mut Region)> {
+        self.regions.range_mut(..=addr).next_back()
+            .filter(|(x, region)| addr - **x < region.contents.len() as u64)
+            .map(|(x, region)| (*x, region))
+    }
+
+    /// Record a write of `sz` bytes at `addr` by the instruction at `pc`.
+    /// `val` is `None` for writes logged without their value
+    pub fn write(&mut self, pc: u64, addr: u64, val: Option<u64>, sz: u8) {
+        // Most writes don't go anywhere near code
+        let last = addr.wrapping_add(sz.max(1) as u64 - 1);
+        if self.region(addr).is_none() && self.region(last).is_none() {
+            return;
+        }
+
+        let bytes = val.unwrap_or(0).to_le_bytes();
+        for ii in 0..sz.min(8) as u64 {
+            let byte = addr.wrapping_add(ii);
+            let Some((base, region)) = self.region(byte) else { continue };
+            if val.is_some() {
+                region.contents[(byte - base) as usize] = bytes[ii as usize];
+            }
+
+            let dirty = self.dirty.entry(byte & !(PAGE_SIZE - 1))
+                .or_default();
+            dirty.unknown |= val.is_none();
+            let writer = Writer { pc, addr, sz };
+            if dirty.writers.len() < MAX_WRITERS &&
+                    dirty.writers.last() != Some(&writer) {
+                dirty.writers.push(writer);
+            }
+        }
+    }
+
+    /// Re-hash the pages written to since the last check, and get the ones
+    /// whose contents changed
+    pub fn check(&mut self) -> Vec<Modification> {
+        let mut modified = Vec::new();
+        for (page, dirty) in std::mem::take(&mut self.dirty) {
+            let Some((base, region)) = self.region(page) else { continue };
+            let index = ((page - base) / PAGE_SIZE) as usize;
+            let start = index * PAGE_SIZE as usize;
+            let end   = (start + PAGE_SIZE as usize)
+                .min(region.contents.len());
+
+            let before = region.hashes[index];
+            let after  = if dirty.unknown || before.is_none() {
+                None
+            } else {
+                Some(hash(&region.contents[start..end]))
+            };
+            region.hashes[index] = after;
+
+            // Writes which put back the same bytes aren't modifications.
+            // Pages which became unknown are reported the first time
+            if after == before {
+                continue;
+            }
+            let Some(before) = before else { continue };
+            modified.push(Modification {
+                path:    region.path.clone(),
+                offset:  region.offset + start as u64,
+                writers: dirty.writers,
+                page, before, after,
+            });
+        }
+        modified
+    }
+}
+
+#[test]
+fn detect_self_patching() {
+    let mut watch = IntegrityWatch::new();
+    watch.mmap(0x10000, 0x2000, true, true, "", 0);
+    watch.mmap(0x20000, 0x1000, true, false, "", 0);
+
+    // Data writes, and writes which don't change the code, are not reported
+    watch.write(0x10000, 0x20000, Some(0x41), 1);
+    watch.write(0x10004, 0x10100, Some(0), 8);
+    assert_eq!(watch.check(), []);
+
+    // A patch straddling two pages is reported for both
+    watch.write(0x10008, 0x10ffe, Some(0x9090_9090), 4);
+    let modified = watch.check();
+    assert_eq!(modified.iter().map(|x| x.page).collect::<Vec<_>>(),
+        [0x10000, 0x11000]);
+    assert_eq!(modified[0].writers,
+        [Writer { pc: 0x10008, addr: 0x10ffe, sz: 4 }]);
+    assert!(modified[0].after.is_some());
+    assert_eq!(watch.check(), []);
+
+    // Writes without values make the page unknown, which is reported once
+    watch.write(0x1000c, 0x10010, None, 4);
+    watch.write(0x1000c, 0x10010, None, 4);
+    assert_eq!(watch.check()[0].after, None);
+    watch.write(0x1000c, 0x10010, Some(0), 4);
+    assert_eq!(watch.check(), []);
+
+    // Unmapped code is no longer watched
+    watch.munmap(0x10000, 0x2000);
+    watch.write(0x10008, 0x11000, Some(1), 1);
+    assert_eq!(watch.check(), []);
+}
@@ -0,0 +1,262 @@
+//! Structure layout hints from memory access patterns
+//!
+//! Memory traces only tell us the addresses being accessed, but a lot of
+//! those addresses are `base pointer + field offset`. We guess the base
+//! pointers by watching pointer-sized values that are loaded from and stored
+//! to memory: if a value points into memory the target has touched, it's
+//! likely a pointer to an object. Subsequent accesses just above a known base
+//! are then recorded as fields of that object.
+//!
+//! Objects are grouped by the PC which first loaded or stored their pointer,
+//! objects produced by the same instruction are likely of the same type. The
+//! result is a set of layout hints, eg. "offset 0x10 accessed as u32, 0x18 as
+//! pointer", which can be exported as C structures to import into Ghidra with
+//! `File -> Parse C Source`.
+
+use std::io::Write;
+use std::collections::{BTreeMap, HashSet};
+
+/// Size of a page, used to track which memory the target has touched
+const PAGE_SIZE: u64 = 4096;
+
+/// Largest offset from a base pointer that we consider a field access
+const MAX_STRUCT_SIZE: u64 = 0x1000;
+
+/// Maximum number of base pointers to track at once, this bounds memory usage
+/// for targets which shuffle a ton of pointers around
+const MAX_BASES: usize = 1 << 20;
+
+/// Information about a single field offset in a structure
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Field {
+    /// Bitmask of the access sizes in bytes that were observed at this
+    /// offset, bit `n` is set for an access of `1 << n` bytes
+    pub sizes: u8,
+
+    /// Number of reads of this field
+    pub reads: u64,
+
+    /// Number of writes to this field
+    pub writes: u64,
+
+    /// Set if a pointer-sized access of this field ever held a pointer
+    pub pointer: bool,
+}
+
+impl Field {
+    /// Largest access size in bytes observed for this field
+    pub fn size(&self) -> u64 {
+        1 << (7 - self.sizes.leading_zeros())
+    }
+}
+
+/// Layout hints for a group of objects which originated at the same PC
+#[derive(Clone, Debug, Default)]
+pub struct Layout {
+    /// PC which first loaded or stored the pointers to these objects
+    pub origin: u64,
+
+    /// Number of distinct objects seen with this origin
+    pub instances: u64,
+
+    /// Fields, keyed by offset from the base of the object
+    pub fields: BTreeMap<u64, Field>,
+}
+
+/// Collects structure layout hints from memory accesses
+///
+/// Feed this every memory access of a process with
+/// [`LayoutInference::access`]. Since pointers are shared between threads,
+/// this should be shared between all threads of a process.
+pub struct LayoutInference {
+    /// Size of a pointer in bytes for the target
+    pointer_size: u8,
+
+    /// Page numbers of every page the target has accessed
+    pages: HashSet<u64>,
+
+    /// Known base pointers, mapped to the origin PC of the object
+    bases: BTreeMap<u64, u64>,
+
+    /// Layouts, keyed by origin PC
+    layouts: BTreeMap<u64, Layout>,
+}
+
+impl LayoutInference {
+    /// Create a new inference state for a target with `pointer_size` byte
+    /// pointers
+    pub fn new(pointer_size: u8) -> Self {
+        Self {
+            pointer_size,
+            pages:   HashSet::new(),
+            bases:   BTreeMap::new(),
+            layouts: BTreeMap::new(),
+        }
+    }
+
+    /// Check if `val` looks like a pointer to memory the target has used
+    fn is_pointer(&self, val: u64) -> bool {
+        val != 0 && self.pages.contains(&(val / PAGE_SIZE))
+    }
+
+    /// Observe a memory access of `sz` bytes at `addr` by the instruction at
+    /// `pc`, which read or wrote `val`
+    pub fn access(&mut self, pc: u64, addr: u64, val: u64, sz: u8,
+            write: bool) {
+        // Track that this memory is in use
+        self.pages.insert(addr / PAGE_SIZE);
+
+        let pointer = sz == self.pointer_size && self.is_pointer(val);
+
+        // Attribute the access to the nearest base pointer below it
+        if let Some((&base, &origin)) = self.bases.range(..=addr).next_back() {
+            if addr - base < MAX_STRUCT_SIZE {
+                let layout = self.layouts.get_mut(&origin).unwrap();
+                let field  = layout.fields.entry(addr - base).or_default();
+                field.sizes   |= sz;
+                field.pointer |= pointer;
+                if write {
+                    field.writes += 1;
+                } else {
+                    field.reads  += 1;
+                }
+            }
+        }
+
+        // If the value moved was a pointer, it's the base of a new object
+        if pointer && self.bases.len() < MAX_BASES &&
+                !self.bases.contains_key(&val) {
+            self.bases.insert(val, pc);
+
+            let layout = self.layouts.entry(pc).or_insert_with(|| Layout {
+                origin: pc,
+                ..Default::default()
+            });
+            layout.instances += 1;
+        }
+    }
+
+    /// Inform the inference that `base..base + len` is no longer mapped, this
+    /// forgets all base pointers in the range
+    pub fn unmap(&mut self, base: u64, len: u64) {
+        let end = base.saturating_add(len);
+        let dead = self.bases.range(base..end)
+            .map(|(&x, _)| x).collect::<Vec<_>>();
+        for addr in dead {
+            self.bases.remove(&addr);
+        }
+
+        // Huge ranges (eg. the whole address space) are cheaper to check
+        // against the pages seen than page by page
+        let pages = base / PAGE_SIZE..end.div_ceil(PAGE_SIZE);
+        if pages.end - pages.start > self.pages.len() as u64 {
+            self.pages.retain(|x| !pages.contains(x));
+        } else {
+            for page in pages {
+                self.pages.remove(&page);
+            }
+        }
+    }
+
+    /// Get all layouts which had at least one field accessed
+    pub fn layouts(&self) -> impl Iterator<Item = &Layout> {
+        self.layouts.values().filter(|x| !x.fields.is_empty())
+    }
+
+    /// Write human readable layout hints
+    pub fn write_hints(&self, mut out: impl Write) -> std::io::Result<()> {
+        for layout in self.layouts() {
+            writeln!(out, "objects from {:#x} ({} instances)",
+                layout.origin, layout.instances)?;
+
+            for (offset, field) in &layout.fields {
+                let kind = if field.pointer {
+                    "pointer".to_string()
+                } else {
+                    format!("u{}", field.size() * 8)
+                };
+                writeln!(out, "    offset {offset:#x} accessed as {kind} \
+                    ({} reads, {} writes)", field.reads, field.writes)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write the layouts as C structures which can be imported into Ghidra
+    ///
+    /// Gaps between fields are filled with `undefined1` arrays, and fields
+    /// which overlap a previous field are left out (with a comment) as they
+    /// are likely unions or sub-accesses
+    pub fn write_c_header(&self, mut out: impl Write)
+            -> std::io::Result<()> {
+        writeln!(out, "// Structure layout hints generated by cannoli\n")?;
+        writeln!(out, "typedef unsigned char      undefined1;")?;
+        writeln!(out, "typedef unsigned char      uint8_t;")?;
+        writeln!(out, "typedef unsigned short     uint16_t;")?;
+        writeln!(out, "typedef unsigned int       uint32_t;")?;
+        writeln!(out, "typedef unsigned long long uint64_t;\n")?;
+
+        for layout in self.layouts() {
+            writeln!(out, "// Objects from pc {:#x}, {} instances",
+                layout.origin, layout.instances)?;
+            writeln!(out, "struct struct_{:x} {{", layout.origin)?;
+
+            // Current offset into the structure
+            let mut cur = 0;
+
+            for (&offset, field) in &layout.fields {
+                // Skip fields which overlap the previous one
+                if offset < cur {
+                    writeln!(out, "    // overlapping u{} access at {offset:#x}",
+                        field.size() * 8)?;
+                    continue;
+                }
+
+                // Pad up to the field
+                if offset > cur {
+                    writeln!(out, "    undefined1 pad_{cur:x}[{}];",
+                        offset - cur)?;
+                }
+
+                // Emit the field
+                let ty = if field.pointer {
+                    "void *".to_string()
+                } else {
+                    format!("uint{}_t ", field.size() * 8)
+                };
+                writeln!(out, "    {ty}field_{offset:x};")?;
+                cur = offset + field.size();
+            }
+
+            writeln!(out, "}};\n")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn layout_from_pointer() {
+    let mut layout = LayoutInference::new(8);
+
+    // Touch the object so its address looks like a pointer, then load the
+    // pointer and access some fields through it
+    layout.access(0x100, 0x5000, 0, 8, true);
+    layout.access(0x104, 0x6000, 0x5000, 8, false);
+    layout.access(0x108, 0x5010, 7, 4, false);
+    layout.access(0x10c, 0x5018, 0x5000, 8, true);
+
+    let layouts = layout.layouts().collect::<Vec<_>>();
+    assert_eq!(layouts.len(), 1);
+    assert_eq!(layouts[0].origin, 0x104);
+
+    let fields = layouts[0].fields.iter()
+        .map(|(&off, x)| (off, x.size(), x.pointer))
+        .collect::<Vec<_>>();
+    assert_eq!(fields, [(0x10, 4, false), (0x18, 8, true)]);
+
+    // Unmapping up to the end of the address space doesn't overflow
+    layout.unmap(0x1000, u64::MAX);
+    assert!(!layout.is_pointer(0x5000));
+}
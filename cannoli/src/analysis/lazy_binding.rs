@@ -0,0 +1,213 @@
+//! Detection of the dynamic loader's lazy symbol resolution
+//!
+//! With lazy binding, the first call through a PLT entry doesn't go to the
+//! function but to the dynamic loader's resolver trampoline, which looks the
+//! symbol up, patches the GOT and then jumps to the function. Every imported
+//! function thus drags a few thousand instructions of the loader into the
+//! trace the first time it's called, which shows up as bogus callees in call
+//! graphs and as outliers in timing data.
+//!
+//! [`LazyBinding`] is fed every executed PC of a thread and tells which PCs
+//! belong to a resolution, so they can be dropped, and reports a
+//! [`PltResolve`] with the resolved symbol when the loader hands control to
+//! the function.
+//!
+//! The loader is recognized by the file name of its mapping. Its code only
+//! counts as resolution once the main binary started executing, so the
+//! loader's startup work isn't affected. Control which leaves the loader by
+//! returning to just after the place it came from was a regular call (eg.
+//! `dlopen()`), which is reported as [`Step::Returned`]. Calls the loader
+//! makes into other modules after startup, such as destructors run at exit,
+//! can't be told apart from resolutions and are reported as such.
+
+use std::fmt;
+use std::sync::Arc;
+use std::collections::HashMap;
+use crate::address_space::AddressSpace;
+use crate::symbols::{Resolver, SymbolTable};
+
+/// Prefixes of the file names of dynamic loaders
+const LOADERS: &[&str] = &["ld-linux", "ld.so", "ld64.so", "ld-musl",
+    "ld-uClibc"];
+
+/// Maximum distance between the instruction which entered the loader and
+/// the address that a return lands on, as for
+/// [`crate::analysis::functions`]
+const MAX_RETURN_GAP: u64 = 16;
+
+/// A symbol resolved by the dynamic loader
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PltResolve {
+    /// Address of the last instruction executed before entering the loader,
+    /// typically in the PLT
+    pub from: u64,
+
+    /// Address the loader passed control to, the resolved function
+    pub target: u64,
+
+    /// Module containing `target` and the offset of `target` in it, if known
+    pub module: Option<(Arc<str>, u64)>,
+
+    /// Name of the symbol at `target`, if the module has one
+    pub symbol: Option<Arc<str>>,
+
+    /// Number of instructions executed in the loader
+    pub insns: u64,
+}
+
+impl fmt::Display for PltResolve {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.symbol, &self.module) {
+            (Some(symbol), _) => write!(f, "plt_resolve({symbol})"),
+            (None, Some((path, offset))) => {
+                let name = path.rsplit('/').next().unwrap_or(path);
+                write!(f, "plt_resolve({name}+{offset:#x})")
+            }
+            (None, None) => write!(f, "plt_resolve({:#x})", self.target),
+        }
+    }
+}
+
+/// What a PC passed to [`LazyBinding::observe`] is
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Step {
+    /// Regular code
+    Pass,
+
+    /// Code of the loader, executed after startup. Drop this to suppress
+    /// the resolution
+    Loader,
+
+    /// The first instruction of the function the loader resolved. This is
+    /// regular code
+    Resolved(PltResolve),
+
+    /// Regular code, the loader was left by returning from a call into it.
+    /// `insns` instructions were executed in the loader
+    Returned {
+        insns: u64,
+    },
+}
+
+/// Tracks the lazy resolution of a single thread, see the module
+/// documentation
+#[derive(Default)]
+pub struct LazyBinding {
+    /// Additional prefixes of file names of loaders
+    loaders: Vec<String>,
+
+    /// Set once the main binary executed
+    started: bool,
+
+    /// Last PC executed outside of the loader
+    prev: Option<u64>,
+
+    /// PC executed before entering the loader and the number of
+    /// instructions executed in it, while in the loader
+    entered: Option<(u64, u64)>,
+
+    /// Symbol tables of modules, `None` if they couldn't be loaded
+    symbols: HashMap<Arc<str>, Option<SymbolTable>>,
+}
+
+impl LazyBinding {
+    /// Create a new tracker which knows the loaders of glibc, musl and
+    /// uClibc
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Also treat files whose name starts with `prefix` as the loader
+    pub fn loader(mut self, prefix: impl Into<String>) -> Self {
+        self.loaders.push(prefix.into());
+        self
+    }
+
+    /// Check if `pc` is in the code of a dynamic loader
+    pub fn is_loader(&self, space: &AddressSpace, pc: u64) -> bool {
+        let Some(mapping) = space.lookup(pc).filter(|x| !x.anon) else {
+            return false;
+        };
+        let name = mapping.path.rsplit('/').next().unwrap_or(&mapping.path);
+        LOADERS.iter().copied().chain(self.loaders.iter().map(|x| &**x))
+            .any(|x| name.starts_with(x))
+    }
+
+    /// Find the name of the symbol starting at `addr`, in module `path` at
+    /// `offset`
+    fn symbol(&mut self, path: &Arc<str>, addr: u64, offset: u64)
+            -> Option<Arc<str>> {
+        let table = self.symbols.entry(path.clone())
+            .or_insert_with(|| SymbolTable::from_elf(&**path).ok())
+            .as_ref()?;
+
+        // Symbols of PIEs and shared libraries are relative to the image,
+        // other binaries are loaded where their symbols say
+        let base     = addr - offset;
+        let first    = table.symbols().first()?.addr;
+        let resolved = table.resolve(
+            if first >= base { addr } else { offset })?;
+        (resolved.offset == 0).then_some(resolved.symbol.name)
+    }
+
+    /// Observe the next PC executed by the thread, with `space` the mappings
+    /// of its process
+    pub fn observe(&mut self, space: &AddressSpace, pc: u64) -> Step {
+        if self.is_loader(space, pc) {
+            if !self.started {
+                return Step::Pass;
+            }
+
+            let (_, insns) = self.entered
+                .get_or_insert((self.prev.unwrap_or(pc), 0));
+            *insns += 1;
+            return Step::Loader;
+        }
+
+        if !self.started {
+            self.started = space.module_offset(pc)
+                .is_some_and(|(path, _)| Some(&path) == space.main_module());
+        }
+        self.prev = Some(pc);
+
+        let Some((from, insns)) = self.entered.take() else {
+            return Step::Pass;
+        };
+        if pc.wrapping_sub(from).wrapping_sub(1) < MAX_RETURN_GAP {
+            return Step::Returned { insns };
+        }
+
+        let module = space.module_offset(pc);
+        let symbol = module.as_ref()
+            .and_then(|(path, offset)| self.symbol(path, pc, *offset));
+        Step::Resolved(PltResolve { target: pc, from, module, symbol, insns })
+    }
+}
+
+#[test]
+fn resolve_through_loader() {
+    let mut space = AddressSpace::new();
+    space.mmap(0x1000, 0x1000, false, true, false, true, "/bin/app", 0);
+    space.mmap(0x8000, 0x1000, false, true, false, true,
+        "/lib/ld-linux-x86-64.so.2", 0);
+    space.mmap(0x9000, 0x1000, false, true, false, true, "/lib/libexample.so", 0);
+
+    let mut binding = LazyBinding::new();
+    let steps = [0x8000, 0x8004, 0x1000, 0x1004, 0x8100, 0x8104, 0x9010,
+        0x9014, 0x1010, 0x8200, 0x1014].iter()
+        .map(|&pc| binding.observe(&space, pc))
+        .collect::<Vec<_>>();
+
+    // Startup is left alone, the call from the PLT stub at 0x1004 resolves
+    // to libc, and the call at 0x1010 returns
+    assert_eq!(steps[..4], [Step::Pass, Step::Pass, Step::Pass, Step::Pass]);
+    assert_eq!(steps[4..6], [Step::Loader, Step::Loader]);
+    let Step::Resolved(resolved) = &steps[6] else {
+        panic!("Expected a resolution, got {:?}", steps[6]);
+    };
+    assert_eq!((resolved.from, resolved.target, resolved.insns),
+        (0x1004, 0x9010, 2));
+    assert_eq!(resolved.module, Some(("/lib/libexample.so".into(), 0x10)));
+    assert_eq!(steps[7..], [Step::Pass, Step::Pass, Step::Loader,
+        Step::Returned { insns: 1 }]);
+}
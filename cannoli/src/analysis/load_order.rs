@@ -0,0 +1,228 @@
+//! Load order and dependencies of the modules of a process
+//!
+//! Startup time of dynamically linked programs goes into mapping and
+//! relocating libraries, and a library nobody expected showing up in a
+//! process is worth a look in a security review. [`LoadOrder`] follows the
+//! mappings and executed PCs of a process and reports, for every module:
+//! when it was mapped, when its code first executed, and whether it was
+//! mapped at runtime (eg. by `dlopen()`) rather than at startup. It also
+//! reports which module's code first called into which, the dependency
+//! edges of the process as they were exercised.
+//!
+//! Time is counted in instructions executed by the process, which unlike
+//! wall time is the same for every run and isn't skewed by tracing.
+//!
+//! A transfer of control from one module to another is a call, unless it
+//! lands just after the place an earlier call left the module from, which
+//! is a return. Calls resolved by the dynamic loader count as calls from the
+//! module of the PLT stub to the resolved function, see
+//! [`crate::analysis::lazy_binding`], so they don't show up as dependencies
+//! on the loader. Modules are only recorded once mapped executable.
+
+use std::sync::Arc;
+use std::collections::{HashMap, HashSet};
+use crate::address_space::AddressSpace;
+use crate::analysis::lazy_binding::{LazyBinding, Step};
+
+/// Maximum distance between the instruction which left a module and the
+/// address that a return lands on, as for [`crate::analysis::functions`]
+const MAX_RETURN_GAP: u64 = 16;
+
+/// Maximum number of calls between modules waiting for their return
+const MAX_PENDING: usize = 1024;
+
+/// A module of the process
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ModuleLoad {
+    /// Path of the module
+    pub path: Arc<str>,
+
+    /// Instructions the process executed before the module was mapped
+    pub mapped: u64,
+
+    /// Instructions the process executed before the first instruction of
+    /// the module, `None` if it never executed
+    pub first_exec: Option<u64>,
+
+    /// Set if the module was mapped after the main binary started
+    /// executing, rather than by the loader at startup
+    pub runtime: bool,
+}
+
+/// The first call from a module into another
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Dependency {
+    /// Module the call came from
+    pub caller: Arc<str>,
+
+    /// Module the call went to
+    pub callee: Arc<str>,
+
+    /// Address of the last instruction executed in `caller`
+    pub from: u64,
+
+    /// Address of the first instruction executed in `callee`
+    pub to: u64,
+
+    /// Instructions the process executed before the call
+    pub insns: u64,
+}
+
+/// Tracks the modules of a single process, see the module documentation
+///
+/// Feed this the mappings and executed PCs of the threads of a process, in
+/// trace order.
+#[derive(Default)]
+pub struct LoadOrder {
+    /// Modules in the order they were mapped
+    modules: Vec<ModuleLoad>,
+
+    /// Indices into `modules`, keyed by path
+    index: HashMap<Arc<str>, usize>,
+
+    /// Dependencies in the order they were first called
+    dependencies: Vec<Dependency>,
+
+    /// Caller and callee of every dependency
+    edges: HashSet<(Arc<str>, Arc<str>)>,
+
+    /// Recognizes calls resolved by the dynamic loader
+    binding: LazyBinding,
+
+    /// Instructions executed so far
+    insns: u64,
+
+    /// Set once the main binary executed
+    started: bool,
+
+    /// Last PC executed outside of the loader's resolution, and its module
+    prev: Option<(u64, Option<Arc<str>>)>,
+
+    /// Addresses of the last instructions of calls between modules which
+    /// didn't return yet
+    pending: Vec<u64>,
+}
+
+impl LoadOrder {
+    /// Create a new, empty, tracker
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Observe a mapping of the file at `path`, empty for anonymous
+    /// mappings, which was executable if `exec` is set
+    pub fn mmap(&mut self, path: &str, exec: bool) {
+        if !exec || path.is_empty() || self.index.contains_key(path) {
+            return;
+        }
+
+        let path: Arc<str> = path.into();
+        self.index.insert(path.clone(), self.modules.len());
+        self.modules.push(ModuleLoad {
+            path,
+            mapped:     self.insns,
+            first_exec: None,
+            runtime:    self.started,
+        });
+    }
+
+    /// Observe the next PC executed by the process, with `space` its
+    /// mappings
+    pub fn exec(&mut self, space: &AddressSpace, pc: u64) {
+        let insns = self.insns;
+        self.insns += 1;
+        if self.binding.observe(space, pc) == Step::Loader {
+            return;
+        }
+
+        let module = space.module_offset(pc).map(|(path, _)| path);
+        if let Some(module) = &module {
+            if let Some(&index) = self.index.get(module) {
+                self.modules[index].first_exec.get_or_insert(insns);
+            }
+            self.started |= Some(module) == space.main_module();
+        }
+
+        let prev = self.prev.replace((pc, module.clone()));
+        let Some((from, caller)) = prev.filter(|x| x.1 != module) else {
+            return;
+        };
+        if let Some(index) = self.pending.iter()
+                .rposition(|x| pc.wrapping_sub(*x).wrapping_sub(1) <
+                    MAX_RETURN_GAP) {
+            self.pending.truncate(index);
+            return;
+        }
+
+        if self.pending.len() == MAX_PENDING {
+            self.pending.remove(0);
+        }
+        self.pending.push(from);
+        let (Some(caller), Some(callee)) = (caller, module) else {
+            return;
+        };
+        if self.edges.insert((caller.clone(), callee.clone())) {
+            self.dependencies.push(Dependency {
+                caller, callee, from, insns,
+                to: pc,
+            });
+        }
+    }
+
+    /// Get the modules of the process, in the order they were mapped
+    pub fn modules(&self) -> &[ModuleLoad] {
+        &self.modules
+    }
+
+    /// Get the dependencies between the modules of the process, in the order
+    /// they were first called
+    pub fn dependencies(&self) -> &[Dependency] {
+        &self.dependencies
+    }
+}
+
+#[test]
+fn load_order() {
+    let mut space = AddressSpace::new();
+    let mut order = LoadOrder::new();
+    let mmap = |space: &mut AddressSpace, order: &mut LoadOrder,
+            base: u64, path: &str| {
+        space.mmap(base, 0x1000, false, true, false, true, path, 0);
+        order.mmap(path, true);
+    };
+    mmap(&mut space, &mut order, 0x1000, "/bin/app");
+    mmap(&mut space, &mut order, 0x8000, "/lib/ld-linux-x86-64.so.2");
+    order.exec(&space, 0x8000);
+    mmap(&mut space, &mut order, 0x9000, "/lib/libc.so.6");
+
+    // The loader starts the main binary, which calls into libc through the
+    // loader and gets the return, then dlopen()s a library and calls it
+    for pc in [0x8004, 0x1000, 0x1004, 0x8100, 0x9010, 0x9014, 0x1008,
+            0x9020, 0x1010] {
+        order.exec(&space, pc);
+    }
+    mmap(&mut space, &mut order, 0xa000, "/lib/libplugin.so");
+    for pc in [0x1014, 0xa000, 0x9000, 0xa004, 0x1018] {
+        order.exec(&space, pc);
+    }
+
+    let modules = order.modules().iter()
+        .map(|x| (&*x.path, x.mapped, x.first_exec, x.runtime))
+        .collect::<Vec<_>>();
+    assert_eq!(modules, [
+        ("/bin/app",                  0,  Some(2),  false),
+        ("/lib/ld-linux-x86-64.so.2", 0,  Some(0),  false),
+        ("/lib/libc.so.6",            1,  Some(5),  false),
+        ("/lib/libplugin.so",         10, Some(11), true),
+    ]);
+
+    let edges = order.dependencies().iter()
+        .map(|x| (&*x.caller, &*x.callee, x.from, x.to))
+        .collect::<Vec<_>>();
+    assert_eq!(edges, [
+        ("/lib/ld-linux-x86-64.so.2", "/bin/app", 0x8004, 0x1000),
+        ("/bin/app", "/lib/libc.so.6", 0x1004, 0x9010),
+        ("/bin/app", "/lib/libplugin.so", 0x1014, 0xa000),
+        ("/lib/libplugin.so", "/lib/libc.so.6", 0xa000, 0x9000),
+    ]);
+}
@@ -0,0 +1,315 @@
+//! Instruction mix per function
+//!
+//! A static-profiler-style report of where emulated time goes: for every
+//! function, the number of instructions executed, split into loads, stores,
+//! branches and everything else, along with the number of calls it made and
+//! received.
+//!
+//! Instructions are classed by what the trace says about them. Branches are
+//! tagged when they are lifted (see the `branch` argument of the jitter's
+//! `hook_inst`, and [`crate::Cannoli::branch`]), loads and stores are the
+//! instructions which memory accesses are logged for. An instruction which
+//! both loads and stores counts as both, "other" is everything which is none
+//! of them. Without memory hooks nothing counts as a load or store, and
+//! without branch hooks only taken branches are seen, as transfers of control.
+//!
+//! Counts are kept per PC and only grouped into functions by
+//! [`InsnMix::report`], so any source of symbols (eg. functions inferred
+//! with [`crate::analysis::functions`]) can be used after the fact. A
+//! transfer of control to the start of a symbol is counted as a call, this
+//! includes tail calls and loops back to the entry of a function.
+
+use std::io::Write;
+use std::sync::Arc;
+use std::collections::HashMap;
+use crate::symbols::Resolved;
+
+/// Maximum distance between two PCs for them to be considered sequential
+/// execution rather than a transfer of control
+const MAX_INSN_LEN: u64 = 16;
+
+/// Counts for a single PC
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct PcCounts {
+    /// Number of times the instruction executed
+    execs: u64,
+
+    /// Executions which loaded from memory
+    loads: u64,
+
+    /// Executions which stored to memory
+    stores: u64,
+
+    /// Executions as a branch
+    branches: u64,
+
+    /// Executions which were none of the above
+    other: u64,
+}
+
+/// The instruction currently executing
+#[derive(Clone, Copy, Debug)]
+struct Current {
+    /// PC of the instruction
+    pc: u64,
+
+    /// Set if it's a branch
+    branch: bool,
+
+    /// Set if it loaded from memory
+    load: bool,
+
+    /// Set if it stored to memory
+    store: bool,
+}
+
+impl PcCounts {
+    /// Count an execution of `insn`
+    fn add(&mut self, insn: &Current) {
+        self.execs    += 1;
+        self.loads    += insn.load as u64;
+        self.stores   += insn.store as u64;
+        self.branches += insn.branch as u64;
+        self.other    += !(insn.load || insn.store || insn.branch) as u64;
+    }
+
+    /// Add the counts of `other`
+    fn merge(&mut self, other: &PcCounts) {
+        self.execs    += other.execs;
+        self.loads    += other.loads;
+        self.stores   += other.stores;
+        self.branches += other.branches;
+        self.other    += other.other;
+    }
+}
+
+/// Instruction mix of a single function
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FunctionMix {
+    /// Name of the function, `None` for PCs the resolver had no symbol for
+    pub name: Option<Arc<str>>,
+
+    /// Address of the start of the function, 0 if it has no name
+    pub addr: u64,
+
+    /// Instructions executed in the function
+    pub insns: u64,
+
+    /// Instructions which loaded from memory
+    pub loads: u64,
+
+    /// Instructions which stored to memory
+    pub stores: u64,
+
+    /// Branch instructions
+    pub branches: u64,
+
+    /// Instructions which are neither loads, stores, nor branches
+    pub other: u64,
+
+    /// Calls the function made
+    pub calls_made: u64,
+
+    /// Number of times the function was called
+    pub calls: u64,
+}
+
+impl FunctionMix {
+    /// Average number of instructions executed in the function (not
+    /// counting its callees) per call, `None` if it was never called
+    pub fn insns_per_call(&self) -> Option<f64> {
+        (self.calls > 0).then(|| self.insns as f64 / self.calls as f64)
+    }
+}
+
+/// Counts the instruction mix of a thread, see the module documentation
+///
+/// Feed this the executed PCs and memory accesses of a single thread, in
+/// trace order. Use a separate instance per thread and
+/// [`InsnMix::merge`] them together at the end.
+#[derive(Default)]
+pub struct InsnMix {
+    /// Instruction currently executing, it's counted once the next one
+    /// executes
+    current: Option<Current>,
+
+    /// Counts, keyed by PC
+    pcs: HashMap<u64, PcCounts>,
+
+    /// Number of transfers of control, keyed by source and target PC
+    transfers: HashMap<(u64, u64), u64>,
+}
+
+impl InsnMix {
+    /// Create a new, empty, mix
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count the current instruction
+    fn retire(&mut self) {
+        if let Some(insn) = self.current.take() {
+            self.pcs.entry(insn.pc).or_default().add(&insn);
+        }
+    }
+
+    /// Record the execution of the instruction at `pc`, `branch` is set if
+    /// it was tagged as a branch when it was lifted
+    pub fn exec(&mut self, pc: u64, branch: bool) {
+        if let Some(prev) = &mut self.current {
+            if pc.wrapping_sub(prev.pc).wrapping_sub(1) >= MAX_INSN_LEN {
+                *self.transfers.entry((prev.pc, pc)).or_default() += 1;
+
+                // A taken branch, even if we didn't get a tag for it
+                prev.branch = true;
+            }
+        }
+
+        self.retire();
+        self.current = Some(Current {
+            pc, branch,
+            load:  false,
+            store: false,
+        });
+    }
+
+    /// Record a load by the instruction at `pc`
+    pub fn read(&mut self, pc: u64) {
+        match &mut self.current {
+            Some(insn) if insn.pc == pc => insn.load = true,
+            _ => {}
+        }
+    }
+
+    /// Record a store by the instruction at `pc`
+    pub fn write(&mut self, pc: u64) {
+        match &mut self.current {
+            Some(insn) if insn.pc == pc => insn.store = true,
+            _ => {}
+        }
+    }
+
+    /// Merge the counts of `other` into this one
+    pub fn merge(&mut self, other: &InsnMix) {
+        for (pc, counts) in &other.pcs {
+            self.pcs.entry(*pc).or_default().merge(counts);
+        }
+        if let Some(insn) = &other.current {
+            self.pcs.entry(insn.pc).or_default().add(insn);
+        }
+        for (transfer, count) in &other.transfers {
+            *self.transfers.entry(*transfer).or_default() += count;
+        }
+    }
+
+    /// Group the counts into functions, with `resolve` finding the symbol of
+    /// a PC (eg. [`crate::symbols::ResolverChain::resolve`]). Functions are
+    /// sorted by the number of instructions executed, most first
+    pub fn report(&self, resolve: impl Fn(u64) -> Option<Resolved>)
+            -> Vec<FunctionMix> {
+        let mut pcs = self.pcs.clone();
+        if let Some(insn) = &self.current {
+            pcs.entry(insn.pc).or_default().add(insn);
+        }
+
+        // Function of every PC, keyed by the start of the function
+        let mut functions: HashMap<u64, FunctionMix> = HashMap::new();
+        let mut by_pc: HashMap<u64, u64> = HashMap::new();
+        let all = pcs.keys().chain(self.transfers.keys()
+            .flat_map(|(from, to)| [from, to]));
+        for &pc in all {
+            by_pc.entry(pc).or_insert_with(|| {
+                let resolved = resolve(pc);
+                let addr = resolved.as_ref().map_or(0, |x| x.symbol.addr);
+                functions.entry(addr).or_insert_with(|| FunctionMix {
+                    name: resolved.map(|x| x.symbol.name),
+                    addr,
+                    ..Default::default()
+                });
+                addr
+            });
+        }
+
+        for (pc, x) in &pcs {
+            let mix = functions.get_mut(&by_pc[pc]).unwrap();
+            mix.insns    += x.execs;
+            mix.loads    += x.loads;
+            mix.stores   += x.stores;
+            mix.branches += x.branches;
+            mix.other    += x.other;
+        }
+        for ((from, to), count) in &self.transfers {
+            let target = by_pc[to];
+            if *to == target && functions[&target].name.is_some() {
+                functions.get_mut(&by_pc[from]).unwrap().calls_made += count;
+                functions.get_mut(&target).unwrap().calls += count;
+            }
+        }
+
+        let mut ret = functions.into_values().collect::<Vec<_>>();
+        ret.sort_by(|a, b| b.insns.cmp(&a.insns).then(a.addr.cmp(&b.addr)));
+        ret
+    }
+}
+
+/// Write `functions` as a text table
+pub fn write_table(functions: &[FunctionMix], mut out: impl Write)
+        -> std::io::Result<()> {
+    writeln!(out, "{:>12} {:>6} {:>6} {:>6} {:>6} {:>10} {:>10} {:>10}  \
+        function", "insns", "load%", "store%", "br%", "other%", "calls",
+        "calls out", "insns/call")?;
+
+    for func in functions {
+        let pct = |x: u64| x as f64 * 100. / func.insns.max(1) as f64;
+        let name = func.name.as_ref().map_or("?".to_string(),
+            |x| format!("{x} ({:#x})", func.addr));
+        writeln!(out, "{:>12} {:>6.1} {:>6.1} {:>6.1} {:>6.1} {:>10} {:>10} \
+            {:>10}  {name}", func.insns, pct(func.loads), pct(func.stores),
+            pct(func.branches), pct(func.other), func.calls, func.calls_made,
+            func.insns_per_call().map_or("-".to_string(),
+                |x| format!("{x:.1}")))?;
+    }
+
+    Ok(())
+}
+
+#[test]
+fn mix_per_function() {
+    use crate::symbols::{Resolver, Symbol, SymbolTable};
+
+    let symbols = SymbolTable::new("test", vec![
+        Symbol { name: "main".into(), addr: 0x1000, size: None,
+            mangled: None },
+        Symbol { name: "copy".into(), addr: 0x2000, size: None,
+            mangled: None },
+    ]);
+
+    // `main` calls `copy` twice in a loop, which loads and stores once per
+    // call
+    let mut mix = InsnMix::new();
+    mix.exec(0x1000, false);
+    for _ in 0..2 {
+        mix.exec(0x1004, true);
+        mix.exec(0x2000, false);
+        mix.read(0x2000);
+        mix.exec(0x2004, false);
+        mix.write(0x2004);
+        mix.exec(0x2008, true);
+        mix.exec(0x1008, false);
+        mix.exec(0x100c, false);
+    }
+
+    let report = mix.report(|x| symbols.resolve(x));
+    let main = &report[0];
+    assert_eq!(main.name.as_deref(), Some("main"));
+    assert_eq!((main.insns, main.branches, main.other), (7, 3, 4));
+    assert_eq!((main.calls, main.calls_made), (0, 2));
+    assert_eq!(main.insns_per_call(), None);
+
+    // Returns and loops are transfers, but not to the start of a function
+    let copy = &report[1];
+    assert_eq!((copy.insns, copy.loads, copy.stores, copy.branches,
+        copy.other), (6, 2, 2, 2, 0));
+    assert_eq!((copy.calls, copy.calls_made), (2, 0));
+    assert_eq!(copy.insns_per_call(), Some(3.));
+}
@@ -0,0 +1,22 @@
+//! Reusable analyses which can be driven from a [`crate::Cannoli`]
+//! implementation
+//!
+//! These are plain state machines which are fed events in trace order, thus
+//! they are meant to be used from the sequential [`crate::Cannoli::trace`]
+//! callback rather than the parallel callbacks
+
+pub mod cfg;
+pub mod cmp;
+pub mod concolic;
+pub mod dictionary;
+pub mod functions;
+pub mod integrity;
+pub mod lazy_binding;
+pub mod load_order;
+pub mod layout;
+pub mod mix;
+pub mod rep;
+pub mod source_coverage;
+pub mod suggest;
+pub mod topk;
+pub mod unreached;
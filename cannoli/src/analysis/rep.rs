@@ -0,0 +1,238 @@
+//! Summarizing block-transfer instructions
+//!
+//! QEMU executes an x86 `rep movs`/`stos`/`lods`/`cmps`/`scas` one iteration
+//! at a time by jumping back to the instruction, so every iteration shows up
+//! in the trace as another execution of the same PC with its own memory
+//! accesses. A `memset()` of a page is thousands of events, which floods the
+//! trace for analyses that only care that the transfer happened.
+//!
+//! [`RepCollapser`] recognizes these runs without decoding instructions: the
+//! same PC executing back to back, where every iteration makes the same
+//! accesses as the first with each address moved by the size of the access,
+//! in the same direction. A run is replaced with a single [`Event::Rep`]
+//! holding the count, the direction and the accesses of the first iteration.
+//! The values moved are lost, keep the full trace when they matter.
+//!
+//! Iterations which break the pattern (eg. the final check of the count on
+//! some QEMU versions, which executes the PC without accessing memory) are
+//! passed through unchanged, as are runs of a single iteration.
+//!
+//! The summary is only made in the client, after the events arrived: the
+//! jitter still streams every iteration, so this makes traces smaller to
+//! store and analyze but doesn't reduce what QEMU sends. To keep block
+//! transfers from flooding the pipe, leave their code or their memory
+//! accesses out with the filters, see [`crate::control::Filters`].
+
+use crate::event::{Event, RepAccess};
+
+/// A run of iterations of the same PC
+struct Run {
+    /// PC of the block-transfer instruction
+    pc: u64,
+
+    /// Number of iterations so far
+    count: u64,
+
+    /// Direction the addresses move in, `None` until the second iteration
+    backward: Option<bool>,
+
+    /// Accesses of the first iteration
+    accesses: Vec<RepAccess>,
+
+    /// Events of the first iteration, emitted as they were if the run ends up
+    /// being a single iteration
+    first: Vec<Event>,
+}
+
+/// Get the memory access of `event`, if it is one
+fn access(event: &Event) -> Option<RepAccess> {
+    match *event {
+        Event::Read { addr, sz, .. } | Event::ReadAddr { addr, sz, .. } =>
+            Some(RepAccess { write: false, addr, sz }),
+        Event::Write { addr, sz, .. } | Event::WriteAddr { addr, sz, .. } =>
+            Some(RepAccess { write: true, addr, sz }),
+        _ => None,
+    }
+}
+
+/// Collapses runs of block-transfer iterations in a trace, see the module
+/// documentation
+///
+/// This holds on to the latest iteration until it knows whether the run
+/// continues, so call [`RepCollapser::finish`] once the trace ends.
+#[derive(Default)]
+pub struct RepCollapser {
+    /// Events of the iteration currently being received, starting with its
+    /// [`Event::Exec`]
+    iteration: Vec<Event>,
+
+    /// Run the previous iterations are part of
+    run: Option<Run>,
+}
+
+impl RepCollapser {
+    /// Create a new collapser
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed the next `event` of the trace, appending the events which are
+    /// final to `out`
+    pub fn push(&mut self, event: Event, out: &mut Vec<Event>) {
+        match event {
+            Event::Exec { .. } => {
+                self.end_iteration(out);
+                self.iteration.push(event);
+            }
+            _ if access(&event).is_some() && !self.iteration.is_empty() => {
+                self.iteration.push(event);
+            }
+            _ => {
+                self.end_iteration(out);
+                self.end_run(out);
+                out.push(event);
+            }
+        }
+    }
+
+    /// Feed every event of `events`, see [`RepCollapser::push`]
+    pub fn extend(&mut self, events: impl IntoIterator<Item = Event>,
+            out: &mut Vec<Event>) {
+        for event in events {
+            self.push(event, out);
+        }
+    }
+
+    /// The trace ended, append everything still held back to `out`
+    pub fn finish(&mut self, out: &mut Vec<Event>) {
+        self.end_iteration(out);
+        self.end_run(out);
+    }
+
+    /// Emit the current run, summarized if it's more than one iteration
+    fn end_run(&mut self, out: &mut Vec<Event>) {
+        let Some(run) = self.run.take() else { return };
+        if run.count == 1 {
+            out.extend(run.first);
+        } else {
+            out.push(Event::Rep {
+                pc:       run.pc,
+                count:    run.count,
+                backward: run.backward.unwrap_or(false),
+                accesses: run.accesses,
+            });
+        }
+    }
+
+    /// The current iteration is complete, add it to the run if it continues
+    /// it or start a new run with it
+    fn end_iteration(&mut self, out: &mut Vec<Event>) {
+        let events = std::mem::take(&mut self.iteration);
+        let Some(Event::Exec { pc }) = events.first() else { return };
+        let pc = *pc;
+        let accesses = events[1..].iter().filter_map(access)
+            .collect::<Vec<_>>();
+
+        if let Some(run) = &mut self.run {
+            if run.pc == pc && Self::continues(run, &accesses) {
+                run.count += 1;
+                return;
+            }
+        }
+        self.end_run(out);
+
+        // Only iterations which access memory can start a run, a PC which
+        // executes back to back without doing so is just a loop
+        if accesses.is_empty() {
+            out.extend(events);
+        } else {
+            self.run = Some(Run {
+                count:    1,
+                backward: None,
+                first:    events,
+                pc, accesses,
+            });
+        }
+    }
+
+    /// Check if an iteration with `accesses` is the next iteration of `run`,
+    /// settling the direction of the run on its second iteration
+    fn continues(run: &mut Run, accesses: &[RepAccess]) -> bool {
+        if accesses.len() != run.accesses.len() {
+            return false;
+        }
+
+        let expected = |backward: bool| {
+            run.accesses.iter().zip(accesses).all(|(first, x)| {
+                let delta = (first.sz as u64).wrapping_mul(run.count);
+                let addr  = if backward {
+                    first.addr.wrapping_sub(delta)
+                } else {
+                    first.addr.wrapping_add(delta)
+                };
+                first.write == x.write && first.sz == x.sz && x.addr == addr
+            })
+        };
+
+        match run.backward {
+            Some(backward) => expected(backward),
+            None => {
+                let backward = if expected(false) {
+                    false
+                } else if expected(true) {
+                    true
+                } else {
+                    return false;
+                };
+                run.backward = Some(backward);
+                true
+            }
+        }
+    }
+}
+
+#[test]
+fn collapse_rep_movs() {
+    let mut collapser = RepCollapser::new();
+    let mut out = Vec::new();
+
+    // `rep movsd` of 3 dwords, followed by the final check of the count and
+    // the next instruction
+    collapser.push(Event::Exec { pc: 0x1000 }, &mut out);
+    for ii in 0..3 {
+        collapser.extend([
+            Event::Exec  { pc: 0x1002 },
+            Event::Read  { pc: 0x1002, addr: 0x5000 + ii * 4, val: 0, sz: 4 },
+            Event::Write { pc: 0x1002, addr: 0x6000 + ii * 4, val: 0, sz: 4 },
+        ], &mut out);
+    }
+    collapser.extend([
+        Event::Exec { pc: 0x1002 },
+        Event::Exec { pc: 0x1004 },
+    ], &mut out);
+    collapser.finish(&mut out);
+
+    assert_eq!(out, [
+        Event::Exec { pc: 0x1000 },
+        Event::Rep  { pc: 0x1002, count: 3, backward: false, accesses: vec![
+            RepAccess { write: false, addr: 0x5000, sz: 4 },
+            RepAccess { write: true,  addr: 0x6000, sz: 4 },
+        ] },
+        Event::Exec { pc: 0x1002 },
+        Event::Exec { pc: 0x1004 },
+    ]);
+
+    // A single iteration, and accesses which don't move, are left alone
+    let mut out = Vec::new();
+    let events = [
+        Event::Exec  { pc: 0x2000 },
+        Event::Write { pc: 0x2000, addr: 0x7000, val: 1, sz: 1 },
+        Event::Exec  { pc: 0x2004 },
+        Event::Read  { pc: 0x2004, addr: 0x8000, val: 0, sz: 8 },
+        Event::Exec  { pc: 0x2004 },
+        Event::Read  { pc: 0x2004, addr: 0x8000, val: 1, sz: 8 },
+    ];
+    collapser.extend(events.clone(), &mut out);
+    collapser.finish(&mut out);
+    assert_eq!(out, events);
+}
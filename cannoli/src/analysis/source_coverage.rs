@@ -0,0 +1,162 @@
+//! Coverage keyed by source line, which survives rebuilds of the target
+//!
+//! Coverage kept by address is only good for the build it was taken on, a
+//! fuzzing campaign which recompiles its target starts over every time. A
+//! [`SourceCoverage`] maps every covered instruction to its
+//! [`SourceLine`] from the DWARF line table of its module, and can be saved
+//! and loaded again to keep adding to it after the target changed:
+//!
+//! ```no_run
+//! use cannoli::analysis::source_coverage::SourceCoverage;
+//!
+//! let mut coverage = SourceCoverage::load("coverage.lines").unwrap();
+//! // For every PC in a trace, with `AddressSpace::module_offset`
+//! coverage.add(&"/work/target".into(), 0x1149);
+//! coverage.save("coverage.lines").unwrap();
+//! ```
+//!
+//! Instructions of modules without debug information, and the few without
+//! a line in a module with one, can't be keyed this way and are only
+//! counted.
+
+use std::sync::Arc;
+use std::path::Path;
+use std::collections::{BTreeSet, HashMap};
+use crate::symbols::lines::{LineTable, SourceLine};
+
+/// Covered source lines, see the module documentation
+#[derive(Clone, Debug, Default)]
+pub struct SourceCoverage {
+    /// Line tables of the modules seen, `None` if a module has none or it
+    /// couldn't be read
+    tables: HashMap<Arc<str>, Option<Arc<LineTable>>>,
+
+    /// Covered lines
+    lines: BTreeSet<SourceLine>,
+
+    /// Number of instructions added which have no line
+    unmapped: u64,
+}
+
+impl SourceCoverage {
+    /// Create empty coverage
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load coverage saved with [`Self::save`], empty coverage if `path`
+    /// doesn't exist yet
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let mut ret = Self::default();
+        let contents = match std::fs::read_to_string(path) {
+            Ok(x) => x,
+            Err(x) if x.kind() == std::io::ErrorKind::NotFound =>
+                return Ok(ret),
+            Err(x) => return Err(x),
+        };
+
+        // <file>:<line>, file names may have colons of their own
+        for entry in contents.lines().filter(|x| !x.is_empty()) {
+            let line = entry.rsplit_once(':')
+                .and_then(|(file, line)| Some(SourceLine {
+                    file: file.into(),
+                    line: line.parse().ok()?,
+                }))
+                .ok_or_else(|| std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("invalid coverage entry `{entry}`")))?;
+            ret.lines.insert(line);
+        }
+        Ok(ret)
+    }
+
+    /// Save the covered lines to `path`, one `<file>:<line>` per line
+    pub fn save(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        std::fs::write(path, self.lines.iter()
+            .map(|x| format!("{}:{}\n", x.file, x.line))
+            .collect::<String>())
+    }
+
+    /// Use `table` as the line table of `module` rather than reading it
+    /// from the file
+    pub fn set_table(&mut self, module: Arc<str>, table: LineTable) {
+        let table = (!table.is_empty()).then(|| Arc::new(table));
+        self.tables.insert(module, table);
+    }
+
+    /// Add the instruction at `offset` in `module`, as given by
+    /// [`crate::address_space::AddressSpace::module_offset`]. Returns if it
+    /// covered a new line
+    pub fn add(&mut self, module: &Arc<str>, offset: u64) -> bool {
+        let table = self.tables.entry(module.clone()).or_insert_with(|| {
+            LineTable::from_elf(&**module).ok()
+                .filter(|x| !x.is_empty())
+                .map(Arc::new)
+        });
+
+        match table.as_ref().and_then(|x| x.line_at_offset(offset)) {
+            Some(line) if self.lines.contains(line) => false,
+            Some(line) => self.lines.insert(line.clone()),
+            None => {
+                self.unmapped += 1;
+                false
+            }
+        }
+    }
+
+    /// Add every line covered by `other`
+    pub fn merge(&mut self, other: &Self) {
+        self.lines.extend(other.lines.iter().cloned());
+        self.unmapped += other.unmapped;
+    }
+
+    /// Get the covered lines, ordered by file and line
+    pub fn lines(&self) -> impl Iterator<Item = &SourceLine> {
+        self.lines.iter()
+    }
+
+    /// Get the number of covered lines
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    /// Check if no line is covered
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    /// Get the number of instructions added which have no line
+    pub fn unmapped(&self) -> u64 {
+        self.unmapped
+    }
+}
+
+#[test]
+fn source_coverage() {
+    let headers = "  LOAD 0x000000 0x400000 0x400000 0x2000 0x2000 R E\n";
+    let lines = "CU: /src/a:b.c:\na:b.c 7 0x401000 x\na:b.c 8 0x401008 x\n\
+                 a:b.c - 0x401010\n";
+    let module: Arc<str> = "/bin/target".into();
+
+    let mut coverage = SourceCoverage::new();
+    coverage.set_table(module.clone(), LineTable::parse(headers, lines));
+    assert!(coverage.add(&module, 0x1000));
+    assert!(coverage.add(&module, 0x1008));
+    assert!(!coverage.add(&module, 0x1004));
+    assert!(!coverage.add(&module, 0x1010));
+    assert_eq!(coverage.unmapped(), 1);
+
+    // The next build moved the code, the lines stay covered
+    let path = std::env::temp_dir()
+        .join(format!("cannoli_source_coverage_{}", std::process::id()));
+    coverage.save(&path).unwrap();
+    let mut loaded = SourceCoverage::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let moved = "CU: /src/a:b.c:\na:b.c 8 0x401100 x\na:b.c 9 0x401108 x\n";
+    loaded.set_table(module.clone(), LineTable::parse(headers, moved));
+    assert_eq!(loaded.len(), 2);
+    assert!(!loaded.add(&module, 0x1100));
+    assert!(loaded.add(&module, 0x1108));
+    assert_eq!(loaded.lines().map(|x| x.line).collect::<Vec<_>>(),
+        [7, 8, 9]);
+}
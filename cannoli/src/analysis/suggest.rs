@@ -0,0 +1,310 @@
+//! Filter rules suggested by how much of a trace every block produced
+//!
+//! Most of the events of a trace tend to come from a handful of hot loops
+//! (`memcpy()`, a checksum, the allocator) that nobody looks at. After a
+//! short capture, a [`FilterSuggester`] fed with the events of a thread
+//! counts the events of every block the thread executed, and
+//! [`FilterSuggester::suggest`] picks the fewest blocks responsible for a
+//! given share of the events as [`Filters::exclude`] ranges:
+//!
+//! ```
+//! use cannoli::analysis::suggest::FilterSuggester;
+//! use cannoli::event::Event;
+//!
+//! let mut suggester = FilterSuggester::new();
+//! for _ in 0..9 {
+//!     suggester.event(&Event::Exec { pc: 0x1000 });
+//!     suggester.event(&Event::Exec { pc: 0x1004 });
+//! }
+//! suggester.event(&Event::Exec { pc: 0x2000 });
+//! let suggestion = suggester.suggest(0.8, 16);
+//! assert_eq!(suggestion.exclude[0].range(), [0x1000, 0x1005]);
+//! ```
+//!
+//! [`Suggestion::write`] prints them in the syntax of [`crate::config`], to
+//! paste into the config of the next run, and [`Suggestion::apply`] adds
+//! them to filters, eg. to push them to a running capture with
+//! [`crate::control::set_filters`].
+//!
+//! Blocks are straight-line runs of executed PCs, cut where control doesn't
+//! go to the next instruction, and runs which overlap are merged. Events
+//! of code which never sent exec, register or branch events (eg. memory
+//! events of code hooked `once`) are counted by instruction. The ranges are
+//! addresses, so they only fit runs where the code is mapped at the same
+//! addresses, which it is for the same target under QEMU unless it loads
+//! modules in a different order.
+
+use std::io::Write;
+use std::collections::{BTreeMap, HashMap};
+use crate::control::Filters;
+use crate::event::Event;
+use crate::grep::event_pc;
+
+/// Maximum distance between two PCs for them to be considered sequential
+/// execution rather than a transfer of control, see
+/// [`crate::analysis::cfg`]
+const MAX_INSN_LEN: u64 = 16;
+
+/// A range of code and the events it produced
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HotBlock {
+    /// Address of the first instruction
+    pub start: u64,
+
+    /// Address just past the start of the last instruction, which is enough
+    /// for filters which look at the PC of instructions
+    pub end: u64,
+
+    /// Number of events with a PC in the block
+    pub events: u64,
+}
+
+impl HotBlock {
+    /// Get the block as a `[start, end)` range of [`Filters::exclude`]
+    pub fn range(&self) -> [u64; 2] {
+        [self.start, self.end]
+    }
+}
+
+/// Blocks suggested for exclusion, see [`FilterSuggester::suggest`]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Suggestion {
+    /// Blocks to exclude, most events first
+    pub exclude: Vec<HotBlock>,
+
+    /// Number of events of the excluded blocks
+    pub events: u64,
+
+    /// Number of events with a PC in the trace
+    pub total: u64,
+}
+
+impl Suggestion {
+    /// Get the share of the events which excluding the blocks drops, from 0
+    /// to 1
+    pub fn share(&self) -> f64 {
+        self.events as f64 / self.total.max(1) as f64
+    }
+
+    /// Add the ranges of the blocks to the excluded ranges of `filters`
+    /// which aren't there yet
+    pub fn apply(&self, filters: &mut Filters) {
+        for block in &self.exclude {
+            if !filters.exclude.contains(&block.range()) {
+                filters.exclude.push(block.range());
+            }
+        }
+    }
+
+    /// Write the `exclude` rule of `filters` with the blocks applied to it
+    /// to `out`, as the `[filters]` table of a config file. Blocks get a
+    /// comment with their share of the events and with `label` of their
+    /// start if it gives one, eg. the module and offset
+    pub fn write(&self, mut out: impl Write, filters: &Filters,
+            label: impl Fn(u64) -> Option<String>) -> std::io::Result<()> {
+        writeln!(out, "# Excluding these {} blocks drops {:.2}% of the {} \
+            events", self.exclude.len(), self.share() * 100., self.total)?;
+        writeln!(out, "[filters]")?;
+        writeln!(out, "exclude = [")?;
+        for range in &filters.exclude {
+            if !self.exclude.iter().any(|x| x.range() == *range) {
+                writeln!(out, "    [{:#x}, {:#x}],", range[0], range[1])?;
+            }
+        }
+        for block in &self.exclude {
+            let share = block.events as f64 * 100. / self.total.max(1) as f64;
+            let label = label(block.start)
+                .map_or(String::new(), |x| format!(" {x}"));
+            writeln!(out, "    [{:#x}, {:#x}], # {share:6.2}%{label}",
+                block.start, block.end)?;
+        }
+        writeln!(out, "]")
+    }
+}
+
+/// Counts the events of every block of a thread, see the module
+/// documentation
+///
+/// Use a separate instance per thread and [`FilterSuggester::merge`] them
+/// together.
+#[derive(Clone, Debug, Default)]
+pub struct FilterSuggester {
+    /// Last executed PC, and the start of the block it's in
+    prev: Option<(u64, u64)>,
+
+    /// End of every block seen, keyed by its start
+    blocks: BTreeMap<u64, u64>,
+
+    /// Number of events of every PC
+    counts: HashMap<u64, u64>,
+
+    /// Number of events with a PC
+    total: u64,
+}
+
+impl FilterSuggester {
+    /// Create a suggester which hasn't seen any events
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Observe the next event of the thread
+    pub fn event(&mut self, event: &Event) {
+        let Some(pc) = event_pc(event) else { return };
+        self.total += 1;
+        *self.counts.entry(pc).or_default() += 1;
+
+        if matches!(event, Event::Exec { .. } | Event::Regs { .. } |
+                Event::Branch { .. }) {
+            self.executed(pc);
+        }
+    }
+
+    /// Observe that the instruction at `pc` executed, extending the block
+    /// of the previous one if it's sequential
+    fn executed(&mut self, pc: u64) {
+        let start = match self.prev {
+            // Sequential if `0 < pc - prev <= max`
+            Some((prev, start))
+                if pc.wrapping_sub(prev).wrapping_sub(1) < MAX_INSN_LEN =>
+                start,
+            _ => pc,
+        };
+        self.prev = Some((pc, start));
+
+        let end = self.blocks.entry(start).or_insert(pc);
+        *end = (*end).max(pc.saturating_add(1));
+    }
+
+    /// Merge the events counted by `other` into `self`
+    pub fn merge(&mut self, other: &FilterSuggester) {
+        for (&start, &end) in &other.blocks {
+            let ours = self.blocks.entry(start).or_insert(end);
+            *ours = (*ours).max(end);
+        }
+        for (&pc, &count) in &other.counts {
+            *self.counts.entry(pc).or_default() += count;
+        }
+        self.total += other.total;
+    }
+
+    /// Get the number of events with a PC seen so far
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Get every block with the events it produced, sorted by address.
+    /// Overlapping blocks are merged, and PCs outside of any block are
+    /// blocks of their own
+    pub fn blocks(&self) -> Vec<HotBlock> {
+        let mut ret: Vec<HotBlock> = Vec::new();
+        for (&start, &end) in &self.blocks {
+            match ret.last_mut() {
+                Some(last) if start < last.end => last.end = last.end.max(end),
+                _ => ret.push(HotBlock { start, end, events: 0 }),
+            }
+        }
+
+        let mut orphans = Vec::new();
+        for (&pc, &count) in &self.counts {
+            let idx = ret.partition_point(|x| x.start <= pc);
+            match idx.checked_sub(1).map(|x| &mut ret[x]) {
+                Some(block) if pc < block.end => block.events += count,
+                _ => orphans.push(HotBlock {
+                    start:  pc,
+                    end:    pc.saturating_add(1),
+                    events: count,
+                }),
+            }
+        }
+
+        ret.extend(orphans);
+        ret.sort_unstable_by_key(|x| x.start);
+        ret
+    }
+
+    /// Suggest the fewest blocks which together produced at least `share`
+    /// (from 0 to 1) of the events, but at most `max` of them
+    pub fn suggest(&self, share: f64, max: usize) -> Suggestion {
+        let mut blocks = self.blocks();
+        blocks.sort_unstable_by(|a, b| b.events.cmp(&a.events)
+            .then_with(|| a.start.cmp(&b.start)));
+
+        let goal = (self.total as f64 * share.clamp(0., 1.)).ceil() as u64;
+        let mut ret = Suggestion { total: self.total, ..Default::default() };
+        for block in blocks {
+            if ret.events >= goal || ret.exclude.len() >= max ||
+                    block.events == 0 {
+                break;
+            }
+            ret.events += block.events;
+            ret.exclude.push(block);
+        }
+        ret
+    }
+}
+
+#[test]
+#[cfg(feature = "client")]
+fn suggest_filters() {
+    let mut suggester = FilterSuggester::new();
+    for _ in 0..50 {
+        // A hot loop with a read, entered in its middle once below
+        for pc in [0x1000u64, 0x1004, 0x1008] {
+            suggester.event(&Event::Exec { pc });
+        }
+        suggester.event(&Event::Read { pc: 0x1008, addr: 0x8000, val: 0,
+            sz: 4 });
+
+        // A colder loop
+        for pc in [0x2000u64, 0x2002] {
+            suggester.event(&Event::Exec { pc });
+        }
+    }
+    for pc in [0x1004u64, 0x1008, 0x100c, 0x3000] {
+        suggester.event(&Event::Exec { pc });
+    }
+
+    // Writes of code without exec events are counted by instruction
+    let mut other = FilterSuggester::new();
+    other.event(&Event::Write { pc: 0x5000, addr: 0x8000, val: 0, sz: 1 });
+    suggester.merge(&other);
+    assert_eq!(suggester.total(), 305);
+
+    assert_eq!(suggester.blocks(), [
+        HotBlock { start: 0x1000, end: 0x100d, events: 203 },
+        HotBlock { start: 0x2000, end: 0x2003, events: 100 },
+        HotBlock { start: 0x3000, end: 0x3001, events: 1 },
+        HotBlock { start: 0x5000, end: 0x5001, events: 1 },
+    ]);
+
+    let suggestion = suggester.suggest(0.8, 16);
+    assert_eq!(suggestion.exclude.iter().map(|x| x.range())
+        .collect::<Vec<_>>(), [[0x1000, 0x100d], [0x2000, 0x2003]]);
+    assert_eq!(suggestion.events, 303);
+    assert_eq!(suggester.suggest(0.8, 1).exclude.len(), 1);
+    assert!(suggester.suggest(0., 16).exclude.is_empty());
+
+    let mut filters = Filters {
+        exclude: vec![[0x9000, 0x9100], [0x2000, 0x2003]],
+        ..Filters::default()
+    };
+    let mut out = Vec::new();
+    suggestion.write(&mut out, &filters,
+        |x| (x == 0x1000).then(|| "libtarget+0x1000".into())).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(),
+        "# Excluding these 2 blocks drops 99.34% of the 305 events\n\
+         [filters]\n\
+         exclude = [\n    \
+             [0x9000, 0x9100],\n    \
+             [0x1000, 0x100d], #  66.56% libtarget+0x1000\n    \
+             [0x2000, 0x2003], #  32.79%\n\
+         ]\n");
+
+    suggestion.apply(&mut filters);
+    assert_eq!(filters.exclude,
+        [[0x9000, 0x9100], [0x2000, 0x2003], [0x1000, 0x100d]]);
+    let config = crate::config::Config::parse(
+        "[filters]\nexclude = [\n    [0x1000, 0x100d], # hot\n]").unwrap();
+    assert_eq!(config.filters.exclude, [[0x1000, 0x100d]]);
+}
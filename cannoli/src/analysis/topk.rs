@@ -0,0 +1,168 @@
+//! Hottest keys of a stream in bounded memory
+//!
+//! Counting every PC of a long trace exactly takes memory proportional to
+//! the code it covered, and a JIT or a large binary can make that a lot. A
+//! [`TopK`] keeps a fixed number of counters with the space-saving
+//! algorithm (Metwally, Agrawal, El Abbadi, 2005): a key without a counter
+//! takes over the smallest one, inheriting its count as possible error. Any
+//! key which occurred more than `total / capacity` times is guaranteed to
+//! have a counter, and no count is ever too low.
+//!
+//! Keys are anything hashable, PCs for the hottest instructions, or the
+//! start of the containing symbol for the hottest functions:
+//!
+//! ```
+//! use cannoli::analysis::topk::TopK;
+//!
+//! let mut hot = TopK::new(2);
+//! for pc in [0x1000u64, 0x1000, 0x2000, 0x3000, 0x1000] {
+//!     hot.add(pc, 1);
+//! }
+//! assert_eq!(hot.top(1)[0].key, 0x1000);
+//! ```
+
+use std::hash::Hash;
+use std::collections::{BTreeSet, HashMap};
+
+/// The count of a key in a [`TopK`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Counter<K> {
+    /// The key
+    pub key: K,
+
+    /// Number of occurrences, which is never less than the real number
+    pub count: u64,
+
+    /// Maximum amount `count` is too high by
+    pub error: u64,
+}
+
+impl<K> Counter<K> {
+    /// Get the number of occurrences which are guaranteed to have happened
+    pub fn guaranteed(&self) -> u64 {
+        self.count - self.error
+    }
+}
+
+/// Approximate counts of the most frequent keys, see the module
+/// documentation
+#[derive(Clone, Debug)]
+pub struct TopK<K> {
+    /// Maximum number of counters
+    capacity: usize,
+
+    /// Count and error of every key with a counter
+    counters: HashMap<K, (u64, u64)>,
+
+    /// Keys with a counter ordered by count, to find the smallest one
+    by_count: BTreeSet<(u64, K)>,
+
+    /// Sum of every weight added
+    total: u64,
+}
+
+impl<K: Hash + Ord + Clone> TopK<K> {
+    /// Create an empty top-list keeping at most `capacity` counters
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            counters: HashMap::new(),
+            by_count: BTreeSet::new(),
+            total:    0,
+        }
+    }
+
+    /// Count `weight` more occurrences of `key`
+    pub fn add(&mut self, key: K, weight: u64) {
+        self.total += weight;
+        self.insert(key, weight, 0);
+    }
+
+    /// Add `count` to the counter of `key` with `error` more possible error,
+    /// taking over the smallest counter if `key` doesn't have one
+    fn insert(&mut self, key: K, count: u64, error: u64) {
+        let (count, error) = if let Some(&(old, old_error)) =
+                self.counters.get(&key) {
+            self.by_count.remove(&(old, key.clone()));
+            (old + count, old_error + error)
+        } else if self.counters.len() < self.capacity {
+            (count, error)
+        } else {
+            let (min, victim) = self.by_count.pop_first().unwrap();
+            self.counters.remove(&victim);
+            (min + count, min + error)
+        };
+
+        self.counters.insert(key.clone(), (count, error));
+        self.by_count.insert((count, key));
+    }
+
+    /// Add the counts of `other`, eg. to combine the top-lists of several
+    /// threads. Counts stay upper bounds, with the errors of both
+    pub fn merge(&mut self, other: &TopK<K>) {
+        self.total += other.total;
+        for (key, &(count, error)) in &other.counters {
+            self.insert(key.clone(), count, error);
+        }
+    }
+
+    /// Get the `count` keys with the highest counts, highest first
+    pub fn top(&self, count: usize) -> Vec<Counter<K>> {
+        self.by_count.iter().rev().take(count).map(|(count, key)| Counter {
+            key:   key.clone(),
+            count: *count,
+            error: self.counters[key].1,
+        }).collect()
+    }
+
+    /// Get the sum of every weight added, including of keys which no longer
+    /// have a counter
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Get the number of keys with a counter
+    pub fn len(&self) -> usize {
+        self.counters.len()
+    }
+
+    /// Check if nothing has been counted
+    pub fn is_empty(&self) -> bool {
+        self.counters.is_empty()
+    }
+
+    /// Get the maximum number of counters
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+#[test]
+fn space_saving() {
+    // Two heavy hitters among a stream of keys occurring once
+    let mut hot = TopK::new(8);
+    for ii in 0..1000u64 {
+        hot.add(0xa, 1);
+        if ii % 2 == 0 {
+            hot.add(0xb, 1);
+        }
+        hot.add(0x1000 + ii, 1);
+    }
+    assert_eq!(hot.total(), 2500);
+    assert_eq!(hot.len(), 8);
+
+    let top = hot.top(2);
+    assert_eq!((top[0].key, top[1].key), (0xa, 0xb));
+    for (counter, real) in top.iter().zip([1000, 500]) {
+        assert!(counter.count >= real && counter.guaranteed() <= real);
+    }
+
+    // Merging keeps counts as upper bounds
+    let mut other = TopK::new(4);
+    other.add(0xb, 600);
+    hot.merge(&other);
+    let top = hot.top(1);
+    assert_eq!(top[0].key, 0xb);
+    assert!(top[0].count >= 1100 && top[0].guaranteed() <= 1100);
+    assert_eq!(hot.total(), 3100);
+}
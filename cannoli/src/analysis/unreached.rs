@@ -0,0 +1,163 @@
+//! Conditional branches which only ever went one way, as a list of what to
+//! test next
+//!
+//! A [`Cfg`] knows which way branches went when the trace has branch hooks
+//! ([`Block::branch`]), and otherwise only which successors were reached.
+//! Decoding the instructions of every block with
+//! [`Signatures::branch_successors`] gives both successors of conditional
+//! branches statically, so plain exec traces tell which side is missing
+//! too, and the report says where the missing side goes rather than only
+//! that it wasn't taken. That also finds branches inside of blocks, which
+//! short forward branches look like when they're never taken or always
+//! taken.
+//!
+//! Blocks ending with an instruction which can't be decoded (unsupported
+//! architectures, indirect branches, code which can't be read) are still
+//! reported from the branch hooks when there are some, without the address
+//! of the missing side.
+
+use crate::analysis::cfg::{Block, Cfg};
+use crate::symbols::signatures::Signatures;
+
+/// Bytes read to decode an instruction, the longest instruction of any
+/// target
+const MAX_INSN_LEN: usize = 16;
+
+/// A direction of a conditional branch which never executed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UnreachedBranch {
+    /// Entry of the function the branch is in
+    pub function: u64,
+
+    /// Start of the block ending with the branch
+    pub block: u64,
+
+    /// PC of the branch
+    pub pc: u64,
+
+    /// Whether the branch was never taken, rather than never not taken
+    pub taken: bool,
+
+    /// Address the missing side goes to, if the branch could be decoded
+    pub target: Option<u64>,
+}
+
+/// A conditional branch of a block, as its PC, whether each side was seen,
+/// and where each side goes if it's known, not taken first
+type Branch = (u64, [bool; 2], [Option<u64>; 2]);
+
+/// Decode the conditional branches of `block`. Also returns whether one of
+/// them ends the block
+fn decode(block: &Block, sigs: &Signatures,
+        read: &mut impl FnMut(u64, usize) -> Option<Vec<u8>>)
+        -> (Vec<Branch>, bool) {
+    let mut ret  = Vec::new();
+    let mut ends = false;
+    for (ii, &pc) in block.insns.iter().enumerate() {
+        let Some((taken, next)) = read(pc, MAX_INSN_LEN)
+                .and_then(|x| sigs.branch_successors(&x, pc)) else {
+            continue;
+        };
+
+        // Where control went after the branch and its delay slots
+        let after = ii + 1 + sigs.delay_slots();
+        let went = |x: u64| match block.insns.get(after) {
+            Some(&insn) => insn == x,
+            None => block.succs.contains(&x),
+        };
+
+        // Branch hooks are on the last instruction of the block
+        let hooked = match block.insns.get(after - 1) {
+            Some(&x) if x == block.last => {
+                ends = true;
+                block.branch.unwrap_or_default()
+            }
+            _ => [false; 2],
+        };
+        ret.push((pc, [hooked[0] || went(next), hooked[1] || went(taken)],
+            [Some(next), Some(taken)]));
+    }
+    (ret, ends)
+}
+
+/// Find the directions of conditional branches in `cfg` which never
+/// executed. `read` reads up to `len` bytes of code at an address, and
+/// `sigs` decodes them, `None` to only use the branch hooks of the trace
+pub fn unreached_branches(cfg: &Cfg, sigs: Option<&Signatures>,
+        mut read: impl FnMut(u64, usize) -> Option<Vec<u8>>)
+        -> Vec<UnreachedBranch> {
+    let mut ret = Vec::new();
+    for block in cfg.blocks() {
+        let (mut branches, ends) = sigs.map(|x| decode(block, x, &mut read))
+            .unwrap_or_default();
+        if let (false, Some(seen)) = (ends, block.branch) {
+            branches.push((block.last, seen, [None, None]));
+        }
+
+        for (pc, seen, targets) in branches {
+            for taken in [false, true] {
+                if !seen[taken as usize] {
+                    ret.push(UnreachedBranch {
+                        function: cfg.entry(),
+                        block:    block.start,
+                        target:   targets[taken as usize],
+                        pc,
+                        taken,
+                    });
+                }
+            }
+        }
+    }
+    ret
+}
+
+#[test]
+fn report_unreached() {
+    use crate::Architecture;
+    use crate::analysis::cfg::CfgBuilder;
+
+    // x86_64 code at 0x1000:
+    //   0x1000: test edi, edi
+    //   0x1002: je 0x1008
+    //   0x1004: mov eax, 1
+    //   ...
+    //   0x1008: ret
+    let mut text = vec![0x85, 0xff, 0x74, 0x04, 0xb8, 0x01, 0x00, 0x00, 0xc3];
+    text.resize(32, 0xcc);
+    let read = |addr: u64, len: usize| {
+        let off = addr.checked_sub(0x1000)? as usize;
+        Some(text.get(off..)?.iter().take(len).copied().collect::<Vec<_>>())
+    };
+
+    // Only the jump was taken, in an exec trace without branch hooks
+    let mut builder = CfgBuilder::new();
+    for pc in [0x1000, 0x1002, 0x1008] {
+        builder.observe(pc);
+    }
+    let cfg = builder.build(0x1000, 0x1000..0x1009).unwrap();
+    let x86 = Signatures::new(Architecture::X86_64, false).unwrap();
+    assert_eq!(unreached_branches(&cfg, Some(&x86), read), [UnreachedBranch {
+        function: 0x1000,
+        block:    0x1000,
+        pc:       0x1002,
+        taken:    false,
+        target:   Some(0x1004),
+    }]);
+    assert!(unreached_branches(&cfg, None, read).is_empty());
+
+    // Static decoding of the other architectures
+    let aarch64 = Signatures::new(Architecture::Aarch64, false).unwrap();
+    assert_eq!(aarch64.branch_successors(&0x5400_0040u32.to_le_bytes(),
+        0x1000), Some((0x1008, 0x1004)));
+    let mips = Signatures::new(Architecture::Mips, false).unwrap();
+    assert_eq!(mips.branch_successors(&0x1040_fffeu32.to_le_bytes(),
+        0x1000), Some((0x0ffc, 0x1008)));
+    let riscv = Signatures::new(Architecture::Riscv64, false).unwrap();
+    assert_eq!(riscv.branch_successors(&0x00b5_0463u32.to_le_bytes(),
+        0x1000), Some((0x1008, 0x1004)));
+    assert_eq!(riscv.branch_successors(&0xc111u16.to_le_bytes(), 0x1000),
+        Some((0x1004, 0x1002)));
+    assert_eq!(x86.branch_successors(&[0x0f, 0x85, 0x10, 0, 0, 0], 0x1000),
+        Some((0x1016, 0x1006)));
+    assert_eq!(x86.branch_successors(&[0xc3], 0x1000), None);
+}
@@ -0,0 +1,102 @@
+//! Authentication of the QEMU processes connecting to [`crate::create_cannoli`]
+//!
+//! The listener is on loopback, but on a shared machine any local user can
+//! connect to it, feed it made-up events, or read guest memory and push
+//! filters through the control channel. Connections are only accepted if
+//!
+//! - the process at the other end belongs to the same user as the client, or
+//!   to root. The owner of a loopback connection is looked up in
+//!   `/proc/net/tcp`, and connections whose owner can't be found are
+//!   rejected
+//! - the token in [`TOKEN_VAR`], if the client has one set, is sent by the
+//!   jitter as part of its greeting
+//!
+//! The token is read from the environment on both sides, so the same value
+//! has to be exported to the client and to QEMU:
+//!
+//! ```text
+//! export CANNOLI_TOKEN=$(head -c 32 /dev/urandom | xxd -p -c 64)
+//! ```
+//!
+//! qemu-user passes its environment on to the guest, run it with
+//! `-U CANNOLI_TOKEN` to keep the token from the target.
+//!
+//! The server answers the greeting with [`ACCEPTED`] before anything else is
+//! sent over the connection, and closes it otherwise, so a rejected jitter
+//! fails right away instead of filling a pipe nobody reads.
+
+use std::net::{SocketAddr, TcpStream};
+use std::os::unix::ffi::OsStringExt;
+
+/// Environment variable holding the shared token
+pub const TOKEN_VAR: &str = "CANNOLI_TOKEN";
+
+/// Longest token accepted, so a bad greeting can't make the server allocate
+/// an arbitrary amount of memory
+pub const MAX_TOKEN_LEN: u32 = 1024;
+
+/// Byte the server answers an accepted greeting with
+pub const ACCEPTED: u8 = 1;
+
+/// Get the token from the environment, `None` if it isn't set or is empty
+pub fn token() -> Option<Vec<u8>> {
+    std::env::var_os(TOKEN_VAR)
+        .map(|x| x.into_vec())
+        .filter(|x| !x.is_empty())
+}
+
+/// Check if `got` is the `expected` token, taking the same time wherever
+/// they differ. Any token is accepted if none is expected
+pub(crate) fn check_token(expected: Option<&[u8]>, got: &[u8]) -> bool {
+    let Some(expected) = expected else { return true };
+    expected.len() == got.len() &&
+        expected.iter().zip(got).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// Format an address the way `/proc/net/tcp` lists it, `None` for IPv6
+fn proc_addr(addr: SocketAddr) -> Option<String> {
+    match addr {
+        SocketAddr::V4(addr) => Some(format!("{:08X}:{:04X}",
+            u32::from_ne_bytes(addr.ip().octets()), addr.port())),
+        SocketAddr::V6(_) => None,
+    }
+}
+
+/// Get the UID owning the other end of the loopback connection `stream`, by
+/// finding the socket which is connected from its peer address to its local
+/// address
+pub(crate) fn peer_uid(stream: &TcpStream) -> Option<u32> {
+    let local = proc_addr(stream.local_addr().ok()?)?;
+    let peer  = proc_addr(stream.peer_addr().ok()?)?;
+
+    let table = std::fs::read_to_string("/proc/net/tcp").ok()?;
+    table.lines().skip(1).find_map(|line| {
+        // sl local_address rem_address st tx:rx tr:when retrnsmt uid
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        (fields.get(1) == Some(&peer.as_str()) &&
+            fields.get(2) == Some(&local.as_str()))
+            .then(|| fields.get(7)?.parse().ok()).flatten()
+    })
+}
+
+/// Check if connections from processes of `uid` are accepted
+pub(crate) fn check_uid(uid: u32) -> bool {
+    uid == 0 || uid == unsafe { libc::geteuid() }
+}
+
+#[test]
+fn loopback_peer() {
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (stream, _) = listener.accept().unwrap();
+    let uid = peer_uid(&stream).unwrap();
+    assert_eq!(uid, unsafe { libc::geteuid() });
+    assert!(check_uid(uid));
+
+    assert!(check_token(None, b""));
+    assert!(check_token(Some(b"secret"), b"secret"));
+    assert!(!check_token(Some(b"secret"), b"secreT"));
+    assert!(!check_token(Some(b"secret"), b"secret2"));
+}
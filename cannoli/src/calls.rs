@@ -0,0 +1,200 @@
+//! Calls and returns, reported to [`crate::Cannoli::call`] and
+//! [`crate::Cannoli::ret`]
+//!
+//! Call graphs and backtraces built from executed PCs have to guess which
+//! control transfers are calls: a jump into another function looks the same
+//! as a call to it, and a `longjmp()` the same as a return. The jitter knows
+//! the instructions though. With [`crate::control::HookKind::Edge`] it
+//! decodes every instruction which ends a basic block, and announces those
+//! which are calls or returns (see
+//! [`crate::symbols::signatures::Signatures::call_kind`] for the
+//! architectures and instructions). The edges leaving them (see
+//! [`crate::edges`]) are then reported as calls and returns as well, with
+//! where they went.
+//!
+//! Traces which only need functions being entered and left (profiling,
+//! call graphs) hook with [`crate::control::HookKind::Function`] instead.
+//! The jitter then only hooks calls, returns and the starts of translation
+//! blocks, so the trace holds a few events per call rather than one per
+//! instruction, and [`crate::Cannoli::edge`] only sees the edges of calls
+//! and returns.
+//!
+//! [`ShadowStack`] keeps the call stack of a thread from them. Returns are
+//! matched with the calls they return from by address, a return which goes
+//! somewhere no call on the stack returns to (stack switching, a smashed
+//! return address) is counted rather than popping the stack.
+
+use crate::{Cannoli, edges};
+use crate::symbols::signatures::CallKind;
+
+/// Maximum distance between a call instruction and the address its return
+/// lands on, as for [`crate::analysis::functions`]. On MIPS the call is
+/// reported from its delay slot, which the return skips
+const MAX_RETURN_GAP: u64 = 16;
+
+/// Default maximum depth of a [`ShadowStack`]
+const MAX_DEPTH: usize = 4096;
+
+/// Report the edge `(pc, target, taken)` of a thread to `user` if it's a
+/// call or a return
+pub(crate) fn report<T: Cannoli>(user: &mut T, pid: &T::PidContext,
+        tid: &T::TidContext, branches: &edges::Branches,
+        (pc, target, _): (u64, u64, bool)) {
+    match branches.call_kind(pc) {
+        Some(CallKind::Call)   => user.call(pid, tid, pc, target),
+        Some(CallKind::Return) => user.ret(pid, tid, pc, target),
+        None => {}
+    }
+}
+
+/// A call which didn't return yet
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Frame {
+    /// Address of the call instruction
+    pub site: u64,
+
+    /// Address of the function called
+    pub target: u64,
+}
+
+/// Call stack of a single thread, see the module documentation
+#[derive(Clone, Debug)]
+pub struct ShadowStack {
+    /// Calls which didn't return yet, the outermost first
+    frames: Vec<Frame>,
+
+    /// Maximum number of frames kept, the outermost are dropped past it
+    max_depth: usize,
+
+    /// Frames dropped as the stack was too deep
+    dropped: u64,
+
+    /// Frames popped without returning, by returns from outer frames
+    unwound: u64,
+
+    /// Returns which didn't match any call on the stack
+    mismatched: u64,
+}
+
+impl Default for ShadowStack {
+    fn default() -> Self {
+        Self {
+            frames:     Vec::new(),
+            max_depth:  MAX_DEPTH,
+            dropped:    0,
+            unwound:    0,
+            mismatched: 0,
+        }
+    }
+}
+
+impl ShadowStack {
+    /// Create an empty call stack
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keep at most `max_depth` frames, dropping the outermost ones past it
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth.max(1);
+        self
+    }
+
+    /// Observe a call at `pc` to `target`, see [`Cannoli::call`]
+    pub fn call(&mut self, pc: u64, target: u64) {
+        if self.frames.len() >= self.max_depth {
+            self.frames.remove(0);
+            self.dropped += 1;
+        }
+        self.frames.push(Frame { site: pc, target });
+    }
+
+    /// Observe a return to `retaddr`, see [`Cannoli::ret`]. Returns the
+    /// frame returned from, frames above it are unwound (eg. by
+    /// `longjmp()` or exceptions). `None` if no call on the stack returns to
+    /// `retaddr`, the stack is left alone then
+    pub fn ret(&mut self, retaddr: u64) -> Option<Frame> {
+        let Some(index) = self.frames.iter().rposition(|x| {
+            retaddr.wrapping_sub(x.site).wrapping_sub(1) < MAX_RETURN_GAP
+        }) else {
+            self.mismatched += 1;
+            return None;
+        };
+
+        self.unwound += (self.frames.len() - index - 1) as u64;
+        let frame = self.frames[index];
+        self.frames.truncate(index);
+        Some(frame)
+    }
+
+    /// Get the calls which didn't return yet, the outermost first
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    /// Get the number of calls which didn't return yet
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Get a backtrace of the thread, as the call sites with the innermost
+    /// first
+    pub fn backtrace(&self) -> impl Iterator<Item = u64> + '_ {
+        self.frames.iter().rev().map(|x| x.site)
+    }
+
+    /// Get the number of frames dropped as the stack was deeper than
+    /// [`ShadowStack::max_depth`]
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Get the number of frames popped without their return
+    pub fn unwound(&self) -> u64 {
+        self.unwound
+    }
+
+    /// Get the number of returns which didn't match any call on the stack
+    pub fn mismatched(&self) -> u64 {
+        self.mismatched
+    }
+}
+
+#[test]
+fn shadow_stack() {
+    use crate::Architecture;
+    use crate::symbols::signatures::Signatures;
+
+    let x86 = Signatures::new(Architecture::X86_64, false).unwrap();
+    assert_eq!(x86.call_kind(&[0xe8, 0, 0, 0, 0]), Some(CallKind::Call));
+    assert_eq!(x86.call_kind(&[0x41, 0xff, 0xd3]), Some(CallKind::Call));
+    assert_eq!(x86.call_kind(&[0xff, 0xe0]), None);
+    assert_eq!(x86.call_kind(&[0xf3, 0xc3]), Some(CallKind::Return));
+    let arm = Signatures::new(Architecture::Aarch64, false).unwrap();
+    assert_eq!(arm.call_kind(&0x9400_0010u32.to_le_bytes()),
+        Some(CallKind::Call));
+    assert_eq!(arm.call_kind(&0xd65f_03c0u32.to_le_bytes()),
+        Some(CallKind::Return));
+    assert_eq!(arm.call_kind(&0x1400_0010u32.to_le_bytes()), None);
+
+    let mut stack = ShadowStack::new().max_depth(3);
+    stack.call(0x1000, 0x2000);
+    stack.call(0x2010, 0x3000);
+    assert_eq!(stack.ret(0x2015), Some(Frame { site: 0x2010, target: 0x3000 }));
+    assert_eq!(stack.backtrace().collect::<Vec<_>>(), [0x1000]);
+
+    // A return to nowhere leaves the stack, one past a frame unwinds it
+    assert_eq!(stack.ret(0x9000), None);
+    stack.call(0x2020, 0x4000);
+    stack.call(0x4010, 0x5000);
+    assert_eq!(stack.ret(0x1005).map(|x| x.target), Some(0x2000));
+    assert_eq!((stack.depth(), stack.unwound(), stack.mismatched()),
+        (0, 2, 1));
+
+    // Too deep stacks drop their outermost frames
+    for pc in 0..4 {
+        stack.call(pc * 0x100, 0x8000);
+    }
+    assert_eq!(stack.backtrace().collect::<Vec<_>>(), [0x300, 0x200, 0x100]);
+    assert_eq!(stack.dropped(), 1);
+}
@@ -0,0 +1,253 @@
+//! Canonical, diff-friendly form of a trace
+//!
+//! Two runs of the same target with the same inputs produce traces which
+//! differ all over the place: ASLR moves every module, the stack and the
+//! heap, and the threads are interleaved however they were scheduled. The
+//! canonical form renders a trace as text with those differences removed, so
+//! a plain `diff` of two runs shows what the target did differently:
+//!
+//! - Every thread is rendered on its own, threads and processes are numbered
+//!   in the order they connect rather than by TID and PID
+//! - Addresses in files are rendered relative to the module they're in, as
+//!   `name+0x1234`
+//! - Addresses in the heap are rendered relative to the start of the heap,
+//!   as `heap+0x10`
+//! - Addresses in anonymous mappings (including mmap()ed allocations and
+//!   allocator arenas) are rendered relative to the mapping, which is named
+//!   after the thread that created it and how many mappings that thread
+//!   created before it, as `anon1.3+0x10`
+//! - Values loaded and stored are rendered the same way when they point into
+//!   a mapping, so pointers compare equal across runs
+//! - Register state is left out, it's full of pointers
+//!
+//! Addresses which aren't in any known mapping are rendered as they are.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use crate::address_space::{AddressSpace, HeapKind};
+use crate::event::Event;
+use crate::heap::HeapEvent;
+
+/// Names of the mappings of a process, shared between its threads
+#[derive(Clone, Default)]
+pub struct ProcessLabels {
+    /// Mappings of the process
+    space: AddressSpace,
+
+    /// Base, length and name of every anonymous mapping, keyed by base
+    anon: BTreeMap<u64, (u64, Arc<str>)>,
+
+    /// Start of the heap, once it grew
+    heap: Option<u64>,
+
+    /// Number of threads which joined so far
+    threads: u32,
+}
+
+impl ProcessLabels {
+    /// Create empty labels for a new process
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create labels for a process forked from the process with `self`,
+    /// which starts out with the same mappings
+    pub fn fork(&self) -> Self {
+        Self { threads: 0, ..self.clone() }
+    }
+
+    /// Render `addr` relative to whatever it's in
+    pub fn describe(&self, addr: u64) -> String {
+        let Some(mapping) = self.space.lookup(addr) else {
+            return format!("{addr:#x}");
+        };
+
+        if !mapping.anon {
+            let (path, offset) = self.space.module_offset(addr).unwrap();
+            let name = path.rsplit('/').next().unwrap_or(&path);
+            return format!("{name}+{offset:#x}");
+        }
+
+        if let (Some(HeapKind::Brk), Some(heap)) = (mapping.heap, self.heap) {
+            return format!("heap+{:#x}", addr - heap);
+        }
+
+        match self.anon.range(..=addr).next_back() {
+            Some((base, (len, name))) if addr - base < *len => {
+                format!("{name}+{:#x}", addr - base)
+            }
+            _ => format!("{addr:#x}"),
+        }
+    }
+
+    /// Describe `val`, as an address if it points into a mapping
+    fn value(&self, val: u64) -> String {
+        if self.space.lookup(val).is_some() {
+            self.describe(val)
+        } else {
+            format!("{val:#x}")
+        }
+    }
+}
+
+/// Renders the trace of one thread in canonical form, see the module
+/// documentation
+pub struct Canonicalizer {
+    /// Names of the mappings of the process
+    labels: Arc<Mutex<ProcessLabels>>,
+
+    /// Number of this thread in its process
+    thread: u32,
+
+    /// Number of anonymous mappings this thread created
+    mappings: u64,
+}
+
+impl Canonicalizer {
+    /// Create a canonicalizer for a new thread of the process with `labels`
+    pub fn new(labels: Arc<Mutex<ProcessLabels>>) -> Self {
+        let thread = {
+            let mut labels = labels.lock().unwrap();
+            labels.threads += 1;
+            labels.threads - 1
+        };
+        Self { labels, thread, mappings: 0 }
+    }
+
+    /// Number of this thread in its process, in the order threads joined
+    pub fn thread(&self) -> u32 {
+        self.thread
+    }
+
+    /// Append the canonical form of `event` to `out` as a line
+    pub fn render(&mut self, event: &Event, out: &mut String) {
+        let mut labels = self.labels.lock().unwrap();
+
+        // Apply changes to the address space first, so the line can refer to
+        // the new mapping
+        match event {
+            Event::Mmap { base, len, anon, read, write, exec, path,
+                    offset } => {
+                labels.space.mmap(*base, *len, *anon, *read, *write, *exec,
+                    path, *offset);
+                let end = base.saturating_add(*len);
+                labels.anon.retain(|x, (l, _)| {
+                    x.saturating_add(*l) <= *base || *x >= end
+                });
+                if *anon {
+                    let name = format!("anon{}.{}", self.thread,
+                        self.mappings);
+                    labels.anon.insert(*base, (*len, name.into()));
+                    self.mappings += 1;
+                }
+            }
+            Event::Brk { old, new } => {
+                labels.heap.get_or_insert(*old);
+                labels.space.heap(&HeapEvent::Brk { old: *old, new: *new });
+            }
+            Event::Arena { base, len } => {
+                labels.space.heap(&HeapEvent::Arena { base: *base,
+                    len: *len });
+            }
+            _ => {}
+        }
+
+        let d = |x: u64| labels.describe(x);
+        let _ = match event {
+            Event::Exec { pc } | Event::Regs { pc, .. } =>
+                writeln!(out, "exec {}", d(*pc)),
+            Event::Branch { pc, taken, .. } =>
+                writeln!(out, "exec {} {}", d(*pc),
+                    if *taken { "taken" } else { "not-taken" }),
+            Event::Read { pc, addr, val, sz } =>
+                writeln!(out, "read {} {} {sz} {}", d(*pc), d(*addr),
+                    labels.value(*val)),
+            Event::Write { pc, addr, val, sz } =>
+                writeln!(out, "write {} {} {sz} {}", d(*pc), d(*addr),
+                    labels.value(*val)),
+            Event::ReadAddr { pc, addr, sz } =>
+                writeln!(out, "read {} {} {sz}", d(*pc), d(*addr)),
+            Event::WriteAddr { pc, addr, sz } =>
+                writeln!(out, "write {} {} {sz}", d(*pc), d(*addr)),
+            Event::Rep { pc, count, backward, accesses } => {
+                let _ = write!(out, "rep {} {count} {}", d(*pc),
+                    if *backward { "backward" } else { "forward" });
+                for access in accesses {
+                    let _ = write!(out, " {}:{}:{}",
+                        if access.write { "write" } else { "read" },
+                        d(access.addr), access.sz);
+                }
+                writeln!(out)
+            }
+            Event::Cmp { pc, lhs, rhs, sz } =>
+                writeln!(out, "cmp {} {sz} {} {}", d(*pc), labels.value(*lhs),
+                    labels.value(*rhs)),
+            Event::RegFile { pc, index, regs } =>
+                writeln!(out, "regfile {} {index} {}", d(*pc), regs.len()),
+            Event::Signal { sig, from, to } =>
+                writeln!(out, "signal {sig} {} {}", d(*from), d(*to)),
+            Event::Mmap { base, len, read, write, exec, .. } =>
+                writeln!(out, "mmap {} {len:#x} {}{}{}", d(*base),
+                    if *read  { "r" } else { "-" },
+                    if *write { "w" } else { "-" },
+                    if *exec  { "x" } else { "-" }),
+            Event::Munmap { base, len } =>
+                writeln!(out, "munmap {} {len:#x}", d(*base)),
+            Event::Brk { old, new } => {
+                // The new break is past the end of the heap, so it's not in
+                // any mapping
+                let heap = labels.heap.unwrap_or(*old);
+                writeln!(out, "brk heap+{:#x} heap+{:#x}",
+                    old.wrapping_sub(heap), new.wrapping_sub(heap))
+            }
+            Event::Arena { base, len } =>
+                writeln!(out, "arena {} {len:#x}", d(*base)),
+        };
+
+        // Forget the mapping after the line referred to it
+        if let Event::Munmap { base, len } = event {
+            labels.space.munmap(*base, *len);
+        }
+    }
+}
+
+#[test]
+fn canonical_across_aslr() {
+    // The same execution with everything at different addresses
+    let run = |app: u64, stack: u64| {
+        let labels = Arc::new(Mutex::new(ProcessLabels::new()));
+        let mut canon = Canonicalizer::new(labels);
+        let mut out = String::new();
+        for event in [
+            Event::Mmap {
+                base: app, len: 0x2000, anon: false, read: true,
+                write: false, exec: true, path: "/bin/app".into(), offset: 0,
+            },
+            Event::Mmap {
+                base: stack, len: 0x10000, anon: true, read: true,
+                write: true, exec: false, path: String::new(), offset: 0,
+            },
+            Event::Exec  { pc: app + 0x1000 },
+            Event::Write { pc: app + 0x1000, addr: stack + 0xff8,
+                val: app + 0x1004, sz: 8 },
+            Event::Mmap {
+                base: app + 0x2000, len: 0x1000, anon: true, read: true,
+                write: true, exec: false, path: String::new(), offset: 0,
+            },
+            Event::Brk   { old: app + 0x2000, new: app + 0x3000 },
+            Event::Read  { pc: app + 0x1008, addr: app + 0x2010,
+                val: 0x41, sz: 1 },
+        ] {
+            canon.render(&event, &mut out);
+        }
+        out
+    };
+
+    let out = run(0x555555554000, 0x7ffff0000000);
+    assert_eq!(out, run(0x400000, 0x7fff00000000));
+    assert_eq!(out.lines().nth(3),
+        Some("write app+0x1000 anon0.0+0xff8 8 app+0x1004"));
+    assert_eq!(out.lines().nth(5), Some("brk heap+0x0 heap+0x1000"));
+    assert_eq!(out.lines().last(), Some("read app+0x1008 heap+0x10 1 0x41"));
+}
@@ -0,0 +1,458 @@
+//! On-disk captures of traces
+//!
+//! A capture is a single file holding the events of every target thread of
+//! one or more capture sessions. Each session is a segment of the capture,
+//! so iterative exploration of a target (run it, look at the trace, run it
+//! again with a different input) accumulates in one artifact instead of a
+//! pile of files. A session started with [`CaptureWriter::append`] adds a new
+//! segment to an existing capture, with its own [`RunManifest`].
+//!
+//! The file starts with the magic `CANNOLI\0` and a `u32` format version,
+//! followed by records. Every record is a kind byte and a `u32` length of the
+//! body, all integers are little-endian:
+//!
+//! ```text
+//! 0x00 Segment  index: u32, started: u64, manifest_len: u32, manifest
+//! 0x01 Events   pid: i32, tid: i32, events
+//! 0x02 Zstd     pid: i32, tid: i32, zstd frame of events
+//! ```
+//!
+//! `started` is the time the segment began, in seconds since the Unix epoch,
+//! and `manifest` is the JSON of the segment's manifest (empty if it has
+//! none). Events are in the [`crate::event`] encoding, and the events records
+//! of a thread are in trace order. Writers set to compress (see
+//! [`CaptureWriter::compression`]) write zstd records instead, a few times
+//! smaller since consecutive events share most of their bytes. Reading and
+//! writing them needs the `zstd` feature.
+//!
+//! A capture which was cut short (eg. the client was killed) ends in a
+//! partial record, which readers ignore and [`CaptureWriter::append`]
+//! overwrites.
+
+use std::fs::File;
+use std::io::{Read, Write, Seek, SeekFrom, BufReader, BufWriter};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::event::Event;
+use crate::harness::RunManifest;
+
+/// Magic bytes at the start of every capture
+pub const MAGIC: &[u8; 8] = b"CANNOLI\0";
+
+/// Version of the capture format, bumped on incompatible changes
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Size of the magic and version at the start of the file
+const HEADER_SIZE: u64 = 12;
+
+/// Record kind for the start of a segment
+const RECORD_SEGMENT: u8 = 0x00;
+
+/// Record kind for a chunk of events
+const RECORD_EVENTS: u8 = 0x01;
+
+/// Record kind for a zstd compressed chunk of events
+const RECORD_ZSTD: u8 = 0x02;
+
+/// Create an [`std::io::ErrorKind::InvalidData`] error
+fn invalid(msg: impl Into<String>) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg.into())
+}
+
+/// A capture session within a capture
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Segment {
+    /// Index of the segment, counting from 0
+    pub index: u32,
+
+    /// Time the segment began, in seconds since the Unix epoch
+    pub started: u64,
+
+    /// Manifest of the run which produced the segment, if one was given
+    pub manifest: Option<Box<RunManifest>>,
+
+    /// Offset of the segment's record in the file
+    pub offset: u64,
+}
+
+/// A record of a capture
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Record {
+    /// The following records belong to a new segment
+    Segment(Segment),
+
+    /// Events of a target thread
+    Events { pid: i32, tid: i32, events: Vec<Event> },
+}
+
+/// Parse the body of a segment record found at `offset`
+fn parse_segment(body: &[u8], offset: u64) -> std::io::Result<Segment> {
+    let short = || invalid("Truncated segment record");
+    let index   = u32::from_le_bytes(
+        body.get(0..4).ok_or_else(short)?.try_into().unwrap());
+    let started = u64::from_le_bytes(
+        body.get(4..12).ok_or_else(short)?.try_into().unwrap());
+    let len     = u32::from_le_bytes(
+        body.get(12..16).ok_or_else(short)?.try_into().unwrap()) as usize;
+    let json    = body.get(16..16 + len).ok_or_else(short)?;
+
+    let manifest = if json.is_empty() {
+        None
+    } else {
+        Some(serde_json::from_slice(json)?)
+    };
+    Ok(Segment { index, started, manifest, offset })
+}
+
+/// Check the header of the capture in `file`, leaving the file positioned
+/// after it
+fn check_header(file: &mut impl Read) -> std::io::Result<()> {
+    let mut header = [0u8; HEADER_SIZE as usize];
+    file.read_exact(&mut header)?;
+    if &header[..8] != MAGIC {
+        return Err(invalid("Not a Cannoli capture"));
+    }
+
+    let version = u32::from_le_bytes(header[8..].try_into().unwrap());
+    if version != FORMAT_VERSION {
+        return Err(invalid(format!("Unsupported capture version {version}")));
+    }
+    Ok(())
+}
+
+/// Find every segment of the capture in `file`, and the offset where the
+/// complete records end
+fn scan(file: &mut File) -> std::io::Result<(Vec<Segment>, u64)> {
+    let size = file.metadata()?.len();
+    file.seek(SeekFrom::Start(0))?;
+    check_header(file)?;
+
+    let mut segments = Vec::new();
+    let mut offset   = HEADER_SIZE;
+    let mut reader   = BufReader::new(file);
+    loop {
+        if offset + 5 > size {
+            break;
+        }
+        let mut header = [0u8; 5];
+        reader.read_exact(&mut header)?;
+        let len = u32::from_le_bytes(header[1..].try_into().unwrap()) as u64;
+        if offset + 5 + len > size {
+            break;
+        }
+
+        if header[0] == RECORD_SEGMENT {
+            let mut body = vec![0u8; len as usize];
+            reader.read_exact(&mut body)?;
+            segments.push(parse_segment(&body, offset)?);
+        } else {
+            reader.seek_relative(len as i64)?;
+        }
+        offset += 5 + len;
+    }
+
+    Ok((segments, offset))
+}
+
+/// Compress the events of the events record in `record` at `level`,
+/// returning whether they were
+#[cfg(feature = "zstd")]
+fn compress(record: &mut Vec<u8>, level: Option<i32>)
+        -> std::io::Result<bool> {
+    let Some(level) = level else {
+        return Ok(false);
+    };
+    let frame = zstd::bulk::compress(&record[8..], level)?;
+    record.truncate(8);
+    record.extend_from_slice(&frame);
+    Ok(true)
+}
+
+/// Compression is not available without the `zstd` feature
+#[cfg(not(feature = "zstd"))]
+fn compress(_record: &mut Vec<u8>, _level: Option<i32>)
+        -> std::io::Result<bool> {
+    Ok(false)
+}
+
+/// Decompress the zstd frame `frame` of an events record
+#[cfg(feature = "zstd")]
+fn decompress(frame: &[u8]) -> std::io::Result<Vec<u8>> {
+    zstd::stream::decode_all(frame)
+}
+
+/// Compressed records can't be read without the `zstd` feature
+#[cfg(not(feature = "zstd"))]
+fn decompress(_frame: &[u8]) -> std::io::Result<Vec<u8>> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported,
+        "Compressed capture, Cannoli was built without the zstd feature"))
+}
+
+/// Writes a capture, see the module documentation
+pub struct CaptureWriter {
+    /// The capture
+    file: BufWriter<File>,
+
+    /// Index of the segment being written
+    segment: u32,
+
+    /// Scratch buffer for encoding records
+    record: Vec<u8>,
+
+    /// zstd compression level for events, `None` to write them raw
+    compression: Option<i32>,
+}
+
+impl CaptureWriter {
+    /// Create a new capture at `path`, replacing any existing file, and start
+    /// its first segment
+    pub fn create(path: impl AsRef<Path>, manifest: Option<&RunManifest>)
+            -> std::io::Result<Self> {
+        let mut file = File::create(path)?;
+        file.write_all(MAGIC)?;
+        file.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        Self::start(file, 0, manifest)
+    }
+
+    /// Resume the capture at `path`, appending a new segment to it. If there
+    /// is no capture at `path` yet this is the same as
+    /// [`CaptureWriter::create`]
+    pub fn append(path: impl AsRef<Path>, manifest: Option<&RunManifest>)
+            -> std::io::Result<Self> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Self::create(path, manifest);
+        }
+
+        // Drop whatever the previous session didn't get to finish writing
+        let mut file = File::options().read(true).write(true).open(path)?;
+        let (segments, end) = scan(&mut file)?;
+        file.set_len(end)?;
+        file.seek(SeekFrom::Start(end))?;
+
+        let index = segments.last().map_or(0, |x| x.index + 1);
+        Self::start(file, index, manifest)
+    }
+
+    /// Write the record starting segment `index` to the end of `file`
+    fn start(file: File, index: u32, manifest: Option<&RunManifest>)
+            -> std::io::Result<Self> {
+        let mut ret = Self {
+            file:    BufWriter::new(file),
+            segment:     index,
+            record:      Vec::new(),
+            compression: None,
+        };
+        ret.write_segment(manifest)?;
+        Ok(ret)
+    }
+
+    /// Write the record starting the current segment
+    fn write_segment(&mut self, manifest: Option<&RunManifest>)
+            -> std::io::Result<()> {
+        let json = manifest.map(serde_json::to_vec).transpose()?
+            .unwrap_or_default();
+        let started = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map_or(0, |x| x.as_secs());
+
+        self.record.extend_from_slice(&self.segment.to_le_bytes());
+        self.record.extend_from_slice(&started.to_le_bytes());
+        self.record.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        self.record.extend_from_slice(&json);
+        self.write_record(RECORD_SEGMENT)?;
+        self.file.flush()
+    }
+
+    /// End the current segment and start the next one, for tools which
+    /// write several sessions in one go (eg. [`crate::excerpt`])
+    pub fn next_segment(&mut self, manifest: Option<&RunManifest>)
+            -> std::io::Result<()> {
+        self.segment += 1;
+        self.write_segment(manifest)
+    }
+
+    /// Write the record in `self.record` with `kind`
+    fn write_record(&mut self, kind: u8) -> std::io::Result<()> {
+        self.file.write_all(&[kind])?;
+        self.file.write_all(&(self.record.len() as u32).to_le_bytes())?;
+        self.file.write_all(&self.record)?;
+        self.record.clear();
+        Ok(())
+    }
+
+    /// Compress the events written from now on at the zstd `level`, or
+    /// write them raw with `None`. Without the `zstd` feature events are
+    /// always written raw
+    pub fn compression(mut self, level: Option<i32>) -> Self {
+        if cfg!(not(feature = "zstd")) && level.is_some() {
+            eprintln!("Cannoli: built without the zstd feature, \
+                capturing events uncompressed");
+        }
+        self.compression = level;
+        self
+    }
+
+    /// Index of the segment being written
+    pub fn segment(&self) -> u32 {
+        self.segment
+    }
+
+    /// Append the next chunk of `events` of the thread `tid` of process `pid`
+    pub fn write_events(&mut self, pid: i32, tid: i32, events: &[Event])
+            -> std::io::Result<()> {
+        self.record.extend_from_slice(&pid.to_le_bytes());
+        self.record.extend_from_slice(&tid.to_le_bytes());
+        for event in events {
+            event.encode(&mut self.record);
+        }
+        if compress(&mut self.record, self.compression)? {
+            self.write_record(RECORD_ZSTD)
+        } else {
+            self.write_record(RECORD_EVENTS)
+        }
+    }
+
+    /// Flush buffered records to the file
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Reads a capture record by record, see the module documentation
+pub struct CaptureReader {
+    /// The capture
+    file: BufReader<File>,
+
+    /// Offset of the next record
+    offset: u64,
+
+    /// Size of the file when it was opened
+    size: u64,
+}
+
+impl CaptureReader {
+    /// Open the capture at `path`, positioned at its first record
+    pub fn open(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let mut file = File::open(path)?;
+        let size = file.metadata()?.len();
+        check_header(&mut file)?;
+        Ok(Self { file: BufReader::new(file), offset: HEADER_SIZE, size })
+    }
+
+    /// Get every segment of the capture at `path`, without reading the
+    /// events
+    pub fn segments(path: impl AsRef<Path>) -> std::io::Result<Vec<Segment>> {
+        Ok(scan(&mut File::open(path)?)?.0)
+    }
+
+    /// Continue reading at the start of `segment`
+    pub fn seek_segment(&mut self, segment: &Segment) -> std::io::Result<()> {
+        self.seek(segment.offset)
+    }
+
+    /// Offset of the next record in the file
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Continue reading at the record at `offset`, which must be an offset
+    /// previously returned by [`CaptureReader::offset`]
+    pub fn seek(&mut self, offset: u64) -> std::io::Result<()> {
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.offset = offset;
+        Ok(())
+    }
+
+    /// Read the next record, `None` at the end of the capture
+    pub fn next_record(&mut self) -> std::io::Result<Option<Record>> {
+        if self.offset + 5 > self.size {
+            return Ok(None);
+        }
+        let mut header = [0u8; 5];
+        self.file.read_exact(&mut header)?;
+        let len = u32::from_le_bytes(header[1..].try_into().unwrap()) as u64;
+        if self.offset + 5 + len > self.size {
+            // The capture was cut short
+            self.offset = self.size;
+            return Ok(None);
+        }
+
+        let mut body = vec![0u8; len as usize];
+        self.file.read_exact(&mut body)?;
+        let offset = self.offset;
+        self.offset += 5 + len;
+
+        match header[0] {
+            RECORD_SEGMENT => Ok(Some(Record::Segment(
+                parse_segment(&body, offset)?))),
+            RECORD_EVENTS | RECORD_ZSTD => {
+                let short = || invalid("Truncated events record");
+                let pid = i32::from_le_bytes(
+                    body.get(0..4).ok_or_else(short)?.try_into().unwrap());
+                let tid = i32::from_le_bytes(
+                    body.get(4..8).ok_or_else(short)?.try_into().unwrap());
+
+                if header[0] == RECORD_ZSTD {
+                    body = decompress(&body[8..])?;
+                } else {
+                    body.drain(..8);
+                }
+                let mut bytes  = &body[..];
+                let mut events = Vec::new();
+                while !bytes.is_empty() {
+                    events.push(Event::decode(&mut bytes)
+                        .map_err(|x| invalid(format!("{x:?}")))?);
+                }
+                Ok(Some(Record::Events { pid, tid, events }))
+            }
+            kind => Err(invalid(format!("Unknown record kind {kind:#x}"))),
+        }
+    }
+}
+
+#[test]
+fn resume_capture() {
+    let path = std::env::temp_dir()
+        .join(format!("cannoli_capture_{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    // First session, which gets cut short in the middle of a record
+    let mut writer = CaptureWriter::append(&path, None).unwrap();
+    writer.write_events(1, 1, &[Event::Exec { pc: 0x1000 }]).unwrap();
+    writer.write_events(1, 2, &[Event::Exec { pc: 0x2000 }]).unwrap();
+    drop(writer);
+    let size = std::fs::metadata(&path).unwrap().len();
+    File::options().write(true).open(&path).unwrap()
+        .set_len(size - 3).unwrap();
+
+    // Second session
+    let mut writer = CaptureWriter::append(&path, None).unwrap();
+    assert_eq!(writer.segment(), 1);
+    writer.write_events(5, 5, &[
+        Event::Exec  { pc: 0x3000 },
+        Event::Write { pc: 0x3000, addr: 0x5000, val: 0x41, sz: 1 },
+    ]).unwrap();
+    drop(writer);
+
+    let segments = CaptureReader::segments(&path).unwrap();
+    assert_eq!(segments.iter().map(|x| x.index).collect::<Vec<_>>(), [0, 1]);
+
+    let mut reader = CaptureReader::open(&path).unwrap();
+    let mut records = Vec::new();
+    while let Some(record) = reader.next_record().unwrap() {
+        records.push(record);
+    }
+    assert_eq!(records.len(), 4);
+    assert_eq!(records[1], Record::Events {
+        pid: 1, tid: 1, events: vec![Event::Exec { pc: 0x1000 }] });
+
+    // Jump straight to the second session
+    reader.seek_segment(&segments[1]).unwrap();
+    assert_eq!(reader.next_record().unwrap(),
+        Some(Record::Segment(segments[1].clone())));
+    assert!(matches!(reader.next_record().unwrap(),
+        Some(Record::Events { pid: 5, .. })));
+    assert_eq!(reader.next_record().unwrap(), None);
+
+    std::fs::remove_file(&path).unwrap();
+}
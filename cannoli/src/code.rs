@@ -0,0 +1,141 @@
+//! Instruction bytes, reported to [`crate::Cannoli::exec_with_bytes`]
+//!
+//! Disassembling a trace otherwise means opening the guest binary again and
+//! working out where it was mapped, which doesn't work at all for code
+//! which was generated at runtime. With
+//! [`crate::control::Filters::exec_bytes`] set, the jitter reads the code of
+//! every instruction it lifts with an exec hook and announces it in the
+//! trace of the thread which lifted it, before the instruction can execute,
+//! like system call sites (see [`crate::syscalls`]).
+//!
+//! The jitter doesn't decode instructions, so it sends a window of up to
+//! [`MAX_BYTES`] bytes starting at the instruction, fewer at the end of a
+//! mapping. QEMU lifts a translation block an instruction at a time, so the
+//! window of an instruction is cut where the next lifted instruction
+//! starts, which leaves exactly the instruction for everything but the last
+//! instruction of a block. Consumers decode the first instruction of the
+//! bytes they get.
+//!
+//! The code is shared by every thread of a process. Lifting an address
+//! again (eg. after the code was modified) replaces its bytes.
+//!
+//! The code of dynamic code (see [`crate::address_space`]) can be captured
+//! by the client instead, with [`crate::Cannoli::CAPTURE_DYNAMIC_CODE`]: it
+//! reads the window of an instruction from the guest with
+//! [`crate::control::read_memory`] the first time it executes, and keeps it
+//! like announced code. The read happens after the instruction executed, so
+//! code which was modified since then has its new bytes, and code modified
+//! later keeps the bytes of the first read.
+
+use std::sync::{Arc, Mutex, RwLock, LazyLock};
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+use crate::ClientInfo;
+
+/// Maximum number of bytes announced for an instruction, the longest
+/// instruction of any target QEMU supports
+pub const MAX_BYTES: usize = 16;
+
+/// How long to wait for the guest to send the code of an instruction, see
+/// [`Code::with_read`]
+const READ_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Bytes of the lifted instructions of a process, announced by the jitter
+#[derive(Default)]
+pub(crate) struct Code {
+    /// Bytes of every announced instruction, keyed by its PC
+    bytes: RwLock<BTreeMap<u64, Vec<u8>>>,
+}
+
+/// Code of every process with a connected thread, keyed by PID
+static CODE: LazyLock<Mutex<HashMap<i32, Arc<Code>>>> =
+    LazyLock::new(Default::default);
+
+impl Code {
+    /// Get the code of the process of `ci`
+    pub(crate) fn get(ci: &ClientInfo) -> Arc<Self> {
+        CODE.lock().unwrap().entry(ci.pid).or_default().clone()
+    }
+
+    /// Forget the code of the process `pid`, once its last thread is gone
+    pub(crate) fn remove(pid: i32) {
+        CODE.lock().unwrap().remove(&pid);
+    }
+
+    /// Add the instruction at `pc`, given as the bytes starting at it
+    pub(crate) fn add(&self, pc: u64, bytes: &[u8]) {
+        let mut code = self.bytes.write().unwrap();
+        let mut bytes = &bytes[..bytes.len().min(MAX_BYTES)];
+
+        // Cut this one and the one before at the next instruction start
+        if let Some((&next, _)) = code.range(pc + 1..).next() {
+            let len = next - pc;
+            if len < bytes.len() as u64 {
+                bytes = &bytes[..len as usize];
+            }
+        }
+        if let Some((&prev, prev_bytes)) = code.range_mut(..pc).next_back() {
+            let len = pc - prev;
+            if len < prev_bytes.len() as u64 {
+                prev_bytes.truncate(len as usize);
+            }
+        }
+        code.insert(pc, bytes.to_vec());
+    }
+
+    /// Invoke `f` with the bytes of the instruction at `pc`, which are
+    /// empty if it wasn't announced
+    pub(crate) fn with<R>(&self, pc: u64, f: impl FnOnce(&[u8]) -> R) -> R {
+        let code = self.bytes.read().unwrap();
+        f(code.get(&pc).map_or(&[][..], |x| &x[..]))
+    }
+
+    /// Invoke `f` with the bytes of the instruction at `pc`, reading them
+    /// through the thread `tid` of the process `pid` if they weren't
+    /// announced or read yet. They're empty if the read failed, which isn't
+    /// tried again
+    pub(crate) fn with_read<R>(&self, pid: i32, tid: i32, pc: u64,
+            f: impl FnOnce(&[u8]) -> R) -> R {
+        if !self.bytes.read().unwrap().contains_key(&pc) {
+            // The window may reach past the end of the mapping, then only
+            // read up to the end of the page
+            let page = (0x1000 - (pc & 0xfff)).min(MAX_BYTES as u64) as u32;
+            let bytes = [MAX_BYTES as u32, page].into_iter()
+                .find_map(|len| crate::control::read_memory(pid, tid, pc, len,
+                    READ_TIMEOUT).ok())
+                .unwrap_or_default();
+            self.add(pc, &bytes);
+        }
+        self.with(pc, f)
+    }
+}
+
+#[test]
+fn cut_instructions() {
+    let code = Code::default();
+
+    // `push rbp; mov rbp, rsp; ret` lifted in order, with windows reaching
+    // past every instruction
+    let text = [0x55, 0x48, 0x89, 0xe5, 0xc3, 0xcc, 0xcc];
+    code.add(0x1000, &text);
+    code.add(0x1001, &text[1..]);
+    code.add(0x1004, &text[4..]);
+    code.with(0x1000, |x| assert_eq!(x, [0x55]));
+    code.with(0x1001, |x| assert_eq!(x, [0x48, 0x89, 0xe5]));
+    code.with(0x1004, |x| assert_eq!(x, [0xc3, 0xcc, 0xcc]));
+    code.with(0x2000, |x| assert!(x.is_empty()));
+
+    // Lifting in another order cuts the same way
+    let code = Code::default();
+    code.add(0x1001, &text[1..]);
+    code.add(0x1000, &text);
+    code.with(0x1000, |x| assert_eq!(x, [0x55]));
+    code.add(0x1000, &[0x90; 32]);
+    code.with(0x1000, |x| assert_eq!(x, [0x90]));
+
+    // Announced code isn't read again, and code which can't be read (here
+    // without a connected thread) is empty
+    code.with_read(-1, -1, 0x1000, |x| assert_eq!(x, [0x90]));
+    code.with_read(-1, -1, 0x3000, |x| assert!(x.is_empty()));
+    assert!(code.bytes.read().unwrap().contains_key(&0x3000));
+}
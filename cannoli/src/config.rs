@@ -0,0 +1,136 @@
+//! TOML configuration for a capture
+//!
+//! ```toml
+//! [filters]
+//! hook    = "always"                # once, always, register, branch, edge,
+//!                                   # function
+//! reads   = true
+//! writes  = false
+//! read_values  = false              # only log the address of reads
+//! write_values = true
+//! include = [[0x400000, 0x480000]]  # only hook code in these ranges
+//! modules = ["libtarget"]           # and in these files
+//! exclude = []
+//! syscalls = true                   # report system calls
+//! exec_regs = true                  # send registers with exec hooks
+//! exec_reg_count = 8                # but only the first 8
+//! exec_bytes = false                # send the code with exec hooks
+//! cmp = true                        # report operands of comparisons
+//! start = { on = "exec", module = "libtarget", offset = 0x1234 }
+//!                                   # hook nothing before this runs
+//! sample = { every = "blocks", count = 1000 }
+//!                                   # only send every 1000th block, or
+//!                                   # { every = "time", micros = 100 }
+//!
+//! [[filters.reg_files]]            # also capture the SSE registers with
+//! name   = "xmm"                    # register and branch hooks, see
+//! kind   = "vector"                 # `cannoli::regfile`
+//! offset = 0x3d0
+//! count  = 16
+//! stride = 64
+//! width  = 16
+//! branches_only = false
+//! ```
+//!
+//! Everything is optional, and the defaults hook everything. See
+//! [`crate::control::watch_filters`] for applying a config file to a running
+//! capture whenever it's edited.
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::thread::JoinHandle;
+use inotify::{Inotify, WatchMask};
+use serde::{Serialize, Deserialize};
+use crate::control::Filters;
+
+/// Contents of a config file
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// What the jitter hooks
+    pub filters: Filters,
+}
+
+impl Config {
+    /// Parse a config from TOML
+    pub fn parse(text: &str) -> std::io::Result<Self> {
+        toml::from_str(text).map_err(|x| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, x)
+        })
+    }
+
+    /// Load the config file at `path`
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+}
+
+/// Watch the config file at `path` with inotify, invoking `callback` with
+/// the new config every time the file is written or replaced. Editors often
+/// save by renaming a new file over the old one, so the directory of the file
+/// is watched rather than the file itself
+pub fn watch(path: impl Into<PathBuf>,
+        mut callback: impl FnMut(std::io::Result<Config>) + Send + 'static)
+        -> std::io::Result<JoinHandle<()>> {
+    let path = path.into();
+    let dir  = match path.parent() {
+        Some(x) if !x.as_os_str().is_empty() => x.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let name: OsString = path.file_name().ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput,
+            format!("{} is not a file", path.display()))
+    })?.into();
+
+    let mut inotify = Inotify::init()?;
+    inotify.watches().add(&dir,
+        WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO | WatchMask::CREATE)?;
+
+    Ok(std::thread::spawn(move || {
+        let mut buffer = [0u8; 4096];
+        loop {
+            let events = match inotify.read_events_blocking(&mut buffer) {
+                Ok(events) => events,
+                Err(err) => {
+                    eprintln!("Stopped watching {}: {err}", path.display());
+                    return;
+                }
+            };
+
+            // Only reload once for a batch of events
+            let changed = events.into_iter()
+                .any(|x| x.name == Some(name.as_os_str()));
+            if changed {
+                callback(Config::load(&path));
+            }
+        }
+    }))
+}
+
+#[test]
+fn parse_config() {
+    let config = Config::parse("
+        [filters]
+        hook    = \"once\"
+        writes  = false
+        include = [[0x1000, 0x2000]]
+        start   = { on = \"mmap\", module = \"libtarget\" }
+        sample  = { every = \"time\", micros = 500 }
+        cmp     = true
+    ").unwrap();
+    assert_eq!(config.filters.hook, crate::control::HookKind::Once);
+    assert!(config.filters.reads && !config.filters.writes);
+    assert!(config.filters.cmp);
+    assert_eq!(config.filters.include, [[0x1000, 0x2000]]);
+    assert_eq!(config.filters.start, Some(crate::control::Trigger::Mmap {
+        module: "libtarget".into() }));
+    assert!(Config::parse("[filters]\nstart = { on = \"exec\" }").is_err());
+    assert_eq!(config.filters.sample.map(|x| x.period()),
+        Some(crate::control::Sample::MAX_COUNT));
+    let config = Config::parse(
+        "[filters]\nsample = { every = \"blocks\", count = 0 }").unwrap();
+    assert_eq!(config.filters.sample.map(|x| x.period()), Some(1));
+
+    assert_eq!(Config::parse("").unwrap(), Config::default());
+    assert!(Config::parse("[filters]\nbogus = 1").is_err());
+}
@@ -0,0 +1,593 @@
+//! Protocol conformance checks between the jitter and the client
+//!
+//! The QEMU patch, the jitter and the client have to agree on a lot which
+//! the type system can't see: which hooks produce which events, what sizes
+//! memory accesses have, that executed code is mapped. Bumping the QEMU
+//! submodule can quietly break any of it on one architecture only. A
+//! [`Matrix`] lists a QEMU build per architecture, each with a small guest
+//! to run, and every guest is run under every hook configuration of
+//! [`configs`] with `Recorder<ConformanceSink>` checking the events:
+//!
+//! ```toml
+//! [[target]]
+//! name    = "x86_64"
+//! qemu    = "build/qemu-x86_64"
+//! jitter  = "target/release/libjitter.so"
+//! guest   = "guests/hello-x86_64"
+//! args    = ["--iterations", "10"]
+//! version = "QEMU emulator version 7.1.0"  # pinned `qemu --version`
+//! ```
+//!
+//! `cannoli conformance matrix.toml` drives it. Every run checks that:
+//!
+//! * only the events of the hooks which are on show up, and every kind of
+//!   event which should show up does
+//! * memory accesses are 1, 2, 4, 8 or 16 bytes, and their values fit
+//! * the register events of a thread all have the same size
+//! * mappings aren't empty, executed code is in an executable mapping, and
+//!   every executable mapping of the guest saw instructions executed
+//!
+//! Guests should be small and exit by themselves, statically linked so the
+//! same guest behaves the same under every configuration.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use serde::Deserialize;
+use crate::ClientInfo;
+use crate::control::{self, Filters, HookKind};
+use crate::event::Event;
+use crate::sinks::Sink;
+
+/// Granularity executed code is checked against mappings with
+const PAGE_SIZE: u64 = 0x1000;
+
+/// How often [`finish`] checks whether every thread is done
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// A QEMU build to check, with the guest to run under it
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Target {
+    /// Name of the target in reports, eg. the architecture
+    pub name: String,
+
+    /// QEMU user-mode binary
+    pub qemu: PathBuf,
+
+    /// Jitter passed to QEMU
+    pub jitter: PathBuf,
+
+    /// Guest to run
+    pub guest: PathBuf,
+
+    /// Arguments of the guest
+    #[serde(default)]
+    pub args: Vec<String>,
+
+    /// What `qemu --version` has to print, to catch running the checks
+    /// against another build than the pinned one
+    #[serde(default)]
+    pub version: Option<String>,
+}
+
+/// Contents of a matrix file, see the module documentation
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Matrix {
+    /// QEMU builds to check
+    #[serde(default)]
+    pub target: Vec<Target>,
+}
+
+impl Matrix {
+    /// Parse a matrix from TOML
+    pub fn parse(text: &str) -> std::io::Result<Self> {
+        toml::from_str(text).map_err(|x| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, x)
+        })
+    }
+
+    /// Load the matrix file at `path`
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+}
+
+/// A hook configuration every target is run under
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HookConfig {
+    /// Name of the configuration in reports
+    pub name: &'static str,
+
+    /// Filters pushed to the jitter
+    pub filters: Filters,
+}
+
+/// Get the hook configurations every target is run under
+pub fn configs() -> Vec<HookConfig> {
+    let nothing = Filters {
+        exec:   false,
+        reads:  false,
+        writes: false,
+        ..Filters::default()
+    };
+    let exec = |hook| Filters { exec: true, hook, ..nothing.clone() };
+    vec![
+        HookConfig { name: "once",      filters: exec(HookKind::Once) },
+        HookConfig { name: "always",    filters: exec(HookKind::Always) },
+        HookConfig { name: "registers", filters: exec(HookKind::Register) },
+        HookConfig { name: "branches",  filters: exec(HookKind::Branch) },
+        HookConfig { name: "exec-regs", filters: Filters {
+            exec_regs: true,
+            ..exec(HookKind::Always)
+        } },
+        HookConfig { name: "memory", filters: Filters {
+            reads:  true,
+            writes: true,
+            ..nothing.clone()
+        } },
+        HookConfig { name: "addresses", filters: Filters {
+            reads:        true,
+            writes:       true,
+            read_values:  false,
+            write_values: false,
+            ..nothing.clone()
+        } },
+        HookConfig { name: "everything", filters: Filters {
+            syscalls: true,
+            ..Filters::default()
+        } },
+    ]
+}
+
+/// Kinds of events a configuration produces
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct Expect {
+    /// [`Event::Exec`]
+    exec: bool,
+
+    /// [`Event::Regs`] for every instruction
+    regs: bool,
+
+    /// [`Event::Regs`] at system call sites
+    syscalls: bool,
+
+    /// [`Event::Branch`]
+    branches: bool,
+
+    /// [`Event::Read`], or [`Event::ReadAddr`] without values
+    reads: Option<bool>,
+
+    /// [`Event::Write`], or [`Event::WriteAddr`] without values
+    writes: Option<bool>,
+}
+
+impl Expect {
+    /// Get the kinds of events `filters` produce
+    fn new(filters: &Filters) -> Self {
+        let plain = matches!(filters.hook, HookKind::Once | HookKind::Always);
+        Self {
+            exec:     filters.exec && plain && !filters.exec_regs,
+            regs:     filters.exec && (filters.hook == HookKind::Register ||
+                plain && filters.exec_regs),
+            syscalls: filters.syscalls,
+            branches: filters.exec && filters.hook == HookKind::Branch,
+            reads:    filters.reads.then_some(filters.read_values),
+            writes:   filters.writes.then_some(filters.write_values),
+        }
+    }
+}
+
+/// Number of events of every kind seen
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Counts {
+    /// Instructions
+    pub exec: u64,
+
+    /// Register events
+    pub regs: u64,
+
+    /// Branches
+    pub branches: u64,
+
+    /// Memory reads, with or without values
+    pub reads: u64,
+
+    /// Memory writes, with or without values
+    pub writes: u64,
+
+    /// Mappings
+    pub maps: u64,
+}
+
+impl Counts {
+    /// Add `other` to the counts
+    fn add(&mut self, other: &Counts) {
+        self.exec     += other.exec;
+        self.regs     += other.regs;
+        self.branches += other.branches;
+        self.reads    += other.reads;
+        self.writes   += other.writes;
+        self.maps     += other.maps;
+    }
+}
+
+/// A broken invariant
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Violation {
+    /// What is broken
+    pub rule: &'static str,
+
+    /// How many times it was seen broken
+    pub count: u64,
+
+    /// The first time it was seen broken
+    pub example: String,
+}
+
+/// Violations, keyed by rule, with their count and first example
+type Violations = BTreeMap<&'static str, (u64, String)>;
+
+/// Record that `rule` is broken, as shown by `example`
+fn violate(violations: &mut Violations, rule: &'static str,
+        example: impl FnOnce() -> String) {
+    violations.entry(rule).or_insert_with(|| (0, example())).0 += 1;
+}
+
+/// Code executed and files mapped by a process
+#[derive(Default)]
+struct Process {
+    /// Pages executed
+    pages: HashSet<u64>,
+
+    /// Executable mappings, as start, end and path
+    mappings: Vec<(u64, u64, String)>,
+}
+
+/// Checks the events of a thread
+struct Checker {
+    /// Kinds of events expected
+    expect: Expect,
+
+    /// Events seen
+    counts: Counts,
+
+    /// Broken invariants
+    violations: Violations,
+
+    /// Size of the first register event
+    regs_len: Option<usize>,
+
+    /// Code executed and files mapped by the thread
+    process: Process,
+}
+
+impl Checker {
+    /// Check events produced with `filters`
+    fn new(filters: &Filters) -> Self {
+        Self {
+            expect:     Expect::new(filters),
+            counts:     Counts::default(),
+            violations: Violations::new(),
+            regs_len:   None,
+            process:    Process::default(),
+        }
+    }
+
+    /// Check the access of `sz` bytes with `val` by the instruction at `pc`
+    fn access(&mut self, pc: u64, sz: u8, val: Option<u64>) {
+        if !matches!(sz, 1 | 2 | 4 | 8 | 16) {
+            violate(&mut self.violations, "memory access of an invalid size",
+                || format!("{sz} bytes at {pc:#x}"));
+        }
+        if let Some(val) = val.filter(|_| sz < 8) {
+            if val >> (sz as u32 * 8) != 0 {
+                violate(&mut self.violations,
+                    "memory value larger than its access",
+                    || format!("{val:#x} in {sz} bytes at {pc:#x}"));
+            }
+        }
+    }
+
+    /// Check the next event of the thread
+    fn event(&mut self, event: &Event) {
+        let expect = self.expect;
+        let mut unexpected = |rule, pc: u64| {
+            violate(&mut self.violations, rule, || format!("at {pc:#x}"));
+        };
+        match *event {
+            Event::Exec { pc } => {
+                if !expect.exec {
+                    unexpected("exec events with exec hooks off", pc);
+                }
+                self.counts.exec += 1;
+                self.process.pages.insert(pc & !(PAGE_SIZE - 1));
+            }
+            Event::Regs { pc, ref regs } => {
+                if !expect.regs && !expect.syscalls {
+                    unexpected("register events without register hooks",
+                        pc);
+                }
+                if *self.regs_len.get_or_insert(regs.len()) != regs.len() {
+                    unexpected("register events of different sizes", pc);
+                }
+                self.counts.regs += 1;
+                if expect.regs {
+                    self.process.pages.insert(pc & !(PAGE_SIZE - 1));
+                }
+            }
+            Event::Branch { pc, .. } => {
+                if !expect.branches {
+                    unexpected("branch events without branch hooks", pc);
+                }
+                self.counts.branches += 1;
+                self.process.pages.insert(pc & !(PAGE_SIZE - 1));
+            }
+            Event::Read { pc, val, sz, .. } => {
+                if expect.reads != Some(true) {
+                    unexpected("read values without read hooks logging \
+                        them", pc);
+                }
+                self.counts.reads += 1;
+                self.access(pc, sz, Some(val));
+            }
+            Event::ReadAddr { pc, sz, .. } => {
+                if expect.reads != Some(false) {
+                    unexpected("reads without values when values are \
+                        logged", pc);
+                }
+                self.counts.reads += 1;
+                self.access(pc, sz, None);
+            }
+            Event::Write { pc, val, sz, .. } => {
+                if expect.writes != Some(true) {
+                    unexpected("write values without write hooks logging \
+                        them", pc);
+                }
+                self.counts.writes += 1;
+                self.access(pc, sz, Some(val));
+            }
+            Event::WriteAddr { pc, sz, .. } => {
+                if expect.writes != Some(false) {
+                    unexpected("writes without values when values are \
+                        logged", pc);
+                }
+                self.counts.writes += 1;
+                self.access(pc, sz, None);
+            }
+            Event::Mmap { base, len, exec, ref path, .. } => {
+                if len == 0 {
+                    unexpected("empty mapping", base);
+                }
+                if exec {
+                    self.process.mappings.push((base, base.wrapping_add(len),
+                        path.clone()));
+                }
+                self.counts.maps += 1;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Results of the threads of the run being checked
+struct Run {
+    /// Filters of the run
+    filters: Filters,
+
+    /// Events seen
+    counts: Counts,
+
+    /// Broken invariants
+    violations: Violations,
+
+    /// Every process, by PID
+    processes: HashMap<i32, Process>,
+}
+
+/// Run being checked, set by [`start`]
+static RUN: Mutex<Option<Run>> = Mutex::new(None);
+
+/// Number of [`ConformanceSink`]s not dropped yet
+static OPEN: AtomicUsize = AtomicUsize::new(0);
+
+/// Start checking a run with `filters`, pushing them to every target
+pub fn start(filters: Filters) -> std::io::Result<()> {
+    *RUN.lock().unwrap() = Some(Run {
+        filters:    filters.clone(),
+        counts:     Counts::default(),
+        violations: Violations::new(),
+        processes:  HashMap::new(),
+    });
+    control::set_filters(filters).map(|_| ())
+}
+
+/// Results of checking a run
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Report {
+    /// Events seen
+    pub counts: Counts,
+
+    /// Broken invariants, none if the run conforms
+    pub violations: Vec<Violation>,
+}
+
+impl Report {
+    /// Check if the run conforms
+    pub fn passed(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+/// Get the report of the run of `guest` started with [`start`], once every
+/// thread of it is done or `timeout` passed
+pub fn finish(guest: &Path, timeout: Duration) -> Report {
+    let started = Instant::now();
+    while OPEN.load(Ordering::Relaxed) != 0 && started.elapsed() < timeout {
+        std::thread::sleep(POLL_INTERVAL);
+    }
+    let Some(mut run) = RUN.lock().unwrap().take() else {
+        return Report::default();
+    };
+    if OPEN.load(Ordering::Relaxed) != 0 {
+        violate(&mut run.violations, "threads still running",
+            || format!("{} after {timeout:?}", OPEN.load(Ordering::Relaxed)));
+    }
+    check_run(&mut run, guest);
+
+    Report {
+        counts:     run.counts,
+        violations: run.violations.into_iter().map(|(rule, x)| {
+            Violation { rule, count: x.0, example: x.1 }
+        }).collect(),
+    }
+}
+
+/// Check the invariants of the whole run `run` of `guest`
+fn check_run(run: &mut Run, guest: &Path) {
+    let expect = Expect::new(&run.filters);
+    let counts = run.counts;
+    let missing = [
+        (expect.exec, counts.exec, "no exec events"),
+        (expect.regs || expect.syscalls, counts.regs, "no register events"),
+        (expect.branches, counts.branches, "no branch events"),
+        (expect.reads.is_some(), counts.reads, "no read events"),
+        (expect.writes.is_some(), counts.writes, "no write events"),
+        (true, counts.maps, "no mappings"),
+    ];
+    for (expected, count, rule) in missing {
+        if expected && count == 0 {
+            violate(&mut run.violations, rule, String::new);
+        }
+    }
+
+    let guest = guest.file_name().and_then(|x| x.to_str()).unwrap_or("");
+    let mut pids = run.processes.keys().copied().collect::<Vec<_>>();
+    pids.sort();
+    for pid in pids {
+        let process = &run.processes[&pid];
+        if process.mappings.is_empty() {
+            continue;
+        }
+        let mapped = |page: u64| process.mappings.iter().any(|x| {
+            x.0 < page + PAGE_SIZE && page < x.1
+        });
+        let mut pages = process.pages.iter().copied().collect::<Vec<_>>();
+        pages.sort();
+        for page in pages.into_iter().filter(|x| !mapped(*x)) {
+            violate(&mut run.violations,
+                "code executed outside executable mappings",
+                || format!("page {page:#x} of process {pid}"));
+        }
+
+        // Only code the hooks see every execution of counts, and the guest
+        // may map more of itself than it runs
+        if !(expect.exec || expect.regs || expect.branches) {
+            continue;
+        }
+        let executed = process.mappings.iter()
+            .filter(|x| control::is_module(&x.2, guest))
+            .any(|x| process.pages.iter().any(|page| {
+                x.0 < page + PAGE_SIZE && *page < x.1
+            }));
+        if !executed {
+            violate(&mut run.violations, "guest code never executed",
+                || format!("process {pid}"));
+        }
+    }
+}
+
+/// Checks the events of a thread, see the module documentation
+pub struct ConformanceSink {
+    /// Process ID of the thread
+    pid: i32,
+
+    /// The checks, `None` if no run was started
+    checker: Option<Checker>,
+}
+
+impl Sink for ConformanceSink {
+    fn open(ci: &ClientInfo) -> std::io::Result<Self> {
+        OPEN.fetch_add(1, Ordering::Relaxed);
+        let checker = RUN.lock().unwrap().as_ref()
+            .map(|x| Checker::new(&x.filters));
+        Ok(Self { pid: ci.pid, checker })
+    }
+
+    fn write(&mut self, events: &[Event]) -> std::io::Result<()> {
+        if let Some(checker) = &mut self.checker {
+            for event in events {
+                checker.event(event);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ConformanceSink {
+    /// Add the results of the thread to those of the run
+    fn drop(&mut self) {
+        if let Some(checker) = self.checker.take() {
+            if let Some(run) = RUN.lock().unwrap().as_mut() {
+                run.counts.add(&checker.counts);
+                for (rule, (count, example)) in checker.violations {
+                    run.violations.entry(rule).or_insert((0, example)).0 +=
+                        count;
+                }
+                let process = run.processes.entry(self.pid).or_default();
+                process.pages.extend(checker.process.pages);
+                process.mappings.extend(checker.process.mappings);
+            }
+        }
+        OPEN.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[test]
+fn conformance_checks() {
+    let config = |name| configs().into_iter().find(|x| x.name == name)
+        .unwrap().filters;
+    let mmap = Event::Mmap {
+        base: 0x400000, len: 0x1000, anon: false, read: true, write: false,
+        exec: true, path: "/guests/hello".into(), offset: 0,
+    };
+    let check = |filters: &Filters, events: &[Event]| {
+        let mut checker = Checker::new(filters);
+        for event in events {
+            checker.event(event);
+        }
+        let mut run = Run {
+            filters:    filters.clone(),
+            counts:     checker.counts,
+            violations: checker.violations,
+            processes:  HashMap::from([(1, checker.process)]),
+        };
+        check_run(&mut run, Path::new("guests/hello"));
+        run.violations.into_keys().collect::<Vec<_>>()
+    };
+
+    // Conforming runs
+    let exec = Event::Exec { pc: 0x400010 };
+    assert!(check(&config("always"), &[mmap.clone(), exec.clone()])
+        .is_empty());
+    let read = Event::ReadAddr { pc: 0x400010, addr: 0x7000, sz: 4 };
+    let write = Event::WriteAddr { pc: 0x400010, addr: 0x7000, sz: 2 };
+    assert!(check(&config("addresses"), &[mmap.clone(), read, write])
+        .is_empty());
+
+    // Events of the wrong kind, bad accesses, and code out of place
+    let read = Event::Read { pc: 0x400010, addr: 0x7000, val: 0x100, sz: 1 };
+    let write = Event::Write { pc: 0x400010, addr: 0x7000, val: 0, sz: 3 };
+    assert_eq!(check(&config("memory"), &[mmap.clone(), exec, read, write]),
+        ["exec events with exec hooks off",
+         "memory access of an invalid size",
+         "memory value larger than its access"]);
+    assert_eq!(check(&config("once"), &[mmap, Event::Exec { pc: 0x500000 }]),
+        ["code executed outside executable mappings",
+         "guest code never executed"]);
+    assert_eq!(check(&config("registers"), &[]),
+        ["no mappings", "no register events"]);
+}
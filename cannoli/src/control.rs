@@ -0,0 +1,661 @@
+//! Control channel from the Cannoli client to the jitter running in QEMU
+//!
+//! Every target thread has a TCP connection to the client, which until now
+//! only carried the greeting. The client can send [`ControlMessage`]s back
+//! over the same connection to steer a running capture without restarting the
+//! guest, and the jitter answers requests with [`ControlReply`]s. Messages
+//! are framed as a little-endian `u32` length followed by the message as
+//! JSON.
+//!
+//! Control traffic never shares a queue with the events. Events go through
+//! the shared memory pipe, which can hold gigabytes the client hasn't
+//! processed yet, while control messages and replies go through the TCP
+//! connection and are handled by a dedicated thread on either side. A request
+//! is answered right away even while the target thread is stalled waiting
+//! for the client to free pipe buffers, see [`ping`].
+//!
+//! The jitter applies [`Filters`] when it decides how to hook code, which is
+//! when QEMU translates it. Code which was already translated keeps the hooks
+//! it was translated with until QEMU translates it again (eg. when its code
+//! cache fills up), so filter changes apply to new code right away and to
+//! hot code eventually.
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+#[cfg(feature = "client")]
+use std::path::Path;
+use std::sync::{Arc, Mutex, LazyLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::time::{Duration, Instant};
+use std::collections::HashMap;
+use serde::{Serialize, Deserialize};
+use serde::de::DeserializeOwned;
+use crate::ClientInfo;
+use crate::address_space::Mapping;
+use crate::regfile::RegFile;
+
+/// Largest control message we accept, anything bigger is a corrupt stream
+const MAX_MESSAGE_SIZE: u32 = 16 * 1024 * 1024;
+
+/// Largest read of guest memory a [`ControlMessage::ReadMemory`] may ask
+/// for, the bytes are sent as JSON and have to fit in a message
+pub const MAX_READ_MEMORY: u32 = 1024 * 1024;
+
+/// How the jitter hooks instructions which pass the [`Filters`], see
+/// `jitter::HookType` for the details of each
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HookKind {
+    /// Only the first execution of each translation of an instruction
+    Once,
+
+    /// Every execution
+    #[default]
+    Always,
+
+    /// Every execution, with the register state
+    Register,
+
+    /// Every execution, with the register state and branch information
+    Branch,
+
+    /// Only the sources and targets of control flow edges, see
+    /// [`crate::edges`]
+    Edge,
+
+    /// Only calls and returns, and where they went, see [`crate::calls`]
+    Function,
+}
+
+/// Check if the file at `path` is the module `name`, given by its path or
+/// by the start of its file name (eg. `libssl` for `/usr/lib/libssl.so.3`)
+pub fn is_module(path: &str, name: &str) -> bool {
+    let file = path.rsplit('/').next().unwrap_or(path);
+    !path.is_empty() && (path == name || file.starts_with(name))
+}
+
+/// Point at which the jitter starts hooking, see [`Filters::start`]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "on", rename_all = "lowercase", deny_unknown_fields)]
+pub enum Trigger {
+    /// A file of `module` is mapped, named as in [`is_module`]
+    Mmap { module: String },
+
+    /// The instruction at `offset` in `module` is translated, with offsets
+    /// as [`crate::address_space::AddressSpace::module_offset`] gives them
+    /// (and as `cannoli top` prints them). `offset` is the address of the
+    /// instruction without a module
+    Exec { module: Option<String>, offset: u64 },
+}
+
+/// Sampling of exec hooks, see [`Filters::sample`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "every", rename_all = "lowercase", deny_unknown_fields)]
+pub enum Sample {
+    /// Send every `count`th block executed, at most [`Sample::MAX_COUNT`]
+    Blocks { count: u32 },
+
+    /// Send the next block executed every `micros` microseconds
+    Time { micros: u64 },
+}
+
+impl Sample {
+    /// Largest [`Sample::Blocks`] count, the jitter counts down from it in
+    /// a sign-extended 32-bit immediate
+    pub const MAX_COUNT: u32 = i32::MAX as u32;
+
+    /// Get the number of blocks between samples the jitter counts down
+    /// from. Time sampling never gets there, a timer samples the next block
+    /// instead
+    pub fn period(&self) -> u32 {
+        match self {
+            Sample::Blocks { count } => (*count).clamp(1, Self::MAX_COUNT),
+            Sample::Time { .. } => Self::MAX_COUNT,
+        }
+    }
+}
+
+/// What the jitter hooks
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Filters {
+    /// Hook instruction execution
+    pub exec: bool,
+
+    /// How instructions are hooked
+    pub hook: HookKind,
+
+    /// Hook memory reads
+    pub reads: bool,
+
+    /// Hook memory writes
+    pub writes: bool,
+
+    /// Log the values of hooked memory reads. Without them read events only
+    /// carry the PC, address and size, which is about half the size
+    pub read_values: bool,
+
+    /// Log the values of hooked memory writes
+    pub write_values: bool,
+
+    /// Only hook code in these `[start, end)` ranges, or everywhere if
+    /// neither these nor [`Filters::modules`] are given
+    pub include: Vec<[u64; 2]>,
+
+    /// Also hook code in these files, named as in [`is_module`]. The
+    /// jitter adds the ranges they are mapped at to the included ranges as
+    /// they are mapped, see [`Filters::resolve`]
+    pub modules: Vec<String>,
+
+    /// Never hook code in these `[start, end)` ranges
+    pub exclude: Vec<[u64; 2]>,
+
+    /// FP and vector register files captured by register and branch hooks,
+    /// see [`crate::regfile`]
+    pub reg_files: Vec<RegFile>,
+
+    /// Report system calls, see [`crate::Cannoli::syscall_entry`]. System
+    /// call instructions and the instructions after them are hooked with
+    /// register hooks, even where nothing else is hooked
+    pub syscalls: bool,
+
+    /// Send the general purpose registers with every instruction hooked
+    /// `once` or `always`, see [`crate::Cannoli::exec_with_regs`]. Those
+    /// hooks then fire on every execution, like register hooks
+    pub exec_regs: bool,
+
+    /// Only send the first this many general purpose registers, in QEMU's
+    /// order, with [`Filters::exec_regs`], to keep the events small. All of
+    /// them if `None`
+    pub exec_reg_count: Option<usize>,
+
+    /// Send the code of every instruction hooked `once` or `always`, see
+    /// [`crate::Cannoli::exec_with_bytes`]. Not applied to instructions
+    /// which send their registers with [`Filters::exec_regs`]
+    pub exec_bytes: bool,
+
+    /// Report the operands of comparison instructions, see
+    /// [`crate::Cannoli::cmp`]. The jitter decodes every instruction it
+    /// lifts, and hooks the comparisons
+    /// [`crate::symbols::signatures::Signatures::comparison`] knows with
+    /// their own hook, even where nothing else is hooked. Comparisons with
+    /// memory operands aren't reported
+    pub cmp: bool,
+
+    /// Don't hook instructions or memory accesses until the trigger fires in
+    /// the jitter, which catches the exact point where a client reacting to
+    /// events would be late. The trigger is armed again when filters with
+    /// another trigger are set
+    pub start: Option<Trigger>,
+
+    /// Only send a sample of the instructions hooked `always`, for
+    /// statistical profiling where the pipe can't keep up with every
+    /// block. Only the first instruction of every translation block is
+    /// hooked then, and only every so many blocks the process executes are
+    /// sent, see [`Sample`]. Register hooks (including those of
+    /// [`Filters::exec_regs`]) and edge hooks aren't sampled
+    pub sample: Option<Sample>,
+}
+
+impl Default for Filters {
+    fn default() -> Self {
+        Self {
+            exec:           true,
+            hook:           HookKind::Always,
+            reads:          true,
+            writes:         true,
+            read_values:    true,
+            write_values:   true,
+            include:        Vec::new(),
+            modules:        Vec::new(),
+            exclude:        Vec::new(),
+            reg_files:      Vec::new(),
+            syscalls:       false,
+            exec_regs:      false,
+            exec_reg_count: None,
+            exec_bytes:     false,
+            cmp:            false,
+            start:          None,
+            sample:         None,
+        }
+    }
+}
+
+impl Filters {
+    /// Check if code at `pc` may be hooked at all
+    pub fn allows_pc(&self, pc: u64) -> bool {
+        let inside = |ranges: &[[u64; 2]]| {
+            ranges.iter().any(|[start, end]| pc >= *start && pc < *end)
+        };
+
+        ((self.include.is_empty() && self.modules.is_empty()) ||
+            inside(&self.include)) && !inside(&self.exclude)
+    }
+
+    /// Check if the file at `path` is one of [`Filters::modules`]
+    pub fn matches_module(&self, path: &str) -> bool {
+        self.modules.iter().any(|x| is_module(path, x))
+    }
+
+    /// Get the filters with the ranges of the `mappings` of
+    /// [`Filters::modules`] added to the included ranges, which is how the
+    /// jitter applies module filters
+    pub fn resolve<'a>(&self,
+            mappings: impl IntoIterator<Item = &'a Mapping>) -> Self {
+        let mut ret = self.clone();
+        if !self.modules.is_empty() {
+            ret.include.extend(mappings.into_iter()
+                .filter(|x| !x.anon && self.matches_module(&x.path))
+                .map(|x| [x.base, x.end()]));
+        }
+        ret
+    }
+
+    /// Get how the instruction at `pc` should be hooked, `None` if it
+    /// shouldn't be
+    pub fn hook_inst(&self, pc: u64) -> Option<HookKind> {
+        (self.exec && self.allows_pc(pc)).then_some(self.hook)
+    }
+
+    /// Check if the operands of a comparison at `pc` should be reported
+    pub fn hook_cmp(&self, pc: u64) -> bool {
+        self.cmp && self.allows_pc(pc)
+    }
+
+    /// Check if a memory access by the instruction at `pc` should be hooked
+    pub fn hook_mem(&self, pc: u64, write: bool) -> bool {
+        (if write { self.writes } else { self.reads }) && self.allows_pc(pc)
+    }
+
+    /// Check if the values of hooked memory accesses are logged, or only
+    /// their addresses
+    pub fn mem_values(&self, write: bool) -> bool {
+        if write { self.write_values } else { self.read_values }
+    }
+}
+
+/// Frame `msg` to be written as is
+fn frame(msg: &impl Serialize) -> std::io::Result<Vec<u8>> {
+    let body = serde_json::to_vec(msg)?;
+    let mut frame = (body.len() as u32).to_le_bytes().to_vec();
+    frame.extend_from_slice(&body);
+    Ok(frame)
+}
+
+/// Write `msg` framed to `stream`
+fn write_frame(msg: &impl Serialize, mut stream: impl Write)
+        -> std::io::Result<()> {
+    stream.write_all(&frame(msg)?)
+}
+
+/// Read the next framed message from `stream`
+fn read_frame<T: DeserializeOwned>(mut stream: impl Read)
+        -> std::io::Result<T> {
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len)?;
+    let len = u32::from_le_bytes(len);
+    if len > MAX_MESSAGE_SIZE {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData,
+            format!("Control message of {len} bytes is too large")));
+    }
+
+    let mut body = vec![0u8; len as usize];
+    stream.read_exact(&mut body)?;
+    Ok(serde_json::from_slice(&body)?)
+}
+
+/// A message from the client to the jitter
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ControlMessage {
+    /// Replace the filters of the target process
+    SetFilters(Filters),
+
+    /// Request a [`ControlReply::Pong`] with the same `id`
+    Ping { id: u64 },
+
+    /// Request a [`ControlReply::Memory`] with `len` bytes of guest memory
+    /// at `addr`, at most [`MAX_READ_MEMORY`]
+    ReadMemory { id: u64, addr: u64, len: u32 },
+
+    /// Mark the epoch `id` in the trace of every thread, see [`epoch`]
+    Epoch { id: u64 },
+
+    /// Request a [`ControlReply::GuestBase`]
+    GuestBase { id: u64 },
+}
+
+impl ControlMessage {
+    /// Write the framed message to `stream`
+    pub fn write_to(&self, stream: impl Write) -> std::io::Result<()> {
+        write_frame(self, stream)
+    }
+
+    /// Read the next framed message from `stream`
+    pub fn read_from(stream: impl Read) -> std::io::Result<Self> {
+        read_frame(stream)
+    }
+}
+
+/// A reply from the jitter to a request of the client
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ControlReply {
+    /// Reply to [`ControlMessage::Ping`]
+    Pong { id: u64 },
+
+    /// Reply to [`ControlMessage::ReadMemory`], `None` if any of the memory
+    /// isn't mapped readable
+    Memory { id: u64, data: Option<Vec<u8>> },
+
+    /// Reply to [`ControlMessage::GuestBase`] with QEMU's `guest_base`,
+    /// `None` if QEMU doesn't export it
+    GuestBase { id: u64, base: Option<u64> },
+}
+
+impl ControlReply {
+    /// Get the ID of the request this replies to
+    pub fn id(&self) -> u64 {
+        match self {
+            ControlReply::Pong { id } | ControlReply::Memory { id, .. } |
+            ControlReply::GuestBase { id, .. } => *id,
+        }
+    }
+
+    /// Write the framed reply to `stream`
+    pub fn write_to(&self, stream: impl Write) -> std::io::Result<()> {
+        write_frame(self, stream)
+    }
+
+    /// Read the next framed reply from `stream`
+    pub fn read_from(stream: impl Read) -> std::io::Result<Self> {
+        read_frame(stream)
+    }
+}
+
+/// Connection of a target thread. Messages are written with its lock held
+/// so they aren't interleaved, but never with [`CONNECTIONS`] locked, so a
+/// thread which doesn't read its stream only blocks the messages to it
+type Connection = Arc<Mutex<TcpStream>>;
+
+/// Connections to every target thread, keyed by PID and TID
+static CONNECTIONS: LazyLock<Mutex<HashMap<(i32, i32), Connection>>> =
+    LazyLock::new(Default::default);
+
+/// Latest filters set with [`set_filters`], sent to threads which connect
+/// later
+static FILTERS: Mutex<Option<Filters>> = Mutex::new(None);
+
+/// Requests waiting for a reply, keyed by ID
+static PENDING: LazyLock<Mutex<HashMap<u64, SyncSender<ControlReply>>>> =
+    LazyLock::new(Default::default);
+
+/// ID of the next request
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Remember the connection of a newly connected thread, and bring it up to
+/// date with the current filters
+pub(crate) fn register(ci: &ClientInfo, stream: &TcpStream)
+        -> std::io::Result<()> {
+    let stream = stream.try_clone()?;
+    if let Some(filters) = FILTERS.lock().unwrap().clone() {
+        ControlMessage::SetFilters(filters).write_to(&stream)?;
+    }
+    CONNECTIONS.lock().unwrap().insert((ci.pid, ci.tid),
+        Arc::new(Mutex::new(stream)));
+    Ok(())
+}
+
+/// Forget the connection of a thread which exited
+pub(crate) fn unregister(ci: &ClientInfo) {
+    CONNECTIONS.lock().unwrap().remove(&(ci.pid, ci.tid));
+}
+
+/// Receive replies from the connection `stream` of a thread until it's
+/// closed, handing them to the requests waiting for them
+pub(crate) fn receive(stream: TcpStream) {
+    while let Ok(reply) = ControlReply::read_from(&stream) {
+        if let Some(waiter) = PENDING.lock().unwrap().remove(&reply.id()) {
+            let _ = waiter.send(reply);
+        }
+    }
+}
+
+/// Send the request `msg`, built for a fresh request ID, to the thread `tid`
+/// of the target process `pid` and wait at most `timeout` for the reply
+pub fn request(pid: i32, tid: i32, timeout: Duration,
+        msg: impl FnOnce(u64) -> ControlMessage)
+        -> std::io::Result<ControlReply> {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let (send, recv) = sync_channel(1);
+    PENDING.lock().unwrap().insert(id, send);
+
+    let stream = CONNECTIONS.lock().unwrap().get(&(pid, tid)).cloned();
+    let sent = match stream {
+        Some(stream) => msg(id).write_to(&*stream.lock().unwrap()),
+        None => Err(std::io::Error::new(std::io::ErrorKind::NotFound,
+            format!("Thread {pid}:{tid} isn't connected"))),
+    };
+    let reply = sent.and_then(|()| recv.recv_timeout(timeout).map_err(|_| {
+        std::io::Error::new(std::io::ErrorKind::TimedOut,
+            format!("Thread {pid}:{tid} didn't reply"))
+    }));
+
+    PENDING.lock().unwrap().remove(&id);
+    reply
+}
+
+/// Measure the round trip time of the control channel to the thread `tid` of
+/// the target process `pid`
+pub fn ping(pid: i32, tid: i32, timeout: Duration)
+        -> std::io::Result<Duration> {
+    let started = Instant::now();
+    request(pid, tid, timeout, |id| ControlMessage::Ping { id })?;
+    Ok(started.elapsed())
+}
+
+/// Read `len` bytes of the guest memory at `addr` of the target process
+/// `pid`, through its thread `tid`. This is the memory as it is now, which
+/// the target may have changed since the events the client is processing.
+/// See [`crate::guest::GuestMemory`] for cached reads
+pub fn read_memory(pid: i32, tid: i32, addr: u64, len: u32,
+        timeout: Duration) -> std::io::Result<Vec<u8>> {
+    if len > MAX_READ_MEMORY {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput,
+            format!("Can't read more than {MAX_READ_MEMORY} bytes at once")));
+    }
+
+    let reply = request(pid, tid, timeout,
+        |id| ControlMessage::ReadMemory { id, addr, len })?;
+    match reply {
+        ControlReply::Memory { data: Some(data), .. }
+                if data.len() == len as usize => Ok(data),
+        _ => Err(std::io::Error::new(std::io::ErrorKind::InvalidData,
+            format!("Failed to read {len} bytes at {addr:#x}"))),
+    }
+}
+
+/// Get QEMU's `guest_base` of the target process `pid` through its thread
+/// `tid`, the offset of guest addresses in QEMU's own address space. See
+/// [`crate::dual`] for what it's good for
+pub fn guest_base(pid: i32, tid: i32, timeout: Duration)
+        -> std::io::Result<u64> {
+    let reply = request(pid, tid, timeout,
+        |id| ControlMessage::GuestBase { id })?;
+    match reply {
+        ControlReply::GuestBase { base: Some(base), .. } => Ok(base),
+        _ => Err(std::io::Error::new(std::io::ErrorKind::Unsupported,
+            "QEMU doesn't export `guest_base`")),
+    }
+}
+
+/// Start a new epoch in every connected thread and return its ID, which
+/// increases with every epoch. Each thread marks the epoch in its trace the
+/// next time it exits translated code, which flushes the events it produced
+/// so far, and [`crate::Cannoli::epoch_complete`] is invoked once
+/// processing reaches the mark. QEMU exits translated code at least for
+/// every syscall and signal, a thread blocked in a syscall marks the epoch
+/// once the syscall returns
+pub fn epoch() -> std::io::Result<u64> {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed) + 1;
+    broadcast(&ControlMessage::Epoch { id })?;
+    Ok(id)
+}
+
+/// Send `msg` to the connected threads `select` picks by PID and TID,
+/// returning the number of threads it was delivered to. A failed write
+/// doesn't keep it from the other threads, it's reported and the connection
+/// is forgotten as its thread is gone or its stream is broken
+fn deliver(msg: &ControlMessage, select: impl Fn(&(i32, i32)) -> bool)
+        -> std::io::Result<usize> {
+    let frame = frame(msg)?;
+    let streams = CONNECTIONS.lock().unwrap().iter()
+        .filter(|(x, _)| select(x))
+        .map(|(&key, stream)| (key, stream.clone()))
+        .collect::<Vec<_>>();
+
+    let mut sent   = 0;
+    let mut failed = Vec::new();
+    for (key, stream) in streams {
+        let written = (&*stream.lock().unwrap()).write_all(&frame);
+        match written {
+            Ok(()) => sent += 1,
+            Err(err) => {
+                eprintln!("Failed to send control message to thread \
+                    {}:{}: {err}", key.0, key.1);
+                failed.push((key, stream));
+            }
+        }
+    }
+
+    // Only forget the connections which failed, not ones of a thread which
+    // reconnected with the same IDs in the meantime
+    let mut connections = CONNECTIONS.lock().unwrap();
+    for (key, stream) in failed {
+        if connections.get(&key).is_some_and(|x| Arc::ptr_eq(x, &stream)) {
+            connections.remove(&key);
+        }
+    }
+    Ok(sent)
+}
+
+/// Send `msg` to every thread of the target process `pid`, returning the
+/// number of threads it was delivered to, see [`broadcast`]
+pub fn send(pid: i32, msg: &ControlMessage) -> std::io::Result<usize> {
+    deliver(msg, |&(x, _)| x == pid)
+}
+
+/// Send `msg` to every connected thread, returning the number of threads it
+/// was delivered to. Threads it can't be written to are reported on stderr
+/// and forgotten, only failing to encode `msg` is an error
+pub fn broadcast(msg: &ControlMessage) -> std::io::Result<usize> {
+    deliver(msg, |_| true)
+}
+
+/// Replace the filters of every target, including ones which connect later
+pub fn set_filters(filters: Filters) -> std::io::Result<usize> {
+    *FILTERS.lock().unwrap() = Some(filters.clone());
+    broadcast(&ControlMessage::SetFilters(filters))
+}
+
+/// Handle to toggle hooks of every target at runtime, see
+/// [`crate::Cannoli::control`]
+///
+/// Every change pushes the updated filters to every target, including ones
+/// which connect later. Like any filter change it applies to code as QEMU
+/// translates it, code which was already translated with hooks keeps them
+/// for a while. Use [`Filters::start`] to start hooking at a precise point
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Control;
+
+impl Control {
+    /// Get the filters currently set, the defaults if none were set
+    pub fn filters(&self) -> Filters {
+        FILTERS.lock().unwrap().clone().unwrap_or_default()
+    }
+
+    /// Change the current filters with `change` and push them to every
+    /// target, returning the number of threads they were sent to
+    pub fn update(&self, change: impl FnOnce(&mut Filters))
+            -> std::io::Result<usize> {
+        // Keep the lock while sending, so concurrent updates arrive in order
+        let mut filters = FILTERS.lock().unwrap();
+        let filters = filters.get_or_insert_with(Default::default);
+        change(filters);
+        broadcast(&ControlMessage::SetFilters(filters.clone()))
+    }
+
+    /// Enable or disable instruction hooks
+    pub fn set_exec(&self, enabled: bool) -> std::io::Result<usize> {
+        self.update(|x| x.exec = enabled)
+    }
+
+    /// Enable or disable memory read hooks
+    pub fn set_reads(&self, enabled: bool) -> std::io::Result<usize> {
+        self.update(|x| x.reads = enabled)
+    }
+
+    /// Enable or disable memory write hooks
+    pub fn set_writes(&self, enabled: bool) -> std::io::Result<usize> {
+        self.update(|x| x.writes = enabled)
+    }
+}
+
+/// Load the filters from the config file at `path`, and push them to the
+/// targets again every time the file changes. Invalid configs are reported
+/// and ignored, the previous filters stay in effect
+#[cfg(feature = "client")]
+pub fn watch_filters(path: impl AsRef<Path>) -> std::io::Result<()> {
+    let path = path.as_ref();
+    set_filters(crate::config::Config::load(path)?.filters)?;
+
+    crate::config::watch(path, |config| match config {
+        Ok(config) => {
+            if let Err(err) = set_filters(config.filters) {
+                eprintln!("Failed to push new filters: {err}");
+            }
+        }
+        Err(err) => eprintln!("Ignoring invalid config: {err}"),
+    })?;
+    Ok(())
+}
+
+#[test]
+fn filters_and_framing() {
+    let filters = Filters {
+        include: vec![[0x1000, 0x2000]],
+        exclude: vec![[0x1800, 0x1900]],
+        reads:   false,
+        ..Default::default()
+    };
+    assert_eq!(filters.hook_inst(0x1000), Some(HookKind::Always));
+    assert_eq!(filters.hook_inst(0x1880), None);
+    assert_eq!(filters.hook_inst(0x2000), None);
+    assert!(!filters.hook_mem(0x1000, false));
+    assert!(filters.hook_mem(0x1000, true));
+    assert!(filters.mem_values(false) && filters.mem_values(true));
+
+    // Modules only allow code once they're mapped
+    let modules = Filters { modules: vec!["libssl".into()],
+        ..Default::default() };
+    assert_eq!(modules.hook_inst(0x7000), None);
+    let mut space = crate::address_space::AddressSpace::new();
+    space.mmap(0x7000, 0x1000, false, true, false, true,
+        "/usr/lib/libssl.so.3", 0);
+    space.mmap(0x9000, 0x1000, false, true, false, true, "/bin/app", 0);
+    let resolved = modules.resolve(space.mappings());
+    assert_eq!(resolved.hook_inst(0x7000), Some(HookKind::Always));
+    assert_eq!(resolved.hook_inst(0x9000), None);
+    assert!(is_module("/usr/lib/libssl.so.3", "libssl"));
+    assert!(is_module("/bin/app", "/bin/app"));
+    assert!(!is_module("/bin/app", "libssl") && !is_module("", ""));
+
+    let msg = ControlMessage::SetFilters(filters);
+    let mut buf = Vec::new();
+    msg.write_to(&mut buf).unwrap();
+    assert_eq!(ControlMessage::read_from(&buf[..]).unwrap(), msg);
+
+    let reply = ControlReply::Pong { id: 7 };
+    let mut buf = Vec::new();
+    reply.write_to(&mut buf).unwrap();
+    assert_eq!(ControlReply::read_from(&buf[..]).unwrap().id(), 7);
+}
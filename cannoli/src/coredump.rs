@@ -0,0 +1,768 @@
+//! Aligning a core dump of a traced process with its trace
+//!
+//! A core dump says what memory and registers looked like when the target
+//! crashed, but not how they got there. The trace of the same run says how,
+//! but not what the end result was. An [`Aligner`] puts the two together: it
+//! follows the events of the crashed process and then, for the
+//! [`CoreDump`], tells
+//!
+//! - how the modules of the dump line up with those of the trace
+//!   ([`Slide`]), which only differ if the dump is from another run
+//! - whether the registers of the crashed thread are those of the last
+//!   instruction traced on it ([`Registers`])
+//! - for every interesting region of the dump (writable, or written by the
+//!   trace), where it came from and who wrote it ([`Region`]): the mapping
+//!   which created it, the instructions which wrote to it the most, the last
+//!   write, and how many of the last values written still are in the dump.
+//!   Values which aren't were changed by something the trace doesn't show,
+//!   eg. the kernel, a filtered out module or the crash itself
+//!
+//! Dumps are ELF core files, as written by the kernel or by QEMU for the
+//! guest. Modules are aligned with the `NT_FILE` note, which QEMU doesn't
+//! write, dumps without it are assumed to be from the traced run. The
+//! registers of the trace are in QEMU's layout and those of the dump in the
+//! kernel's, they're compared as the set of register-sized values in them,
+//! which is enough to tell if they're from the same instruction.
+
+use std::io;
+use std::path::Path;
+use std::collections::{BTreeMap, HashMap};
+use serde::Serialize;
+use crate::Event;
+use crate::heap::HeapEvent;
+use crate::history::{Block, BranchHistory, Histories};
+use crate::address_space::{AddressSpace, HeapKind};
+
+/// Program header type of a loadable segment
+const PT_LOAD: u32 = 1;
+
+/// Program header type of notes
+const PT_NOTE: u32 = 4;
+
+/// Note with the status and registers of a thread
+const NT_PRSTATUS: u32 = 1;
+
+/// Note with the files mapped by the process
+const NT_FILE: u32 = 0x46494c45;
+
+/// Most writers reported for a region
+const MAX_WRITERS: usize = 5;
+
+/// A loadable segment of a core dump, a mapping of the process
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct CoreSegment {
+    /// Address of the segment
+    pub base: u64,
+
+    /// Length of the segment in bytes
+    pub len: u64,
+
+    /// Readable
+    pub read: bool,
+
+    /// Writable
+    pub write: bool,
+
+    /// Executable
+    pub exec: bool,
+
+    /// Offset of the dumped bytes in the file
+    #[serde(skip)]
+    offset: u64,
+
+    /// Number of bytes dumped, from the start of the segment
+    pub dumped: u64,
+}
+
+/// A thread of a core dump
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CoreThread {
+    /// Thread ID
+    pub tid: i32,
+
+    /// Signal the thread got, 0 for threads which didn't crash
+    pub signal: u16,
+
+    /// General purpose registers, in the layout of the kernel for the
+    /// architecture
+    pub regs: Vec<u8>,
+}
+
+/// A file mapping of a core dump
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CoreFile {
+    /// Start of the mapping
+    pub start: u64,
+
+    /// End of the mapping, exclusive
+    pub end: u64,
+
+    /// Offset of the mapping in the file, in bytes
+    pub offset: u64,
+
+    /// Path of the file
+    pub path: String,
+}
+
+/// A parsed ELF core dump
+#[derive(Clone, Debug)]
+pub struct CoreDump {
+    /// Whether the dump is of a 64-bit process
+    pub is_64: bool,
+
+    /// Whether the dump is of a big endian process
+    pub big_endian: bool,
+
+    /// Mappings, sorted by address
+    pub segments: Vec<CoreSegment>,
+
+    /// Threads, in the order of the dump, the crashed one first
+    pub threads: Vec<CoreThread>,
+
+    /// File mappings, empty without an `NT_FILE` note
+    pub files: Vec<CoreFile>,
+
+    /// Contents of the file
+    data: Vec<u8>,
+}
+
+impl CoreDump {
+    /// Load the core dump at `path`
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::parse(std::fs::read(path)?)
+    }
+
+    /// Parse the core dump `data`
+    pub fn parse(data: Vec<u8>) -> io::Result<Self> {
+        let invalid = |x: &str| io::Error::new(io::ErrorKind::InvalidData,
+            format!("Invalid core dump: {x}"));
+        if data.get(..4) != Some(b"\x7fELF") {
+            return Err(invalid("not an ELF"));
+        }
+        let is_64      = data.get(4) == Some(&2);
+        let big_endian = data.get(5) == Some(&2);
+        let word = if is_64 { 8 } else { 4 };
+
+        // Reads of the endianness of the dump
+        let read = |offset: u64, len: usize| -> io::Result<u64> {
+            let bytes = usize::try_from(offset).ok()
+                .and_then(|x| data.get(x..x.checked_add(len)?))
+                .ok_or_else(|| invalid("truncated"))?;
+            let mut buf = [0u8; 8];
+            if big_endian {
+                buf[8 - len..].copy_from_slice(bytes);
+                Ok(u64::from_be_bytes(buf))
+            } else {
+                buf[..len].copy_from_slice(bytes);
+                Ok(u64::from_le_bytes(buf))
+            }
+        };
+
+        if read(16, 2)? != 4 {
+            return Err(invalid("not a core file"));
+        }
+        let (phoff, phentsize, phnum) = if is_64 {
+            (read(32, 8)?, read(54, 2)?, read(56, 2)?)
+        } else {
+            (read(28, 4)?, read(42, 2)?, read(44, 2)?)
+        };
+
+        let mut ret = Self {
+            segments: Vec::new(),
+            threads:  Vec::new(),
+            files:    Vec::new(),
+            is_64, big_endian,
+            data: Vec::new(),
+        };
+        for ii in 0..phnum {
+            let ph = phoff + ii * phentsize;
+            let (kind, flags, offset, vaddr, filesz, memsz) = if is_64 {
+                (read(ph, 4)?, read(ph + 4, 4)?, read(ph + 8, 8)?,
+                    read(ph + 16, 8)?, read(ph + 32, 8)?, read(ph + 40, 8)?)
+            } else {
+                (read(ph, 4)?, read(ph + 24, 4)?, read(ph + 4, 4)?,
+                    read(ph + 8, 4)?, read(ph + 16, 4)?, read(ph + 20, 4)?)
+            };
+
+            match kind as u32 {
+                PT_LOAD => ret.segments.push(CoreSegment {
+                    base:   vaddr,
+                    len:    memsz,
+                    read:   flags & 4 != 0,
+                    write:  flags & 2 != 0,
+                    exec:   flags & 1 != 0,
+                    dumped: filesz.min(memsz),
+                    offset,
+                }),
+                PT_NOTE => {
+                    // namesz, descsz, type, then the name and the
+                    // description, both padded to 4 bytes
+                    let align = |x: u64| (x + 3) & !3;
+                    let mut note = offset;
+                    while note + 12 <= offset + filesz {
+                        let namesz = read(note, 4)?;
+                        let descsz = read(note + 4, 4)?;
+                        let kind   = read(note + 8, 4)? as u32;
+                        let desc   = note + 12 + align(namesz);
+                        note = desc + align(descsz);
+                        if note > offset + filesz {
+                            return Err(invalid("truncated note"));
+                        }
+
+                        match kind {
+                            NT_PRSTATUS => {
+                                // `elf_prstatus`: the signal after
+                                // `elf_siginfo`, the PID after the signal
+                                // masks and the registers after 4 times,
+                                // followed by `pr_fpvalid`
+                                let (pid, regs) = if is_64 {
+                                    (32, 112)
+                                } else {
+                                    (24, 72)
+                                };
+                                let start = (desc + regs) as usize;
+                                let end = (desc + descsz)
+                                    .saturating_sub(word) as usize;
+                                ret.threads.push(CoreThread {
+                                    tid:    read(desc + pid, 4)? as i32,
+                                    signal: read(desc + 12, 2)? as u16,
+                                    regs:   data.get(start..end)
+                                        .ok_or_else(|| invalid("truncated"))?
+                                        .to_vec(),
+                                });
+                            }
+                            NT_FILE => {
+                                // count, page size, count times start, end
+                                // and page offset, then the paths
+                                let count = read(desc, word as usize)?;
+                                let page  = read(desc + word, word as usize)?;
+                                let mut path = desc + (2 + 3 * count) * word;
+                                for jj in 0..count {
+                                    let entry = desc + (2 + 3 * jj) * word;
+                                    let end = data.get(path as usize..)
+                                        .and_then(|x| {
+                                            x.iter().position(|&x| x == 0)
+                                        })
+                                        .ok_or_else(|| invalid("truncated"))?;
+                                    let name = &data[path as usize..][..end];
+                                    ret.files.push(CoreFile {
+                                        start:  read(entry, word as usize)?,
+                                        end:    read(entry + word,
+                                            word as usize)?,
+                                        offset: read(entry + 2 * word,
+                                            word as usize)? * page,
+                                        path: String::from_utf8_lossy(name)
+                                            .into_owned(),
+                                    });
+                                    path += end as u64 + 1;
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        ret.segments.sort_by_key(|x| x.base);
+        ret.data = data;
+        Ok(ret)
+    }
+
+    /// Get the segment containing `addr`
+    pub fn segment(&self, addr: u64) -> Option<&CoreSegment> {
+        let idx = self.segments.partition_point(|x| x.base <= addr);
+        self.segments.get(idx.checked_sub(1)?)
+            .filter(|x| addr - x.base < x.len)
+    }
+
+    /// Read `len` bytes at `addr` from the dump, `None` if they weren't
+    /// all dumped
+    pub fn read(&self, addr: u64, len: usize) -> Option<&[u8]> {
+        let segment = self.segment(addr)?;
+        let start = addr - segment.base;
+        if start + len as u64 > segment.dumped {
+            return None;
+        }
+        let start = usize::try_from(segment.offset + start).ok()?;
+        self.data.get(start..start.checked_add(len)?)
+    }
+
+    /// Get the thread which crashed, the first one with a signal or the
+    /// first one
+    pub fn crashed(&self) -> Option<&CoreThread> {
+        self.threads.iter().find(|x| x.signal != 0)
+            .or(self.threads.first())
+    }
+
+    /// Get the file mapping containing `addr`
+    fn file(&self, addr: u64) -> Option<&CoreFile> {
+        self.files.iter().find(|x| (x.start..x.end).contains(&addr))
+    }
+}
+
+/// Where a module is loaded in the trace and in the dump
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Slide {
+    /// Path of the module
+    pub module: String,
+
+    /// Base of the module in the trace
+    pub trace: u64,
+
+    /// Base of the module in the dump
+    pub core: u64,
+}
+
+/// How the registers of the crashed thread compare with the trace
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Registers {
+    /// Thread ID of the crashed thread
+    pub tid: i32,
+
+    /// Signal the thread got
+    pub signal: u16,
+
+    /// PC of the last instruction traced on the thread, in the dump
+    pub last_pc: Option<u64>,
+
+    /// Whether the PC is among the registers of the dump
+    pub pc_in_core: bool,
+
+    /// Number of register-sized values of the last traced registers which
+    /// are among the registers of the dump
+    pub matching: usize,
+
+    /// Number of register-sized values of the last traced registers, 0 if
+    /// none were traced
+    pub traced: usize,
+
+    /// Last blocks traced on the thread, the oldest first, in the dump's
+    /// addresses, see [`crate::history`]
+    pub history: Vec<Block>,
+}
+
+/// Where a region of the dump came from, according to the trace
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Origin {
+    /// Mapped from a file
+    File {
+        /// Path of the file
+        path: String,
+
+        /// Offset in the file the region starts at
+        offset: u64,
+    },
+
+    /// Heap growth, see [`crate::heap`]
+    Heap {
+        /// What the heap mapping is used for
+        heap: HeapKind,
+    },
+
+    /// An anonymous mapping
+    Anonymous,
+
+    /// The trace has no mapping there
+    Untraced,
+}
+
+/// A region of the dump with what the trace says about it
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Region {
+    /// The segment of the dump
+    pub segment: CoreSegment,
+
+    /// Where it came from
+    pub origin: Origin,
+
+    /// Whether the trace has a mapping with the same range and permissions
+    pub same_mapping: bool,
+
+    /// Number of traced writes to the region
+    pub writes: u64,
+
+    /// PCs which wrote to the region the most with their number of writes,
+    /// in the dump's addresses, most first
+    pub writers: Vec<(u64, u64)>,
+
+    /// PC and address of the last write to the region, in the dump's
+    /// addresses
+    pub last_write: Option<(u64, u64)>,
+
+    /// Number of addresses of the region whose last traced value is the one
+    /// in the dump
+    pub agreeing: u64,
+
+    /// Number of addresses of the region whose last traced value isn't the
+    /// one in the dump
+    pub disagreeing: u64,
+}
+
+/// The alignment of a dump with a trace, see the module documentation
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Alignment {
+    /// Modules loaded in both
+    pub slides: Vec<Slide>,
+
+    /// Registers of the crashed thread, if the dump has threads
+    pub registers: Option<Registers>,
+
+    /// Regions which are writable or were written, by address
+    pub regions: Vec<Region>,
+
+    /// Number of mappings of the trace which aren't in the dump
+    pub missing: usize,
+}
+
+impl Alignment {
+    /// Save the alignment to `path` as JSON, to annotate the dump with
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        std::fs::write(path, serde_json::to_vec_pretty(self)?)
+    }
+}
+
+/// Aligns a core dump with the trace of the process, see the module
+/// documentation
+#[derive(Debug)]
+pub struct Aligner {
+    /// The dump
+    core: CoreDump,
+
+    /// Mappings of the process in the trace
+    space: AddressSpace,
+
+    /// Last traced registers of every thread, with their PC
+    regs: HashMap<i32, (u64, Vec<u8>)>,
+
+    /// Last PC traced on every thread
+    pcs: HashMap<i32, u64>,
+
+    /// Last blocks traced on every thread
+    histories: HashMap<i32, BranchHistory>,
+
+    /// Last traced write to every address, as its value, size and PC
+    values: BTreeMap<u64, (u64, u8, u64)>,
+
+    /// Number of traced writes by every PC to every region, by the index of
+    /// the region in the dump
+    writers: HashMap<(usize, u64), u64>,
+
+    /// Last write to every region, as its PC and address, in the trace's
+    /// addresses
+    last: HashMap<usize, (u64, u64)>,
+}
+
+impl Aligner {
+    /// Create an aligner of `core`
+    pub fn new(core: CoreDump) -> Self {
+        Self {
+            core,
+            space:   AddressSpace::new(),
+            regs:    HashMap::new(),
+            pcs:       HashMap::new(),
+            histories: HashMap::new(),
+            values:    BTreeMap::new(),
+            writers: HashMap::new(),
+            last:    HashMap::new(),
+        }
+    }
+
+    /// Get the dump
+    pub fn core(&self) -> &CoreDump {
+        &self.core
+    }
+
+    /// Get the slides of the modules loaded in both the trace and the dump
+    fn slides(&self) -> Vec<Slide> {
+        let mut ret: Vec<Slide> = Vec::new();
+        for file in self.core.files.iter().filter(|x| x.offset == 0) {
+            let trace = self.space.mappings()
+                .find(|x| *x.path == *file.path && x.offset == 0);
+            if let Some(trace) = trace {
+                if !ret.iter().any(|x| *x.module == *file.path) {
+                    ret.push(Slide {
+                        module: file.path.clone(),
+                        trace:  trace.base,
+                        core:   file.start,
+                    });
+                }
+            }
+        }
+        ret
+    }
+
+    /// Translate the address `addr` of the trace to the dump, with the
+    /// slides `slides`
+    fn to_core(&self, slides: &[Slide], addr: u64) -> u64 {
+        self.space.module_offset(addr)
+            .and_then(|(path, offset)| {
+                let slide = slides.iter().find(|x| *x.module == *path)?;
+                Some(slide.core.wrapping_add(offset))
+            })
+            .unwrap_or(addr)
+    }
+
+    /// Translate the address `addr` of the dump to the trace, with the
+    /// slides `slides`
+    fn to_trace(&self, slides: &[Slide], addr: u64) -> u64 {
+        self.core.file(addr)
+            .and_then(|file| {
+                let slide = slides.iter().find(|x| *x.module == file.path)?;
+                Some(slide.trace.wrapping_add(file.offset)
+                    .wrapping_add(addr - file.start))
+            })
+            .unwrap_or(addr)
+    }
+
+    /// Observe the execution of `pc` by the thread `tid`
+    fn exec(&mut self, tid: i32, pc: u64) {
+        self.histories.entry(tid).or_insert_with(Histories::payload)
+            .exec(pc);
+    }
+
+    /// Apply an event of the thread `tid` of the process
+    pub fn event(&mut self, tid: i32, event: &Event) {
+        match event {
+            Event::Mmap { base, len, anon, read, write, exec, path,
+                    offset } => {
+                self.space.mmap(*base, *len, *anon, *read, *write, *exec,
+                    path, *offset);
+            }
+            Event::Munmap { base, len } => self.space.munmap(*base, *len),
+            Event::Brk { old, new } => {
+                self.space.heap(&HeapEvent::Brk { old: *old, new: *new });
+            }
+            Event::Arena { base, len } => {
+                self.space.heap(&HeapEvent::Arena { base: *base, len: *len });
+            }
+            Event::Regs { pc, regs } | Event::Branch { pc, regs, .. } => {
+                self.regs.insert(tid, (*pc, regs.clone()));
+                self.pcs.insert(tid, *pc);
+                self.exec(tid, *pc);
+            }
+            Event::Exec { pc } => {
+                self.pcs.insert(tid, *pc);
+                self.exec(tid, *pc);
+            }
+            Event::Write { pc, addr, val, sz } => {
+                self.pcs.insert(tid, *pc);
+                self.values.insert(*addr, (*val, *sz, *pc));
+
+                // Before modules are aligned, regions of the dump are
+                // looked up at the addresses of the trace
+                let Some(idx) = self.core.segments.iter()
+                    .position(|x| (x.base..x.base + x.len).contains(addr))
+                    else { return };
+                *self.writers.entry((idx, *pc)).or_default() += 1;
+                self.last.insert(idx, (*pc, *addr));
+            }
+            Event::WriteAddr { pc, .. } | Event::Read { pc, .. } |
+                    Event::ReadAddr { pc, .. } | Event::Rep { pc, .. } |
+                    Event::Cmp { pc, .. } | Event::RegFile { pc, .. } => {
+                self.pcs.insert(tid, *pc);
+            }
+            Event::Signal { .. } => {}
+        }
+    }
+
+    /// Compare the registers of the crashed thread with the trace
+    fn registers(&self, slides: &[Slide]) -> Option<Registers> {
+        let thread = self.core.crashed()?;
+        let word = if self.core.is_64 { 8 } else { 4 };
+        let words = |x: &[u8]| x.chunks_exact(word).map(|x| {
+            let mut buf = [0u8; 8];
+            if self.core.big_endian {
+                buf[8 - word..].copy_from_slice(x);
+                u64::from_be_bytes(buf)
+            } else {
+                buf[..word].copy_from_slice(x);
+                u64::from_le_bytes(buf)
+            }
+        }).collect::<Vec<_>>();
+        let core = words(&thread.regs);
+
+        let last_pc = self.pcs.get(&thread.tid)
+            .map(|&x| self.to_core(slides, x));
+        let traced = self.regs.get(&thread.tid)
+            .map_or(Vec::new(), |(_, x)| words(x));
+        let history = self.histories.get(&thread.tid)
+            .map_or(Vec::new(), |x| x.blocks(&self.space))
+            .into_iter()
+            .map(|x| Block { pc: self.to_core(slides, x.pc), ..x })
+            .collect();
+        Some(Registers {
+            tid:        thread.tid,
+            signal:     thread.signal,
+            pc_in_core: last_pc.is_some_and(|x| core.contains(&x)),
+            matching:   traced.iter().filter(|x| core.contains(x)).count(),
+            traced:     traced.len(),
+            last_pc, history,
+        })
+    }
+
+    /// Align the dump with the events applied so far
+    pub fn finish(self) -> Alignment {
+        let slides = self.slides();
+        let registers = self.registers(&slides);
+        let same = |x: &CoreSegment| self.space.mappings().any(|y| {
+            y.base == self.to_trace(&slides, x.base) && y.len == x.len &&
+                (y.read, y.write, y.exec) == (x.read, x.write, x.exec)
+        });
+
+        // Writes are moved to the dump's regions once modules are aligned
+        let mut written: HashMap<usize, (u64, Vec<(u64, u64)>)> =
+            HashMap::new();
+        for (&(idx, pc), &count) in &self.writers {
+            let entry = written.entry(idx).or_default();
+            entry.0 += count;
+            entry.1.push((self.to_core(&slides, pc), count));
+        }
+        let mut agreement: HashMap<usize, (u64, u64)> = HashMap::new();
+        for (&addr, &(val, sz, _)) in &self.values {
+            let addr = self.to_core(&slides, addr);
+            let Some(idx) = self.core.segments.iter()
+                .position(|x| (x.base..x.base + x.len).contains(&addr))
+                else { continue };
+            let Some(dumped) = self.core.read(addr, sz as usize) else {
+                continue;
+            };
+            let expected = if self.core.big_endian {
+                val.to_be_bytes()[8 - sz as usize..].to_vec()
+            } else {
+                val.to_le_bytes()[..sz as usize].to_vec()
+            };
+            let entry = agreement.entry(idx).or_default();
+            if dumped == expected {
+                entry.0 += 1;
+            } else {
+                entry.1 += 1;
+            }
+        }
+
+        let mut regions = Vec::new();
+        for (idx, segment) in self.core.segments.iter().enumerate() {
+            let (writes, mut writers) = written.remove(&idx)
+                .unwrap_or_default();
+            if !segment.write && writes == 0 {
+                continue;
+            }
+            writers.sort_by(|x, y| y.1.cmp(&x.1).then(x.0.cmp(&y.0)));
+            writers.truncate(MAX_WRITERS);
+
+            let origin = match self.space.lookup(
+                    self.to_trace(&slides, segment.base)) {
+                None => Origin::Untraced,
+                Some(x) if !x.anon => Origin::File {
+                    path:   x.path.to_string(),
+                    offset: x.offset + (self.to_trace(&slides, segment.base)
+                        - x.base),
+                },
+                Some(x) => match x.heap {
+                    Some(heap) => Origin::Heap { heap },
+                    None => Origin::Anonymous,
+                },
+            };
+            let (agreeing, disagreeing) = agreement.get(&idx).copied()
+                .unwrap_or_default();
+            regions.push(Region {
+                segment:      segment.clone(),
+                same_mapping: same(segment),
+                last_write:   self.last.get(&idx).map(|&(pc, addr)| {
+                    (self.to_core(&slides, pc), self.to_core(&slides, addr))
+                }),
+                origin, writes, writers, agreeing, disagreeing,
+            });
+        }
+
+        let missing = self.space.mappings().filter(|x| {
+            self.core.segment(self.to_core(&slides, x.base)).is_none()
+        }).count();
+        Alignment { slides, registers, regions, missing }
+    }
+}
+
+#[test]
+fn align_core() {
+    // A little endian 64-bit core with one thread and two segments, the
+    // second of them dumped
+    let mut core = vec![0u8; 0x200];
+    core[..6].copy_from_slice(b"\x7fELF\x02\x01");
+    core[16..18].copy_from_slice(&4u16.to_le_bytes());
+    core[32..40].copy_from_slice(&0x40u64.to_le_bytes());
+    core[54..56].copy_from_slice(&56u16.to_le_bytes());
+    core[56..58].copy_from_slice(&3u16.to_le_bytes());
+    let phdr = |core: &mut Vec<u8>, ii: usize, fields: [u64; 6]| {
+        let ph = 0x40 + ii * 56;
+        core[ph..ph + 4].copy_from_slice(&(fields[0] as u32).to_le_bytes());
+        core[ph + 4..ph + 8].copy_from_slice(
+            &(fields[1] as u32).to_le_bytes());
+        for (jj, x) in fields[2..].iter().enumerate() {
+            let at = ph + [8, 16, 32, 40][jj];
+            core[at..at + 8].copy_from_slice(&x.to_le_bytes());
+        }
+    };
+    // type, flags, offset, vaddr, filesz, memsz
+    phdr(&mut core, 0, [4, 0, 0x100, 0, 12 + 8 + 112 + 3 * 8 + 8, 0]);
+    phdr(&mut core, 1, [1, 5, 0, 0x400000, 0, 0x1000]);
+    phdr(&mut core, 2, [1, 6, 0x1c0, 0x600000, 0x10, 0x1000]);
+
+    // NT_PRSTATUS of TID 7 with SIGSEGV, PC 0x400010 and RSP 0x7ff0
+    let note = 0x100;
+    core[note..note + 4].copy_from_slice(&5u32.to_le_bytes());
+    core[note + 4..note + 8].copy_from_slice(&(112u32 + 3 * 8 + 8)
+        .to_le_bytes());
+    core[note + 8..note + 12].copy_from_slice(&NT_PRSTATUS.to_le_bytes());
+    core[note + 12..note + 17].copy_from_slice(b"CORE\0");
+    let desc = note + 20;
+    core[desc + 12..desc + 14].copy_from_slice(&11u16.to_le_bytes());
+    core[desc + 32..desc + 36].copy_from_slice(&7u32.to_le_bytes());
+    core[desc + 112..desc + 120].copy_from_slice(&0x400010u64.to_le_bytes());
+    core[desc + 120..desc + 128].copy_from_slice(&0x7ff0u64.to_le_bytes());
+
+    // The data segment holds 0x41 at 0x600000 and 0x00 at 0x600008
+    core[0x1c0] = 0x41;
+
+    let core = CoreDump::parse(core).unwrap();
+    assert_eq!(core.segments.len(), 2);
+    assert_eq!(core.crashed().map(|x| (x.tid, x.signal)), Some((7, 11)));
+    assert_eq!(core.read(0x600000, 1), Some(&[0x41][..]));
+    assert_eq!(core.read(0x600010, 1), None);
+
+    let mut aligner = Aligner::new(core);
+    aligner.event(7, &Event::Mmap {
+        base: 0x600000, len: 0x1000, anon: true, read: true, write: true,
+        exec: false, path: String::new(), offset: 0,
+    });
+    aligner.event(7, &Event::Write { pc: 0x400004, addr: 0x600000,
+        val: 0x41, sz: 1 });
+    aligner.event(7, &Event::Write { pc: 0x400008, addr: 0x600008,
+        val: 0x42, sz: 1 });
+    aligner.event(7, &Event::Exec { pc: 0x400100 });
+    aligner.event(7, &Event::Regs { pc: 0x400010,
+        regs: [0x7ff0u64, 0x1234].iter().flat_map(|x| x.to_le_bytes())
+            .collect() });
+
+    let alignment = aligner.finish();
+    assert!(alignment.slides.is_empty());
+    assert_eq!(alignment.registers, Some(Registers {
+        tid: 7, signal: 11, last_pc: Some(0x400010), pc_in_core: true,
+        matching: 1, traced: 2,
+        history: vec![
+            Block { pc: 0x400100, module: None, offset: 0x400100 },
+            Block { pc: 0x400010, module: None, offset: 0x400010 },
+        ],
+    }));
+
+    // Only the data segment is interesting, one of its values was changed
+    // since it was written
+    let [region] = &alignment.regions[..] else { panic!() };
+    assert_eq!((region.origin.clone(), region.same_mapping),
+        (Origin::Anonymous, true));
+    assert_eq!((region.writes, region.agreeing, region.disagreeing),
+        (2, 1, 1));
+    assert_eq!(region.last_write, Some((0x400008, 0x600008)));
+    assert_eq!(alignment.missing, 0);
+}
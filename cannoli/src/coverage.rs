@@ -0,0 +1,337 @@
+//! Basic block coverage in the drcov format
+//!
+//! drcov is the coverage format of DynamoRIO's drcov tool, which Lighthouse
+//! and most coverage explorers for disassemblers read. A drcov file holds a
+//! table of the modules of a process and a table of the basic blocks
+//! executed in them, as offsets from the module base.
+//!
+//! [`Drcov`] builds both from the events of one process: the module table
+//! from the file mappings it saw being created (see
+//! [`crate::address_space`]), the blocks from the executed PCs. The jitter
+//! doesn't report basic blocks, so a block is a run of instructions executed
+//! one after another, which is what a drcov consumer needs to mark them.
+//! Code outside of files (eg. JIT-ed code) has no module and is dropped.
+//!
+//! [`DrcovCollector`] is a [`Cannoli`] implementation writing a drcov file
+//! for every traced process, to the directory set with [`configure`]:
+//!
+//! ```no_run
+//! use cannoli::create_cannoli;
+//! use cannoli::coverage::{self, DrcovCollector};
+//!
+//! coverage::configure("coverage".into()).unwrap();
+//! create_cannoli::<DrcovCollector>(4).unwrap();
+//! ```
+//!
+//! Coverage only needs every instruction once, so hook instructions with
+//! [`crate::control::HookKind::Once`], or with
+//! [`crate::control::HookKind::Edge`] to only get the block starts.
+
+use std::io::Write;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::path::{Path, PathBuf};
+use std::collections::{BTreeMap, HashMap};
+use crate::{Cannoli, ClientInfo};
+use crate::address_space::AddressSpace;
+use crate::event::Event;
+
+/// Maximum distance between two PCs for them to be in the same block. This
+/// is the largest instruction size we expect to see
+const MAX_INSN_LEN: u64 = 16;
+
+/// A module in the module table
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Module {
+    /// Path of the file
+    path: Arc<str>,
+
+    /// Address the start of the file is loaded at
+    base: u64,
+
+    /// Address one past the end of the last mapping of the file
+    end: u64,
+}
+
+/// Basic block coverage of a process, see the module documentation
+#[derive(Default)]
+pub struct Drcov {
+    /// Mappings of the process
+    space: AddressSpace,
+
+    /// Modules which had code executed, in order of their IDs
+    modules: Vec<Module>,
+
+    /// IDs of the modules, keyed by path and base, so a file loaded again
+    /// at another address gets another ID
+    ids: HashMap<(Arc<str>, u64), u16>,
+
+    /// Size of the executed blocks, keyed by module ID and offset
+    blocks: BTreeMap<(u16, u32), u16>,
+
+    /// Number of blocks which were dropped as they're outside of a module
+    dropped: u64,
+}
+
+impl Drcov {
+    /// Create empty coverage
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Apply an event to the coverage, [`Event::Mmap`] and
+    /// [`Event::Munmap`] to track the modules, [`Event::Exec`],
+    /// [`Event::Regs`] and [`Event::Branch`] as single instructions
+    pub fn event(&mut self, event: &Event) {
+        match event {
+            Event::Mmap { base, len, anon, read, write, exec, path,
+                    offset } => {
+                self.space.mmap(*base, *len, *anon, *read, *write, *exec,
+                    path, *offset);
+            }
+            Event::Munmap { base, len } => self.space.munmap(*base, *len),
+            Event::Exec { pc } | Event::Regs { pc, .. } |
+                Event::Branch { pc, .. } => self.block(*pc, 1),
+            _ => {}
+        }
+    }
+
+    /// Add the block of `size` bytes at `pc`
+    pub fn block(&mut self, pc: u64, size: u64) {
+        let Some((path, offset)) = self.space.module_offset(pc) else {
+            self.dropped += 1;
+            return;
+        };
+        let Ok(offset) = u32::try_from(offset) else {
+            self.dropped += 1;
+            return;
+        };
+
+        // Modules only end up in the table once code in them executed
+        let base = pc - offset as u64;
+        let end = self.space.mappings().filter(|x| x.path == path)
+            .map(|x| x.end()).max().unwrap_or(pc + size);
+        let id = match self.ids.get(&(path.clone(), base)) {
+            Some(&id) => id,
+            None => {
+                let Ok(id) = u16::try_from(self.modules.len()) else {
+                    self.dropped += 1;
+                    return;
+                };
+                self.modules.push(Module { path: path.clone(), base, end });
+                self.ids.insert((path, base), id);
+                id
+            }
+        };
+        let module = &mut self.modules[id as usize];
+        module.end = module.end.max(end);
+
+        let size = size.min(u16::MAX as u64) as u16;
+        let entry = self.blocks.entry((id, offset)).or_default();
+        *entry = (*entry).max(size);
+    }
+
+    /// Get the number of blocks
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// Check if no block was executed
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    /// Get the number of blocks which were dropped as they're outside of a
+    /// module
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Write the coverage in the drcov format (version 2)
+    pub fn write_to(&self, mut w: impl Write) -> std::io::Result<()> {
+        writeln!(w, "DRCOV VERSION: 2")?;
+        writeln!(w, "DRCOV FLAVOR: drcov")?;
+        writeln!(w, "Module Table: version 2, count {}", self.modules.len())?;
+        writeln!(w, "Columns: id, base, end, entry, checksum, timestamp, \
+            path")?;
+        for (id, module) in self.modules.iter().enumerate() {
+            writeln!(w, "{id:3}, {:#018x}, {:#018x}, {:#018x}, {:#010x}, \
+                {:#010x}, {}", module.base, module.end, 0, 0, 0,
+                module.path)?;
+        }
+
+        // struct { u32 start; u16 size; u16 mod_id; }, little endian
+        writeln!(w, "BB Table: {} bbs", self.blocks.len())?;
+        for (&(id, offset), &size) in &self.blocks {
+            w.write_all(&offset.to_le_bytes())?;
+            w.write_all(&size.to_le_bytes())?;
+            w.write_all(&id.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Write the coverage to a drcov file at `path`
+    pub fn save(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        self.write_to(&mut file)?;
+        file.flush()
+    }
+}
+
+/// Directory [`DrcovCollector`] writes to, set with [`configure`]
+static DIRECTORY: OnceLock<PathBuf> = OnceLock::new();
+
+/// Set the directory [`DrcovCollector`] writes its drcov files to. This must
+/// be called before [`crate::create_cannoli`], and can only be called once
+pub fn configure(dir: PathBuf) -> Result<(), PathBuf> {
+    DIRECTORY.set(dir)
+}
+
+/// Coverage of a process, written to its drcov file once the last thread of
+/// the process is gone
+pub struct DrcovProcess {
+    /// The coverage
+    drcov: Mutex<Drcov>,
+
+    /// Path of the drcov file
+    path: PathBuf,
+}
+
+impl Drop for DrcovProcess {
+    fn drop(&mut self) {
+        let drcov = self.drcov.get_mut().unwrap_or_else(|x| x.into_inner());
+        match drcov.save(&self.path) {
+            Ok(()) => eprintln!("wrote {} blocks to {} ({} outside of \
+                modules)", drcov.len(), self.path.display(),
+                drcov.dropped()),
+            Err(err) => eprintln!("failed to write {}: {err}",
+                self.path.display()),
+        }
+    }
+}
+
+/// A [`Cannoli`] implementation writing a drcov file for every process to
+/// the directory set with [`configure`], named
+/// `drcov.<comm>.<pid>.proc.log` like DynamoRIO does
+pub struct DrcovCollector {
+    /// Block being executed by this thread, as the PC of its first and last
+    /// instruction
+    block: Option<(u64, u64)>,
+}
+
+impl DrcovCollector {
+    /// Add the current block to the coverage of the process
+    fn finish_block(&mut self, drcov: &mut Drcov) {
+        if let Some((start, last)) = self.block.take() {
+            drcov.block(start, last - start + 1);
+        }
+    }
+}
+
+impl Cannoli for DrcovCollector {
+    type Trace = Event;
+
+    type PidContext = DrcovProcess;
+    type TidContext = ();
+
+    fn init_pid(ci: &ClientInfo) -> Arc<Self::PidContext> {
+        // Tenants of a daemon get their own directory, see
+        // `crate::tenants`
+        let dir = match &ci.tenant {
+            Some(tenant) => {
+                let _ = std::fs::create_dir_all(&tenant.output);
+                tenant.output.as_path()
+            }
+            None => DIRECTORY.get().map_or(Path::new("."), |x| x.as_path()),
+        };
+        let comm = ci.comm.as_deref().map_or("unknown", |x| x.trim());
+        Arc::new(DrcovProcess {
+            drcov: Mutex::new(Drcov::new()),
+            path:  dir.join(format!("drcov.{comm}.{}.proc.log", ci.pid)),
+        })
+    }
+
+    fn init_tid(_pid: &Self::PidContext,
+            _ci: &ClientInfo) -> (Self, Self::TidContext) {
+        (Self { block: None }, ())
+    }
+
+    fn exec(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            pc: u64, trace: &mut Vec<Self::Trace>) {
+        trace.push(Event::Exec { pc });
+    }
+
+    fn mmap(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            base: u64, len: u64, anon: bool, read: bool, write: bool,
+            exec: bool, path: &str, offset: u64,
+            trace: &mut Vec<Self::Trace>) {
+        trace.push(Event::Mmap {
+            path: path.to_string(),
+            base, len, anon, read, write, exec, offset,
+        });
+    }
+
+    fn munmap(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            base: u64, len: u64, trace: &mut Vec<Self::Trace>) {
+        trace.push(Event::Munmap { base, len });
+    }
+
+    fn trace(&mut self, pid: &Self::PidContext,
+            _tid: &Self::TidContext, trace: &[Self::Trace]) {
+        let mut drcov = pid.drcov.lock().unwrap();
+        for event in trace {
+            let Event::Exec { pc } = *event else {
+                // Mappings may change what the block is part of
+                self.finish_block(&mut drcov);
+                drcov.event(event);
+                continue;
+            };
+
+            match &mut self.block {
+                Some((_, last)) if pc > *last && pc - *last <= MAX_INSN_LEN =>
+                    *last = pc,
+                _ => {
+                    self.finish_block(&mut drcov);
+                    self.block = Some((pc, pc));
+                }
+            }
+        }
+
+        // Other threads may be done before the next trace
+        self.finish_block(&mut drcov);
+    }
+
+    fn edge(&mut self, pid: &Self::PidContext,
+            _tid: &Self::TidContext, _pc: u64, target: u64, _taken: bool) {
+        pid.drcov.lock().unwrap().block(target, 1);
+    }
+}
+
+#[test]
+fn drcov_format() {
+    let mut drcov = Drcov::new();
+    drcov.event(&Event::Mmap {
+        base: 0x400000, len: 0x1000, anon: false, read: true, write: false,
+        exec: false, path: "/bin/t".into(), offset: 0,
+    });
+    drcov.event(&Event::Mmap {
+        base: 0x401000, len: 0x1000, anon: false, read: true, write: false,
+        exec: true, path: "/bin/t".into(), offset: 0x1000,
+    });
+    drcov.block(0x401010, 8);
+    drcov.event(&Event::Exec { pc: 0x401010 });
+    drcov.event(&Event::Exec { pc: 0x401000 });
+    drcov.event(&Event::Exec { pc: 0x7000 });
+    assert_eq!(drcov.len(), 2);
+    assert_eq!(drcov.dropped(), 1);
+
+    let mut out = Vec::new();
+    drcov.write_to(&mut out).unwrap();
+    let header = "DRCOV VERSION: 2\nDRCOV FLAVOR: drcov\n\
+        Module Table: version 2, count 1\n\
+        Columns: id, base, end, entry, checksum, timestamp, path\n  \
+        0, 0x0000000000400000, 0x0000000000402000, 0x0000000000000000, \
+        0x00000000, 0x00000000, /bin/t\nBB Table: 2 bbs\n";
+    assert_eq!(&out[..header.len()], header.as_bytes());
+    assert_eq!(&out[header.len()..],
+        [0, 0x10, 0, 0, 1, 0, 0, 0, 0x10, 0x10, 0, 0, 8, 0, 0, 0]);
+}
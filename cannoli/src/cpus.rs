@@ -0,0 +1,83 @@
+//! Per-vCPU attribution of the traces of user-mode targets
+//!
+//! QEMU's user-mode emulation runs every guest thread on a vCPU of its own,
+//! a `CPUState` with a `cpu_index`, and the jitter streams a trace per
+//! thread, so the TID on the wire is the vCPU. What's missing to analyze an
+//! SMP target per core is the index QEMU gave the vCPU, and a place to keep
+//! per-core state which outlives the threads running on the core.
+//!
+//! The jitter reads the index of the vCPU of its thread from QEMU, which
+//! exports it from the Cannoli patches as `cannoli_cpu_index()`, and sends it
+//! when it connects as [`crate::ClientInfo::cpu`]. The [`crate::sinks::jsonl`]
+//! sink writes it with every event. [`CpuContexts`] holds a context per vCPU
+//! index, the per-core counterpart of [`crate::Cannoli::TidContext`]: keep
+//! one in the `PidContext` and pick the context of a thread in
+//! [`crate::Cannoli::init_tid`]:
+//!
+//! ```
+//! use std::sync::Arc;
+//! use std::sync::atomic::AtomicU64;
+//! use cannoli::cpus::CpuContexts;
+//!
+//! // Blocks executed on every vCPU of a process
+//! let blocks: CpuContexts<AtomicU64> = CpuContexts::new();
+//! let core: Arc<AtomicU64> = blocks.get_or_init(0, |_| AtomicU64::new(0));
+//! # assert!(Arc::ptr_eq(&core, &blocks.get(0).unwrap()));
+//! ```
+//!
+//! QEMU gives a new vCPU the index one past the highest one in use, so the
+//! index of a thread which exited may be given to a later one, and a forked
+//! child keeps the vCPU of the thread which forked. User mode has no
+//! clusters or topology, every vCPU is a peer of the others.
+
+use std::sync::{Arc, Mutex};
+use std::collections::BTreeMap;
+
+/// Contexts of the vCPUs of a process, keyed by vCPU index, see the module
+/// documentation
+#[derive(Debug)]
+pub struct CpuContexts<T> {
+    /// The contexts
+    contexts: Mutex<BTreeMap<u32, Arc<T>>>,
+}
+
+impl<T> Default for CpuContexts<T> {
+    fn default() -> Self {
+        Self { contexts: Mutex::new(BTreeMap::new()) }
+    }
+}
+
+impl<T> CpuContexts<T> {
+    /// Create the contexts of a process without any vCPUs
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the context of the vCPU `cpu`, created with `init` if it has none
+    pub fn get_or_init(&self, cpu: u32, init: impl FnOnce(u32) -> T)
+            -> Arc<T> {
+        self.contexts.lock().unwrap().entry(cpu)
+            .or_insert_with(|| Arc::new(init(cpu))).clone()
+    }
+
+    /// Get the context of the vCPU `cpu`, `None` if it has none
+    pub fn get(&self, cpu: u32) -> Option<Arc<T>> {
+        self.contexts.lock().unwrap().get(&cpu).cloned()
+    }
+
+    /// Get the contexts of every vCPU which has one, by index
+    pub fn all(&self) -> Vec<(u32, Arc<T>)> {
+        self.contexts.lock().unwrap().iter()
+            .map(|(cpu, x)| (*cpu, x.clone())).collect()
+    }
+}
+
+#[test]
+fn cpu_contexts() {
+    let contexts = CpuContexts::new();
+    contexts.get_or_init(1, |cpu| cpu * 10);
+    assert_eq!(*contexts.get_or_init(1, |_| 0), 10);
+    contexts.get_or_init(0, |cpu| cpu * 10);
+    assert_eq!(contexts.all().iter().map(|x| *x.1).collect::<Vec<_>>(),
+        [0, 10]);
+}
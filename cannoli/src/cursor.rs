@@ -0,0 +1,407 @@
+//! Time-travel navigation over the trace of a thread in a capture
+//!
+//! A [`TraceCursor`] points at one event of one thread of a capture, and
+//! moves backwards as easily as forwards. These are the primitives a
+//! reverse-debugging front end needs:
+//!
+//! - [`TraceCursor::step_back`] and [`TraceCursor::step`] move by one event
+//! - [`TraceCursor::run_back_to_write`] goes back to the last store to an
+//!   address, to answer "where did this value come from"
+//! - [`TraceCursor::prev_call`] goes back to the call which entered the
+//!   current function, like a reverse `finish`
+//!
+//! Events are numbered per thread and segment, the same way
+//! [`crate::grep::Match::ordinal`] numbers them. Records are found with the
+//! capture's [`Index`], which also lets searches skip the records which
+//! can't have a matching store without decoding them.
+//!
+//! Calls are inferred from the order PCs execute in, the same way
+//! [`crate::analysis::functions`] does it: a transfer of control is a call
+//! until control comes back to right after where it came from. A jump which
+//! never comes back counts as a call too, so `prev_call` may stop at a jump
+//! out of a loop. The call stack is replayed from the start of the thread,
+//! with a snapshot kept every [`SNAPSHOT_INTERVAL`] records, so going back
+//! to a call only replays a few records however long the trace is.
+
+use std::path::Path;
+use std::collections::BTreeMap;
+use crate::capture::{CaptureReader, Record};
+use crate::event::Event;
+use crate::grep::{Index, Kind};
+
+/// Number of records of the thread between snapshots of the call stack
+pub const SNAPSHOT_INTERVAL: usize = 16;
+
+/// Maximum distance between two PCs for them to be considered sequential
+/// execution rather than a transfer of control
+const MAX_INSN_LEN: u64 = 16;
+
+/// Maximum distance between a call and the address its return lands on
+const MAX_RETURN_GAP: u64 = 16;
+
+/// Maximum number of frames of a call stack, jumps which never return look
+/// like calls, so this bounds the memory used
+const MAX_DEPTH: usize = 4096;
+
+/// An events record of the thread
+#[derive(Clone, Copy, Debug)]
+struct ThreadRecord {
+    /// Offset of the record in the capture
+    offset: u64,
+
+    /// Number of the first event of the record
+    first: u64,
+
+    /// Number of events in the record
+    events: u64,
+
+    /// Mask of the kinds of the events, see [`Kind::bit`]
+    kinds: u8,
+
+    /// Lowest address and end of the highest address range accessed
+    addrs: (u64, u64),
+}
+
+/// A tentative call frame
+#[derive(Clone, Copy, Debug)]
+struct Frame {
+    /// Number of the event of the call instruction, `None` for the first
+    /// frame of the thread
+    call: Option<u64>,
+
+    /// PC of the call instruction
+    call_site: u64,
+
+    /// Lowest PC executed in the frame
+    lo: u64,
+
+    /// Highest PC executed in the frame
+    hi: u64,
+}
+
+/// Call stack of the thread at some point of the trace
+#[derive(Clone, Debug, Default)]
+struct CallStack {
+    /// Number and PC of the last instruction executed
+    prev: Option<(u64, u64)>,
+
+    /// Tentative frames, the top of the stack is the current one
+    frames: Vec<Frame>,
+}
+
+impl CallStack {
+    /// Observe the instruction at `pc`, which is event number `ordinal`
+    fn observe(&mut self, ordinal: u64, pc: u64) {
+        let Some((prev_ordinal, prev)) = self.prev.replace((ordinal, pc))
+        else {
+            self.frames.push(Frame {
+                call: None, call_site: 0, lo: pc, hi: pc });
+            return;
+        };
+
+        let sequential = pc.wrapping_sub(prev).wrapping_sub(1) < MAX_INSN_LEN;
+        if !sequential {
+            let returned = self.frames.iter().rposition(|frame| {
+                frame.call.is_some() &&
+                    pc.wrapping_sub(frame.call_site).wrapping_sub(1) <
+                        MAX_RETURN_GAP
+            });
+
+            if let Some(idx) = returned {
+                self.frames.truncate(idx);
+            } else if !self.frames.last().is_some_and(|top| {
+                (top.lo..=top.hi).contains(&pc)
+            }) {
+                if self.frames.len() >= MAX_DEPTH {
+                    let top = self.frames.pop().unwrap();
+                    let below = self.frames.last_mut().unwrap();
+                    below.lo = below.lo.min(top.lo);
+                    below.hi = below.hi.max(top.hi);
+                }
+                self.frames.push(Frame {
+                    call:      Some(prev_ordinal),
+                    call_site: prev,
+                    lo:        pc,
+                    hi:        pc,
+                });
+            }
+        }
+
+        if let Some(top) = self.frames.last_mut() {
+            top.lo = top.lo.min(pc);
+            top.hi = top.hi.max(pc);
+        }
+    }
+}
+
+/// Get the PC of the instruction `event` reports executing, if it does
+fn executed_pc(event: &Event) -> Option<u64> {
+    match *event {
+        Event::Exec { pc } | Event::Regs { pc, .. } |
+        Event::Branch { pc, .. } => Some(pc),
+        _ => None,
+    }
+}
+
+/// Check if `event` stores to `addr`
+fn writes(event: &Event, addr: u64) -> bool {
+    let covers = |start: u64, len: u64| {
+        addr.wrapping_sub(start) < len
+    };
+    match event {
+        Event::Write { addr: start, sz, .. } |
+        Event::WriteAddr { addr: start, sz, .. } => covers(*start, *sz as u64),
+        Event::Rep { count, backward, accesses, .. } => {
+            accesses.iter().filter(|x| x.write).any(|x| {
+                let len  = (x.sz as u64).saturating_mul(*count);
+                let last = len.saturating_sub(x.sz as u64);
+                let start = if *backward {
+                    x.addr.saturating_sub(last)
+                } else {
+                    x.addr
+                };
+                covers(start, len)
+            })
+        }
+        _ => false,
+    }
+}
+
+/// A position in the trace of a thread, see the module documentation
+pub struct TraceCursor {
+    /// The capture
+    reader: CaptureReader,
+
+    /// Events records of the thread, in trace order
+    records: Vec<ThreadRecord>,
+
+    /// Number of events of the thread
+    len: u64,
+
+    /// Number of the event the cursor is at
+    position: u64,
+
+    /// Index and events of the record last read
+    loaded: Option<(usize, Vec<Event>)>,
+
+    /// Call stacks at the start of every [`SNAPSHOT_INTERVAL`]th record
+    snapshots: BTreeMap<usize, CallStack>,
+}
+
+impl TraceCursor {
+    /// Open a cursor on the thread `tid` in segment `segment` of the capture
+    /// at `capture`, at the first event of the thread
+    pub fn open(capture: impl AsRef<Path>, segment: u32, tid: i32)
+            -> std::io::Result<Self> {
+        let index = Index::open(&capture)?;
+        let mut records = Vec::new();
+        let mut len = 0;
+        for x in index.summaries() {
+            if x.segment != segment || x.tid != tid || x.events == 0 {
+                continue;
+            }
+            records.push(ThreadRecord {
+                offset: x.offset,
+                first:  len,
+                events: x.events as u64,
+                kinds:  x.kinds,
+                addrs:  x.addrs,
+            });
+            len += x.events as u64;
+        }
+        if records.is_empty() {
+            return Err(std::io::Error::new(std::io::ErrorKind::NotFound,
+                format!("No events of thread {tid} in segment {segment}")));
+        }
+
+        Ok(Self {
+            reader:    CaptureReader::open(capture)?,
+            snapshots: BTreeMap::from([(0, CallStack::default())]),
+            loaded:    None,
+            position:  0,
+            records, len,
+        })
+    }
+
+    /// Get the number of events of the thread
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Check if the thread has no events, which a cursor never has
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Get the number of the event the cursor is at
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Find the record holding event number `ordinal`
+    fn record_of(&self, ordinal: u64) -> usize {
+        self.records.partition_point(|x| x.first <= ordinal) - 1
+    }
+
+    /// Get the events of record `idx`
+    fn load(&mut self, idx: usize) -> std::io::Result<&[Event]> {
+        if self.loaded.as_ref().is_none_or(|x| x.0 != idx) {
+            self.reader.seek(self.records[idx].offset)?;
+            let Some(Record::Events { events, .. }) =
+                    self.reader.next_record()? else {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "Index doesn't match the capture"));
+            };
+            self.loaded = Some((idx, events));
+        }
+        Ok(&self.loaded.as_ref().unwrap().1)
+    }
+
+    /// Get the event the cursor is at
+    pub fn event(&mut self) -> std::io::Result<&Event> {
+        let idx   = self.record_of(self.position);
+        let first = self.records[idx].first;
+        let at    = (self.position - first) as usize;
+        self.load(idx)?.get(at).ok_or_else(|| std::io::Error::new(
+            std::io::ErrorKind::InvalidData, "Index doesn't match the capture"))
+    }
+
+    /// Move to event number `ordinal`, which must be below [`Self::len`]
+    pub fn seek(&mut self, ordinal: u64) {
+        assert!(ordinal < self.len, "Event {ordinal} is past the trace");
+        self.position = ordinal;
+    }
+
+    /// Move to the next event. Returns `false` at the last event
+    pub fn step(&mut self) -> bool {
+        if self.position + 1 >= self.len {
+            return false;
+        }
+        self.position += 1;
+        true
+    }
+
+    /// Move to the previous event. Returns `false` at the first event
+    pub fn step_back(&mut self) -> bool {
+        if self.position == 0 {
+            return false;
+        }
+        self.position -= 1;
+        true
+    }
+
+    /// Move back to the last event before the cursor which stored to
+    /// `addr`. Returns `false`, and stays, if there is none
+    pub fn run_back_to_write(&mut self, addr: u64) -> std::io::Result<bool> {
+        let mut idx = self.record_of(self.position);
+        loop {
+            let record = self.records[idx];
+            if record.kinds & Kind::Write.bit() != 0 &&
+                    (record.addrs.0..record.addrs.1).contains(&addr) {
+                let end = self.position.min(record.first + record.events)
+                    - record.first;
+                let found = self.load(idx)?[..end as usize].iter()
+                    .rposition(|x| writes(x, addr));
+                if let Some(at) = found {
+                    self.position = record.first + at as u64;
+                    return Ok(true);
+                }
+            }
+
+            let Some(prev) = idx.checked_sub(1) else { return Ok(false) };
+            idx = prev;
+        }
+    }
+
+    /// Get the call stack at the start of record `idx`
+    fn stack_at(&mut self, idx: usize) -> std::io::Result<CallStack> {
+        let (&start, stack) = self.snapshots.range(..=idx).next_back()
+            .unwrap();
+        let mut stack = stack.clone();
+        for ii in start..idx {
+            if ii % SNAPSHOT_INTERVAL == 0 {
+                self.snapshots.entry(ii).or_insert_with(|| stack.clone());
+            }
+            let first = self.records[ii].first;
+            for (at, event) in self.load(ii)?.iter().enumerate() {
+                if let Some(pc) = executed_pc(event) {
+                    stack.observe(first + at as u64, pc);
+                }
+            }
+        }
+        if idx.is_multiple_of(SNAPSHOT_INTERVAL) {
+            self.snapshots.entry(idx).or_insert_with(|| stack.clone());
+        }
+        Ok(stack)
+    }
+
+    /// Move back to the call instruction which entered the function the
+    /// cursor is in. Returns `false`, and stays, if the cursor is in the
+    /// first frame of the thread
+    pub fn prev_call(&mut self) -> std::io::Result<bool> {
+        let idx = self.record_of(self.position);
+        let mut stack = self.stack_at(idx)?;
+        let first = self.records[idx].first;
+        let end   = (self.position - first) as usize;
+        for (at, event) in self.load(idx)?[..=end].iter().enumerate() {
+            if let Some(pc) = executed_pc(event) {
+                stack.observe(first + at as u64, pc);
+            }
+        }
+
+        match stack.frames.last().and_then(|x| x.call) {
+            Some(call) => {
+                self.position = call;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+#[test]
+fn navigate_trace() {
+    use crate::capture::CaptureWriter;
+
+    let dir  = std::env::temp_dir();
+    let path = dir.join(format!("cannoli_cursor_{}", std::process::id()));
+    let exec = |pc| Event::Exec { pc };
+    let store = |pc, addr, val| Event::Write { pc, addr, val, sz: 4 };
+
+    // `main` stores to 0x8000, calls 0x2000 which loops and stores again
+    let mut writer = CaptureWriter::create(&path, None).unwrap();
+    writer.write_events(1, 1, &[exec(0x1000), store(0x1000, 0x8000, 1),
+        exec(0x1004), exec(0x2000)]).unwrap();
+    writer.write_events(1, 2, &[exec(0x5000)]).unwrap();
+    writer.write_events(1, 1, &[exec(0x2004), exec(0x2000), exec(0x2004),
+        store(0x2004, 0x8002, 2), exec(0x2008), exec(0x1008)]).unwrap();
+    drop(writer);
+
+    let mut cursor = TraceCursor::open(&path, 0, 1).unwrap();
+    assert_eq!(cursor.len(), 10);
+    cursor.seek(8);
+    assert_eq!(cursor.event().unwrap(), &exec(0x2008));
+
+    // Back to the call, then further back finds nothing
+    assert!(cursor.prev_call().unwrap());
+    assert_eq!(cursor.position(), 2);
+    assert!(!cursor.prev_call().unwrap());
+
+    // Stores are found across records
+    cursor.seek(9);
+    assert!(cursor.run_back_to_write(0x8003).unwrap());
+    assert_eq!(cursor.position(), 7);
+    assert!(cursor.run_back_to_write(0x8000).unwrap());
+    assert_eq!(cursor.position(), 1);
+    assert!(!cursor.run_back_to_write(0x8000).unwrap());
+    assert!(cursor.step_back() && !cursor.step_back());
+    assert_eq!(cursor.event().unwrap(), &exec(0x1000));
+
+    // Back in main after the return
+    cursor.seek(9);
+    assert!(!cursor.prev_call().unwrap());
+
+    std::fs::remove_file(&path).unwrap();
+    let _ = std::fs::remove_file(Index::path_for(&path));
+}
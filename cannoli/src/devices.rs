@@ -0,0 +1,202 @@
+//! Classification of memory accesses as device (MMIO) accesses
+//!
+//! Driver and firmware analyses care about the interaction with devices
+//! rather than RAM traffic. A [`DeviceMap`] holds the device memory map of
+//! the machine, and turns the memory events which hit a device into
+//! [`MmioAccess`]es, `mmio_read(device, offset, val)` and
+//! `mmio_write(device, offset, val)`, with the offset relative to the
+//! device's registers.
+//!
+//! Cannoli only traces QEMU's user-mode emulation, so there is no machine
+//! description to take the memory map from. Devices are configured in TOML:
+//!
+//! ```toml
+//! [[device]]
+//! name = "uart0"
+//! base = 0x09000000
+//! len  = 0x1000
+//! ```
+//!
+//! In addition, user-mode drivers reach device registers by mapping device
+//! files (eg. `/dev/uio0` or `/dev/mem`), accesses to mappings of files in
+//! `/dev` are classified as accesses to a device named after the file, with
+//! the offset into the file.
+
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+use std::collections::BTreeMap;
+use serde::{Serialize, Deserialize};
+use crate::address_space::AddressSpace;
+use crate::event::Event;
+
+/// A device in the memory map
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Device {
+    /// Name of the device
+    pub name: String,
+
+    /// Base address of the device's registers
+    pub base: u64,
+
+    /// Size of the device's registers in bytes
+    pub len: u64,
+}
+
+/// Contents of a device map file
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DeviceFile {
+    /// The devices
+    #[serde(default)]
+    device: Vec<Device>,
+}
+
+/// An access to a device
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MmioAccess {
+    /// Name of the device
+    pub device: Arc<str>,
+
+    /// Offset of the access from the base of the device
+    pub offset: u64,
+
+    /// PC of the instruction which made the access
+    pub pc: u64,
+
+    /// Value read or written, `None` if it wasn't logged
+    pub val: Option<u64>,
+
+    /// Size of the access in bytes
+    pub sz: u8,
+
+    /// Set for writes, reads otherwise
+    pub write: bool,
+}
+
+impl fmt::Display for MmioAccess {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = if self.write { "mmio_write" } else { "mmio_read" };
+        write!(f, "{kind}({}, {:#x}", self.device, self.offset)?;
+        match self.val {
+            Some(val) => write!(f, ", {val:#x})"),
+            None      => write!(f, ", ?)"),
+        }
+    }
+}
+
+/// The device memory map of a machine, see the module documentation
+#[derive(Clone, Debug, Default)]
+pub struct DeviceMap {
+    /// Devices, keyed by base address
+    devices: BTreeMap<u64, Device>,
+}
+
+impl DeviceMap {
+    /// Create an empty map, only mappings of device files are classified
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse a device map from TOML
+    pub fn parse(text: &str) -> std::io::Result<Self> {
+        let file: DeviceFile = toml::from_str(text).map_err(|x| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, x)
+        })?;
+
+        let mut ret = Self::new();
+        for device in file.device {
+            ret.add(device)?;
+        }
+        Ok(ret)
+    }
+
+    /// Load the device map file at `path`
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// Add `device` to the map, it may not overlap any other device
+    pub fn add(&mut self, device: Device) -> std::io::Result<()> {
+        let end = device.base.saturating_add(device.len);
+        let overlap = self.devices.range(..end).next_back()
+            .filter(|(_, x)| x.base.saturating_add(x.len) > device.base);
+        if let Some((_, other)) = overlap {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput,
+                format!("Device {} overlaps {}", device.name, other.name)));
+        }
+
+        self.devices.insert(device.base, device);
+        Ok(())
+    }
+
+    /// Get all devices, sorted by base address
+    pub fn devices(&self) -> impl Iterator<Item = &Device> {
+        self.devices.values()
+    }
+
+    /// Find the device `addr` is in, and the offset of `addr` from its base.
+    /// `space` is used to find mappings of device files, if given
+    pub fn lookup(&self, space: Option<&AddressSpace>, addr: u64)
+            -> Option<(Arc<str>, u64)> {
+        let device = self.devices.range(..=addr).next_back()
+            .filter(|(_, x)| addr - x.base < x.len);
+        if let Some((_, device)) = device {
+            return Some((device.name.as_str().into(), addr - device.base));
+        }
+
+        let mapping = space?.lookup(addr)
+            .filter(|x| !x.anon && x.path.starts_with("/dev/"))?;
+        let name = mapping.path.rsplit('/').next().unwrap_or(&mapping.path);
+        Some((name.into(), addr - mapping.base + mapping.offset))
+    }
+
+    /// Classify a memory event, `None` if it's not a load or store, or
+    /// doesn't access a device
+    pub fn access(&self, space: Option<&AddressSpace>, event: &Event)
+            -> Option<MmioAccess> {
+        let (pc, addr, val, sz, write) = match *event {
+            Event::Read      { pc, addr, val, sz } =>
+                (pc, addr, Some(val), sz, false),
+            Event::Write     { pc, addr, val, sz } =>
+                (pc, addr, Some(val), sz, true),
+            Event::ReadAddr  { pc, addr, sz } => (pc, addr, None, sz, false),
+            Event::WriteAddr { pc, addr, sz } => (pc, addr, None, sz, true),
+            _ => return None,
+        };
+
+        let (device, offset) = self.lookup(space, addr)?;
+        Some(MmioAccess { device, offset, pc, val, sz, write })
+    }
+}
+
+#[test]
+fn classify_mmio() {
+    let map = DeviceMap::parse("
+        [[device]]
+        name = \"uart0\"
+        base = 0x09000000
+        len  = 0x1000
+    ").unwrap();
+    assert!(map.clone().add(Device {
+        name: "gpio".into(),
+        base: 0x0900_0800,
+        len:  0x100,
+    }).is_err());
+
+    let mut space = AddressSpace::new();
+    space.mmap(0x7000_0000, 0x1000, false, true, true, false, "/dev/uio0",
+        0x2000);
+
+    let write = Event::Write { pc: 0x1000, addr: 0x0900_0004, val: 0x41,
+        sz: 1 };
+    assert_eq!(map.access(Some(&space), &write).unwrap().to_string(),
+        "mmio_write(uart0, 0x4, 0x41)");
+
+    let read = Event::ReadAddr { pc: 0x1004, addr: 0x7000_0010, sz: 4 };
+    assert_eq!(map.access(Some(&space), &read).unwrap().to_string(),
+        "mmio_read(uio0, 0x2010, ?)");
+    assert!(map.access(None, &read).is_none());
+    assert!(map.access(Some(&space), &Event::Exec { pc: 0 }).is_none());
+}
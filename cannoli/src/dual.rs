@@ -0,0 +1,240 @@
+//! Dual addressing of memory accesses, and remapping events
+//!
+//! In system mode an access has a virtual address and a physical one, and
+//! analyses which track memory across remappings (eg. shared memory, or a
+//! buffer mapped twice) key on the physical one. Cannoli only traces QEMU's
+//! user-mode emulation, which has no guest page tables: guest memory is a
+//! range of QEMU's own address space at `guest_base`. This module gives the
+//! user-mode counterparts:
+//!
+//! - the host address, `guest_base + addr`, where QEMU keeps the byte,
+//!   which [`crate::control::guest_base`] gets from a running target
+//! - the [`Backing`] of the address, its identity across remappings: the
+//!   file and offset of file mappings, which stay the same when the file is
+//!   mapped again elsewhere or by another process, and the offset into an
+//!   anonymous mapping, numbered in the order they were made
+//!
+//! A [`DualSpace`] tracks the backings from the `Mmap` and `Munmap` events
+//! of a thread, and reports every change as a [`Remap`], the counterpart of
+//! a page-table change: after one, the same guest address may name other
+//! memory. [`DualSpace::access`] turns memory events into [`DualAddr`]s.
+//!
+//! This is an approximation. Memory shared by anonymous `MAP_SHARED`
+//! mappings (eg. across `fork()`) or moved by `mremap()` gets a new
+//! identity, as the mapping events don't tell, and the host address is only
+//! meaningful within the process.
+
+use std::fmt;
+use std::sync::Arc;
+use std::collections::BTreeMap;
+use crate::event::Event;
+
+/// What backs guest memory, which identifies it across remappings
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Backing {
+    /// A file, at `offset` into it
+    File { path: Arc<str>, offset: u64 },
+
+    /// The `id`th anonymous mapping of the process, at `offset` into it
+    Anon { id: u64, offset: u64 },
+}
+
+impl Backing {
+    /// Get the backing `delta` bytes further
+    fn advance(&self, delta: u64) -> Self {
+        match self {
+            Backing::File { path, offset } => Backing::File {
+                path: path.clone(), offset: offset.wrapping_add(delta) },
+            Backing::Anon { id, offset } => Backing::Anon {
+                id: *id, offset: offset.wrapping_add(delta) },
+        }
+    }
+}
+
+impl fmt::Display for Backing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Backing::File { path, offset } => write!(f, "{path}+{offset:#x}"),
+            Backing::Anon { id, offset } =>
+                write!(f, "anon#{id}+{offset:#x}"),
+        }
+    }
+}
+
+/// Both addresses of guest memory
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DualAddr {
+    /// Guest (virtual) address
+    pub guest: u64,
+
+    /// Host address, `None` if `guest_base` isn't known
+    pub host: Option<u64>,
+
+    /// What backs the address, `None` if it isn't mapped
+    pub backing: Option<Backing>,
+}
+
+impl fmt::Display for DualAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:#x}", self.guest)?;
+        if let Some(host) = self.host {
+            write!(f, " host {host:#x}")?;
+        }
+        if let Some(backing) = &self.backing {
+            write!(f, " {backing}")?;
+        }
+        Ok(())
+    }
+}
+
+/// A change of what backs a range of guest addresses
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Remap {
+    /// Base guest address of the range
+    pub base: u64,
+
+    /// Length of the range in bytes
+    pub len: u64,
+
+    /// What backs `base` now, `None` if the range was unmapped
+    pub backing: Option<Backing>,
+}
+
+/// A range of guest addresses with the same backing
+#[derive(Clone, Debug)]
+struct Range {
+    /// Address one past the end of the range
+    end: u64,
+
+    /// What backs the base of the range
+    backing: Backing,
+}
+
+/// Backings of the guest memory of a process, see the module documentation
+#[derive(Clone, Debug, Default)]
+pub struct DualSpace {
+    /// QEMU's `guest_base`, `None` if it isn't known
+    guest_base: Option<u64>,
+
+    /// Mapped ranges, keyed by base address
+    ranges: BTreeMap<u64, Range>,
+
+    /// Anonymous mappings made so far
+    anon: u64,
+}
+
+impl DualSpace {
+    /// Create the backings of a process without any mappings, with QEMU's
+    /// `guest_base` if it's known
+    pub fn new(guest_base: Option<u64>) -> Self {
+        Self { guest_base, ..Self::default() }
+    }
+
+    /// Remove the backings of the range at `base` of `len` bytes, splitting
+    /// the ranges it overlaps
+    fn remove(&mut self, base: u64, len: u64) {
+        let end = base.saturating_add(len);
+        let overlapping: Vec<u64> = self.ranges.range(..end).rev()
+            .take_while(|(_, x)| x.end > base).map(|(x, _)| *x).collect();
+        for start in overlapping {
+            let range = self.ranges.remove(&start).unwrap();
+            if start < base {
+                self.ranges.insert(start, Range {
+                    end: base, backing: range.backing.clone() });
+            }
+            if range.end > end {
+                self.ranges.insert(end, Range {
+                    end: range.end, backing: range.backing.advance(end - start)
+                });
+            }
+        }
+    }
+
+    /// Map `len` bytes at `base`, anonymous or `offset` into the file at
+    /// `path`, as reported by the [`crate::Cannoli::mmap`] callback
+    pub fn mmap(&mut self, base: u64, len: u64, anon: bool, path: &str,
+            offset: u64) -> Remap {
+        self.remove(base, len);
+        let backing = if anon {
+            self.anon += 1;
+            Backing::Anon { id: self.anon - 1, offset: 0 }
+        } else {
+            Backing::File { path: path.into(), offset }
+        };
+        self.ranges.insert(base, Range {
+            end: base.saturating_add(len), backing: backing.clone() });
+        Remap { base, len, backing: Some(backing) }
+    }
+
+    /// Unmap `len` bytes at `base`, as reported by the
+    /// [`crate::Cannoli::munmap`] callback
+    pub fn munmap(&mut self, base: u64, len: u64) -> Remap {
+        self.remove(base, len);
+        Remap { base, len, backing: None }
+    }
+
+    /// Track the mapping changes of `event`, `None` if it isn't one
+    pub fn event(&mut self, event: &Event) -> Option<Remap> {
+        match event {
+            Event::Mmap { base, len, anon, path, offset, .. } =>
+                Some(self.mmap(*base, *len, *anon, path, *offset)),
+            Event::Munmap { base, len } => Some(self.munmap(*base, *len)),
+            _ => None,
+        }
+    }
+
+    /// Get both addresses of the guest address `addr`
+    pub fn translate(&self, addr: u64) -> DualAddr {
+        let backing = self.ranges.range(..=addr).next_back()
+            .filter(|(_, x)| addr < x.end)
+            .map(|(base, x)| x.backing.advance(addr - base));
+        DualAddr {
+            guest: addr,
+            host: self.guest_base.map(|x| x.wrapping_add(addr)),
+            backing,
+        }
+    }
+
+    /// Get both addresses of the memory accessed by `event`, `None` if it
+    /// isn't a memory access
+    pub fn access(&self, event: &Event) -> Option<DualAddr> {
+        match event {
+            Event::Read      { addr, .. } | Event::Write     { addr, .. } |
+            Event::ReadAddr  { addr, .. } | Event::WriteAddr { addr, .. } =>
+                Some(self.translate(*addr)),
+            _ => None,
+        }
+    }
+}
+
+#[test]
+fn dual_addresses() {
+    let mut space = DualSpace::new(Some(0x7f00_0000_0000));
+    let map = space.mmap(0x10000, 0x3000, false, "/lib/libc.so", 0x1000);
+    assert_eq!(map.backing, Some(Backing::File {
+        path: "/lib/libc.so".into(), offset: 0x1000 }));
+    space.mmap(0x40000, 0x2000, true, "", 0);
+
+    let read = Event::ReadAddr { pc: 0, addr: 0x11004, sz: 4 };
+    assert_eq!(space.access(&read).unwrap().to_string(),
+        "0x11004 host 0x7f0000011004 /lib/libc.so+0x2004");
+    assert_eq!(space.access(&Event::Exec { pc: 0x11004 }), None);
+
+    // Unmapping the middle keeps the identity of what's left
+    let unmap = Event::Munmap { base: 0x11000, len: 0x1000 };
+    assert_eq!(space.event(&unmap).unwrap().backing, None);
+    assert_eq!(space.translate(0x11004).backing, None);
+    assert_eq!(space.translate(0x12008).backing, Some(Backing::File {
+        path: "/lib/libc.so".into(), offset: 0x3008 }));
+
+    // The same file mapped elsewhere is the same memory, anonymous mappings
+    // are all different
+    space.mmap(0x80000, 0x1000, false, "/lib/libc.so", 0x3000);
+    assert_eq!(space.translate(0x80008).backing,
+        space.translate(0x12008).backing);
+    space.mmap(0x40000, 0x1000, true, "", 0);
+    assert_eq!(space.translate(0x40010).backing,
+        Some(Backing::Anon { id: 1, offset: 0x10 }));
+    assert_eq!(space.translate(0x41010).to_string(),
+        "0x41010 host 0x7f0000041010 anon#0+0x1010");
+}
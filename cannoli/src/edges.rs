@@ -0,0 +1,164 @@
+//! Control flow edges, reported to [`crate::Cannoli::edge`]
+//!
+//! Edge coverage only needs to know where control flow went, not every
+//! instruction on the way. With [`crate::control::HookKind::Edge`] the
+//! jitter only hooks two kinds of instructions, with the cheapest hooks it
+//! has:
+//!
+//! - instructions which may end a basic block, the sources of edges
+//! - the first instruction of every translation block, the targets
+//!
+//! The jitter can't see where a branch goes when it translates it, so the
+//! client pairs every source with the next target in the thread's trace, in
+//! the sequential phase where the trace is in order. Translation blocks
+//! start at branch targets, so control never reaches code after a source
+//! without passing a target first.
+//!
+//! Whether a branch was taken is told by its fall-through address, the
+//! instruction after it. The jitter decodes it when it translates the branch
+//! (see [`crate::symbols::signatures::Signatures::fallthrough_len`]) and
+//! announces it in its trace. Branches without one (x86 jumps, calls and
+//! returns, and targets [`crate::symbols::signatures`] doesn't support)
+//! always count as taken. ARM code is assumed to be in ARM mode, and on MIPS
+//! the source is the delay slot of the branch, as that's where QEMU ends the
+//! block.
+//!
+//! Blocks which QEMU ends early (eg. at its instruction limit) end without a
+//! source, and the jitter finds block starts by the order QEMU translates
+//! instructions in, which misses a block starting right after such an end.
+//! A branch into one is dropped rather than paired with the wrong target.
+
+use std::sync::{Arc, Mutex, RwLock, LazyLock};
+use std::collections::HashMap;
+use crate::ClientInfo;
+use crate::symbols::signatures::CallKind;
+
+/// An edge hook in the trace
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Mark {
+    /// PC of the hooked instruction
+    pub pc: u64,
+
+    /// The instruction may end a basic block
+    pub source: bool,
+
+    /// The instruction starts a translation block
+    pub target: bool,
+}
+
+/// Fall-through addresses of the branches of a process, announced by the
+/// jitter
+#[derive(Default)]
+pub(crate) struct Branches {
+    /// Address of the instruction after every known branch, keyed by the PC
+    /// of the branch
+    fallthrough: RwLock<HashMap<u64, u64>>,
+
+    /// Branches which are calls or returns, keyed by their PC, see
+    /// [`crate::calls`]
+    calls: RwLock<HashMap<u64, CallKind>>,
+}
+
+/// Branches of every process with a connected thread, keyed by PID
+static BRANCHES: LazyLock<Mutex<HashMap<i32, Arc<Branches>>>> =
+    LazyLock::new(Default::default);
+
+impl Branches {
+    /// Get the branches of the process of `ci`
+    pub(crate) fn get(ci: &ClientInfo) -> Arc<Self> {
+        BRANCHES.lock().unwrap().entry(ci.pid).or_default().clone()
+    }
+
+    /// Forget the branches of the process `pid`, once its last thread is
+    /// gone
+    pub(crate) fn remove(pid: i32) {
+        BRANCHES.lock().unwrap().remove(&pid);
+    }
+
+    /// Add the branch at `pc` which falls through to `next`
+    pub(crate) fn add(&self, pc: u64, next: u64) {
+        self.fallthrough.write().unwrap().insert(pc, next);
+    }
+
+    /// Add the branch at `pc`, which is a call or a return
+    pub(crate) fn add_call(&self, pc: u64, kind: CallKind) {
+        self.calls.write().unwrap().insert(pc, kind);
+    }
+
+    /// Get whether the branch at `pc` is a call or a return
+    pub(crate) fn call_kind(&self, pc: u64) -> Option<CallKind> {
+        self.calls.read().unwrap().get(&pc).copied()
+    }
+
+    /// Check if going from the branch at `pc` to `target` took the branch
+    fn taken(&self, pc: u64, target: u64) -> bool {
+        self.fallthrough.read().unwrap().get(&pc) != Some(&target)
+    }
+}
+
+/// Pairs the sources and targets of a thread's trace into edges
+#[derive(Default)]
+pub(crate) struct Edges {
+    /// The last source, waiting for its target
+    source: Option<u64>,
+}
+
+impl Edges {
+    /// Forget the last source, when the trace has a gap
+    pub(crate) fn reset(&mut self) {
+        self.source = None;
+    }
+
+    /// Process the next edge hook in the trace. Returns the edge it
+    /// completes, as the source, target and if the branch was taken
+    pub(crate) fn mark(&mut self, mark: &Mark, branches: &Branches)
+            -> Option<(u64, u64, bool)> {
+        let edge = if mark.target {
+            self.source.take()
+                .map(|pc| (pc, mark.pc, branches.taken(pc, mark.pc)))
+        } else {
+            // Another source first, the target wasn't seen
+            self.source = None;
+            None
+        };
+
+        if mark.source {
+            self.source = Some(mark.pc);
+        }
+        edge
+    }
+}
+
+#[test]
+fn pair_edges() {
+    use crate::Architecture;
+    use crate::symbols::signatures::Signatures;
+
+    let mark = |pc, source, target| Mark { pc, source, target };
+    let branches = Branches::default();
+
+    // `jne 0x1010` at 0x1000, falling through to 0x1002
+    let x86 = Signatures::new(Architecture::X86_64, false).unwrap();
+    let len = x86.fallthrough_len(&[0x75, 0x0e]).unwrap();
+    assert_eq!(x86.fallthrough_len(&[0x0f, 0x85, 0, 0, 0, 0]), Some(6));
+    assert_eq!(x86.fallthrough_len(&[0xc3]), None);
+    branches.add(0x1000, 0x1000 + len);
+
+    let mut edges = Edges::default();
+    assert_eq!(edges.mark(&mark(0x1000, true, false), &branches), None);
+    assert_eq!(edges.mark(&mark(0x1010, false, true), &branches),
+        Some((0x1000, 0x1010, true)));
+
+    // A single instruction block is a target and a source
+    assert_eq!(edges.mark(&mark(0x1010, true, true), &branches), None);
+    assert_eq!(edges.mark(&mark(0x1000, true, true), &branches),
+        Some((0x1010, 0x1000, true)));
+    assert_eq!(edges.mark(&mark(0x1002, false, true), &branches),
+        Some((0x1000, 0x1002, false)));
+
+    // A missed target drops the edge
+    edges.mark(&mark(0x2000, true, false), &branches);
+    assert_eq!(edges.mark(&mark(0x3000, true, false), &branches), None);
+    assert_eq!(edges.mark(&mark(0x4000, false, true), &branches),
+        Some((0x3000, 0x4000, true)));
+}
@@ -0,0 +1,334 @@
+//! Estimating the overhead of capture profiles from a short dry run
+//!
+//! Which [`Profile`] a multi-hour capture can afford depends on the target:
+//! a tight loop over memory makes `memory-audit` expensive, a chatty server
+//! makes `syscall-audit` grow quickly. Rather than finding out hours in,
+//! run the target briefly with [`Counter`], which hooks like the `full`
+//! profile but only counts events, and project what every profile would
+//! cost from the counts with [`estimate`].
+//!
+//! Slowdowns need a second run to compare with. The target is run once with
+//! the filters of `syscall-audit`, which hook so little that it runs about
+//! as fast as under QEMU without hooks, and once with those of `full`. Both
+//! runs are compared by their progress: the whole run if the target exited
+//! in both, otherwise the number of system calls it made, so both runs can
+//! be cut short. Events are assumed to all cost about the same, which holds
+//! for the events of the profiles since they all go through the same
+//! buffers.
+//!
+//! Storage is what a capture of the events would take (see
+//! [`crate::capture`]) before any summarizing, or the drcov files of
+//! `coverage`, so they are upper bounds.
+
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use crate::{Cannoli, ClientInfo};
+use crate::profile::Profile;
+
+/// Size of an encoded [`crate::Event::Exec`]
+const EXEC_SIZE: u64 = 9;
+
+/// Size of an encoded [`crate::Event::Read`] or [`crate::Event::Write`]
+const MEM_SIZE: u64 = 26;
+
+/// Size of an encoded [`crate::Event::Regs`] without its registers
+const REGS_SIZE: u64 = 13;
+
+/// Size of an encoded [`crate::Event::Mmap`] without its path
+const MMAP_SIZE: u64 = 30;
+
+/// Size of an encoded [`crate::Event::Munmap`]
+const MUNMAP_SIZE: u64 = 17;
+
+/// Size of a basic block entry of a drcov file
+const DRCOV_SIZE: u64 = 8;
+
+/// How long the counts have to stay the same for [`settle`] to consider the
+/// run processed
+const SETTLE_TIME: Duration = Duration::from_millis(250);
+
+/// Number of events of every kind counted so far
+static COUNTS: [AtomicU64; 9] = [const { AtomicU64::new(0) }; 9];
+
+/// Indices into [`COUNTS`]
+const EXEC:     usize = 0;
+const COVERED:  usize = 1;
+const READS:    usize = 2;
+const WRITES:   usize = 3;
+const REGS:     usize = 4;
+const REGS_LEN: usize = 5;
+const SYSCALLS: usize = 6;
+const MAPS:     usize = 7;
+const MAPS_LEN: usize = 8;
+
+/// Events counted during a run
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Counts {
+    /// Instructions executed
+    pub exec: u64,
+
+    /// Distinct instructions executed, by process
+    pub covered: u64,
+
+    /// Memory reads
+    pub reads: u64,
+
+    /// Memory writes
+    pub writes: u64,
+
+    /// Register events, at system call sites with the filters of `full`
+    pub regs: u64,
+
+    /// Total size of the registers of the register events
+    pub regs_len: u64,
+
+    /// System calls made
+    pub syscalls: u64,
+
+    /// Mappings and unmappings
+    pub maps: u64,
+
+    /// Encoded size of the mappings and unmappings
+    pub maps_len: u64,
+}
+
+impl Counts {
+    /// Get the number of events of `profile`, and what storing them takes
+    pub fn profile(&self, profile: Profile) -> (u64, u64) {
+        let exec = (self.exec, self.exec * EXEC_SIZE);
+        let mem  = (self.reads + self.writes,
+            (self.reads + self.writes) * MEM_SIZE);
+        let regs = (self.regs, self.regs * REGS_SIZE + self.regs_len);
+        let maps = (self.maps, self.maps_len);
+        match profile {
+            Profile::Coverage => (self.covered, self.covered * DRCOV_SIZE),
+            Profile::MemoryAudit => (mem.0 + maps.0, mem.1 + maps.1),
+            Profile::SyscallAudit => (regs.0 + maps.0, regs.1 + maps.1),
+            Profile::Full => (exec.0 + mem.0 + regs.0 + maps.0,
+                exec.1 + mem.1 + regs.1 + maps.1),
+        }
+    }
+}
+
+/// Get the events counted by [`Counter`] so far
+pub fn counts() -> Counts {
+    let get = |x: usize| COUNTS[x].load(Ordering::Relaxed);
+    Counts {
+        exec:     get(EXEC),
+        covered:  get(COVERED),
+        reads:    get(READS),
+        writes:   get(WRITES),
+        regs:     get(REGS),
+        regs_len: get(REGS_LEN),
+        syscalls: get(SYSCALLS),
+        maps:     get(MAPS),
+        maps_len: get(MAPS_LEN),
+    }
+}
+
+/// Start counting from zero again, for the next run
+pub fn reset() {
+    for count in &COUNTS {
+        count.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Wait for the server to be done with what a run traced, and get the
+/// counts. Runs end before the last of their events are processed
+pub fn settle() -> Counts {
+    let mut last = counts();
+    loop {
+        std::thread::sleep(SETTLE_TIME);
+        let now = counts();
+        if now == last {
+            return now;
+        }
+        last = now;
+    }
+}
+
+/// Count `n` events in `index` of [`COUNTS`]
+fn count(index: usize, n: u64) {
+    COUNTS[index].fetch_add(n, Ordering::Relaxed);
+}
+
+/// A [`Cannoli`] which only counts events, see the module documentation
+pub struct Counter;
+
+impl Cannoli for Counter {
+    /// PCs executed, to count distinct ones
+    type Trace = u64;
+
+    /// Instructions executed by the process
+    type PidContext = Mutex<HashSet<u64>>;
+
+    type TidContext = ();
+
+    fn init_pid(_ci: &ClientInfo) -> Arc<Self::PidContext> {
+        Arc::new(Mutex::new(HashSet::new()))
+    }
+
+    fn init_tid(_pid: &Self::PidContext,
+            _ci: &ClientInfo) -> (Self, Self::TidContext) {
+        (Self, ())
+    }
+
+    fn exec(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            pc: u64, trace: &mut Vec<Self::Trace>) {
+        count(EXEC, 1);
+        trace.push(pc);
+    }
+
+    fn regs(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            _pc: u64, regs: &[u8], _trace: &mut Vec<Self::Trace>) {
+        count(REGS, 1);
+        count(REGS_LEN, regs.len() as u64);
+    }
+
+    fn syscall_entry(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            _pc: u64, _nr: u64, _args: &[u64],
+            _trace: &mut Vec<Self::Trace>) {
+        count(SYSCALLS, 1);
+    }
+
+    fn read(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            _pc: u64, _addr: u64, _val: u64, _sz: u8, _helper: bool,
+            _trace: &mut Vec<Self::Trace>) {
+        count(READS, 1);
+    }
+
+    fn write(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            _pc: u64, _addr: u64, _val: u64, _sz: u8, _helper: bool,
+            _trace: &mut Vec<Self::Trace>) {
+        count(WRITES, 1);
+    }
+
+    fn mmap(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            _base: u64, _len: u64, _anon: bool, _read: bool, _write: bool,
+            _exec: bool, path: &str, _offset: u64,
+            _trace: &mut Vec<Self::Trace>) {
+        count(MAPS, 1);
+        count(MAPS_LEN, MMAP_SIZE + path.len() as u64);
+    }
+
+    fn munmap(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            _base: u64, _len: u64, _trace: &mut Vec<Self::Trace>) {
+        count(MAPS, 1);
+        count(MAPS_LEN, MUNMAP_SIZE);
+    }
+
+    fn trace(&mut self, pid: &Self::PidContext,
+            _tid: &Self::TidContext, trace: &[Self::Trace]) {
+        let mut covered = pid.lock().unwrap();
+        let new = trace.iter().filter(|x| covered.insert(**x)).count();
+        count(COVERED, new as u64);
+    }
+}
+
+/// A dry run of the target
+#[derive(Clone, Copy, Debug)]
+pub struct DryRun {
+    /// Events counted
+    pub counts: Counts,
+
+    /// Wall-clock time the run took
+    pub elapsed: Duration,
+
+    /// Whether the target exited by itself, rather than being cut short
+    pub exited: bool,
+}
+
+/// Projected overhead of a profile
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Estimate {
+    /// The profile
+    pub profile: Profile,
+
+    /// Events per second of the profile
+    pub events_per_sec: f64,
+
+    /// Bytes of output per second of the profile
+    pub bytes_per_sec: f64,
+
+    /// How many times slower the target runs than under QEMU without hooks,
+    /// `None` if the runs can't be compared, see the module documentation
+    pub slowdown: Option<f64>,
+}
+
+/// Project the overhead of every profile from the dry run `baseline`, with
+/// the filters of `syscall-audit`, and the dry run `full`, with those of
+/// `full`
+pub fn estimate(baseline: &DryRun, full: &DryRun) -> Vec<Estimate> {
+    // Progress made by each run, in runs or in system calls
+    let progress = if baseline.exited && full.exited {
+        Some((1., 1.))
+    } else if baseline.counts.syscalls != 0 && full.counts.syscalls != 0 {
+        Some((baseline.counts.syscalls as f64, full.counts.syscalls as f64))
+    } else {
+        None
+    };
+
+    let full_time = full.elapsed.as_secs_f64().max(f64::MIN_POSITIVE);
+    let (full_events, _) = full.counts.profile(Profile::Full);
+    let (base_events, _) = full.counts.profile(Profile::SyscallAudit);
+    Profile::ALL.into_iter().map(|profile| {
+        let (events, bytes) = full.counts.profile(profile);
+        let Some((base_progress, full_progress)) = progress else {
+            return Estimate {
+                profile,
+                events_per_sec: events as f64 / full_time,
+                bytes_per_sec:  bytes as f64 / full_time,
+                slowdown:       None,
+            };
+        };
+
+        // Time per unit of progress without hooks, and the cost of every
+        // event hooked on top of those of the baseline
+        let per_unit = |x: u64| x as f64 / full_progress;
+        let base = (baseline.elapsed.as_secs_f64() / base_progress)
+            .max(f64::MIN_POSITIVE);
+        let per_event = (full_time / full_progress - base).max(0.) /
+            per_unit(full_events.saturating_sub(base_events)).max(1.);
+        let time = base +
+            per_event * per_unit(events.saturating_sub(base_events));
+        Estimate {
+            profile,
+            events_per_sec: per_unit(events) / time,
+            bytes_per_sec:  per_unit(bytes) / time,
+            slowdown:       Some(time / base),
+        }
+    }).collect()
+}
+
+#[test]
+fn estimates() {
+    let counts = Counts {
+        exec: 1000, covered: 100, reads: 300, writes: 200, regs: 20,
+        regs_len: 20 * 100, syscalls: 10, maps: 2, maps_len: 100,
+    };
+    assert_eq!(counts.profile(Profile::Coverage), (100, 800));
+    assert_eq!(counts.profile(Profile::MemoryAudit), (502, 500 * 26 + 100));
+    assert_eq!(counts.profile(Profile::SyscallAudit), (22, 20 * 113 + 100));
+
+    // 10 system calls in 1s without hooks, and 10 in 3s with every event
+    let baseline = DryRun {
+        counts:  Counts { syscalls: 10, ..Counts::default() },
+        elapsed: Duration::from_secs(1),
+        exited:  false,
+    };
+    let full = DryRun { counts, elapsed: Duration::from_secs(3),
+        exited: false };
+    let estimates = estimate(&baseline, &full);
+    let full = estimates.iter().find(|x| x.profile == Profile::Full).unwrap();
+    assert!((full.slowdown.unwrap() - 3.).abs() < 1e-9);
+    assert!((full.events_per_sec - 1522. / 3.).abs() < 1e-9);
+    let syscalls = &estimates[2];
+    assert!((syscalls.slowdown.unwrap() - 1.).abs() < 1e-9);
+
+    // Nothing to compare with
+    let baseline = DryRun { counts: Counts::default(), ..baseline };
+    assert!(estimate(&baseline, &DryRun { counts, ..baseline })
+        .iter().all(|x| x.slowdown.is_none()));
+}
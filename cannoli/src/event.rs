@@ -0,0 +1,59 @@
+//! Owned events covering the payload of every [`crate::Cannoli`] callback
+//!
+//! The definitions and their encoding live in [`cannoli_types::event`], so
+//! consumers without `std` can decode them too, see there.
+
+use crate::heap::HeapEvent;
+
+pub use cannoli_types::event::*;
+
+impl From<HeapEvent> for Event {
+    fn from(event: HeapEvent) -> Self {
+        match event {
+            HeapEvent::Brk   { old, new }  => Event::Brk   { old, new },
+            HeapEvent::Arena { base, len } => Event::Arena { base, len },
+        }
+    }
+}
+
+#[test]
+fn encode_roundtrip() {
+    let events = [
+        Event::Exec   { pc: 0x1000 },
+        Event::Branch { pc: 0x1004, taken: true, regs: vec![1, 2, 3] },
+        Event::Write  { pc: 0x1008, addr: 0x5000, val: 0x41, sz: 1 },
+        Event::Mmap   {
+            base: 0x7000, len: 0x1000, anon: false, read: true,
+            write: false, exec: true, path: "/lib/libc.so".into(), offset: 0,
+        },
+        Event::Brk    { old: 0x9000, new: 0xa000 },
+        Event::ReadAddr { pc: 0x100c, addr: 0x5000, sz: 4 },
+        Event::Rep    { pc: 0x1010, count: 3, backward: false, accesses: vec![
+            RepAccess { write: false, addr: 0x5000, sz: 8 },
+            RepAccess { write: true,  addr: 0x6000, sz: 8 },
+        ] },
+        Event::Cmp    { pc: 0x1014, lhs: 0x41, rhs: 0x7f454c46, sz: 4 },
+        Event::RegFile { pc: 0x1018, index: 1, regs: vec![0; 16] },
+        Event::Signal { sig: 11, from: 0x101c, to: 0x2000 },
+    ];
+
+    let mut bytes = Vec::new();
+    for event in &events {
+        event.encode(&mut bytes);
+    }
+
+    let mut cursor = bytes.as_slice();
+    for event in &events {
+        assert_eq!(&Event::decode(&mut cursor).unwrap(), event);
+    }
+    assert!(cursor.is_empty());
+    assert!(matches!(Event::decode(&mut &bytes[..4]),
+        Err(DecodeError::BufferTruncated)));
+
+    for event in &events {
+        let json = serde_json::to_string(event).unwrap();
+        assert_eq!(&serde_json::from_str::<Event>(&json).unwrap(), event);
+    }
+    assert_eq!(serde_json::to_string(&events[0]).unwrap(),
+        r#"{"event":"exec","pc":4096}"#);
+}
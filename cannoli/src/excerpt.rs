@@ -0,0 +1,248 @@
+//! Excerpts of captures around events of interest
+//!
+//! Captures of long runs get huge, but whoever looks at a bug usually only
+//! needs what happened right around a few events: the crash, the write of a
+//! magic value, the call into a suspicious function. [`excerpt`] copies only
+//! the events within `radius` events of each [`Mark`], in the same thread,
+//! into a new (small, shareable) capture. Overlapping windows are merged.
+//!
+//! Marks name an event by its thread and its number in the thread, the same
+//! way `cannoli grep` prints matches, so the matches of any
+//! [`crate::grep::Query`] can be used as marks, or a list of marks can be
+//! kept in a file with one `<segment> <pid> <tid> #<event number>` per line.
+//!
+//! Mapping events (`mmap`, `munmap`, `brk` and allocator arenas) are always
+//! copied, whether they are in a window or not, so the excerpt can still be
+//! symbolized and canonicalized. Segments of the capture are copied along
+//! with their manifests.
+
+use std::path::Path;
+use std::str::FromStr;
+use std::collections::{HashMap, HashSet, VecDeque};
+use crate::capture::{CaptureReader, CaptureWriter, Record};
+use crate::event::Event;
+use crate::grep::Match;
+
+/// An event of interest in a capture
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Mark {
+    /// Segment the event is in
+    pub segment: u32,
+
+    /// Thread ID of the thread
+    pub tid: i32,
+
+    /// Number of events of the thread in the segment before this one
+    pub ordinal: u64,
+}
+
+impl From<&Match> for Mark {
+    fn from(found: &Match) -> Self {
+        Self { segment: found.segment, tid: found.tid,
+            ordinal: found.ordinal }
+    }
+}
+
+impl FromStr for Mark {
+    type Err = String;
+
+    /// Parse a mark from `<segment> <pid> <tid> #<event number>`, anything
+    /// following it (eg. the event, as printed by `cannoli grep`) is ignored
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid mark `{text}`");
+        let mut fields = text.split_whitespace();
+        let segment = fields.next().and_then(|x| x.parse().ok());
+        let _pid    = fields.next();
+        let tid     = fields.next().and_then(|x| x.parse().ok());
+        let ordinal = fields.next().and_then(|x| x.strip_prefix('#'))
+            .and_then(|x| x.parse().ok());
+        Ok(Self {
+            segment: segment.ok_or_else(invalid)?,
+            tid:     tid.ok_or_else(invalid)?,
+            ordinal: ordinal.ok_or_else(invalid)?,
+        })
+    }
+}
+
+/// What was copied by [`excerpt`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ExcerptStats {
+    /// Number of windows, after merging overlapping ones
+    pub windows: u64,
+
+    /// Events copied to the excerpt
+    pub events: u64,
+
+    /// Events in the capture
+    pub total: u64,
+}
+
+/// Check if `event` changes the mappings of the process
+fn is_mapping(event: &Event) -> bool {
+    matches!(event, Event::Mmap { .. } | Event::Munmap { .. } |
+        Event::Brk { .. } | Event::Arena { .. })
+}
+
+/// Window state of a single thread
+#[derive(Default)]
+struct Thread {
+    /// Number of events of the thread so far
+    ordinal: u64,
+
+    /// The last `radius` events, which precede the next window
+    before: VecDeque<Event>,
+
+    /// Number of events left in the current window
+    after: u64,
+
+    /// Ordinal following the last event of the last window
+    end: Option<u64>,
+}
+
+impl Thread {
+    /// Drop the oldest event before the next window, keeping it if it's a
+    /// mapping event
+    fn evict(&mut self, out: &mut Vec<Event>) {
+        if let Some(event) = self.before.pop_front() {
+            if is_mapping(&event) {
+                out.push(event);
+            }
+        }
+    }
+}
+
+/// Copy the events within `radius` events of every mark in `marks` from the
+/// capture at `capture` into a new capture at `out`, see the module
+/// documentation
+pub fn excerpt(capture: impl AsRef<Path>, out: impl AsRef<Path>,
+        marks: &[Mark], radius: u64) -> std::io::Result<ExcerptStats> {
+    let marks   = marks.iter().copied().collect::<HashSet<_>>();
+    let mut reader  = CaptureReader::open(capture)?;
+    let mut writer: Option<CaptureWriter> = None;
+    let mut stats   = ExcerptStats::default();
+    let mut segment = 0;
+    let mut threads: HashMap<i32, (i32, Thread)> = HashMap::new();
+    let mut copied  = Vec::new();
+
+    // Copy the mapping events left before the windows of the segment
+    let finish = |writer: &mut Option<CaptureWriter>,
+            threads: &mut HashMap<i32, (i32, Thread)>| {
+        let mut count = 0;
+        for (tid, (pid, mut thread)) in threads.drain() {
+            let mut copied = Vec::new();
+            while !thread.before.is_empty() {
+                thread.evict(&mut copied);
+            }
+            count += copied.len() as u64;
+            if let (Some(writer), false) = (writer.as_mut(), copied.is_empty())
+            {
+                writer.write_events(pid, tid, &copied)?;
+            }
+        }
+        Ok::<_, std::io::Error>(count)
+    };
+
+    while let Some(record) = reader.next_record()? {
+        let (pid, tid, events) = match record {
+            Record::Segment(x) => {
+                stats.events += finish(&mut writer, &mut threads)?;
+                segment = x.index;
+                let manifest = x.manifest.as_deref();
+                match &mut writer {
+                    Some(writer) => writer.next_segment(manifest)?,
+                    None => writer = Some(CaptureWriter::create(&out,
+                        manifest)?),
+                }
+                continue;
+            }
+            Record::Events { pid, tid, events } => (pid, tid, events),
+        };
+
+        stats.total += events.len() as u64;
+        let (_, thread) = threads.entry(tid)
+            .or_insert_with(|| (pid, Thread::default()));
+        for event in events {
+            let mark = Mark { segment, tid, ordinal: thread.ordinal };
+            thread.ordinal += 1;
+
+            if marks.contains(&mark) {
+                // Windows which touch are merged too
+                let first = mark.ordinal - thread.before.len() as u64;
+                if thread.end != Some(first) {
+                    stats.windows += 1;
+                }
+                copied.extend(thread.before.drain(..));
+                copied.push(event);
+                thread.after = radius;
+                thread.end   = Some(thread.ordinal);
+            } else if thread.after > 0 {
+                copied.push(event);
+                thread.after -= 1;
+                thread.end    = Some(thread.ordinal);
+            } else {
+                thread.before.push_back(event);
+                if thread.before.len() as u64 > radius {
+                    thread.evict(&mut copied);
+                }
+            }
+        }
+
+        stats.events += copied.len() as u64;
+        if !copied.is_empty() {
+            let writer = writer.as_mut().ok_or_else(|| {
+                std::io::Error::new(std::io::ErrorKind::InvalidData,
+                    "Events before the first segment")
+            })?;
+            writer.write_events(pid, tid, &copied)?;
+            copied.clear();
+        }
+    }
+    stats.events += finish(&mut writer, &mut threads)?;
+
+    if let Some(writer) = &mut writer {
+        writer.flush()?;
+    }
+    Ok(stats)
+}
+
+#[test]
+fn excerpt_around_marks() {
+    let dir  = std::env::temp_dir();
+    let path = dir.join(format!("cannoli_excerpt_{}", std::process::id()));
+    let out  = dir.join(format!("cannoli_excerpt_out_{}",
+        std::process::id()));
+
+    let mut writer = CaptureWriter::create(&path, None).unwrap();
+    let mut events = vec![Event::Mmap {
+        base: 0x1000, len: 0x1000, anon: false, read: true, write: false,
+        exec: true, path: "/bin/app".into(), offset: 0,
+    }];
+    events.extend((0..20).map(|x| Event::Exec { pc: 0x1000 + x * 4 }));
+    writer.write_events(1, 1, &events[..10]).unwrap();
+    writer.write_events(1, 2, &[Event::Exec { pc: 0x2000 }]).unwrap();
+    writer.write_events(1, 1, &events[10..]).unwrap();
+    drop(writer);
+
+    // Marks on events 5 and 7 overlap, and the one on 15 spans records
+    let marks = ["0 1 1 #5", "0 1 1 #7 exec 0x1018", "0 1 1 #15"].iter()
+        .map(|x| x.parse::<Mark>().unwrap())
+        .collect::<Vec<_>>();
+    assert!("0 1 #5".parse::<Mark>().is_err());
+    let stats = excerpt(&path, &out, &marks, 1).unwrap();
+    assert_eq!(stats, ExcerptStats { windows: 2, events: 9, total: 22 });
+
+    let mut reader = CaptureReader::open(&out).unwrap();
+    let mut copied = Vec::new();
+    while let Some(record) = reader.next_record().unwrap() {
+        if let Record::Events { events, .. } = record {
+            copied.extend(events);
+        }
+    }
+    let mut expected = vec![events[0].clone()];
+    expected.extend_from_slice(&events[4..9]);
+    expected.extend_from_slice(&events[14..17]);
+    assert_eq!(copied, expected);
+
+    std::fs::remove_file(&path).unwrap();
+    std::fs::remove_file(&out).unwrap();
+}
@@ -0,0 +1,869 @@
+//! Searching captures for events
+//!
+//! A [`Query`] is a list of terms, every term has to match for an event to
+//! match:
+//!
+//! ```text
+//! exec read write mmap munmap heap rep   kinds of events, any of them
+//! cmp
+//! pc=<start>[-<end>]                     PC in a range, end exclusive
+//! addr=<start>[-<end>]                   memory accessed or mapped overlaps
+//!                                        a range
+//! value=<value>                          value loaded, stored or compared
+//! pid=<pid> tid=<tid> segment=<index>    where the event comes from
+//! module=<name>                          PC in a file whose name starts
+//!                                        with <name>
+//! addr-module=<name>                     same, for the address accessed
+//! in=[<module>!]<symbol>                 PC inside a function of a module
+//! ```
+//!
+//! For example `write value=0x41414141`, `exec in=parse_header` or
+//! `read module=libssl pc=0x7f0000001000-0x7f0000002000`. Numbers are
+//! decimal, or hex with a `0x` prefix.
+//!
+//! Modules and symbols are resolved with the mappings recorded in the
+//! capture, symbols are loaded from the mapped files with `nm` (see
+//! [`SymbolTable::from_elf`]).
+//!
+//! The same terms can be compiled into the client with [`filter!`], which
+//! checks them with plain matches instead of parsing a query at runtime.
+//! Terms are separated by commas, kinds by `|`, and ranges are Rust ranges:
+//!
+//! ```
+//! use cannoli::event::Event;
+//!
+//! let filter = cannoli::filter!(read | write, addr in 0x8000..0x9000,
+//!     value == 0x41414141);
+//! let event = Event::Write { pc: 0x1000, addr: 0x8000, val: 0x41414141,
+//!     sz: 4 };
+//! assert!(filter.matches(&event));
+//! ```
+//!
+//! Terms which need the mappings of the target (`module == "libssl"`,
+//! `addr_module == "libssl"`) only match with [`Filter::matches_in`]. `pid`,
+//! `tid`, `segment` and `in` have no equivalent, they aren't properties of
+//! an event.
+//!
+//! An [`Index`] of a capture summarizes every events record with the kinds
+//! of events in it and the ranges of PCs and addresses, so a search can skip
+//! the records which can't match without decoding them. The index is kept
+//! next to the capture and rebuilt when the capture grows.
+
+use std::fs::File;
+use std::io::{Read, Write, BufReader, BufWriter};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::collections::HashMap;
+use crate::address_space::AddressSpace;
+use crate::capture::{CaptureReader, Record};
+use crate::event::Event;
+use crate::symbols::SymbolTable;
+
+/// Magic bytes at the start of an index
+const INDEX_MAGIC: &[u8; 8] = b"CNLINDEX";
+
+/// Version of the index format, bumped on incompatible changes
+const INDEX_VERSION: u32 = 1;
+
+/// Extension appended to a capture's path to get the path of its index
+pub const INDEX_EXTENSION: &str = "index";
+
+/// Kind of an event, see the module documentation
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    /// Executed a PC, with or without registers, or was delivered a signal
+    Exec,
+
+    /// Memory load, including the loads of a summarized block transfer
+    Read,
+
+    /// Memory store, including the stores of a summarized block transfer
+    Write,
+
+    /// Memory was mapped
+    Mmap,
+
+    /// Memory was unmapped
+    Munmap,
+
+    /// Heap growth
+    Heap,
+
+    /// Summarized block transfer
+    Rep,
+
+    /// Comparison
+    Cmp,
+}
+
+impl Kind {
+    /// Get the kind named `name`
+    pub fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "exec"   => Kind::Exec,
+            "read"   => Kind::Read,
+            "write"  => Kind::Write,
+            "mmap"   => Kind::Mmap,
+            "munmap" => Kind::Munmap,
+            "heap"   => Kind::Heap,
+            "rep"    => Kind::Rep,
+            "cmp"    => Kind::Cmp,
+            _ => return None,
+        })
+    }
+
+    /// Bit for this kind in a mask of kinds
+    pub const fn bit(self) -> u8 {
+        1 << self as u8
+    }
+
+    /// Get the mask of the kinds of `event`
+    pub fn mask(event: &Event) -> u8 {
+        match event {
+            Event::Exec { .. } | Event::Regs { .. } | Event::Branch { .. } |
+            Event::RegFile { .. } | Event::Signal { .. } => Kind::Exec.bit(),
+            Event::Read { .. } | Event::ReadAddr { .. } => Kind::Read.bit(),
+            Event::Write { .. } | Event::WriteAddr { .. } =>
+                Kind::Write.bit(),
+            Event::Rep { accesses, .. } => {
+                accesses.iter().fold(Kind::Rep.bit(), |mask, x| {
+                    mask | if x.write {
+                        Kind::Write.bit()
+                    } else {
+                        Kind::Read.bit()
+                    }
+                })
+            }
+            Event::Cmp { .. }    => Kind::Cmp.bit(),
+            Event::Mmap { .. }   => Kind::Mmap.bit(),
+            Event::Munmap { .. } => Kind::Munmap.bit(),
+            Event::Brk { .. } | Event::Arena { .. } => Kind::Heap.bit(),
+        }
+    }
+}
+
+/// Get the PC of `event`, if it has one
+pub fn event_pc(event: &Event) -> Option<u64> {
+    match *event {
+        Event::Exec { pc } | Event::Regs { pc, .. } |
+        Event::Branch { pc, .. } | Event::Read { pc, .. } |
+        Event::Write { pc, .. } | Event::ReadAddr { pc, .. } |
+        Event::WriteAddr { pc, .. } | Event::Rep { pc, .. } |
+        Event::Cmp { pc, .. } | Event::RegFile { pc, .. } |
+        Event::Signal { from: pc, .. } => Some(pc),
+        _ => None,
+    }
+}
+
+/// Get the range of memory `event` accesses or maps, if any
+pub fn event_addrs(event: &Event) -> Option<Range<u64>> {
+    let range = |start: u64, len: u64| start..start.saturating_add(len);
+    match event {
+        Event::Read { addr, sz, .. } | Event::Write { addr, sz, .. } |
+        Event::ReadAddr { addr, sz, .. } | Event::WriteAddr { addr, sz, .. } =>
+            Some(range(*addr, *sz as u64)),
+        Event::Rep { count, backward, accesses, .. } => {
+            accesses.iter().map(|x| {
+                let len  = (x.sz as u64).saturating_mul(*count);
+                let last = len.saturating_sub(x.sz as u64);
+                if *backward {
+                    range(x.addr.saturating_sub(last), len)
+                } else {
+                    range(x.addr, len)
+                }
+            }).reduce(|a, b| a.start.min(b.start)..a.end.max(b.end))
+        }
+        Event::Mmap { base, len, .. } | Event::Munmap { base, len } |
+        Event::Arena { base, len } => Some(range(*base, *len)),
+        Event::Brk { old, new } => Some(*old..*new),
+        _ => None,
+    }
+}
+
+/// Check if `a` and `b` overlap
+fn overlaps(a: &Range<u64>, b: &Range<u64>) -> bool {
+    a.start < b.end && b.start < a.end
+}
+
+/// Check if `event` loaded, stored or compared `value`
+pub fn has_value(event: &Event, value: u64) -> bool {
+    match *event {
+        Event::Read { val, .. } | Event::Write { val, .. } => val == value,
+        Event::Cmp { lhs, rhs, .. } => lhs == value || rhs == value,
+        _ => false,
+    }
+}
+
+/// Check if `addr` is in the module `name` of `space`, for [`filter!`]
+#[doc(hidden)]
+pub fn in_module(space: Option<&AddressSpace>, addr: Option<u64>,
+        name: &str) -> bool {
+    space.zip(addr).and_then(|(space, addr)| space.lookup(addr))
+        .is_some_and(|x| !x.anon && is_module(&x.path, name))
+}
+
+/// Parse a decimal number, or a hex number with a `0x` prefix
+fn parse_number(text: &str) -> Result<u64, String> {
+    let parsed = match text.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None      => text.parse(),
+    };
+    parsed.map_err(|_| format!("invalid number `{text}`"))
+}
+
+/// Parse `<start>[-<end>]` into a range, a single number is a range of one
+fn parse_range(text: &str) -> Result<Range<u64>, String> {
+    match text.split_once('-') {
+        Some((start, end)) => Ok(parse_number(start)?..parse_number(end)?),
+        None => {
+            let start = parse_number(text)?;
+            Ok(start..start.saturating_add(1))
+        }
+    }
+}
+
+/// Check if the file at `path` is the module `name`
+fn is_module(path: &str, name: &str) -> bool {
+    path.rsplit('/').next().unwrap_or(path).starts_with(name)
+}
+
+/// A single term of a [`Query`], see the module documentation
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Condition {
+    /// The event is of one of the kinds in the mask
+    Kinds(u8),
+
+    /// The PC is in the range
+    Pc(Range<u64>),
+
+    /// The memory accessed or mapped overlaps the range
+    Addr(Range<u64>),
+
+    /// The value loaded or stored
+    Value(u64),
+
+    /// The event is from this process
+    Pid(i32),
+
+    /// The event is from this thread
+    Tid(i32),
+
+    /// The event is from this segment of the capture
+    Segment(u32),
+
+    /// The PC is in the module
+    Module(String),
+
+    /// The memory accessed is in the module
+    AddrModule(String),
+
+    /// The PC is in the symbol, of the module if one is given
+    Symbol { module: Option<String>, name: String },
+}
+
+/// A search for events, see the module documentation
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Query {
+    /// Every condition has to hold for an event to match
+    pub conditions: Vec<Condition>,
+}
+
+impl Query {
+    /// Parse the terms of a query
+    pub fn parse(terms: &[impl AsRef<str>]) -> Result<Self, String> {
+        let mut kinds = 0;
+        let mut conditions = Vec::new();
+        for term in terms {
+            let term = term.as_ref();
+            if let Some(kind) = Kind::parse(term) {
+                kinds |= kind.bit();
+                continue;
+            }
+
+            let Some((key, value)) = term.split_once('=') else {
+                return Err(format!("unknown term `{term}`"));
+            };
+            let id = |x: &str| x.parse().map_err(|_| {
+                format!("invalid id `{x}`")
+            });
+            conditions.push(match key {
+                "pc"          => Condition::Pc(parse_range(value)?),
+                "addr"        => Condition::Addr(parse_range(value)?),
+                "value"       => Condition::Value(parse_number(value)?),
+                "pid"         => Condition::Pid(id(value)?),
+                "tid"         => Condition::Tid(id(value)?),
+                "segment"     => Condition::Segment(id(value)? as u32),
+                "module"      => Condition::Module(value.into()),
+                "addr-module" => Condition::AddrModule(value.into()),
+                "in" => match value.split_once('!') {
+                    Some((module, name)) => Condition::Symbol {
+                        module: Some(module.into()),
+                        name:   name.into(),
+                    },
+                    None => Condition::Symbol {
+                        module: None,
+                        name:   value.into(),
+                    },
+                },
+                _ => return Err(format!("unknown term `{term}`")),
+            });
+        }
+
+        if kinds != 0 {
+            conditions.push(Condition::Kinds(kinds));
+        }
+        Ok(Self { conditions })
+    }
+
+    /// Check if the query needs the mappings of the target
+    fn needs_mappings(&self) -> bool {
+        self.conditions.iter().any(|x| matches!(x, Condition::Module(_) |
+            Condition::AddrModule(_) | Condition::Symbol { .. }))
+    }
+
+    /// Check if any event of the record summarized by `summary` could match
+    fn may_match(&self, summary: &Summary) -> bool {
+        let pcs   = summary.pcs.0..summary.pcs.1.saturating_add(1);
+        let addrs = summary.addrs.0..summary.addrs.1;
+        self.conditions.iter().all(|x| match x {
+            Condition::Kinds(mask)  => summary.kinds & mask != 0,
+            Condition::Pc(range)    => overlaps(range, &pcs),
+            Condition::Addr(range)  => overlaps(range, &addrs),
+            Condition::Pid(pid)     => summary.pid == *pid,
+            Condition::Tid(tid)     => summary.tid == *tid,
+            Condition::Segment(seg) => summary.segment == *seg,
+            _ => true,
+        })
+    }
+}
+
+/// A query compiled by [`filter!`], see the module documentation
+#[derive(Clone, Copy)]
+pub struct Filter<F> {
+    /// Checks every term, given the mappings of the target if known
+    check: F,
+}
+
+impl<F> Filter<F>
+        where F: Fn(&Event, Option<&AddressSpace>) -> bool {
+    /// Wrap the check [`filter!`] generates
+    #[doc(hidden)]
+    pub const fn new(check: F) -> Self {
+        Self { check }
+    }
+
+    /// Check if `event` matches. Module terms never match without the
+    /// mappings, see [`Filter::matches_in`]
+    pub fn matches(&self, event: &Event) -> bool {
+        (self.check)(event, None)
+    }
+
+    /// Check if `event`, of a process with the mappings `space`, matches
+    pub fn matches_in(&self, space: &AddressSpace, event: &Event) -> bool {
+        (self.check)(event, Some(space))
+    }
+}
+
+/// Compile the terms of a query into a [`Filter`], see the [`grep`] module
+/// documentation for the terms
+///
+/// [`grep`]: crate::grep
+#[macro_export]
+macro_rules! filter {
+    // Kinds of events
+    (@kind exec)   => { $crate::grep::Kind::Exec };
+    (@kind read)   => { $crate::grep::Kind::Read };
+    (@kind write)  => { $crate::grep::Kind::Write };
+    (@kind mmap)   => { $crate::grep::Kind::Mmap };
+    (@kind munmap) => { $crate::grep::Kind::Munmap };
+    (@kind heap)   => { $crate::grep::Kind::Heap };
+    (@kind rep)    => { $crate::grep::Kind::Rep };
+    (@kind cmp)    => { $crate::grep::Kind::Cmp };
+    (@kind $other:ident) => {
+        compile_error!(concat!("unknown kind of event `",
+            stringify!($other), "`"))
+    };
+
+    // Terms, every one has to hold
+    (@terms $event:ident $space:ident) => { true };
+    (@terms $event:ident $space:ident
+            pc in $range:expr $(, $($rest:tt)*)?) => {
+        $crate::grep::event_pc($event).is_some_and(|x| ($range).contains(&x))
+            && $crate::filter!(@terms $event $space $($($rest)*)?)
+    };
+    (@terms $event:ident $space:ident
+            addr in $range:expr $(, $($rest:tt)*)?) => {
+        $crate::grep::event_addrs($event).is_some_and(|x| {
+            let range: ::std::ops::Range<u64> = $range;
+            range.start < x.end && x.start < range.end
+        }) && $crate::filter!(@terms $event $space $($($rest)*)?)
+    };
+    (@terms $event:ident $space:ident
+            value == $value:expr $(, $($rest:tt)*)?) => {
+        $crate::grep::has_value($event, $value)
+            && $crate::filter!(@terms $event $space $($($rest)*)?)
+    };
+    (@terms $event:ident $space:ident
+            module == $name:expr $(, $($rest:tt)*)?) => {
+        $crate::grep::in_module($space, $crate::grep::event_pc($event),
+            $name) && $crate::filter!(@terms $event $space $($($rest)*)?)
+    };
+    (@terms $event:ident $space:ident
+            addr_module == $name:expr $(, $($rest:tt)*)?) => {
+        $crate::grep::in_module($space,
+            $crate::grep::event_addrs($event).map(|x| x.start), $name)
+            && $crate::filter!(@terms $event $space $($($rest)*)?)
+    };
+    (@terms $event:ident $space:ident
+            $($kind:ident)|+ $(, $($rest:tt)*)?) => {
+        $crate::grep::Kind::mask($event) & {
+            const MASK: u8 = 0 $(| $crate::filter!(@kind $kind).bit())+;
+            MASK
+        } != 0 && $crate::filter!(@terms $event $space $($($rest)*)?)
+    };
+    (@terms $event:ident $space:ident $($rest:tt)*) => {
+        compile_error!(concat!("unknown filter term `",
+            stringify!($($rest)*), "`"))
+    };
+
+    ($($terms:tt)*) => {
+        $crate::grep::Filter::new(|event: &$crate::event::Event,
+                space: Option<&$crate::address_space::AddressSpace>| {
+            let _ = (event, space);
+            $crate::filter!(@terms event space $($terms)*)
+        })
+    };
+}
+
+/// Summary of an events record of a capture, see [`Index`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Summary {
+    /// Offset of the record in the capture
+    pub offset: u64,
+
+    /// Segment the record is in
+    pub segment: u32,
+
+    /// Process ID of the thread
+    pub pid: i32,
+
+    /// Thread ID of the thread
+    pub tid: i32,
+
+    /// Number of events in the record
+    pub events: u32,
+
+    /// Mask of the kinds of the events, see [`Kind::bit`]
+    pub kinds: u8,
+
+    /// Lowest and highest PC of the events, the lowest is above the highest
+    /// if no event has a PC
+    pub pcs: (u64, u64),
+
+    /// Lowest address and end of the highest address range of memory
+    /// accessed or mapped, the lowest is above the end if there is none
+    pub addrs: (u64, u64),
+}
+
+/// Summaries of the events records of a capture, see the module documentation
+pub struct Index {
+    /// Size of the capture when the index was built
+    capture_len: u64,
+
+    /// Summaries of the records, in the order they are in the capture
+    summaries: Vec<Summary>,
+}
+
+impl Index {
+    /// Get the path of the index of the capture at `capture`
+    pub fn path_for(capture: impl AsRef<Path>) -> PathBuf {
+        let mut path = capture.as_ref().as_os_str().to_owned();
+        path.push(".");
+        path.push(INDEX_EXTENSION);
+        path.into()
+    }
+
+    /// Build the index of the capture at `capture`
+    pub fn build(capture: impl AsRef<Path>) -> std::io::Result<Self> {
+        let capture_len = std::fs::metadata(&capture)?.len();
+        let mut reader  = CaptureReader::open(capture)?;
+        let mut summaries = Vec::new();
+        let mut segment = 0;
+
+        loop {
+            let offset = reader.offset();
+            match reader.next_record()? {
+                None => break,
+                Some(Record::Segment(x)) => segment = x.index,
+                Some(Record::Events { pid, tid, events }) => {
+                    let mut summary = Summary {
+                        events: events.len() as u32,
+                        kinds:  0,
+                        pcs:    (u64::MAX, 0),
+                        addrs:  (u64::MAX, 0),
+                        offset, segment, pid, tid,
+                    };
+                    for event in &events {
+                        summary.kinds |= Kind::mask(event);
+                        if let Some(pc) = event_pc(event) {
+                            summary.pcs.0 = summary.pcs.0.min(pc);
+                            summary.pcs.1 = summary.pcs.1.max(pc);
+                        }
+                        if let Some(addrs) = event_addrs(event) {
+                            summary.addrs.0 = summary.addrs.0.min(addrs.start);
+                            summary.addrs.1 = summary.addrs.1.max(addrs.end);
+                        }
+                    }
+                    summaries.push(summary);
+                }
+            }
+        }
+
+        Ok(Self { capture_len, summaries })
+    }
+
+    /// Load the index of the capture at `capture`, building it if it doesn't
+    /// exist or is out of date. The index is saved if it was built, failing
+    /// to save it isn't an error
+    pub fn open(capture: impl AsRef<Path>) -> std::io::Result<Self> {
+        let capture = capture.as_ref();
+        let path    = Self::path_for(capture);
+        let len     = std::fs::metadata(capture)?.len();
+        if let Ok(index) = Self::load(&path) {
+            if index.capture_len == len {
+                return Ok(index);
+            }
+        }
+
+        let index = Self::build(capture)?;
+        let _ = index.save(&path);
+        Ok(index)
+    }
+
+    /// Load an index from `path`
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let mut file = BufReader::new(File::open(path)?);
+        let mut header = [0u8; 20];
+        file.read_exact(&mut header)?;
+        if &header[..8] != INDEX_MAGIC ||
+                header[8..12] != INDEX_VERSION.to_le_bytes() {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData,
+                "Not an index of the current version"));
+        }
+        let capture_len = u64::from_le_bytes(header[12..].try_into().unwrap());
+
+        let mut summaries = Vec::new();
+        let mut entry = [0u8; 57];
+        loop {
+            match file.read_exact(&mut entry) {
+                Ok(()) => {}
+                Err(err) if err.kind() ==
+                    std::io::ErrorKind::UnexpectedEof => break,
+                Err(err) => return Err(err),
+            }
+            let u64 = |x: usize| {
+                u64::from_le_bytes(entry[x..x + 8].try_into().unwrap())
+            };
+            let u32 = |x: usize| {
+                u32::from_le_bytes(entry[x..x + 4].try_into().unwrap())
+            };
+            summaries.push(Summary {
+                offset:  u64(0),
+                segment: u32(8),
+                pid:     u32(12) as i32,
+                tid:     u32(16) as i32,
+                events:  u32(20),
+                kinds:   entry[24],
+                pcs:     (u64(25), u64(33)),
+                addrs:   (u64(41), u64(49)),
+            });
+        }
+
+        Ok(Self { capture_len, summaries })
+    }
+
+    /// Save the index to `path`
+    pub fn save(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(INDEX_MAGIC)?;
+        file.write_all(&INDEX_VERSION.to_le_bytes())?;
+        file.write_all(&self.capture_len.to_le_bytes())?;
+        for x in &self.summaries {
+            file.write_all(&x.offset.to_le_bytes())?;
+            file.write_all(&x.segment.to_le_bytes())?;
+            file.write_all(&x.pid.to_le_bytes())?;
+            file.write_all(&x.tid.to_le_bytes())?;
+            file.write_all(&x.events.to_le_bytes())?;
+            file.write_all(&[x.kinds])?;
+            file.write_all(&x.pcs.0.to_le_bytes())?;
+            file.write_all(&x.pcs.1.to_le_bytes())?;
+            file.write_all(&x.addrs.0.to_le_bytes())?;
+            file.write_all(&x.addrs.1.to_le_bytes())?;
+        }
+        file.flush()
+    }
+
+    /// Get the summaries of the events records, in capture order
+    pub fn summaries(&self) -> &[Summary] {
+        &self.summaries
+    }
+}
+
+/// An event which matched a [`Query`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Match {
+    /// Segment the event is in
+    pub segment: u32,
+
+    /// Process ID of the thread
+    pub pid: i32,
+
+    /// Thread ID of the thread
+    pub tid: i32,
+
+    /// Number of events of the thread in the segment before this one
+    pub ordinal: u64,
+
+    /// The event
+    pub event: Event,
+
+    /// Module the PC of the event is in and its offset in the module, if
+    /// known
+    pub module: Option<(Arc<str>, u64)>,
+}
+
+/// State of a search
+struct Search<'a> {
+    /// The query
+    query: &'a Query,
+
+    /// Mappings of every process, keyed by segment and PID
+    spaces: HashMap<(u32, i32), AddressSpace>,
+
+    /// Range of the symbol of [`Condition::Symbol`] in each module, `None`
+    /// if the module doesn't have it
+    symbols: HashMap<Arc<str>, Option<Range<u64>>>,
+
+    /// Number of events of every thread so far, keyed by segment and TID
+    ordinals: HashMap<(u32, i32), u64>,
+}
+
+impl Search<'_> {
+    /// Find the range of symbol `name` in the module at `path`
+    fn symbol(&mut self, path: &Arc<str>, name: &str) -> Option<Range<u64>> {
+        self.symbols.entry(path.clone()).or_insert_with(|| {
+            let table   = SymbolTable::from_elf(&**path).ok()?;
+            let symbols = table.symbols();
+            let idx     = symbols.iter().position(|x| {
+                &*x.name == name || &**x.raw_name() == name
+            })?;
+            let start   = symbols[idx].addr;
+            let end     = match symbols[idx].size {
+                Some(size) => start.saturating_add(size),
+                None => symbols[idx..].iter().map(|x| x.addr)
+                    .find(|x| *x > start)
+                    .unwrap_or(start.saturating_add(1)),
+            };
+            Some(start..end)
+        }).clone()
+    }
+
+    /// Check a single condition for `event`
+    fn check(&mut self, condition: &Condition, segment: u32, pid: i32,
+            tid: i32, event: &Event) -> bool {
+        let space = self.spaces.get(&(segment, pid));
+        let pc_module = event_pc(event)
+            .and_then(|pc| Some((pc, space?.module_offset(pc)?)));
+
+        match condition {
+            Condition::Kinds(mask)  => Kind::mask(event) & mask != 0,
+            Condition::Pc(range)    => event_pc(event)
+                .is_some_and(|x| range.contains(&x)),
+            Condition::Addr(range)  => event_addrs(event)
+                .is_some_and(|x| overlaps(range, &x)),
+            Condition::Value(value) => has_value(event, *value),
+            Condition::Pid(x)     => pid == *x,
+            Condition::Tid(x)     => tid == *x,
+            Condition::Segment(x) => segment == *x,
+            Condition::Module(name) => pc_module
+                .is_some_and(|(_, (path, _))| is_module(&path, name)),
+            Condition::AddrModule(name) => {
+                let Some(addrs) = event_addrs(event) else { return false };
+                space.and_then(|x| x.lookup(addrs.start))
+                    .is_some_and(|x| !x.anon && is_module(&x.path, name))
+            }
+            Condition::Symbol { module, name } => {
+                let Some((pc, (path, offset))) = pc_module else {
+                    return false;
+                };
+                if module.as_ref().is_some_and(|x| !is_module(&path, x)) {
+                    return false;
+                }
+                let Some(range) = self.symbol(&path, name) else {
+                    return false;
+                };
+
+                // Symbols of PIEs and shared libraries are relative to the
+                // image, other binaries are loaded where their symbols say
+                let base = pc - offset;
+                range.contains(if range.start >= base { &pc } else { &offset })
+            }
+        }
+    }
+
+    /// Check if `event` matches the query, tracking the mappings
+    fn matches(&mut self, segment: u32, pid: i32, tid: i32, event: &Event)
+            -> bool {
+        if self.query.needs_mappings() {
+            let space = self.spaces.entry((segment, pid)).or_default();
+            match event {
+                Event::Mmap { base, len, anon, read, write, exec, path,
+                        offset } => {
+                    space.mmap(*base, *len, *anon, *read, *write, *exec,
+                        path, *offset);
+                }
+                Event::Munmap { base, len } => space.munmap(*base, *len),
+                _ => {}
+            }
+        }
+
+        self.query.conditions.iter()
+            .all(|x| self.check(x, segment, pid, tid, event))
+    }
+}
+
+/// Search the capture at `capture` for events matching `query`, in capture
+/// order, invoking `found` with every match until it returns `false`.
+/// Records which can't match are skipped with `index` if one is given
+pub fn search(capture: impl AsRef<Path>, query: &Query, index: Option<&Index>,
+        mut found: impl FnMut(Match) -> bool) -> std::io::Result<()> {
+    let mut reader = CaptureReader::open(capture)?;
+    let mut search = Search {
+        spaces:   HashMap::new(),
+        symbols:  HashMap::new(),
+        ordinals: HashMap::new(),
+        query,
+    };
+    let mappings = Kind::Mmap.bit() | Kind::Munmap.bit();
+
+    // Without an index every record is read in order
+    let mut summaries = index.map(|x| x.summaries().iter());
+    let mut segment = 0;
+    loop {
+        if let Some(summaries) = &mut summaries {
+            let Some(summary) = summaries.next() else { break };
+            let needed = query.may_match(summary) ||
+                (query.needs_mappings() && summary.kinds & mappings != 0);
+            if !needed {
+                *search.ordinals.entry((summary.segment, summary.tid))
+                    .or_default() += summary.events as u64;
+                continue;
+            }
+            segment = summary.segment;
+            reader.seek(summary.offset)?;
+        }
+
+        let (pid, tid, events) = match reader.next_record()? {
+            None => break,
+            Some(Record::Segment(x)) => {
+                segment = x.index;
+                continue;
+            }
+            Some(Record::Events { pid, tid, events }) => (pid, tid, events),
+        };
+
+        for event in events {
+            let ordinal = search.ordinals.entry((segment, tid)).or_default();
+            *ordinal += 1;
+            let ordinal = *ordinal - 1;
+
+            if !search.matches(segment, pid, tid, &event) {
+                continue;
+            }
+            let module = event_pc(&event).and_then(|pc| {
+                search.spaces.get(&(segment, pid))?.module_offset(pc)
+            });
+            if !found(Match { segment, pid, tid, ordinal, event, module }) {
+                return Ok(());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn grep_capture() {
+    use crate::capture::CaptureWriter;
+
+    let path = std::env::temp_dir()
+        .join(format!("cannoli_grep_{}", std::process::id()));
+    let mut writer = CaptureWriter::create(&path, None).unwrap();
+    writer.write_events(1, 1, &[
+        Event::Mmap {
+            base: 0x400000, len: 0x1000, anon: false, read: true,
+            write: false, exec: true, path: "/usr/lib/libssl.so.3".into(),
+            offset: 0,
+        },
+        Event::Exec  { pc: 0x400010 },
+        Event::Write { pc: 0x400010, addr: 0x8000, val: 0x41414141, sz: 4 },
+    ]).unwrap();
+    writer.write_events(1, 2, &[
+        Event::Exec  { pc: 0x500000 },
+        Event::Read  { pc: 0x500000, addr: 0x400800, val: 0, sz: 8 },
+        Event::Write { pc: 0x500000, addr: 0x9000, val: 0x41414141, sz: 4 },
+    ]).unwrap();
+    drop(writer);
+
+    let grep = |terms: &[&str], index: Option<&Index>| {
+        let mut matches = Vec::new();
+        search(&path, &Query::parse(terms).unwrap(), index, |x| {
+            matches.push((x.tid, x.ordinal));
+            true
+        }).unwrap();
+        matches
+    };
+
+    let index = Index::build(&path).unwrap();
+    assert_eq!(index.summaries().len(), 2);
+    for index in [None, Some(&index)] {
+        assert_eq!(grep(&["write", "value=0x41414141"], index),
+            [(1, 2), (2, 2)]);
+        assert_eq!(grep(&["module=libssl"], index), [(1, 1), (1, 2)]);
+        assert_eq!(grep(&["read", "addr-module=libssl"], index), [(2, 1)]);
+        assert_eq!(grep(&["pc=0x500000", "tid=2", "exec"], index), [(2, 0)]);
+        assert_eq!(grep(&["addr=0x9002-0x9010"], index), [(2, 2)]);
+    }
+    assert!(Query::parse(&["bogus"]).is_err());
+
+    // The index survives a round trip
+    let saved = Index::path_for(&path);
+    index.save(&saved).unwrap();
+    assert_eq!(Index::load(&saved).unwrap().summaries(), index.summaries());
+    std::fs::remove_file(&saved).unwrap();
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn filter_macro() {
+    let mut space = AddressSpace::new();
+    space.mmap(0x400000, 0x1000, false, true, false, true,
+        "/usr/lib/libssl.so.3", 0);
+    let write = Event::Write { pc: 0x400010, addr: 0x8000, val: 0x41, sz: 4 };
+    let read  = Event::Read  { pc: 0x500000, addr: 0x400800, val: 0, sz: 8 };
+
+    let filter = crate::filter!(write | cmp, value == 0x41);
+    assert!(filter.matches(&write) && !filter.matches(&read));
+    let filter = crate::filter!(pc in 0x400000..0x401000,
+        addr in 0x7ffc..0x8001,);
+    assert!(filter.matches(&write) && !filter.matches(&read));
+
+    // Modules only match with the mappings
+    let filter = crate::filter!(module == "libssl");
+    assert!(!filter.matches(&write) && filter.matches_in(&space, &write));
+    let filter = crate::filter!(read, addr_module == "libssl");
+    assert!(filter.matches_in(&space, &read));
+    assert!(!filter.matches_in(&space, &write));
+    assert!(crate::filter!().matches(&read));
+}
@@ -0,0 +1,227 @@
+//! Cached reads of guest memory through the control channel
+//!
+//! Events only carry the values of the memory accesses the target made.
+//! Analyses which want structured data at a point of interest (the fields
+//! of a struct, the nodes of a linked list) can read the rest through the
+//! control channel with a [`GuestMemory`] kept in their context:
+//!
+//! ```no_run
+//! # fn f(ci: &cannoli::ClientInfo, list: u64) -> std::io::Result<()> {
+//! use cannoli::guest::GuestMemory;
+//!
+//! let mut mem = GuestMemory::new(ci);
+//!
+//! // `list->head->next->value`, with `head` at 0x0, `next` at 0x8 and
+//! // `value` at 0x10
+//! let value = mem.deref_chain(list, &[0x8, 0x10])?;
+//! let value = mem.read_u32(value)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Reads go to the target process as it is now, which is usually ahead of
+//! the events being processed, as the jitter doesn't wait for the client.
+//! The data is only consistent with the trace when the target is stalled
+//! (eg. on a full pipe) or the memory doesn't change. Memory is fetched a
+//! page at a time and cached until [`GuestMemory::invalidate`], so walking a
+//! structure costs a round trip per page it touches rather than per field.
+//!
+//! The jitter finds guest memory in QEMU's address space through QEMU's
+//! `guest_base`, which `qemu-rs` exports by linking QEMU with `-rdynamic`.
+//! Reads fail with a QEMU which doesn't export it, rather than reading the
+//! wrong memory of guests which aren't mapped at their own addresses (eg.
+//! 32-bit guests on 64-bit hosts).
+
+use std::io::{Error, ErrorKind, Result};
+use std::time::Duration;
+use std::collections::HashMap;
+use crate::ClientInfo;
+use crate::control;
+
+/// Size of the pages memory is fetched and cached in
+const PAGE_SIZE: u64 = 4096;
+
+/// How long to wait for the jitter to answer a read by default
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Cached reader of the memory of a target process, see the module
+/// documentation
+#[derive(Clone, Debug)]
+pub struct GuestMemory {
+    /// Process ID of the target
+    pid: i32,
+
+    /// Thread ID of the thread whose connection the reads are sent over
+    tid: i32,
+
+    /// Size of a guest pointer in bytes
+    pointer_size: usize,
+
+    /// Is the target big endian?
+    big_endian: bool,
+
+    /// How long to wait for a read
+    timeout: Duration,
+
+    /// Pages read so far, keyed by address. `None` for pages which aren't
+    /// mapped readable
+    pages: HashMap<u64, Option<Box<[u8]>>>,
+}
+
+impl GuestMemory {
+    /// Create a reader of the memory of the target process of `ci`, which
+    /// reads through the connection of its thread
+    pub fn new(ci: &ClientInfo) -> Self {
+        Self {
+            pid:          ci.pid,
+            tid:          ci.tid,
+            pointer_size: ci.arch.bitness() as usize / 8,
+            big_endian:   ci.big_endian,
+            timeout:      DEFAULT_TIMEOUT,
+            pages:        HashMap::new(),
+        }
+    }
+
+    /// Wait at most `timeout` for the jitter to answer a read
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Forget every page read so far, so the next reads see memory as it is
+    /// now
+    pub fn invalidate(&mut self) {
+        self.pages.clear();
+    }
+
+    /// Get the page at `page`, reading it if it isn't cached yet
+    fn page(&mut self, page: u64) -> Result<&[u8]> {
+        if !self.pages.contains_key(&page) {
+            // Only a missing page is cached, other failures may be
+            // temporary
+            let data = match control::read_memory(self.pid, self.tid, page,
+                    PAGE_SIZE as u32, self.timeout) {
+                Ok(data) => Some(data.into_boxed_slice()),
+                Err(err) if err.kind() == ErrorKind::InvalidData => None,
+                Err(err) => return Err(err),
+            };
+            self.pages.insert(page, data);
+        }
+
+        self.pages[&page].as_deref().ok_or_else(|| Error::new(
+            ErrorKind::InvalidData,
+            format!("Guest memory at {page:#x} isn't readable")))
+    }
+
+    /// Fill `buf` with the guest memory at `addr`
+    pub fn read(&mut self, addr: u64, buf: &mut [u8]) -> Result<()> {
+        let mut done = 0;
+        while done < buf.len() {
+            let addr   = addr.wrapping_add(done as u64);
+            let offset = (addr % PAGE_SIZE) as usize;
+            let page   = self.page(addr - offset as u64)?;
+            let len    = (buf.len() - done).min(page.len() - offset);
+            buf[done..done + len].copy_from_slice(&page[offset..][..len]);
+            done += len;
+        }
+        Ok(())
+    }
+
+    /// Read an unsigned integer of `size` bytes in the target's byte order
+    fn read_uint(&mut self, addr: u64, size: usize) -> Result<u64> {
+        let mut bytes = [0u8; 8];
+        if self.big_endian {
+            self.read(addr, &mut bytes[8 - size..])?;
+            Ok(u64::from_be_bytes(bytes))
+        } else {
+            self.read(addr, &mut bytes[..size])?;
+            Ok(u64::from_le_bytes(bytes))
+        }
+    }
+
+    /// Read a `u8` from guest memory
+    pub fn read_u8(&mut self, addr: u64) -> Result<u8> {
+        Ok(self.read_uint(addr, 1)? as u8)
+    }
+
+    /// Read a `u16` in the target's byte order from guest memory
+    pub fn read_u16(&mut self, addr: u64) -> Result<u16> {
+        Ok(self.read_uint(addr, 2)? as u16)
+    }
+
+    /// Read a `u32` in the target's byte order from guest memory
+    pub fn read_u32(&mut self, addr: u64) -> Result<u32> {
+        Ok(self.read_uint(addr, 4)? as u32)
+    }
+
+    /// Read a `u64` in the target's byte order from guest memory
+    pub fn read_u64(&mut self, addr: u64) -> Result<u64> {
+        self.read_uint(addr, 8)
+    }
+
+    /// Read a pointer of the target's size and byte order from guest memory
+    pub fn read_ptr(&mut self, addr: u64) -> Result<u64> {
+        self.read_uint(addr, self.pointer_size)
+    }
+
+    /// Read a NUL-terminated string of at most `max` bytes from guest
+    /// memory, without the terminator
+    pub fn read_cstr(&mut self, addr: u64, max: usize) -> Result<Vec<u8>> {
+        let mut ret = Vec::new();
+        while ret.len() < max {
+            match self.read_u8(addr.wrapping_add(ret.len() as u64))? {
+                0 => break,
+                byte => ret.push(byte),
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Follow a chain of pointers: for every offset, load the pointer at the
+    /// current address and add the offset to it. Returns the final address,
+    /// so `deref_chain(addr, &[a, b])` is `*(*addr + a) + b`. Fails on a
+    /// NULL pointer or unreadable memory along the way
+    pub fn deref_chain(&mut self, addr: u64, offsets: &[i64])
+            -> Result<u64> {
+        let mut addr = addr;
+        for &offset in offsets {
+            let ptr = self.read_ptr(addr)?;
+            if ptr == 0 {
+                return Err(Error::new(ErrorKind::InvalidData,
+                    format!("NULL pointer at {addr:#x}")));
+            }
+            addr = ptr.wrapping_add_signed(offset);
+        }
+        Ok(addr)
+    }
+}
+
+#[test]
+fn deref_cached() {
+    use crate::Architecture;
+
+    // Disconnected, so everything comes from the pre-filled cache
+    let mut mem = GuestMemory {
+        pid:          -1,
+        tid:          -1,
+        pointer_size: Architecture::X86_64.bitness() as usize / 8,
+        big_endian:   false,
+        timeout:      Duration::ZERO,
+        pages:        HashMap::new(),
+    };
+    let mut page = vec![0u8; PAGE_SIZE as usize];
+    page[0x10..0x18].copy_from_slice(&0x1ff8u64.to_le_bytes());
+    mem.pages.insert(0x1000, Some(page.into_boxed_slice()));
+    let mut page = vec![0u8; PAGE_SIZE as usize];
+    page[..4].copy_from_slice(b"ok\0\0");
+    mem.pages.insert(0x2000, Some(page.into_boxed_slice()));
+    mem.pages.insert(0x3000, None);
+
+    // A pointer read across a page boundary
+    assert_eq!(mem.read_ptr(0x1ffc).unwrap(), 0x6b6f_0000_0000);
+    assert_eq!(mem.deref_chain(0x1010, &[0x8]).unwrap(), 0x2000);
+    assert_eq!(mem.read_cstr(0x2000, 16).unwrap(), b"ok");
+    assert!(mem.deref_chain(0x1000, &[0x8]).is_err());
+    assert!(mem.read_u8(0x3000).is_err());
+    assert!(mem.read_u8(0x4000).is_err());
+}
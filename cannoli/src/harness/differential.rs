@@ -0,0 +1,305 @@
+//! Differential testing of two versions of a target
+//!
+//! Running a patched and an unpatched build of a target with identical
+//! inputs and finding the first place where they behave differently tells
+//! whether (and where) the patch changed anything observable. [`Differential`]
+//! compares the captures of two such runs and reports the first
+//! architectural [`Divergence`]: an instruction executed at a different PC,
+//! or a store of a different value or to a different address.
+//!
+//! Both captures are put in canonical form first (see [`crate::canon`]), so
+//! ASLR and allocator placement don't count as divergences. The guest
+//! binaries of the two runs are different files, so addresses in them are
+//! rendered as `guest+0x1234` for both. Only the main thread (the first
+//! thread of the first process in the capture) is compared, other threads
+//! interleave however they were scheduled.
+//!
+//! Cannoli only traces QEMU's user-mode emulation, which has no snapshots,
+//! so both runs start from the beginning of the process. Code the two builds
+//! run identically but at different offsets (eg. startup code, when the
+//! patch moved things around) can be skipped by starting the comparison at
+//! a sync point in each binary, usually the same function in both, with
+//! [`Differential::start_at`]. PCs in the guest binary at or after the sync
+//! point are then rendered relative to it, as `sync+0x10`, so code which
+//! the patch shifted by the same amount in both builds still compares
+//! equal.
+
+use std::fmt;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::collections::VecDeque;
+use crate::canon::{Canonicalizer, ProcessLabels};
+use crate::capture::{CaptureReader, Record};
+use crate::event::Event;
+
+/// Name the guest binaries are rendered as
+const GUEST: &str = "guest";
+
+/// The first difference between two runs
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Divergence {
+    /// Number of compared events (executed instructions and stores) the
+    /// runs had in common before diverging
+    pub index: u64,
+
+    /// Event of the first run, `None` if it ended here
+    pub a: Option<String>,
+
+    /// Event of the second run, `None` if it ended here
+    pub b: Option<String>,
+
+    /// Common events leading up to the divergence, oldest first
+    pub context: Vec<String>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "runs diverge after {} events", self.index)?;
+        for line in &self.context {
+            writeln!(f, "  {line}")?;
+        }
+        writeln!(f, "- {}", self.a.as_deref().unwrap_or("(end of run)"))?;
+        write!(f, "+ {}", self.b.as_deref().unwrap_or("(end of run)"))
+    }
+}
+
+/// One side of the comparison, turning events into canonical lines
+struct Side {
+    /// Renders the events
+    canon: Canonicalizer,
+
+    /// Prefix of addresses in the guest binary, as rendered
+    guest: String,
+
+    /// Offset of the sync point in the guest binary
+    start: Option<u64>,
+
+    /// Set once the sync point was reached, or if there is none
+    synced: bool,
+
+    /// Scratch buffer for rendering
+    rendered: String,
+}
+
+impl Side {
+    /// Create a side for the guest binary named `guest`
+    fn new(guest: &str, start: Option<u64>) -> Self {
+        let labels = Arc::new(Mutex::new(ProcessLabels::new()));
+        Self {
+            canon:    Canonicalizer::new(labels),
+            guest:    format!("{guest}+"),
+            synced:   start.is_none(),
+            start,
+            rendered: String::new(),
+        }
+    }
+
+    /// Render `event`, `None` if it isn't compared
+    fn line(&mut self, event: &Event) -> Option<String> {
+        self.rendered.clear();
+        self.canon.render(event, &mut self.rendered);
+        let line = self.rendered.trim_end();
+        if !line.starts_with("exec ") && !line.starts_with("write ") {
+            return None;
+        }
+
+        let line = line.replace(&self.guest, &format!("{GUEST}+"));
+
+        // Rebase the PC, the second field, onto the sync point
+        let (kind, rest) = line.split_once(' ')?;
+        let (pc, rest) = rest.split_once(' ').unwrap_or((rest, ""));
+        let offset = pc.strip_prefix(GUEST)
+            .and_then(|x| x.strip_prefix("+0x"))
+            .and_then(|x| u64::from_str_radix(x, 16).ok());
+        let pc = match (offset, self.start) {
+            (Some(offset), Some(start)) if offset >= start => {
+                self.synced |= offset == start && kind == "exec";
+                format!("sync+{:#x}", offset - start)
+            }
+            _ => pc.to_string(),
+        };
+        if !self.synced {
+            return None;
+        }
+        Some(format!("{kind} {pc} {rest}").trim_end().to_string())
+    }
+
+    /// Get the next compared line of `events`
+    fn next(&mut self,
+            events: &mut impl Iterator<Item = std::io::Result<Event>>)
+            -> std::io::Result<Option<String>> {
+        for event in events {
+            if let Some(line) = self.line(&event?) {
+                return Ok(Some(line));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Events of the main thread of the first segment of a capture
+fn main_thread(path: &Path)
+        -> std::io::Result<impl Iterator<Item = std::io::Result<Event>>> {
+    let mut reader  = CaptureReader::open(path)?;
+    let mut thread  = None;
+    let mut pending = VecDeque::new();
+    let mut done    = false;
+    Ok(std::iter::from_fn(move || loop {
+        if let Some(event) = pending.pop_front() {
+            return Some(Ok(event));
+        }
+        if done {
+            return None;
+        }
+
+        match reader.next_record() {
+            Ok(Some(Record::Segment(segment))) => {
+                done = segment.index > 0;
+            }
+            Ok(Some(Record::Events { pid, tid, events })) => {
+                if *thread.get_or_insert((pid, tid)) == (pid, tid) {
+                    pending.extend(events);
+                }
+            }
+            Ok(None) => done = true,
+            Err(err) => {
+                done = true;
+                return Some(Err(err));
+            }
+        }
+    }))
+}
+
+/// Name of the guest of the first segment of the capture at `path`
+fn guest_name(path: &Path) -> std::io::Result<String> {
+    let segments = CaptureReader::segments(path)?;
+    let manifest = segments.first().and_then(|x| x.manifest.as_ref())
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData,
+            format!("{} has no manifest to take the guest from",
+                path.display())))?;
+    Ok(manifest.guest.path.file_name()
+        .map(|x| x.to_string_lossy().into_owned())
+        .unwrap_or_default())
+}
+
+/// Compares two runs, see the module documentation
+#[derive(Clone, Debug)]
+pub struct Differential {
+    /// Offsets into the first and second guest binary to start comparing at
+    start: Option<(u64, u64)>,
+
+    /// Number of common events to report before a divergence
+    context: usize,
+}
+
+impl Default for Differential {
+    fn default() -> Self {
+        Self { start: None, context: 8 }
+    }
+}
+
+impl Differential {
+    /// Create a comparison from the start of the runs, reporting 8 events of
+    /// context
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start comparing once the first run executes offset `a` of its guest
+    /// binary, and the second run executes offset `b` of its guest binary
+    pub fn start_at(mut self, a: u64, b: u64) -> Self {
+        self.start = Some((a, b));
+        self
+    }
+
+    /// Report `count` common events before a divergence
+    pub fn context(mut self, count: usize) -> Self {
+        self.context = count;
+        self
+    }
+
+    /// Compare the events of the main threads of two runs, whose guest
+    /// binaries are named `guest_a` and `guest_b`. `None` if they don't
+    /// diverge
+    pub fn compare<A, B>(&self, guest_a: &str, a: A, guest_b: &str, b: B)
+            -> std::io::Result<Option<Divergence>>
+            where A: IntoIterator<Item = std::io::Result<Event>>,
+                  B: IntoIterator<Item = std::io::Result<Event>> {
+        let mut side_a = Side::new(guest_a, self.start.map(|x| x.0));
+        let mut side_b = Side::new(guest_b, self.start.map(|x| x.1));
+        let (mut a, mut b) = (a.into_iter(), b.into_iter());
+
+        let mut context = VecDeque::with_capacity(self.context + 1);
+        let mut index   = 0;
+        loop {
+            let line_a = side_a.next(&mut a)?;
+            let line_b = side_b.next(&mut b)?;
+            if line_a != line_b {
+                return Ok(Some(Divergence {
+                    index,
+                    a:       line_a,
+                    b:       line_b,
+                    context: context.into(),
+                }));
+            }
+
+            let Some(line) = line_a else {
+                return Ok(None);
+            };
+            context.push_back(line);
+            if context.len() > self.context {
+                context.pop_front();
+            }
+            index += 1;
+        }
+    }
+
+    /// Compare the first segments of the captures at `a` and `b`, which must
+    /// have manifests to take the names of the guest binaries from
+    pub fn compare_captures(&self, a: impl AsRef<Path>, b: impl AsRef<Path>)
+            -> std::io::Result<Option<Divergence>> {
+        let (a, b) = (a.as_ref(), b.as_ref());
+        self.compare(&guest_name(a)?, main_thread(a)?,
+            &guest_name(b)?, main_thread(b)?)
+    }
+}
+
+#[test]
+fn diverge_after_patch() {
+    // Two builds of the same firmware at different addresses, the patched
+    // one has an extra instruction before `check` and stores a different
+    // value
+    let run = |path: &str, base: u64, skew: u64, val: u64| {
+        vec![
+            Event::Mmap {
+                base, len: 0x2000, anon: false, read: true, write: false,
+                exec: true, path: path.into(), offset: 0,
+            },
+            Event::Exec  { pc: base + 0x100 },
+            Event::Exec  { pc: base + 0x104 + skew },
+            Event::Exec  { pc: base + 0x108 + skew },
+            Event::Read  { pc: base + 0x108 + skew, addr: base, val: 7,
+                sz: 4 },
+            Event::Write { pc: base + 0x108 + skew, addr: base + 0x1000,
+                val, sz: 4 },
+        ].into_iter().map(Ok)
+    };
+
+    let diff = Differential::new();
+    assert_eq!(diff.compare("fw_v1", run("/fw_v1", 0x1000, 0, 1),
+        "fw_v1", run("/fw_v1", 0x8000, 0, 1)).unwrap(), None);
+
+    let found = diff.compare("fw_v1", run("/fw_v1", 0x1000, 0, 1),
+        "fw_v2", run("/fw_v2", 0x8000, 4, 2)).unwrap().unwrap();
+    assert_eq!(found.index, 1);
+    assert_eq!(found.a.as_deref(), Some("exec guest+0x104"));
+    assert_eq!(found.b.as_deref(), Some("exec guest+0x108"));
+
+    // Syncing past the moved code finds the different store
+    let found = diff.start_at(0x108, 0x10c)
+        .compare("fw_v1", run("/fw_v1", 0x1000, 0, 1),
+            "fw_v2", run("/fw_v2", 0x8000, 4, 2)).unwrap().unwrap();
+    assert_eq!(found.context, ["exec sync+0x0"]);
+    assert_eq!(found.a.as_deref(), Some("write sync+0x0 guest+0x1000 4 0x1"));
+    assert_eq!(found.b.as_deref(), Some("write sync+0x0 guest+0x1000 4 0x2"));
+}
@@ -0,0 +1,365 @@
+//! Metadata describing how a capture was produced
+//!
+//! A [`RunManifest`] is written next to each capture and records everything
+//! needed to run the target again the same way: hashes of the guest binary,
+//! QEMU and the jitter, the guest's argv, environment and working directory,
+//! and information about the host the capture came from. This turns a
+//! capture into a reproducible artifact rather than a one-off.
+
+#[cfg(feature = "client")]
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+#[cfg(feature = "client")]
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Serialize, Deserialize};
+#[cfg(feature = "client")]
+use sha2::{Digest, Sha256};
+use crate::pin::{PIN_VAR, Pins};
+
+/// Version of the manifest format, bumped on incompatible changes
+pub const MANIFEST_VERSION: u32 = 1;
+
+/// Extension appended to a capture's path to get the path of its manifest
+pub const MANIFEST_EXTENSION: &str = "manifest.json";
+
+/// A file which was used by the run, identified by its contents
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileInfo {
+    /// Absolute path to the file at the time of the run
+    pub path: PathBuf,
+
+    /// SHA-256 of the file, in hex
+    pub sha256: String,
+}
+
+#[cfg(feature = "client")]
+impl FileInfo {
+    /// Hash the file at `path`
+    pub fn new(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let path = std::fs::canonicalize(path)?;
+        Ok(Self { sha256: sha256_file(&path)?, path })
+    }
+
+    /// Check if the file at `path` still has the contents we recorded
+    pub fn matches(&self) -> bool {
+        sha256_file(&self.path).is_ok_and(|x| x == self.sha256)
+    }
+}
+
+/// An input file the guest reads
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputFile {
+    /// The input
+    pub file: FileInfo,
+
+    /// Where the input is copied to before running, for guests which read
+    /// their input from a fixed location
+    pub copy_to: Option<PathBuf>,
+}
+
+/// Information about the machine a capture was taken on
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HostInfo {
+    /// Hostname
+    pub hostname: String,
+
+    /// Kernel release, eg. `6.1.0-13-amd64`
+    pub kernel: String,
+
+    /// Host architecture, eg. `x86_64`
+    pub arch: String,
+
+    /// Number of CPUs available
+    pub cpus: usize,
+}
+
+impl HostInfo {
+    /// Get information about the current host
+    pub fn current() -> Self {
+        let read = |path: &str| std::fs::read_to_string(path)
+            .map(|x| x.trim().to_string())
+            .unwrap_or_default();
+
+        Self {
+            hostname: read("/proc/sys/kernel/hostname"),
+            kernel:   read("/proc/sys/kernel/osrelease"),
+            arch:     std::env::consts::ARCH.to_string(),
+            cpus:     std::thread::available_parallelism()
+                .map_or(1, |x| x.get()),
+        }
+    }
+}
+
+/// Everything needed to reproduce a run of a target under Cannoli
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunManifest {
+    /// Version of the manifest format, [`MANIFEST_VERSION`]
+    pub version: u32,
+
+    /// Version of cannoli which produced the capture
+    pub cannoli_version: String,
+
+    /// Time the run started, in seconds since the Unix epoch
+    pub started: u64,
+
+    /// Guest binary
+    pub guest: FileInfo,
+
+    /// Arguments passed to the guest, not including the binary itself
+    pub argv: Vec<String>,
+
+    /// Complete environment of the guest
+    pub env: Vec<(String, String)>,
+
+    /// Working directory of the guest
+    pub cwd: PathBuf,
+
+    /// QEMU binary
+    pub qemu: FileInfo,
+
+    /// First line of `qemu -version`
+    pub qemu_version: String,
+
+    /// Additional arguments passed to QEMU, before the guest binary
+    pub qemu_args: Vec<String>,
+
+    /// Jitter shared object passed to QEMU with `-cannoli`, this holds the
+    /// hooks and thus decides what gets traced
+    pub jitter: FileInfo,
+
+    /// Host the run happened on
+    pub host: HostInfo,
+
+    /// Input files of the run
+    #[serde(default)]
+    pub inputs: Vec<InputFile>,
+}
+
+/// Hash a file with SHA-256, returning the hex digest
+#[cfg(feature = "client")]
+fn sha256_file(path: &Path) -> std::io::Result<String> {
+    let mut file   = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf    = vec![0u8; 1024 * 1024];
+
+    loop {
+        let bytes = file.read(&mut buf)?;
+        if bytes == 0 {
+            break;
+        }
+        hasher.update(&buf[..bytes]);
+    }
+
+    Ok(hasher.finalize().iter().map(|x| format!("{x:02x}")).collect())
+}
+
+/// Get the version string reported by a QEMU binary
+#[cfg(feature = "client")]
+fn qemu_version(qemu: &Path) -> String {
+    Command::new(qemu).arg("-version").output().ok()
+        .and_then(|x| {
+            String::from_utf8_lossy(&x.stdout).lines().next()
+                .map(|x| x.to_string())
+        })
+        .unwrap_or_default()
+}
+
+impl RunManifest {
+    /// Create a manifest for running `guest` with `argv` under `qemu` with
+    /// the `jitter` hooks. The environment and working directory are taken
+    /// from the current process
+    #[cfg(feature = "client")]
+    pub fn new(qemu: impl AsRef<Path>, jitter: impl AsRef<Path>,
+            guest: impl AsRef<Path>, argv: &[String])
+            -> std::io::Result<Self> {
+        let qemu = FileInfo::new(qemu)?;
+
+        Ok(Self {
+            version:         MANIFEST_VERSION,
+            cannoli_version: env!("CARGO_PKG_VERSION").to_string(),
+            started:         SystemTime::now().duration_since(UNIX_EPOCH)
+                .map_or(0, |x| x.as_secs()),
+            guest:           FileInfo::new(guest)?,
+            argv:            argv.to_vec(),
+            env:             std::env::vars().collect(),
+            cwd:             std::env::current_dir()?,
+            qemu_version:    qemu_version(&qemu.path),
+            qemu_args:       Vec::new(),
+            jitter:          FileInfo::new(jitter)?,
+            host:            HostInfo::current(),
+            inputs:          Vec::new(),
+            qemu,
+        })
+    }
+
+    /// Make the guest's random numbers the same in every run, seeded with
+    /// `seed`. `getrandom()` is pinned by the jitter (see [`crate::pin`]),
+    /// and QEMU's `-seed` covers the `AT_RANDOM` bytes glibc derives its
+    /// stack protector and pointer guard from. Reads of `/dev/urandom`
+    /// aren't covered
+    pub fn deterministic_rng(&mut self, seed: u64) {
+        if let Some(pos) = self.qemu_args.iter().position(|x| x == "-seed") {
+            self.qemu_args.drain(pos..(pos + 2).min(self.qemu_args.len()));
+        }
+        self.qemu_args.extend(["-seed".to_string(), seed.to_string()]);
+        self.pin(Pins { random: Some(seed), ..self.pins() });
+    }
+
+    /// Make the guest's time the same in every run, starting at `nanos`
+    /// nanoseconds since the Unix epoch and advancing by
+    /// [`crate::pin::DEFAULT_STEP`] on every read, see [`crate::pin`]
+    pub fn deterministic_time(&mut self, nanos: u64) {
+        self.pin(Pins { time: Some(nanos), ..self.pins() });
+    }
+
+    /// Pin the system calls of `pins` in the run, see [`crate::pin`]. They
+    /// are passed to the jitter in QEMU's environment, which QEMU is told
+    /// to leave out of the guest's
+    pub fn pin(&mut self, pins: Pins) {
+        self.env.retain(|(x, _)| x != PIN_VAR);
+        self.env.push((PIN_VAR.to_string(), pins.to_string()));
+        if !self.qemu_args.windows(2).any(|x| x == ["-U", PIN_VAR]) {
+            self.qemu_args.extend(["-U".to_string(), PIN_VAR.to_string()]);
+        }
+    }
+
+    /// Get what is pinned in the run, see [`RunManifest::pin`]
+    pub fn pins(&self) -> Pins {
+        self.env.iter().find(|(x, _)| x == PIN_VAR)
+            .and_then(|(_, x)| Pins::parse(x))
+            .unwrap_or_default()
+    }
+
+    /// Get the seed of the guest's random numbers, if they were made
+    /// deterministic with [`RunManifest::deterministic_rng`]
+    pub fn rng_seed(&self) -> Option<u64> {
+        let pos = self.qemu_args.iter().position(|x| x == "-seed")?;
+        self.qemu_args.get(pos + 1)?.parse().ok()
+    }
+
+    /// Get the path of the manifest for the capture at `capture`
+    pub fn path_for(capture: impl AsRef<Path>) -> PathBuf {
+        let mut path = capture.as_ref().as_os_str().to_owned();
+        path.push(".");
+        path.push(MANIFEST_EXTENSION);
+        path.into()
+    }
+
+    /// Load a manifest from `path`
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let manifest: Self = serde_json::from_slice(&std::fs::read(path)?)?;
+        if manifest.version != MANIFEST_VERSION {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData,
+                format!("Unsupported manifest version {}", manifest.version)));
+        }
+        Ok(manifest)
+    }
+
+    /// Save the manifest to `path`
+    pub fn save(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        std::fs::write(path, serde_json::to_vec_pretty(self)?)
+    }
+
+    /// Get a description of every file which no longer matches what was
+    /// used for the run, an empty list means the run can be reproduced
+    /// exactly
+    #[cfg(feature = "client")]
+    pub fn verify(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        for (name, file) in [("guest",  &self.guest),
+                             ("qemu",   &self.qemu),
+                             ("jitter", &self.jitter)] {
+            if !file.matches() {
+                problems.push(format!("{name} {} has changed or is missing",
+                    file.path.display()));
+            }
+        }
+
+        for input in &self.inputs {
+            if !input.file.matches() {
+                problems.push(format!("input {} has changed or is missing",
+                    input.file.path.display()));
+            }
+        }
+
+        if qemu_version(&self.qemu.path) != self.qemu_version {
+            problems.push(format!("qemu no longer reports version `{}`",
+                self.qemu_version));
+        }
+
+        problems
+    }
+
+    /// Copy the inputs which the guest reads from a fixed location into
+    /// place, this must be done before running the [`RunManifest::command`]
+    pub fn stage_inputs(&self) -> std::io::Result<()> {
+        for input in &self.inputs {
+            if let Some(dest) = &input.copy_to {
+                if let Some(parent) = dest.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                std::fs::copy(&input.file.path, dest)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Build the command which runs the target exactly as recorded
+    pub fn command(&self) -> Command {
+        let mut command = Command::new(&self.qemu.path);
+        command.arg("-cannoli").arg(&self.jitter.path)
+            .args(&self.qemu_args)
+            .arg(&self.guest.path)
+            .args(&self.argv)
+            .env_clear()
+            .envs(self.env.iter().map(|(k, v)| (k, v)))
+            .current_dir(&self.cwd);
+        command
+    }
+}
+
+#[test]
+#[cfg(feature = "client")]
+fn manifest_roundtrip() {
+    let exe = FileInfo::new(std::env::current_exe().unwrap()).unwrap();
+    assert!(exe.matches());
+
+    let manifest = RunManifest {
+        version:         MANIFEST_VERSION,
+        cannoli_version: "0.1.0".into(),
+        started:         0,
+        guest:           exe.clone(),
+        argv:            vec!["--flag".into()],
+        env:             vec![("HOME".into(), "/root".into())],
+        cwd:             "/tmp".into(),
+        qemu:            exe.clone(),
+        qemu_version:    "QEMU emulator version 7.1.0".into(),
+        qemu_args:       Vec::new(),
+        jitter:          exe.clone(),
+        host:            HostInfo::current(),
+        inputs:          vec![InputFile { file: exe, copy_to: None }],
+    };
+
+    let path = std::env::temp_dir()
+        .join(format!("cannoli_manifest_{}", std::process::id()));
+    manifest.save(&path).unwrap();
+    let loaded = RunManifest::load(&path);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(loaded.unwrap(), manifest);
+
+    let mut seeded = manifest.clone();
+    assert_eq!(seeded.rng_seed(), None);
+    seeded.deterministic_rng(1);
+    seeded.deterministic_rng(1234);
+    seeded.deterministic_time(5);
+    assert_eq!(seeded.qemu_args, ["-U", PIN_VAR, "-seed", "1234"]);
+    assert_eq!(seeded.rng_seed(), Some(1234));
+    assert_eq!(seeded.pins(), Pins { time: Some(5), random: Some(1234),
+        ..Pins::default() });
+    assert_eq!(seeded.env.iter().filter(|(x, _)| x == PIN_VAR).count(), 1);
+
+    assert_eq!(RunManifest::path_for("/tmp/trace.bin"),
+        PathBuf::from("/tmp/trace.bin.manifest.json"));
+}
@@ -0,0 +1,17 @@
+//! Utilities for launching targets under Cannoli
+//!
+//! The client only sees what QEMU streams to it, so everything about how the
+//! target was started (the binaries used, arguments, environment) lives
+//! here, on the side that launches QEMU.
+
+pub mod manifest;
+#[cfg(feature = "client")]
+pub mod template;
+pub mod differential;
+pub mod pool;
+
+pub use manifest::RunManifest;
+#[cfg(feature = "client")]
+pub use template::{Template, Batch};
+pub use differential::{Differential, Divergence};
+pub use pool::{Pool, Limits, Outcome, PoolResult, Summary};
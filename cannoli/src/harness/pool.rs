@@ -0,0 +1,314 @@
+//! Running many traced runs in parallel, with resource limits
+//!
+//! Analyses over a whole corpus spend most of their time waiting on QEMU, and
+//! [`crate::harness::Batch::run`] only runs one target at a time. A [`Pool`]
+//! keeps `jobs` runs going at once, each under [`Limits`] so an input which
+//! hangs or eats memory can't stall or take down the rest, and reports how
+//! every run ended. Every run connects to the same Cannoli server, which
+//! handles any number of clients at once.
+//!
+//! CPU time and memory are limited with `setrlimit()` in the QEMU process.
+//! The memory limit is on address space (`RLIMIT_AS`), which includes what
+//! QEMU reserves for itself: its translation buffer (1 GiB by default on
+//! 64-bit hosts, smaller with `-tb-size`) and, for 32-bit guests, the 4 GiB
+//! guest address space. Wall-clock time is enforced by killing the run.
+//!
+//! Inputs are staged right before each run starts. Runs copying their input
+//! to the same path would overwrite each other's, so such manifests are
+//! rejected, use `{run}` in [`crate::harness::Template::input_file`].
+
+use std::collections::HashSet;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::process::ExitStatus;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::channel;
+use std::time::{Duration, Instant};
+use crate::harness::RunManifest;
+
+/// How often running targets are checked for exiting or running out of time
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Resource limits of every run of a [`Pool`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// CPU time, rounded up to whole seconds
+    pub cpu: Option<Duration>,
+
+    /// Address space in bytes, see the module documentation
+    pub memory: Option<u64>,
+
+    /// Wall-clock time
+    pub wall: Option<Duration>,
+}
+
+/// How a run of a [`Pool`] ended
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// QEMU exited or was killed by a signal
+    Exited(ExitStatus),
+
+    /// The run was killed for using up its CPU or wall-clock time
+    TimedOut,
+
+    /// The run couldn't be started
+    Failed(String),
+}
+
+/// Result of a single run of a [`Pool`]
+#[derive(Clone, Debug)]
+pub struct PoolResult {
+    /// Index of the run's manifest
+    pub index: usize,
+
+    /// How the run ended
+    pub outcome: Outcome,
+
+    /// Wall-clock time the run took
+    pub elapsed: Duration,
+}
+
+/// Counts of how the runs of a [`Pool`] ended
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Summary {
+    /// Runs which exited successfully
+    pub succeeded: usize,
+
+    /// Runs which exited with a failure status
+    pub failed: usize,
+
+    /// Runs which were killed by a signal, other than for running out of
+    /// time
+    pub crashed: usize,
+
+    /// Runs which ran out of time
+    pub timed_out: usize,
+
+    /// Runs which couldn't be started
+    pub errors: usize,
+
+    /// Total wall-clock time of all runs
+    pub elapsed: Duration,
+}
+
+impl Summary {
+    /// Summarize `results`
+    pub fn new(results: &[PoolResult]) -> Self {
+        let mut ret = Self::default();
+        for result in results {
+            match &result.outcome {
+                Outcome::Exited(x) if x.success() => ret.succeeded += 1,
+                Outcome::Exited(x) if x.signal().is_some() =>
+                    ret.crashed += 1,
+                Outcome::Exited(_) => ret.failed    += 1,
+                Outcome::TimedOut  => ret.timed_out += 1,
+                Outcome::Failed(_) => ret.errors    += 1,
+            }
+            ret.elapsed += result.elapsed;
+        }
+        ret
+    }
+}
+
+/// Runs manifests in parallel, see the module documentation
+#[derive(Clone, Debug)]
+pub struct Pool {
+    /// Number of runs going at once
+    jobs: usize,
+
+    /// Limits of every run
+    limits: Limits,
+}
+
+impl Pool {
+    /// Create a pool running `jobs` runs at once, without limits
+    pub fn new(jobs: usize) -> Self {
+        Self { jobs: jobs.max(1), limits: Limits::default() }
+    }
+
+    /// Create a pool running as many runs at once as there are CPUs
+    pub fn per_cpu() -> Self {
+        Self::new(std::thread::available_parallelism().map_or(1, |x| x.get()))
+    }
+
+    /// Apply `limits` to every run
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Get the number of runs going at once
+    pub fn jobs(&self) -> usize {
+        self.jobs
+    }
+
+    /// Run the target of every manifest, invoking `progress` as each run
+    /// ends. Returns the results in the order of `manifests`
+    pub fn run(&self, manifests: &[RunManifest],
+            mut progress: impl FnMut(&PoolResult))
+            -> std::io::Result<Vec<PoolResult>> {
+        let mut dests = HashSet::new();
+        for input in manifests.iter().flat_map(|x| &x.inputs) {
+            if let Some(dest) = &input.copy_to {
+                if !dests.insert(dest) {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("Several runs copy their input to {}",
+                            dest.display())));
+                }
+            }
+        }
+
+        let next        = AtomicUsize::new(0);
+        let mut results = Vec::with_capacity(manifests.len());
+        let (send, recv) = channel();
+        std::thread::scope(|s| {
+            for _ in 0..self.jobs.min(manifests.len()) {
+                let send = send.clone();
+                let next = &next;
+                s.spawn(move || loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(manifest) = manifests.get(index) else { break };
+
+                    let started = Instant::now();
+                    let outcome = self.run_one(manifest);
+                    let result  = PoolResult {
+                        index, outcome, elapsed: started.elapsed() };
+                    if send.send(result).is_err() {
+                        break;
+                    }
+                });
+            }
+            drop(send);
+
+            for result in recv {
+                progress(&result);
+                results.push(result);
+            }
+        });
+
+        results.sort_by_key(|x| x.index);
+        Ok(results)
+    }
+
+    /// Run the target of `manifest` under the limits
+    fn run_one(&self, manifest: &RunManifest) -> Outcome {
+        if let Err(err) = manifest.stage_inputs() {
+            return Outcome::Failed(format!("Failed to stage inputs: {err}"));
+        }
+
+        let mut command = manifest.command();
+        let Limits { cpu, memory, .. } = self.limits;
+        if cpu.is_some() || memory.is_some() {
+            // Only async-signal-safe calls are made between fork and exec
+            unsafe {
+                command.pre_exec(move || {
+                    let set = |resource, soft: u64, hard: u64| {
+                        let limit = libc::rlimit {
+                            rlim_cur: soft, rlim_max: hard };
+                        if libc::setrlimit(resource, &limit) != 0 {
+                            return Err(std::io::Error::last_os_error());
+                        }
+                        Ok(())
+                    };
+
+                    // Past the soft limit the kernel sends SIGXCPU, and
+                    // SIGKILL a second later at the hard limit
+                    if let Some(cpu) = cpu {
+                        let secs = cpu.as_secs() +
+                            (cpu.subsec_nanos() != 0) as u64;
+                        set(libc::RLIMIT_CPU, secs.max(1), secs.max(1) + 1)?;
+                    }
+                    if let Some(memory) = memory {
+                        set(libc::RLIMIT_AS, memory, memory)?;
+                    }
+                    Ok(())
+                });
+            }
+        }
+
+        let mut child = match command.spawn() {
+            Ok(x) => x,
+            Err(err) => return Outcome::Failed(
+                format!("Failed to start QEMU: {err}")),
+        };
+
+        let started = Instant::now();
+        loop {
+            match child.try_wait() {
+                Ok(Some(status)) => {
+                    let cpu_killed = matches!(status.signal(),
+                        Some(libc::SIGXCPU) | Some(libc::SIGKILL)) &&
+                        self.limits.cpu.is_some();
+                    return if cpu_killed {
+                        Outcome::TimedOut
+                    } else {
+                        Outcome::Exited(status)
+                    };
+                }
+                Ok(None) => {}
+                Err(err) => return Outcome::Failed(
+                    format!("Failed to wait for QEMU: {err}")),
+            }
+
+            if self.limits.wall.is_some_and(|x| started.elapsed() >= x) {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Outcome::TimedOut;
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+#[test]
+#[cfg(feature = "client")]
+fn pool_limits() {
+    use crate::harness::manifest::{FileInfo, HostInfo, MANIFEST_VERSION};
+
+    // A script standing in for QEMU, running the guest's arguments with
+    // `sh` after dropping `-cannoli <jitter> <guest>`
+    let path = std::env::temp_dir()
+        .join(format!("cannoli_pool_{}", std::process::id()));
+    std::fs::write(&path, "#!/bin/sh\nshift 3\nexec /bin/sh \"$@\"\n")
+        .unwrap();
+    std::fs::set_permissions(&path,
+        std::os::unix::fs::PermissionsExt::from_mode(0o755)).unwrap();
+    let qemu = FileInfo::new(&path).unwrap();
+    let manifest = |script: &str| RunManifest {
+        version:         MANIFEST_VERSION,
+        cannoli_version: "0.1.0".into(),
+        started:         0,
+        guest:           qemu.clone(),
+        argv:            vec!["-c".into(), script.into()],
+        env:             Vec::new(),
+        cwd:             "/".into(),
+        qemu:            qemu.clone(),
+        qemu_version:    String::new(),
+        qemu_args:       Vec::new(),
+        jitter:          qemu.clone(),
+        host:            HostInfo::current(),
+        inputs:          Vec::new(),
+    };
+
+    let pool = Pool::new(3).limits(Limits {
+        wall: Some(Duration::from_millis(300)),
+        ..Default::default()
+    });
+    let mut seen = 0;
+    let results = pool.run(&[
+        manifest("exit 0"),
+        manifest("exit 3"),
+        manifest("sleep 10"),
+        manifest("kill -SEGV $$"),
+    ], |_| seen += 1).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(seen, 4);
+    assert_eq!(results.iter().map(|x| x.index).collect::<Vec<_>>(),
+        [0, 1, 2, 3]);
+    assert_eq!(results[2].outcome, Outcome::TimedOut);
+    assert!(results[2].elapsed < Duration::from_secs(5));
+    let summary = Summary::new(&results);
+    assert_eq!((summary.succeeded, summary.failed, summary.timed_out,
+        summary.crashed), (1, 1, 1, 1));
+}
@@ -0,0 +1,299 @@
+//! Templated guest command lines and inputs for batch runs
+//!
+//! A [`Template`] describes how to run the target for one input, with
+//! placeholders substituted per run:
+//!
+//! ```text
+//! {run}         index of the run in the batch
+//! {input}       path of the input for this run
+//! {input_name}  file name of the corpus entry for this run
+//! ```
+//!
+//! `{{` and `}}` produce literal braces. Placeholders may be used in the
+//! guest's arguments, in environment variables, and in the path the input is
+//! copied to for targets which read their input from a fixed location.
+//!
+//! [`Batch`] runs a template over a whole corpus, writing the manifest of
+//! every run so any run of interest can be reproduced on its own.
+
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+use crate::harness::{RunManifest, Pool, PoolResult};
+use crate::harness::manifest::{FileInfo, InputFile};
+
+/// Substitute `vars` into `template`
+pub fn expand(template: &str, vars: &[(&str, &str)])
+        -> std::io::Result<String> {
+    let invalid = |msg: String| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, msg)
+    };
+
+    let mut ret   = String::with_capacity(template.len());
+    let mut chars = template.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                ret.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                ret.push('}');
+            }
+            '{' => {
+                let name = chars.by_ref().take_while(|&x| x != '}')
+                    .collect::<String>();
+                let (_, val) = vars.iter().find(|(x, _)| *x == name)
+                    .ok_or_else(|| invalid(format!(
+                        "Unknown placeholder {{{name}}} in `{template}`")))?;
+                ret += val;
+            }
+            _ => ret.push(ch),
+        }
+    }
+
+    Ok(ret)
+}
+
+/// How to run the target for a single input
+#[derive(Clone, Debug)]
+pub struct Template {
+    /// Manifest everything is based on, this is hashed once up front
+    base: RunManifest,
+
+    /// Templated guest arguments
+    argv: Vec<String>,
+
+    /// Templated environment variables set in addition to the environment of
+    /// the current process
+    env: Vec<(String, String)>,
+
+    /// Templated path to copy the input to before each run, relative to the
+    /// working directory
+    input_file: Option<String>,
+}
+
+impl Template {
+    /// Create a template for running `guest` with the templated `argv` under
+    /// `qemu` with the `jitter` hooks
+    pub fn new(qemu: impl AsRef<Path>, jitter: impl AsRef<Path>,
+            guest: impl AsRef<Path>, argv: &[String])
+            -> std::io::Result<Self> {
+        Ok(Self {
+            base:       RunManifest::new(qemu, jitter, guest, &[])?,
+            argv:       argv.to_vec(),
+            env:        Vec::new(),
+            input_file: None,
+        })
+    }
+
+    /// Set the templated environment variable `key` for the guest
+    pub fn env(mut self, key: impl Into<String>, val: impl Into<String>)
+            -> Self {
+        self.env.push((key.into(), val.into()));
+        self
+    }
+
+    /// Pass additional arguments to QEMU
+    pub fn qemu_args(mut self, args: &[String]) -> Self {
+        self.base.qemu_args.extend_from_slice(args);
+        self
+    }
+
+    /// Give every run the same random numbers, see
+    /// [`RunManifest::deterministic_rng`]
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.base.deterministic_rng(seed);
+        self
+    }
+
+    /// Give every run the same time, see
+    /// [`RunManifest::deterministic_time`]
+    pub fn time(mut self, nanos: u64) -> Self {
+        self.base.deterministic_time(nanos);
+        self
+    }
+
+    /// Run the guest in `dir` rather than the current directory
+    pub fn cwd(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.base.cwd = dir.into();
+        self
+    }
+
+    /// Copy each input to the templated `path` before running, `{input}`
+    /// then refers to this path rather than the corpus entry
+    pub fn input_file(mut self, path: impl Into<String>) -> Self {
+        self.input_file = Some(path.into());
+        self
+    }
+
+    /// Prepare run number `run` for the corpus entry `input`, copying the
+    /// input into place and returning the manifest of the run. The input is
+    /// recorded in the manifest so the run can be reproduced later
+    pub fn instantiate(&self, run: usize, input: &Path)
+            -> std::io::Result<RunManifest> {
+        let run_str    = run.to_string();
+        let input_name = input.file_name()
+            .map(|x| x.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        let mut manifest = self.base.clone();
+        let mut input    = InputFile {
+            file:    FileInfo::new(input)?,
+            copy_to: None,
+        };
+
+        // Copy the input to where the guest expects it
+        if let Some(path) = &self.input_file {
+            input.copy_to = Some(self.base.cwd.join(expand(path, &[
+                ("run",        &run_str),
+                ("input_name", &input_name),
+            ])?));
+        }
+        let input_path = input.copy_to.as_ref().unwrap_or(&input.file.path)
+            .to_string_lossy().into_owned();
+        manifest.inputs.push(input);
+        manifest.stage_inputs()?;
+
+        let vars = [
+            ("run",        run_str.as_str()),
+            ("input",      input_path.as_str()),
+            ("input_name", input_name.as_str()),
+        ];
+
+        manifest.argv = self.argv.iter().map(|x| expand(x, &vars))
+            .collect::<std::io::Result<_>>()?;
+        for (key, val) in &self.env {
+            let val = expand(val, &vars)?;
+            manifest.env.retain(|(x, _)| x != key);
+            manifest.env.push((key.clone(), val));
+        }
+
+        Ok(manifest)
+    }
+}
+
+/// Result of a single run of a [`Batch`]
+#[derive(Clone, Debug)]
+pub struct RunResult {
+    /// Index of the run
+    pub run: usize,
+
+    /// Corpus entry used for the run
+    pub input: PathBuf,
+
+    /// Path the manifest of the run was written to
+    pub manifest: PathBuf,
+
+    /// Exit status of QEMU
+    pub status: ExitStatus,
+}
+
+/// Runs a [`Template`] once for every entry of a corpus, one at a time or on
+/// a [`Pool`]
+pub struct Batch {
+    /// Template for each run
+    template: Template,
+
+    /// Corpus entries, in the order they are run
+    corpus: Vec<PathBuf>,
+
+    /// Directory the manifests are written to
+    out_dir: PathBuf,
+}
+
+impl Batch {
+    /// Create a batch running `template` over `corpus`, writing manifests to
+    /// `out_dir`
+    pub fn new(template: Template, corpus: Vec<PathBuf>,
+            out_dir: impl Into<PathBuf>) -> Self {
+        Self { template, corpus, out_dir: out_dir.into() }
+    }
+
+    /// Create a batch running `template` over every file in the directory
+    /// `corpus`, sorted by name
+    pub fn from_dir(template: Template, corpus: impl AsRef<Path>,
+            out_dir: impl Into<PathBuf>) -> std::io::Result<Self> {
+        let mut entries = Vec::new();
+        for entry in std::fs::read_dir(corpus)? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                entries.push(entry.path());
+            }
+        }
+        entries.sort();
+
+        Ok(Self::new(template, entries, out_dir))
+    }
+
+    /// Get the path the manifest of run `run` is written to
+    pub fn manifest_path(&self, run: usize) -> PathBuf {
+        self.out_dir.join(format!("run_{run:06}.manifest.json"))
+    }
+
+    /// Prepare and save the manifest of run `run`, returning its path
+    pub fn prepare(&self, run: usize)
+            -> std::io::Result<(RunManifest, PathBuf)> {
+        let manifest = self.template.instantiate(run, &self.corpus[run])?;
+        let path     = self.manifest_path(run);
+        manifest.save(&path)?;
+        Ok((manifest, path))
+    }
+
+    /// Number of runs in the batch
+    pub fn len(&self) -> usize {
+        self.corpus.len()
+    }
+
+    /// Check if the batch has no runs
+    pub fn is_empty(&self) -> bool {
+        self.corpus.is_empty()
+    }
+
+    /// Run every entry of the corpus, invoking `progress` after each run
+    pub fn run(&self, mut progress: impl FnMut(&RunResult))
+            -> std::io::Result<Vec<RunResult>> {
+        std::fs::create_dir_all(&self.out_dir)?;
+
+        let mut results = Vec::new();
+        for run in 0..self.len() {
+            let (manifest, path) = self.prepare(run)?;
+            let result = RunResult {
+                run,
+                input:    self.corpus[run].clone(),
+                manifest: path,
+                status:   manifest.command().status()?,
+            };
+            progress(&result);
+            results.push(result);
+        }
+
+        Ok(results)
+    }
+
+    /// Run every entry of the corpus in parallel on `pool`, invoking
+    /// `progress` as each run ends. The manifests are all written up front,
+    /// the [`PoolResult::index`] of a run is its index in the batch
+    pub fn run_pool(&self, pool: &Pool, progress: impl FnMut(&PoolResult))
+            -> std::io::Result<Vec<PoolResult>> {
+        std::fs::create_dir_all(&self.out_dir)?;
+
+        let manifests = (0..self.len())
+            .map(|run| self.prepare(run).map(|(x, _)| x))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        pool.run(&manifests, progress)
+    }
+
+    /// Get the corpus entry of run `run`
+    pub fn input(&self, run: usize) -> &Path {
+        &self.corpus[run]
+    }
+}
+
+#[test]
+fn expand_placeholders() {
+    let vars = [("run", "3"), ("input", "/corpus/a")];
+    assert_eq!(expand("-f {input} -n {run} {{x}}", &vars).unwrap(),
+        "-f /corpus/a -n 3 {x}");
+    assert!(expand("{nope}", &vars).is_err());
+}
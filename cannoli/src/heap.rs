@@ -0,0 +1,163 @@
+//! Recognizing heap growth in the guest's mappings
+//!
+//! QEMU doesn't tell us about `brk()`, it grows the program break by mapping
+//! anonymous memory right after the old break, so heap growth looks like any
+//! other anonymous mapping. Allocators also reserve arenas with plain
+//! anonymous mappings. [`HeapClassifier`] runs in the jitter, where mappings
+//! are seen in the order the guest makes them, and recognizes both so they
+//! can be reported as [`HeapEvent`]s alongside the mapping itself:
+//!
+//! - The program break starts at the end of the main binary's image (including
+//!   its `.bss`) as it was when the guest started executing. Readable and
+//!   writable anonymous mappings starting exactly at the current break extend
+//!   it
+//! - glibc reserves arenas for secondary threads as inaccessible anonymous
+//!   mappings of `HEAP_MAX_SIZE` (64 MiB for 64-bit targets, 1 MiB for
+//!   32-bit), aligned to their size. When the first attempt isn't aligned it
+//!   maps twice the size and trims it, in which case the aligned part that's
+//!   kept is reported
+//!
+//! Shrinking the break doesn't unmap anything in QEMU, so only growth is
+//! reported.
+
+/// Size of the pages the program break is aligned to
+const PAGE_SIZE: u64 = 4096;
+
+/// A change to the heap, see the module documentation
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HeapEvent {
+    /// The program break grew from `old` to `new`
+    Brk { old: u64, new: u64 },
+
+    /// An allocator arena was reserved at `base..base + len`
+    Arena { base: u64, len: u64 },
+}
+
+/// Recognizes heap growth from the mappings of a single process, see the
+/// module documentation
+#[derive(Clone, Debug)]
+pub struct HeapClassifier {
+    /// Size of glibc arenas on the target
+    arena_size: u64,
+
+    /// Path of the main binary, the first file mapped
+    main: Option<Box<[u8]>>,
+
+    /// Page-aligned end of the main binary's image so far
+    image_end: u64,
+
+    /// Page-aligned current program break, `None` until the guest starts
+    /// executing
+    brk: Option<u64>,
+}
+
+/// Round `x` up to the next multiple of `align`, a power of two
+fn align_up(x: u64, align: u64) -> u64 {
+    x.saturating_add(align - 1) & !(align - 1)
+}
+
+impl HeapClassifier {
+    /// Create a classifier for a target with `bitness`-bit pointers
+    pub fn new(bitness: u8) -> Self {
+        Self {
+            arena_size: if bitness == 64 { 64 << 20 } else { 1 << 20 },
+            main:       None,
+            image_end:  0,
+            brk:        None,
+        }
+    }
+
+    /// The guest is about to execute its first instruction, the program loader
+    /// is done and the program break starts at the end of the main image
+    pub fn start(&mut self) {
+        if self.brk.is_none() {
+            self.brk = Some(self.image_end);
+        }
+    }
+
+    /// Get the current program break, once the guest started executing
+    pub fn program_break(&self) -> Option<u64> {
+        self.brk
+    }
+
+    /// Classify a successful mapping with the arguments of the `mmap()`
+    /// callback, returning the heap change it makes if any
+    #[allow(clippy::too_many_arguments)]
+    pub fn mmap(&mut self, base: u64, len: u64, anon: bool, read: bool,
+            write: bool, exec: bool, path: &[u8]) -> Option<HeapEvent> {
+        let end = align_up(base.saturating_add(len), PAGE_SIZE);
+
+        // While loading, build up the image of the main binary. The `.bss`
+        // past the end of the file is mapped anonymously right after it
+        let Some(brk) = self.brk else {
+            if !anon {
+                let main = self.main.get_or_insert_with(|| path.into());
+                if **main == *path {
+                    self.image_end = self.image_end.max(end);
+                }
+            } else if self.main.is_some() && base == self.image_end {
+                self.image_end = end;
+            }
+            return None;
+        };
+
+        if !anon {
+            return None;
+        }
+
+        if read && write && base == brk {
+            self.brk = Some(end);
+            return Some(HeapEvent::Brk { old: brk, new: end });
+        }
+
+        let size = self.arena_size;
+        if !read && !write && !exec {
+            if len == size && base & (size - 1) == 0 {
+                return Some(HeapEvent::Arena { base, len });
+            }
+
+            // Oversized reservation which is about to be trimmed
+            if len == size * 2 {
+                return Some(HeapEvent::Arena {
+                    base: align_up(base, size),
+                    len:  size,
+                });
+            }
+        }
+
+        None
+    }
+}
+
+#[test]
+fn classify_heap() {
+    let mut heap = HeapClassifier::new(64);
+
+    // Program loading: the binary, its .bss, the interpreter and the stack
+    assert_eq!(heap.mmap(0x400000, 0x1800, false, true, false, true,
+        b"/bin/app"), None);
+    assert_eq!(heap.mmap(0x402000, 0x1000, false, true, true, false,
+        b"/bin/app"), None);
+    assert_eq!(heap.mmap(0x403000, 0x2000, true, true, true, false,
+        b""), None);
+    assert_eq!(heap.mmap(0x7f0000000000, 0x30000, false, true, false, true,
+        b"/lib/ld.so"), None);
+    assert_eq!(heap.mmap(0x7fff00000000, 0x800000, true, true, true, false,
+        b""), None);
+    heap.start();
+    assert_eq!(heap.program_break(), Some(0x405000));
+
+    // Heap growth, and an unrelated anonymous mapping
+    assert_eq!(heap.mmap(0x405000, 0x21000, true, true, true, false, b""),
+        Some(HeapEvent::Brk { old: 0x405000, new: 0x426000 }));
+    assert_eq!(heap.mmap(0x7f1000000000, 0x1000, true, true, true, false,
+        b""), None);
+    assert_eq!(heap.mmap(0x426000, 0x1000, true, true, true, false, b""),
+        Some(HeapEvent::Brk { old: 0x426000, new: 0x427000 }));
+
+    // Arena reservations, aligned and oversized
+    assert_eq!(heap.mmap(0x7f0004000000, 64 << 20, true, false, false, false,
+        b""), Some(HeapEvent::Arena { base: 0x7f0004000000, len: 64 << 20 }));
+    assert_eq!(heap.mmap(0x7f0123000000, 128 << 20, true, false, false, false,
+        b""), Some(HeapEvent::Arena { base: 0x7f0124000000, len: 64 << 20 }));
+}
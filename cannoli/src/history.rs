@@ -0,0 +1,283 @@
+//! The last basic blocks every thread executed
+//!
+//! A finding says where something went wrong, but rarely how the target got
+//! there, and keeping the whole trace around for the few findings of a run
+//! is expensive. The framework instead keeps a small ring of the blocks
+//! every thread executed last (32 by default, see [`configure`]), which
+//! costs next to nothing, and attaches it to findings:
+//!
+//! - [`crate::telemetry::Alert`]s of [`crate::telemetry::Telemetry::spawn`]
+//!   carry the history of every thread of the process
+//! - [`crate::coredump::Registers`] carries the history of the crashed
+//!   thread, from the trace the dump is aligned with
+//! - anything else can get it with [`recent`] and [`process`], eg. from
+//!   [`crate::Cannoli::exit`] of a process which was killed, until which
+//!   the histories of its threads are kept
+//!
+//! Blocks are the targets of edges with [`crate::control::HookKind::Edge`]
+//! and [`crate::control::HookKind::Function`]. With hooks on every
+//! instruction, an executed PC starts a block unless it's at most
+//! [`MAX_INSTRUCTION`] bytes past the previous one, which is a guess: a
+//! short forward jump looks like falling through, and an instruction
+//! looping on itself like a single execution. Blocks are symbolized
+//! with the executable mappings of the process when they're asked for, so
+//! a block of a library unloaded since has no module.
+//!
+//! Start triggers (see [`crate::control::Filters::start`]) fire before
+//! anything is hooked, so there is no history to attach to them.
+
+use std::fmt;
+use std::sync::{Arc, Mutex, LazyLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::collections::{HashMap, VecDeque};
+use serde::Serialize;
+use crate::ClientInfo;
+use crate::address_space::AddressSpace;
+
+/// Default number of blocks kept for every thread
+const DEFAULT_LEN: usize = 32;
+
+/// Largest distance between consecutive PCs which is taken as falling
+/// through to the next instruction, the longest x86 instruction is 15 bytes
+pub const MAX_INSTRUCTION: u64 = 16;
+
+/// Number of blocks kept for every thread, see [`configure`]
+static LEN: AtomicUsize = AtomicUsize::new(DEFAULT_LEN);
+
+/// Histories of every process with a connected thread, keyed by PID
+static HISTORIES: LazyLock<Mutex<HashMap<i32, Arc<Histories>>>> =
+    LazyLock::new(Default::default);
+
+/// An executed block, symbolized
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Block {
+    /// Address of the block
+    pub pc: u64,
+
+    /// Path of the file the block is in, if it's in one
+    pub module: Option<String>,
+
+    /// Offset of the block in the file, as
+    /// [`AddressSpace::module_offset`] gives it, or the address of the
+    /// block without a module
+    pub offset: u64,
+}
+
+impl Block {
+    /// Symbolize the block at `pc` with the mappings of `space`
+    pub fn new(space: &AddressSpace, pc: u64) -> Self {
+        match space.module_offset(pc) {
+            Some((path, offset)) => Self {
+                pc, offset, module: Some(path.to_string()),
+            },
+            None => Self { pc, module: None, offset: pc },
+        }
+    }
+}
+
+impl fmt::Display for Block {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.module {
+            Some(path) => write!(f, "{:#x} {}+{:#x}", self.pc,
+                path.rsplit('/').next().unwrap_or(path), self.offset),
+            None => write!(f, "{:#x}", self.pc),
+        }
+    }
+}
+
+/// Ring of the last blocks a thread executed, see the module documentation
+#[derive(Clone, Debug, Default)]
+pub struct BranchHistory {
+    /// Addresses of the blocks, the oldest first
+    pcs: VecDeque<u64>,
+
+    /// Maximum number of blocks kept
+    len: usize,
+
+    /// First PC given to [`BranchHistory::exec`], which isn't known to
+    /// start a block until the history is merged after the one before it
+    first: Option<u64>,
+
+    /// Last PC given to [`BranchHistory::exec`]
+    last: Option<u64>,
+}
+
+impl BranchHistory {
+    /// Create an empty history keeping the last `len` blocks
+    pub fn new(len: usize) -> Self {
+        Self { pcs: VecDeque::with_capacity(len), len, ..Default::default() }
+    }
+
+    /// Check if `pc` falls through from the last executed PC
+    fn follows(&self, pc: u64) -> bool {
+        self.last.is_some_and(|x| pc.wrapping_sub(x) <= MAX_INSTRUCTION)
+    }
+
+    /// Add the block at `pc`, dropping the oldest one if the history is full
+    pub fn push(&mut self, pc: u64) {
+        if self.len == 0 {
+            return;
+        }
+        if self.pcs.len() == self.len {
+            self.pcs.pop_front();
+        }
+        self.pcs.push_back(pc);
+    }
+
+    /// Observe the execution of the instruction at `pc`, which is added if
+    /// it starts a block
+    pub fn exec(&mut self, pc: u64) {
+        if self.last.is_none() && self.pcs.is_empty() {
+            self.first = Some(pc);
+        } else if !self.follows(pc) {
+            self.push(pc);
+        }
+        self.last = Some(pc);
+    }
+
+    /// Add the blocks of `later`, a history of what the thread executed
+    /// right after this one
+    pub fn merge(&mut self, later: &BranchHistory) {
+        if let Some(pc) = later.first {
+            self.exec(pc);
+        }
+        later.pcs.iter().for_each(|&x| self.push(x));
+        self.last = later.last.or(self.last);
+    }
+
+    /// Get the addresses of the blocks, the oldest first
+    pub fn pcs(&self) -> impl Iterator<Item = u64> + '_ {
+        self.first.iter().chain(&self.pcs).copied()
+            .skip((self.pcs.len() + self.first.is_some() as usize)
+                .saturating_sub(self.len))
+    }
+
+    /// Symbolize the blocks with the mappings of `space`, the oldest first
+    pub fn blocks(&self, space: &AddressSpace) -> Vec<Block> {
+        self.pcs().map(|x| Block::new(space, x)).collect()
+    }
+
+    /// Forget every block, when the trace has a gap
+    pub fn clear(&mut self) {
+        *self = Self::new(self.len);
+    }
+}
+
+/// Keep the last `len` blocks of every thread which connects from now on,
+/// 0 to not keep any
+pub fn configure(len: usize) {
+    LEN.store(len, Ordering::Relaxed);
+}
+
+/// Get the last blocks of the thread `tid` of the process `pid`, the oldest
+/// first, or `None` if the process isn't connected
+pub fn recent(pid: i32, tid: i32) -> Option<Vec<Block>> {
+    let histories = HISTORIES.lock().unwrap().get(&pid)?.clone();
+    let space = histories.space.lock().unwrap();
+    let threads = histories.threads.lock().unwrap();
+    Some(threads.get(&tid)?.blocks(&space))
+}
+
+/// Get the last blocks of every thread of the process `pid`, including those
+/// which exited, by TID
+pub fn process(pid: i32) -> Vec<(i32, Vec<Block>)> {
+    let Some(histories) = HISTORIES.lock().unwrap().get(&pid).cloned() else {
+        return Vec::new();
+    };
+    let space = histories.space.lock().unwrap();
+    let mut ret = histories.threads.lock().unwrap().iter()
+        .map(|(&tid, x)| (tid, x.blocks(&space)))
+        .collect::<Vec<_>>();
+    ret.sort_by_key(|x| x.0);
+    ret
+}
+
+/// A change of the mappings in a payload, applied in order after it
+#[derive(Clone, Debug)]
+pub(crate) struct Map {
+    /// Address of the range
+    pub base: u64,
+
+    /// Length of the range in bytes
+    pub len: u64,
+
+    /// File mapped executable in the range and its offset, `None` if the
+    /// range was unmapped or mapped otherwise
+    pub file: Option<(Arc<str>, u64)>,
+}
+
+/// Histories and executable mappings of a process
+#[derive(Default)]
+pub(crate) struct Histories {
+    /// Executable mappings, to symbolize blocks with
+    space: Mutex<AddressSpace>,
+
+    /// History of every thread, keyed by TID
+    threads: Mutex<HashMap<i32, BranchHistory>>,
+}
+
+impl Histories {
+    /// Get the histories of the process of `ci`
+    pub(crate) fn get(ci: &ClientInfo) -> Arc<Self> {
+        HISTORIES.lock().unwrap().entry(ci.pid).or_default().clone()
+    }
+
+    /// Forget the histories of the process `pid`, once its last thread is
+    /// gone
+    pub(crate) fn remove(pid: i32) {
+        HISTORIES.lock().unwrap().remove(&pid);
+    }
+
+    /// Create an empty history for a payload of a thread
+    pub(crate) fn payload() -> BranchHistory {
+        BranchHistory::new(LEN.load(Ordering::Relaxed))
+    }
+
+    /// Apply the blocks and mapping changes of a payload of the thread
+    /// `tid`, or forget its blocks if the payload was skipped
+    pub(crate) fn record(&self, tid: i32, blocks: Option<&BranchHistory>,
+            maps: &[Map]) {
+        let mut threads = self.threads.lock().unwrap();
+        let history = threads.entry(tid).or_insert_with(Self::payload);
+        match blocks {
+            Some(blocks) => history.merge(blocks),
+            None => history.clear(),
+        }
+        drop(threads);
+
+        let mut space = self.space.lock().unwrap();
+        for map in maps {
+            match &map.file {
+                Some((path, offset)) => space.mmap(map.base, map.len, false,
+                    true, false, true, path, *offset),
+                None => space.munmap(map.base, map.len),
+            }
+        }
+    }
+}
+
+#[test]
+fn branch_history() {
+    let mut space = AddressSpace::new();
+    space.mmap(0x400000, 0x1000, false, true, false, true, "/bin/true", 0);
+
+    // Two payloads, the second starting in the middle of a block
+    let mut first = BranchHistory::new(4);
+    [0x400000, 0x400004, 0x400010, 0x400100, 0x400104]
+        .into_iter().for_each(|x| first.exec(x));
+    let mut second = BranchHistory::new(4);
+    [0x400108, 0x400000, 0x500000].into_iter().for_each(|x| second.exec(x));
+    second.push(0x400020);
+
+    let mut history = BranchHistory::new(4);
+    history.merge(&first);
+    assert_eq!(history.pcs().collect::<Vec<_>>(), [0x400000, 0x400100]);
+    history.merge(&second);
+    assert_eq!(history.pcs().collect::<Vec<_>>(),
+        [0x400100, 0x400000, 0x500000, 0x400020]);
+
+    let blocks = history.blocks(&space);
+    assert_eq!(blocks[1].to_string(), "0x400000 true+0x0");
+    assert_eq!(blocks[2], Block { pc: 0x500000, module: None,
+        offset: 0x500000 });
+}
@@ -3,16 +3,68 @@
 
 #![feature(array_chunks, once_cell)]
 
-use std::io::Read;
+use std::io::{Read, Write};
 use std::any::Any;
-use std::ffi::CStr;
+use std::panic::AssertUnwindSafe;
 use std::mem::{size_of, MaybeUninit};
-use std::net::{TcpListener, TcpStream};
+use std::net::{Shutdown, TcpListener, TcpStream};
 use std::sync::{Arc, Mutex, LazyLock};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
 use std::time::{Instant, Duration};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use mempipe::RecvPipe;
 
+pub mod event;
+pub mod sinks;
+pub mod harness;
+pub mod analysis;
+pub mod address_space;
+#[cfg(feature = "client")]
+pub mod devices;
+pub mod heap;
+pub mod shadow;
+pub mod canon;
+pub mod capture;
+pub mod migrate;
+pub mod grep;
+pub mod excerpt;
+pub mod cursor;
+pub mod symbols;
+#[cfg(feature = "client")]
+pub mod config;
+pub mod control;
+pub mod guest;
+pub mod telemetry;
+pub mod syscalls;
+pub mod pin;
+pub mod edges;
+pub mod calls;
+pub mod prototypes;
+pub mod history;
+pub mod code;
+pub mod coverage;
+pub mod regfile;
+pub mod realmode;
+pub mod reexec;
+pub mod auth;
+pub mod tenants;
+pub mod systemd;
+pub mod coredump;
+pub mod profile;
+pub mod perfetto;
+pub mod estimate;
+#[cfg(feature = "client")]
+pub mod conformance;
+pub mod store;
+pub mod replay;
+pub mod new_code;
+pub mod cpus;
+pub mod dual;
+pub mod names;
+
+pub use event::Event;
+pub use cannoli_types::{Architecture, ClientConn, MAX_IMAGE_LEN};
+
 /// Wrapper around [`Error`]
 type Result<T> = std::result::Result<T, Error>;
 
@@ -43,6 +95,13 @@ pub enum Error {
 
     /// Getting the path for mmap() did not contain valid UTF-8 characters
     PathEncoding(std::str::Utf8Error),
+
+    /// Failed to set up the control channel to a client
+    Control(std::io::Error),
+
+    /// A callback of the user's [`Cannoli`] implementation panicked with
+    /// [`PanicPolicy::Abort`]
+    Panic(AnalysisError),
 }
 
 /// Chunk size to use when streaming data over IPC
@@ -51,181 +110,10 @@ const CHUNK_SIZE: usize = 256 * 1024;
 /// Number of chunks to use with IPC
 const NUM_BUFFERS: usize = 16;
 
-/// Header sent when a client connects
-///
-/// Must only contain plain-old-data otherwise you will break the unsafe
-/// serialization and deserialization we use :)
-///
-/// This also cannot have unaligned fields that cause padding bytes
-#[derive(Clone, Copy, Debug)]
-#[repr(C)]
-pub struct ClientConn {
-    /// UID for the pipe
-    pub uid: u64,
-
-    /// Architecture
-    pub arch: i32,
-
-    /// Big endian flag
-    pub big_endian: i32,
-
-    /// Parent process ID
-    pub ppid: i32,
-
-    /// Process ID
-    pub pid: i32,
-
-    /// Thread ID
-    pub tid: i32,
-
-    /// Length of the parent comm (in bytes)
-    pub pcomm_len: u32,
-
-    /// Length of the comm (in bytes)
-    pub comm_len: u32,
-}
-
-/// Different QEMU target architectures
-#[repr(C)]
-#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub enum Architecture {
-    Aarch64,
-    Aarch64be,
-    Alpha,
-    Armv5teb,
-    Armv5tel,
-    Cris,
-    Hexagon,
-    I386,
-    I686,
-    M68k,
-    Microblaze,
-    Mips,
-    Mips64,
-    Nios2,
-    Openrisc,
-    Parisc,
-    Ppc,
-    Ppc64,
-    Ppc64le,
-    Riscv32,
-    Riscv64,
-    S390x,
-    Sh4,
-    Sparc,
-    Sparc64,
-    X86_64,
-    Xtensa,
-}
-
-impl From<i32> for Architecture {
-    fn from(val: i32) -> Self {
-        match val {
-             0 => Self::Aarch64,
-             1 => Self::Aarch64be,
-             2 => Self::Alpha,
-             3 => Self::Armv5teb,
-             4 => Self::Armv5tel,
-             5 => Self::Cris,
-             6 => Self::Hexagon,
-             7 => Self::I386,
-             8 => Self::I686,
-             9 => Self::M68k,
-            10 => Self::Microblaze,
-            11 => Self::Mips,
-            12 => Self::Mips64,
-            13 => Self::Nios2,
-            14 => Self::Openrisc,
-            15 => Self::Parisc,
-            16 => Self::Ppc,
-            17 => Self::Ppc64,
-            18 => Self::Ppc64le,
-            19 => Self::Riscv32,
-            20 => Self::Riscv64,
-            21 => Self::S390x,
-            22 => Self::Sh4,
-            23 => Self::Sparc,
-            24 => Self::Sparc64,
-            25 => Self::X86_64,
-            26 => Self::Xtensa,
-            _  => panic!("Cannoli: Unhandled architecture ID {}", val),
-        }
-    }
-}
-
-impl Architecture {
-    /// Convert a `UNAME_MACHINE` C-string to an [`Architecture`]
-    ///
-    /// # Safety
-    ///
-    /// Expects a pointer to a valid null-terminatedf string
-    pub unsafe fn from_cstr(arch: *const i8) -> Self {
-        let arch = CStr::from_ptr(arch).to_str().expect(
-            "Cannoli: Invalid string passed to Architecture::from_cstr()");
-        match arch {
-            "aarch64"    => Architecture::Aarch64,
-            "aarch64_be" => Architecture::Aarch64be,
-            "alpha"      => Architecture::Alpha,
-            "armv5teb"   => Architecture::Armv5teb,
-            "armv5tel"   => Architecture::Armv5tel,
-            "cris"       => Architecture::Cris,
-            "hexagon"    => Architecture::Hexagon,
-            "i386"       => Architecture::I386,
-            "i686"       => Architecture::I686,
-            "m68k"       => Architecture::M68k,
-            "microblaze" => Architecture::Microblaze,
-            "mips"       => Architecture::Mips,
-            "mips64"     => Architecture::Mips64,
-            "nios2"      => Architecture::Nios2,
-            "openrisc"   => Architecture::Openrisc,
-            "parisc"     => Architecture::Parisc,
-            "ppc"        => Architecture::Ppc,
-            "ppc64"      => Architecture::Ppc64,
-            "ppc64le"    => Architecture::Ppc64le,
-            "riscv32"    => Architecture::Riscv32,
-            "riscv64"    => Architecture::Riscv64,
-            "s390x"      => Architecture::S390x,
-            "sh4"        => Architecture::Sh4,
-            "sparc"      => Architecture::Sparc,
-            "sparc64"    => Architecture::Sparc64,
-            "x86_64"     => Architecture::X86_64,
-            "xtensa"     => Architecture::Xtensa,
-            _ => panic!("Cannoli: Unhandled architecture name {}", arch),
-        }
-    }
-
-    pub fn bitness(&self) -> u8 {
-        match self {
-            Architecture::Aarch64     => 64,
-            Architecture::Aarch64be   => 64,
-            Architecture::Alpha       => 32,
-            Architecture::Armv5teb    => 64,
-            Architecture::Armv5tel    => 64,
-            Architecture::Cris        => 32,
-            Architecture::Hexagon     => 32,
-            Architecture::I386        => 32,
-            Architecture::I686        => 32,
-            Architecture::M68k        => 32,
-            Architecture::Microblaze  => 32,
-            Architecture::Mips        => 32,
-            Architecture::Mips64      => 64,
-            Architecture::Nios2       => 32,
-            Architecture::Openrisc    => 32,
-            Architecture::Parisc      => 32,
-            Architecture::Ppc         => 32,
-            Architecture::Ppc64       => 64,
-            Architecture::Ppc64le     => 64,
-            Architecture::Riscv32     => 32,
-            Architecture::Riscv64     => 64,
-            Architecture::S390x       => 32,
-            Architecture::Sh4         => 32,
-            Architecture::Sparc       => 32,
-            Architecture::Sparc64     => 64,
-            Architecture::X86_64      => 64,
-            Architecture::Xtensa      => 32,
-        }
-    }
-}
+/// Longest pcomm or comm accepted in a greeting, so a bad greeting can't
+/// make the server allocate an arbitrary amount of memory. The kernel keeps
+/// them under 16 bytes
+const MAX_COMM_LEN: u32 = 256;
 
 /// Gross macro to deserialize multiple plain-old-data types into a tuple
 /// with only one length check.
@@ -273,25 +161,119 @@ macro_rules! consume {
     }}
 }
 
+/// Invoke the system call callbacks if the registers `regs` at `pc` are at a
+/// system call site of `syscalls`
+fn check_syscall<T: Cannoli>(pid: &T::PidContext, tid: &T::TidContext,
+        syscalls: &syscalls::Sites, pc: u64, regs: &[u8],
+        trace: &mut Vec<T::Trace>, marks: &mut Marks) {
+    let Some(call) = syscalls.check(pc, regs) else { return };
+    match call {
+        syscalls::Call::Entry { nr, args } => T::syscall_entry(pid, tid,
+            pc, nr, &args[..syscalls.num_args()], trace),
+        syscalls::Call::Exit { syscall, ret } =>
+            T::syscall_exit(pid, tid, syscall, ret, trace),
+    }
+    marks.syscalls.extend(syscalls.mark(pc, &call));
+}
+
+/// Marks in a payload which are reported in order after its trace
+#[derive(Default)]
+struct Marks {
+    /// Epochs completed by the end of the payload
+    epochs: Vec<u64>,
+
+    /// Edge hooks, see [`edges`]
+    edges: Vec<edges::Mark>,
+
+    /// System calls which are paired in order, see [`syscalls`]
+    syscalls: Vec<syscalls::Mark>,
+
+    /// Blocks executed, see [`history`]
+    history: history::BranchHistory,
+
+    /// Changes of the executable mappings, see [`history`]
+    maps: Vec<history::Map>,
+}
+
+/// Trace of a payload with its sequence number, `None` if processing it
+/// panicked, and its marks
+type Sequenced<T> = (u64, Option<Vec<T>>, Marks);
+
+/// Report the execution of the instruction at `pc` of dynamic code of the
+/// client `ci`, with its code if it was `announced` or if the analysis
+/// captures it, see [`Cannoli::exec_dynamic`]
+fn exec_dynamic<T: Cannoli>(pid: &T::PidContext, tid: &T::TidContext,
+        ci: &ClientInfo, code: &code::Code, pc: u64, announced: bool,
+        trace: &mut Vec<T::Trace>) {
+    let mut report = |x: &[u8]| T::exec_dynamic(pid, tid, pc, x, trace);
+    if announced {
+        code.with(pc, report)
+    } else if T::CAPTURE_DYNAMIC_CODE {
+        code.with_read(ci.pid, ci.tid, pc, report)
+    } else {
+        report(&[])
+    }
+}
+
 /// Given a payload of bytes that came from the IPC channel, deserialize it and
-/// invoke callbacks based on the payload
+/// invoke callbacks based on the payload. Epochs and edge hooks in the
+/// payload are added to `marks`, system call sites, branches and code
+/// announced in it to `syscalls`, `branches` and `code`
+#[allow(clippy::too_many_arguments)]
 fn parse_payload<T: Cannoli>(pid: &T::PidContext, tid: &T::TidContext,
-        trace: &mut Vec<T::Trace>, mut payload: &[u8]) -> Result<()> {
+        ci: &ClientInfo, trace: &mut Vec<T::Trace>, marks: &mut Marks,
+        syscalls: &syscalls::Sites, branches: &edges::Branches,
+        code: &code::Code, mut payload: &[u8]) -> Result<()> {
     // Clear the trace
     trace.clear();
+    marks.epochs.clear();
+    marks.edges.clear();
+    marks.syscalls.clear();
+    marks.maps.clear();
+    marks.history = history::Histories::payload();
+
+    // Set by a helper marker for the memory access after it
+    let mut next_helper = false;
 
     // Parse the payload while there's more data
     while !payload.is_empty() {
         // Get the opcode
         let op: u8 = consume!(payload, u8).0;
+        let helper = std::mem::take(&mut next_helper);
 
         // Handle each opcode
         match op {
             0x00 => { // Exec32
-                T::exec(pid, tid, consume!(payload, u32).0 as u64, trace)
+                let pc = consume!(payload, u32).0 as u64;
+                marks.history.exec(pc);
+                T::exec(pid, tid, pc, trace)
             },
             0x80 => { // Exec64
-                T::exec(pid, tid, consume!(payload, u64).0, trace)
+                let pc = consume!(payload, u64).0;
+                marks.history.exec(pc);
+                T::exec(pid, tid, pc, trace)
+            },
+
+            0x08 => { // ExecBytes32
+                let pc = consume!(payload, u32).0 as u64;
+                marks.history.exec(pc);
+                code.with(pc, |x| T::exec_with_bytes(pid, tid, pc, x, trace))
+            },
+            0x88 => { // ExecBytes64
+                let pc = consume!(payload, u64).0;
+                marks.history.exec(pc);
+                code.with(pc, |x| T::exec_with_bytes(pid, tid, pc, x, trace))
+            },
+
+            0x09 | 0x0a => { // ExecDynamic32, with code for 0x0a
+                let pc = consume!(payload, u32).0 as u64;
+                marks.history.exec(pc);
+                exec_dynamic::<T>(pid, tid, ci, code, pc, op == 0x0a, trace)
+            },
+            0x89 | 0x8a => { // ExecDynamic64, with code for 0x8a
+                let pc = consume!(payload, u64).0;
+                marks.history.exec(pc);
+                exec_dynamic::<T>(pid, tid, ci, code, pc, op == 0x8a, trace)
             },
 
             0x01 => { // Regs32
@@ -299,14 +281,77 @@ fn parse_payload<T: Cannoli>(pid: &T::PidContext, tid: &T::TidContext,
                 let pc   = consume!(payload, u32).0 as u64;
                 let regs = &payload[..size as usize];
                 payload = &payload[size as usize..];
-                T::regs(pid, tid, pc, regs, trace)
+                marks.history.exec(pc);
+                T::regs(pid, tid, pc, regs, trace);
+                check_syscall::<T>(pid, tid, syscalls, pc, regs, trace, marks)
             },
             0x81 => { // Regs64
                 let size = consume!(payload, u32).0;
                 let pc   = consume!(payload, u64).0;
                 let regs = &payload[..size as usize];
                 payload = &payload[size as usize..];
-                T::regs(pid, tid, pc, regs, trace)
+                marks.history.exec(pc);
+                T::regs(pid, tid, pc, regs, trace);
+                check_syscall::<T>(pid, tid, syscalls, pc, regs, trace, marks)
+            },
+
+            0x03 => { // ExecRegs32
+                let size = consume!(payload, u32).0;
+                let pc   = consume!(payload, u32).0 as u64;
+                let regs = payload.get(..size as usize)
+                    .ok_or(Error::BufferTruncated)?;
+                payload = &payload[size as usize..];
+                marks.history.exec(pc);
+                T::exec_with_regs(pid, tid, pc, regs, trace);
+                check_syscall::<T>(pid, tid, syscalls, pc, regs, trace, marks)
+            },
+            0x83 => { // ExecRegs64
+                let size = consume!(payload, u32).0;
+                let pc   = consume!(payload, u64).0;
+                let regs = payload.get(..size as usize)
+                    .ok_or(Error::BufferTruncated)?;
+                payload = &payload[size as usize..];
+                marks.history.exec(pc);
+                T::exec_with_regs(pid, tid, pc, regs, trace);
+                check_syscall::<T>(pid, tid, syscalls, pc, regs, trace, marks)
+            },
+
+            0x05..=0x07 => { // Edge32
+                let pc = consume!(payload, u32).0 as u64;
+                if op & 2 != 0 {
+                    marks.history.push(pc);
+                }
+                marks.edges.push(edges::Mark {
+                    pc,
+                    source: op & 1 != 0,
+                    target: op & 2 != 0,
+                });
+            },
+            0x85..=0x87 => { // Edge64
+                let pc = consume!(payload, u64).0;
+                if op & 2 != 0 {
+                    marks.history.push(pc);
+                }
+                marks.edges.push(edges::Mark {
+                    pc,
+                    source: op & 1 != 0,
+                    target: op & 2 != 0,
+                });
+            },
+
+            0x02 => { // RegFile32
+                let (size, pc, index) = consume!(payload, u32, u32, u32);
+                let regs = payload.get(..size as usize)
+                    .ok_or(Error::BufferTruncated)?;
+                payload = &payload[size as usize..];
+                T::reg_file(pid, tid, pc as u64, index as usize, regs, trace)
+            },
+            0x82 => { // RegFile64
+                let (size, pc, index) = consume!(payload, u32, u64, u32);
+                let regs = payload.get(..size as usize)
+                    .ok_or(Error::BufferTruncated)?;
+                payload = &payload[size as usize..];
+                T::reg_file(pid, tid, pc, index as usize, regs, trace)
             },
 
             0x30 => { // Mmap32
@@ -318,11 +363,22 @@ fn parse_payload<T: Cannoli>(pid: &T::PidContext, tid: &T::TidContext,
                     .map_err(Error::PathEncoding)?;
                 payload = &payload[path_len as usize..];
 
+                marks.maps.push(history::Map {
+                    base: addr as u64,
+                    len:  len as u64,
+                    file: (exec != 0 && anon == 0)
+                        .then(|| (path.into(), offset as u64)),
+                });
                 T::mmap(pid, tid, addr as u64, len as u64, anon != 0, read != 0,
                     write != 0, exec != 0, path, offset as u64, trace)
             },
             0x31 => { // Munmap32
                 let (addr, len) = consume!(payload, u32, u32);
+                marks.maps.push(history::Map {
+                    base: addr as u64,
+                    len:  len as u64,
+                    file: None,
+                });
                 T::munmap(pid, tid, addr as u64, len as u64, trace)
             },
             0xb0 => { // Mmap64
@@ -334,96 +390,261 @@ fn parse_payload<T: Cannoli>(pid: &T::PidContext, tid: &T::TidContext,
                     .map_err(Error::PathEncoding)?;
                 payload = &payload[path_len as usize..];
 
+                marks.maps.push(history::Map {
+                    base: addr,
+                    len,
+                    file: (exec != 0 && anon == 0)
+                        .then(|| (path.into(), offset)),
+                });
                 T::mmap(pid, tid, addr, len, anon != 0, read != 0,
                     write != 0, exec != 0, path, offset, trace)
             },
             0xb1 => { // Munmap64
                 let (addr, len) = consume!(payload, u64, u64);
+                marks.maps.push(history::Map { base: addr, len, file: None });
                 T::munmap(pid, tid, addr, len, trace)
             },
 
+            0x32 => { // Brk32
+                let (old, new) = consume!(payload, u32, u32);
+                T::heap(pid, tid, &heap::HeapEvent::Brk {
+                    old: old as u64,
+                    new: new as u64,
+                }, trace)
+            },
+            0x33 => { // Arena32
+                let (base, len) = consume!(payload, u32, u32);
+                T::heap(pid, tid, &heap::HeapEvent::Arena {
+                    base: base as u64,
+                    len:  len  as u64,
+                }, trace)
+            },
+            0xb2 => { // Brk64
+                let (old, new) = consume!(payload, u64, u64);
+                T::heap(pid, tid, &heap::HeapEvent::Brk { old, new }, trace)
+            },
+            0xb3 => { // Arena64
+                let (base, len) = consume!(payload, u64, u64);
+                T::heap(pid, tid, &heap::HeapEvent::Arena { base, len }, trace)
+            },
+
+            0x34 => { // Epoch, the same for every bitness
+                marks.epochs.push(consume!(payload, u64).0);
+            },
+            0x35 => { // Syscall site, the same for every bitness
+                let (pc, next) = consume!(payload, u64, u64);
+                syscalls.add(pc, next)
+            },
+            0x36 => { // Branch, the same for every bitness
+                let (pc, next) = consume!(payload, u64, u64);
+                branches.add(pc, next)
+            },
+            0x39 => { // Call or return, the same for every bitness
+                let (pc, kind) = consume!(payload, u64, u8);
+                let kind = match kind {
+                    0 => symbols::signatures::CallKind::Call,
+                    _ => symbols::signatures::CallKind::Return,
+                };
+                branches.add_call(pc, kind)
+            },
+            0x38 => { // Image, the same for every bitness
+                let len  = consume!(payload, u32).0 as usize;
+                let path = String::from_utf8_lossy(
+                    payload.get(..len).ok_or(Error::BufferTruncated)?);
+                payload = &payload[len..];
+                let argc = consume!(payload, u32).0;
+                let mut argv = Vec::new();
+                for _ in 0..argc {
+                    let len = consume!(payload, u32).0 as usize;
+                    argv.push(String::from_utf8_lossy(
+                        payload.get(..len).ok_or(Error::BufferTruncated)?)
+                        .into_owned());
+                    payload = &payload[len..];
+                }
+                T::exec_image(pid, tid, &path, &argv, trace)
+            },
+            0x3b => { // Signal, the same for every bitness
+                let (sig, from, to) = consume!(payload, i32, u64, u64);
+                T::signal(pid, tid, sig, from, to, trace)
+            },
+            0x3a => { // Helper, the same for every bitness
+                next_helper = true;
+            },
+            0x37 => { // Code, the same for every bitness
+                let (pc, len) = consume!(payload, u64, u8);
+                let bytes = payload.get(..len as usize)
+                    .ok_or(Error::BufferTruncated)?;
+                payload = &payload[len as usize..];
+                code.add(pc, bytes)
+            },
+
             0x11 => { // Read8_32
                 let (addr, val, pc) = consume!(payload, u32, u8, u32);
-                T::read(pid, tid, pc as u64, addr as u64, val as u64, 1, trace)
+                T::read(pid, tid, pc as u64, addr as u64,
+                    val as u64, 1, helper, trace)
             },
             0x12 => { // Read16_32
                 let (addr, val, pc) = consume!(payload, u32, u16, u32);
-                T::read(pid, tid, pc as u64, addr as u64, val as u64, 2, trace)
+                T::read(pid, tid, pc as u64, addr as u64,
+                    val as u64, 2, helper, trace)
             },
             0x14 => { // Read32_32
                 let (addr, val, pc) = consume!(payload, u32, u32, u32);
-                T::read(pid, tid, pc as u64, addr as u64, val as u64, 4, trace)
+                T::read(pid, tid, pc as u64, addr as u64,
+                    val as u64, 4, helper, trace)
             },
             0x18 => { // Read64_32
                 let (addr, val, pc) = consume!(payload, u32, u64, u32);
-                T::read(pid, tid, pc as u64, addr as u64, val as u64, 8, trace)
+                T::read(pid, tid, pc as u64, addr as u64,
+                    val as u64, 8, helper, trace)
             },
 
             0x21 => { // Write8_32
                 let (addr, val, pc) = consume!(payload, u32, u8, u32);
                 T::write(pid, tid, pc as u64, addr as u64,
-                    val as u64, 1, trace)
+                    val as u64, 1, helper, trace)
             },
             0x22 => { // Write16_32
                 let (addr, val, pc) = consume!(payload, u32, u16, u32);
                 T::write(pid, tid, pc as u64, addr as u64,
-                    val as u64, 2, trace)
+                    val as u64, 2, helper, trace)
             },
             0x24 => { // Write32_32
                 let (addr, val, pc) = consume!(payload, u32, u32, u32);
                 T::write(pid, tid, pc as u64, addr as u64,
-                    val as u64, 4, trace)
+                    val as u64, 4, helper, trace)
             },
             0x28 => { // Write64_32
                 let (addr, val, pc) = consume!(payload, u32, u64, u32);
                 T::write(pid, tid, pc as u64, addr as u64,
-                    val as u64, 8, trace)
+                    val as u64, 8, helper, trace)
             },
 
             0x91 => { // Read8_64
                 let (addr, val, pc) = consume!(payload, u64, u8, u64);
-                T::read(pid, tid, pc as u64, addr as u64, val as u64, 1, trace)
+                T::read(pid, tid, pc as u64, addr as u64,
+                    val as u64, 1, helper, trace)
             },
             0x92 => { // Read16_64
                 let (addr, val, pc) = consume!(payload, u64, u16, u64);
-                T::read(pid, tid, pc as u64, addr as u64, val as u64, 2, trace)
+                T::read(pid, tid, pc as u64, addr as u64,
+                    val as u64, 2, helper, trace)
             },
             0x94 => { // Read32_64
                 let (addr, val, pc) = consume!(payload, u64, u32, u64);
-                T::read(pid, tid, pc as u64, addr as u64, val as u64, 4, trace)
+                T::read(pid, tid, pc as u64, addr as u64,
+                    val as u64, 4, helper, trace)
             },
             0x98 => { // Read64_64
                 let (addr, val, pc) = consume!(payload, u64, u64, u64);
-                T::read(pid, tid, pc as u64, addr as u64, val as u64, 8, trace)
+                T::read(pid, tid, pc as u64, addr as u64,
+                    val as u64, 8, helper, trace)
             },
 
             0xa1 => { // Write8_64
                 let (addr, val, pc) = consume!(payload, u64, u8, u64);
                 T::write(pid, tid, pc as u64, addr as u64,
-                    val as u64, 1, trace)
+                    val as u64, 1, helper, trace)
             },
             0xa2 => { // Write16_64
                 let (addr, val, pc) = consume!(payload, u64, u16, u64);
                 T::write(pid, tid, pc as u64, addr as u64,
-                    val as u64, 2, trace)
+                    val as u64, 2, helper, trace)
             },
             0xa4 => { // Write32_64
                 let (addr, val, pc) = consume!(payload, u64, u32, u64);
                 T::write(pid, tid, pc as u64, addr as u64,
-                    val as u64, 4, trace)
+                    val as u64, 4, helper, trace)
             },
             0xa8 => { // Write64_64
                 let (addr, val, pc) = consume!(payload, u64, u64, u64);
                 T::write(pid, tid, pc as u64, addr as u64,
-                    val as u64, 8, trace)
+                    val as u64, 8, helper, trace)
+            },
+            0x51 => { // ReadAddr8_32
+                let (addr, pc) = consume!(payload, u32, u32);
+                T::read_addr(pid, tid, pc as u64, addr as u64, 1,
+                    helper, trace)
+            },
+            0x52 => { // ReadAddr16_32
+                let (addr, pc) = consume!(payload, u32, u32);
+                T::read_addr(pid, tid, pc as u64, addr as u64, 2,
+                    helper, trace)
+            },
+            0x54 => { // ReadAddr32_32
+                let (addr, pc) = consume!(payload, u32, u32);
+                T::read_addr(pid, tid, pc as u64, addr as u64, 4,
+                    helper, trace)
+            },
+            0x58 => { // ReadAddr64_32
+                let (addr, pc) = consume!(payload, u32, u32);
+                T::read_addr(pid, tid, pc as u64, addr as u64, 8,
+                    helper, trace)
+            },
+
+            0x61 => { // WriteAddr8_32
+                let (addr, pc) = consume!(payload, u32, u32);
+                T::write_addr(pid, tid, pc as u64, addr as u64, 1,
+                    helper, trace)
+            },
+            0x62 => { // WriteAddr16_32
+                let (addr, pc) = consume!(payload, u32, u32);
+                T::write_addr(pid, tid, pc as u64, addr as u64, 2,
+                    helper, trace)
+            },
+            0x64 => { // WriteAddr32_32
+                let (addr, pc) = consume!(payload, u32, u32);
+                T::write_addr(pid, tid, pc as u64, addr as u64, 4,
+                    helper, trace)
+            },
+            0x68 => { // WriteAddr64_32
+                let (addr, pc) = consume!(payload, u32, u32);
+                T::write_addr(pid, tid, pc as u64, addr as u64, 8,
+                    helper, trace)
+            },
+
+            0xd1 => { // ReadAddr8_64
+                let (addr, pc) = consume!(payload, u64, u64);
+                T::read_addr(pid, tid, pc, addr, 1, helper, trace)
+            },
+            0xd2 => { // ReadAddr16_64
+                let (addr, pc) = consume!(payload, u64, u64);
+                T::read_addr(pid, tid, pc, addr, 2, helper, trace)
+            },
+            0xd4 => { // ReadAddr32_64
+                let (addr, pc) = consume!(payload, u64, u64);
+                T::read_addr(pid, tid, pc, addr, 4, helper, trace)
+            },
+            0xd8 => { // ReadAddr64_64
+                let (addr, pc) = consume!(payload, u64, u64);
+                T::read_addr(pid, tid, pc, addr, 8, helper, trace)
+            },
+
+            0xe1 => { // WriteAddr8_64
+                let (addr, pc) = consume!(payload, u64, u64);
+                T::write_addr(pid, tid, pc, addr, 1, helper, trace)
+            },
+            0xe2 => { // WriteAddr16_64
+                let (addr, pc) = consume!(payload, u64, u64);
+                T::write_addr(pid, tid, pc, addr, 2, helper, trace)
+            },
+            0xe4 => { // WriteAddr32_64
+                let (addr, pc) = consume!(payload, u64, u64);
+                T::write_addr(pid, tid, pc, addr, 4, helper, trace)
             },
+            0xe8 => { // WriteAddr64_64
+                let (addr, pc) = consume!(payload, u64, u64);
+                T::write_addr(pid, tid, pc, addr, 8, helper, trace)
+            },
+
             0x40 => { // Branch32
                 let size = consume!(payload, u32).0;
                 let pc   = consume!(payload, u32).0 as u64;
                 let branch = consume!(payload, u8).0 != 0;
                 let regs = &payload[..size as usize];
                 payload = &payload[size as usize..];
-                T::branch(pid, tid, pc, branch, regs, trace)
+                T::branch(pid, tid, pc, branch, regs, trace);
+                check_syscall::<T>(pid, tid, syscalls, pc, regs, trace, marks)
             },
             0xc0 => { // Branch64
                 let size = consume!(payload, u32).0;
@@ -431,7 +652,17 @@ fn parse_payload<T: Cannoli>(pid: &T::PidContext, tid: &T::TidContext,
                 let branch = consume!(payload, u8).0 != 0;
                 let regs = &payload[..size as usize];
                 payload = &payload[size as usize..];
-                T::branch(pid, tid, pc, branch, regs, trace)
+                T::branch(pid, tid, pc, branch, regs, trace);
+                check_syscall::<T>(pid, tid, syscalls, pc, regs, trace, marks)
+            },
+
+            0x70 => { // Cmp32
+                let (pc, lhs, rhs, sz) = consume!(payload, u32, u32, u32, u8);
+                T::cmp(pid, tid, pc as u64, lhs as u64, rhs as u64, sz, trace)
+            },
+            0xf0 => { // Cmp64
+                let (pc, lhs, rhs, sz) = consume!(payload, u64, u64, u64, u8);
+                T::cmp(pid, tid, pc, lhs, rhs, sz, trace)
             },
             _ => {
                 // Invalid opcode
@@ -443,6 +674,98 @@ fn parse_payload<T: Cannoli>(pid: &T::PidContext, tid: &T::TidContext,
     Ok(())
 }
 
+/// How far behind processing the trace of a thread is, see
+/// [`Cannoli::pressure`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Pressure {
+    /// Less than half of the IPC buffers are waiting to be processed
+    Normal,
+
+    /// At least half of the IPC buffers are waiting to be processed
+    Elevated,
+
+    /// Almost every IPC buffer is waiting to be processed, the target thread
+    /// is about to stall until processing catches up
+    Critical,
+}
+
+impl Pressure {
+    /// Get the pressure when `pending` out of `total` buffers are waiting to
+    /// be processed
+    pub fn from_pending(pending: usize, total: usize) -> Self {
+        if pending * 8 >= total * 7 {
+            Pressure::Critical
+        } else if pending * 2 >= total {
+            Pressure::Elevated
+        } else {
+            Pressure::Normal
+        }
+    }
+}
+
+/// What to do when a callback of a [`Cannoli`] implementation panics, see
+/// [`Cannoli::PANIC_POLICY`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PanicPolicy {
+    /// Stop processing every client, including the clients of other tenants
+    /// of a shared server, and return the panic from [`create_cannoli`]
+    Abort,
+
+    /// Stop analyzing the client whose callback panicked and report the
+    /// panic to [`Cannoli::analysis_error`], other clients keep going. The
+    /// rest of its trace is drained without invoking any callback, so the
+    /// target thread keeps running, and [`Cannoli::thread_exit`] isn't
+    /// invoked for it
+    DropClient,
+
+    /// Drop the chunk of the trace that was being processed, report the
+    /// panic to [`Cannoli::analysis_error`] and keep going
+    SkipBatch,
+
+    /// Like [`PanicPolicy::SkipBatch`], but if the panic was in
+    /// [`Cannoli::trace`] the user's structure is also replaced with a fresh
+    /// one from [`Cannoli::init_tid`], as it may have been left in a broken
+    /// state. The new `TidContext` is dropped, the trace processing threads
+    /// keep sharing the existing one
+    Restart,
+}
+
+/// A panic in a callback of a [`Cannoli`] implementation
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AnalysisError {
+    /// Process ID of the target
+    pub pid: i32,
+
+    /// Thread ID of the target
+    pub tid: i32,
+
+    /// Set if the panic was in [`Cannoli::trace`], otherwise it was in one of
+    /// the callbacks executed in parallel
+    pub in_trace: bool,
+
+    /// Message the callback panicked with
+    pub message: String,
+}
+
+impl AnalysisError {
+    /// Create an error for `ci` from the payload of a caught panic
+    fn new(ci: &ClientInfo, in_trace: bool,
+            payload: Box<dyn Any + Send>) -> Self {
+        let message = if let Some(x) = payload.downcast_ref::<&str>() {
+            x.to_string()
+        } else if let Some(x) = payload.downcast_ref::<String>() {
+            x.clone()
+        } else {
+            "Box<dyn Any>".to_string()
+        };
+
+        Self { pid: ci.pid, tid: ci.tid, in_trace, message }
+    }
+}
+
+/// Set when a panic aborts the run, every client stops processing
+static ABORT: AtomicBool = AtomicBool::new(false);
+
 /// Information about a newly connected client
 #[derive(Debug, Clone)]
 pub struct ClientInfo {
@@ -461,9 +784,16 @@ pub struct ClientInfo {
     /// Process ID
     pub pid: i32,
 
-    /// Thread ID
+    /// Thread ID, as seen by the guest. QEMU user-mode runs every guest
+    /// thread on a host thread of its own and passes `gettid()` through, so
+    /// this is the TID the target itself reports for the thread
     pub tid: i32,
 
+    /// Index of the vCPU QEMU runs the thread on, its `cpu_index`, the
+    /// per-core identity of the trace, see [`cpus`]. `None` if QEMU doesn't
+    /// export it
+    pub cpu: Option<u32>,
+
     /// Parent comm, `/proc/ppid/comm`, this is the raw value read from `comm`
     /// and may include weird stuff like newlines
     pub pcomm: Option<String>,
@@ -471,6 +801,35 @@ pub struct ClientInfo {
     /// comm, `/proc/pid/comm`, this is the raw value read from `comm`
     /// and may include weird stuff like newlines
     pub comm: Option<String>,
+
+    /// Path of the main binary of the target, as the mapped file once QEMU
+    /// loaded it, and until then as canonicalized from QEMU's command line.
+    /// This is the file to load the symbols of the target from
+    pub exe: Option<String>,
+
+    /// Arguments of the target, starting with the one naming its main binary
+    pub argv: Vec<String>,
+
+    /// Environment of the target as `NAME=value` entries, QEMU's own
+    /// environment with its `-E` and `-U` options applied. Variables QEMU
+    /// gets from `QEMU_SET_ENV` and `QEMU_UNSET_ENV` aren't reflected
+    pub env: Vec<String>,
+
+    /// Working directory of the target
+    pub cwd: Option<String>,
+
+    /// Tenant the target belongs to, when serving several with
+    /// [`CannoliOpts::tenants`]
+    pub tenant: Option<Arc<tenants::Tenant>>,
+}
+
+impl ClientInfo {
+    /// Get the value of the environment variable `name` of the target
+    pub fn var(&self, name: &str) -> Option<&str> {
+        self.env.iter().rev().find_map(|x| {
+            x.strip_prefix(name)?.strip_prefix('=')
+        })
+    }
 }
 
 /// Handle a newly connected client. This is run on a new thread each time a
@@ -484,14 +843,27 @@ fn handle_client<T>(
             HashMap<i32, Arc<dyn Any + Send + Sync>>>> =
         LazyLock::new(|| Mutex::new(HashMap::new()));
 
+    /// PIDs of every process which connected, so a process which exec()s
+    /// isn't reported as forked again
+    static SEEN_PIDS: LazyLock<Mutex<HashSet<i32>>> =
+        LazyLock::new(Default::default);
+
     /// Storage for the mini state-machine we use to sequence traces
     struct State<T: Cannoli + 'static> {
         /// Next sequence number we are looking for to report traces
         next_seq: u64,
 
         /// Vector of traces, maintained sorted, with a sequence identifer in
-        /// the first part of the tuple
-        traces: Vec<(u64, Vec<T::Trace>)>,
+        /// the first part of the tuple. Traces whose processing panicked are
+        /// `None`, they're skipped. The last part holds the epochs and edge
+        /// hooks of the trace
+        traces: Vec<Sequenced<T::Trace>>,
+
+        /// Pairs edge hooks into edges across traces
+        edges: edges::Edges,
+
+        /// Pairs `mprotect()` entries with their exits across traces
+        protections: syscalls::Protections,
 
         /// User's [`Cannoli`]-implementing type
         user: T,
@@ -501,69 +873,97 @@ fn handle_client<T>(
     let pipe = RecvPipe::<CHUNK_SIZE, NUM_BUFFERS>::open(ci.uid)
         .map_err(Error::OpenPipe)?;
 
-    // Make the stream nonblocking
-    stream.set_nonblocking(true)
-        .map_err(Error::SetNonblocking)?;
+    // Allow sending control messages to the client
+    control::register(ci, &stream).map_err(Error::Control)?;
 
-    // Scratch buffer to check for socket close
-    let mut scratch_buffer = [0u8; 1];
+    // Set once the client closed the connection
+    let closed = &AtomicBool::new(false);
+
+    // Set once a panic dropped the client, see `PanicPolicy::DropClient`
+    let dropped = &AtomicBool::new(false);
 
     // Get a reference to the pipe so we can `move` the reference into the
     // threads we create
     let pipe = &pipe;
 
-    // Get the PID context
-    let any_pid_context: Arc<dyn Any + Send + Sync> = {
+    // Get the PID context, and the system call sites, branches, code and
+    // block histories shared with the other threads of the process
+    let (any_pid_context, syscalls, branches, code, histories):
+            (Arc<dyn Any + Send + Sync>, _, _, _, _) = {
         // Get the contexts
         let mut contexts = PID_CONTEXTS.lock().unwrap();
 
+        // A new process of a traced parent was forked from it
+        if SEEN_PIDS.lock().unwrap().insert(ci.pid) &&
+                contexts.contains_key(&ci.ppid) {
+            T::fork(ci.ppid, ci.pid);
+        }
+
         // Either get the existing context or create a new one
-        contexts.entry(ci.pid).or_insert_with(|| {
+        (contexts.entry(ci.pid).or_insert_with(|| {
             T::init_pid(ci)
-        }).clone()
+        }).clone(), syscalls::Sites::get(ci), edges::Branches::get(ci),
+            code::Code::get(ci), history::Histories::get(ci))
     };
+    let (syscalls, branches, code, histories) =
+        (&*syscalls, &*branches, &*code, &*histories);
 
     // Get the PID context with the correct type
     let pid_context = any_pid_context.downcast_ref::<T::PidContext>().unwrap();
 
+    // A new thread of the process
+    T::thread_start(pid_context, ci.tid);
+
     // Create a new instance of the user's structure
     let (user_type, user_ctxt) = T::init_tid(&*pid_context, ci);
     let user_ctxt = &user_ctxt;
 
     // Create the sequencing state machine
     let state = Mutex::new(State {
-        next_seq: 0,
-        traces:   Vec::new(),
-        user:     user_type,
+        next_seq:    0,
+        traces:      Vec::new(),
+        edges:       Default::default(),
+        protections: Default::default(),
+        user:        user_type,
     });
     let state = &state;
 
+    // Last pressure reported to the user, as a `Pressure` discriminant
+    let pressure = &AtomicU8::new(Pressure::Normal as u8);
+
     // Create a thread scope
-    std::thread::scope(|s| -> Result<()> {
+    let result = std::thread::scope(|s| -> Result<()> {
         // Holds the handles to the threads we create
         let mut threads = Vec::new();
 
+        // Receive control replies on a thread of their own, so they are never
+        // stuck behind trace processing. Our IPC mechanism has no way of
+        // telling if the remote process died, the connection closing does
+        let replies = stream.try_clone().map_err(Error::CloneSocket)?;
+        s.spawn(move || {
+            control::receive(replies);
+            closed.store(true, Ordering::Relaxed);
+        });
+
         // Create the number of threads requested
         for _ in 0..num_threads {
-            // Clone the stream so we can checked for a closed socked on
-            // multiple threads
-            let mut stream = stream.try_clone().map_err(Error::CloneSocket)?;
-
             // Create the IPC reader thread!
             threads.push(s.spawn(move || -> Result<()> {
                 // Buffer for trace results
                 let mut trace = Vec::new();
 
+                // Epochs and edge hooks in the payload
+                let mut marks = Marks::default();
+
                 // Current ticket for getting a trace
                 let mut ticket = Some(pipe.request_ticket());
 
                 // The last time this thread read data
                 let mut last_data = Instant::now();
 
-                // Loop forever while the socket is open. This allows us to
-                // check if the remote process died, our IPC mechanism doesn't
-                // have a way of checking that
-                while !matches!(stream.read(&mut scratch_buffer), Ok(0)) {
+                // Loop forever while the socket is open
+                while !ABORT.load(Ordering::Relaxed) &&
+                        !closed.load(Ordering::Relaxed) {
                     // If we haven't gotten any data recent, sleep a bit before
                     // hot polling. This prevents us completely eating 100% CPU
                     // when there are threads connected to us but not streaming
@@ -574,16 +974,27 @@ fn handle_client<T>(
 
                     // Poll via shared memory while we keep getting stuff
                     let mut hot_poll = 10000;
-                    while hot_poll > 0 {
+                    while hot_poll > 0 && !ABORT.load(Ordering::Relaxed) {
                         // Update that we did a hot poll
                         hot_poll -= 1;
 
                         // Attempt to get a payload from the pipe, parse it if
-                        // there was one
+                        // there was one. Panics in the user's callbacks are
+                        // caught so the buffer still goes back to QEMU
                         let (new_ticket, payload) = pipe.try_recv(
                             ticket.take().unwrap(),
-                            |x| parse_payload::<T>(
-                                &*pid_context, user_ctxt, &mut trace, x));
+                            |x| if dropped.load(Ordering::Relaxed) {
+                                Ok(None)
+                            } else {
+                                match std::panic::catch_unwind(
+                                        AssertUnwindSafe(|| parse_payload::<T>(
+                                            &*pid_context, user_ctxt, ci,
+                                            &mut trace, &mut marks, syscalls,
+                                            branches, code, x))) {
+                                    Ok(result) => result.map(|()| None),
+                                    Err(panic) => Ok(Some(panic)),
+                                }
+                            });
 
                         // Replace the ticket with the new ticket
                         ticket = Some(new_ticket);
@@ -592,12 +1003,52 @@ fn handle_client<T>(
                         if let Some(payload) = payload {
                             // It's possible payload parsing failed, so check
                             // the error
-                            let (seq, _) = payload?;
+                            let (seq, panic) = payload?;
+
+                            // The chunk is dropped if a callback panicked
+                            let skipped = match panic {
+                                Some(panic) => {
+                                    let err = AnalysisError::new(
+                                        ci, false, panic);
+                                    match T::PANIC_POLICY {
+                                        PanicPolicy::Abort => {
+                                            ABORT.store(true,
+                                                Ordering::Relaxed);
+                                            return Err(Error::Panic(err));
+                                        }
+                                        PanicPolicy::DropClient => dropped
+                                            .store(true, Ordering::Relaxed),
+                                        PanicPolicy::SkipBatch |
+                                        PanicPolicy::Restart => {}
+                                    }
+                                    T::analysis_error(
+                                        &*pid_context, user_ctxt, &err);
+                                    true
+                                }
+                                None => false,
+                            };
+
+                            // Let the user know when we're falling behind, or
+                            // catching up again
+                            let level = Pressure::from_pending(
+                                pipe.pending(), NUM_BUFFERS);
+                            if pressure.swap(level as u8, Ordering::Relaxed)
+                                    != level as u8 {
+                                T::pressure(&*pid_context, user_ctxt, level);
+                            }
 
                             // Refresh hot polling
                             hot_poll = 10000;
                             last_data = Instant::now();
 
+                            // A dropped client only has its buffers handed
+                            // back to QEMU
+                            if dropped.load(Ordering::Relaxed) {
+                                trace.clear();
+                                marks = Marks::default();
+                                continue;
+                            }
+
                             // Yay, we got a trace!
                             //
                             // This isn't super optimized, but due to the
@@ -616,7 +1067,9 @@ fn handle_client<T>(
 
                             // Insert the trace!
                             let cap = trace.capacity();
-                            state.traces.insert(idx, (seq, trace));
+                            state.traces.insert(idx, (seq,
+                                (!skipped).then_some(trace),
+                                std::mem::take(&mut marks)));
 
                             // Report traces in order
                             while !state.traces.is_empty() &&
@@ -626,11 +1079,77 @@ fn handle_client<T>(
                                     state.next_seq.wrapping_add(1);
 
                                 // Remove the entry from traces
-                                let trace = state.traces.remove(0).1;
-
-                                // Report the trace
-                                state.user.trace(&*pid_context,
-                                    user_ctxt, &trace);
+                                let (_, trace, marks) =
+                                    state.traces.remove(0);
+
+                                // Keep the last blocks of the thread
+                                histories.record(ci.tid, trace.as_ref()
+                                    .map(|_| &marks.history), &marks.maps);
+
+                                // Report the trace, its edges, and the epochs
+                                // which are complete with it. Edges don't
+                                // span skipped traces
+                                let State { user, edges, protections, .. } =
+                                    &mut *state;
+                                let result = std::panic::catch_unwind(
+                                    AssertUnwindSafe(|| {
+                                        if let Some(trace) = &trace {
+                                            user.trace(&*pid_context,
+                                                user_ctxt, trace);
+                                            for mark in &marks.edges {
+                                                let Some(edge) = edges.mark(
+                                                    mark, branches)
+                                                    else { continue };
+                                                user.edge(&*pid_context,
+                                                    user_ctxt, edge.0, edge.1,
+                                                    edge.2);
+                                                calls::report(user,
+                                                    &*pid_context, user_ctxt,
+                                                    branches, edge);
+                                            }
+                                            for mark in &marks.syscalls {
+                                                let Some((base, len, prot)) =
+                                                    protections.mark(mark)
+                                                    else { continue };
+                                                user.mprotect(&*pid_context,
+                                                    user_ctxt, base, len,
+                                                    prot);
+                                            }
+                                        } else {
+                                            edges.reset();
+                                            protections.reset();
+                                        }
+                                        for &id in &marks.epochs {
+                                            user.epoch_complete(
+                                                &*pid_context, user_ctxt, id);
+                                        }
+                                    }));
+                                let Err(panic) = result else { continue };
+
+                                let err = AnalysisError::new(ci, true, panic);
+                                match T::PANIC_POLICY {
+                                    PanicPolicy::Abort => {
+                                        ABORT.store(true, Ordering::Relaxed);
+                                        return Err(Error::Panic(err));
+                                    }
+                                    PanicPolicy::DropClient => {
+                                        dropped.store(true, Ordering::Relaxed);
+                                    }
+                                    PanicPolicy::SkipBatch => {}
+                                    PanicPolicy::Restart => {
+                                        state.user = T::init_tid(
+                                            &*pid_context, ci).0;
+                                    }
+                                }
+                                T::analysis_error(
+                                    &*pid_context, user_ctxt, &err);
+
+                                // Nothing more is reported for a dropped
+                                // client
+                                if dropped.load(Ordering::Relaxed) {
+                                    state.traces.clear();
+                                    break;
+                                }
                             }
 
                             // Drop the lock and re-allocate the trace buffer
@@ -645,12 +1164,24 @@ fn handle_client<T>(
         }
 
         // Check threads for errors
-        for thr in threads {
-            thr.join().ok().ok_or(Error::JoinThread)??;
-        }
+        let result = threads.into_iter()
+            .try_for_each(|thr| thr.join().ok().ok_or(Error::JoinThread)?);
 
-        Ok(())
-    })?;
+        // The reply thread only stops once the connection is closed, which
+        // it isn't when processing stopped because of an abort
+        let _ = stream.shutdown(Shutdown::Both);
+        result
+    });
+
+    // The client is gone, stop sending it control messages
+    control::unregister(ci);
+    result?;
+
+    // Every trace of the thread was processed, unless a panic dropped it
+    if !dropped.load(Ordering::Relaxed) {
+        state.lock().unwrap().user.thread_exit(pid_context, user_ctxt,
+            ci.tid);
+    }
 
     // Potentially delete the PID from the global database, we have to detect
     // when all threads are exited, this is kinda gross but whatever
@@ -662,7 +1193,19 @@ fn handle_client<T>(
         // this pid.
         let mut contexts = PID_CONTEXTS.lock().unwrap();
         if Arc::strong_count(&contexts[&ci.pid]) == 1 {
-            contexts.remove(&ci.pid);
+            let context = contexts.remove(&ci.pid).unwrap();
+            drop(contexts);
+
+            // The process is gone, with the status of its exit system call
+            // if one was seen
+            let code = syscalls::Sites::get(ci).exit_code();
+            if let Some(context) = context.downcast_ref::<T::PidContext>() {
+                T::exit(context, code);
+            }
+            syscalls::Sites::remove(ci.pid);
+            edges::Branches::remove(ci.pid);
+            code::Code::remove(ci.pid);
+            history::Histories::remove(ci.pid);
         }
     }
 
@@ -670,6 +1213,118 @@ fn handle_client<T>(
     Ok(())
 }
 
+/// Options of a Cannoli server, see [`create_cannoli_with`]
+///
+/// Instrumenting less is the cheapest way to keep up with a target: code
+/// outside of the included ranges and modules isn't hooked at all, so it
+/// doesn't fill the pipe. Tracing only a library of interest rather than the
+/// whole libc looks like
+///
+/// ```no_run
+/// # use cannoli::{Cannoli, CannoliOpts, create_cannoli_with};
+/// # fn run<T: Cannoli + 'static>() where T::PidContext: 'static {
+/// create_cannoli_with::<T>(CannoliOpts::new(4).module("libtarget"))
+///     .unwrap();
+/// # }
+/// ```
+///
+/// The filters are applied by jitters which decide what to hook with
+/// `jitter::control`, like the default one does. Use
+/// [`control::set_filters`] to change them during the run
+#[derive(Clone, Debug)]
+pub struct CannoliOpts {
+    /// Number of processing threads for every connection
+    threads: usize,
+
+    /// Filters pushed to every target, `None` to leave them to the jitter
+    filters: Option<control::Filters>,
+
+    /// Tenants served in daemon mode, `None` to serve only our own user
+    tenants: Option<Arc<tenants::Tenants>>,
+
+    /// Socket to accept connections on, `None` to bind the default address
+    listener: Option<Arc<TcpListener>>,
+
+    /// Whether to notify systemd of our state
+    notify: bool,
+}
+
+impl CannoliOpts {
+    /// Create options for `threads` processing threads for every
+    /// connection, which instrument everything
+    pub fn new(threads: usize) -> Self {
+        Self {
+            threads,
+            filters:  None,
+            tenants:  None,
+            listener: None,
+            notify:   false,
+        }
+    }
+
+    /// Push `filters` to every target
+    pub fn filters(mut self, filters: control::Filters) -> Self {
+        self.filters = Some(filters);
+        self
+    }
+
+    /// Push the filters of `profile` to every target, see [`profile`]. The
+    /// code ranges and modules given so far are kept
+    pub fn profile(mut self, profile: profile::Profile) -> Self {
+        let old = self.filters.take().unwrap_or_default();
+        self.filters = Some(control::Filters {
+            include: old.include,
+            modules: old.modules,
+            exclude: old.exclude,
+            ..profile.filters()
+        });
+        self
+    }
+
+    /// Instrument code in `range`, see [`control::Filters::include`]
+    pub fn include(mut self, range: std::ops::Range<u64>) -> Self {
+        self.filters.get_or_insert_with(Default::default)
+            .include.push([range.start, range.end]);
+        self
+    }
+
+    /// Never instrument code in `range`, see [`control::Filters::exclude`]
+    pub fn exclude(mut self, range: std::ops::Range<u64>) -> Self {
+        self.filters.get_or_insert_with(Default::default)
+            .exclude.push([range.start, range.end]);
+        self
+    }
+
+    /// Instrument code in the module `name`, see
+    /// [`control::Filters::modules`]
+    pub fn module(mut self, name: impl Into<String>) -> Self {
+        self.filters.get_or_insert_with(Default::default)
+            .modules.push(name.into());
+        self
+    }
+
+    /// Serve the connections of `tenants` rather than only those of our own
+    /// user, see [`tenants`]
+    pub fn tenants(mut self, tenants: tenants::Tenants) -> Self {
+        self.tenants = Some(Arc::new(tenants));
+        self
+    }
+
+    /// Accept connections on `listener` rather than on `127.0.0.1:11458`,
+    /// eg. the socket passed by systemd, see [`systemd::listener`]
+    pub fn listener(mut self, listener: TcpListener) -> Self {
+        self.listener = Some(Arc::new(listener));
+        self
+    }
+
+    /// Notify systemd once we accept connections, feed its watchdog and
+    /// notify it when we stop, see [`systemd`]
+    pub fn notify(mut self) -> Self {
+        self.notify = true;
+        self
+    }
+}
+
 /// Create a new Cannoli server. This will spin up the required processing
 /// needed to talk with QEMU and deserialize messages, while dispatching
 /// callbacks to a user-controlled `user_self`
@@ -679,38 +1334,176 @@ fn handle_client<T>(
 pub fn create_cannoli<T>(threads: usize) -> Result<()>
         where T: Cannoli + 'static,
               T::PidContext: Send + Sync + 'static {
+    create_cannoli_with::<T>(CannoliOpts::new(threads))
+}
+
+/// Create a new Cannoli server with `opts`, see [`create_cannoli`]
+pub fn create_cannoli_with<T>(opts: CannoliOpts) -> Result<()>
+        where T: Cannoli + 'static,
+              T::PidContext: Send + Sync + 'static {
+    let threads = opts.threads;
+    if let Some(filters) = opts.filters {
+        control::set_filters(filters).map_err(Error::Control)?;
+    }
+
     // Create socket, waiting for clients to connect and inform us about some
     // memory regions
-    let listener = TcpListener::bind("127.0.0.1:11458")
-        .map_err(Error::Bind)?;
+    let listener = match opts.listener {
+        Some(listener) => listener,
+        None => Arc::new(TcpListener::bind("127.0.0.1:11458")
+            .map_err(Error::Bind)?),
+    };
+
+    // Poll for connections, so we notice when a panic aborts the run
+    listener.set_nonblocking(true).map_err(Error::SetNonblocking)?;
+
+    // Tell systemd we're up, failing to is only worth a warning
+    let mut notifier = opts.notify.then(systemd::Notifier::from_env);
+    if let Some(Err(err)) = notifier.as_ref().map(|x| x.notify("READY=1")) {
+        eprintln!("Failed to notify systemd: {err}");
+    }
+
+    // Token the jitters have to greet us with, if any, or the tokens of the
+    // tenants in daemon mode
+    let token = auth::token();
+    let token = token.as_deref();
+    let tenants = opts.tenants.as_deref();
+
+    // Panic which aborted the run
+    let failure = Mutex::new(None);
+    let failure = &failure;
 
     // Create a new thread scope for handling connections
     std::thread::scope(|scope| {
         // Wait for connections
-        for stream in listener.incoming() {
+        while !ABORT.load(Ordering::Relaxed) {
+            if let Some(Err(err)) = notifier.as_mut().map(|x| x.tick()) {
+                eprintln!("Failed to feed the systemd watchdog: {err}");
+            }
+
+            let stream = match listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => {
+                    std::thread::sleep(Duration::from_millis(20));
+                    continue;
+                }
+                Err(err) => panic!("Failed to get TCP stream: {err}"),
+            };
+
             // Spawn a thread on new connections
             scope.spawn(move || {
                 // Get access to the stream
-                let mut stream = stream.expect("Failed to get TCP stream");
+                let mut stream = stream;
+                stream.set_nonblocking(false)
+                    .expect("Failed to make TCP stream blocking");
+
+                // Only other users' processes are turned away before they
+                // get to say anything, tenants say who they are with their
+                // token
+                let uid = auth::peer_uid(&stream);
+                match uid {
+                    _ if tenants.is_some() => {}
+                    Some(uid) if auth::check_uid(uid) => {}
+                    uid => {
+                        eprintln!("Rejected connection from UID {uid:?}");
+                        return;
+                    }
+                }
 
-                // Get the header
+                // Get the header, anyone can connect so a short greeting is
+                // only a rejection
                 let mut header: MaybeUninit<ClientConn> =
                     MaybeUninit::uninit();
-                stream.read_exact(unsafe {
+                if let Err(err) = stream.read_exact(unsafe {
                     core::slice::from_raw_parts_mut(
                         header.as_mut_ptr() as *mut u8,
                         core::mem::size_of_val(&header))
-                }).expect("Failed to get client header");
+                }) {
+                    eprintln!("Rejected connection without a header: {err}");
+                    return;
+                }
 
                 // Get the actual header now that it's initialized
                 let header: ClientConn = unsafe { header.assume_init() };
 
-                // Get the pcomm and comm
+                // Get the pcomm and comm, bounded like the token
+                if header.pcomm_len > MAX_COMM_LEN ||
+                        header.comm_len > MAX_COMM_LEN {
+                    eprintln!("Rejected comm of {} and {} bytes from PID {}",
+                        header.pcomm_len, header.comm_len, header.pid);
+                    return;
+                }
                 let mut comm =
                     vec![0u8; header.pcomm_len as usize +
                               header.comm_len  as usize];
-                stream.read_exact(&mut comm)
-                    .expect("Failed to get client pcomm and comm");
+                if let Err(err) = stream.read_exact(&mut comm) {
+                    eprintln!("Rejected PID {} without a comm: {err}",
+                        header.pid);
+                    return;
+                }
+
+                // Get the token and check it, the jitter is told it was
+                // accepted before anything else is sent to it
+                if header.token_len > auth::MAX_TOKEN_LEN {
+                    eprintln!("Rejected token of {} bytes from PID {}",
+                        header.token_len, header.pid);
+                    return;
+                }
+                let mut got = vec![0u8; header.token_len as usize];
+                if let Err(err) = stream.read_exact(&mut got) {
+                    eprintln!("Rejected PID {} without a token: {err}",
+                        header.pid);
+                    return;
+                }
+
+                // Get the image the target runs, bounded like the token
+                let image_len = [header.exe_len, header.cwd_len,
+                    header.argv_len, header.env_len].iter()
+                    .try_fold(0u32, |acc, &x| acc.checked_add(x))
+                    .filter(|&x| x <= MAX_IMAGE_LEN);
+                let Some(image_len) = image_len else {
+                    eprintln!("Rejected image of PID {} over {} bytes",
+                        header.pid, MAX_IMAGE_LEN);
+                    return;
+                };
+                let mut image = vec![0u8; image_len as usize];
+                if let Err(err) = stream.read_exact(&mut image) {
+                    eprintln!("Rejected PID {} without an image: {err}",
+                        header.pid);
+                    return;
+                }
+
+                let admission = match tenants {
+                    Some(tenants) => match tenants.admit(&got, uid,
+                            header.pid) {
+                        Ok(admission) => Some(admission),
+                        Err(err) => {
+                            eprintln!("Rejected PID {}: {err}", header.pid);
+                            return;
+                        }
+                    },
+                    None if !auth::check_token(token, &got) => {
+                        eprintln!("Rejected invalid token from PID {}",
+                            header.pid);
+                        return;
+                    }
+                    None => None,
+                };
+                if let Err(err) = stream.write_all(&[auth::ACCEPTED]) {
+                    eprintln!("Failed to accept PID {}: {err}", header.pid);
+                    return;
+                }
+
+                // Split the image into its parts, lossy as they're only
+                // informative
+                let (exe, rest) = image.split_at(header.exe_len as usize);
+                let (cwd, rest) = rest.split_at(header.cwd_len as usize);
+                let (argv, env) = rest.split_at(header.argv_len as usize);
+                let string = |x: &[u8]| String::from_utf8_lossy(x).into_owned();
+                let list = |x: &[u8]| x.strip_suffix(&[0])
+                    .map_or(Vec::new(), |x| {
+                        x.split(|&x| x == 0).map(string).collect()
+                    });
 
                 // Construct client information
                 let ci = ClientInfo {
@@ -727,6 +1520,7 @@ pub fn create_cannoli<T>(threads: usize) -> Result<()>
                     ppid: header.ppid,
                     pid:  header.pid,
                     tid:  header.tid,
+                    cpu:  u32::try_from(header.cpu).ok(),
 
                     pcomm: std::str::from_utf8(
                         &comm[..header.pcomm_len as usize])
@@ -734,17 +1528,36 @@ pub fn create_cannoli<T>(threads: usize) -> Result<()>
                     comm: std::str::from_utf8(
                         &comm[header.pcomm_len as usize..])
                         .ok().map(|x| x.to_string()),
+
+                    // Image the target runs
+                    exe:  Some(string(exe)).filter(|x| !x.is_empty()),
+                    argv: list(argv),
+                    env:  list(env),
+                    cwd:  Some(string(cwd)).filter(|x| !x.is_empty()),
+
+                    // Tenant of the connection, if we're serving several
+                    tenant: admission.as_ref().map(|x| x.tenant().clone()),
                 };
 
-                // Handle the client
-                handle_client::<T>(stream, threads, &ci)
-                    .expect("Failed to handle client");
+                // Handle the client, a panic aborts the run
+                match handle_client::<T>(stream, threads, &ci) {
+                    Err(Error::Panic(err)) => {
+                        failure.lock().unwrap().get_or_insert(err);
+                    }
+                    result => result.expect("Failed to handle client"),
+                }
             });
         }
 
         // All done!
         Ok(())
-    })
+    })?;
+    if let Some(Err(err)) = notifier.map(|x| x.notify("STOPPING=1")) {
+        eprintln!("Failed to notify systemd: {err}");
+    }
+
+    let failure = failure.lock().unwrap().take();
+    failure.map_or(Ok(()), |x| Err(Error::Panic(x)))
 }
 
 /// Trait which must be implemented by a user to implement their hooks and
@@ -770,6 +1583,23 @@ pub trait Cannoli: Send + Sync {
     /// TL;DR: This context is target-TID specific
     type TidContext: Sync;
 
+    /// What to do when one of the callbacks panics. By default only the
+    /// client it panicked for is dropped, see [`PanicPolicy::DropClient`]
+    const PANIC_POLICY: PanicPolicy = PanicPolicy::DropClient;
+
+    /// Read the code of dynamic code from the guest with
+    /// [`control::read_memory`] the first time each instruction executes,
+    /// for [`Cannoli::exec_dynamic`]. Off by default, as every read waits for
+    /// a round trip to the target, see [`code`]
+    const CAPTURE_DYNAMIC_CODE: bool = false;
+
+    /// Get a handle to toggle the hooks of the targets while they run, eg.
+    /// `Self::control().set_exec(false)` from [`Cannoli::trace`] once the
+    /// interesting part of a run is over
+    fn control() -> control::Control where Self: Sized {
+        control::Control
+    }
+
     /// Called when the first thread of a given target PID is connected, this
     /// creates the `PidContext` which is then shared with all threads
     /// for a given PID
@@ -781,6 +1611,43 @@ pub trait Cannoli: Send + Sync {
     fn init_tid(pid: &Self::PidContext, ci: &ClientInfo)
         -> (Self, Self::TidContext) where Self: Sized;
 
+    /// Invoked when the guest thread `tid` of a process connects, before
+    /// [`Cannoli::init_tid`] creates its contexts. Every thread of the guest
+    /// has a trace of its own, see [`ClientInfo::tid`]
+    fn thread_start(_pid: &Self::PidContext, _tid: i32) where Self: Sized {}
+
+    /// Invoked when the guest thread `tid` exited, once every trace of it
+    /// was processed, with the `Self` of the thread. This is the last
+    /// callback of the thread, and precedes [`Cannoli::exit`] for the last
+    /// thread of a process
+    fn thread_exit(&mut self, _pid: &Self::PidContext,
+        _ctx: &Self::TidContext, _tid: i32) {}
+
+    /// Invoked when the first thread of the process `child` connects, if it
+    /// was forked from the process `parent` while `parent` was traced.
+    /// Invoked before [`Cannoli::init_pid`] of the child, so the process
+    /// tree of a multi-process target can be tracked
+    fn fork(_parent: i32, _child: i32) where Self: Sized {}
+
+    /// Invoked when the process starts running an image, once at launch and
+    /// again after every `execve()`, with the path of the main binary and
+    /// the arguments of the guest. `argv` is empty if the jitter couldn't
+    /// find them in QEMU's command line
+    ///
+    /// Executed on multiple threads, before any instruction of the image
+    /// executes, see [`Cannoli::exec`]
+    fn exec_image(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            _path: &str, _argv: &[String], _trace: &mut Vec<Self::Trace>) {}
+
+    /// Invoked once the last thread of a process is gone, after every trace
+    /// of it was processed and before its `PidContext` is dropped. `code` is
+    /// the status the process passed to `exit_group()` (or `exit()` of its
+    /// last thread), which is only known with
+    /// [`control::Filters::syscalls`] set, and `None` when it was killed.
+    /// The last blocks of its threads are still there, see
+    /// [`history::process`]
+    fn exit(_pid: &Self::PidContext, _code: Option<i32>) where Self: Sized {}
+
     /// Invoked when a PC execution opcode was lifted from the trace
     ///
     /// Executed on multiple threads
@@ -799,6 +1666,53 @@ pub trait Cannoli: Send + Sync {
     fn exec(_pid: &Self::PidContext, _tid: &Self::TidContext, _pc: u64,
             _trace: &mut Vec<Self::Trace>) {}
 
+    /// Invoked instead of [`Cannoli::exec`] when an instruction executes
+    /// with [`control::Filters::exec_regs`] set, with the general purpose
+    /// registers before it executed. `regs` holds the first
+    /// [`control::Filters::exec_reg_count`] of them, in QEMU's order and the
+    /// host's byte order, or all of them. Comparing the registers of
+    /// consecutive instructions gives the register deltas of time travel
+    /// debuggers
+    ///
+    /// By default this only invokes [`Cannoli::exec`], so analyses which
+    /// don't look at the registers don't change with the mode
+    ///
+    /// Executed on multiple threads, see [`Cannoli::exec`]
+    fn exec_with_regs(pid: &Self::PidContext, tid: &Self::TidContext,
+            pc: u64, _regs: &[u8], trace: &mut Vec<Self::Trace>) {
+        Self::exec(pid, tid, pc, trace)
+    }
+
+    /// Invoked instead of [`Cannoli::exec`] when an instruction executes
+    /// with [`control::Filters::exec_bytes`] set, with the code of the
+    /// instruction. `bytes` starts with the instruction and may hold the
+    /// start of the instructions after it, see [`code`] for how much of
+    /// them. It's empty in the rare case another thread of the process
+    /// lifted the instruction and its announcement wasn't processed yet
+    ///
+    /// By default this only invokes [`Cannoli::exec`]
+    ///
+    /// Executed on multiple threads, see [`Cannoli::exec`]
+    fn exec_with_bytes(pid: &Self::PidContext, tid: &Self::TidContext,
+            pc: u64, _bytes: &[u8], trace: &mut Vec<Self::Trace>) {
+        Self::exec(pid, tid, pc, trace)
+    }
+
+    /// Invoked instead of [`Cannoli::exec`] and [`Cannoli::exec_with_bytes`]
+    /// when an instruction of dynamic code executes, code in memory which
+    /// was mapped anonymous and executable (eg. by a JIT in the target), see
+    /// [`address_space`]. `bytes` is the code of the instruction like for
+    /// [`Cannoli::exec_with_bytes`], with [`control::Filters::exec_bytes`]
+    /// set or with [`Cannoli::CAPTURE_DYNAMIC_CODE`], and empty otherwise
+    ///
+    /// By default this only invokes [`Cannoli::exec_with_bytes`]
+    ///
+    /// Executed on multiple threads, see [`Cannoli::exec`]
+    fn exec_dynamic(pid: &Self::PidContext, tid: &Self::TidContext,
+            pc: u64, bytes: &[u8], trace: &mut Vec<Self::Trace>) {
+        Self::exec_with_bytes(pid, tid, pc, bytes, trace)
+    }
+
     /// Invoked when execution of an instruction with register tracing occurs
     ///
     /// Executed on multiple threads
@@ -837,9 +1751,62 @@ pub trait Cannoli: Send + Sync {
             _pc: u64, _branch: bool, _regs: &[u8],
             _trace: &mut Vec<Self::Trace>) {}
 
+    /// Invoked with the FP or vector register file `index` of
+    /// [`control::Filters::reg_files`] when an instruction with register or
+    /// branch tracing executes, before [`Cannoli::regs`] or
+    /// [`Cannoli::branch`] of the same instruction. `regs` holds the
+    /// registers back to back, see [`regfile::RegFile::reg`]
+    ///
+    /// Executed on multiple threads, see [`Cannoli::regs`]
+    fn reg_file(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            _pc: u64, _index: usize, _regs: &[u8],
+            _trace: &mut Vec<Self::Trace>) {}
+
+    /// Invoked when a comparison instruction at `pc` compared the `sz` byte
+    /// operands `lhs` and `rhs`, see [`analysis::cmp`]. The jitter reports
+    /// them with [`control::Filters::cmp`] set, before the instruction
+    /// executes
+    ///
+    /// Executed on multiple threads, see [`Cannoli::read`]
+    fn cmp(_pid: &Self::PidContext, _tid: &Self::TidContext,
+           _pc: u64, _lhs: u64, _rhs: u64, _sz: u8,
+           _trace: &mut Vec<Self::Trace>) {}
+
+    /// Invoked when the system call instruction at `pc` is about to make
+    /// system call `nr` with the arguments `args`, after [`Cannoli::regs`] or
+    /// [`Cannoli::branch`] of the same instruction. Only reported with
+    /// [`control::Filters::syscalls`] set, see [`syscalls`] for what is
+    /// recognized
+    ///
+    /// Executed on multiple threads, see [`Cannoli::regs`]
+    fn syscall_entry(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            _pc: u64, _nr: u64, _args: &[u64],
+            _trace: &mut Vec<Self::Trace>) {}
+
+    /// Invoked when the system call made by the instruction at `pc` returned
+    /// `ret`, in the target's register width. Reported like
+    /// [`Cannoli::syscall_entry`], but not for system calls which don't
+    /// return
+    ///
+    /// Executed on multiple threads, see [`Cannoli::regs`]
+    fn syscall_exit(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            _pc: u64, _ret: u64, _trace: &mut Vec<Self::Trace>) {}
+
+    /// Invoked when the signal `sig` was delivered to the guest thread, which
+    /// was about to execute `from` (for synchronous signals like `SIGSEGV`,
+    /// the instruction which faulted) and continues in the handler at `to`.
+    /// The handler returns to `from` with the `rt_sigreturn` or `sigreturn`
+    /// system call, unless it `longjmp()`s out
+    ///
+    /// Executed on multiple threads, see [`Cannoli::read`]
+    fn signal(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            _sig: i32, _from: u64, _to: u64,
+            _trace: &mut Vec<Self::Trace>) {}
 
     /// Invoked when a memory load was lifted from the trace with a given
-    /// access size in bytes
+    /// access size in bytes. `helper` is set if one of QEMU's helpers (the C
+    /// functions implementing eg. atomics and FPU state saves) made the
+    /// access for the instruction at `pc`, rather than code QEMU generated
     ///
     /// Executed on multiple threads
     ///
@@ -854,12 +1821,13 @@ pub trait Cannoli: Send + Sync {
     /// are processing traces, the order of the events are not stable. This
     /// function is only meant to reason about the arguments in isolation,
     /// not with respect to previous operations.
+    #[allow(clippy::too_many_arguments)]
     fn read(_pid: &Self::PidContext, _tid: &Self::TidContext,
-            _pc: u64, _addr: u64, _val: u64, _sz: u8,
+            _pc: u64, _addr: u64, _val: u64, _sz: u8, _helper: bool,
             _trace: &mut Vec<Self::Trace>) {}
 
     /// Invoked when a memory store was lifted from the trace with a given
-    /// access size in bytes
+    /// access size in bytes. `helper` is set like for [`Cannoli::read`]
     ///
     /// Executed on multiple threads
     ///
@@ -874,11 +1842,54 @@ pub trait Cannoli: Send + Sync {
     /// are processing traces, the order of the events are not stable. This
     /// function is only meant to reason about the arguments in isolation,
     /// not with respect to previous operations.
+    #[allow(clippy::too_many_arguments)]
     fn write(_pid: &Self::PidContext, _tid: &Self::TidContext,
              _pc: u64, _addr: u64,
-             _val: u64, _sz: u8,
+             _val: u64, _sz: u8, _helper: bool,
              _trace: &mut Vec<Self::Trace>) {}
 
+    /// Invoked when a memory load was lifted from the trace while read values
+    /// are suppressed (see [`control::Filters::read_values`]), so only the
+    /// address and size are known
+    ///
+    /// Executed on multiple threads, see [`Cannoli::read`]. By default this
+    /// invokes [`Cannoli::read`] with a value of zero
+    fn read_addr(pid: &Self::PidContext, tid: &Self::TidContext,
+                 pc: u64, addr: u64, sz: u8, helper: bool,
+                 trace: &mut Vec<Self::Trace>) {
+        Self::read(pid, tid, pc, addr, 0, sz, helper, trace)
+    }
+
+    /// Invoked when a memory store was lifted from the trace while write
+    /// values are suppressed (see [`control::Filters::write_values`]), so
+    /// only the address and size are known
+    ///
+    /// Executed on multiple threads, see [`Cannoli::write`]. By default this
+    /// invokes [`Cannoli::write`] with a value of zero
+    fn write_addr(pid: &Self::PidContext, tid: &Self::TidContext,
+                  pc: u64, addr: u64, sz: u8, helper: bool,
+                  trace: &mut Vec<Self::Trace>) {
+        Self::write(pid, tid, pc, addr, 0, sz, helper, trace)
+    }
+
+    /// Invoked when the pressure on trace processing for this thread changes,
+    /// starting from [`Pressure::Normal`]. Processing which can't keep up
+    /// eventually stalls the target thread, so analyses can use this to shed
+    /// work (eg. skip symbolization or sample events) while the pressure is
+    /// up, typically by storing the level in the `TidContext`
+    ///
+    /// Executed on multiple threads, from the trace processing threads right
+    /// after they receive a chunk
+    fn pressure(_pid: &Self::PidContext, _tid: &Self::TidContext,
+                _level: Pressure) {}
+
+    /// Invoked when a callback panicked and [`Cannoli::PANIC_POLICY`] keeps
+    /// the run going. The chunk of the trace being processed was dropped
+    ///
+    /// Executed on multiple threads
+    fn analysis_error(_pid: &Self::PidContext, _tid: &Self::TidContext,
+                      _error: &AnalysisError) {}
+
     /// When a new sequential chunk of traces is available, this is invoked.
     /// This is _always_ invoked sequentially, such that the traces could be
     /// concatenated together to get a trace of all execution in-order
@@ -888,6 +1899,59 @@ pub trait Cannoli: Send + Sync {
     fn trace(&mut self, _pid: &Self::PidContext,
         _tid: &Self::TidContext, _trace: &[Self::Trace]) {}
 
+    /// Invoked for every control flow edge with [`control::HookKind::Edge`]:
+    /// the instruction at `pc`, which may end a basic block, was followed by
+    /// the block at `target`. `taken` is unset if execution continued with
+    /// the instruction after `pc`, see [`edges`] for how that's known. This
+    /// is all edge coverage needs, at a fraction of the events of hooking
+    /// every instruction
+    ///
+    /// Executed serially, in order with [`Cannoli::trace`], after the trace
+    /// of the same events
+    fn edge(&mut self, _pid: &Self::PidContext,
+        _tid: &Self::TidContext, _pc: u64, _target: u64, _taken: bool) {}
+
+    /// Invoked for every call with [`control::HookKind::Edge`] or
+    /// [`control::HookKind::Function`]: the call instruction at `pc` went to
+    /// the function at `target`. The jitter tells calls apart by their
+    /// instructions, see [`calls`] for which are known and
+    /// [`calls::ShadowStack`] to keep the call stack with them
+    ///
+    /// Executed serially, in order with [`Cannoli::trace`], right after the
+    /// [`Cannoli::edge`] of the call
+    fn call(&mut self, _pid: &Self::PidContext,
+        _tid: &Self::TidContext, _pc: u64, _target: u64) {}
+
+    /// Invoked for every return with [`control::HookKind::Edge`] or
+    /// [`control::HookKind::Function`]: the return instruction at `pc` went
+    /// back to `retaddr`, see [`Cannoli::call`]
+    ///
+    /// Executed serially, in order with [`Cannoli::trace`], right after the
+    /// [`Cannoli::edge`] of the return
+    fn ret(&mut self, _pid: &Self::PidContext,
+        _tid: &Self::TidContext, _pc: u64, _retaddr: u64) {}
+
+    /// Invoked when `mprotect(base, len, prot)` succeeded in the target,
+    /// `prot` being the `PROT_*` bits of the guest (1 read, 2 write, 4
+    /// exec). Memory made writable and executable at once, or anonymous
+    /// memory made executable, is how W^X violations and JIT-ed code show up
+    ///
+    /// System calls are only recognized with [`control::Filters::syscalls`]
+    /// set, see [`syscalls`]. Executed serially, in order with
+    /// [`Cannoli::trace`], after the trace of the same events
+    fn mprotect(&mut self, _pid: &Self::PidContext,
+        _tid: &Self::TidContext, _base: u64, _len: u64, _prot: u32) {}
+
+    /// Invoked when the epoch `id` of [`control::epoch`] is complete for this
+    /// thread: every event the thread produced before the epoch was
+    /// requested has been passed to [`Cannoli::trace`], up to the thread's
+    /// next exit from translated code, and no event after that. This is a
+    /// consistent cut point for snapshots of the analysis state
+    ///
+    /// Executed serially, in order with [`Cannoli::trace`]
+    fn epoch_complete(&mut self, _pid: &Self::PidContext,
+        _tid: &Self::TidContext, _id: u64) {}
+
     /// Invoked after a _successful_ mmap() in the target application, provides
     /// the base address, length, anon state, read, write, and exec flags
     fn mmap(_pid: &Self::PidContext, _tid: &Self::TidContext,
@@ -899,5 +1963,20 @@ pub trait Cannoli: Send + Sync {
     fn munmap(_pid: &Self::PidContext, _tid: &Self::TidContext,
               _base: u64, _len: u64,
               _trace: &mut Vec<Self::Trace>) {}
+
+    /// Invoked right after the `mmap()` callback for a mapping which grew the
+    /// heap, either by moving the program break or by reserving an allocator
+    /// arena. See [`heap`] for how these are recognized
+    fn heap(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            _event: &heap::HeapEvent,
+            _trace: &mut Vec<Self::Trace>) {}
 }
 
+#[test]
+fn pressure_levels() {
+    assert_eq!(Pressure::from_pending(0,  16), Pressure::Normal);
+    assert_eq!(Pressure::from_pending(7,  16), Pressure::Normal);
+    assert_eq!(Pressure::from_pending(8,  16), Pressure::Elevated);
+    assert_eq!(Pressure::from_pending(14, 16), Pressure::Critical);
+    assert_eq!(Pressure::from_pending(16, 16), Pressure::Critical);
+}
@@ -0,0 +1,208 @@
+//! Migration of captures between versions of the capture format
+//!
+//! [`crate::capture::FORMAT_VERSION`] is bumped whenever the capture format
+//! changes incompatibly, and [`crate::capture::CaptureReader`] only reads
+//! the current version. [`migrate`] rewrites a capture of another version,
+//! so archives of old captures stay readable after upgrading, and captures
+//! can be handed to an older build when nothing in them needs the newer
+//! format.
+//!
+//! The magic and the framing of records (a kind byte and a `u32` length)
+//! are the same in every version, only the version number and the bodies of
+//! records change. Every version change adds a step to `MIGRATIONS`, which
+//! rewrites single records to the next version, and back if the older
+//! version can express them. Migrating across several versions chains the
+//! steps. A record cut short at the end of the capture is dropped, as
+//! readers ignore it anyway.
+
+use std::fs::File;
+use std::io::{Read, Write, BufReader, BufWriter};
+use std::path::Path;
+use crate::capture::{MAGIC, FORMAT_VERSION};
+
+/// Oldest version of the capture format which can be migrated
+pub const OLDEST_VERSION: u32 = 1;
+
+/// Rewrites a record, given as its kind and body, for another version.
+/// Returns `None` to drop the record
+type Rewrite = fn(u8, Vec<u8>) -> std::io::Result<Option<(u8, Vec<u8>)>>;
+
+/// A step between two consecutive versions of the capture format
+struct Migration {
+    /// Version the step upgrades from, to the one after it
+    from: u32,
+
+    /// Rewrite a record of version `from` for version `from + 1`
+    upgrade: Rewrite,
+
+    /// Rewrite a record of version `from + 1` for version `from`, `None` if
+    /// captures can't be downgraded
+    downgrade: Option<Rewrite>,
+}
+
+/// Steps between every version, ordered by version. The format is still at
+/// its first version
+static MIGRATIONS: &[Migration] = &[];
+
+/// What was done by [`migrate`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MigrateStats {
+    /// Version of the original capture
+    pub from: u32,
+
+    /// Version of the migrated capture
+    pub to: u32,
+
+    /// Records written to the migrated capture
+    pub records: u64,
+
+    /// Records which the steps dropped, or which were cut short
+    pub dropped: u64,
+}
+
+/// Create an error for a capture which can't be migrated
+fn unsupported(msg: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::Unsupported, msg)
+}
+
+/// Get the version of the capture at `path`
+pub fn version(path: impl AsRef<Path>) -> std::io::Result<u32> {
+    let mut header = [0u8; 12];
+    File::open(path)?.read_exact(&mut header)?;
+    if &header[..8] != MAGIC {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData,
+            "Not a Cannoli capture"));
+    }
+    Ok(u32::from_le_bytes(header[8..].try_into().unwrap()))
+}
+
+/// Get the rewrites taking a record from version `from` to version `to`,
+/// in the order to apply them
+fn chain(steps: &[Migration], from: u32, to: u32)
+        -> std::io::Result<Vec<Rewrite>> {
+    let step = |version: u32| steps.iter().find(|x| x.from == version)
+        .ok_or_else(|| unsupported(format!(
+            "No migration between versions {version} and {}", version + 1)));
+
+    if from <= to {
+        (from..to).map(|x| step(x).map(|x| x.upgrade)).collect()
+    } else {
+        (to..from).rev().map(|version| step(version).and_then(|x| {
+            x.downgrade.ok_or_else(|| unsupported(format!(
+                "Captures can't be downgraded from version {} to {version}",
+                version + 1)))
+        })).collect()
+    }
+}
+
+/// Migrate the capture at `input` to version `to` of the format, writing
+/// it to `output`. Migrating to the version the capture already has copies
+/// it
+pub fn migrate(input: impl AsRef<Path>, output: impl AsRef<Path>, to: u32)
+        -> std::io::Result<MigrateStats> {
+    migrate_with(MIGRATIONS, input.as_ref(), output.as_ref(), to)
+}
+
+/// Migrate with the steps `steps`, see [`migrate`]
+fn migrate_with(steps: &[Migration], input: &Path, output: &Path, to: u32)
+        -> std::io::Result<MigrateStats> {
+    let from = version(input)?;
+    if !(OLDEST_VERSION..=FORMAT_VERSION).contains(&to) {
+        return Err(unsupported(format!("Unknown capture version {to}")));
+    }
+    let rewrites = chain(steps, from, to)?;
+
+    let mut stats  = MigrateStats { from, to, ..Default::default() };
+    let mut reader = BufReader::new(File::open(input)?);
+    reader.read_exact(&mut [0u8; 12])?;
+    let mut writer = BufWriter::new(File::create(output)?);
+    writer.write_all(MAGIC)?;
+    writer.write_all(&to.to_le_bytes())?;
+
+    'records: loop {
+        let mut header = [0u8; 5];
+        match reader.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(x) if x.kind() == std::io::ErrorKind::UnexpectedEof => {
+                break;
+            }
+            Err(x) => return Err(x),
+        }
+        let len = u32::from_le_bytes(header[1..].try_into().unwrap());
+        let mut body = Vec::new();
+        reader.by_ref().take(len as u64).read_to_end(&mut body)?;
+        if body.len() != len as usize {
+            stats.dropped += 1;
+            break;
+        }
+
+        let mut record = (header[0], body);
+        for rewrite in &rewrites {
+            match rewrite(record.0, record.1)? {
+                Some(x) => record = x,
+                None => {
+                    stats.dropped += 1;
+                    continue 'records;
+                }
+            }
+        }
+
+        writer.write_all(&[record.0])?;
+        writer.write_all(&(record.1.len() as u32).to_le_bytes())?;
+        writer.write_all(&record.1)?;
+        stats.records += 1;
+    }
+
+    writer.flush()?;
+    Ok(stats)
+}
+
+#[test]
+fn migrate_capture() {
+    use crate::capture::{CaptureReader, CaptureWriter};
+    use crate::event::Event;
+
+    let dir  = std::env::temp_dir();
+    let path = |x: &str| dir.join(format!("cannoli_migrate_{x}_{}",
+        std::process::id()));
+    let mut writer = CaptureWriter::create(path("in"), None).unwrap();
+    writer.write_events(1, 1, &[Event::Exec { pc: 0x1000 }]).unwrap();
+    drop(writer);
+
+    // Nothing to migrate yet, the capture is copied
+    let stats = migrate(path("in"), path("out"), FORMAT_VERSION).unwrap();
+    assert_eq!((stats.records, stats.dropped), (2, 0));
+    assert_eq!(std::fs::read(path("in")).unwrap(),
+        std::fs::read(path("out")).unwrap());
+    let mut reader = CaptureReader::open(path("out")).unwrap();
+    assert!(reader.next_record().unwrap().is_some());
+    assert!(migrate(path("in"), path("out"), FORMAT_VERSION + 1).is_err());
+
+    // A step which grows events records by a byte, and can be undone
+    fn upgrade(kind: u8, mut body: Vec<u8>)
+            -> std::io::Result<Option<(u8, Vec<u8>)>> {
+        if kind == 0x01 {
+            body.push(0);
+        }
+        Ok(Some((kind, body)))
+    }
+    fn downgrade(kind: u8, mut body: Vec<u8>)
+            -> std::io::Result<Option<(u8, Vec<u8>)>> {
+        if kind == 0x01 {
+            body.pop();
+        }
+        Ok(Some((kind, body)))
+    }
+    let steps = [Migration { from: 0, upgrade, downgrade: Some(downgrade) }];
+    let up = chain(&steps, 0, 1).unwrap();
+    let (kind, body) = up[0](0x01, vec![1, 2]).unwrap().unwrap();
+    assert_eq!((kind, body.clone()), (0x01, vec![1, 2, 0]));
+    let back = chain(&steps, 1, 0).unwrap();
+    assert_eq!(back[0](kind, body).unwrap(), Some((0x01, vec![1, 2])));
+    assert!(chain(&steps, 0, 2).is_err());
+    assert!(chain(&[Migration { from: 0, upgrade, downgrade: None }],
+        1, 0).is_err());
+
+    std::fs::remove_file(path("in")).unwrap();
+    std::fs::remove_file(path("out")).unwrap();
+}
@@ -0,0 +1,150 @@
+//! Guest process and thread names
+//!
+//! In system mode, process and thread names come from parsing the guest
+//! kernel's structures (eg. `task_struct::comm`). Cannoli only traces QEMU's
+//! user-mode emulation, where there is no guest kernel: the host kernel runs
+//! every guest thread as a thread of QEMU, and `prctl(PR_SET_NAME)` or
+//! `pthread_setname_np()` in the guest rename the host thread. [`GuestNames`]
+//! reads the names from there:
+//!
+//! - the process is named after its main binary
+//!   ([`crate::ClientInfo::exe`]), truncated like the kernel truncates
+//!   `comm`, as the comm of the process is QEMU's own (eg. `qemu-x86_64`)
+//!   unless QEMU runs through `binfmt_misc`
+//! - a thread is named by `/proc/<pid>/task/<tid>/comm` once the guest
+//!   renamed it, and after the process until then, like threads inherit the
+//!   name of the process they're created in
+//!
+//! The [`crate::sinks::jsonl`] sink writes both with every event, as
+//! `process` and `thread`.
+//!
+//! This is an approximation. The names are read from `/proc` of the host
+//! running the analysis, so they're only read if the target runs on it, and
+//! not when replaying a trace. A thread which is renamed is noticed on the
+//! next [`GuestNames::refresh`] at most every [`REFRESH`], so the events
+//! right after `prctl()` may carry the old name, and the name of a thread is
+//! only read while the thread is alive.
+
+use std::path::Path;
+use std::time::{Duration, Instant};
+use crate::ClientInfo;
+
+/// Longest name the kernel keeps in `comm`, `TASK_COMM_LEN` without the NUL
+const COMM_LEN: usize = 15;
+
+/// Time between reads of the name of a thread by [`GuestNames::refresh`]
+pub const REFRESH: Duration = Duration::from_millis(100);
+
+/// Truncate `name` to what the kernel keeps of it in `comm`
+fn truncate(name: &str) -> &str {
+    let mut len = name.len().min(COMM_LEN);
+    while !name.is_char_boundary(len) {
+        len -= 1;
+    }
+    &name[..len]
+}
+
+/// Get the name of a thread whose comm is `comm` in a process named
+/// `process`, where QEMU's comm is `qemu`
+fn thread_name(comm: &str, qemu: Option<&str>, process: &str) -> String {
+    if qemu.is_some_and(|x| x.trim_end() == comm) {
+        process.into()
+    } else {
+        comm.into()
+    }
+}
+
+/// Names of a guest thread and its process, see the module documentation
+#[derive(Clone, Debug)]
+pub struct GuestNames {
+    /// Process ID of the thread
+    pid: i32,
+
+    /// Thread ID of the thread
+    tid: i32,
+
+    /// Comm of QEMU, as the thread got it when QEMU started
+    qemu: Option<String>,
+
+    /// Last time the name of the thread was read
+    read: Instant,
+
+    /// Name of the process
+    pub process: String,
+
+    /// Name of the thread
+    pub thread: String,
+}
+
+impl GuestNames {
+    /// Get the names of the thread of `ci`
+    pub fn new(ci: &ClientInfo) -> Self {
+        let process = ci.exe.as_deref()
+            .and_then(|x| Path::new(x).file_name())
+            .map(|x| truncate(&x.to_string_lossy()).to_string())
+            .or_else(|| ci.comm.as_deref().map(|x| x.trim_end().into()))
+            .unwrap_or_else(|| ci.pid.to_string());
+        let mut ret = Self {
+            pid:    ci.pid,
+            tid:    ci.tid,
+            qemu:   ci.comm.clone(),
+            read:   Instant::now(),
+            thread: process.clone(),
+            process,
+        };
+        ret.read_thread();
+        ret
+    }
+
+    /// Read the name of the thread from `/proc`, returns if it changed. The
+    /// name is kept if the thread is gone
+    fn read_thread(&mut self) -> bool {
+        self.read = Instant::now();
+        let path = format!("/proc/{}/task/{}/comm", self.pid, self.tid);
+        let Ok(comm) = std::fs::read_to_string(path) else { return false };
+        let name = thread_name(comm.trim_end(), self.qemu.as_deref(),
+            &self.process);
+        let changed = name != self.thread;
+        self.thread = name;
+        changed
+    }
+
+    /// Read the name of the thread again, unless it was read less than
+    /// [`REFRESH`] ago. Returns if it changed
+    pub fn refresh(&mut self) -> bool {
+        self.read.elapsed() >= REFRESH && self.read_thread()
+    }
+}
+
+#[test]
+fn guest_names() {
+    assert_eq!(truncate("a_rather_long_binary_name"), "a_rather_long_b");
+    assert_eq!(truncate("ünïcödé_bnäry"), "ünïcödé_bn");
+    assert_eq!(thread_name("qemu-x86_64", Some("qemu-x86_64\n"), "app"),
+        "app");
+    assert_eq!(thread_name("worker", Some("qemu-x86_64\n"), "app"),
+        "worker");
+
+    // The names of a process which isn't running come from its greeting
+    let mut ci = ClientInfo {
+        uid:        0,
+        arch:       crate::Architecture::Aarch64,
+        big_endian: false,
+        ppid:       0,
+        pid:        -2,
+        tid:        -2,
+        cpu:        None,
+        pcomm:      None,
+        comm:       Some("qemu-aarch64\n".into()),
+        exe:        Some("/opt/target/bin/a_rather_long_binary_name".into()),
+        argv:       Vec::new(),
+        env:        Vec::new(),
+        cwd:        None,
+        tenant:     None,
+    };
+    let names = GuestNames::new(&ci);
+    assert_eq!((&*names.process, &*names.thread),
+        ("a_rather_long_b", "a_rather_long_b"));
+    ci.exe = None;
+    assert_eq!(GuestNames::new(&ci).process, "qemu-aarch64");
+}
@@ -0,0 +1,376 @@
+//! Notifications of code executing for the first time
+//!
+//! In soak tests of production-like deployments, code which never executed
+//! before is what's worth a look: an error path nobody tested, a feature
+//! thought unused, or code that shouldn't be there at all. [`NewCode`]
+//! follows the mappings and the executed basic blocks of a process and tells
+//! the first time each block executes.
+//!
+//! Blocks can also be checked against a [`SeenSet`], the blocks seen by
+//! earlier processes, which is kept in a file across runs. Then only blocks
+//! which no process ever executed are new. Blocks in files are known by
+//! their module and offset (see
+//! [`crate::address_space::AddressSpace::module_offset`]), so they're the
+//! same in every run regardless of ASLR. Code outside of files (eg. JIT-ed
+//! code) only has its address, so it's only new once per process and never
+//! makes it into the seen-set.
+//!
+//! [`NewCodeNotifier`] is a [`Cannoli`] implementation which calls the hook
+//! set with [`on_new_code`] for every new block, with the seen-set set with
+//! [`configure`] if any:
+//!
+//! ```no_run
+//! use cannoli::{create_cannoli_with, CannoliOpts};
+//! use cannoli::control::{Filters, HookKind};
+//! use cannoli::new_code::{self, NewCodeNotifier};
+//!
+//! new_code::configure("seen.txt".into()).unwrap();
+//! new_code::on_new_code(|x| eprintln!("new code at {:#x}", x.pc));
+//! let filters = Filters { hook: HookKind::Edge, ..Filters::default() };
+//! create_cannoli_with::<NewCodeNotifier>(
+//!     CannoliOpts::new(2).filters(filters)).unwrap();
+//! ```
+//!
+//! Blocks are the targets of [`Cannoli::edge`], so hook instructions with
+//! [`crate::control::HookKind::Edge`]. The first block of a process isn't
+//! the target of an edge, and isn't reported.
+
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::collections::{BTreeSet, HashSet};
+use crate::{Cannoli, ClientInfo};
+use crate::address_space::AddressSpace;
+use crate::event::Event;
+
+/// Callback set with [`on_new_code`]
+type Hook = Box<dyn Fn(&NewBlock) + Send + Sync>;
+
+/// Callback set with [`on_new_code`]
+static HOOK: RwLock<Option<Hook>> = RwLock::new(None);
+
+/// Seen-set set with [`configure`], and the file it's kept in
+static SEEN: Mutex<Option<(PathBuf, SeenSet)>> = Mutex::new(None);
+
+/// A block which executed for the first time
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NewBlock {
+    /// Process ID of the target
+    pub pid: i32,
+
+    /// Thread ID of the target thread which executed the block
+    pub tid: i32,
+
+    /// Address of the first instruction of the block
+    pub pc: u64,
+
+    /// Path of the file the block is in, `None` if it's not in a file
+    pub module: Option<Arc<str>>,
+
+    /// Offset of the block from where the start of `module` is loaded, the
+    /// same as `pc` without a module
+    pub offset: u64,
+}
+
+/// Blocks seen by earlier processes, by module and offset, see the module
+/// documentation
+///
+/// The file has a block per line, as the offset in hex followed by the path
+/// of the module.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SeenSet {
+    /// Module and offset of every block seen
+    blocks: HashSet<(Arc<str>, u64)>,
+}
+
+impl SeenSet {
+    /// Create an empty seen-set
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read a seen-set from `r`
+    pub fn read_from(r: impl BufRead) -> std::io::Result<Self> {
+        let mut ret = Self::new();
+        for line in r.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let parsed = line.split_once(' ').and_then(|(offset, path)| {
+                let offset = offset.strip_prefix("0x").unwrap_or(offset);
+                Some((u64::from_str_radix(offset, 16).ok()?, path))
+            });
+            let Some((offset, path)) = parsed else {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("invalid seen-set line {line:?}")));
+            };
+            ret.blocks.insert((path.into(), offset));
+        }
+        Ok(ret)
+    }
+
+    /// Load the seen-set kept at `path`, an empty one if the file doesn't
+    /// exist yet
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        match std::fs::File::open(path) {
+            Ok(file) => Self::read_from(std::io::BufReader::new(file)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound =>
+                Ok(Self::new()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Add the block at `offset` in `module`, returning whether it wasn't
+    /// seen yet
+    pub fn insert(&mut self, module: &Arc<str>, offset: u64) -> bool {
+        self.blocks.insert((module.clone(), offset))
+    }
+
+    /// Check if the block at `offset` in `module` was seen
+    pub fn contains(&self, module: &Arc<str>, offset: u64) -> bool {
+        self.blocks.contains(&(module.clone(), offset))
+    }
+
+    /// Get the number of blocks seen
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// Check if no block was seen
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    /// Write the seen-set, sorted by module and offset
+    pub fn write_to(&self, mut w: impl Write) -> std::io::Result<()> {
+        let mut blocks = self.blocks.iter().collect::<Vec<_>>();
+        blocks.sort();
+        for (path, offset) in blocks {
+            writeln!(w, "{offset:#x} {path}")?;
+        }
+        Ok(())
+    }
+
+    /// Write the seen-set to `path`, replacing the file at once so a crash
+    /// doesn't leave half of it
+    pub fn save(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let path = path.as_ref();
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        let mut file = std::io::BufWriter::new(std::fs::File::create(&tmp)?);
+        self.write_to(&mut file)?;
+        file.flush()?;
+        std::fs::rename(tmp, path)
+    }
+}
+
+/// First executions of the blocks of a single process, see the module
+/// documentation
+#[derive(Default)]
+pub struct NewCode {
+    /// Mappings of the process
+    space: AddressSpace,
+
+    /// Addresses of the blocks executed so far
+    seen: BTreeSet<u64>,
+}
+
+impl NewCode {
+    /// Create a tracker which saw nothing executing yet
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Apply an event, [`Event::Mmap`] and [`Event::Munmap`] to track the
+    /// modules. Code mapped where code was unmapped is new again
+    pub fn event(&mut self, event: &Event) {
+        match event {
+            Event::Mmap { base, len, anon, read, write, exec, path,
+                    offset } => {
+                self.space.mmap(*base, *len, *anon, *read, *write, *exec,
+                    path, *offset);
+            }
+            Event::Munmap { base, len } => {
+                self.space.munmap(*base, *len);
+                let end = base.saturating_add(*len);
+                let gone = self.seen.range(*base..end).copied()
+                    .collect::<Vec<_>>();
+                for pc in gone {
+                    self.seen.remove(&pc);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Observe the block at `pc` executing. If it's the first time, and it's
+    /// not in `seen` either, the block is returned as its module and offset
+    /// and added to `seen`
+    pub fn block(&mut self, pc: u64, seen: Option<&mut SeenSet>)
+            -> Option<(Option<Arc<str>>, u64)> {
+        if !self.seen.insert(pc) {
+            return None;
+        }
+
+        let Some((path, offset)) = self.space.module_offset(pc) else {
+            return Some((None, pc));
+        };
+        if seen.is_some_and(|x| !x.insert(&path, offset)) {
+            return None;
+        }
+        Some((Some(path), offset))
+    }
+}
+
+/// Keep the blocks seen in the file at `path` across runs, loading it if it
+/// exists. This must be called before [`crate::create_cannoli`], the file is
+/// written every time a process exits, and with [`save`]
+pub fn configure(path: PathBuf) -> std::io::Result<()> {
+    let seen = SeenSet::load(&path)?;
+    *SEEN.lock().unwrap() = Some((path, seen));
+    Ok(())
+}
+
+/// Call `hook` for every new block [`NewCodeNotifier`] finds. It's called
+/// from the sequential phase of the thread which executed the block, so it
+/// should hand off anything slow
+pub fn on_new_code(hook: impl Fn(&NewBlock) + Send + Sync + 'static) {
+    *HOOK.write().unwrap() = Some(Box::new(hook));
+}
+
+/// Write the seen-set set with [`configure`] to its file, if there is one
+pub fn save() -> std::io::Result<()> {
+    let seen = SEEN.lock().unwrap();
+    match &*seen {
+        Some((path, seen)) => seen.save(path),
+        None => Ok(()),
+    }
+}
+
+/// New code of a process, the seen-set is saved once the last thread of the
+/// process is gone
+pub struct NewCodeProcess {
+    /// Process ID of the target
+    pid: i32,
+
+    /// The blocks the process executed
+    code: Mutex<NewCode>,
+}
+
+impl Drop for NewCodeProcess {
+    fn drop(&mut self) {
+        if let Err(err) = save() {
+            eprintln!("[pid {}] failed to save the seen-set: {err}",
+                self.pid);
+        }
+    }
+}
+
+/// A [`Cannoli`] implementation calling the hook set with [`on_new_code`]
+/// for every block which executes for the first time, see the module
+/// documentation
+pub struct NewCodeNotifier {
+    /// Thread ID of the target thread
+    tid: i32,
+}
+
+impl Cannoli for NewCodeNotifier {
+    type Trace = Event;
+
+    type PidContext = NewCodeProcess;
+    type TidContext = ();
+
+    fn init_pid(ci: &ClientInfo) -> Arc<Self::PidContext> {
+        Arc::new(NewCodeProcess {
+            pid:  ci.pid,
+            code: Mutex::new(NewCode::new()),
+        })
+    }
+
+    fn init_tid(_pid: &Self::PidContext,
+            ci: &ClientInfo) -> (Self, Self::TidContext) {
+        (Self { tid: ci.tid }, ())
+    }
+
+    fn mmap(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            base: u64, len: u64, anon: bool, read: bool, write: bool,
+            exec: bool, path: &str, offset: u64,
+            trace: &mut Vec<Self::Trace>) {
+        trace.push(Event::Mmap {
+            path: path.to_string(),
+            base, len, anon, read, write, exec, offset,
+        });
+    }
+
+    fn munmap(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            base: u64, len: u64, trace: &mut Vec<Self::Trace>) {
+        trace.push(Event::Munmap { base, len });
+    }
+
+    fn trace(&mut self, pid: &Self::PidContext,
+            _tid: &Self::TidContext, trace: &[Self::Trace]) {
+        let mut code = pid.code.lock().unwrap();
+        for event in trace {
+            code.event(event);
+        }
+    }
+
+    fn edge(&mut self, pid: &Self::PidContext,
+            _tid: &Self::TidContext, _pc: u64, target: u64, _taken: bool) {
+        let new = {
+            let mut seen = SEEN.lock().unwrap();
+            pid.code.lock().unwrap().block(target,
+                seen.as_mut().map(|x| &mut x.1))
+        };
+        let Some((module, offset)) = new else {
+            return;
+        };
+
+        // Not holding any lock, the hook may call `save()`
+        if let Some(hook) = HOOK.read().unwrap().as_ref() {
+            hook(&NewBlock {
+                pid:    pid.pid,
+                tid:    self.tid,
+                pc:     target,
+                module,
+                offset,
+            });
+        }
+    }
+}
+
+#[test]
+fn new_code() {
+    let mut code = NewCode::new();
+    code.event(&Event::Mmap {
+        base: 0x400000, len: 0x1000, anon: false, read: true, write: false,
+        exec: true, path: "/bin/t".into(), offset: 0,
+    });
+
+    // Blocks are new once per process, and once ever with a seen-set
+    let mut seen = SeenSet::read_from(&b"0x10 /bin/t\n"[..]).unwrap();
+    assert_eq!(code.block(0x400010, Some(&mut seen)), None);
+    assert_eq!(code.block(0x400020, Some(&mut seen)),
+        Some((Some("/bin/t".into()), 0x20)));
+    assert_eq!(code.block(0x400020, None), None);
+    assert_eq!(code.block(0x7000, Some(&mut seen)), Some((None, 0x7000)));
+    assert_eq!(seen.len(), 2);
+
+    // Code mapped where code was unmapped is new again
+    code.event(&Event::Munmap { base: 0x400000, len: 0x1000 });
+    code.event(&Event::Mmap {
+        base: 0x400000, len: 0x1000, anon: false, read: true, write: false,
+        exec: true, path: "/bin/u".into(), offset: 0,
+    });
+    assert_eq!(code.block(0x400020, Some(&mut seen)),
+        Some((Some("/bin/u".into()), 0x20)));
+
+    let mut out = Vec::new();
+    seen.write_to(&mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(),
+        "0x10 /bin/t\n0x20 /bin/t\n0x20 /bin/u\n");
+    assert_eq!(SeenSet::read_from(&b"0x10"[..]).unwrap_err().kind(),
+        std::io::ErrorKind::InvalidData);
+}
@@ -0,0 +1,244 @@
+//! Export of traces in the Chrome Trace Event format, for Perfetto
+//!
+//! [ui.perfetto.dev](https://ui.perfetto.dev) (and `chrome://tracing`) show
+//! a trace as one track per thread with the function calls stacked on it.
+//! [`TraceEventWriter`] turns the events of a capture into that: every guest
+//! thread is a track of its process, and every function call which returned
+//! is a duration event named after the function.
+//!
+//! Calls are recognized like [`crate::analysis::functions`] does, from the
+//! executed PCs only: a transfer of control which comes back to just after
+//! where it came from was a call. Functions are named with the symbols of
+//! the files mapped by the process (see [`ModuleSymbols`]), as an offset in
+//! their module without symbols, or by their address outside of files.
+//! Calls still running when the trace ends are kept if a symbol starts at
+//! their entry, the others are likely jumps rather than calls.
+//!
+//! Events carry no time, so a thread's clock is the number of events it
+//! traced, shown as microseconds. Durations compare within a thread, not
+//! between threads.
+
+use std::io::Write;
+use std::collections::HashMap;
+use crate::Event;
+use crate::symbols::{ModuleSymbols, Resolver};
+
+/// Maximum distance between two PCs for them to be considered sequential
+/// execution rather than a transfer of control
+const MAX_INSN_LEN: u64 = 16;
+
+/// Maximum distance between the instruction which transferred control and
+/// the address that a return lands on
+const MAX_RETURN_GAP: u64 = 16;
+
+/// Maximum number of open calls of a thread, deeper transfers are counted as
+/// part of the call they're in
+const MAX_DEPTH: usize = 4096;
+
+/// A tentative call
+struct Frame {
+    /// Address control was transferred to
+    entry: u64,
+
+    /// Address of the instruction which transferred control, `None` for the
+    /// first frame of a thread
+    call_site: Option<u64>,
+
+    /// Time of the call
+    start: u64,
+
+    /// Lowest PC executed in the frame
+    lo: u64,
+
+    /// Highest PC executed in the frame
+    hi: u64,
+}
+
+/// Calls of a thread
+#[derive(Default)]
+struct Thread {
+    /// Last PC executed
+    prev: Option<u64>,
+
+    /// Number of events so far, the clock of the thread
+    time: u64,
+
+    /// Open calls, the top of the stack is the current one
+    frames: Vec<Frame>,
+}
+
+/// Writes traces in the Chrome Trace Event format, see the module
+/// documentation
+pub struct TraceEventWriter<W: Write> {
+    /// Where the JSON is written
+    out: W,
+
+    /// Symbols of every process, by PID
+    symbols: HashMap<i32, ModuleSymbols>,
+
+    /// Every thread, by PID and TID
+    threads: HashMap<(i32, i32), Thread>,
+
+    /// Whether a trace event was written yet
+    written: bool,
+}
+
+impl<W: Write> TraceEventWriter<W> {
+    /// Start writing a trace to `out`
+    pub fn new(mut out: W) -> std::io::Result<Self> {
+        out.write_all(b"{\"traceEvents\":[")?;
+        Ok(Self {
+            out,
+            symbols: HashMap::new(),
+            threads: HashMap::new(),
+            written: false,
+        })
+    }
+
+    /// Name the function at `entry` of the process `pid`
+    fn name(&self, pid: i32, entry: u64) -> String {
+        let Some(symbols) = self.symbols.get(&pid) else {
+            return format!("{entry:#x}");
+        };
+        match symbols.resolve(entry) {
+            Some(x) if x.offset == 0 => x.symbol.name.to_string(),
+            _ => match symbols.space().module_offset(entry) {
+                Some((module, offset)) => format!("{module}+{offset:#x}"),
+                None => format!("{entry:#x}"),
+            },
+        }
+    }
+
+    /// Write the call `frame` of the thread `tid` of the process `pid`,
+    /// which returned at `end`
+    fn call(&mut self, pid: i32, tid: i32, frame: &Frame, end: u64)
+            -> std::io::Result<()> {
+        let event = serde_json::json!({
+            "name": self.name(pid, frame.entry),
+            "cat":  "function",
+            "ph":   "X",
+            "ts":   frame.start,
+            "dur":  end - frame.start,
+            "pid":  pid,
+            "tid":  tid,
+            "args": { "entry": format!("{:#x}", frame.entry) },
+        });
+        if self.written {
+            self.out.write_all(b",")?;
+        }
+        self.written = true;
+        self.out.write_all(b"\n")?;
+        serde_json::to_writer(&mut self.out, &event)?;
+        Ok(())
+    }
+
+    /// Add the next `events` of the thread `tid` of the process `pid`
+    pub fn events(&mut self, pid: i32, tid: i32, events: &[Event])
+            -> std::io::Result<()> {
+        let mut thread = self.threads.remove(&(pid, tid)).unwrap_or_default();
+        let mut ret = Ok(());
+        for event in events {
+            thread.time += 1;
+            let pc = match *event {
+                Event::Mmap { .. } | Event::Munmap { .. } => {
+                    self.symbols.entry(pid).or_default().event(event);
+                    continue;
+                }
+                Event::Exec { pc } | Event::Regs { pc, .. } |
+                    Event::Branch { pc, .. } => pc,
+                _ => continue,
+            };
+            if let Err(err) = self.observe(pid, tid, &mut thread, pc) {
+                ret = Err(err);
+                break;
+            }
+        }
+        self.threads.insert((pid, tid), thread);
+        ret
+    }
+
+    /// Observe the instruction at `pc` executing on the thread `thread`
+    fn observe(&mut self, pid: i32, tid: i32, thread: &mut Thread, pc: u64)
+            -> std::io::Result<()> {
+        let time = thread.time;
+        let Some(prev) = thread.prev.replace(pc) else {
+            thread.frames.push(Frame {
+                entry: pc, call_site: None, start: time, lo: pc, hi: pc });
+            return Ok(());
+        };
+
+        let sequential = pc.wrapping_sub(prev).wrapping_sub(1) < MAX_INSN_LEN;
+        if !sequential {
+            let returned = thread.frames.iter().rposition(|frame| {
+                frame.call_site.is_some_and(|site| {
+                    pc.wrapping_sub(site).wrapping_sub(1) < MAX_RETURN_GAP
+                })
+            });
+
+            if let Some(idx) = returned {
+                // Frames above the one which returned were jumps
+                let frame = thread.frames.drain(idx..).next().unwrap();
+                self.call(pid, tid, &frame, time)?;
+            } else if !thread.frames.last().is_some_and(|top| {
+                (top.lo..=top.hi).contains(&pc)
+            }) && thread.frames.len() < MAX_DEPTH {
+                thread.frames.push(Frame {
+                    entry:     pc,
+                    call_site: Some(prev),
+                    start:     time,
+                    lo:        pc,
+                    hi:        pc,
+                });
+            }
+        }
+
+        if let Some(top) = thread.frames.last_mut() {
+            top.lo = top.lo.min(pc);
+            top.hi = top.hi.max(pc);
+        }
+        Ok(())
+    }
+
+    /// End the calls still running, and finish the trace
+    pub fn finish(mut self) -> std::io::Result<W> {
+        let mut threads = std::mem::take(&mut self.threads).into_iter()
+            .collect::<Vec<_>>();
+        threads.sort_by_key(|x| x.0);
+        for ((pid, tid), thread) in threads {
+            for frame in &thread.frames {
+                let named = frame.call_site.is_none() ||
+                    self.symbols.get(&pid)
+                        .and_then(|x| x.resolve(frame.entry))
+                        .is_some_and(|x| x.offset == 0);
+                if named {
+                    self.call(pid, tid, frame, thread.time + 1)?;
+                }
+            }
+        }
+        self.out.write_all(b"\n]}\n")?;
+        Ok(self.out)
+    }
+}
+
+#[test]
+fn trace_events() {
+    let mut writer = TraceEventWriter::new(Vec::new()).unwrap();
+
+    // 0x1000 calls 0x2000 from 0x1004, which loops and returns, and then
+    // calls it again without it returning
+    let pcs = [0x1000, 0x1004, 0x2000, 0x2004, 0x2000, 0x2004, 0x1008,
+        0x100c, 0x2000];
+    let events = pcs.map(|pc| Event::Exec { pc });
+    writer.events(1, 2, &events[..4]).unwrap();
+    writer.events(1, 2, &events[4..]).unwrap();
+    let json = String::from_utf8(writer.finish().unwrap()).unwrap();
+
+    let trace: serde_json::Value = serde_json::from_str(&json).unwrap();
+    let calls = trace["traceEvents"].as_array().unwrap().iter()
+        .map(|x| {
+            (x["name"].as_str().unwrap(), x["ts"].as_u64().unwrap(),
+                x["dur"].as_u64().unwrap(), x["tid"].as_i64().unwrap())
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(calls, [("0x2000", 3, 4, 2), ("0x1000", 1, 9, 2)]);
+}
@@ -0,0 +1,289 @@
+//! Pinning the time and the random numbers of the guest
+//!
+//! Targets which read the time or random numbers trace differently on every
+//! run. QEMU's `-seed` only covers the random numbers QEMU makes itself, the
+//! time always comes from the host. With [`Pins`] in the [`PIN_VAR`]
+//! environment variable of QEMU, the jitter intercepts the system calls
+//! which read them, at the system call sites [`crate::syscalls`] describes:
+//! the arguments are recorded at the system call instruction, and at the
+//! instruction after it what the host returned is overwritten with the
+//! [`Pinned`] result of [`Pinner::pin`], before anything else sees it.
+//!
+//! The clock starts at [`Pins::time`] and every read of it, by any thread of
+//! the process, advances it by [`Pins::step`], so loops waiting for time to
+//! pass still finish. Every clock reads the same. `getrandom()` is filled
+//! from a generator seeded with [`Pins::random`]. Both are shared by the
+//! threads of a process, so they only repeat if the threads read them in the
+//! same order. Time read without a system call (eg. `rdtsc`, or a vDSO which
+//! doesn't fall back to one) and reads of `/dev/urandom` aren't pinned.
+//!
+//! The pins are read when the jitter is loaded rather than sent with the
+//! filters, so the system calls of the loader and of libc's startup are
+//! pinned too. [`crate::harness::RunManifest::pin`] sets them for a run.
+
+use std::fmt;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Environment variable of QEMU which holds the [`Pins`] of the jitter
+pub const PIN_VAR: &str = "CANNOLI_PIN";
+
+/// Default nanoseconds the pinned clock advances on every read, 1 ms
+pub const DEFAULT_STEP: u64 = 1_000_000;
+
+/// What to pin, as [`Pins::parse`] reads from and [`fmt::Display`] writes
+/// to [`PIN_VAR`], eg. `time=1700000000000000000,step=1000000,random=1`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Pins {
+    /// Time of the first read of the clock, in nanoseconds since the Unix
+    /// epoch. The host's time if `None`
+    pub time: Option<u64>,
+
+    /// Nanoseconds the clock advances on every read
+    pub step: u64,
+
+    /// Seed of the random numbers of `getrandom()`. The host's (or QEMU's
+    /// `-seed`) if `None`
+    pub random: Option<u64>,
+}
+
+impl Default for Pins {
+    fn default() -> Self {
+        Self { time: None, step: DEFAULT_STEP, random: None }
+    }
+}
+
+impl Pins {
+    /// Parse pins written by [`fmt::Display`], `None` if they're invalid
+    pub fn parse(pins: &str) -> Option<Self> {
+        let mut ret = Self::default();
+        for pin in pins.split(',').filter(|x| !x.is_empty()) {
+            let (key, val) = pin.split_once('=')?;
+            let val = val.parse().ok()?;
+            match key {
+                "time"   => ret.time   = Some(val),
+                "step"   => ret.step   = val,
+                "random" => ret.random = Some(val),
+                _ => return None,
+            }
+        }
+        Some(ret)
+    }
+
+    /// Check if nothing is pinned
+    pub fn is_empty(&self) -> bool {
+        self.time.is_none() && self.random.is_none()
+    }
+}
+
+impl fmt::Display for Pins {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut pins = Vec::new();
+        if let Some(time) = self.time {
+            pins.push(format!("time={time}"));
+            pins.push(format!("step={}", self.step));
+        }
+        if let Some(seed) = self.random {
+            pins.push(format!("random={seed}"));
+        }
+        write!(f, "{}", pins.join(","))
+    }
+}
+
+/// A system call whose result is pinned
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PinnedCall {
+    /// `time(tloc)`
+    Time,
+
+    /// `gettimeofday(tv, tz)`
+    Gettimeofday,
+
+    /// `clock_gettime(clock, ts)`, with a `timespec` of target longs
+    ClockGettime,
+
+    /// `clock_gettime64(clock, ts)` of 32-bit targets, with a 64-bit
+    /// `timespec`
+    ClockGettime64,
+
+    /// `getrandom(buf, len, flags)`
+    Getrandom,
+}
+
+/// Result of a pinned system call
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Pinned {
+    /// Guest memory to overwrite, as addresses and their new contents
+    pub writes: Vec<(u64, Vec<u8>)>,
+
+    /// Return value
+    pub ret: u64,
+}
+
+/// The pinned clock and random numbers of a process
+#[derive(Debug)]
+pub struct Pinner {
+    /// What is pinned
+    pins: Pins,
+
+    /// Reads of the clock so far
+    reads: AtomicU64,
+
+    /// State of the random number generator
+    random: Mutex<u64>,
+}
+
+impl Pinner {
+    /// Create the clock and random numbers of `pins`
+    pub fn new(pins: Pins) -> Self {
+        Self {
+            pins,
+            reads:  AtomicU64::new(0),
+            random: Mutex::new(pins.random.unwrap_or(0)),
+        }
+    }
+
+    /// Get the pinned system call named `name` as in
+    /// [`crate::symbols::signatures::Signatures::syscall_name`], `None` if
+    /// its result isn't pinned
+    pub fn call(&self, name: &str) -> Option<PinnedCall> {
+        let call = match name {
+            "time"            => PinnedCall::Time,
+            "gettimeofday"    => PinnedCall::Gettimeofday,
+            "clock_gettime"   => PinnedCall::ClockGettime,
+            "clock_gettime64" => PinnedCall::ClockGettime64,
+            "getrandom"       => PinnedCall::Getrandom,
+            _ => return None,
+        };
+        match call {
+            PinnedCall::Getrandom => self.pins.random.map(|_| call),
+            _ => self.pins.time.map(|_| call),
+        }
+    }
+
+    /// Get the next read of the clock, in nanoseconds since the Unix epoch
+    fn now(&self) -> u64 {
+        let reads = self.reads.fetch_add(1, Ordering::Relaxed);
+        self.pins.time.unwrap_or(0)
+            .wrapping_add(reads.wrapping_mul(self.pins.step))
+    }
+
+    /// Fill `buf` with the next random numbers, a SplitMix64 stream
+    fn fill(&self, buf: &mut [u8]) {
+        let mut state = self.random.lock().unwrap();
+        for chunk in buf.chunks_mut(8) {
+            *state = state.wrapping_add(0x9e3779b97f4a7c15);
+            let mut x = *state;
+            x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+            x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+            x ^= x >> 31;
+            chunk.copy_from_slice(&x.to_le_bytes()[..chunk.len()]);
+        }
+    }
+
+    /// Pin the result of `call` with the arguments `args`, which returned
+    /// `ret` on a target with `width`-byte registers. `None` if the call
+    /// failed, failures are left as they are
+    pub fn pin(&self, call: PinnedCall, args: &[u64], ret: u64, width: usize,
+            big_endian: bool) -> Option<Pinned> {
+        let ret = if width == 4 {
+            ret as u32 as i32 as i64
+        } else {
+            ret as i64
+        };
+        if (-4095..0).contains(&ret) {
+            return None;
+        }
+        let arg = |index: usize| args.get(index).copied().unwrap_or(0);
+
+        // Values as stored in guest memory, truncated to `size` bytes
+        let word = |val: u64, size: usize| {
+            if big_endian {
+                val.to_be_bytes()[8 - size..].to_vec()
+            } else {
+                val.to_le_bytes()[..size].to_vec()
+            }
+        };
+        let pair = |a: u64, b: u64, size: usize| {
+            let mut ret = word(a, size);
+            ret.extend(word(b, size));
+            ret
+        };
+
+        let mut writes = Vec::new();
+        let mut ret = ret as u64;
+        match call {
+            PinnedCall::Time => {
+                let secs = self.now() / 1_000_000_000;
+                if arg(0) != 0 {
+                    writes.push((arg(0), word(secs, width)));
+                }
+                ret = secs;
+            }
+            PinnedCall::Gettimeofday => {
+                let now = self.now();
+                if arg(0) != 0 {
+                    writes.push((arg(0), pair(now / 1_000_000_000,
+                        now % 1_000_000_000 / 1000, width)));
+                }
+            }
+            PinnedCall::ClockGettime | PinnedCall::ClockGettime64 => {
+                let now = self.now();
+                let size = if call == PinnedCall::ClockGettime64 {
+                    8
+                } else {
+                    width
+                };
+                if arg(1) != 0 {
+                    writes.push((arg(1), pair(now / 1_000_000_000,
+                        now % 1_000_000_000, size)));
+                }
+            }
+            PinnedCall::Getrandom => {
+                let mut buf = vec![0u8; ret.min(arg(1)) as usize];
+                self.fill(&mut buf);
+                writes.push((arg(0), buf));
+            }
+        }
+        if width == 4 {
+            ret &= 0xffff_ffff;
+        }
+        Some(Pinned { writes, ret })
+    }
+}
+
+#[test]
+fn pinned_results() {
+    let pins = Pins { time: Some(1_700_000_000_123_456_789), step: 1000,
+        random: None };
+    assert_eq!(Pins::parse(&pins.to_string()), Some(pins));
+    assert_eq!(Pins::parse(""), Some(Pins::default()));
+    assert_eq!(Pins::parse("seed=1"), None);
+    assert_eq!(Pins::parse("random=1").unwrap().random, Some(1));
+
+    let pinner = Pinner::new(pins);
+    assert_eq!(pinner.call("getrandom"), None);
+    let call = pinner.call("clock_gettime").unwrap();
+    let pinned = pinner.pin(call, &[1, 0x1000], 0, 8, false).unwrap();
+    assert_eq!(pinned.writes, [(0x1000, [1_700_000_000u64, 123_456_789]
+        .iter().flat_map(|x| x.to_le_bytes()).collect::<Vec<_>>())]);
+
+    // Every read advances the clock by a step
+    let pinned = pinner.pin(PinnedCall::Gettimeofday, &[0x2000, 0], 0, 4,
+        true).unwrap();
+    assert_eq!(pinned.writes[0].1, [1_700_000_000u32, 123_457]
+        .iter().flat_map(|x| x.to_be_bytes()).collect::<Vec<_>>());
+    let pinned = pinner.pin(PinnedCall::Time, &[0], 0, 4, false).unwrap();
+    assert_eq!((pinned.writes.len(), pinned.ret), (0, 1_700_000_000));
+
+    // Failures are left alone, and only the bytes returned are random
+    assert_eq!(pinner.pin(PinnedCall::Time, &[0], -14i64 as u64, 8, false),
+        None);
+    let pinner = Pinner::new(Pins { random: Some(5), ..Pins::default() });
+    assert_eq!(pinner.call("time"), None);
+    let a = pinner.pin(PinnedCall::Getrandom, &[0x3000, 16, 0], 12, 8, false);
+    let b = Pinner::new(Pins { random: Some(5), ..Pins::default() })
+        .pin(PinnedCall::Getrandom, &[0x3000, 16, 0], 12, 8, false);
+    assert_eq!(a, b);
+    assert_eq!(a.unwrap().writes[0].1.len(), 12);
+}
@@ -0,0 +1,161 @@
+//! Capture profiles, ready-made combinations of hooks and sinks
+//!
+//! Picking what to hook is a tradeoff between overhead and what can be told
+//! from the trace afterwards, and the right [`Filters`] aren't obvious from
+//! their fields. A [`Profile`] names a combination which works well for one
+//! job, with the sink which makes sense for it:
+//!
+//! | profile         | hooks                                | output      |
+//! |-----------------|--------------------------------------|-------------|
+//! | `coverage`      | every instruction once               | drcov files |
+//! | `memory-audit`  | reads and writes with their values   | capture     |
+//! | `syscall-audit` | system calls only                    | capture     |
+//! | `full`          | everything, including system calls   | capture     |
+//!
+//! ```no_run
+//! use cannoli::CannoliOpts;
+//! use cannoli::profile::Profile;
+//!
+//! let profile = Profile::parse("coverage").unwrap();
+//! profile.configure("coverage".as_ref()).unwrap();
+//! profile.serve(CannoliOpts::new(4)).unwrap();
+//! ```
+//!
+//! Captures of `memory-audit` summarize block transfers (see
+//! [`crate::sinks::Summarized`]), captures of `syscall-audit` hold the
+//! registers at every system call site, with the number and arguments at
+//! the instruction and the return value after it (see [`crate::syscalls`]).
+//! Every capture has the mappings of the target. Use
+//! [`CannoliOpts::profile`] to only take the filters of a profile, eg. for
+//! an analysis of your own.
+
+use std::fmt;
+use std::path::Path;
+use crate::{CannoliOpts, Result, create_cannoli_with};
+use crate::capture::CaptureWriter;
+use crate::control::{Filters, HookKind};
+use crate::coverage::{self, DrcovCollector};
+use crate::sinks::{Recorder, Summarized};
+use crate::sinks::capture::{self, CaptureSink};
+
+/// A capture profile, see the module documentation
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Profile {
+    /// Code coverage, written as drcov files
+    Coverage,
+
+    /// Memory reads and writes with their values
+    MemoryAudit,
+
+    /// System calls
+    SyscallAudit,
+
+    /// Everything
+    Full,
+}
+
+impl Profile {
+    /// Every profile
+    pub const ALL: [Profile; 4] = [Profile::Coverage, Profile::MemoryAudit,
+        Profile::SyscallAudit, Profile::Full];
+
+    /// Get the profile named `name`
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|x| x.name() == name)
+    }
+
+    /// Get the name of the profile
+    pub fn name(self) -> &'static str {
+        match self {
+            Profile::Coverage     => "coverage",
+            Profile::MemoryAudit  => "memory-audit",
+            Profile::SyscallAudit => "syscall-audit",
+            Profile::Full         => "full",
+        }
+    }
+
+    /// Get the filters of the profile
+    pub fn filters(self) -> Filters {
+        let nothing = Filters {
+            exec:   false,
+            reads:  false,
+            writes: false,
+            ..Filters::default()
+        };
+        match self {
+            Profile::Coverage => Filters {
+                exec: true,
+                hook: HookKind::Once,
+                ..nothing
+            },
+            Profile::MemoryAudit => Filters {
+                reads:  true,
+                writes: true,
+                ..nothing
+            },
+            Profile::SyscallAudit => Filters {
+                syscalls: true,
+                ..nothing
+            },
+            Profile::Full => Filters {
+                syscalls: true,
+                ..Filters::default()
+            },
+        }
+    }
+
+    /// Write the output of the profile to the directory `dir`, which is
+    /// created if needed. This must be called before
+    /// [`Profile::serve`], tenants (see [`crate::tenants`]) write to their
+    /// own output directory regardless
+    pub fn configure(self, dir: &Path) -> std::io::Result<()> {
+        let already = || std::io::Error::new(std::io::ErrorKind::AlreadyExists,
+            "the output was already configured");
+        std::fs::create_dir_all(dir)?;
+        match self {
+            Profile::Coverage => coverage::configure(dir.to_path_buf())
+                .map_err(|_| already()),
+            _ => capture::configure(CaptureWriter::create(
+                dir.join("capture.cap"), None)?).map_err(|_| already()),
+        }
+    }
+
+    /// Run a server with `opts`, the filters of the profile and its sink,
+    /// see [`crate::create_cannoli_with`]
+    pub fn serve(self, opts: CannoliOpts) -> Result<()> {
+        let opts = opts.profile(self);
+        match self {
+            Profile::Coverage => create_cannoli_with::<DrcovCollector>(opts),
+            Profile::MemoryAudit => create_cannoli_with::<
+                Recorder<Summarized<CaptureSink>>>(opts),
+            Profile::SyscallAudit | Profile::Full =>
+                create_cannoli_with::<Recorder<CaptureSink>>(opts),
+        }
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[test]
+fn profiles() {
+    for profile in Profile::ALL {
+        assert_eq!(Profile::parse(&profile.to_string()), Some(profile));
+    }
+    assert_eq!(Profile::parse("memory"), None);
+
+    let coverage = Profile::Coverage.filters();
+    assert_eq!(coverage.hook_inst(0x1000), Some(HookKind::Once));
+    assert!(!coverage.hook_mem(0x1000, false));
+
+    let audit = Profile::MemoryAudit.filters();
+    assert!(audit.hook_inst(0x1000).is_none());
+    assert!(audit.hook_mem(0x1000, true) && audit.mem_values(true));
+
+    let syscalls = Profile::SyscallAudit.filters();
+    assert!(syscalls.syscalls && syscalls.hook_inst(0x1000).is_none());
+    assert!(Profile::Full.filters().syscalls);
+}
@@ -0,0 +1,632 @@
+//! Arguments and return values of functions, decoded from prototypes
+//!
+//! Register traces carry everything needed to log calls like `ltrace` does,
+//! on any architecture QEMU runs: the registers at the entry of a function
+//! hold its arguments, and the registers at the instruction it returns to
+//! hold its return value. A [`Prototypes`] file describes the functions to
+//! log with C-like declarations, one per line:
+//!
+//! ```text
+//! # Comments start with `#`
+//! int open(str, int, hex)
+//! long read(int, hex, ulong);
+//! str getenv(str)
+//! int stat(str, {ulong, ulong, uint, uint})
+//! void free(hex)
+//! ```
+//!
+//! Types are `void` (return values only), `int` and `uint` (32 bits),
+//! `long` and `ulong` (a register), `hex` (a register, printed in hex),
+//! `char`, `str` (a pointer to a NUL-terminated string) and `{...}`, a
+//! pointer to a struct with the fields given, which are laid out with their
+//! natural alignment. Strings and structs are read through the control
+//! channel with [`GuestMemory`], so they show the memory as it is when the
+//! trace is processed rather than at the call, see [`crate::guest`].
+//!
+//! A [`CallTracer`] of every thread is given the registers of every
+//! instruction (see [`crate::control::HookKind::Register`]) in trace order,
+//! and the prototype of the function an instruction is the entry of, which
+//! the caller finds by symbolizing it (eg. with
+//! [`crate::symbols::ModuleSymbols`]). It knows where the arguments are from
+//! the [`CallAbi`] of the target, and matches returns with calls by the
+//! stack pointer: on x86 a function has returned once the stack pointer is
+//! above where it was at the entry, elsewhere once the link register of the
+//! entry is reached with the same stack pointer. Frames left without
+//! returning (`longjmp()`, exceptions) are dropped. Arguments wider than a
+//! register and floating point arguments aren't supported, and arguments
+//! past those in registers are read from the stack, which like strings
+//! may have changed since.
+
+use std::fmt;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::collections::HashMap;
+use crate::{Architecture, ClientInfo};
+use crate::guest::GuestMemory;
+
+/// Longest string read for a `str`, longer ones are truncated
+const MAX_STRING: usize = 64;
+
+/// Most calls waiting for their return in a thread, the outermost are
+/// dropped past it
+const MAX_PENDING: usize = 1024;
+
+/// Type of an argument or return value
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Type {
+    /// No value, only for return values
+    Void,
+
+    /// 32-bit signed integer
+    Int,
+
+    /// 32-bit unsigned integer
+    Uint,
+
+    /// Signed integer the size of a register
+    Long,
+
+    /// Unsigned integer the size of a register
+    Ulong,
+
+    /// Integer the size of a register, printed in hex
+    Hex,
+
+    /// A character
+    Char,
+
+    /// Pointer to a NUL-terminated string
+    Str,
+
+    /// Pointer to a struct with these fields
+    Struct(Vec<Type>),
+}
+
+/// Split `text` at the commas which aren't in braces
+fn split_top(text: &str) -> Vec<&str> {
+    let mut ret = Vec::new();
+    let (mut depth, mut start) = (0i32, 0);
+    for (ii, c) in text.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            ',' if depth == 0 => {
+                ret.push(&text[start..ii]);
+                start = ii + 1;
+            }
+            _ => {}
+        }
+    }
+    ret.push(&text[start..]);
+    ret
+}
+
+impl FromStr for Type {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let text = text.trim();
+        if let Some(fields) =
+                text.strip_prefix('{').and_then(|x| x.strip_suffix('}')) {
+            let fields = split_top(fields).into_iter()
+                .map(|x| match x.parse()? {
+                    Type::Void => Err("A field can't be void".to_string()),
+                    field => Ok(field),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            return Ok(Type::Struct(fields));
+        }
+
+        Ok(match text {
+            "void"  => Type::Void,
+            "int"   => Type::Int,
+            "uint"  => Type::Uint,
+            "long"  => Type::Long,
+            "ulong" => Type::Ulong,
+            "hex"   => Type::Hex,
+            "char"  => Type::Char,
+            "str"   => Type::Str,
+            _ => return Err(format!("Unknown type `{text}`")),
+        })
+    }
+}
+
+impl Type {
+    /// Get the size of the type in memory, with `width`-byte pointers
+    fn size(&self, width: usize) -> usize {
+        match self {
+            Type::Int | Type::Uint => 4,
+            Type::Char => 1,
+            Type::Void => 0,
+            _ => width,
+        }
+    }
+
+    /// Decode the register-sized `raw` as a value of this type
+    fn decode(&self, raw: u64, width: usize,
+            mem: &mut GuestMemory) -> Value {
+        let shift = 64 - width as u32 * 8;
+        match self {
+            Type::Void  => Value::Void,
+            Type::Int   => Value::Int(raw as i32 as i64),
+            Type::Uint  => Value::Uint(raw as u32 as u64),
+            Type::Long  => Value::Int(((raw << shift) as i64) >> shift),
+            Type::Ulong => Value::Uint(raw),
+            Type::Hex   => Value::Hex(raw),
+            Type::Char  => Value::Char(raw as u8),
+            Type::Str => {
+                let text = (raw != 0).then(|| {
+                    mem.read_cstr(raw, MAX_STRING + 1).ok()
+                }).flatten();
+                Value::Str { addr: raw, text: text.map(|x| {
+                    let truncated = x.len() > MAX_STRING;
+                    let x = &x[..x.len().min(MAX_STRING)];
+                    (String::from_utf8_lossy(x).into_owned(), truncated)
+                }) }
+            }
+            Type::Struct(fields) => {
+                let fields = (raw != 0).then(|| {
+                    Self::read_fields(fields, raw, width, mem)
+                }).flatten();
+                Value::Struct { addr: raw, fields }
+            }
+        }
+    }
+
+    /// Read the struct with `fields` at `addr`, `None` if it's unreadable
+    fn read_fields(fields: &[Type], addr: u64, width: usize,
+            mem: &mut GuestMemory) -> Option<Vec<Value>> {
+        let mut offset = 0u64;
+        let mut ret = Vec::with_capacity(fields.len());
+        for field in fields {
+            let size = field.size(width) as u64;
+            offset = offset.next_multiple_of(size.max(1));
+            let addr = addr.wrapping_add(offset);
+            let raw = match size {
+                1 => mem.read_u8(addr).ok()? as u64,
+                4 => mem.read_u32(addr).ok()? as u64,
+                _ => mem.read_ptr(addr).ok()?,
+            };
+            ret.push(field.decode(raw, width, mem));
+            offset += size;
+        }
+        Some(ret)
+    }
+}
+
+/// A decoded argument or return value
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Value {
+    /// No value
+    Void,
+
+    /// A signed integer
+    Int(i64),
+
+    /// An unsigned integer
+    Uint(u64),
+
+    /// An integer printed in hex
+    Hex(u64),
+
+    /// A character
+    Char(u8),
+
+    /// A string at `addr`, with its text and whether it was truncated
+    /// unless it couldn't be read
+    Str { addr: u64, text: Option<(String, bool)> },
+
+    /// A struct at `addr`, with its fields unless it couldn't be read
+    Struct { addr: u64, fields: Option<Vec<Value>> },
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Void    => write!(f, "<void>"),
+            Value::Int(x)  => write!(f, "{x}"),
+            Value::Uint(x) => write!(f, "{x}"),
+            Value::Hex(x)  => write!(f, "{x:#x}"),
+            Value::Char(x) => write!(f, "'{}'", x.escape_ascii()),
+            Value::Str { addr: 0, .. } | Value::Struct { addr: 0, .. } =>
+                write!(f, "NULL"),
+            Value::Str { text: Some((text, truncated)), .. } => {
+                write!(f, "{text:?}")?;
+                if *truncated {
+                    write!(f, "...")?;
+                }
+                Ok(())
+            }
+            Value::Struct { fields: Some(fields), .. } => {
+                write!(f, "{{")?;
+                for (ii, field) in fields.iter().enumerate() {
+                    let sep = if ii == 0 { "" } else { ", " };
+                    write!(f, "{sep}{field}")?;
+                }
+                write!(f, "}}")
+            }
+            Value::Str { addr, .. } | Value::Struct { addr, .. } =>
+                write!(f, "{addr:#x}"),
+        }
+    }
+}
+
+/// Declaration of a function to log
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Prototype {
+    /// Name of the function, as its symbol
+    pub name: String,
+
+    /// Types of the arguments
+    pub args: Vec<Type>,
+
+    /// Type of the return value
+    pub ret: Type,
+}
+
+impl FromStr for Prototype {
+    type Err = String;
+
+    /// Parse a prototype from `<type> <name>(<type>, ...)`, optionally
+    /// followed by `;`
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid prototype `{text}`");
+        let decl = text.trim().trim_end_matches(';').trim_end();
+        let (head, args) = decl.strip_suffix(')')
+            .and_then(|x| x.split_once('('))
+            .ok_or_else(invalid)?;
+        let (ret, name) = head.trim().rsplit_once(char::is_whitespace)
+            .ok_or_else(invalid)?;
+        if name.is_empty() {
+            return Err(invalid());
+        }
+
+        let args = match args.trim() {
+            "" | "void" => Vec::new(),
+            args => split_top(args).into_iter().map(|x| match x.parse()? {
+                Type::Void => Err(format!("An argument of `{name}` can't be \
+                    void")),
+                arg => Ok(arg),
+            }).collect::<Result<_, _>>()?,
+        };
+        Ok(Self { name: name.to_string(), args, ret: ret.parse()? })
+    }
+}
+
+/// Prototypes of the functions to log, by name, see the module
+/// documentation
+#[derive(Clone, Debug, Default)]
+pub struct Prototypes {
+    /// Prototypes, keyed by the name of the function
+    functions: HashMap<String, Arc<Prototype>>,
+}
+
+impl Prototypes {
+    /// Create an empty set of prototypes
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parse prototypes, one per line
+    pub fn parse(text: &str) -> std::io::Result<Self> {
+        let mut ret = Self::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let prototype = line.parse().map_err(|x| Error::new(
+                ErrorKind::InvalidData, format!("Line {}: {x}", number + 1)))?;
+            ret.add(prototype);
+        }
+        Ok(ret)
+    }
+
+    /// Load the prototypes file at `path`
+    pub fn load(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// Add `prototype`, replacing any prototype of the same name
+    pub fn add(&mut self, prototype: Prototype) {
+        self.functions.insert(prototype.name.clone(), Arc::new(prototype));
+    }
+
+    /// Get the prototype of the function `name`
+    pub fn get(&self, name: &str) -> Option<&Arc<Prototype>> {
+        self.functions.get(name)
+    }
+
+    /// Get the number of prototypes
+    pub fn len(&self) -> usize {
+        self.functions.len()
+    }
+
+    /// Check if there are no prototypes
+    pub fn is_empty(&self) -> bool {
+        self.functions.is_empty()
+    }
+}
+
+/// Where a function's arguments and return value are, as indices into the
+/// general purpose registers, see [`crate::syscalls::SyscallAbi`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CallAbi {
+    /// Registers holding the first arguments, in order
+    pub args: &'static [usize],
+
+    /// Offset of the first argument passed on the stack from the stack
+    /// pointer at the entry of the function
+    pub stack: u64,
+
+    /// Register holding the return value
+    pub ret: usize,
+
+    /// Register holding the stack pointer
+    pub sp: usize,
+
+    /// Register holding the return address at the entry of the function,
+    /// `None` if it's on the stack
+    pub link: Option<usize>,
+}
+
+impl CallAbi {
+    /// Get the C calling convention of `arch` on Linux, in QEMU's order of
+    /// the general purpose registers. `None` if calls of `arch` aren't
+    /// supported
+    pub fn new(arch: Architecture) -> Option<Self> {
+        let (args, stack, ret, sp, link): (&'static [usize], _, _, _, _) =
+                match arch {
+            // rdi, rsi, rdx, rcx, r8, r9; rax; rsp, after the return address
+            Architecture::X86_64 => (&[7, 6, 2, 1, 8, 9], 8, 0, 4, None),
+
+            // Everything on the stack; eax; esp
+            Architecture::I386 | Architecture::I686 => (&[], 4, 0, 4, None),
+
+            // x0-x7; x0; sp; x30
+            Architecture::Aarch64 | Architecture::Aarch64be =>
+                (&[0, 1, 2, 3, 4, 5, 6, 7], 0, 0, 31, Some(30)),
+
+            // r0-r3; r0; sp; lr
+            Architecture::Armv5tel | Architecture::Armv5teb =>
+                (&[0, 1, 2, 3], 0, 0, 13, Some(14)),
+
+            // a0-a3, with home slots on the stack; v0; sp; ra
+            Architecture::Mips => (&[4, 5, 6, 7], 16, 2, 29, Some(31)),
+
+            // a0-a7; a0; sp; ra
+            Architecture::Riscv32 | Architecture::Riscv64 =>
+                (&[10, 11, 12, 13, 14, 15, 16, 17], 0, 10, 2, Some(1)),
+
+            _ => return None,
+        };
+        Some(Self { args, stack, ret, sp, link })
+    }
+}
+
+/// A call to a function with a prototype
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Call {
+    /// Prototype of the function called
+    pub prototype: Arc<Prototype>,
+
+    /// Arguments of the call
+    pub args: Vec<Value>,
+
+    /// Number of calls with prototypes the thread is in, not counting this
+    /// one
+    pub depth: usize,
+}
+
+impl fmt::Display for Call {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}(", self.prototype.name)?;
+        for (ii, arg) in self.args.iter().enumerate() {
+            let sep = if ii == 0 { "" } else { ", " };
+            write!(f, "{sep}{arg}")?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// A return from a function with a prototype
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Return {
+    /// Prototype of the function returning
+    pub prototype: Arc<Prototype>,
+
+    /// Value returned
+    pub value: Value,
+
+    /// Number of calls with prototypes the thread is in, not counting this
+    /// one
+    pub depth: usize,
+}
+
+impl fmt::Display for Return {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} = {}", self.prototype.name, self.value)
+    }
+}
+
+/// A call waiting for its return
+#[derive(Clone, Debug)]
+struct Pending {
+    /// Prototype of the function called
+    prototype: Arc<Prototype>,
+
+    /// Stack pointer at the entry of the function
+    sp: u64,
+
+    /// Return address, if it was in a register
+    retaddr: Option<u64>,
+}
+
+/// Decodes the calls and returns of a thread, see the module documentation
+pub struct CallTracer {
+    /// Calling convention of the target
+    abi: CallAbi,
+
+    /// Size of a general purpose register in bytes
+    width: usize,
+
+    /// Reads strings, structs and stack arguments
+    mem: GuestMemory,
+
+    /// Calls which didn't return yet, the outermost first
+    pending: Vec<Pending>,
+}
+
+impl CallTracer {
+    /// Create a tracer for the thread of `ci`, `None` if calls of its
+    /// architecture aren't supported
+    pub fn new(ci: &ClientInfo) -> Option<Self> {
+        Some(Self {
+            abi:     CallAbi::new(ci.arch)?,
+            width:   ci.arch.bitness() as usize / 8,
+            mem:     GuestMemory::new(ci),
+            pending: Vec::new(),
+        })
+    }
+
+    /// Read general purpose register `index` from `regs`, which QEMU keeps
+    /// in host byte order
+    fn reg(&self, regs: &[u8], index: usize) -> Option<u64> {
+        let bytes = regs.get(index * self.width..(index + 1) * self.width)?;
+        let mut value = [0u8; 8];
+        value[..bytes.len()].copy_from_slice(bytes);
+        Some(u64::from_le_bytes(value))
+    }
+
+    /// Observe the registers `regs` of the instruction at `pc`, returning
+    /// the call they return from, if any. Invoke for every instruction
+    /// before [`CallTracer::entered`]
+    pub fn returned(&mut self, pc: u64, regs: &[u8]) -> Option<Return> {
+        let sp = self.reg(regs, self.abi.sp)?;
+        while let Some(frame) = self.pending.last() {
+            let above = sp > frame.sp;
+            let returned = match frame.retaddr {
+                Some(retaddr) => pc == retaddr && sp == frame.sp,
+                None => above,
+            };
+            if returned {
+                let frame = self.pending.pop().unwrap();
+                let raw = self.reg(regs, self.abi.ret)?;
+                self.mem.invalidate();
+                return Some(Return {
+                    value: frame.prototype.ret.decode(raw, self.width,
+                        &mut self.mem),
+                    prototype: frame.prototype,
+                    depth: self.pending.len(),
+                });
+            }
+            if !above {
+                break;
+            }
+
+            // Left without returning
+            self.pending.pop();
+        }
+        None
+    }
+
+    /// Observe the entry of the function with `prototype`, whose registers
+    /// are `regs`, and decode its arguments
+    pub fn entered(&mut self, prototype: &Arc<Prototype>,
+            regs: &[u8]) -> Option<Call> {
+        let sp = self.reg(regs, self.abi.sp)?;
+        let retaddr = match self.abi.link {
+            Some(link) => Some(self.reg(regs, link)?),
+            None => None,
+        };
+
+        self.mem.invalidate();
+        let mut args = Vec::with_capacity(prototype.args.len());
+        for (ii, arg) in prototype.args.iter().enumerate() {
+            let raw = match self.abi.args.get(ii) {
+                Some(&reg) => self.reg(regs, reg)?,
+                None => {
+                    let slot = (ii - self.abi.args.len()) * self.width;
+                    let addr = sp.wrapping_add(self.abi.stack + slot as u64);
+                    self.mem.read_ptr(addr).unwrap_or(0)
+                }
+            };
+            args.push(arg.decode(raw, self.width, &mut self.mem));
+        }
+
+        if self.pending.len() == MAX_PENDING {
+            self.pending.remove(0);
+        }
+        let depth = self.pending.len();
+        self.pending.push(Pending { prototype: prototype.clone(), sp,
+            retaddr });
+        Some(Call { prototype: prototype.clone(), args, depth })
+    }
+
+    /// Forget the calls waiting for their return, when the trace has a gap
+    pub fn reset(&mut self) {
+        self.pending.clear();
+    }
+}
+
+#[test]
+fn prototypes() {
+    let prototypes = Prototypes::parse("# libc\n\
+        int open(str, int, hex);\n\
+        void exit(int)\n\
+        int stat(str, {ulong, uint, char})\n").unwrap();
+    assert_eq!(prototypes.len(), 3);
+    assert_eq!(prototypes.get("stat").unwrap().args[1],
+        Type::Struct(vec![Type::Ulong, Type::Uint, Type::Char]));
+    assert!(Prototypes::parse("int f(void, int)").is_err());
+    assert!("int (int)".parse::<Prototype>().is_err());
+    assert!("quad f()".parse::<Prototype>().is_err());
+
+    // Disconnected, so pointers aren't read
+    let ci = ClientInfo {
+        uid:        0,
+        arch:       Architecture::X86_64,
+        big_endian: false,
+        ppid:       0,
+        pid:        -3,
+        tid:        -3,
+        cpu:        None,
+        pcomm:      None,
+        comm:       None,
+        exe:        None,
+        argv:       Vec::new(),
+        env:        Vec::new(),
+        cwd:        None,
+        tenant:     None,
+    };
+    let mut tracer = CallTracer::new(&ci).unwrap();
+    let mut regs = vec![0u8; 16 * 8];
+    let set = |regs: &mut Vec<u8>, index: usize, value: u64| {
+        regs[index * 8..index * 8 + 8].copy_from_slice(&value.to_le_bytes());
+    };
+
+    // open(0x2000, -1, 0x1ff) from a recursive call, returning 3
+    let open = prototypes.get("open").unwrap();
+    for sp in [0x7ff0, 0x7f00] {
+        set(&mut regs, 4, sp);
+        set(&mut regs, 7, 0x2000);
+        set(&mut regs, 6, u32::MAX as u64);
+        set(&mut regs, 2, 0x1ff);
+        assert_eq!(tracer.returned(0x1000, &regs), None);
+        let call = tracer.entered(open, &regs).unwrap();
+        assert_eq!(call.to_string(), "open(0x2000, -1, 0x1ff)");
+    }
+    set(&mut regs, 4, 0x7ef0);
+    assert_eq!(tracer.returned(0x1010, &regs), None);
+    set(&mut regs, 4, 0x7f08);
+    set(&mut regs, 0, 3);
+    let ret = tracer.returned(0x3000, &regs).unwrap();
+    assert_eq!((ret.to_string(), ret.depth), ("open = 3".to_string(), 1));
+
+    // A longjmp() out of the outer call returns from it at once
+    set(&mut regs, 4, 0x9000);
+    assert_eq!(tracer.returned(0x4000, &regs).map(|x| x.depth), Some(0));
+    assert_eq!(tracer.returned(0x4004, &regs), None);
+}
@@ -0,0 +1,219 @@
+//! Segment:offset addresses of 16-bit real-mode code
+//!
+//! Cannoli only traces QEMU's user-mode emulation, so BIOS and bootloader
+//! code, which needs `qemu-system-*`, can't be traced. DOS-era 16-bit code
+//! still runs under `qemu-i386` in virtual-8086 mode, which DOS emulators
+//! (eg. dosemu) enter with the `vm86()` system call. QEMU translates it like
+//! any other code, and its events carry linear addresses (`segment * 16 +
+//! offset`), which don't match anything in a listing of the code.
+//!
+//! A [`SegOff`] is such an address as `segment:offset`. Relative to the
+//! segment the code used ([`SegOff::new`]) it reads like the listing, and
+//! without one [`SegOff::normalized`] gives the canonical form, with an
+//! offset below 16. The segments in use are captured with a
+//! [`RegFile::segments`] register file along with the register hooks, and
+//! the mode of the CPU with a [`RegFile::hflags`] one.
+//! [`Segments::from_reg_file`] and [`Segments::with_hflags`] decode them
+//! and [`Segments::code`] and [`Segments::data`] annotate PCs and memory
+//! accesses:
+//!
+//! ```
+//! use cannoli::realmode::{SegOff, Segments, HF_VM};
+//! use cannoli::regfile::{RegFile, I386_HFLAGS, I386_SEGS};
+//!
+//! // es, cs, ss, ds, fs and gs, and the hflags of virtual-8086 mode, as
+//! // captured at an instruction
+//! let file = RegFile::segments(I386_SEGS);
+//! let regs = [0x0000u16, 0x1234, 0x2000, 0x3000, 0, 0].iter()
+//!     .flat_map(|x| x.to_le_bytes()).collect::<Vec<_>>();
+//! let hflags = RegFile::hflags(I386_HFLAGS);
+//! let segs = Segments::from_reg_file(&file, &regs).unwrap()
+//!     .with_hflags(&hflags, &HF_VM.to_le_bytes()).unwrap();
+//! assert_eq!(segs.code(0x12350).unwrap().to_string(), "1234:0010");
+//! assert_eq!(SegOff::normalized(0x12350).unwrap().to_string(), "1235:0000");
+//! ```
+//!
+//! Addresses are only annotated while the CPU runs 16-bit code, in real
+//! mode (`CR0.PE` clear) or in virtual-8086 mode (`EFLAGS.VM` set, with
+//! `CR0.PE` still set, which is how `qemu-i386` runs it), so the protected
+//! mode code of the emulator keeps its linear addresses. Only addresses a
+//! 16-bit segment can reach are annotated, up to the end of the high memory
+//! area at `ffff:ffff`. Accesses are annotated relative to `ds`, so those
+//! of code which overrides their segment have a different offset than in
+//! the listing.
+
+use std::fmt;
+use crate::regfile::{RegFile, RegKind};
+
+/// Highest linear address a 16-bit segment can reach, `ffff:ffff`
+pub const MAX_LINEAR: u64 = 0xffff * 16 + 0xffff;
+
+/// Bit of `hflags` which mirrors `CR0.PE`, set in protected mode
+pub const HF_PE: u32 = 1 << 7;
+
+/// Bit of `hflags` which mirrors `EFLAGS.VM`, set in virtual-8086 mode
+pub const HF_VM: u32 = 1 << 17;
+
+/// A real-mode address as a segment and an offset into it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SegOff {
+    /// Segment, the linear address of the segment divided by 16
+    pub seg: u16,
+
+    /// Offset into the segment
+    pub off: u16,
+}
+
+impl SegOff {
+    /// Get the linear address `linear` relative to the segment `seg`, `None`
+    /// if it's out of the 64 KiB the segment reaches
+    pub fn new(seg: u16, linear: u64) -> Option<Self> {
+        let off = linear.checked_sub(seg as u64 * 16)?;
+        Some(Self { seg, off: u16::try_from(off).ok()? })
+    }
+
+    /// Get the linear address `linear` in canonical form, with the offset
+    /// below 16 except in the high memory area which only segment `ffff`
+    /// reaches. `None` if no segment reaches it
+    pub fn normalized(linear: u64) -> Option<Self> {
+        if linear > MAX_LINEAR {
+            return None;
+        }
+        Self::new((linear >> 4).min(0xffff) as u16, linear)
+    }
+
+    /// Get the linear address
+    pub fn linear(&self) -> u64 {
+        self.seg as u64 * 16 + self.off as u64
+    }
+}
+
+impl fmt::Display for SegOff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04x}:{:04x}", self.seg, self.off)
+    }
+}
+
+/// Segment selectors of an x86 target, as captured by
+/// [`RegFile::segments`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Segments {
+    /// Extra segment
+    pub es: u16,
+
+    /// Code segment
+    pub cs: u16,
+
+    /// Stack segment
+    pub ss: u16,
+
+    /// Data segment
+    pub ds: u16,
+
+    /// Second extra segment
+    pub fs: u16,
+
+    /// Third extra segment
+    pub gs: u16,
+
+    /// The `hflags` of the CPU, `None` if they weren't captured
+    pub hflags: Option<u32>,
+}
+
+impl Segments {
+    /// Decode the segments from `regs` captured with the register file
+    /// `file`, `None` if it isn't a segments register file
+    pub fn from_reg_file(file: &RegFile, regs: &[u8]) -> Option<Self> {
+        if file.kind != RegKind::Segments {
+            return None;
+        }
+        let seg = |index| file.reg(regs, index)?.get(..2)
+            .map(|x| u16::from_le_bytes([x[0], x[1]]));
+
+        Some(Self {
+            es: seg(0)?,
+            cs: seg(1)?,
+            ss: seg(2)?,
+            ds: seg(3)?,
+            fs: seg(4)?,
+            gs: seg(5)?,
+            hflags: None,
+        })
+    }
+
+    /// Add the `hflags` from `regs` captured with the register file `file`,
+    /// `None` if it isn't an `hflags` register file
+    pub fn with_hflags(self, file: &RegFile, regs: &[u8]) -> Option<Self> {
+        if file.kind != RegKind::Hflags {
+            return None;
+        }
+        let hflags = u32::from_le_bytes(file.reg(regs, 0)?.try_into().ok()?);
+        Some(Self { hflags: Some(hflags), ..self })
+    }
+
+    /// Check if the CPU runs 16-bit code, in real mode or virtual-8086 mode.
+    /// `false` if the `hflags` weren't captured
+    pub fn is_16bit(&self) -> bool {
+        self.hflags.is_some_and(|x| x & HF_PE == 0 || x & HF_VM != 0)
+    }
+
+    /// Annotate the PC `pc` relative to `cs`, `None` if the CPU doesn't run
+    /// 16-bit code or `cs` doesn't reach it
+    pub fn code(&self, pc: u64) -> Option<SegOff> {
+        if !self.is_16bit() {
+            return None;
+        }
+        SegOff::new(self.cs, pc)
+    }
+
+    /// Annotate the address `addr` of a memory access relative to `ds`, or
+    /// to `ss` if only the stack segment reaches it. `None` if the CPU
+    /// doesn't run 16-bit code
+    pub fn data(&self, addr: u64) -> Option<SegOff> {
+        if !self.is_16bit() {
+            return None;
+        }
+        SegOff::new(self.ds, addr).or_else(|| SegOff::new(self.ss, addr))
+    }
+}
+
+#[test]
+fn segment_offset() {
+    assert_eq!(SegOff::new(0xb800, 0xb8000 + 160),
+        Some(SegOff { seg: 0xb800, off: 160 }));
+    assert_eq!(SegOff::new(0xb800, 0xb7fff), None);
+    assert_eq!(SegOff::new(0xb800, 0xb8000 + 0x10000), None);
+    assert_eq!(SegOff { seg: 0x1234, off: 0x10 }.linear(), 0x12350);
+
+    // The high memory area is only reached from segment ffff
+    assert_eq!(SegOff::normalized(0xffff0),
+        Some(SegOff { seg: 0xffff, off: 0 }));
+    assert_eq!(SegOff::normalized(0x100000),
+        Some(SegOff { seg: 0xffff, off: 0x10 }));
+    assert_eq!(SegOff::normalized(MAX_LINEAR).unwrap().to_string(),
+        "ffff:ffff");
+    assert_eq!(SegOff::normalized(MAX_LINEAR + 1), None);
+
+    // Selectors are the low 16 bits of the captured registers
+    let file = RegFile { width: 4, ..RegFile::segments(0) };
+    let regs = [0u32, 0x0100, 0x9000, 0x0200, 0, 0].iter()
+        .flat_map(|x| x.to_le_bytes()).collect::<Vec<_>>();
+    let segs = Segments::from_reg_file(&file, &regs).unwrap();
+    assert_eq!((segs.cs, segs.ss, segs.ds), (0x0100, 0x9000, 0x0200));
+    assert_eq!(Segments::from_reg_file(&RegFile::x87(0), &regs), None);
+    assert_eq!(Segments::from_reg_file(&file, &regs[..8]), None);
+
+    // Nothing is annotated unless the CPU is known to run 16-bit code
+    assert_eq!(segs.code(0x1004), None);
+    let hflags = RegFile::hflags(0);
+    let real = segs.with_hflags(&hflags, &0u32.to_le_bytes()).unwrap();
+    assert_eq!(real.data(0x2004), Some(SegOff { seg: 0x0200, off: 4 }));
+    assert_eq!(real.data(0x9fffe), Some(SegOff { seg: 0x9000, off: 0xfffe }));
+    assert_eq!(real.code(0x400000), None);
+    let protected = segs.with_hflags(&hflags, &HF_PE.to_le_bytes()).unwrap();
+    assert_eq!((protected.code(0x1004), protected.data(0x2004)), (None, None));
+    let vm86 = segs.with_hflags(&hflags, &(HF_PE | HF_VM).to_le_bytes());
+    assert_eq!(vm86.unwrap().code(0x1004),
+        Some(SegOff { seg: 0x0100, off: 4 }));
+    assert_eq!(segs.with_hflags(&file, &regs), None);
+}
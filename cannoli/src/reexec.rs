@@ -0,0 +1,502 @@
+//! Re-executing a short stretch of a trace from its reconstructed state
+//!
+//! "What if this byte had been different?" is usually answered by changing
+//! the input and running the target again, all the way from the start. A
+//! [`Window`] answers it for a short stretch of code instead: it rebuilds the
+//! registers and memory of a thread at a point of a capture, lets the state
+//! be modified, and hands it to an emulator (eg. Unicorn) to run from there.
+//!
+//! Cannoli has no access to guest memory, so memory is reconstructed from
+//! the capture, the way [`crate::analysis::integrity`] does it:
+//!
+//! - File mappings start out with the contents of the file at the path QEMU
+//!   reported, anonymous mappings and the program break with zeros
+//! - Writes with values are replayed on top, in trace order, for every
+//!   thread of the process
+//! - Reads with values show what memory actually held, and overwrite the
+//!   reconstruction. This fills in memory which was written without hooks,
+//!   eg. by the kernel on behalf of the guest or by QEMU's loader
+//!
+//! Reads which disagreed with the reconstruction and writes without values
+//! are counted, as a measure of how much the reconstruction can be trusted.
+//! Registers are taken from the last register or branch hook of the thread
+//! at or before the point, so the thread has to be traced with one of those,
+//! at least around the point of interest. Their layout is QEMU's for the
+//! target, eg. `rax`, `rcx`, `rdx`, `rbx`, `rsp`, `rbp`, `rsi`, `rdi`, `r8`
+//! to `r15` for x86-64.
+//!
+//! Cannoli doesn't depend on an emulator itself, the emulator is plugged in
+//! by implementing [`Emulator`]. With the `unicorn-engine` crate that's
+//! about this much:
+//!
+//! ```ignore
+//! struct Uc<'a>(Unicorn<'a, ()>);
+//!
+//! impl Emulator for Uc<'_> {
+//!     type Error = uc_error;
+//!
+//!     fn map(&mut self, base: u64, data: &[u8], perms: Perms)
+//!             -> Result<(), uc_error> {
+//!         let mut prot = Permission::NONE;
+//!         if perms.read  { prot |= Permission::READ; }
+//!         if perms.write { prot |= Permission::WRITE; }
+//!         if perms.exec  { prot |= Permission::EXEC; }
+//!         self.0.mem_map(base, data.len(), prot)?;
+//!         self.0.mem_write(base, data)
+//!     }
+//!
+//!     fn set_regs(&mut self, regs: &[u8]) -> Result<(), uc_error> {
+//!         for (reg, val) in X86_64_GPRS.iter().zip(regs.chunks(8)) {
+//!             self.0.reg_write(*reg,
+//!                 u64::from_le_bytes(val.try_into().unwrap()))?;
+//!         }
+//!         Ok(())
+//!     }
+//!
+//!     fn run(&mut self, pc: u64, count: u64) -> Result<u64, uc_error> {
+//!         self.0.emu_start(pc, u64::MAX, 0, count as usize)?;
+//!         self.0.pc_read()
+//!     }
+//! }
+//! ```
+
+use std::sync::Arc;
+use std::path::Path;
+use std::collections::{BTreeMap, HashMap};
+use crate::capture::{CaptureReader, Record};
+use crate::event::Event;
+use crate::excerpt::Mark;
+
+/// Size of the pages memory is reconstructed in
+const PAGE_SIZE: u64 = 4096;
+
+/// Largest mapping which is handed to the emulator
+const MAX_REGION: u64 = 256 << 20;
+
+/// Access permissions of a mapping
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Perms {
+    /// Readable
+    pub read: bool,
+
+    /// Writable
+    pub write: bool,
+
+    /// Executable
+    pub exec: bool,
+}
+
+/// Runs the code of a [`Window`], see the module documentation
+pub trait Emulator {
+    /// Errors of the emulator
+    type Error;
+
+    /// Map `data` at `base`. Both are page aligned
+    fn map(&mut self, base: u64, data: &[u8], perms: Perms)
+        -> Result<(), Self::Error>;
+
+    /// Set the general purpose registers, in QEMU's layout for the target
+    fn set_regs(&mut self, regs: &[u8]) -> Result<(), Self::Error>;
+
+    /// Run at most `count` instructions starting at `pc`, returning the PC
+    /// execution stopped at
+    fn run(&mut self, pc: u64, count: u64) -> Result<u64, Self::Error>;
+}
+
+/// A mapping of the process
+#[derive(Clone, Debug)]
+struct Region {
+    /// Length in bytes, page aligned
+    len: u64,
+
+    /// Access permissions
+    perms: Perms,
+
+    /// File the mapping starts out with the contents of, and the offset in
+    /// it, `None` for zeros
+    file: Option<(Arc<str>, u64)>,
+}
+
+/// Reconstructed memory of a process
+#[derive(Default)]
+struct Memory {
+    /// Mappings, keyed by base address
+    regions: BTreeMap<u64, Region>,
+
+    /// Pages which were materialized, keyed by address
+    pages: HashMap<u64, Box<[u8]>>,
+
+    /// Contents of mapped files, `None` if the file can't be read
+    files: HashMap<Arc<str>, Option<Arc<[u8]>>>,
+}
+
+impl Memory {
+    /// Get the mapping containing `addr`
+    fn region(&self, addr: u64) -> Option<(u64, &Region)> {
+        let (base, region) = self.regions.range(..=addr).next_back()?;
+        (addr - base < region.len).then_some((*base, region))
+    }
+
+    /// Map `len` bytes at `base`, replacing whatever was there
+    fn map(&mut self, base: u64, len: u64, region: Region) {
+        let end = base.saturating_add(len + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+        self.unmap(base, end - base);
+        self.regions.insert(base, Region { len: end - base, ..region });
+    }
+
+    /// Unmap `len` bytes at `base`, splitting mappings which overlap it
+    fn unmap(&mut self, base: u64, len: u64) {
+        let end = base.saturating_add(len);
+        let overlapping = self.regions.range(..end)
+            .filter(|(start, x)| *start + x.len > base)
+            .map(|(start, _)| *start)
+            .collect::<Vec<_>>();
+        for start in overlapping {
+            let region = self.regions.remove(&start).unwrap();
+            let rend = start + region.len;
+            if start < base {
+                self.regions.insert(start,
+                    Region { len: base - start, ..region.clone() });
+            }
+            if rend > end {
+                let file = region.file.clone()
+                    .map(|(path, offset)| (path, offset + (end - start)));
+                self.regions.insert(end,
+                    Region { len: rend - end, file, ..region });
+            }
+        }
+        self.pages.retain(|page, _| *page < base || *page >= end);
+    }
+
+    /// Get the page at `page`, materializing it, `None` if it's not mapped
+    fn page(&mut self, page: u64) -> Option<&mut Box<[u8]>> {
+        if !self.pages.contains_key(&page) {
+            let (base, region) = self.region(page)?;
+            let mut data = vec![0u8; PAGE_SIZE as usize].into_boxed_slice();
+            if let Some((path, offset)) = region.file.clone() {
+                let contents = self.files.entry(path.clone())
+                    .or_insert_with(|| std::fs::read(&*path).ok()
+                        .map(Into::into));
+                if let Some(contents) = contents {
+                    let start = (offset + (page - base)) as usize;
+                    if let Some(src) = contents.get(start..) {
+                        let len = src.len().min(data.len());
+                        data[..len].copy_from_slice(&src[..len]);
+                    }
+                }
+            }
+            self.pages.insert(page, data);
+        }
+        self.pages.get_mut(&page)
+    }
+
+    /// Store `bytes` at `addr`, returning `false` if any of it isn't mapped
+    fn write(&mut self, addr: u64, bytes: &[u8]) -> bool {
+        let mut mapped = true;
+        for (ii, byte) in bytes.iter().enumerate() {
+            let addr = addr.wrapping_add(ii as u64);
+            match self.page(addr & !(PAGE_SIZE - 1)) {
+                Some(page) => page[(addr % PAGE_SIZE) as usize] = *byte,
+                None => mapped = false,
+            }
+        }
+        mapped
+    }
+
+    /// Load `len` bytes at `addr`, `None` if any of it isn't mapped
+    fn read(&mut self, addr: u64, len: usize) -> Option<Vec<u8>> {
+        (0..len as u64).map(|ii| {
+            let addr = addr.wrapping_add(ii);
+            let page = self.page(addr & !(PAGE_SIZE - 1))?;
+            Some(page[(addr % PAGE_SIZE) as usize])
+        }).collect()
+    }
+}
+
+/// Replays the events of a process onto its reconstructed memory
+#[derive(Default)]
+pub struct Replay {
+    /// Reconstructed memory
+    memory: Memory,
+
+    /// Reads whose value differed from the reconstruction
+    mismatches: u64,
+
+    /// Writes whose value wasn't logged
+    unknown_writes: u64,
+}
+
+impl Replay {
+    /// Start with an empty address space
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replay the next `event` of any thread of the process
+    pub fn observe(&mut self, event: &Event) {
+        match *event {
+            Event::Mmap { base, len, anon, read, write, exec, ref path,
+                    offset } => {
+                let file = (!anon).then(|| (path.as_str().into(), offset));
+                self.memory.map(base, len,
+                    Region { len, perms: Perms { read, write, exec }, file });
+            }
+            Event::Munmap { base, len } => self.memory.unmap(base, len),
+            Event::Brk { old, new } if new > old => {
+                let perms = Perms { read: true, write: true, exec: false };
+                let old = (old + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+                if new > old {
+                    self.memory.map(old, new - old,
+                        Region { len: 0, perms, file: None });
+                }
+            }
+            Event::Write { addr, val, sz, .. } => {
+                let bytes = &val.to_le_bytes()[..sz.min(8) as usize];
+                self.memory.write(addr, bytes);
+            }
+            Event::Read { addr, val, sz, .. } => {
+                let bytes = &val.to_le_bytes()[..sz.min(8) as usize];
+                if self.memory.read(addr, bytes.len()).as_deref() != Some(bytes)
+                {
+                    self.mismatches += 1;
+                    self.memory.write(addr, bytes);
+                }
+            }
+            Event::WriteAddr { .. } => self.unknown_writes += 1,
+            Event::Rep { ref accesses, .. }
+                    if accesses.iter().any(|x| x.write) => {
+                self.unknown_writes += 1;
+            }
+            _ => {}
+        }
+    }
+
+    /// Take the state of a thread which is about to execute `pc` with the
+    /// registers `regs`
+    pub fn window(self, pc: u64, regs: Vec<u8>) -> Window {
+        Window {
+            pc,
+            regs,
+            memory:         self.memory,
+            mismatches:     self.mismatches,
+            unknown_writes: self.unknown_writes,
+        }
+    }
+}
+
+/// The reconstructed state of a thread at a point of a trace, see the module
+/// documentation
+pub struct Window {
+    /// PC of the next instruction
+    pub pc: u64,
+
+    /// General purpose registers, in QEMU's layout for the target
+    pub regs: Vec<u8>,
+
+    /// Reconstructed memory
+    memory: Memory,
+
+    /// Reads whose value differed from the reconstruction, up to this point
+    pub mismatches: u64,
+
+    /// Writes whose value wasn't logged, up to this point. Memory they
+    /// wrote is stale
+    pub unknown_writes: u64,
+}
+
+impl Window {
+    /// Reconstruct the state of the thread of `mark` right before its last
+    /// register or branch hook at or before `mark`, from the capture at
+    /// `capture`
+    pub fn at(capture: impl AsRef<Path>, mark: Mark)
+            -> std::io::Result<Self> {
+        let capture = capture.as_ref();
+        let invalid = |msg: String| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, msg)
+        };
+
+        // Find the last event with registers, and the process of the thread
+        let mut reader  = CaptureReader::open(capture)?;
+        let mut segment = 0;
+        let mut ordinal = 0;
+        let mut found   = None;
+        while let Some(record) = reader.next_record()? {
+            match record {
+                Record::Segment(x) => segment = x.index,
+                Record::Events { pid, tid, events }
+                        if segment == mark.segment && tid == mark.tid => {
+                    for event in events {
+                        if ordinal > mark.ordinal {
+                            break;
+                        }
+                        if matches!(event, Event::Regs { .. } |
+                                Event::Branch { .. }) {
+                            found = Some((pid, ordinal));
+                        }
+                        ordinal += 1;
+                    }
+                }
+                Record::Events { .. } => {}
+            }
+        }
+        let (pid, target) = found.ok_or_else(|| invalid(format!(
+            "No registers of thread {} at or before #{}", mark.tid,
+            mark.ordinal)))?;
+
+        // Replay the process up to it
+        let mut reader  = CaptureReader::open(capture)?;
+        let mut replay  = Replay::new();
+        let mut segment = 0;
+        let mut ordinal = 0;
+        while let Some(record) = reader.next_record()? {
+            let (rpid, tid, events) = match record {
+                Record::Segment(x) => {
+                    segment = x.index;
+                    continue;
+                }
+                Record::Events { pid, tid, events } => (pid, tid, events),
+            };
+            if segment != mark.segment || rpid != pid {
+                continue;
+            }
+
+            for event in events {
+                if tid == mark.tid {
+                    if ordinal == target {
+                        return match event {
+                            Event::Regs { pc, regs } |
+                            Event::Branch { pc, regs, .. } =>
+                                Ok(replay.window(pc, regs)),
+                            _ => unreachable!(),
+                        };
+                    }
+                    ordinal += 1;
+                }
+                replay.observe(&event);
+            }
+        }
+        Err(invalid("Capture changed while reading it".into()))
+    }
+
+    /// Load `len` bytes of memory at `addr`, `None` if any of it isn't
+    /// mapped
+    pub fn read(&mut self, addr: u64, len: usize) -> Option<Vec<u8>> {
+        self.memory.read(addr, len)
+    }
+
+    /// Store `bytes` to memory at `addr` before running, returning `false`
+    /// if any of it isn't mapped
+    pub fn write(&mut self, addr: u64, bytes: &[u8]) -> bool {
+        self.memory.write(addr, bytes)
+    }
+
+    /// Get general purpose register `index` of a target with `width` byte
+    /// registers
+    pub fn reg(&self, index: usize, width: usize) -> Option<u64> {
+        let bytes = self.regs.get(index * width..)?.get(..width.min(8))?;
+        let mut val = [0u8; 8];
+        val[..bytes.len()].copy_from_slice(bytes);
+        Some(u64::from_le_bytes(val))
+    }
+
+    /// Set general purpose register `index` of a target with `width` byte
+    /// registers, returning `false` if there's no such register
+    pub fn set_reg(&mut self, index: usize, width: usize, val: u64) -> bool {
+        let Some(bytes) = self.regs.get_mut(index * width..)
+                .and_then(|x| x.get_mut(..width.min(8))) else {
+            return false;
+        };
+        bytes.copy_from_slice(&val.to_le_bytes()[..bytes.len()]);
+        true
+    }
+
+    /// Map the memory and load the registers into `emu`, then run at most
+    /// `count` instructions from [`Window::pc`]. Returns the PC execution
+    /// stopped at. Mappings larger than 256 MiB are left out
+    pub fn run<E: Emulator>(&mut self, emu: &mut E, count: u64)
+            -> Result<u64, E::Error> {
+        let regions = self.memory.regions.iter()
+            .filter(|(_, x)| x.len <= MAX_REGION)
+            .map(|(base, x)| (*base, x.len, x.perms))
+            .collect::<Vec<_>>();
+        for (base, len, perms) in regions {
+            let mut data = Vec::with_capacity(len as usize);
+            for page in (base..base + len).step_by(PAGE_SIZE as usize) {
+                data.extend_from_slice(&self.memory.page(page).unwrap()[..]);
+            }
+            emu.map(base, &data, perms)?;
+        }
+
+        emu.set_regs(&self.regs)?;
+        emu.run(self.pc, count)
+    }
+}
+
+#[test]
+fn what_if() {
+    /// Emulates a single `add rax, [rdi]` by hand
+    #[derive(Default)]
+    struct Toy {
+        memory: BTreeMap<u64, Vec<u8>>,
+        rax:    u64,
+        rdi:    u64,
+    }
+
+    impl Emulator for Toy {
+        type Error = ();
+
+        fn map(&mut self, base: u64, data: &[u8], _perms: Perms)
+                -> Result<(), ()> {
+            self.memory.insert(base, data.to_vec());
+            Ok(())
+        }
+
+        fn set_regs(&mut self, regs: &[u8]) -> Result<(), ()> {
+            let reg = |x: usize| u64::from_le_bytes(
+                regs[x * 8..x * 8 + 8].try_into().unwrap());
+            (self.rax, self.rdi) = (reg(0), reg(7));
+            Ok(())
+        }
+
+        fn run(&mut self, pc: u64, _count: u64) -> Result<u64, ()> {
+            let (base, data) = self.memory.range(..=self.rdi).next_back()
+                .ok_or(())?;
+            let off = (self.rdi - base) as usize;
+            self.rax += u64::from_le_bytes(
+                data[off..off + 8].try_into().unwrap());
+            Ok(pc + 3)
+        }
+    }
+
+    let mut regs = vec![0u8; 16 * 8];
+    regs[7 * 8..8 * 8].copy_from_slice(&0x10008u64.to_le_bytes());
+
+    let mut replay = Replay::new();
+    for event in [
+        Event::Mmap {
+            base: 0x10000, len: 0x2000, anon: true, read: true, write: true,
+            exec: false, path: "".into(), offset: 0,
+        },
+        Event::Write { pc: 0x1000, addr: 0x10008, val: 5, sz: 8 },
+        // The kernel wrote here, only the read tells
+        Event::Read  { pc: 0x1004, addr: 0x11000, val: 0x41, sz: 1 },
+        Event::Read  { pc: 0x1008, addr: 0x10008, val: 5, sz: 8 },
+        Event::Munmap { base: 0x11000, len: 0x1000 },
+    ] {
+        replay.observe(&event);
+    }
+
+    let mut window = replay.window(0x2000, regs);
+    assert_eq!((window.mismatches, window.unknown_writes), (1, 0));
+    assert_eq!(window.read(0x10008, 2), Some(vec![5, 0]));
+    assert_eq!(window.read(0x11000, 1), None);
+    assert_eq!(window.reg(7, 8), Some(0x10008));
+
+    // What if the value in memory had been 7, and rax 1?
+    assert!(window.write(0x10008, &7u64.to_le_bytes()));
+    assert!(window.set_reg(0, 8, 1));
+    let mut toy = Toy::default();
+    assert_eq!(window.run(&mut toy, 1), Ok(0x2003));
+    assert_eq!(toy.rax, 8);
+    assert_eq!(toy.memory.keys().copied().collect::<Vec<_>>(), [0x10000]);
+}
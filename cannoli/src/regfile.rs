@@ -0,0 +1,340 @@
+//! Floating point and vector register files
+//!
+//! Register and branch hooks only carry the general purpose registers, as
+//! those are the only registers QEMU tells the jitter the location of. Math
+//! and crypto code keeps most of its state in the FP and vector registers
+//! instead. Every [`RegFile`] in [`crate::control::Filters::reg_files`] is
+//! captured along with the general purpose registers, on every instruction
+//! which gets a register or branch hook, and handed to
+//! [`crate::Cannoli::reg_file`].
+//!
+//! A register file is described by where its registers are in QEMU's
+//! `CPUArchState`, which depends on the QEMU version and the target. The
+//! offset can be looked up in the QEMU build, eg. with `ptype /o
+//! CPUX86State` in gdb or `pahole -C CPUX86State`. The constructors fill in
+//! the layout of the register files of the common targets:
+//!
+//! - [`RegFile::x87`]: `fpregs` of x86, the 8 80-bit x87 registers in
+//!   physical (not stack) order
+//! - [`RegFile::xmm`]: `xmm_regs` of x86, the SSE part of the 16 AVX-512
+//!   registers
+//! - [`RegFile::neon`]: `vfp.zregs` of AArch64, the NEON part of the 32 SVE
+//!   registers
+//! - [`RegFile::segments`]: `segs` of 32-bit x86, the segment selectors,
+//!   to tell where 16-bit code is, see [`crate::realmode`]
+//! - [`RegFile::hflags`]: `hflags` of 32-bit x86, which tell if the CPU is
+//!   running 16-bit code
+//!
+//! The offsets of the last two in the `qemu-i386` the patch is pinned to
+//! are [`I386_SEGS`] and [`I386_HFLAGS`].
+//!
+//! Only the first `width` bytes of each register are captured, so the
+//! unused upper parts of the larger registers QEMU keeps don't bloat the
+//! trace. Registers are stored in host byte order by QEMU, which is always
+//! little-endian for the jitter.
+//!
+//! The condition flags of ARM targets are captured the same way, with
+//! [`RegFile::nzcv`], usually only for the instructions which end a basic
+//! block (`branches_only`), so analyses can tell why a conditional branch
+//! went the way it did with [`Flags::condition`]. x86 is not supported:
+//! QEMU computes its flags lazily from the last flag-setting operation,
+//! which is only known at translation time and not stored in the CPU state
+//! at every instruction. The operands of x86 comparisons are available as
+//! [`crate::event::Event::Cmp`] instead.
+
+use serde::{Serialize, Deserialize};
+
+/// Largest register file which can be captured, in bytes
+pub const MAX_REG_FILE: u32 = 4096;
+
+/// Offset of `hflags` in the `CPUX86State` of `qemu-i386`, after the 8
+/// `regs`, `eip`, `eflags`, `cc_dst`, `cc_src`, `cc_src2`, `cc_op` and
+/// `df`, which are all 4 bytes on 32-bit x86
+pub const I386_HFLAGS: u32 = 0x3c;
+
+/// Offset of `segs` in the `CPUX86State` of `qemu-i386`, after `hflags`
+/// and `hflags2`
+pub const I386_SEGS: u32 = 0x44;
+
+/// How the registers of a [`RegFile`] are decoded
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RegKind {
+    /// Vector registers, holding lanes of integers or floats
+    #[default]
+    Vector,
+
+    /// x87 registers in the 80-bit extended precision format
+    X87,
+
+    /// The `CF`, `VF`, `NF` and `ZF` fields ARM targets keep the condition
+    /// flags in, see [`Flags`]
+    Nzcv,
+
+    /// Segment selectors of x86, see [`crate::realmode`]
+    Segments,
+
+    /// The `hflags` of x86, which mirror the mode bits of `CR0` and
+    /// `EFLAGS`, see [`crate::realmode`]
+    Hflags,
+}
+
+/// Condition flags of an ARM target
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Flags {
+    /// Negative
+    pub n: bool,
+
+    /// Zero
+    pub z: bool,
+
+    /// Carry
+    pub c: bool,
+
+    /// Overflow
+    pub v: bool,
+}
+
+impl Flags {
+    /// Evaluate the 4-bit ARM condition code `cond` (`0` is `eq`, `1` is
+    /// `ne`, up to `14` which is `al`) with these flags
+    pub fn condition(&self, cond: u8) -> bool {
+        let Flags { n, z, c, v } = *self;
+        let base = match (cond >> 1) & 7 {
+            0 => z,
+            1 => c,
+            2 => n,
+            3 => v,
+            4 => c && !z,
+            5 => n == v,
+            6 => !z && n == v,
+            _ => return true,
+        };
+
+        // Odd condition codes are the inverse of the even ones before them
+        base != (cond & 1 != 0)
+    }
+}
+
+/// A file of registers in QEMU's `CPUArchState`, see the module
+/// documentation
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RegFile {
+    /// Name of the registers, registers are named by appending their index
+    pub name: String,
+
+    /// How the registers are decoded
+    pub kind: RegKind,
+
+    /// Byte offset of the first register in `CPUArchState`
+    pub offset: u32,
+
+    /// Number of registers
+    pub count: u32,
+
+    /// Bytes from one register to the next in `CPUArchState`
+    pub stride: u32,
+
+    /// Bytes captured of each register
+    pub width: u32,
+
+    /// Only capture the register file for instructions which end a basic
+    /// block, with branch hooks
+    pub branches_only: bool,
+}
+
+impl RegFile {
+    /// x87 registers of x86 targets, with `fpregs` at `offset`
+    pub fn x87(offset: u32) -> Self {
+        Self { name: "fpr".into(), kind: RegKind::X87, offset, count: 8,
+            stride: 16, width: 10, branches_only: false }
+    }
+
+    /// SSE registers of x86 targets, with `xmm_regs` at `offset`. 32-bit
+    /// targets only have 8 of them
+    pub fn xmm(offset: u32, count: u32) -> Self {
+        Self { name: "xmm".into(), kind: RegKind::Vector, offset, count,
+            stride: 64, width: 16, branches_only: false }
+    }
+
+    /// NEON registers of AArch64 targets, with `vfp.zregs` at `offset`
+    pub fn neon(offset: u32) -> Self {
+        Self { name: "v".into(), kind: RegKind::Vector, offset, count: 32,
+            stride: 256, width: 16, branches_only: false }
+    }
+
+    /// Condition flags of ARM and AArch64 targets, with `CF` at `offset`,
+    /// captured for conditional branches only
+    pub fn nzcv(offset: u32) -> Self {
+        Self { name: "nzcv".into(), kind: RegKind::Nzcv, offset, count: 4,
+            stride: 4, width: 4, branches_only: true }
+    }
+
+    /// Segment selectors of 32-bit x86 targets, with `segs` at `offset`
+    /// ([`I386_SEGS`]), in QEMU's order (`es`, `cs`, `ss`, `ds`, `fs`, `gs`)
+    pub fn segments(offset: u32) -> Self {
+        Self { name: "seg".into(), kind: RegKind::Segments, offset, count: 6,
+            stride: 16, width: 2, branches_only: false }
+    }
+
+    /// The `hflags` of 32-bit x86 targets, with `hflags` at `offset`
+    /// ([`I386_HFLAGS`])
+    pub fn hflags(offset: u32) -> Self {
+        Self { name: "hflags".into(), kind: RegKind::Hflags, offset,
+            count: 1, stride: 4, width: 4, branches_only: false }
+    }
+
+    /// Number of bytes captured of the register file
+    pub fn size(&self) -> u32 {
+        self.count.saturating_mul(self.width)
+    }
+
+    /// Check if the register file can be captured
+    pub fn validate(&self) -> Result<(), String> {
+        if self.count == 0 || self.width == 0 {
+            return Err(format!("Register file `{}` is empty", self.name));
+        }
+        if self.stride < self.width {
+            return Err(format!("Registers of `{}` overlap, stride {} is \
+                less than width {}", self.name, self.stride, self.width));
+        }
+        if self.size() > MAX_REG_FILE {
+            return Err(format!("Register file `{}` is {} bytes, at most {} \
+                can be captured", self.name, self.size(), MAX_REG_FILE));
+        }
+        Ok(())
+    }
+
+    /// Get the name of register `index`
+    pub fn reg_name(&self, index: usize) -> String {
+        format!("{}{index}", self.name)
+    }
+
+    /// Get the bytes of register `index` from the captured register file
+    /// `regs`
+    pub fn reg<'a>(&self, regs: &'a [u8], index: usize) -> Option<&'a [u8]> {
+        let width = self.width as usize;
+        regs.get(index.checked_mul(width)?..)?.get(..width)
+    }
+
+    /// Get the value of the x87 register `index` as the nearest `f64`,
+    /// `None` if this isn't an x87 register file
+    pub fn float(&self, regs: &[u8], index: usize) -> Option<f64> {
+        if self.kind != RegKind::X87 {
+            return None;
+        }
+        let reg = self.reg(regs, index)?;
+        Some(f80_to_f64(reg.try_into().ok()?))
+    }
+
+    /// Get the condition flags, `None` if this isn't an `nzcv` register
+    /// file
+    pub fn flags(&self, regs: &[u8]) -> Option<Flags> {
+        if self.kind != RegKind::Nzcv {
+            return None;
+        }
+        let field = |index| self.lanes_u32(regs, index).first().copied();
+        let (cf, vf, nf, zf) = (field(0)?, field(1)?, field(2)?, field(3)?);
+
+        // QEMU keeps N and V in bit 31, C as 0 or 1, and Z as the inverse
+        Some(Flags {
+            n: nf & 0x8000_0000 != 0,
+            z: zf == 0,
+            c: cf != 0,
+            v: vf & 0x8000_0000 != 0,
+        })
+    }
+
+    /// Get the `u32` lanes of the vector register `index`
+    pub fn lanes_u32(&self, regs: &[u8], index: usize) -> Vec<u32> {
+        self.reg(regs, index).unwrap_or_default().chunks_exact(4)
+            .map(|x| u32::from_le_bytes(x.try_into().unwrap()))
+            .collect()
+    }
+
+    /// Get the `u64` lanes of the vector register `index`
+    pub fn lanes_u64(&self, regs: &[u8], index: usize) -> Vec<u64> {
+        self.reg(regs, index).unwrap_or_default().chunks_exact(8)
+            .map(|x| u64::from_le_bytes(x.try_into().unwrap()))
+            .collect()
+    }
+
+    /// Get the `f32` lanes of the vector register `index`
+    pub fn lanes_f32(&self, regs: &[u8], index: usize) -> Vec<f32> {
+        self.lanes_u32(regs, index).into_iter().map(f32::from_bits).collect()
+    }
+
+    /// Get the `f64` lanes of the vector register `index`
+    pub fn lanes_f64(&self, regs: &[u8], index: usize) -> Vec<f64> {
+        self.lanes_u64(regs, index).into_iter().map(f64::from_bits).collect()
+    }
+}
+
+/// Convert an 80-bit extended precision float, as stored in memory, to the
+/// nearest `f64`
+pub fn f80_to_f64(bytes: [u8; 10]) -> f64 {
+    let mantissa = u64::from_le_bytes(bytes[..8].try_into().unwrap());
+    let high     = u16::from_le_bytes([bytes[8], bytes[9]]);
+    let sign     = if high & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = (high & 0x7fff) as i32;
+
+    let value = match exponent {
+        // Infinities have no fraction bits set, anything else is a NaN
+        0x7fff if mantissa << 1 == 0 => f64::INFINITY,
+        0x7fff => f64::NAN,
+
+        // The integer bit is explicit, so the mantissa is in [0, 2)
+        // and denormals only differ in their exponent
+        _ => {
+            let exponent = exponent.max(1) - 16383;
+            (mantissa as f64 / (1u64 << 63) as f64) *
+                2f64.powi(exponent.clamp(-1100, 1100))
+        }
+    };
+    sign * value
+}
+
+#[test]
+fn decode_reg_files() {
+    let x87 = RegFile::x87(0x100);
+    assert!(x87.validate().is_ok());
+    assert!(RegFile { stride: 8, ..RegFile::xmm(0, 16) }.validate().is_err());
+
+    // fpr0 = -2.5, fpr1 = 1.0, fpr2 = +inf, the rest 0
+    let mut regs = vec![0u8; x87.size() as usize];
+    regs[..10].copy_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0xa0, 0x00, 0xc0]);
+    regs[10..20].copy_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0x80, 0xff, 0x3f]);
+    regs[20..30].copy_from_slice(&[0, 0, 0, 0, 0, 0, 0, 0x80, 0xff, 0x7f]);
+    assert_eq!(x87.float(&regs, 0), Some(-2.5));
+    assert_eq!(x87.float(&regs, 1), Some(1.0));
+    assert_eq!(x87.float(&regs, 2), Some(f64::INFINITY));
+    assert_eq!(x87.float(&regs, 3), Some(0.0));
+    assert_eq!(x87.float(&regs, 8), None);
+    assert_eq!(x87.reg_name(3), "fpr3");
+
+    let xmm  = RegFile::xmm(0x400, 16);
+    let mut regs = vec![0u8; xmm.size() as usize];
+    regs[16..24].copy_from_slice(&1.5f64.to_le_bytes());
+    regs[24..28].copy_from_slice(&0xdeadbeefu32.to_le_bytes());
+    assert_eq!(xmm.lanes_f64(&regs, 1)[0], 1.5);
+    assert_eq!(xmm.lanes_u32(&regs, 1)[2], 0xdeadbeef);
+    assert_eq!(xmm.float(&regs, 1), None);
+}
+
+#[test]
+fn nzcv_flags() {
+    let nzcv = RegFile::nzcv(0x200);
+    assert!(nzcv.validate().is_ok() && nzcv.branches_only);
+
+    // After `cmp w0, #5` with w0 = 3: negative, not zero, borrow
+    let regs = [0u32, 0, 0xfffffffe, 0xfffffffe].iter()
+        .flat_map(|x| x.to_le_bytes())
+        .collect::<Vec<_>>();
+    let flags = nzcv.flags(&regs).unwrap();
+    assert_eq!(flags, Flags { n: true, z: false, c: false, v: false });
+    assert!(!flags.condition(0x0) && flags.condition(0x1));   // eq, ne
+    assert!(flags.condition(0xb) && !flags.condition(0xc));   // lt, gt
+    assert!(flags.condition(0x3) && flags.condition(0xe));    // lo, al
+    assert_eq!(RegFile::x87(0).flags(&regs), None);
+}
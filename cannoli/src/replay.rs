@@ -0,0 +1,288 @@
+//! Replaying captures through a [`Cannoli`] implementation
+//!
+//! Developing an analysis against a live target means re-running QEMU for
+//! every change. With a capture of the target (see [`crate::capture`]),
+//! [`read_trace`] drives the analysis from the file instead, invoking its
+//! callbacks as the server would have for the traced events: the threads of
+//! every segment are started when their first events show up, and exit in
+//! order of appearance at the end of their segment.
+//!
+//! Captures hold events rather than the raw trace, so some callbacks can't
+//! be replayed: register events go to [`Cannoli::exec_with_regs`], which
+//! can't tell them apart from those of [`Cannoli::regs`], memory accesses
+//! don't record if QEMU's helpers made them, and system calls, edges,
+//! `mprotect()` and epochs aren't recorded at all. The contexts are
+//! created from the manifest of the segment where there is one, the
+//! architecture isn't recorded and has to be given.
+
+use std::path::Path;
+use std::sync::Arc;
+use crate::{Architecture, Cannoli, ClientInfo};
+use crate::capture::{CaptureReader, Record};
+use crate::event::Event;
+use crate::harness::RunManifest;
+use crate::heap::HeapEvent;
+
+/// A target thread being replayed
+struct Thread<T: Cannoli> {
+    /// Process ID
+    pid: i32,
+
+    /// Thread ID
+    tid: i32,
+
+    /// Context of the process
+    pid_ctx: Arc<T::PidContext>,
+
+    /// Context of the thread
+    tid_ctx: T::TidContext,
+
+    /// The analysis of the thread
+    cannoli: T,
+}
+
+/// Replays the segments of a capture, see the module documentation
+struct Replay<T: Cannoli> {
+    /// Architecture of the target
+    arch: Architecture,
+
+    /// Whether the target is big endian
+    big_endian: bool,
+
+    /// Manifest of the current segment
+    manifest: Option<Box<RunManifest>>,
+
+    /// Processes of the current segment, in order of appearance
+    pids: Vec<(i32, Arc<T::PidContext>)>,
+
+    /// Threads of the current segment, in order of appearance
+    threads: Vec<Thread<T>>,
+
+    /// Number of events replayed
+    events: u64,
+}
+
+impl<T: Cannoli> Replay<T> {
+    /// Build the information a client of process `pid` would have sent
+    fn client_info(&self, pid: i32, tid: i32) -> ClientInfo {
+        let manifest = self.manifest.as_deref();
+        ClientInfo {
+            uid:        0,
+            arch:       self.arch,
+            big_endian: self.big_endian,
+            ppid:       0,
+            pid, tid,
+            cpu:        None,
+            pcomm:      None,
+            comm:       None,
+            exe:        manifest.map(|x| x.guest.path.display().to_string()),
+            argv:       manifest.map_or(Vec::new(), |x| x.argv.clone()),
+            env:        manifest.map_or(Vec::new(), |x| {
+                x.env.iter().map(|(k, v)| format!("{k}={v}")).collect()
+            }),
+            cwd:        manifest.map(|x| x.cwd.display().to_string()),
+            tenant:     None,
+        }
+    }
+
+    /// Get the index of the thread `tid` of `pid`, starting it if it's new
+    fn thread(&mut self, pid: i32, tid: i32) -> usize {
+        if let Some(index) = self.threads.iter()
+                .position(|x| x.pid == pid && x.tid == tid) {
+            return index;
+        }
+
+        let ci = self.client_info(pid, tid);
+        let first = self.pids.is_empty();
+        let pid_ctx = match self.pids.iter().find(|x| x.0 == pid) {
+            Some((_, ctx)) => ctx.clone(),
+            None => {
+                let ctx = T::init_pid(&ci);
+                self.pids.push((pid, ctx.clone()));
+                ctx
+            }
+        };
+        T::thread_start(&pid_ctx, tid);
+        let (mut cannoli, tid_ctx) = T::init_tid(&pid_ctx, &ci);
+
+        // The guest the segment launched starts running its image
+        if let (true, Some(exe)) = (first, &ci.exe) {
+            let mut trace = Vec::new();
+            T::exec_image(&pid_ctx, &tid_ctx, exe, &ci.argv, &mut trace);
+            cannoli.trace(&pid_ctx, &tid_ctx, &trace);
+        }
+
+        self.threads.push(Thread { pid, tid, pid_ctx, tid_ctx, cannoli });
+        self.threads.len() - 1
+    }
+
+    /// Replay a chunk of `events` of the thread `tid` of `pid`
+    fn events(&mut self, pid: i32, tid: i32, events: &[Event]) {
+        let index  = self.thread(pid, tid);
+        let thread = &mut self.threads[index];
+        let mut trace = Vec::new();
+        for event in events {
+            dispatch::<T>(&thread.pid_ctx, &thread.tid_ctx, event, &mut trace);
+        }
+        thread.cannoli.trace(&thread.pid_ctx, &thread.tid_ctx, &trace);
+        self.events += events.len() as u64;
+    }
+
+    /// End the current segment, exiting its threads and processes
+    fn finish(&mut self) {
+        for mut thread in self.threads.drain(..) {
+            thread.cannoli.thread_exit(&thread.pid_ctx, &thread.tid_ctx,
+                thread.tid);
+        }
+        for (_, ctx) in self.pids.drain(..) {
+            T::exit(&ctx, None);
+        }
+    }
+}
+
+/// Invoke the callback of `event` of [`Cannoli`] `T`
+fn dispatch<T: Cannoli>(pid: &T::PidContext, tid: &T::TidContext,
+        event: &Event, trace: &mut Vec<T::Trace>) {
+    match event {
+        Event::Exec { pc } => T::exec(pid, tid, *pc, trace),
+        Event::Regs { pc, regs } =>
+            T::exec_with_regs(pid, tid, *pc, regs, trace),
+        Event::Branch { pc, taken, regs } =>
+            T::branch(pid, tid, *pc, *taken, regs, trace),
+        Event::Read { pc, addr, val, sz } =>
+            T::read(pid, tid, *pc, *addr, *val, *sz, false, trace),
+        Event::Write { pc, addr, val, sz } =>
+            T::write(pid, tid, *pc, *addr, *val, *sz, false, trace),
+        Event::ReadAddr { pc, addr, sz } =>
+            T::read_addr(pid, tid, *pc, *addr, *sz, false, trace),
+        Event::WriteAddr { pc, addr, sz } =>
+            T::write_addr(pid, tid, *pc, *addr, *sz, false, trace),
+        Event::Rep { pc, count, backward, accesses } => {
+            // Every iteration accesses the next element
+            for iter in 0..*count {
+                for access in accesses {
+                    let step = iter.wrapping_mul(access.sz as u64);
+                    let addr = if *backward {
+                        access.addr.wrapping_sub(step)
+                    } else {
+                        access.addr.wrapping_add(step)
+                    };
+                    if access.write {
+                        T::write_addr(pid, tid, *pc, addr, access.sz, false,
+                            trace);
+                    } else {
+                        T::read_addr(pid, tid, *pc, addr, access.sz, false,
+                            trace);
+                    }
+                }
+            }
+        }
+        Event::Cmp { pc, lhs, rhs, sz } =>
+            T::cmp(pid, tid, *pc, *lhs, *rhs, *sz, trace),
+        Event::RegFile { pc, index, regs } =>
+            T::reg_file(pid, tid, *pc, *index as usize, regs, trace),
+        Event::Mmap { base, len, anon, read, write, exec, path, offset } =>
+            T::mmap(pid, tid, *base, *len, *anon, *read, *write, *exec,
+                path, *offset, trace),
+        Event::Munmap { base, len } =>
+            T::munmap(pid, tid, *base, *len, trace),
+        Event::Brk { old, new } =>
+            T::heap(pid, tid, &HeapEvent::Brk { old: *old, new: *new }, trace),
+        Event::Arena { base, len } =>
+            T::heap(pid, tid, &HeapEvent::Arena { base: *base, len: *len },
+                trace),
+        Event::Signal { sig, from, to } =>
+            T::signal(pid, tid, *sig, *from, *to, trace),
+    }
+}
+
+/// Replay the capture at `path` of a target of `arch` through `T`, see the
+/// module documentation. Returns the number of events replayed
+pub fn read_trace<T: Cannoli>(path: impl AsRef<Path>, arch: Architecture,
+        big_endian: bool) -> std::io::Result<u64> {
+    let mut reader = CaptureReader::open(path)?;
+    let mut replay = Replay::<T> {
+        arch, big_endian,
+        manifest: None,
+        pids:     Vec::new(),
+        threads:  Vec::new(),
+        events:   0,
+    };
+    while let Some(record) = reader.next_record()? {
+        match record {
+            Record::Segment(segment) => {
+                replay.finish();
+                replay.manifest = segment.manifest;
+            }
+            Record::Events { pid, tid, events } =>
+                replay.events(pid, tid, &events),
+        }
+    }
+    replay.finish();
+    Ok(replay.events)
+}
+
+#[test]
+fn replay_capture() {
+    use std::sync::Mutex;
+    use crate::capture::CaptureWriter;
+
+    /// Logs the callbacks it gets
+    struct Log;
+
+    /// Callbacks so far
+    static LOG: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    impl Cannoli for Log {
+        type Trace = String;
+        type PidContext = ();
+        type TidContext = i32;
+
+        fn init_pid(ci: &ClientInfo) -> Arc<()> {
+            LOG.lock().unwrap().push(format!("pid {}", ci.pid));
+            Arc::new(())
+        }
+
+        fn init_tid(_pid: &(), ci: &ClientInfo) -> (Self, i32) {
+            (Self, ci.tid)
+        }
+
+        fn exec(_pid: &(), _tid: &i32, pc: u64, trace: &mut Vec<String>) {
+            trace.push(format!("exec {pc:#x}"));
+        }
+
+        fn write_addr(_pid: &(), _tid: &i32, _pc: u64, addr: u64, _sz: u8,
+                _helper: bool, trace: &mut Vec<String>) {
+            trace.push(format!("write {addr:#x}"));
+        }
+
+        fn trace(&mut self, _pid: &(), tid: &i32, trace: &[String]) {
+            LOG.lock().unwrap().extend(
+                trace.iter().map(|x| format!("{tid}: {x}")));
+        }
+
+        fn thread_exit(&mut self, _pid: &(), _ctx: &i32, tid: i32) {
+            LOG.lock().unwrap().push(format!("exit {tid}"));
+        }
+    }
+
+    let path = std::env::temp_dir()
+        .join(format!("cannoli_replay_{}", std::process::id()));
+    let mut writer = CaptureWriter::create(&path, None).unwrap()
+        .compression(Some(3));
+    writer.write_events(1, 1, &[Event::Exec { pc: 0x1000 }]).unwrap();
+    writer.write_events(1, 2, &[Event::Rep { pc: 0x2000, count: 2,
+        backward: true, accesses: vec![crate::event::RepAccess {
+            write: true, addr: 0x5008, sz: 8 }] }]).unwrap();
+    writer.next_segment(None).unwrap();
+    writer.write_events(3, 3, &[Event::Exec { pc: 0x3000 }]).unwrap();
+    drop(writer);
+
+    assert_eq!(read_trace::<Log>(&path, Architecture::X86_64, false)
+        .unwrap(), 3);
+    assert_eq!(*LOG.lock().unwrap(), [
+        "pid 1", "1: exec 0x1000", "2: write 0x5008", "2: write 0x5000",
+        "exit 1", "exit 2", "pid 3", "3: exec 0x3000", "exit 3",
+    ]);
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,230 @@
+//! Sparse, file-backed shadow memory
+//!
+//! Analyses like taint tracking or ASan-style checking keep a shadow value
+//! for every piece of guest memory. A flat shadow of a 64-bit address space
+//! is out of the question, and even a map of shadow pages grows without bound
+//! on targets which touch wide but sparse ranges (eg. huge `mmap()`s, JIT
+//! heaps, allocator arenas).
+//!
+//! [`ShadowMemory`] keeps one shadow byte per granule of guest memory, where
+//! the granularity is a power of two chosen by the analysis (1 for byte
+//! precise taint, 8 for ASan-like shadow). Shadow memory is split into
+//! chunks which are only materialized when something non-zero is stored in
+//! them, reading a chunk which was never written just gives zeros. At most a
+//! configurable number of chunks are kept in RAM, the least recently used
+//! chunk is written out to a backing file when another one is needed, and is
+//! read back on its next access.
+//!
+//! The backing file is unlinked as soon as it's created, so it disappears
+//! with the analysis.
+
+use std::fs::File;
+use std::path::Path;
+use std::collections::{BTreeMap, HashMap};
+use std::os::unix::fs::FileExt;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Number of shadow bytes in a chunk
+const CHUNK_SIZE: u64 = 64 * 1024;
+
+/// Used to give backing files of the same process unique names
+static NEXT_FILE: AtomicU64 = AtomicU64::new(0);
+
+/// A chunk which is resident in RAM
+struct Chunk {
+    /// Shadow bytes of the chunk
+    bytes: Box<[u8]>,
+
+    /// Last time the chunk was used, as a value of `ShadowMemory::tick`
+    used: u64,
+
+    /// Set if the chunk changed since it was last written to the file
+    dirty: bool,
+}
+
+/// Shadow memory for a guest address space, see the module documentation
+pub struct ShadowMemory {
+    /// log2 of the number of guest bytes per shadow byte
+    shift: u32,
+
+    /// Maximum number of chunks kept in RAM
+    max_resident: usize,
+
+    /// File chunks are written to when they're evicted
+    file: File,
+
+    /// Offset in the file of every chunk which was ever evicted, keyed by
+    /// chunk number
+    offsets: HashMap<u64, u64>,
+
+    /// Chunks in RAM, keyed by chunk number
+    resident: HashMap<u64, Chunk>,
+
+    /// Chunk numbers of the resident chunks, keyed by when they were last
+    /// used
+    lru: BTreeMap<u64, u64>,
+
+    /// Incremented on every chunk access
+    tick: u64,
+}
+
+impl ShadowMemory {
+    /// Create a shadow memory with one shadow byte per `granularity` guest
+    /// bytes, which keeps at most `max_resident` bytes of shadow in RAM. The
+    /// backing file is created in the temporary directory
+    pub fn new(granularity: u64, max_resident: u64)
+            -> std::io::Result<Self> {
+        Self::in_dir(std::env::temp_dir(), granularity, max_resident)
+    }
+
+    /// Like [`ShadowMemory::new`], with the backing file in `dir`
+    pub fn in_dir(dir: impl AsRef<Path>, granularity: u64,
+            max_resident: u64) -> std::io::Result<Self> {
+        assert!(granularity.is_power_of_two(),
+            "Shadow granularity must be a power of two");
+
+        let path = dir.as_ref().join(format!("cannoli_shadow_{}_{}",
+            std::process::id(), NEXT_FILE.fetch_add(1, Ordering::Relaxed)));
+        let file = File::options().read(true).write(true).create_new(true)
+            .open(&path)?;
+        std::fs::remove_file(&path)?;
+
+        Ok(Self {
+            shift:        granularity.trailing_zeros(),
+            max_resident: (max_resident / CHUNK_SIZE).max(1) as usize,
+            offsets:      HashMap::new(),
+            resident:     HashMap::new(),
+            lru:          BTreeMap::new(),
+            tick:         0,
+            file,
+        })
+    }
+
+    /// Number of guest bytes covered by a shadow byte
+    pub fn granularity(&self) -> u64 {
+        1 << self.shift
+    }
+
+    /// Number of bytes of shadow currently in RAM
+    pub fn resident(&self) -> u64 {
+        self.resident.len() as u64 * CHUNK_SIZE
+    }
+
+    /// Get the shadow byte of the granule containing `addr`
+    pub fn get(&mut self, addr: u64) -> std::io::Result<u8> {
+        let index = addr >> self.shift;
+        let Some(chunk) = self.chunk(index / CHUNK_SIZE, false)? else {
+            return Ok(0);
+        };
+        Ok(chunk.bytes[(index % CHUNK_SIZE) as usize])
+    }
+
+    /// Set the shadow byte of the granule containing `addr` to `val`
+    pub fn set(&mut self, addr: u64, val: u8) -> std::io::Result<()> {
+        self.fill(addr, 1, val)
+    }
+
+    /// Set the shadow bytes of every granule overlapping `addr..addr + len`
+    /// to `val`, eg. to clear the shadow of unmapped memory
+    pub fn fill(&mut self, addr: u64, len: u64, val: u8)
+            -> std::io::Result<()> {
+        if len == 0 {
+            return Ok(());
+        }
+
+        let mut index = addr >> self.shift;
+        let last      = addr.saturating_add(len - 1) >> self.shift;
+        while index <= last {
+            let offset = index % CHUNK_SIZE;
+            let count  = (CHUNK_SIZE - offset)
+                .min((last - index).saturating_add(1));
+
+            // Clearing memory that was never written doesn't need a chunk
+            if let Some(chunk) = self.chunk(index / CHUNK_SIZE, val != 0)? {
+                chunk.bytes[offset as usize..(offset + count) as usize]
+                    .fill(val);
+                chunk.dirty = true;
+            }
+
+            let Some(next) = index.checked_add(count) else { break };
+            index = next;
+        }
+        Ok(())
+    }
+
+    /// Get chunk `number`, making it resident. If the chunk was never
+    /// materialized it's only created when `create` is set
+    fn chunk(&mut self, number: u64, create: bool)
+            -> std::io::Result<Option<&mut Chunk>> {
+        self.tick += 1;
+        let tick = self.tick;
+
+        if !self.resident.contains_key(&number) {
+            let offset = self.offsets.get(&number).copied();
+            if offset.is_none() && !create {
+                return Ok(None);
+            }
+
+            if self.resident.len() >= self.max_resident {
+                self.evict()?;
+            }
+
+            let mut bytes = vec![0; CHUNK_SIZE as usize].into_boxed_slice();
+            if let Some(offset) = offset {
+                self.file.read_exact_at(&mut bytes, offset)?;
+            }
+            self.resident.insert(number, Chunk { bytes, used: tick,
+                dirty: false });
+            self.lru.insert(tick, number);
+        }
+
+        let chunk = self.resident.get_mut(&number).unwrap();
+        self.lru.remove(&chunk.used);
+        self.lru.insert(tick, number);
+        chunk.used = tick;
+        Ok(Some(chunk))
+    }
+
+    /// Write the least recently used chunk to the file and drop it from RAM
+    fn evict(&mut self) -> std::io::Result<()> {
+        let Some((_, number)) = self.lru.pop_first() else { return Ok(()) };
+        let chunk = self.resident.remove(&number).unwrap();
+        if chunk.dirty {
+            let next   = self.offsets.len() as u64 * CHUNK_SIZE;
+            let offset = *self.offsets.entry(number).or_insert(next);
+            self.file.write_all_at(&chunk.bytes, offset)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn shadow_eviction() {
+    // Room for two chunks, with 8 byte granules
+    let mut shadow = ShadowMemory::new(8, CHUNK_SIZE * 2).unwrap();
+    let stride = CHUNK_SIZE * 8;
+
+    // Reads and clears don't materialize anything
+    assert_eq!(shadow.get(0xffff_8000_0000_0000).unwrap(), 0);
+    shadow.fill(0, stride * 16, 0).unwrap();
+    assert_eq!(shadow.resident(), 0);
+
+    // Far apart writes spill to the file and come back
+    for ii in 0..8 {
+        shadow.set(0x7f00_0000_0000 + ii * stride, ii as u8 + 1).unwrap();
+    }
+    assert_eq!(shadow.resident(), CHUNK_SIZE * 2);
+    for ii in 0..8 {
+        assert_eq!(shadow.get(0x7f00_0000_0007 + ii * stride).unwrap(),
+            ii as u8 + 1);
+        assert_eq!(shadow.get(0x7f00_0000_0008 + ii * stride).unwrap(), 0);
+    }
+
+    // Fills across chunk boundaries, up to the end of the address space
+    shadow.fill(stride - 1, 2, 0xaa).unwrap();
+    assert_eq!(shadow.get(stride - 8).unwrap(), 0xaa);
+    assert_eq!(shadow.get(stride).unwrap(), 0xaa);
+    assert_eq!(shadow.get(stride + 8).unwrap(), 0);
+    shadow.fill(u64::MAX - 15, 16, 1).unwrap();
+    assert_eq!(shadow.get(u64::MAX).unwrap(), 1);
+}
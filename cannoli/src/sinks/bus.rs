@@ -0,0 +1,484 @@
+//! Sink publishing the events of every thread to subscribers which come and
+//! go at runtime
+//!
+//! [`channel`](super::channel) hands every event to a single consumer, set
+//! up before the server starts. Applications embedding Cannoli, like a GUI
+//! with several views of a live run, instead run `Recorder<BusSink>` in
+//! [`crate::create_cannoli`] for the whole run, and [`subscribe`] to the
+//! kinds of events (see [`Kind`]) each view wants, with a filter on top:
+//!
+//! ```no_run
+//! use cannoli::Event;
+//! use cannoli::grep::Kind;
+//! use cannoli::sinks::Recorder;
+//! use cannoli::sinks::bus::{self, BusSink};
+//!
+//! std::thread::spawn(|| cannoli::create_cannoli::<Recorder<BusSink>>(4));
+//!
+//! let writes = bus::subscribe(Kind::Write, |x| {
+//!     matches!(x, Event::Write { addr: 0x1000..=0x1fff, .. })
+//! });
+//! for event in writes.take(100) {
+//!     println!("{event:?}");
+//! }
+//! ```
+//!
+//! A [`Subscription`] is an [`Iterator`] over the matching events, it gets
+//! the events published after it was made, and dropping it detaches it.
+//! Its kinds and filter can be changed while it's attached, with
+//! [`Subscription::set_topics`] and [`Subscription::set_filter`].
+//!
+//! Unlike the channel, the bus doesn't stall the target by default: a
+//! subscriber which doesn't keep up loses whole chunks of events, counted in
+//! [`Subscription::dropped`], and events nobody subscribed to are dropped.
+//! Events of a thread are in trace order, events of different threads are
+//! interleaved chunk by chunk.
+//!
+//! Every subscriber has a queue of its own, and what happens when it's full
+//! is up to the subscriber, see [`Overflow`] and [`subscribe_with`]. A
+//! consumer which must not miss events can hold up the target for a while,
+//! and one which is better off gone than behind can be detached. Either
+//! way the other subscribers get their chunks first, so one slow or crashed
+//! consumer doesn't hold up the rest. Consumers which are detached (or whose
+//! thread panicked with their subscription) are reported to the callback
+//! set with [`on_consumer_lost`], and their subscription ends once it
+//! received what was queued.
+
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicU8, AtomicU64, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::time::{Duration, Instant};
+use crate::ClientInfo;
+use crate::event::Event;
+use crate::grep::Kind;
+use crate::sinks::Sink;
+
+/// Number of chunks which can be waiting for each subscriber by default
+const CHUNKS: usize = 64;
+
+/// How often a full queue is checked while waiting for room
+const WAIT_POLL: Duration = Duration::from_millis(1);
+
+/// Filter of a subscription
+type Filter = Box<dyn Fn(&Event) -> bool + Send + Sync>;
+
+/// Callback told about lost consumers
+type LostHook = Box<dyn Fn(&ConsumerLost) + Send + Sync>;
+
+/// Every attached subscriber
+static SUBSCRIBERS: RwLock<Vec<Arc<Entry>>> = RwLock::new(Vec::new());
+
+/// Callback set with [`on_consumer_lost`]
+static LOST_HOOK: RwLock<Option<LostHook>> = RwLock::new(None);
+
+/// What to do with a chunk for a subscriber whose queue is full
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Overflow {
+    /// Drop the chunk, counting its events in [`Subscription::dropped`]
+    Drop,
+
+    /// Hold up the thread publishing the chunk until there's room, for up
+    /// to the given time, and detach the subscriber if there's none by then
+    Wait(Duration),
+
+    /// Detach the subscriber right away
+    Detach,
+}
+
+/// Queue and backpressure policy of a subscriber
+#[derive(Clone, Debug)]
+pub struct ConsumerConfig {
+    /// Name of the consumer, for [`ConsumerLost`]
+    pub name: String,
+
+    /// Number of chunks which can be waiting for the consumer
+    pub capacity: usize,
+
+    /// What to do with chunks while the queue is full
+    pub overflow: Overflow,
+}
+
+impl Default for ConsumerConfig {
+    fn default() -> Self {
+        Self {
+            name:     String::new(),
+            capacity: CHUNKS,
+            overflow: Overflow::Drop,
+        }
+    }
+}
+
+/// Why a consumer was lost
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LostReason {
+    /// Its queue was full with [`Overflow::Detach`]
+    Full,
+
+    /// Its queue stayed full for the whole [`Overflow::Wait`]
+    Timeout,
+
+    /// The thread holding its subscription panicked
+    Panicked,
+}
+
+/// A consumer detached from the bus without dropping its subscription
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConsumerLost {
+    /// Name of the consumer, see [`ConsumerConfig::name`]
+    pub name: String,
+
+    /// Why it was lost
+    pub reason: LostReason,
+
+    /// Events it lost before, see [`Subscription::dropped`]
+    pub dropped: u64,
+}
+
+/// Invoke `hook` whenever a consumer is lost, see the module documentation.
+/// The hook is invoked on the thread which detached the consumer, often a
+/// trace processing thread, so it shouldn't block
+pub fn on_consumer_lost(hook: impl Fn(&ConsumerLost) + Send + Sync + 'static) {
+    *LOST_HOOK.write().unwrap() = Some(Box::new(hook));
+}
+
+/// Events of one thread, in trace order
+struct Chunk {
+    /// Process ID of the thread
+    pid: i32,
+
+    /// Thread ID of the thread
+    tid: i32,
+
+    /// The events
+    events: Vec<Event>,
+}
+
+/// State of a subscription shared by both sides
+struct Subscriber {
+    /// Mask of the kinds of events subscribed to, see [`Kind::bit`]
+    kinds: AtomicU8,
+
+    /// Filter the events also have to pass
+    filter: RwLock<Filter>,
+
+    /// Number of events dropped because the subscription was full
+    dropped: AtomicU64,
+
+    /// Why the subscriber was lost, `None` while it's attached
+    lost: Mutex<Option<LostReason>>,
+}
+
+/// Publishing side of a subscription, dropping the last reference to it
+/// ends the subscription
+struct Entry {
+    /// State of the subscription
+    subscriber: Arc<Subscriber>,
+
+    /// Channel to the subscription
+    sender: SyncSender<Chunk>,
+
+    /// Queue and backpressure policy
+    config: ConsumerConfig,
+}
+
+/// Subscribe to the events of `kind` which pass `filter`, published by every
+/// [`BusSink`] from now on. Chunks are dropped while the subscription is
+/// full, see [`subscribe_with`] for other policies
+pub fn subscribe(kind: Kind,
+        filter: impl Fn(&Event) -> bool + Send + Sync + 'static)
+        -> Subscription {
+    subscribe_with(kind, filter, ConsumerConfig::default())
+}
+
+/// Subscribe like [`subscribe`], with the queue and backpressure policy of
+/// `config`
+pub fn subscribe_with(kind: Kind,
+        filter: impl Fn(&Event) -> bool + Send + Sync + 'static,
+        config: ConsumerConfig) -> Subscription {
+    let (sender, receiver) = sync_channel(config.capacity);
+    let subscriber = Arc::new(Subscriber {
+        kinds:   AtomicU8::new(kind.bit()),
+        filter:  RwLock::new(Box::new(filter)),
+        dropped: AtomicU64::new(0),
+        lost:    Mutex::new(None),
+    });
+    SUBSCRIBERS.write().unwrap().push(Arc::new(Entry {
+        subscriber: subscriber.clone(),
+        sender, config,
+    }));
+    Subscription {
+        subscriber,
+        receiver,
+        chunk: Vec::new().into_iter(),
+        pid:   0,
+        tid:   0,
+    }
+}
+
+/// Detach `subscriber` from the bus as lost for `reason`, unless it's gone
+/// already
+fn detach(subscriber: &Arc<Subscriber>, name: &str, reason: LostReason) {
+    let mut subscribers = SUBSCRIBERS.write().unwrap();
+    let Some(index) = subscribers.iter()
+            .position(|x| Arc::ptr_eq(&x.subscriber, subscriber)) else {
+        return;
+    };
+    subscribers.remove(index);
+    drop(subscribers);
+
+    *subscriber.lost.lock().unwrap() = Some(reason);
+    if let Some(hook) = LOST_HOOK.read().unwrap().as_ref() {
+        hook(&ConsumerLost {
+            name:    name.to_string(),
+            reason,
+            dropped: subscriber.dropped.load(Ordering::Relaxed),
+        });
+    }
+}
+
+/// Publish the events of the thread `tid` of the process `pid`
+fn publish(pid: i32, tid: i32, events: &[Event]) {
+    // Subscribers can come and go while chunks wait for room
+    let subscribers = SUBSCRIBERS.read().unwrap().clone();
+    if subscribers.is_empty() {
+        return;
+    }
+
+    // Kinds are only worked out once for every subscriber
+    let masks = events.iter().map(Kind::mask).collect::<Vec<_>>();
+    let mut waiting = Vec::new();
+    for entry in &subscribers {
+        let subscriber = &entry.subscriber;
+        let kinds  = subscriber.kinds.load(Ordering::Relaxed);
+        let filter = subscriber.filter.read().unwrap();
+        let events = events.iter().zip(&masks)
+            .filter(|(x, mask)| *mask & kinds != 0 && filter(x))
+            .map(|(x, _)| x.clone())
+            .collect::<Vec<_>>();
+        if events.is_empty() {
+            continue;
+        }
+
+        let count = events.len() as u64;
+        let chunk = Chunk { pid, tid, events };
+        match (entry.sender.try_send(chunk), entry.config.overflow) {
+            (Err(TrySendError::Full(_)), Overflow::Drop) => {
+                subscriber.dropped.fetch_add(count, Ordering::Relaxed);
+            }
+            (Err(TrySendError::Full(_)), Overflow::Detach) => {
+                subscriber.dropped.fetch_add(count, Ordering::Relaxed);
+                detach(subscriber, &entry.config.name, LostReason::Full);
+            }
+            (Err(TrySendError::Full(chunk)), Overflow::Wait(timeout)) => {
+                waiting.push((entry, chunk, timeout));
+            }
+            (Ok(()), _) | (Err(TrySendError::Disconnected(_)), _) => {}
+        }
+    }
+
+    // Only wait once every other subscriber got the chunk
+    let start = Instant::now();
+    for (entry, mut chunk, timeout) in waiting {
+        loop {
+            match entry.sender.try_send(chunk) {
+                Err(TrySendError::Full(x)) if start.elapsed() < timeout => {
+                    chunk = x;
+                    std::thread::sleep(WAIT_POLL);
+                }
+                Err(TrySendError::Full(x)) => {
+                    let subscriber = &entry.subscriber;
+                    subscriber.dropped.fetch_add(x.events.len() as u64,
+                        Ordering::Relaxed);
+                    detach(subscriber, &entry.config.name,
+                        LostReason::Timeout);
+                    break;
+                }
+                Ok(()) | Err(TrySendError::Disconnected(_)) => break,
+            }
+        }
+    }
+}
+
+/// Publishes the events of a thread to every [`Subscription`]
+pub struct BusSink {
+    /// Process ID of the thread
+    pid: i32,
+
+    /// Thread ID of the thread
+    tid: i32,
+}
+
+impl Sink for BusSink {
+    fn open(ci: &ClientInfo) -> std::io::Result<Self> {
+        Ok(Self { pid: ci.pid, tid: ci.tid })
+    }
+
+    fn write(&mut self, events: &[Event]) -> std::io::Result<()> {
+        publish(self.pid, self.tid, events);
+        Ok(())
+    }
+}
+
+/// Iterator over the events of a subscription, see the module documentation
+pub struct Subscription {
+    /// Publishing side of the subscription
+    subscriber: Arc<Subscriber>,
+
+    /// Receiving side of the channel
+    receiver: Receiver<Chunk>,
+
+    /// Rest of the chunk being iterated
+    chunk: std::vec::IntoIter<Event>,
+
+    /// Process ID of the thread of the chunk
+    pid: i32,
+
+    /// Thread ID of the thread of the chunk
+    tid: i32,
+}
+
+impl Subscription {
+    /// Subscribe to the events of `kinds` instead, from the next chunk
+    /// published on
+    pub fn set_topics(&self, kinds: &[Kind]) {
+        let mask = kinds.iter().fold(0, |mask, x| mask | x.bit());
+        self.subscriber.kinds.store(mask, Ordering::Relaxed);
+    }
+
+    /// Filter the events with `filter` instead, from the next chunk
+    /// published on
+    pub fn set_filter(&self,
+            filter: impl Fn(&Event) -> bool + Send + Sync + 'static) {
+        *self.subscriber.filter.write().unwrap() = Box::new(filter);
+    }
+
+    /// Get the number of matching events which were dropped because the
+    /// subscription didn't keep up
+    pub fn dropped(&self) -> u64 {
+        self.subscriber.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Get why the subscription was detached from the bus, `None` while
+    /// it's attached. Iterating a lost subscription ends once it received
+    /// what was queued for it
+    pub fn lost(&self) -> Option<LostReason> {
+        *self.subscriber.lost.lock().unwrap()
+    }
+
+    /// Get the process ID of the thread the last event came from
+    pub fn pid(&self) -> i32 {
+        self.pid
+    }
+
+    /// Get the thread ID of the thread the last event came from
+    pub fn tid(&self) -> i32 {
+        self.tid
+    }
+}
+
+impl Iterator for Subscription {
+    type Item = Event;
+
+    fn next(&mut self) -> Option<Event> {
+        loop {
+            if let Some(event) = self.chunk.next() {
+                return Some(event);
+            }
+
+            // Subscriptions are only disconnected once they're lost
+            let chunk = self.receiver.recv().ok()?;
+            self.pid   = chunk.pid;
+            self.tid   = chunk.tid;
+            self.chunk = chunk.events.into_iter();
+        }
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            let name = SUBSCRIBERS.read().unwrap().iter()
+                .find(|x| Arc::ptr_eq(&x.subscriber, &self.subscriber))
+                .map(|x| x.config.name.clone());
+            if let Some(name) = name {
+                detach(&self.subscriber, &name, LostReason::Panicked);
+            }
+        }
+        SUBSCRIBERS.write().unwrap()
+            .retain(|x| !Arc::ptr_eq(&x.subscriber, &self.subscriber));
+    }
+}
+
+#[test]
+fn subscriptions() {
+    let write = |addr| Event::Write { pc: 0x400000, addr, val: 0, sz: 1 };
+    let mut writes = subscribe(Kind::Write, |x| {
+        matches!(x, Event::Write { addr: 0x1000.., .. })
+    });
+    let mmaps = subscribe(Kind::Mmap, |_| true);
+
+    // Only the matching events reach each subscription
+    publish(1, 2, &[write(0x10), Event::Exec { pc: 0x400000 },
+        write(0x1000)]);
+    assert_eq!(writes.next(), Some(write(0x1000)));
+    assert_eq!((writes.pid(), writes.tid()), (1, 2));
+    assert!(mmaps.receiver.try_recv().is_err());
+
+    // Topics and filters change while attached
+    writes.set_topics(&[Kind::Write, Kind::Exec]);
+    writes.set_filter(|_| true);
+    publish(1, 3, &[write(0x10), Event::Exec { pc: 0x400000 }]);
+    assert_eq!(writes.next(), Some(write(0x10)));
+    assert_eq!(writes.next(), Some(Event::Exec { pc: 0x400000 }));
+    assert_eq!(writes.tid(), 3);
+
+    // Full subscriptions lose events rather than stalling the target, and
+    // dropped ones are gone
+    for _ in 0..CHUNKS + 1 {
+        publish(1, 2, &[write(0x2000)]);
+    }
+    assert_eq!(writes.dropped(), 1);
+    drop(writes);
+    assert_eq!(SUBSCRIBERS.read().unwrap().len(), 1);
+    drop(mmaps);
+    assert!(SUBSCRIBERS.read().unwrap().is_empty());
+
+    // A consumer which stops reading is detached without holding up the
+    // others, and its subscription ends with what it was sent
+    let lost = Arc::new(Mutex::new(Vec::new()));
+    let log = lost.clone();
+    on_consumer_lost(move |x| log.lock().unwrap().push(x.clone()));
+    let config = |name: &str, overflow| ConsumerConfig {
+        name: name.to_string(), capacity: 1, overflow,
+    };
+    let mut stuck = subscribe_with(Kind::Write, |_| true,
+        config("stuck", Overflow::Detach));
+    let mut slow = subscribe_with(Kind::Write, |_| true,
+        config("slow", Overflow::Wait(Duration::from_millis(10))));
+    let mut fine = subscribe(Kind::Write, |_| true);
+    publish(1, 2, &[write(0x10)]);
+    publish(1, 2, &[write(0x20)]);
+    assert_eq!(*lost.lock().unwrap(), [
+        ConsumerLost { name: "stuck".into(), reason: LostReason::Full,
+            dropped: 1 },
+        ConsumerLost { name: "slow".into(), reason: LostReason::Timeout,
+            dropped: 1 },
+    ]);
+    assert_eq!((fine.next(), fine.next()), (Some(write(0x10)),
+        Some(write(0x20))));
+    assert_eq!(stuck.lost(), Some(LostReason::Full));
+    assert_eq!((stuck.next(), stuck.next()), (Some(write(0x10)), None));
+    assert_eq!((slow.next(), slow.next()), (Some(write(0x10)), None));
+    assert_eq!(fine.lost(), None);
+
+    // Consumers which panic are lost too
+    let panicked = subscribe_with(Kind::Write, |_| true,
+        config("panicked", Overflow::Drop));
+    let _ = std::thread::spawn(move || {
+        let _panicked = panicked;
+        panic!("consumer crashed");
+    }).join();
+    assert_eq!(lost.lock().unwrap()[2].reason, LostReason::Panicked);
+    drop(fine);
+    assert!(SUBSCRIBERS.read().unwrap().is_empty());
+}
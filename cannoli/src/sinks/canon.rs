@@ -0,0 +1,86 @@
+//! Sink writing the trace in canonical form, see [`crate::canon`]
+//!
+//! Every thread is written to `<dir>/<process>.<thread>.txt`, where processes
+//! and threads are numbered in the order they connected. Canonicalize two
+//! runs into different directories and `diff -r` them to see where they
+//! diverge.
+
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock, LazyLock};
+use crate::ClientInfo;
+use crate::canon::{Canonicalizer, ProcessLabels};
+use crate::event::Event;
+use crate::sinks::Sink;
+
+/// Directory traces are written to, set with [`configure`]
+static DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Number of a process and the labels shared by its threads
+type Process = (usize, Arc<Mutex<ProcessLabels>>);
+
+/// Every process seen, keyed by PID
+static PROCESSES: LazyLock<Mutex<HashMap<i32, Process>>> =
+    LazyLock::new(Default::default);
+
+/// Set the directory [`CanonicalSink`] writes to, the current directory if
+/// this isn't called. This must be called before [`crate::create_cannoli`]
+/// to have any effect, and can only be called once
+pub fn configure(dir: impl Into<PathBuf>) -> Result<(), PathBuf> {
+    DIR.set(dir.into())
+}
+
+/// Writes the canonical form of a thread's trace to a file, see the module
+/// documentation
+pub struct CanonicalSink {
+    /// Renders the events
+    canon: Canonicalizer,
+
+    /// File the rendered events are written to
+    file: BufWriter<std::fs::File>,
+
+    /// Scratch buffer for rendering
+    text: String,
+}
+
+impl Sink for CanonicalSink {
+    fn open(ci: &ClientInfo) -> std::io::Result<Self> {
+        let (process, labels) = {
+            let mut processes = PROCESSES.lock().unwrap();
+            if !processes.contains_key(&ci.pid) {
+                // Forked processes start out with the mappings of their
+                // parent
+                let labels = processes.get(&ci.ppid)
+                    .map(|(_, x)| x.lock().unwrap().fork())
+                    .unwrap_or_default();
+                let process = (processes.len(), Arc::new(Mutex::new(labels)));
+                processes.insert(ci.pid, process);
+            }
+            processes[&ci.pid].clone()
+        };
+
+        let canon = Canonicalizer::new(labels);
+        let dir   = DIR.get().cloned().unwrap_or_default();
+        std::fs::create_dir_all(&dir)?;
+        let path  = dir.join(format!("{process}.{}.txt", canon.thread()));
+
+        Ok(Self {
+            file: BufWriter::new(std::fs::File::create(path)?),
+            text: String::new(),
+            canon,
+        })
+    }
+
+    fn write(&mut self, events: &[Event]) -> std::io::Result<()> {
+        self.text.clear();
+        for event in events {
+            self.canon.render(event, &mut self.text);
+        }
+        self.file.write_all(self.text.as_bytes())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
@@ -0,0 +1,72 @@
+//! Sink writing every thread's events to one capture file, see
+//! [`crate::capture`]
+
+use std::sync::{Arc, LazyLock, Mutex, OnceLock};
+use std::collections::HashMap;
+use crate::ClientInfo;
+use crate::capture::CaptureWriter;
+use crate::event::Event;
+use crate::sinks::Sink;
+
+/// Capture the events are written to, set with [`configure`]
+static WRITER: OnceLock<Arc<Mutex<CaptureWriter>>> = OnceLock::new();
+
+/// Captures of the tenants of a daemon, keyed by tenant name, see
+/// [`crate::tenants`]
+static TENANT_WRITERS: LazyLock<Mutex<HashMap<String,
+        Arc<Mutex<CaptureWriter>>>>> = LazyLock::new(Default::default);
+
+/// Set the capture [`CaptureSink`] writes to, eg. a
+/// [`CaptureWriter::append`] to resume an existing capture. This must be
+/// called before [`crate::create_cannoli`], and can only be called once.
+/// Threads of tenants (see [`crate::tenants`]) write to a `capture.cap` in
+/// the output directory of their tenant instead, which is appended to
+pub fn configure(writer: CaptureWriter) -> Result<(), CaptureWriter> {
+    WRITER.set(Arc::new(Mutex::new(writer)))
+        .map_err(|x| Arc::into_inner(x).unwrap().into_inner().unwrap())
+}
+
+/// Writes the events of a thread to the configured capture
+pub struct CaptureSink {
+    /// The capture
+    writer: Arc<Mutex<CaptureWriter>>,
+
+    /// Process ID of the thread
+    pid: i32,
+
+    /// Thread ID of the thread
+    tid: i32,
+}
+
+impl Sink for CaptureSink {
+    fn open(ci: &ClientInfo) -> std::io::Result<Self> {
+        let writer = match &ci.tenant {
+            Some(tenant) => {
+                let mut writers = TENANT_WRITERS.lock().unwrap();
+                match writers.get(&tenant.name) {
+                    Some(writer) => writer.clone(),
+                    None => {
+                        std::fs::create_dir_all(&tenant.output)?;
+                        let writer = Arc::new(Mutex::new(CaptureWriter::append(
+                            tenant.output.join("capture.cap"), None)?));
+                        writers.insert(tenant.name.clone(), writer.clone());
+                        writer
+                    }
+                }
+            }
+            None => WRITER.get().cloned().ok_or_else(|| {
+                std::io::Error::new(std::io::ErrorKind::NotFound,
+                    "No capture was configured")
+            })?,
+        };
+        Ok(Self { writer, pid: ci.pid, tid: ci.tid })
+    }
+
+    fn write(&mut self, events: &[Event]) -> std::io::Result<()> {
+        self.writer.lock().unwrap().write_events(self.pid, self.tid, events)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.lock().unwrap().flush()
+    }
+}
@@ -0,0 +1,139 @@
+//! Sink handing the events of every thread to an [`Iterator`]
+//!
+//! Generic tooling (exporters, filters, converters) which only looks at
+//! events doesn't need to implement [`crate::Cannoli`] at all. [`subscribe`]
+//! returns an [`Events`] iterator, which is fed by `Recorder<ChannelSink>`
+//! running in [`crate::create_cannoli`] on another thread:
+//!
+//! ```no_run
+//! use cannoli::sinks::Recorder;
+//! use cannoli::sinks::channel::{self, ChannelSink};
+//!
+//! let events = channel::subscribe().unwrap();
+//! std::thread::spawn(|| cannoli::create_cannoli::<Recorder<ChannelSink>>(4));
+//! for event in events.filter(|x| matches!(x, cannoli::Event::Mmap { .. })) {
+//!     println!("{event:?}");
+//! }
+//! ```
+//!
+//! The events of a thread are in trace order, the events of different
+//! threads are interleaved chunk by chunk, [`Events::pid`] and
+//! [`Events::tid`] tell which thread the last event came from. The channel is
+//! bounded, so a consumer which doesn't keep up stalls the target like any
+//! other slow analysis. Dropping the iterator closes the sink of every
+//! thread.
+
+use std::sync::OnceLock;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use crate::ClientInfo;
+use crate::event::Event;
+use crate::sinks::Sink;
+
+/// Number of chunks which can be waiting for the consumer
+const CHUNKS: usize = 64;
+
+/// Sending side of the channel, set with [`subscribe`]
+static SENDER: OnceLock<SyncSender<Chunk>> = OnceLock::new();
+
+/// Events of one thread, in trace order
+struct Chunk {
+    /// Process ID of the thread
+    pid: i32,
+
+    /// Thread ID of the thread
+    tid: i32,
+
+    /// The events
+    events: Vec<Event>,
+}
+
+/// Create the iterator [`ChannelSink`] sends to. This must be called before
+/// [`crate::create_cannoli`], and returns `None` if it was called before
+pub fn subscribe() -> Option<Events> {
+    let (sender, receiver) = sync_channel(CHUNKS);
+    SENDER.set(sender).ok()?;
+    Some(Events {
+        receiver,
+        chunk: Vec::new().into_iter(),
+        pid:   0,
+        tid:   0,
+    })
+}
+
+/// Sends the events of a thread to the [`Events`] iterator
+pub struct ChannelSink {
+    /// The channel
+    sender: SyncSender<Chunk>,
+
+    /// Process ID of the thread
+    pid: i32,
+
+    /// Thread ID of the thread
+    tid: i32,
+}
+
+impl Sink for ChannelSink {
+    fn open(ci: &ClientInfo) -> std::io::Result<Self> {
+        let sender = SENDER.get().ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::NotFound,
+                "Nothing subscribed to the events")
+        })?;
+        Ok(Self { sender: sender.clone(), pid: ci.pid, tid: ci.tid })
+    }
+
+    fn write(&mut self, events: &[Event]) -> std::io::Result<()> {
+        let chunk = Chunk {
+            pid:    self.pid,
+            tid:    self.tid,
+            events: events.to_vec(),
+        };
+        self.sender.send(chunk).map_err(|_| {
+            std::io::Error::new(std::io::ErrorKind::BrokenPipe,
+                "The event iterator was dropped")
+        })
+    }
+}
+
+/// Iterator over the events of every thread, see the module documentation
+pub struct Events {
+    /// Receiving side of the channel
+    receiver: Receiver<Chunk>,
+
+    /// Rest of the chunk being iterated
+    chunk: std::vec::IntoIter<Event>,
+
+    /// Process ID of the thread of the chunk
+    pid: i32,
+
+    /// Thread ID of the thread of the chunk
+    tid: i32,
+}
+
+impl Events {
+    /// Get the process ID of the thread the last event came from
+    pub fn pid(&self) -> i32 {
+        self.pid
+    }
+
+    /// Get the thread ID of the thread the last event came from
+    pub fn tid(&self) -> i32 {
+        self.tid
+    }
+}
+
+impl Iterator for Events {
+    type Item = Event;
+
+    fn next(&mut self) -> Option<Event> {
+        loop {
+            if let Some(event) = self.chunk.next() {
+                return Some(event);
+            }
+
+            let chunk = self.receiver.recv().ok()?;
+            self.pid   = chunk.pid;
+            self.tid   = chunk.tid;
+            self.chunk = chunk.events.into_iter();
+        }
+    }
+}
@@ -0,0 +1,194 @@
+//! Sink writing events as JSON lines, to load traces into eg. pandas
+//!
+//! Every event of the configured kinds is written as one line, in the serde
+//! form of [`Event`] with the PID, TID, vCPU (see [`crate::cpus`]) and
+//! process and thread names (see [`crate::names`]) of its thread added. The
+//! vCPU is left out when QEMU doesn't tell it, eg. when replaying a trace:
+//!
+//! ```text
+//! {"cpu":0,"event":"exec","pc":4198921,"pid":812,"process":"app",
+//!  "thread":"worker","tid":812}
+//! ```
+//!
+//! which is a data frame away from Python:
+//!
+//! ```python
+//! trace = pandas.read_json("trace.jsonl", lines=True)
+//! ```
+//!
+//! Only executions, reads, writes and mappings are written by default, and
+//! every field of them. Both can be changed with [`JsonlConfig`], eg. to
+//! drop the registers of `regs` events, or to only keep `pc` and `addr`.
+//! Every thread writes to the same file, a chunk of events at a time.
+
+use std::io::{BufWriter, Write};
+use std::fs::File;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, OnceLock};
+use crate::ClientInfo;
+use crate::event::Event;
+use crate::grep::Kind;
+use crate::names::GuestNames;
+use crate::sinks::Sink;
+
+/// Configuration for [`JsonlSink`]
+#[derive(Clone, Debug)]
+pub struct JsonlConfig {
+    /// File the events are written to, truncated when the first thread
+    /// connects
+    pub path: PathBuf,
+
+    /// Kinds of events written
+    pub kinds: Vec<Kind>,
+
+    /// Fields written, eg. `pid`, `event`, `pc` or `addr`. Every field if
+    /// empty. Events are written even if they have none of them
+    pub fields: Vec<String>,
+}
+
+impl Default for JsonlConfig {
+    fn default() -> Self {
+        Self {
+            path:   "trace.jsonl".into(),
+            kinds:  vec![Kind::Exec, Kind::Read, Kind::Write, Kind::Mmap],
+            fields: Vec::new(),
+        }
+    }
+}
+
+/// Configuration used by [`JsonlSink`], set with [`configure`]
+static CONFIG: OnceLock<JsonlConfig> = OnceLock::new();
+
+/// File the events are written to, created when the first thread connects
+static WRITER: Mutex<Option<Arc<Mutex<BufWriter<File>>>>> = Mutex::new(None);
+
+/// Set the configuration used by [`JsonlSink`]. This must be called before
+/// [`crate::create_cannoli`] to have any effect, and can only be called once
+pub fn configure(config: JsonlConfig) -> Result<(), JsonlConfig> {
+    CONFIG.set(config)
+}
+
+/// Fields added to every event of a thread
+type Labels = serde_json::Map<String, serde_json::Value>;
+
+/// Get the fields added to the events of the thread `tid` of the process
+/// `pid` on the vCPU `cpu`, if it's known, named `names`
+fn labels(pid: i32, tid: i32, cpu: Option<u32>, names: &GuestNames)
+        -> Labels {
+    let mut ret = Labels::new();
+    ret.insert("pid".into(), pid.into());
+    ret.insert("tid".into(), tid.into());
+    if let Some(cpu) = cpu {
+        ret.insert("cpu".into(), cpu.into());
+    }
+    ret.insert("process".into(), names.process.as_str().into());
+    ret.insert("thread".into(),  names.thread.as_str().into());
+    ret
+}
+
+/// Append the line of `event`, with the fields of its thread `labels`, to
+/// `out`, with only the fields of `fields` unless it's empty
+fn render(labels: &Labels, event: &Event, fields: &[String],
+        out: &mut Vec<u8>) -> std::io::Result<()> {
+    let serde_json::Value::Object(mut object) = serde_json::to_value(event)?
+        else { unreachable!("events serialize to objects") };
+    object.extend(labels.clone());
+    if !fields.is_empty() {
+        object.retain(|x, _| fields.contains(x));
+    }
+    serde_json::to_writer(&mut *out, &object)?;
+    out.push(b'\n');
+    Ok(())
+}
+
+/// Writes the events of a thread as JSON lines, see the module documentation
+pub struct JsonlSink {
+    /// The file
+    writer: Arc<Mutex<BufWriter<File>>>,
+
+    /// Configuration
+    config: &'static JsonlConfig,
+
+    /// Mask of the kinds of events written, see [`Kind::bit`]
+    kinds: u8,
+
+    /// Process ID of the thread
+    pid: i32,
+
+    /// Thread ID of the thread
+    tid: i32,
+
+    /// vCPU index of the thread, `None` if it isn't known
+    cpu: Option<u32>,
+
+    /// Names of the thread and its process
+    names: GuestNames,
+
+    /// Fields added to every event of the thread, see [`labels`]
+    labels: Labels,
+
+    /// Scratch buffer for the lines of a chunk
+    lines: Vec<u8>,
+}
+
+impl Sink for JsonlSink {
+    fn open(ci: &ClientInfo) -> std::io::Result<Self> {
+        let config = CONFIG.get_or_init(JsonlConfig::default);
+        let writer = {
+            let mut writer = WRITER.lock().unwrap();
+            if writer.is_none() {
+                *writer = Some(Arc::new(Mutex::new(BufWriter::new(
+                    File::create(&config.path)?))));
+            }
+            writer.clone().unwrap()
+        };
+        let names = GuestNames::new(ci);
+        Ok(Self {
+            kinds:  config.kinds.iter().fold(0, |mask, x| mask | x.bit()),
+            pid:    ci.pid,
+            tid:    ci.tid,
+            cpu:    ci.cpu,
+            labels: labels(ci.pid, ci.tid, ci.cpu, &names),
+            lines:  Vec::new(),
+            writer, config, names,
+        })
+    }
+
+    fn write(&mut self, events: &[Event]) -> std::io::Result<()> {
+        // The guest may have renamed the thread
+        if self.names.refresh() {
+            self.labels = labels(self.pid, self.tid, self.cpu, &self.names);
+        }
+
+        self.lines.clear();
+        for event in events {
+            if Kind::mask(event) & self.kinds != 0 {
+                render(&self.labels, event, &self.config.fields,
+                    &mut self.lines)?;
+            }
+        }
+        self.writer.lock().unwrap().write_all(&self.lines)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.lock().unwrap().flush()
+    }
+}
+
+#[test]
+fn render_lines() {
+    let mut out = Vec::new();
+    let write = Event::Write { pc: 0x1000, addr: 0x2000, val: 7, sz: 4 };
+    let mut labels = Labels::new();
+    labels.insert("pid".into(), 3.into());
+    labels.insert("tid".into(), 4.into());
+    labels.insert("cpu".into(), 1.into());
+    labels.insert("thread".into(), "worker".into());
+    render(&labels, &write, &[], &mut out).unwrap();
+    render(&labels, &Event::Exec { pc: 0x1004 }, &["pc".into(),
+        "addr".into()], &mut out).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(),
+        "{\"addr\":8192,\"cpu\":1,\"event\":\"write\",\"pc\":4096,\"pid\":3,\
+         \"sz\":4,\"thread\":\"worker\",\"tid\":4,\"val\":7}\n\
+         {\"pc\":4100}\n");
+}
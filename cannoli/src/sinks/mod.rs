@@ -0,0 +1,232 @@
+//! Sinks which ship the trace somewhere else rather than analyzing it
+//!
+//! A [`Sink`] receives the trace of one target thread as [`Event`]s, in
+//! order. Wrapping a sink in a [`Recorder`] gives a [`Cannoli`]
+//! implementation which can be passed straight to
+//! [`crate::create_cannoli`], so collecting a trace doesn't need any
+//! analysis code at all. With the [`channel`] sink, the events of a live run
+//! can be consumed as a plain [`Iterator`] instead, and with the [`bus`]
+//! sink by any number of subscribers coming and going during the run.
+
+use std::sync::Arc;
+use crate::{Cannoli, ClientInfo};
+use crate::event::Event;
+use crate::analysis::rep::RepCollapser;
+use crate::heap::HeapEvent;
+
+pub mod nats;
+pub mod canon;
+pub mod capture;
+pub mod channel;
+pub mod bus;
+pub mod jsonl;
+pub mod tenet;
+
+pub use jsonl::JsonlSink;
+
+/// A destination for the events of a single target thread
+pub trait Sink: Send + Sync + Sized + 'static {
+    /// Open the sink for a newly connected target thread
+    fn open(ci: &ClientInfo) -> std::io::Result<Self>;
+
+    /// Write the next chunk of events, these are always in trace order
+    fn write(&mut self, events: &[Event]) -> std::io::Result<()>;
+
+    /// Flush any buffered events, invoked when the target thread exits and
+    /// at every epoch (see [`crate::control::epoch`])
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A [`Sink`] which summarizes block-transfer instructions (see
+/// [`crate::analysis::rep`]) before passing the events on to `S`. Use
+/// `Recorder<Summarized<S>>` instead of `Recorder<S>` to trade the
+/// per-iteration memory events of eg. `rep movs` for a single
+/// [`Event::Rep`]. QEMU still sends every iteration, only what `S` receives
+/// is summarized
+pub struct Summarized<S: Sink> {
+    /// Sink receiving the summarized events
+    sink: S,
+
+    /// Summarizes the events
+    collapser: RepCollapser,
+
+    /// Scratch buffer for the summarized events
+    out: Vec<Event>,
+}
+
+impl<S: Sink> Sink for Summarized<S> {
+    fn open(ci: &ClientInfo) -> std::io::Result<Self> {
+        Ok(Self {
+            sink:      S::open(ci)?,
+            collapser: RepCollapser::new(),
+            out:       Vec::new(),
+        })
+    }
+
+    fn write(&mut self, events: &[Event]) -> std::io::Result<()> {
+        self.out.clear();
+        self.collapser.extend(events.iter().cloned(), &mut self.out);
+        if self.out.is_empty() {
+            return Ok(());
+        }
+        self.sink.write(&self.out)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.out.clear();
+        self.collapser.finish(&mut self.out);
+        if !self.out.is_empty() {
+            self.sink.write(&self.out)?;
+        }
+        self.sink.flush()
+    }
+}
+
+/// A [`Cannoli`] implementation which forwards every event to a [`Sink`]
+///
+/// Errors from the sink are reported and the sink is closed, the trace keeps
+/// getting processed so the target isn't stalled.
+pub struct Recorder<S: Sink> {
+    /// Sink for this thread, `None` once it failed
+    sink: Option<S>,
+
+    /// Thread ID, used when reporting errors
+    tid: i32,
+}
+
+impl<S: Sink> Recorder<S> {
+    /// Report a sink failure and close the sink
+    fn fail(&mut self, err: std::io::Error) {
+        eprintln!("[tid {}] sink failed, dropping events: {err}", self.tid);
+        self.sink = None;
+    }
+}
+
+impl<S: Sink> Cannoli for Recorder<S> {
+    type Trace = Event;
+
+    type PidContext = ();
+    type TidContext = ();
+
+    fn init_pid(_ci: &ClientInfo) -> Arc<Self::PidContext> {
+        Arc::new(())
+    }
+
+    fn init_tid(_pid: &Self::PidContext,
+            ci: &ClientInfo) -> (Self, Self::TidContext) {
+        let mut ret = Self { sink: None, tid: ci.tid };
+        match S::open(ci) {
+            Ok(sink) => ret.sink = Some(sink),
+            Err(err) => ret.fail(err),
+        }
+        (ret, ())
+    }
+
+    fn exec(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            pc: u64, trace: &mut Vec<Self::Trace>) {
+        trace.push(Event::Exec { pc });
+    }
+
+    fn exec_with_regs(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            pc: u64, regs: &[u8], trace: &mut Vec<Self::Trace>) {
+        trace.push(Event::Regs { pc, regs: regs.to_vec() });
+    }
+
+    fn regs(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            pc: u64, regs: &[u8], trace: &mut Vec<Self::Trace>) {
+        trace.push(Event::Regs { pc, regs: regs.to_vec() });
+    }
+
+    fn branch(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            pc: u64, branch: bool, regs: &[u8],
+            trace: &mut Vec<Self::Trace>) {
+        trace.push(Event::Branch { pc, taken: branch, regs: regs.to_vec() });
+    }
+
+    fn reg_file(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            pc: u64, index: usize, regs: &[u8],
+            trace: &mut Vec<Self::Trace>) {
+        trace.push(Event::RegFile {
+            pc, index: index as u32, regs: regs.to_vec() });
+    }
+
+    fn cmp(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            pc: u64, lhs: u64, rhs: u64, sz: u8,
+            trace: &mut Vec<Self::Trace>) {
+        trace.push(Event::Cmp { pc, lhs, rhs, sz });
+    }
+
+    fn read(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            pc: u64, addr: u64, val: u64, sz: u8, _helper: bool,
+            trace: &mut Vec<Self::Trace>) {
+        trace.push(Event::Read { pc, addr, val, sz });
+    }
+
+    fn write(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            pc: u64, addr: u64, val: u64, sz: u8, _helper: bool,
+            trace: &mut Vec<Self::Trace>) {
+        trace.push(Event::Write { pc, addr, val, sz });
+    }
+
+    fn read_addr(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            pc: u64, addr: u64, sz: u8, _helper: bool,
+            trace: &mut Vec<Self::Trace>) {
+        trace.push(Event::ReadAddr { pc, addr, sz });
+    }
+
+    fn write_addr(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            pc: u64, addr: u64, sz: u8, _helper: bool,
+            trace: &mut Vec<Self::Trace>) {
+        trace.push(Event::WriteAddr { pc, addr, sz });
+    }
+
+    fn mmap(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            base: u64, len: u64, anon: bool, read: bool, write: bool,
+            exec: bool, path: &str, offset: u64,
+            trace: &mut Vec<Self::Trace>) {
+        trace.push(Event::Mmap {
+            path: path.to_string(),
+            base, len, anon, read, write, exec, offset,
+        });
+    }
+
+    fn munmap(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            base: u64, len: u64, trace: &mut Vec<Self::Trace>) {
+        trace.push(Event::Munmap { base, len });
+    }
+
+    fn heap(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            event: &HeapEvent, trace: &mut Vec<Self::Trace>) {
+        trace.push((*event).into());
+    }
+
+    fn signal(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            sig: i32, from: u64, to: u64, trace: &mut Vec<Self::Trace>) {
+        trace.push(Event::Signal { sig, from, to });
+    }
+
+    fn trace(&mut self, _pid: &Self::PidContext,
+            _tid: &Self::TidContext, trace: &[Self::Trace]) {
+        if let Some(Err(err)) = self.sink.as_mut().map(|x| x.write(trace)) {
+            self.fail(err);
+        }
+    }
+
+    /// Flush the sink, so what it wrote is consistent with the epoch
+    fn epoch_complete(&mut self, _pid: &Self::PidContext,
+            _tid: &Self::TidContext, _id: u64) {
+        if let Some(Err(err)) = self.sink.as_mut().map(|x| x.flush()) {
+            self.fail(err);
+        }
+    }
+}
+
+impl<S: Sink> Drop for Recorder<S> {
+    fn drop(&mut self) {
+        if let Some(Err(err)) = self.sink.as_mut().map(|x| x.flush()) {
+            self.fail(err);
+        }
+    }
+}
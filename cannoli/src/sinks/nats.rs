@@ -0,0 +1,317 @@
+//! Sink publishing events to a NATS server
+//!
+//! This lets a fleet of emulation workers feed one central analysis pipeline.
+//! Events are batched, optionally compressed with zstd (with the `zstd`
+//! feature), and published to the subject `<prefix>.<run_id>.<pid>` with the
+//! following headers:
+//!
+//! ```text
+//! Cannoli-Tid:      target thread ID
+//! Cannoli-Arch:     target architecture, eg. `X86_64`
+//! Cannoli-Encoding: `raw` or `zstd`
+//! ```
+//!
+//! The payload is a sequence of events in the [`crate::event`] binary
+//! encoding. Subscribe to `<prefix>.<run_id>.>` to get every process of a
+//! run.
+//!
+//! We talk the NATS client protocol directly over TCP, it's a simple text
+//! protocol and this keeps the async runtime of the official client out of
+//! the trace processing threads. Servers must support headers (NATS 2.2+).
+//!
+//! [`NatsConnection`] can also be used on its own to publish derived
+//! summaries rather than raw events.
+
+use std::io::{Read, Write, BufRead, BufReader};
+use std::net::TcpStream;
+use std::sync::OnceLock;
+use crate::ClientInfo;
+use crate::event::Event;
+use crate::sinks::Sink;
+
+/// Configuration for [`NatsSink`]
+#[derive(Clone, Debug)]
+pub struct NatsConfig {
+    /// Address of the NATS server
+    pub server: String,
+
+    /// First token of the subjects events are published to
+    pub prefix: String,
+
+    /// Identifier for this run, used as the second token of the subjects
+    pub run_id: String,
+
+    /// Number of bytes of encoded events to accumulate before publishing a
+    /// batch. This is capped to the server's maximum payload size
+    pub batch_size: usize,
+
+    /// zstd compression level for batches, `None` to publish them raw
+    pub compression: Option<i32>,
+}
+
+impl Default for NatsConfig {
+    fn default() -> Self {
+        Self {
+            server:      "127.0.0.1:4222".into(),
+            prefix:      "cannoli".into(),
+            run_id:      std::process::id().to_string(),
+            batch_size:  256 * 1024,
+            compression: None,
+        }
+    }
+}
+
+/// Configuration used by [`NatsSink`], set with [`configure`]
+static CONFIG: OnceLock<NatsConfig> = OnceLock::new();
+
+/// Set the configuration used by [`NatsSink`]. This must be called before
+/// [`crate::create_cannoli`] to have any effect, and can only be called once
+pub fn configure(config: NatsConfig) -> Result<(), NatsConfig> {
+    CONFIG.set(config)
+}
+
+/// Replace characters which aren't allowed in a subject token
+pub fn subject_token(token: &str) -> String {
+    token.chars().map(|x| {
+        if x.is_ascii_whitespace() || matches!(x, '.' | '*' | '>') {
+            '_'
+        } else {
+            x
+        }
+    }).collect()
+}
+
+/// Create an `InvalidData` error for a protocol problem
+fn protocol_error(msg: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg)
+}
+
+/// A minimal publish-only connection to a NATS server
+pub struct NatsConnection {
+    /// Connection to the server
+    stream: TcpStream,
+
+    /// Maximum payload size the server accepts
+    max_payload: usize,
+
+    /// Partial line received from the server
+    pending: Vec<u8>,
+}
+
+impl NatsConnection {
+    /// Connect to the NATS server at `server`
+    pub fn connect(server: &str) -> std::io::Result<Self> {
+        let stream = TcpStream::connect(server)?;
+        stream.set_nodelay(true)?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+
+        // The server greets us with its `INFO`
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        if !line.starts_with("INFO ") {
+            return Err(protocol_error(format!("Unexpected greeting {line}")));
+        }
+
+        // Get the maximum payload size, we don't need a JSON parser just for
+        // this one field
+        let max_payload = line.split("\"max_payload\":").nth(1)
+            .and_then(|x| {
+                let digits = x.trim_start()
+                    .split(|c: char| !c.is_ascii_digit()).next()?;
+                digits.parse().ok()
+            })
+            .unwrap_or(1024 * 1024);
+
+        // Identify ourselves and make sure the server accepted it
+        let mut ret = Self { stream, max_payload, pending: Vec::new() };
+        ret.stream.write_all(b"CONNECT {\"verbose\":false,\"pedantic\":false,\
+            \"headers\":true,\"name\":\"cannoli\"}\r\nPING\r\n")?;
+        loop {
+            line.clear();
+            reader.read_line(&mut line)?;
+            match line.trim_end() {
+                "PONG" => break,
+                x if x.starts_with("-ERR") => {
+                    return Err(protocol_error(format!("Server error {x}")));
+                }
+                "" => {
+                    return Err(protocol_error("Connection closed".into()));
+                }
+                _ => {}
+            }
+        }
+
+        // Keep anything the server sent after the `PONG` for `service()`
+        ret.pending.extend_from_slice(reader.buffer());
+        Ok(ret)
+    }
+
+    /// Maximum payload size the server accepts
+    pub fn max_payload(&self) -> usize {
+        self.max_payload
+    }
+
+    /// Handle any messages the server sent us, answering keep-alive `PING`s
+    /// and reporting errors
+    fn service(&mut self) -> std::io::Result<()> {
+        self.stream.set_nonblocking(true)?;
+        let mut buf = [0u8; 4096];
+        let result = loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => {
+                    break Err(protocol_error("Connection closed".into()));
+                }
+                Ok(bytes) => self.pending.extend_from_slice(&buf[..bytes]),
+                Err(x) if x.kind() == std::io::ErrorKind::WouldBlock => {
+                    break Ok(());
+                }
+                Err(x) => break Err(x),
+            }
+        };
+        self.stream.set_nonblocking(false)?;
+        result?;
+
+        // Process all complete lines
+        while let Some(end) = self.pending.windows(2)
+                .position(|x| x == b"\r\n") {
+            let line = self.pending.drain(..end + 2).collect::<Vec<_>>();
+            let line = String::from_utf8_lossy(&line[..end]);
+            if line == "PING" {
+                self.stream.write_all(b"PONG\r\n")?;
+            } else if line.starts_with("-ERR") {
+                return Err(protocol_error(format!("Server error {line}")));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Publish `payload` to `subject` with `headers`
+    pub fn publish(&mut self, subject: &str, headers: &[(&str, &str)],
+            payload: &[u8]) -> std::io::Result<()> {
+        self.service()?;
+
+        let mut hdr = String::from("NATS/1.0\r\n");
+        for (key, val) in headers {
+            hdr += &format!("{key}: {val}\r\n");
+        }
+        hdr += "\r\n";
+
+        let mut msg = format!("HPUB {subject} {} {}\r\n{hdr}",
+            hdr.len(), hdr.len() + payload.len()).into_bytes();
+        msg.extend_from_slice(payload);
+        msg.extend_from_slice(b"\r\n");
+        self.stream.write_all(&msg)
+    }
+}
+
+/// Compress `batch` in place at `level`, returning the name of the encoding
+#[cfg(feature = "zstd")]
+fn compress(batch: &mut Vec<u8>, level: Option<i32>)
+        -> std::io::Result<&'static str> {
+    match level {
+        Some(level) => {
+            *batch = zstd::bulk::compress(batch, level)?;
+            Ok("zstd")
+        }
+        None => Ok("raw"),
+    }
+}
+
+/// Compression is not available without the `zstd` feature
+#[cfg(not(feature = "zstd"))]
+fn compress(_batch: &mut Vec<u8>, _level: Option<i32>)
+        -> std::io::Result<&'static str> {
+    Ok("raw")
+}
+
+/// A [`Sink`] publishing batches of events to NATS, see the module
+/// documentation for the subjects and message format
+pub struct NatsSink {
+    /// Connection to the server
+    conn: NatsConnection,
+
+    /// Subject to publish to
+    subject: String,
+
+    /// Target thread ID, as a header value
+    tid: String,
+
+    /// Target architecture, as a header value
+    arch: String,
+
+    /// Encoded events waiting to be published
+    batch: Vec<u8>,
+
+    /// Size of `batch` at which we publish it
+    batch_size: usize,
+
+    /// zstd compression level
+    compression: Option<i32>,
+}
+
+impl NatsSink {
+    /// Publish the current batch
+    fn publish(&mut self) -> std::io::Result<()> {
+        if self.batch.is_empty() {
+            return Ok(());
+        }
+
+        let encoding = compress(&mut self.batch, self.compression)?;
+        self.conn.publish(&self.subject, &[
+            ("Cannoli-Tid",      &self.tid),
+            ("Cannoli-Arch",     &self.arch),
+            ("Cannoli-Encoding", encoding),
+        ], &self.batch)?;
+
+        self.batch.clear();
+        Ok(())
+    }
+}
+
+impl Sink for NatsSink {
+    fn open(ci: &ClientInfo) -> std::io::Result<Self> {
+        let config = CONFIG.get_or_init(NatsConfig::default);
+
+        if cfg!(not(feature = "zstd")) && config.compression.is_some() {
+            eprintln!("cannoli was built without the `zstd` feature, \
+                publishing events uncompressed");
+        }
+
+        let conn = NatsConnection::connect(&config.server)?;
+
+        // Leave some room for the event which crosses the batch size
+        let batch_size = config.batch_size.min(conn.max_payload() / 2);
+
+        Ok(Self {
+            subject: format!("{}.{}.{}", subject_token(&config.prefix),
+                subject_token(&config.run_id), ci.pid),
+            tid:         ci.tid.to_string(),
+            arch:        format!("{:?}", ci.arch),
+            batch:       Vec::with_capacity(batch_size + 1024),
+            compression: config.compression,
+            conn, batch_size,
+        })
+    }
+
+    fn write(&mut self, events: &[Event]) -> std::io::Result<()> {
+        for event in events {
+            event.encode(&mut self.batch);
+            if self.batch.len() >= self.batch_size {
+                self.publish()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.publish()?;
+        self.conn.stream.flush()
+    }
+}
+
+#[test]
+fn sanitize_subject() {
+    assert_eq!(subject_token("run 1.a*>"), "run_1_a__");
+}
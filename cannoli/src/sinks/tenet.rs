@@ -0,0 +1,273 @@
+//! Sink writing traces for Tenet, the trace explorer for IDA
+//!
+//! [Tenet](https://github.com/gaasedelen/tenet) replays a trace of one
+//! thread forwards and backwards over the disassembly, from a text file with
+//! one line per instruction: the registers which changed since the previous
+//! line, and the memory the instruction read and wrote with its bytes:
+//!
+//! ```text
+//! rax=0x3c,rdi=0x0,rip=0x401000
+//! rsp=0x7ffffffde0f8,rip=0x401005,mw=0x7ffffffde0f8:0a10400000000000
+//! ```
+//!
+//! Every thread gets a file of its own in the configured directory, named
+//! `trace.<pid>.<tid>.log`. The registers come from register events, so the
+//! jitter has to send them with every instruction, with
+//! [`crate::control::Filters::exec_regs`], and the memory accesses need
+//! their values. Instructions traced without registers only move the PC,
+//! and accesses without values (eg. summarized by
+//! [`super::Summarized`]) are left out.
+//!
+//! Tenet supports x86, x86-64, ARM and AArch64 targets, other targets fail
+//! to open.
+
+use std::io::{BufWriter, Write};
+use std::fs::File;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use crate::{Architecture, ClientInfo};
+use crate::event::Event;
+use crate::sinks::Sink;
+
+/// Configuration for [`TenetSink`]
+#[derive(Clone, Debug)]
+pub struct TenetConfig {
+    /// Directory the traces are written to, which must exist
+    pub dir: PathBuf,
+}
+
+impl Default for TenetConfig {
+    fn default() -> Self {
+        Self { dir: ".".into() }
+    }
+}
+
+/// Configuration used by [`TenetSink`], set with [`configure`]
+static CONFIG: OnceLock<TenetConfig> = OnceLock::new();
+
+/// Set the configuration used by [`TenetSink`]. This must be called before
+/// [`crate::create_cannoli`] to have any effect, and can only be called once
+pub fn configure(config: TenetConfig) -> Result<(), TenetConfig> {
+    CONFIG.set(config)
+}
+
+/// General purpose registers of an architecture, as Tenet names them
+struct Layout {
+    /// Names of the registers, in QEMU's order, `None` for registers Tenet
+    /// doesn't know
+    names: &'static [Option<&'static str>],
+
+    /// Size of a register in bytes
+    width: usize,
+
+    /// Name of the PC
+    pc: &'static str,
+}
+
+impl Layout {
+    /// Get the layout of `arch`, `None` if Tenet doesn't support it
+    fn new(arch: Architecture) -> Option<Self> {
+        Some(match arch {
+            Architecture::X86_64 => Self {
+                names: &[Some("rax"), Some("rcx"), Some("rdx"), Some("rbx"),
+                    Some("rsp"), Some("rbp"), Some("rsi"), Some("rdi"),
+                    Some("r8"), Some("r9"), Some("r10"), Some("r11"),
+                    Some("r12"), Some("r13"), Some("r14"), Some("r15")],
+                width: 8,
+                pc:    "rip",
+            },
+            Architecture::I386 | Architecture::I686 => Self {
+                names: &[Some("eax"), Some("ecx"), Some("edx"), Some("ebx"),
+                    Some("esp"), Some("ebp"), Some("esi"), Some("edi")],
+                width: 4,
+                pc:    "eip",
+            },
+            Architecture::Aarch64 | Architecture::Aarch64be => Self {
+                names: &[Some("x0"), Some("x1"), Some("x2"), Some("x3"),
+                    Some("x4"), Some("x5"), Some("x6"), Some("x7"),
+                    Some("x8"), Some("x9"), Some("x10"), Some("x11"),
+                    Some("x12"), Some("x13"), Some("x14"), Some("x15"),
+                    Some("x16"), Some("x17"), Some("x18"), Some("x19"),
+                    Some("x20"), Some("x21"), Some("x22"), Some("x23"),
+                    Some("x24"), Some("x25"), Some("x26"), Some("x27"),
+                    Some("x28"), Some("x29"), Some("x30"), Some("sp")],
+                width: 8,
+                pc:    "pc",
+            },
+
+            // r15 is the PC, which comes with the event instead
+            Architecture::Armv5tel | Architecture::Armv5teb => Self {
+                names: &[Some("r0"), Some("r1"), Some("r2"), Some("r3"),
+                    Some("r4"), Some("r5"), Some("r6"), Some("r7"),
+                    Some("r8"), Some("r9"), Some("r10"), Some("r11"),
+                    Some("r12"), Some("sp"), Some("lr"), None],
+                width: 4,
+                pc:    "pc",
+            },
+            _ => return None,
+        })
+    }
+}
+
+/// Assembles the lines of a Tenet trace from the events of a thread
+struct TenetWriter {
+    /// Registers of the target
+    layout: Layout,
+
+    /// Whether the target is big endian
+    big_endian: bool,
+
+    /// Last value of every register, `None` before it was first seen
+    regs: Vec<Option<u64>>,
+
+    /// Line of the current instruction, empty before the first one
+    line: String,
+}
+
+impl TenetWriter {
+    /// Create a writer for a thread of a target with `layout`
+    fn new(layout: Layout, big_endian: bool) -> Self {
+        Self {
+            regs: vec![None; layout.names.len()],
+            line: String::new(),
+            layout, big_endian,
+        }
+    }
+
+    /// Add `event`, appending the lines it completes to `out`
+    fn event(&mut self, event: &Event, out: &mut Vec<u8>) {
+        use std::fmt::Write;
+
+        match event {
+            Event::Exec { pc } => {
+                self.end(out);
+                let _ = write!(self.line, "{}={pc:#x}", self.layout.pc);
+            }
+            Event::Regs { pc, regs } => {
+                self.end(out);
+                let width = self.layout.width;
+                for (index, bytes) in regs.chunks_exact(width).enumerate()
+                        .take(self.regs.len()) {
+                    // QEMU keeps the registers in host byte order
+                    let mut value = [0u8; 8];
+                    value[..width].copy_from_slice(bytes);
+                    let value = u64::from_le_bytes(value);
+                    let Some(name) = self.layout.names[index] else {
+                        continue;
+                    };
+                    if self.regs[index] != Some(value) {
+                        self.regs[index] = Some(value);
+                        let _ = write!(self.line, "{name}={value:#x},");
+                    }
+                }
+                let _ = write!(self.line, "{}={pc:#x}", self.layout.pc);
+            }
+            Event::Read { addr, val, sz, .. } |
+                    Event::Write { addr, val, sz, .. } => {
+                if self.line.is_empty() {
+                    return;
+                }
+
+                // Bytes as they are in memory
+                let sz = (*sz as usize).min(8);
+                let bytes = if self.big_endian {
+                    val.to_be_bytes()[8 - sz..].to_vec()
+                } else {
+                    val.to_le_bytes()[..sz].to_vec()
+                };
+                let kind = if matches!(event, Event::Read { .. }) {
+                    "mr"
+                } else {
+                    "mw"
+                };
+                let _ = write!(self.line, ",{kind}={addr:#x}:");
+                for byte in bytes {
+                    let _ = write!(self.line, "{byte:02x}");
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// End the line of the current instruction, appending it to `out`
+    fn end(&mut self, out: &mut Vec<u8>) {
+        if !self.line.is_empty() {
+            out.extend_from_slice(self.line.as_bytes());
+            out.push(b'\n');
+            self.line.clear();
+        }
+    }
+}
+
+/// Writes the trace of a thread for Tenet, see the module documentation
+pub struct TenetSink {
+    /// The trace file
+    file: BufWriter<File>,
+
+    /// Assembles the lines
+    writer: TenetWriter,
+
+    /// Scratch buffer for the lines of a chunk
+    lines: Vec<u8>,
+}
+
+impl Sink for TenetSink {
+    fn open(ci: &ClientInfo) -> std::io::Result<Self> {
+        let config = CONFIG.get_or_init(TenetConfig::default);
+        let layout = Layout::new(ci.arch).ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::Unsupported,
+                format!("Tenet doesn't support {:?} targets", ci.arch))
+        })?;
+        let path = config.dir.join(format!("trace.{}.{}.log", ci.pid, ci.tid));
+        Ok(Self {
+            file:   BufWriter::new(File::create(path)?),
+            writer: TenetWriter::new(layout, ci.big_endian),
+            lines:  Vec::new(),
+        })
+    }
+
+    fn write(&mut self, events: &[Event]) -> std::io::Result<()> {
+        self.lines.clear();
+        for event in events {
+            self.writer.event(event, &mut self.lines);
+        }
+        self.file.write_all(&self.lines)
+    }
+
+    /// Write the line of the last instruction too, the thread may be gone
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.lines.clear();
+        self.writer.end(&mut self.lines);
+        self.file.write_all(&self.lines)?;
+        self.file.flush()
+    }
+}
+
+#[test]
+fn tenet_lines() {
+    let mut writer = TenetWriter::new(
+        Layout::new(Architecture::I386).unwrap(), false);
+    let regs = |eax: u32, esp: u32| {
+        let mut regs = vec![0u8; 32];
+        regs[..4].copy_from_slice(&eax.to_le_bytes());
+        regs[16..20].copy_from_slice(&esp.to_le_bytes());
+        regs
+    };
+
+    let mut out = Vec::new();
+    for event in [
+        Event::Regs { pc: 0x1000, regs: regs(1, 0x8000) },
+        Event::Write { pc: 0x1000, addr: 0x7ffc, val: 0x1005, sz: 4 },
+        Event::Regs { pc: 0x1005, regs: regs(1, 0x7ffc) },
+        Event::Read { pc: 0x1005, addr: 0x7ffc, val: 0x1005, sz: 4 },
+        Event::Exec { pc: 0x1008 },
+    ] {
+        writer.event(&event, &mut out);
+    }
+    writer.end(&mut out);
+    assert_eq!(String::from_utf8(out).unwrap(),
+        "eax=0x1,ecx=0x0,edx=0x0,ebx=0x0,esp=0x8000,ebp=0x0,esi=0x0,edi=0x0,\
+         eip=0x1000,mw=0x7ffc:05100000\n\
+         esp=0x7ffc,eip=0x1005,mr=0x7ffc:05100000\n\
+         eip=0x1008\n");
+}
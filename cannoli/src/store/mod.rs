@@ -0,0 +1,10 @@
+//! Storage of traces which can be queried after the run
+//!
+//! Captures (see [`crate::capture`]) are cheap to write but can only be read
+//! front to back, so every question about a run is a full pass over it. A
+//! store keeps the events in indexed tables instead, so questions like "who
+//! wrote to this address" are answered without reading (or holding) the
+//! whole trace.
+
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
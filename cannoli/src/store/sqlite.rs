@@ -0,0 +1,385 @@
+//! Trace storage in an indexed SQLite database
+//!
+//! A [`TraceStore`] keeps the instructions, memory accesses and mappings of
+//! a trace in three tables, each row keyed by the PID and TID of its thread
+//! and its position (`seq`) in the trace of that thread:
+//!
+//! ```text
+//! instructions (pid, tid, seq, pc)
+//! accesses     (pid, tid, seq, pc, addr, val, size, write)
+//! mappings     (pid, tid, seq, base, len, mapped, anon, read, write, exec,
+//!               path, offset)
+//! ```
+//!
+//! `val` is `NULL` for accesses traced without values, and unmappings are
+//! rows with `mapped` unset and no flags. Instructions are indexed by PC,
+//! accesses by address and PC, mappings by base, which is what post-hoc
+//! queries mostly look for:
+//!
+//! ```sql
+//! SELECT pid, tid, seq, pc, val FROM accesses
+//! WHERE write AND addr BETWEEN 0x4c8000 AND 0x4c8007 ORDER BY seq;
+//! ```
+//!
+//! Run `Recorder<SqliteSink>` to stream a live run into a store. Events are
+//! inserted in transactions of [`StoreConfig::batch`] events, and only the
+//! batches are kept in memory. Addresses are stored as SQLite's signed
+//! integers, so addresses from `0x8000000000000000` on compare as negative.
+//! Other kinds of events aren't stored.
+
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use rusqlite::{params, Connection};
+use crate::ClientInfo;
+use crate::event::Event;
+use crate::sinks::Sink;
+
+/// A position in the trace, the thread and the index of the event in its
+/// trace
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Position {
+    /// Process ID of the thread
+    pub pid: i32,
+
+    /// Thread ID of the thread
+    pub tid: i32,
+
+    /// Index of the event in the trace of the thread
+    pub seq: u64,
+}
+
+/// A memory access of a [`TraceStore`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Access {
+    /// Where the access is in the trace
+    pub pos: Position,
+
+    /// Instruction which made the access
+    pub pc: u64,
+
+    /// Address accessed
+    pub addr: u64,
+
+    /// Value read or written, `None` if traced without values
+    pub val: Option<u64>,
+
+    /// Size of the access in bytes
+    pub size: u8,
+
+    /// Whether the access is a write
+    pub write: bool,
+}
+
+/// A database of trace events, see the module documentation
+pub struct TraceStore {
+    /// Connection to the database
+    conn: Connection,
+}
+
+impl TraceStore {
+    /// Open the store at `path`, creating it if it doesn't exist
+    pub fn open(path: impl AsRef<Path>) -> rusqlite::Result<Self> {
+        Self::init(Connection::open(path)?)
+    }
+
+    /// Create a store which only lives in memory
+    pub fn open_in_memory() -> rusqlite::Result<Self> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    /// Create the schema if needed
+    fn init(conn: Connection) -> rusqlite::Result<Self> {
+        // Batches are written by one connection, a crash only loses the
+        // batches which weren't committed
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+        conn.execute_batch("
+            CREATE TABLE IF NOT EXISTS instructions (
+                pid INTEGER NOT NULL,
+                tid INTEGER NOT NULL,
+                seq INTEGER NOT NULL,
+                pc  INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS accesses (
+                pid   INTEGER NOT NULL,
+                tid   INTEGER NOT NULL,
+                seq   INTEGER NOT NULL,
+                pc    INTEGER NOT NULL,
+                addr  INTEGER NOT NULL,
+                val   INTEGER,
+                size  INTEGER NOT NULL,
+                write INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS mappings (
+                pid    INTEGER NOT NULL,
+                tid    INTEGER NOT NULL,
+                seq    INTEGER NOT NULL,
+                base   INTEGER NOT NULL,
+                len    INTEGER NOT NULL,
+                mapped INTEGER NOT NULL,
+                anon   INTEGER,
+                read   INTEGER,
+                write  INTEGER,
+                exec   INTEGER,
+                path   TEXT,
+                offset INTEGER
+            );
+            CREATE INDEX IF NOT EXISTS instructions_pc
+                ON instructions (pc);
+            CREATE INDEX IF NOT EXISTS accesses_addr ON accesses (addr);
+            CREATE INDEX IF NOT EXISTS accesses_pc   ON accesses (pc);
+            CREATE INDEX IF NOT EXISTS mappings_base ON mappings (base);
+        ")?;
+        Ok(Self { conn })
+    }
+
+    /// Insert `events` of the thread `tid` of the process `pid`, the first
+    /// of them being at `seq` in its trace, in a single transaction
+    pub fn insert(&mut self, pid: i32, tid: i32, seq: u64, events: &[Event])
+            -> rusqlite::Result<()> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut exec = tx.prepare_cached("
+                INSERT INTO instructions (pid, tid, seq, pc)
+                VALUES (?1, ?2, ?3, ?4)
+            ")?;
+            let mut access = tx.prepare_cached("
+                INSERT INTO accesses
+                    (pid, tid, seq, pc, addr, val, size, write)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+            ")?;
+            let mut mapping = tx.prepare_cached("
+                INSERT INTO mappings (pid, tid, seq, base, len, mapped, anon,
+                    read, write, exec, path, offset)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)
+            ")?;
+
+            for (seq, event) in (seq..).zip(events) {
+                let seq = seq as i64;
+                match *event {
+                    Event::Exec { pc } | Event::Regs { pc, .. } |
+                            Event::Branch { pc, .. } => {
+                        exec.execute(params![pid, tid, seq, pc as i64])?;
+                    }
+                    Event::Read { pc, addr, val, sz } |
+                            Event::Write { pc, addr, val, sz } => {
+                        let write = matches!(event, Event::Write { .. });
+                        access.execute(params![pid, tid, seq, pc as i64,
+                            addr as i64, val as i64, sz, write])?;
+                    }
+                    Event::ReadAddr { pc, addr, sz } |
+                            Event::WriteAddr { pc, addr, sz } => {
+                        let write = matches!(event, Event::WriteAddr { .. });
+                        access.execute(params![pid, tid, seq, pc as i64,
+                            addr as i64, None::<i64>, sz, write])?;
+                    }
+                    Event::Mmap { base, len, anon, read, write, exec,
+                            ref path, offset } => {
+                        mapping.execute(params![pid, tid, seq, base as i64,
+                            len as i64, true, anon, read, write, exec, path,
+                            offset as i64])?;
+                    }
+                    Event::Munmap { base, len } => {
+                        mapping.execute(params![pid, tid, seq, base as i64,
+                            len as i64, false, None::<bool>, None::<bool>,
+                            None::<bool>, None::<bool>, None::<String>,
+                            None::<i64>])?;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        tx.commit()
+    }
+
+    /// Get the accesses to `addrs`, only the writes or reads if `write` is
+    /// given, in the order of the traces of their threads
+    pub fn accesses(&self, addrs: Range<u64>, write: Option<bool>)
+            -> rusqlite::Result<Vec<Access>> {
+        let mut stmt = self.conn.prepare_cached("
+            SELECT pid, tid, seq, pc, addr, val, size, write FROM accesses
+            WHERE addr >= ?1 AND addr < ?2 AND (?3 IS NULL OR write = ?3)
+            ORDER BY pid, tid, seq
+        ")?;
+        let rows = stmt.query_map(params![addrs.start as i64,
+                addrs.end as i64, write], |row| {
+            Ok(Access {
+                pos: Position {
+                    pid: row.get(0)?,
+                    tid: row.get(1)?,
+                    seq: row.get::<_, i64>(2)? as u64,
+                },
+                pc:    row.get::<_, i64>(3)? as u64,
+                addr:  row.get::<_, i64>(4)? as u64,
+                val:   row.get::<_, Option<i64>>(5)?.map(|x| x as u64),
+                size:  row.get(6)?,
+                write: row.get(7)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// Get where the instruction at `pc` executed, in the order of the
+    /// traces of their threads
+    pub fn executions(&self, pc: u64) -> rusqlite::Result<Vec<Position>> {
+        let mut stmt = self.conn.prepare_cached("
+            SELECT pid, tid, seq FROM instructions WHERE pc = ?1
+            ORDER BY pid, tid, seq
+        ")?;
+        let rows = stmt.query_map(params![pc as i64], |row| {
+            Ok(Position {
+                pid: row.get(0)?,
+                tid: row.get(1)?,
+                seq: row.get::<_, i64>(2)? as u64,
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// Get the connection to the database, for queries of your own
+    pub fn connection(&self) -> &Connection {
+        &self.conn
+    }
+}
+
+/// Configuration for [`SqliteSink`]
+#[derive(Clone, Debug)]
+pub struct StoreConfig {
+    /// Database the events are written to, created if needed
+    pub path: PathBuf,
+
+    /// Number of events of a thread inserted in one transaction
+    pub batch: usize,
+}
+
+impl Default for StoreConfig {
+    fn default() -> Self {
+        Self {
+            path:  "trace.sqlite".into(),
+            batch: 64 * 1024,
+        }
+    }
+}
+
+/// Configuration used by [`SqliteSink`], set with [`configure`]
+static CONFIG: OnceLock<StoreConfig> = OnceLock::new();
+
+/// Store the events are written to, opened when the first thread connects
+static STORE: Mutex<Option<Arc<Mutex<TraceStore>>>> = Mutex::new(None);
+
+/// Set the configuration used by [`SqliteSink`]. This must be called before
+/// [`crate::create_cannoli`] to have any effect, and can only be called once
+pub fn configure(config: StoreConfig) -> Result<(), StoreConfig> {
+    CONFIG.set(config)
+}
+
+/// Convert a SQLite error for a [`Sink`]
+fn io_error(err: rusqlite::Error) -> std::io::Error {
+    std::io::Error::other(err)
+}
+
+/// Streams the events of a thread into a [`TraceStore`], see the module
+/// documentation
+pub struct SqliteSink {
+    /// The store, shared by every thread
+    store: Arc<Mutex<TraceStore>>,
+
+    /// Number of events inserted at once
+    batch: usize,
+
+    /// Process ID of the thread
+    pid: i32,
+
+    /// Thread ID of the thread
+    tid: i32,
+
+    /// Position of the first pending event in the trace of the thread
+    seq: u64,
+
+    /// Events not inserted yet
+    pending: Vec<Event>,
+}
+
+impl SqliteSink {
+    /// Insert the pending events
+    fn commit(&mut self) -> std::io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        self.store.lock().unwrap()
+            .insert(self.pid, self.tid, self.seq, &self.pending)
+            .map_err(io_error)?;
+        self.seq += self.pending.len() as u64;
+        self.pending.clear();
+        Ok(())
+    }
+}
+
+impl Sink for SqliteSink {
+    fn open(ci: &ClientInfo) -> std::io::Result<Self> {
+        let config = CONFIG.get_or_init(StoreConfig::default);
+        let store = {
+            let mut store = STORE.lock().unwrap();
+            if store.is_none() {
+                *store = Some(Arc::new(Mutex::new(
+                    TraceStore::open(&config.path).map_err(io_error)?)));
+            }
+            store.clone().unwrap()
+        };
+        Ok(Self {
+            batch:   config.batch.max(1),
+            pid:     ci.pid,
+            tid:     ci.tid,
+            seq:     0,
+            pending: Vec::new(),
+            store,
+        })
+    }
+
+    fn write(&mut self, events: &[Event]) -> std::io::Result<()> {
+        self.pending.extend_from_slice(events);
+        if self.pending.len() >= self.batch {
+            self.commit()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.commit()
+    }
+}
+
+#[test]
+fn store_queries() {
+    let mut store = TraceStore::open_in_memory().unwrap();
+    store.insert(1, 2, 0, &[
+        Event::Mmap { base: 0x400000, len: 0x1000, anon: false, read: true,
+            write: false, exec: true, path: "/bin/true".into(), offset: 0 },
+        Event::Exec { pc: 0x400000 },
+        Event::Write { pc: 0x400000, addr: 0x7000, val: 0x41, sz: 1 },
+        Event::Exec { pc: 0x400004 },
+        Event::ReadAddr { pc: 0x400004, addr: 0x7000, sz: 4 },
+    ]).unwrap();
+    store.insert(1, 2, 5, &[
+        Event::Exec { pc: 0x400000 },
+        Event::Write { pc: 0x400000, addr: 0x7001, val: 0x42, sz: 1 },
+        Event::Munmap { base: 0x400000, len: 0x1000 },
+    ]).unwrap();
+
+    let writes = store.accesses(0x7000..0x7002, Some(true)).unwrap();
+    assert_eq!(writes.iter().map(|x| (x.pos.seq, x.addr, x.val))
+        .collect::<Vec<_>>(), [(2, 0x7000, Some(0x41)),
+            (6, 0x7001, Some(0x42))]);
+    let reads = store.accesses(0x7000..0x7001, Some(false)).unwrap();
+    assert_eq!((reads.len(), reads[0].val, reads[0].size), (1, None, 4));
+    assert_eq!(store.accesses(0x7000..0x8000, None).unwrap().len(), 3);
+
+    let executions = store.executions(0x400000).unwrap();
+    assert_eq!(executions.iter().map(|x| x.seq).collect::<Vec<_>>(),
+        [1, 5]);
+    let mappings: i64 = store.connection().query_row(
+        "SELECT count(*) FROM mappings WHERE NOT mapped", [],
+        |row| row.get(0)).unwrap();
+    assert_eq!(mappings, 1);
+}
@@ -0,0 +1,246 @@
+//! Persistent annotations of module-relative addresses
+//!
+//! An [`AnnotationDb`] is a SQLite database of labels and notes attached to
+//! addresses, shared between runs so knowledge about a target accumulates
+//! like in an RE database. Analyses record what they discovered, users add
+//! their own notes (eg. with `cannoli annotate`), and later runs apply
+//! everything to their symbolized output.
+//!
+//! Addresses are stored relative to the base of the module containing them
+//! (see [`crate::address_space::AddressSpace::module_offset`]), and modules
+//! are identified by their file name only, so annotations survive ASLR and
+//! targets living at different paths on different hosts.
+//!
+//! The database is only accessed when loading and storing annotations, the
+//! trace processing hot path uses an in-memory [`Annotations`] snapshot.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use rusqlite::{params, Connection, OptionalExtension};
+use crate::address_space::AddressSpace;
+use crate::symbols::{Symbol, SymbolTable};
+
+/// An annotation attached to an address
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Annotation {
+    /// File name of the module containing the address, see [`module_name`]
+    pub module: String,
+
+    /// Offset of the address from the base of the module
+    pub offset: u64,
+
+    /// Who created the annotation, eg. `user` or the name of an analysis. Each
+    /// source has at most one annotation per address
+    pub source: String,
+
+    /// Short label, used as the symbol name when applied to symbolized output
+    pub label: String,
+
+    /// Longer free-form note
+    pub note: Option<String>,
+}
+
+/// Get the name modules are identified by in the database, the file name of
+/// `path`
+pub fn module_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// A database of annotations, see the module documentation
+pub struct AnnotationDb {
+    /// Connection to the database
+    conn: Connection,
+}
+
+impl AnnotationDb {
+    /// Open the database at `path`, creating it if it doesn't exist
+    pub fn open(path: impl AsRef<Path>) -> rusqlite::Result<Self> {
+        Self::init(Connection::open(path)?)
+    }
+
+    /// Create a database which only lives in memory
+    pub fn open_in_memory() -> rusqlite::Result<Self> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    /// Create the schema if needed
+    fn init(conn: Connection) -> rusqlite::Result<Self> {
+        conn.execute_batch("
+            CREATE TABLE IF NOT EXISTS annotations (
+                module  TEXT    NOT NULL,
+                offset  INTEGER NOT NULL,
+                source  TEXT    NOT NULL,
+                label   TEXT    NOT NULL,
+                note    TEXT,
+                updated INTEGER NOT NULL,
+                PRIMARY KEY (module, offset, source)
+            );
+        ")?;
+        Ok(Self { conn })
+    }
+
+    /// Add an annotation, replacing the annotation of the same source at the
+    /// same address
+    pub fn annotate(&self, annotation: &Annotation) -> rusqlite::Result<()> {
+        Self::insert(&self.conn, annotation)
+    }
+
+    /// Add many annotations at once, in a single transaction
+    pub fn annotate_all<'a>(&mut self,
+            annotations: impl IntoIterator<Item = &'a Annotation>)
+            -> rusqlite::Result<()> {
+        let tx = self.conn.transaction()?;
+        for annotation in annotations {
+            Self::insert(&tx, annotation)?;
+        }
+        tx.commit()
+    }
+
+    /// Insert or replace `annotation` using `conn`
+    fn insert(conn: &Connection, annotation: &Annotation)
+            -> rusqlite::Result<()> {
+        let updated = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map_or(0, |x| x.as_secs() as i64);
+
+        conn.execute("
+            INSERT OR REPLACE INTO annotations
+                (module, offset, source, label, note, updated)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+        ", params![annotation.module, annotation.offset as i64,
+            annotation.source, annotation.label, annotation.note, updated])?;
+        Ok(())
+    }
+
+    /// Remove the annotation of `source` at `offset` in `module`, returning
+    /// if there was one
+    pub fn remove(&self, module: &str, offset: u64, source: &str)
+            -> rusqlite::Result<bool> {
+        Ok(self.conn.execute("
+            DELETE FROM annotations
+            WHERE module = ?1 AND offset = ?2 AND source = ?3
+        ", params![module, offset as i64, source])? != 0)
+    }
+
+    /// Get the annotation of `source` at `offset` in `module`
+    pub fn get(&self, module: &str, offset: u64, source: &str)
+            -> rusqlite::Result<Option<Annotation>> {
+        self.conn.query_row("
+            SELECT module, offset, source, label, note FROM annotations
+            WHERE module = ?1 AND offset = ?2 AND source = ?3
+        ", params![module, offset as i64, source], Self::row).optional()
+    }
+
+    /// Get all annotations, or only those in `module`, sorted by module,
+    /// offset and source
+    pub fn list(&self, module: Option<&str>)
+            -> rusqlite::Result<Vec<Annotation>> {
+        let mut stmt = self.conn.prepare("
+            SELECT module, offset, source, label, note FROM annotations
+            WHERE ?1 IS NULL OR module = ?1
+            ORDER BY module, offset, source
+        ")?;
+        let rows = stmt.query_map(params![module], Self::row)?;
+        rows.collect()
+    }
+
+    /// Load every annotation into memory for applying to a trace
+    pub fn load(&self) -> rusqlite::Result<Annotations> {
+        let mut ret = Annotations::default();
+        for annotation in self.list(None)? {
+            ret.insert(annotation);
+        }
+        Ok(ret)
+    }
+
+    /// Convert a row of the `annotations` table
+    fn row(row: &rusqlite::Row) -> rusqlite::Result<Annotation> {
+        Ok(Annotation {
+            module: row.get(0)?,
+            offset: row.get::<_, i64>(1)? as u64,
+            source: row.get(2)?,
+            label:  row.get(3)?,
+            note:   row.get(4)?,
+        })
+    }
+}
+
+/// An in-memory snapshot of an [`AnnotationDb`]
+#[derive(Clone, Debug, Default)]
+pub struct Annotations {
+    /// Annotations keyed by module, then offset
+    modules: HashMap<String, BTreeMap<u64, Vec<Annotation>>>,
+}
+
+impl Annotations {
+    /// Add an annotation to the snapshot
+    pub fn insert(&mut self, annotation: Annotation) {
+        self.modules.entry(annotation.module.clone()).or_default()
+            .entry(annotation.offset).or_default()
+            .push(annotation);
+    }
+
+    /// Get the annotations at `offset` in `module`
+    pub fn get(&self, module: &str, offset: u64) -> &[Annotation] {
+        self.modules.get(module)
+            .and_then(|x| x.get(&offset))
+            .map_or(&[], |x| x.as_slice())
+    }
+
+    /// Get the annotations at the absolute address `addr`, using `space` to
+    /// find the module containing it
+    pub fn at(&self, space: &AddressSpace, addr: u64) -> &[Annotation] {
+        match space.module_offset(addr) {
+            Some((path, offset)) => self.get(module_name(&path), offset),
+            None => &[],
+        }
+    }
+
+    /// Create a symbol table from the labels in `module`, for a module
+    /// loaded at `base`. The table is named `annotations` and can be put at
+    /// the front of a [`crate::symbols::ResolverChain`] so labels take
+    /// precedence over every other source of symbols
+    pub fn symbols(&self, module: &str, base: u64) -> SymbolTable {
+        let symbols = self.modules.get(module).into_iter()
+            .flat_map(|x| x.values())
+            .filter_map(|x| x.first())
+            .map(|x| Symbol {
+                name: x.label.as_str().into(),
+                addr: base.wrapping_add(x.offset),
+                size: None,
+                mangled: None,
+            })
+            .collect();
+        SymbolTable::new("annotations", symbols)
+    }
+}
+
+#[test]
+fn annotations_roundtrip() {
+    let db = AnnotationDb::open_in_memory().unwrap();
+    let mut annotation = Annotation {
+        module: module_name("/usr/lib/libfoo.so").into(),
+        offset: 0x1234,
+        source: "user".into(),
+        label:  "parse_header".into(),
+        note:   Some("length is not checked".into()),
+    };
+    db.annotate(&annotation).unwrap();
+
+    // Annotating again from the same source replaces the old annotation
+    annotation.note = None;
+    db.annotate(&annotation).unwrap();
+    assert_eq!(db.list(Some("libfoo.so")).unwrap(), [annotation.clone()]);
+
+    // Apply to a run where the library is mapped somewhere else
+    let mut space = AddressSpace::new();
+    space.mmap(0x7f0000, 0x1000, false, true, false, false,
+        "/opt/libfoo.so", 0);
+    space.mmap(0x7f1000, 0x1000, false, true, false, true,
+        "/opt/libfoo.so", 0x1000);
+    let annotations = db.load().unwrap();
+    assert_eq!(annotations.at(&space, 0x7f1234), [annotation]);
+
+    assert!(db.remove("libfoo.so", 0x1234, "user").unwrap());
+    assert!(db.list(None).unwrap().is_empty());
+}
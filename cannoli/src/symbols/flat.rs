@@ -0,0 +1,202 @@
+//! Flat lookup tables of pre-resolved symbols
+//!
+//! Resolving through a [`ResolverChain`] binary searches every resolver in
+//! the chain, which adds up when it's done for every executed PC. A
+//! [`FlatResolver`] resolves every address of a page once, and then answers
+//! lookups in that page with a single index into the page's table. Pages are
+//! filled either up front for a whole module when it's mapped
+//! ([`FlatResolver::prefill`]), or lazily the first time an address in them
+//! is resolved.
+//!
+//! Each page costs 4 bytes per granule, so 4 KiB per page for targets with
+//! 4-byte aligned instructions and 16 KiB per page with a granule of 1 byte.
+//! Only use this for code addresses, data addresses are spread over far more
+//! pages.
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use crate::symbols::{Resolved, ResolverChain, Symbol};
+
+/// Size of the pages tables are built for
+const PAGE_SIZE: u64 = 4096;
+
+/// A symbol, as referenced by the page tables
+#[derive(Clone, Debug)]
+struct Entry {
+    /// The symbol
+    symbol: Symbol,
+
+    /// Name of the resolver which provided the symbol
+    source: Arc<str>,
+
+    /// Set if the resolution was exact, see [`Resolved::exact`]
+    exact: bool,
+}
+
+/// State behind the lock of a [`FlatResolver`]
+#[derive(Default)]
+struct Tables {
+    /// Table of every filled page, keyed by page number. Each entry is an
+    /// index into `entries` plus one, or zero if nothing resolved
+    pages: HashMap<u64, Box<[u32]>>,
+
+    /// Every distinct symbol referenced by `pages`
+    entries: Vec<Entry>,
+
+    /// Index of each entry, keyed by address, name and source of the symbol
+    index: HashMap<(u64, Arc<str>, Arc<str>, bool), u32>,
+}
+
+/// Caches the results of a [`ResolverChain`] in flat per-page tables, see the
+/// module documentation
+pub struct FlatResolver {
+    /// Chain resolving addresses which aren't in a table yet
+    chain: ResolverChain,
+
+    /// log2 of the granule, addresses are resolved at this alignment
+    granule_shift: u32,
+
+    /// Tables of the pages resolved so far
+    tables: RwLock<Tables>,
+}
+
+impl FlatResolver {
+    /// Cache the results of `chain`. `granule` is the alignment of the
+    /// addresses being resolved (eg. 4 for MIPS instructions, 1 for x86),
+    /// and must be a power of two
+    pub fn new(chain: ResolverChain, granule: u64) -> Self {
+        assert!(granule.is_power_of_two() && granule <= PAGE_SIZE,
+            "Invalid granule {granule}");
+
+        Self {
+            granule_shift: granule.trailing_zeros(),
+            tables:        RwLock::new(Tables::default()),
+            chain,
+        }
+    }
+
+    /// Get the chain being cached, for resolving addresses which shouldn't be
+    /// cached
+    pub fn chain(&self) -> &ResolverChain {
+        &self.chain
+    }
+
+    /// Build the table of page number `page`
+    fn fill(&self, tables: &mut Tables, page: u64) {
+        if tables.pages.contains_key(&page) {
+            return;
+        }
+
+        let granules = (PAGE_SIZE >> self.granule_shift) as usize;
+        let mut table = vec![0u32; granules].into_boxed_slice();
+        for (ii, slot) in table.iter_mut().enumerate() {
+            let addr = page * PAGE_SIZE + ((ii as u64) << self.granule_shift);
+            let Some(resolved) = self.chain.resolve(addr) else { continue };
+
+            // Intern the symbol, addresses in the same symbol share an entry
+            let key = (resolved.symbol.addr, resolved.symbol.name.clone(),
+                resolved.source.clone(), resolved.exact);
+            let idx = *tables.index.entry(key).or_insert_with(|| {
+                tables.entries.push(Entry {
+                    symbol: resolved.symbol,
+                    source: resolved.source,
+                    exact:  resolved.exact,
+                });
+                tables.entries.len() as u32
+            });
+            *slot = idx;
+        }
+
+        tables.pages.insert(page, table);
+    }
+
+    /// Resolve every page of `base..base + len` now, eg. when a module is
+    /// mapped, so the hot path never has to
+    pub fn prefill(&self, base: u64, len: u64) {
+        if len == 0 {
+            return;
+        }
+
+        let mut tables = self.tables.write().unwrap();
+        let last = base.saturating_add(len - 1) / PAGE_SIZE;
+        for page in base / PAGE_SIZE..=last {
+            self.fill(&mut tables, page);
+        }
+    }
+
+    /// Drop the tables of `base..base + len`, eg. when it's unmapped and
+    /// something else may be mapped there later
+    pub fn invalidate(&self, base: u64, len: u64) {
+        if len == 0 {
+            return;
+        }
+
+        let mut tables = self.tables.write().unwrap();
+        let first = base / PAGE_SIZE;
+        let last  = base.saturating_add(len - 1) / PAGE_SIZE;
+        tables.pages.retain(|page, _| *page < first || *page > last);
+    }
+
+    /// Look up `addr` in the table of its page, `None` if the page has no
+    /// table yet
+    fn lookup(&self, tables: &Tables, addr: u64) -> Option<Option<Resolved>> {
+        let table = tables.pages.get(&(addr / PAGE_SIZE))?;
+        let idx   = table[((addr % PAGE_SIZE) >> self.granule_shift) as usize];
+
+        Some(idx.checked_sub(1).map(|idx| {
+            let entry  = &tables.entries[idx as usize];
+            let offset = addr.wrapping_sub(entry.symbol.addr);
+            Resolved {
+                symbol: entry.symbol.clone(),
+                source: entry.source.clone(),
+                exact:  entry.exact &&
+                    entry.symbol.size.is_some_and(|x| offset < x),
+                offset,
+            }
+        }))
+    }
+
+    /// Resolve `addr` to a symbol, building the table of its page if needed
+    pub fn resolve(&self, addr: u64) -> Option<Resolved> {
+        let tables = self.tables.read().unwrap();
+        if let Some(resolved) = self.lookup(&tables, addr) {
+            return resolved;
+        }
+        drop(tables);
+
+        let mut tables = self.tables.write().unwrap();
+        self.fill(&mut tables, addr / PAGE_SIZE);
+        self.lookup(&tables, addr).unwrap()
+    }
+
+    /// Number of pages with a table, each takes 4 bytes per granule
+    pub fn pages(&self) -> usize {
+        self.tables.read().unwrap().pages.len()
+    }
+}
+
+#[test]
+fn flat_matches_chain() {
+    use crate::symbols::SymbolTable;
+
+    let table = SymbolTable::from_nm("app", "\
+        0000000000001ff0 0000000000000020 T start
+        0000000000002010 0000000000000100 T main
+        0000000000002800 T tail
+    ").unwrap();
+    let mut chain = ResolverChain::new();
+    chain.add(0, table);
+    let flat = FlatResolver::new(chain, 4);
+
+    flat.prefill(0x1000, 0x1000);
+    assert_eq!(flat.pages(), 1);
+
+    for addr in (0..0x4000).step_by(4) {
+        assert_eq!(flat.resolve(addr), flat.chain().resolve(addr),
+            "{addr:#x}");
+    }
+    assert_eq!(flat.pages(), 4);
+
+    flat.invalidate(0x2000, 1);
+    assert_eq!(flat.pages(), 3);
+}
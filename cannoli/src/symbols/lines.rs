@@ -0,0 +1,197 @@
+//! Source lines of code from DWARF line tables
+//!
+//! Addresses change with every rebuild of a target, source lines mostly
+//! don't. Keying coverage by [`SourceLine`] instead of by address keeps
+//! coverage accumulated over many runs meaningful after the target is
+//! recompiled, see [`crate::analysis::source_coverage`].
+//!
+//! Line tables are read with `readelf`, like [`super::SymbolTable`] reads
+//! symbols with `nm`, and looked up by the offset from where the file is
+//! loaded, which is what
+//! [`crate::address_space::AddressSpace::module_offset`] gives for a PC.
+//! Files are named the way the line table names them, which is an absolute
+//! path for most compilers, so the target has to be rebuilt in the same
+//! place for its lines to match up.
+
+use std::sync::Arc;
+use std::ffi::OsStr;
+use std::path::Path;
+use std::process::Command;
+use std::collections::HashMap;
+
+/// A line of a source file
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SourceLine {
+    /// Path of the source file
+    pub file: Arc<str>,
+
+    /// Line number, starting at 1
+    pub line: u32,
+}
+
+/// The line table of an ELF, see the module documentation
+#[derive(Clone, Debug, Default)]
+pub struct LineTable {
+    /// Virtual address of the start of the file, from the loadable segment
+    /// mapping it
+    base: u64,
+
+    /// Rows of the line table sorted by address. `None` ends a sequence of
+    /// rows, addresses up to the next row have no line
+    rows: Vec<(u64, Option<SourceLine>)>,
+}
+
+/// Run `readelf` with `args` on `path`
+pub(super) fn readelf(args: &[&str], path: &Path) -> std::io::Result<String> {
+    let output = Command::new("readelf").args(args).arg(path).output()?;
+    if !output.status.success() {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData,
+            format!("readelf failed for {}: {}", path.display(),
+                String::from_utf8_lossy(&output.stderr).trim())));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Get the virtual address the start of an ELF is linked at, from its
+/// program headers (`readelf -W -l`)
+pub(super) fn image_base(headers: &str) -> u64 {
+    let hex = |x: &str| {
+        u64::from_str_radix(x.trim_start_matches("0x"), 16)
+    };
+
+    // LOAD <offset> <vaddr> <paddr> <filesz> <memsz> <flags> <align>, the
+    // first segment maps the start of the file
+    headers.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        (fields.first() == Some(&"LOAD")).then_some(())?;
+        let offset = hex(fields.get(1)?).ok()?;
+        let vaddr  = hex(fields.get(2)?).ok()?;
+        Some(vaddr.wrapping_sub(offset))
+    }).unwrap_or(0)
+}
+
+impl LineTable {
+    /// Load the line table of the ELF at `path` using `readelf`. The table is
+    /// empty if the ELF has no debug information
+    pub fn from_elf(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let path = path.as_ref();
+        Ok(Self::parse(&readelf(&["-W", "-l"], path)?,
+            &readelf(&["-W", "--debug-dump=decodedline"], path)?))
+    }
+
+    /// Parse the program headers (`readelf -W -l`) and the decoded line table
+    /// (`readelf -W --debug-dump=decodedline`) of an ELF
+    pub fn parse(headers: &str, lines: &str) -> Self {
+        let hex = |x: &str| {
+            u64::from_str_radix(x.trim_start_matches("0x"), 16)
+        };
+        let base = image_base(headers);
+
+        // Rows name files relative to the compilation unit, which is named in
+        // full when it starts, as are files the rows switch to
+        let mut files: HashMap<String, Arc<str>> = HashMap::new();
+        let mut cu_dir = String::new();
+        let mut current: Option<Arc<str>> = None;
+        let mut rows = Vec::new();
+        for line in lines.lines() {
+            if let Some(name) = line.strip_suffix(':') {
+                let cu = name.strip_prefix("CU: ");
+                let name = cu.unwrap_or(name);
+                if name.contains(' ') {
+                    continue;
+                }
+                if cu.is_some() {
+                    cu_dir = Path::new(name).parent()
+                        .map_or(String::new(), |x| x.display().to_string());
+                }
+                current = Some(files.entry(name.to_string())
+                    .or_insert_with(|| name.into()).clone());
+                continue;
+            }
+
+            // <file> <line> <address> [view] [stmt]
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let (Some(name), Some(number), Some(addr)) =
+                    (fields.first(), fields.get(1), fields.get(2)) else {
+                continue;
+            };
+            let Ok(addr) = hex(addr) else { continue };
+            if *number == "-" {
+                rows.push((addr, None));
+                continue;
+            }
+            let Ok(number) = number.parse::<u32>() else { continue };
+            if number == 0 {
+                rows.push((addr, None));
+                continue;
+            }
+
+            // The row's file is the last one named in full if that's the one
+            // it means, otherwise relative to the compilation unit
+            let file = match &current {
+                Some(x) if Path::new(&**x).file_name() ==
+                    Some(OsStr::new(name)) => x.clone(),
+                _ => {
+                    let full = Path::new(&cu_dir).join(name)
+                        .display().to_string();
+                    files.entry(full.clone())
+                        .or_insert_with(|| full.into()).clone()
+                }
+            };
+            rows.push((addr, Some(SourceLine { file, line: number })));
+        }
+
+        // Keep the order of rows at the same address, the last one applies
+        rows.sort_by_key(|x| x.0);
+        Self { base, rows }
+    }
+
+    /// Check if the table has no lines, eg. for binaries without debug
+    /// information
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Get the source line of the instruction at virtual address `addr`
+    pub fn line(&self, addr: u64) -> Option<&SourceLine> {
+        let idx = self.rows.partition_point(|x| x.0 <= addr);
+        self.rows.get(idx.checked_sub(1)?)?.1.as_ref()
+    }
+
+    /// Get the source line of the instruction `offset` bytes after the start
+    /// of the loaded file
+    pub fn line_at_offset(&self, offset: u64) -> Option<&SourceLine> {
+        self.line(self.base.wrapping_add(offset))
+    }
+}
+
+#[test]
+fn parse_line_table() {
+    let headers = "\
+  Type Offset   VirtAddr           PhysAddr           FileSiz  MemSiz   Flg
+  LOAD 0x000000 0x0000000000400000 0x0000000000400000 0x000618 0x000618 R
+  LOAD 0x001000 0x0000000000401000 0x0000000000401000 0x000181 0x000181 R E
+";
+    let lines = "\
+Contents of the .debug_line section:
+
+CU: /src/t.c:
+File name  Line number  Starting address  View  Stmt
+h.h   1  0x401139  x
+h.h   2  0x401140  x
+
+/src/t.c:
+t.c   3  0x401147  x
+t.c   4  0x401156  x
+t.c   -  0x401177
+";
+    let table = LineTable::parse(headers, lines);
+    let at = |offset| table.line_at_offset(offset)
+        .map(|x| (x.file.to_string(), x.line));
+    assert_eq!(at(0x1141), Some(("/src/h.h".into(), 2)));
+    assert_eq!(at(0x1150), Some(("/src/t.c".into(), 3)));
+    assert_eq!(at(0x1170), Some(("/src/t.c".into(), 4)));
+    assert_eq!(at(0x1177), None);
+    assert_eq!(at(0x1138), None);
+    assert_eq!(at(0x3000), None);
+}
@@ -0,0 +1,166 @@
+//! Symbolization of addresses from multiple sources of symbols
+//!
+//! Symbol data is rarely perfect. A vendor map file may name a handful of
+//! functions, the ELF may be stripped except for its dynamic symbols, and
+//! the rest can only be guessed with [`crate::analysis::functions`]. A
+//! [`ResolverChain`] combines any number of [`Resolver`]s with priorities,
+//! and reports which source named each symbol so the quality of the output
+//! is obvious.
+
+use std::fmt;
+use std::sync::Arc;
+
+pub mod table;
+pub mod flat;
+pub mod signatures;
+pub mod lines;
+pub mod overlay;
+pub mod modules;
+#[cfg(feature = "sqlite")]
+pub mod annotations;
+
+pub use table::SymbolTable;
+pub use flat::FlatResolver;
+pub use overlay::Overlay;
+pub use modules::ModuleSymbols;
+
+/// A named address range
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Symbol {
+    /// Name of the symbol
+    pub name: Arc<str>,
+
+    /// Address of the start of the symbol
+    pub addr: u64,
+
+    /// Size of the symbol in bytes, if known
+    pub size: Option<u64>,
+
+    /// Mangled name of the symbol if `name` was demangled, see
+    /// [`SymbolTable::demangle`]
+    pub mangled: Option<Arc<str>>,
+}
+
+impl Symbol {
+    /// Get the name of the symbol as it is in the binary, mangled if it was
+    pub fn raw_name(&self) -> &Arc<str> {
+        self.mangled.as_ref().unwrap_or(&self.name)
+    }
+}
+
+/// An address resolved to a symbol
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Resolved {
+    /// Symbol containing the address
+    pub symbol: Symbol,
+
+    /// Offset of the address from the start of the symbol
+    pub offset: u64,
+
+    /// Set if the symbol has a known size and the address is inside of it.
+    /// Otherwise the symbol is only the closest one below the address
+    pub exact: bool,
+
+    /// Name of the [`Resolver`] which provided the symbol
+    pub source: Arc<str>,
+}
+
+impl fmt::Display for Resolved {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.offset != 0 {
+            write!(f, "{}+{:#x} [{}]", self.symbol.name, self.offset,
+                self.source)
+        } else {
+            write!(f, "{} [{}]", self.symbol.name, self.source)
+        }
+    }
+}
+
+/// A source of symbols
+pub trait Resolver: Send + Sync {
+    /// Name of this source, reported as the provenance of its symbols
+    fn name(&self) -> &Arc<str>;
+
+    /// Find the symbol containing `addr`, or the closest symbol below it
+    fn resolve(&self, addr: u64) -> Option<Resolved>;
+}
+
+/// Combines multiple [`Resolver`]s, ordered by priority
+///
+/// A symbol without a size is assumed to extend up to the next symbol known
+/// by *any* resolver, so a sparse map file doesn't swallow every address
+/// above its last symbol. An address is resolved by the highest priority
+/// resolver which has either a sized symbol containing it, or an unsized
+/// symbol which is the closest one below it. If there is no such symbol, the
+/// closest symbol below the address is used and [`Resolved::exact`] is not
+/// set.
+#[derive(Default)]
+pub struct ResolverChain {
+    /// Resolvers, sorted by descending priority
+    resolvers: Vec<(i32, Box<dyn Resolver>)>,
+}
+
+impl ResolverChain {
+    /// Create a new, empty, chain
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a resolver with `priority`, higher priorities are preferred
+    pub fn add(&mut self, priority: i32, resolver: impl Resolver + 'static) {
+        // Insert after everything with the same or higher priority, so equal
+        // priorities are used in the order they were added
+        let idx = self.resolvers.iter()
+            .position(|(x, _)| *x < priority)
+            .unwrap_or(self.resolvers.len());
+        self.resolvers.insert(idx, (priority, Box::new(resolver)));
+    }
+
+    /// Get the names of the resolvers in the chain, in priority order
+    pub fn sources(&self) -> impl Iterator<Item = &Arc<str>> {
+        self.resolvers.iter().map(|(_, x)| x.name())
+    }
+
+    /// Resolve `addr` to a symbol
+    pub fn resolve(&self, addr: u64) -> Option<Resolved> {
+        let candidates = self.resolvers.iter()
+            .filter_map(|(_, x)| x.resolve(addr))
+            .collect::<Vec<_>>();
+
+        // Start of the closest symbol below `addr` from any source
+        let closest = candidates.iter().map(|x| x.symbol.addr).max()?;
+
+        let idx = candidates.iter()
+            .position(|x| {
+                x.exact || (x.symbol.size.is_none() && x.symbol.addr == closest)
+            })
+            .or_else(|| {
+                candidates.iter().position(|x| x.symbol.addr == closest)
+            })?;
+        candidates.into_iter().nth(idx)
+    }
+}
+
+#[test]
+fn chain_priorities() {
+    let map = SymbolTable::from_nm("vendor.map",
+        "0000000000001000 T init\n").unwrap();
+    let elf = SymbolTable::from_nm("app",
+        "0000000000001000 0000000000000100 T _init\n\
+         0000000000002000 0000000000000100 T parse\n").unwrap();
+
+    let mut chain = ResolverChain::new();
+    chain.add(0,  elf);
+    chain.add(10, map);
+
+    // The map file names `init`, even though it has no size
+    let resolved = chain.resolve(0x1000).unwrap();
+    assert_eq!((&*resolved.symbol.name, &*resolved.source),
+        ("init", "vendor.map"));
+
+    // The ELF has a symbol containing the address, the map file's `init`
+    // ends where `parse` starts
+    let resolved = chain.resolve(0x2010).unwrap();
+    assert_eq!((&*resolved.symbol.name, resolved.offset), ("parse", 0x10));
+    assert_eq!(resolved.to_string(), "parse+0x10 [app]");
+}
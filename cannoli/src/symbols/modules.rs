@@ -0,0 +1,222 @@
+//! Symbols of the files a process maps, loaded as they're mapped
+//!
+//! Symbolizing a trace needs the symbols of the main binary and every
+//! library, at the addresses they're loaded at in this run. A
+//! [`ModuleSymbols`] follows the mappings of a process from its
+//! [`Event::Mmap`] and [`Event::Munmap`] events, loads the symbols (and
+//! optionally the line table) of every file the first time it's mapped
+//! executable, and resolves runtime addresses with them:
+//!
+//! ```no_run
+//! # fn f(events: &[cannoli::Event]) {
+//! use cannoli::symbols::{ModuleSymbols, Resolver};
+//!
+//! let mut symbols = ModuleSymbols::new().lines();
+//! for event in events {
+//!     symbols.event(event);
+//!     if let cannoli::Event::Exec { pc } = event {
+//!         println!("{pc:#x} {:?} {:?}", symbols.resolve(*pc),
+//!             symbols.line(*pc));
+//!     }
+//! }
+//! # }
+//! ```
+//!
+//! Symbols are loaded with `nm` and lines with `readelf` (see
+//! [`SymbolTable::from_elf`] and [`LineTable::from_elf`]), from the host
+//! path QEMU reports for the mapping. ELF symbols are at the addresses the
+//! file is linked at, they're slid to where the file is loaded in the
+//! process, so PIEs and libraries resolve the same with and without ASLR.
+//! Resolved symbols have their runtime address.
+//!
+//! Files which can't be loaded, eg. `[vdso]` or files gone since, are
+//! remembered and resolve nothing. Use [`ModuleSymbols::insert`] to give the
+//! symbols of a file from elsewhere, eg. a map file for a stripped binary.
+
+use std::sync::Arc;
+use std::collections::HashMap;
+use crate::Event;
+use crate::address_space::AddressSpace;
+use crate::symbols::{Resolver, Resolved, SymbolTable};
+use crate::symbols::lines::{self, LineTable, SourceLine};
+
+/// Symbols of a mapped file
+#[derive(Clone, Debug)]
+struct Module {
+    /// Virtual address the start of the file is linked at
+    base: u64,
+
+    /// Symbols, at their link addresses
+    table: SymbolTable,
+
+    /// Line table, if lines are loaded and the file has them
+    lines: Option<LineTable>,
+}
+
+/// Symbols of the files mapped by a process, see the module documentation
+#[derive(Clone, Debug)]
+pub struct ModuleSymbols {
+    /// Name of the resolver
+    name: Arc<str>,
+
+    /// Mappings of the process
+    space: AddressSpace,
+
+    /// Symbols of every file mapped executable, by path. `None` for files
+    /// which couldn't be loaded
+    modules: HashMap<Arc<str>, Option<Arc<Module>>>,
+
+    /// Whether to load line tables
+    lines: bool,
+
+    /// Whether to keep the mangled names of symbols
+    raw_names: bool,
+}
+
+impl Default for ModuleSymbols {
+    fn default() -> Self {
+        Self {
+            name:      "modules".into(),
+            space:     AddressSpace::new(),
+            modules:   HashMap::new(),
+            lines:     false,
+            raw_names: false,
+        }
+    }
+}
+
+impl ModuleSymbols {
+    /// Create a resolver without any mappings
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Also load the line tables of the files
+    pub fn lines(mut self) -> Self {
+        self.lines = true;
+        self
+    }
+
+    /// Keep the names of symbols mangled, rather than demangling C++ and Rust
+    /// names, see [`SymbolTable::demangle`]
+    pub fn raw_names(mut self) -> Self {
+        self.raw_names = true;
+        self
+    }
+
+    /// Get the mappings of the process
+    pub fn space(&self) -> &AddressSpace {
+        &self.space
+    }
+
+    /// Use `table` and `lines` for the file at `path`, linked so that its
+    /// start is at the virtual address `base`, rather than loading them when
+    /// the file is mapped
+    pub fn insert(&mut self, path: &str, base: u64, table: SymbolTable,
+            lines: Option<LineTable>) {
+        self.modules.insert(path.into(),
+            Some(Arc::new(Module { base, table, lines })));
+    }
+
+    /// Apply an event, [`Event::Mmap`] and [`Event::Munmap`] to track the
+    /// mappings, loading the symbols of files mapped executable
+    pub fn event(&mut self, event: &Event) {
+        match event {
+            Event::Mmap { base, len, anon, read, write, exec, path,
+                    offset } => {
+                self.space.mmap(*base, *len, *anon, *read, *write, *exec,
+                    path, *offset);
+                if *exec && !*anon && !path.is_empty() {
+                    self.load(path);
+                }
+            }
+            Event::Munmap { base, len } => self.space.munmap(*base, *len),
+            _ => {}
+        }
+    }
+
+    /// Load the symbols of the file at `path`, unless they already are
+    fn load(&mut self, path: &str) {
+        if self.modules.contains_key(path) {
+            return;
+        }
+
+        let path = std::path::Path::new(path);
+        let headers = lines::readelf(&["-W", "-l"], path).ok();
+        let module = headers.zip(SymbolTable::from_elf(path).ok())
+            .map(|(headers, mut table)| {
+                if self.raw_names {
+                    table.raw_names();
+                }
+                let lines = self.lines.then(|| {
+                    lines::readelf(&["-W", "--debug-dump=decodedline"], path)
+                        .ok().map(|x| LineTable::parse(&headers, &x))
+                }).flatten();
+                Arc::new(Module {
+                    base: lines::image_base(&headers),
+                    table,
+                    lines,
+                })
+            });
+        self.modules.insert(path.to_string_lossy().into(), module);
+    }
+
+    /// Get the module of `addr`, and the offset of `addr` from where the
+    /// start of its file is loaded
+    fn module(&self, addr: u64) -> Option<(&Module, u64)> {
+        let (path, offset) = self.space.module_offset(addr)?;
+        Some((self.modules.get(&path)?.as_deref()?, offset))
+    }
+
+    /// Get the source line of the instruction at `addr`, if line tables are
+    /// loaded
+    pub fn line(&self, addr: u64) -> Option<&SourceLine> {
+        let (module, offset) = self.module(addr)?;
+        module.lines.as_ref()?.line_at_offset(offset)
+    }
+}
+
+impl Resolver for ModuleSymbols {
+    fn name(&self) -> &Arc<str> {
+        &self.name
+    }
+
+    fn resolve(&self, addr: u64) -> Option<Resolved> {
+        let (module, offset) = self.module(addr)?;
+        let mut ret = module.table.resolve(module.base.wrapping_add(offset))?;
+
+        // Slide the symbol to where the file is loaded
+        ret.symbol.addr = ret.symbol.addr.wrapping_sub(module.base)
+            .wrapping_add(addr - offset);
+        Some(ret)
+    }
+}
+
+#[test]
+fn module_symbols() {
+    let mut symbols = ModuleSymbols::new();
+    let table = SymbolTable::from_nm("elf:/bin/app",
+        "0000000000401000 0000000000000040 T main\n\
+         0000000000401040 0000000000000020 T helper\n").unwrap();
+    symbols.insert("/bin/app", 0x400000, table, None);
+
+    // Mapped somewhere else than it's linked at
+    let segments = [(0x555000, 0, false), (0x556000, 0x1000, true)];
+    for (base, offset, exec) in segments {
+        symbols.event(&Event::Mmap {
+            base, len: 0x1000, anon: false, read: true, write: false, exec,
+            path: "/bin/app".into(), offset,
+        });
+    }
+
+    let resolved = symbols.resolve(0x556048).unwrap();
+    assert_eq!((&*resolved.symbol.name, resolved.symbol.addr),
+        ("helper", 0x556040));
+    assert_eq!(resolved.to_string(), "helper+0x8 [elf:/bin/app]");
+    assert!(symbols.resolve(0x100).is_none());
+    assert!(symbols.line(0x556048).is_none());
+
+    // Gone once unmapped
+    symbols.event(&Event::Munmap { base: 0x556000, len: 0x1000 });
+    assert!(symbols.resolve(0x556048).is_none());
+}
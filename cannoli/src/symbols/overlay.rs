@@ -0,0 +1,210 @@
+//! Overlays labelling address ranges by component
+//!
+//! Symbols say which function an address is in, but reports are often more
+//! useful organized by what the code is: the crypto library, the parser, a
+//! vendored third-party blob. An [`Overlay`] maps address ranges to labels
+//! and colors, loaded from TOML files like this one:
+//!
+//! ```toml
+//! [[region]]
+//! module = "libcrypto"
+//! start  = 0x0
+//! end    = 0x400000
+//! label  = "crypto"
+//! color  = "red"
+//!
+//! [[region]]
+//! module = "server"
+//! start  = 0x12000
+//! end    = 0x13800
+//! label  = "parser"
+//! color  = "#4080ff"
+//! ```
+//!
+//! Ranges of a `module` are offsets from its base (see
+//! [`crate::address_space::AddressSpace::module_offset`]), so they survive
+//! ASLR, and modules are named as in [`crate::control::is_module`]. Ranges
+//! without a module are absolute addresses, for code outside of files.
+//! `end` is exclusive. When ranges overlap the smallest one wins, so a
+//! function can be labelled inside of a labelled library, and of equal ranges
+//! the one loaded last wins.
+//!
+//! Colors are names or `#rrggbb`, as understood by Graphviz, see
+//! [`Region::ansi`] for terminals.
+
+#[cfg(feature = "client")]
+use std::io;
+#[cfg(feature = "client")]
+use std::path::Path;
+use serde::Deserialize;
+use crate::address_space::AddressSpace;
+use crate::control::is_module;
+
+/// A labelled address range
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Region {
+    /// Module the range is in, `None` for absolute addresses
+    #[serde(default)]
+    pub module: Option<String>,
+
+    /// Start of the range
+    pub start: u64,
+
+    /// End of the range, exclusive
+    pub end: u64,
+
+    /// Label of the code in the range, eg. `crypto`
+    pub label: String,
+
+    /// Color the range is shown with
+    #[serde(default)]
+    pub color: Option<String>,
+}
+
+impl Region {
+    /// Get the escape sequence setting the foreground color of a terminal to
+    /// the color of the region, for the basic color names and `#rrggbb`
+    pub fn ansi(&self) -> Option<String> {
+        let color = self.color.as_deref()?;
+        if let Some(hex) = color.strip_prefix('#') {
+            let rgb = u32::from_str_radix(hex, 16).ok()
+                .filter(|_| hex.len() == 6)?;
+            return Some(format!("\x1b[38;2;{};{};{}m", rgb >> 16,
+                (rgb >> 8) & 0xff, rgb & 0xff));
+        }
+
+        let code = match color {
+            "black"   => 30,
+            "red"     => 31,
+            "green"   => 32,
+            "yellow"  => 33,
+            "blue"    => 34,
+            "magenta" => 35,
+            "cyan"    => 36,
+            "white"   => 37,
+            "gray" | "grey" => 90,
+            _ => return None,
+        };
+        Some(format!("\x1b[{code}m"))
+    }
+}
+
+/// Layout of an overlay file
+#[cfg(feature = "client")]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct File {
+    /// Regions of the file
+    #[serde(default)]
+    region: Vec<Region>,
+}
+
+/// Labelled address ranges, see the module documentation
+#[derive(Clone, Debug, Default)]
+pub struct Overlay {
+    /// Every region, in the order they were loaded
+    regions: Vec<Region>,
+}
+
+impl Overlay {
+    /// Create a new, empty, overlay
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the regions of the overlay file `contents`
+    #[cfg(feature = "client")]
+    pub fn parse(&mut self, contents: &str) -> Result<(), toml::de::Error> {
+        let file: File = toml::from_str(contents)?;
+        self.regions.extend(file.region);
+        Ok(())
+    }
+
+    /// Add the regions of the overlay file at `path`
+    #[cfg(feature = "client")]
+    pub fn load(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        let contents = std::fs::read_to_string(path)?;
+        self.parse(&contents)
+            .map_err(|x| io::Error::new(io::ErrorKind::InvalidData, x))
+    }
+
+    /// Add `region`
+    pub fn add(&mut self, region: Region) {
+        self.regions.push(region);
+    }
+
+    /// Check if the overlay has no regions
+    pub fn is_empty(&self) -> bool {
+        self.regions.is_empty()
+    }
+
+    /// Get every region
+    pub fn regions(&self) -> &[Region] {
+        &self.regions
+    }
+
+    /// Find the region of `offset` in the file at `module`, or of the
+    /// absolute address `offset` if `module` is `None`
+    pub fn lookup(&self, module: Option<&str>, offset: u64)
+            -> Option<&Region> {
+        self.regions.iter().rev()
+            .filter(|x| (x.start..x.end).contains(&offset))
+            .filter(|x| match (module, &x.module) {
+                (Some(path), Some(name)) => is_module(path, name),
+                (None, None) => true,
+                _ => false,
+            })
+            .min_by_key(|x| x.end - x.start)
+    }
+
+    /// Find the region of `addr` in a process with the mappings of `space`
+    pub fn resolve(&self, space: &AddressSpace, addr: u64)
+            -> Option<&Region> {
+        match space.module_offset(addr) {
+            Some((module, offset)) => self.lookup(Some(&module), offset),
+            None => self.lookup(None, addr),
+        }
+    }
+}
+
+#[test]
+#[cfg(feature = "client")]
+fn overlay_lookup() {
+    let mut overlay = Overlay::new();
+    overlay.parse(r##"
+        [[region]]
+        module = "libcrypto"
+        start  = 0x0
+        end    = 0x10000
+        label  = "crypto"
+        color  = "red"
+
+        [[region]]
+        module = "libcrypto"
+        start  = 0x2000
+        end    = 0x3000
+        label  = "aes"
+        color  = "#ff8000"
+
+        [[region]]
+        start = 0x7f0000000000
+        end   = 0x7f0000001000
+        label = "jit"
+    "##).unwrap();
+
+    let lookup = |module, offset| {
+        overlay.lookup(module, offset).map(|x| &*x.label)
+    };
+    assert_eq!(lookup(Some("/usr/lib/libcrypto.so.3"), 0x100), Some("crypto"));
+    assert_eq!(lookup(Some("/usr/lib/libcrypto.so.3"), 0x2800), Some("aes"));
+    assert_eq!(lookup(Some("/usr/lib/libcrypto.so.3"), 0x10000), None);
+    assert_eq!(lookup(Some("/usr/bin/server"), 0x100), None);
+    assert_eq!(lookup(None, 0x7f0000000800), Some("jit"));
+    assert_eq!(lookup(None, 0x100), None);
+
+    let aes = overlay.lookup(Some("libcrypto.so.3"), 0x2000).unwrap();
+    assert_eq!(aes.ansi().as_deref(), Some("\x1b[38;2;255;128;0m"));
+    assert_eq!(overlay.regions()[0].ansi().as_deref(), Some("\x1b[31m"));
+    assert!(overlay.parse("[[region]]\nstart = 0\n").is_err());
+}
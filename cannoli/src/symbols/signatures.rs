@@ -0,0 +1,883 @@
+//! Identification of libc functions in statically linked binaries
+//!
+//! Typical embedded userspaces (BusyBox, musl or uClibc toolchains) link
+//! everything statically and strip the result, so there is no `mmap()` of a
+//! libc to find symbols in, just one huge text section. The functions most
+//! analyses care about (`read`, `write`, `open`, `mmap`, ...) are thin
+//! wrappers around a single system call though, and the system call number
+//! is loaded into a register by an immediate right before the system call
+//! instruction.
+//!
+//! [`Signatures`] scans code for these system call sites, and names the
+//! function containing a site after its system call when that is the only
+//! system call the function makes. Function entries have to come from
+//! elsewhere, typically [`crate::analysis::functions`]. The result is a
+//! [`SymbolTable`] named `signatures`, to be added to a
+//! [`crate::symbols::ResolverChain`] below any real symbols.
+//!
+//! Only ARM (not Thumb), AArch64, MIPS (o32), RISC-V and x86 are supported.
+//! A system call number which is computed rather than loaded by an immediate
+//! is not found, neither are wrappers which share their system call
+//! instruction with other wrappers.
+
+use std::collections::BTreeMap;
+use crate::Architecture;
+use crate::address_space::Mapping;
+use crate::symbols::{Symbol, SymbolTable};
+
+/// Names of system calls on x86-64
+const SYSCALLS_X86_64: &[(u64, &str)] = &[
+    (0, "read"), (1, "write"), (2, "open"), (3, "close"), (4, "stat"),
+    (5, "fstat"), (8, "lseek"), (9, "mmap"), (10, "mprotect"),
+    (11, "munmap"), (12, "brk"), (13, "rt_sigaction"), (16, "ioctl"),
+    (22, "pipe"), (35, "nanosleep"), (39, "getpid"), (41, "socket"),
+    (42, "connect"), (43, "accept"), (44, "sendto"), (45, "recvfrom"),
+    (49, "bind"), (50, "listen"), (56, "clone"), (57, "fork"),
+    (59, "execve"), (60, "exit"), (61, "wait4"), (62, "kill"),
+    (63, "uname"), (79, "getcwd"), (80, "chdir"), (87, "unlink"),
+    (96, "gettimeofday"), (102, "getuid"), (201, "time"),
+    (228, "clock_gettime"), (231, "exit_group"), (257, "openat"),
+    (318, "getrandom"),
+];
+
+/// Names of system calls on i386, and on 32-bit ARM (EABI) where they agree
+/// with i386
+const SYSCALLS_I386: &[(u64, &str)] = &[
+    (1, "exit"), (2, "fork"), (3, "read"), (4, "write"), (5, "open"),
+    (6, "close"), (10, "unlink"), (11, "execve"), (12, "chdir"),
+    (19, "lseek"), (20, "getpid"), (24, "getuid"), (37, "kill"),
+    (42, "pipe"), (45, "brk"), (54, "ioctl"), (78, "gettimeofday"),
+    (91, "munmap"), (106, "stat"), (108, "fstat"), (114, "wait4"),
+    (120, "clone"), (122, "uname"), (125, "mprotect"), (162, "nanosleep"),
+    (174, "rt_sigaction"), (183, "getcwd"), (192, "mmap2"),
+    (403, "clock_gettime64"),
+];
+
+/// Names of system calls only on i386
+const SYSCALLS_I386_ONLY: &[(u64, &str)] = &[
+    (13, "time"), (90, "mmap"), (252, "exit_group"),
+    (265, "clock_gettime"), (295, "openat"), (355, "getrandom"),
+    (359, "socket"), (361, "bind"), (362, "connect"), (363, "listen"),
+    (369, "sendto"), (371, "recvfrom"),
+];
+
+/// Names of system calls only on 32-bit ARM (EABI)
+const SYSCALLS_ARM_ONLY: &[(u64, &str)] = &[
+    (248, "exit_group"), (263, "clock_gettime"), (281, "socket"),
+    (282, "bind"), (283, "connect"), (284, "listen"), (285, "accept"),
+    (290, "sendto"), (292, "recvfrom"), (322, "openat"),
+    (384, "getrandom"),
+];
+
+/// Names of system calls in the generic table, used by AArch64 and RISC-V
+const SYSCALLS_GENERIC: &[(u64, &str)] = &[
+    (17, "getcwd"), (29, "ioctl"), (35, "unlinkat"), (49, "chdir"),
+    (56, "openat"), (57, "close"), (59, "pipe2"), (62, "lseek"),
+    (63, "read"), (64, "write"), (80, "fstat"), (93, "exit"),
+    (94, "exit_group"), (101, "nanosleep"), (113, "clock_gettime"),
+    (129, "kill"),
+    (134, "rt_sigaction"), (160, "uname"), (169, "gettimeofday"),
+    (172, "getpid"), (174, "getuid"), (198, "socket"), (200, "bind"),
+    (201, "listen"), (202, "accept"), (203, "connect"), (206, "sendto"),
+    (207, "recvfrom"), (214, "brk"), (215, "munmap"), (220, "clone"),
+    (221, "execve"), (222, "mmap"), (226, "mprotect"), (260, "wait4"),
+    (278, "getrandom"), (403, "clock_gettime64"),
+];
+
+/// Names of system calls on MIPS (o32)
+const SYSCALLS_MIPS: &[(u64, &str)] = &[
+    (4001, "exit"), (4002, "fork"), (4003, "read"), (4004, "write"),
+    (4005, "open"), (4006, "close"), (4010, "unlink"), (4011, "execve"),
+    (4012, "chdir"), (4013, "time"), (4019, "lseek"), (4020, "getpid"),
+    (4024, "getuid"), (4037, "kill"), (4042, "pipe"), (4045, "brk"),
+    (4054, "ioctl"),
+    (4078, "gettimeofday"), (4090, "mmap"), (4091, "munmap"),
+    (4106, "stat"), (4108, "fstat"), (4114, "wait4"), (4120, "clone"),
+    (4122, "uname"), (4125, "mprotect"), (4166, "nanosleep"),
+    (4168, "accept"), (4169, "bind"), (4170, "connect"), (4174, "listen"),
+    (4176, "recvfrom"), (4180, "sendto"), (4183, "socket"),
+    (4194, "rt_sigaction"), (4203, "getcwd"), (4210, "mmap2"),
+    (4246, "exit_group"), (4263, "clock_gettime"), (4288, "openat"),
+    (4353, "getrandom"), (4403, "clock_gettime64"),
+];
+
+/// System calls whose libc wrapper has a different name
+const LIBC_NAMES: &[(&str, &str)] = &[
+    ("exit_group",   "_exit"),
+    ("mmap2",        "mmap"),
+    ("rt_sigaction", "sigaction"),
+];
+
+/// Kind of a control transfer which links, see [`Signatures::call_kind`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CallKind {
+    /// A call, which leaves a return address
+    Call,
+
+    /// A return to a return address
+    Return,
+}
+
+/// The right-hand operand of a [`Comparison`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CmpOperand {
+    /// A general purpose register, by its index in QEMU's order
+    Reg(usize),
+
+    /// An immediate, truncated to the size of the comparison
+    Imm(u64),
+}
+
+/// A comparison of a register with a register or an immediate, see
+/// [`Signatures::comparison`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Comparison {
+    /// Left-hand operand, a general purpose register by its index in QEMU's
+    /// order
+    pub lhs: usize,
+
+    /// Right-hand operand
+    pub rhs: CmpOperand,
+
+    /// Size of the operands in bytes, the low bytes of the registers
+    pub sz: u8,
+}
+
+/// A system call instruction with a known system call number
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SyscallSite {
+    /// Address of the system call instruction
+    pub addr: u64,
+
+    /// System call number
+    pub nr: u64,
+
+    /// Name of the system call, if it's one we know
+    pub name: Option<&'static str>,
+}
+
+/// Scans code for system call sites and names the wrappers around them, see
+/// the module documentation
+#[derive(Clone, Debug)]
+pub struct Signatures {
+    /// Architecture of the code
+    arch: Architecture,
+
+    /// Set if instructions are big endian
+    big_endian: bool,
+
+    /// Maximum distance in bytes from a function entry to a system call for
+    /// the function to be considered a wrapper of that system call
+    pub max_wrapper: u64,
+
+    /// Number of instructions searched back from a system call instruction
+    /// for the one which loads the system call number
+    pub window: u64,
+}
+
+impl Signatures {
+    /// Create a scanner for code of `arch`, `None` if the architecture isn't
+    /// supported
+    pub fn new(arch: Architecture, big_endian: bool) -> Option<Self> {
+        match arch {
+            Architecture::X86_64 | Architecture::I386 | Architecture::I686 |
+            Architecture::Aarch64 | Architecture::Aarch64be |
+            Architecture::Armv5tel | Architecture::Armv5teb |
+            Architecture::Mips | Architecture::Riscv32 |
+            Architecture::Riscv64 => {}
+            _ => return None,
+        }
+
+        Some(Self { arch, big_endian, max_wrapper: 256, window: 8 })
+    }
+
+    /// Get the name of system call `nr`
+    pub fn syscall_name(&self, nr: u64) -> Option<&'static str> {
+        let tables: &[&[(u64, &str)]] = match self.arch {
+            Architecture::X86_64 => &[SYSCALLS_X86_64],
+            Architecture::I386 | Architecture::I686 =>
+                &[SYSCALLS_I386, SYSCALLS_I386_ONLY],
+            Architecture::Armv5tel | Architecture::Armv5teb =>
+                &[SYSCALLS_I386, SYSCALLS_ARM_ONLY],
+            Architecture::Mips => &[SYSCALLS_MIPS],
+            _ => &[SYSCALLS_GENERIC],
+        };
+        tables.iter().flat_map(|x| x.iter())
+            .find(|(x, _)| *x == nr)
+            .map(|(_, name)| *name)
+    }
+
+    /// Read the 32-bit instruction at `off` in `code`
+    fn word(&self, code: &[u8], off: usize) -> Option<u32> {
+        let bytes = code.get(off..off.checked_add(4)?)?.try_into().ok()?;
+
+        // AArch64 instructions are little endian regardless of data
+        if self.big_endian && self.arch != Architecture::Aarch64be {
+            Some(u32::from_be_bytes(bytes))
+        } else {
+            Some(u32::from_le_bytes(bytes))
+        }
+    }
+
+    /// Get the alignment of instructions, the step system call sites are
+    /// searched with
+    fn step(&self) -> usize {
+        match self.arch {
+            Architecture::X86_64 | Architecture::I386 |
+                Architecture::I686 => 1,
+            Architecture::Riscv32 | Architecture::Riscv64 => 2,
+            _ => 4,
+        }
+    }
+
+    /// Check if there's a system call instruction at `off`
+    fn is_syscall(&self, code: &[u8], off: usize) -> bool {
+        match self.arch {
+            Architecture::X86_64 =>
+                code.get(off..off + 2) == Some(&[0x0f, 0x05]),
+            Architecture::I386 | Architecture::I686 =>
+                code.get(off..off + 2) == Some(&[0xcd, 0x80]),
+            Architecture::Aarch64 | Architecture::Aarch64be =>
+                self.word(code, off) == Some(0xd400_0001),
+            Architecture::Armv5tel | Architecture::Armv5teb =>
+                self.word(code, off)
+                    .is_some_and(|x| x & 0x0fff_ffff == 0x0f00_0000),
+            Architecture::Mips => self.word(code, off)
+                .is_some_and(|x| x & 0xfc00_003f == 0x0000_000c),
+            _ => self.word(code, off) == Some(0x0000_0073),
+        }
+    }
+
+    /// Get the length of the instruction at the start of `code` if it's a
+    /// system call instruction
+    pub fn syscall_len(&self, code: &[u8]) -> Option<u64> {
+        self.is_syscall(code, 0).then_some(match self.arch {
+            Architecture::X86_64 | Architecture::I386 |
+                Architecture::I686 => 2,
+            _ => 4,
+        })
+    }
+
+    /// Get the length of the instruction at the start of `code` if execution
+    /// may continue with the instruction after it, like it does when a
+    /// conditional branch isn't taken. Fixed size instructions always have
+    /// their size. x86 instructions are only decoded if they are conditional
+    /// branches or system calls, jumps, calls and returns never continue
+    /// with the next instruction
+    pub fn fallthrough_len(&self, code: &[u8]) -> Option<u64> {
+        match self.arch {
+            Architecture::X86_64 | Architecture::I386 |
+                    Architecture::I686 => {
+                // Skip prefixes, including REX on x86_64
+                let long = self.arch == Architecture::X86_64;
+                let prefixes = code.iter().take(14).take_while(|&&x| {
+                    matches!(x, 0x26 | 0x2e | 0x36 | 0x3e | 0x64 | 0x65 |
+                        0x66 | 0x67 | 0xf0 | 0xf2 | 0xf3) ||
+                        (long && x & 0xf0 == 0x40)
+                }).count();
+                let short = !long && code[..prefixes].contains(&0x66);
+
+                let len = match code.get(prefixes..)? {
+                    // jcc rel8, loop, jecxz
+                    [0x70..=0x7f, ..] | [0xe0..=0xe3, ..] => 2,
+
+                    // jcc rel32, or rel16 with an operand size prefix
+                    [0x0f, 0x80..=0x8f, ..] => if short { 4 } else { 6 },
+
+                    // syscall, sysenter, int imm8
+                    [0x0f, 0x05 | 0x34, ..] | [0xcd, ..] => 2,
+                    _ => return None,
+                };
+                Some((prefixes + len) as u64)
+            }
+
+            // Compressed instructions don't have the low two bits set
+            Architecture::Riscv32 | Architecture::Riscv64 =>
+                Some(if *code.first()? & 3 == 3 { 4 } else { 2 }),
+            _ => Some(4),
+        }
+    }
+
+    /// Get the number of instructions after a branch which execute before
+    /// it goes anywhere, one on MIPS
+    pub fn delay_slots(&self) -> usize {
+        (self.arch == Architecture::Mips) as usize
+    }
+
+    /// Get the successors of the instruction at the start of `code`, which
+    /// is at `pc`, if it's a conditional branch with a direct target: the
+    /// target it goes to when taken, and the address it continues at when
+    /// not. On MIPS that's after the delay slot. Branches which are always
+    /// taken but encoded as conditional ones (eg. `beq zero, zero` on MIPS)
+    /// count as conditional
+    pub fn branch_successors(&self, code: &[u8], pc: u64)
+            -> Option<(u64, u64)> {
+        let rel = |len: u64, off: i64| {
+            pc.wrapping_add(len).wrapping_add(off as u64)
+        };
+
+        match self.arch {
+            Architecture::X86_64 | Architecture::I386 |
+                    Architecture::I686 => {
+                let len = self.fallthrough_len(code)?;
+                let insn = code.get(..len as usize)?;
+                let opcode = insn.iter().position(|&x| {
+                    matches!(x, 0x70..=0x7f | 0xe0..=0xe3 | 0x0f)
+                })?;
+                let off = match &insn[opcode..] {
+                    [0x70..=0x7f | 0xe0..=0xe3, off] => *off as i8 as i64,
+                    [0x0f, 0x80..=0x8f, off @ ..] if off.len() == 2 =>
+                        i16::from_le_bytes(off.try_into().ok()?) as i64,
+                    [0x0f, 0x80..=0x8f, off @ ..] if off.len() == 4 =>
+                        i32::from_le_bytes(off.try_into().ok()?) as i64,
+                    _ => return None,
+                };
+
+                let mask = if self.arch == Architecture::X86_64 {
+                    u64::MAX
+                } else {
+                    u32::MAX as u64
+                };
+                Some((rel(len, off) & mask, pc.wrapping_add(len) & mask))
+            }
+            Architecture::Aarch64 | Architecture::Aarch64be => {
+                let insn = self.word(code, 0)?;
+                let off = if insn & 0xff00_0010 == 0x5400_0000 ||
+                        insn & 0x7e00_0000 == 0x3400_0000 {
+                    // b.cond, cbz, cbnz
+                    ((insn >> 5 & 0x7_ffff) as i32) << 13 >> 13
+                } else if insn & 0x7e00_0000 == 0x3600_0000 {
+                    // tbz, tbnz
+                    ((insn >> 5 & 0x3fff) as i32) << 18 >> 18
+                } else {
+                    return None;
+                };
+                Some((rel(0, off as i64 * 4), pc.wrapping_add(4)))
+            }
+            Architecture::Armv5tel | Architecture::Armv5teb => {
+                // b<cond>, but not `al` and the unconditional space
+                let insn = self.word(code, 0)?;
+                if insn & 0x0f00_0000 != 0x0a00_0000 || insn >> 28 >= 0xe {
+                    return None;
+                }
+                let off = ((insn & 0xff_ffff) as i32) << 8 >> 8;
+                Some((rel(8, off as i64 * 4), pc.wrapping_add(4)))
+            }
+            Architecture::Mips => {
+                // beq, bne, blez, bgtz, and bltz, bgez in REGIMM
+                let insn = self.word(code, 0)?;
+                let branch = matches!(insn >> 26, 0x04..=0x07) ||
+                    (insn >> 26 == 0x01 && matches!(insn >> 16 & 0x1f, 0 | 1));
+                if !branch {
+                    return None;
+                }
+                let off = (insn & 0xffff) as i16 as i64;
+                Some((rel(4, off * 4), pc.wrapping_add(8)))
+            }
+            Architecture::Riscv32 | Architecture::Riscv64 => {
+                if *code.first()? & 3 == 3 {
+                    // beq, bne, blt, bge, bltu, bgeu
+                    let insn = self.word(code, 0)?;
+                    if insn & 0x7f != 0x63 || matches!(insn >> 12 & 7, 2 | 3) {
+                        return None;
+                    }
+                    let off = (insn >> 31 & 1) << 12 | (insn >> 7 & 1) << 11 |
+                        (insn >> 25 & 0x3f) << 5 | (insn >> 8 & 0xf) << 1;
+                    let off = (off as i32) << 19 >> 19;
+                    Some((rel(0, off as i64), pc.wrapping_add(4)))
+                } else {
+                    // c.beqz, c.bnez
+                    let half = u16::from_le_bytes(
+                        code.get(..2)?.try_into().ok()?) as u32;
+                    if !matches!(half & 0xe003, 0xc001 | 0xe001) {
+                        return None;
+                    }
+                    let off = (half >> 12 & 1) << 8 | (half >> 5 & 3) << 6 |
+                        (half >> 2 & 1) << 5 | (half >> 10 & 3) << 3 |
+                        (half >> 3 & 3) << 1;
+                    let off = (off as i32) << 23 >> 23;
+                    Some((rel(0, off as i64), pc.wrapping_add(2)))
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Get whether the instruction at the start of `code` is a call or a
+    /// return. Direct and indirect calls count, returns are the instructions
+    /// compilers return with (eg. `bx lr` and `pop {.., pc}` on ARM, `jr ra`
+    /// on MIPS). Jumps which happen to go to the return address don't, and
+    /// neither does a tail call, which is a plain jump
+    pub fn call_kind(&self, code: &[u8]) -> Option<CallKind> {
+        let call = |x: bool| x.then_some(CallKind::Call);
+        let ret  = |x: bool| x.then_some(CallKind::Return);
+
+        match self.arch {
+            Architecture::X86_64 | Architecture::I386 |
+                    Architecture::I686 => {
+                let long = self.arch == Architecture::X86_64;
+                let prefixes = code.iter().take(14).take_while(|&&x| {
+                    matches!(x, 0x26 | 0x2e | 0x36 | 0x3e | 0x64 | 0x65 |
+                        0x66 | 0x67 | 0xf0 | 0xf2 | 0xf3) ||
+                        (long && x & 0xf0 == 0x40)
+                }).count();
+
+                match code.get(prefixes..)? {
+                    // call rel, far call ptr, call r/m, far call m
+                    [0xe8 | 0x9a, ..] => Some(CallKind::Call),
+                    [0xff, modrm, ..] => call(matches!(modrm >> 3 & 7, 2 | 3)),
+
+                    // ret, ret imm16, and their far versions
+                    [0xc2 | 0xc3 | 0xca | 0xcb, ..] => Some(CallKind::Return),
+                    _ => None,
+                }
+            }
+            Architecture::Aarch64 | Architecture::Aarch64be => {
+                let insn = self.word(code, 0)?;
+                if insn & 0xfc00_0000 == 0x9400_0000 ||
+                        insn & 0xffff_fc1f == 0xd63f_0000 ||
+                        insn & 0xfeff_f800 == 0xd63f_0800 {
+                    // bl, blr, and blr with pointer authentication
+                    Some(CallKind::Call)
+                } else {
+                    // ret, and ret with pointer authentication
+                    ret(insn & 0xffff_fc1f == 0xd65f_0000 ||
+                        insn & 0xffff_fbff == 0xd65f_0bff)
+                }
+            }
+            Architecture::Armv5tel | Architecture::Armv5teb => {
+                let insn = self.word(code, 0)?;
+                if (insn & 0x0f00_0000 == 0x0b00_0000 && insn >> 28 != 0xf) ||
+                        insn & 0xfe00_0000 == 0xfa00_0000 ||
+                        insn & 0x0fff_fff0 == 0x012f_ff30 {
+                    // bl, blx imm, blx reg
+                    Some(CallKind::Call)
+                } else {
+                    // bx lr, mov pc, lr, ldr pc, [sp], #4, and ldm sp! with
+                    // the pc in the list, what `pop {.., pc}` is
+                    ret(matches!(insn & 0x0fff_ffff,
+                            0x012f_ff1e | 0x01a0_f00e | 0x049d_f004) ||
+                        insn & 0x0fff_8000 == 0x08bd_8000)
+                }
+            }
+            Architecture::Mips => {
+                // jal, jalr, and bltzal, bgezal in REGIMM
+                let insn = self.word(code, 0)?;
+                if insn >> 26 == 0x03 || insn & 0xfc00_003f == 0x0000_0009 ||
+                        (insn >> 26 == 0x01 &&
+                            matches!(insn >> 16 & 0x1f, 0x10 | 0x11)) {
+                    Some(CallKind::Call)
+                } else {
+                    // jr ra, and jr.hb ra
+                    ret(insn & 0xffff_fbff == 0x03e0_0008)
+                }
+            }
+            Architecture::Riscv32 | Architecture::Riscv64 => {
+                // The link registers are ra and t0
+                let link = |reg: u32| matches!(reg, 1 | 5);
+                if *code.first()? & 3 == 3 {
+                    let insn = self.word(code, 0)?;
+                    let (rd, rs1) = (insn >> 7 & 0x1f, insn >> 15 & 0x1f);
+                    match insn & 0x7f {
+                        // jal
+                        0x6f => call(link(rd)),
+
+                        // jalr, which returns when only reading a link
+                        0x67 if insn >> 12 & 7 == 0 && link(rd) =>
+                            Some(CallKind::Call),
+                        0x67 if insn >> 12 & 7 == 0 => ret(rd == 0 &&
+                            link(rs1) && insn >> 20 == 0),
+                        _ => None,
+                    }
+                } else {
+                    let half = u16::from_le_bytes(
+                        code.get(..2)?.try_into().ok()?) as u32;
+                    let rs1 = half >> 7 & 0x1f;
+                    match half & 0xf07f {
+                        // c.jalr
+                        0x9002 if rs1 != 0 => Some(CallKind::Call),
+
+                        // c.jr
+                        0x8002 => ret(link(rs1)),
+
+                        // c.jal, only on RV32
+                        _ => call(half & 0xe003 == 0x2001 &&
+                            self.arch == Architecture::Riscv32),
+                    }
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Get the comparison made by the instruction at the start of `code`, if
+    /// it compares a register with a register or an immediate: `cmp` on x86
+    /// and ARM, `cmp` (`subs` to the zero register) on AArch64, `beq`, `bne`
+    /// and `slt` with its variants on MIPS, and conditional branches on
+    /// RISC-V. Comparisons with memory operands or shifted registers, and
+    /// x86's `ah` to `bh`, aren't decoded
+    pub fn comparison(&self, code: &[u8]) -> Option<Comparison> {
+        let reg = |lhs: usize, rhs: usize, sz: u8| {
+            Some(Comparison { lhs, rhs: CmpOperand::Reg(rhs), sz })
+        };
+        let imm = |lhs: usize, imm: u64, sz: u8| {
+            let imm = if sz >= 8 { imm } else { imm & ((1 << (sz * 8)) - 1) };
+            Some(Comparison { lhs, rhs: CmpOperand::Imm(imm), sz })
+        };
+
+        match self.arch {
+            Architecture::X86_64 | Architecture::I386 |
+                    Architecture::I686 => {
+                let long = self.arch == Architecture::X86_64;
+                let prefixes = code.iter().take(14).take_while(|&&x| {
+                    matches!(x, 0x26 | 0x2e | 0x36 | 0x3e | 0x64 | 0x65 |
+                        0x66 | 0x67 | 0xf0 | 0xf2 | 0xf3) ||
+                        (long && x & 0xf0 == 0x40)
+                }).count();
+                let rex = code[..prefixes].last().copied()
+                    .filter(|x| long && x & 0xf0 == 0x40).unwrap_or(0);
+                let full: u8 = if rex & 8 != 0 {
+                    8
+                } else if code[..prefixes].contains(&0x66) {
+                    2
+                } else {
+                    4
+                };
+
+                // The registers of a ModR/M byte with a register operand.
+                // Byte registers 4 to 7 are `ah` to `bh` without a REX
+                let modrm = |x: u8| (x >> 6 == 3).then_some((
+                    (x >> 3 & 7 | (rex & 4) << 1) as usize,
+                    (x & 7 | (rex & 1) << 3) as usize,
+                ));
+                let high = |x: usize, sz: u8| {
+                    sz == 1 && rex == 0 && (4..8).contains(&x)
+                };
+
+                // A sign-extended immediate of `len` bytes
+                let imm_at = |x: &[u8], len: u8| Some(match len {
+                    1 => *x.first()? as i8 as u64,
+                    2 => i16::from_le_bytes(x.get(..2)?.try_into().ok()?)
+                        as u64,
+                    _ => i32::from_le_bytes(x.get(..4)?.try_into().ok()?)
+                        as u64,
+                });
+
+                match code.get(prefixes..)? {
+                    // cmp r/m, r and cmp r, r/m
+                    [op @ 0x38..=0x3b, m, ..] => {
+                        let sz = if op & 1 == 0 { 1 } else { full };
+                        let (r, rm) = modrm(*m)?;
+                        if high(r, sz) || high(rm, sz) {
+                            return None;
+                        }
+                        if op & 2 == 0 { reg(rm, r, sz) } else { reg(r, rm, sz) }
+                    }
+
+                    // cmp al, imm8 and cmp eax, imm
+                    [0x3c, rest @ ..] => imm(0, imm_at(rest, 1)?, 1),
+                    [0x3d, rest @ ..] =>
+                        imm(0, imm_at(rest, full.min(4))?, full),
+
+                    // cmp r/m, imm
+                    [op @ (0x80 | 0x81 | 0x83), m, rest @ ..]
+                            if m >> 3 & 7 == 7 => {
+                        let (_, rm) = modrm(*m)?;
+                        let (sz, len) = match op {
+                            0x80 => (1, 1),
+                            0x81 => (full, full.min(4)),
+                            _    => (full, 1),
+                        };
+                        if high(rm, sz) {
+                            return None;
+                        }
+                        imm(rm, imm_at(rest, len)?, sz)
+                    }
+                    _ => None,
+                }
+            }
+            Architecture::Aarch64 | Architecture::Aarch64be => {
+                let insn = self.word(code, 0)?;
+                let sz = if insn >> 31 != 0 { 8 } else { 4 };
+                let rn = (insn >> 5 & 0x1f) as usize;
+                if insn & 0x7f80_001f == 0x7100_001f {
+                    // cmp rn, #imm, where rn 31 is the stack pointer
+                    let shift = if insn >> 22 & 1 != 0 { 12 } else { 0 };
+                    imm(rn, ((insn >> 10 & 0xfff) as u64) << shift, sz)
+                } else if insn & 0x7f20_fc1f == 0x6b00_001f && rn != 31 {
+                    // cmp rn, rm, where rm 31 is the zero register
+                    match (insn >> 16 & 0x1f) as usize {
+                        31 => imm(rn, 0, sz),
+                        rm => reg(rn, rm, sz),
+                    }
+                } else {
+                    None
+                }
+            }
+            Architecture::Armv5tel | Architecture::Armv5teb => {
+                let insn = self.word(code, 0)?;
+                let rn = (insn >> 16 & 0xf) as usize;
+                if insn >> 28 == 0xf || rn == 15 {
+                    return None;
+                }
+                if insn & 0x0ff0_f000 == 0x0350_0000 {
+                    // cmp rn, #imm, an 8-bit value rotated right
+                    imm(rn, (insn & 0xff).rotate_right((insn >> 8 & 0xf) * 2)
+                        as u64, 4)
+                } else if insn & 0x0ff0_fff0 == 0x0150_0000 &&
+                        insn & 0xf != 15 {
+                    // cmp rn, rm
+                    reg(rn, (insn & 0xf) as usize, 4)
+                } else {
+                    None
+                }
+            }
+            Architecture::Mips => {
+                let insn = self.word(code, 0)?;
+                let rs = (insn >> 21 & 0x1f) as usize;
+                let rt = (insn >> 16 & 0x1f) as usize;
+                match insn >> 26 {
+                    // beq and bne, but not `b`, which is `beq zero, zero`
+                    0x04 | 0x05 if rs != rt => reg(rs, rt, 4),
+
+                    // slti, sltiu
+                    0x0a | 0x0b => imm(rs, (insn & 0xffff) as i16 as u64, 4),
+
+                    // slt, sltu
+                    0x00 if matches!(insn & 0x3f, 0x2a | 0x2b) =>
+                        reg(rs, rt, 4),
+                    _ => None,
+                }
+            }
+            Architecture::Riscv32 | Architecture::Riscv64 => {
+                // beq, bne, blt, bge, bltu, bgeu
+                if *code.first()? & 3 != 3 {
+                    return None;
+                }
+                let insn = self.word(code, 0)?;
+                if insn & 0x7f != 0x63 || matches!(insn >> 12 & 7, 2 | 3) {
+                    return None;
+                }
+                let sz = if self.arch == Architecture::Riscv64 { 8 } else { 4 };
+                reg((insn >> 15 & 0x1f) as usize, (insn >> 20 & 0x1f) as usize,
+                    sz)
+            }
+            _ => None,
+        }
+    }
+
+    /// Get the system call number loaded by the instruction at `off`, if it
+    /// loads one. `len` is the number of bytes up to the system call
+    /// instruction, so variable length instructions are only decoded if
+    /// they end before it
+    fn number(&self, code: &[u8], off: usize, len: usize) -> Option<u64> {
+        match self.arch {
+            Architecture::X86_64 | Architecture::I386 |
+                    Architecture::I686 => {
+                let bytes = code.get(off..off + len)?;
+                let imm = |x: &[u8]| {
+                    Some(u32::from_le_bytes(x.get(..4)?.try_into().ok()?)
+                        as u64)
+                };
+                match bytes {
+                    // mov eax, imm32
+                    [0xb8, rest @ ..] if len >= 5 => imm(rest),
+
+                    // mov rax, imm32
+                    [0x48, 0xc7, 0xc0, rest @ ..] if len >= 7 => imm(rest),
+
+                    // xor eax, eax
+                    [0x31, 0xc0, ..] => Some(0),
+                    _ => None,
+                }
+            }
+            Architecture::Aarch64 | Architecture::Aarch64be => {
+                // movz w8/x8, #imm
+                let insn = self.word(code, off)?;
+                (insn & 0x7fe0_001f == 0x5280_0008)
+                    .then_some((insn >> 5 & 0xffff) as u64)
+            }
+            Architecture::Armv5tel | Architecture::Armv5teb => {
+                let insn = self.word(code, off)?;
+                if insn & 0x0fff_f000 == 0x03a0_7000 {
+                    // mov r7, #imm, an 8-bit value rotated right
+                    Some((insn & 0xff).rotate_right((insn >> 8 & 0xf) * 2)
+                        as u64)
+                } else if insn & 0x0ff0_f000 == 0x0300_7000 {
+                    // movw r7, #imm16
+                    Some(((insn >> 4 & 0xf000) | (insn & 0xfff)) as u64)
+                } else {
+                    None
+                }
+            }
+            Architecture::Mips => {
+                // addiu/ori v0, zero, imm
+                let insn = self.word(code, off)?;
+                matches!(insn & 0xffff_0000, 0x2402_0000 | 0x3402_0000)
+                    .then_some((insn & 0xffff) as u64)
+            }
+            _ => {
+                // addi a7, zero, imm
+                if let Some(insn) = self.word(code, off) {
+                    if insn & 0x000f_ffff == 0x0000_0893 && len >= 4 {
+                        return Some((insn as i32 >> 20) as u64);
+                    }
+                }
+
+                // c.li a7, imm
+                let half = u16::from_le_bytes(
+                    code.get(off..off + 2)?.try_into().ok()?);
+                (half & 0xef83 == 0x4881).then(|| {
+                    let imm = (half >> 2 & 0x1f) | (half >> 7 & 0x20);
+                    ((imm as i8) << 2 >> 2) as u64
+                })
+            }
+        }
+    }
+
+    /// Find the system call sites in `code`, which is mapped at `base`
+    pub fn scan(&self, code: &[u8], base: u64) -> Vec<SyscallSite> {
+        let step = self.step();
+
+        // Number of steps searched back, x86 instructions which load a
+        // number are at most 7 bytes
+        let per_insn = if step == 1 { 7 } else { 4 / step };
+        let window   = self.window as usize * per_insn;
+
+        let mut ret = Vec::new();
+        for off in (0..code.len()).step_by(step) {
+            if !self.is_syscall(code, off) {
+                continue;
+            }
+
+            // The closest load of a number wins
+            let nr = (1..=window)
+                .filter_map(|x| Some((off.checked_sub(x * step)?, x * step)))
+                .find_map(|(at, len)| self.number(code, at, len));
+            let Some(nr) = nr else { continue };
+
+            ret.push(SyscallSite {
+                addr: base + off as u64,
+                name: self.syscall_name(nr),
+                nr,
+            });
+        }
+        ret
+    }
+
+    /// Find the system call sites in a file backed `mapping` by reading its
+    /// contents from the file
+    pub fn scan_mapping(&self, mapping: &Mapping)
+            -> std::io::Result<Vec<SyscallSite>> {
+        let file  = std::fs::read(&*mapping.path)?;
+        let start = (mapping.offset as usize).min(file.len());
+        let end   = start.saturating_add(mapping.len as usize).min(file.len());
+        Ok(self.scan(&file[start..end], mapping.base))
+    }
+
+    /// Name the functions starting at `entries` which wrap a single system
+    /// call from `sites`. Functions making several different system calls,
+    /// and system calls which aren't within [`Signatures::max_wrapper`] bytes
+    /// of an entry, are left alone
+    pub fn identify(&self, sites: &[SyscallSite], entries: &[u64])
+            -> SymbolTable {
+        let mut entries = entries.to_vec();
+        entries.sort_unstable();
+
+        // System call numbers made by each function
+        let mut calls: BTreeMap<u64, Vec<&SyscallSite>> = BTreeMap::new();
+        for site in sites {
+            let idx = entries.partition_point(|x| *x <= site.addr);
+            let Some(&entry) = idx.checked_sub(1).map(|x| &entries[x])
+                else { continue };
+            if site.addr - entry <= self.max_wrapper {
+                calls.entry(entry).or_default().push(site);
+            }
+        }
+
+        let symbols = calls.into_iter().filter_map(|(entry, sites)| {
+            let name = sites[0].name?;
+            if sites.iter().any(|x| x.nr != sites[0].nr) {
+                return None;
+            }
+
+            let name = LIBC_NAMES.iter().find(|(x, _)| *x == name)
+                .map_or(name, |(_, libc)| libc);
+            Some(Symbol { name: name.into(), addr: entry, size: None,
+                mangled: None })
+        }).collect();
+
+        SymbolTable::new("signatures", symbols)
+    }
+}
+
+#[test]
+fn identify_static_wrappers() {
+    use crate::symbols::Resolver;
+
+    // Little endian MIPS, `write` and `_exit` wrappers and a function which
+    // makes two different system calls
+    let code: Vec<u8> = [
+        0x2402_0fa4, 0x0000_000c, 0x03e0_0008, 0x0000_0000, // write
+        0x2402_1096, 0x0000_000c, 0x1000_ffff, 0x0000_0000, // exit_group
+        0x2402_0fa3, 0x0000_000c, 0x2402_0fa6, 0x0000_000c, // read, close
+    ].iter().flat_map(|x: &u32| x.to_le_bytes()).collect();
+
+    let signatures = Signatures::new(Architecture::Mips, false).unwrap();
+    let sites = signatures.scan(&code, 0x40_0000);
+    assert_eq!(sites.iter().map(|x| (x.addr, x.nr)).collect::<Vec<_>>(), [
+        (0x40_0004, 4004), (0x40_0014, 4246),
+        (0x40_0024, 4003), (0x40_002c, 4006),
+    ]);
+
+    let table = signatures.identify(&sites,
+        &[0x40_0000, 0x40_0010, 0x40_0020]);
+    let names = table.symbols().iter()
+        .map(|x| (x.addr, &*x.name))
+        .collect::<Vec<_>>();
+    assert_eq!(names, [(0x40_0000, "write"), (0x40_0010, "_exit")]);
+    assert_eq!(&*table.resolve(0x40_0008).unwrap().source, "signatures");
+
+    // x86-64 `read` via `xor eax, eax`
+    let x86 = Signatures::new(Architecture::X86_64, false).unwrap();
+    let sites = x86.scan(&[0x31, 0xc0, 0x0f, 0x05, 0xc3], 0x1000);
+    assert_eq!(sites, [SyscallSite {
+        addr: 0x1002,
+        nr:   0,
+        name: Some("read"),
+    }]);
+}
+
+#[test]
+fn decode_comparisons() {
+    let cmp = |lhs, rhs, sz| Some(Comparison { lhs, rhs, sz });
+    let x86 = Signatures::new(Architecture::X86_64, false).unwrap();
+    assert_eq!(x86.comparison(&[0x48, 0x39, 0xd8]),
+        cmp(0, CmpOperand::Reg(3), 8));
+    assert_eq!(x86.comparison(&[0x41, 0x80, 0xf8, 0x2a]),
+        cmp(8, CmpOperand::Imm(0x2a), 1));
+    assert_eq!(x86.comparison(&[0x83, 0xf8, 0xff]),
+        cmp(0, CmpOperand::Imm(0xffff_ffff), 4));
+    assert_eq!(x86.comparison(&[0x66, 0x3d, 0x34, 0x12]),
+        cmp(0, CmpOperand::Imm(0x1234), 2));
+
+    // `ah`, and memory operands
+    assert_eq!(x86.comparison(&[0x38, 0xe0]), None);
+    assert_eq!(x86.comparison(&[0x39, 0x45, 0xfc]), None);
+
+    let arm = Signatures::new(Architecture::Aarch64, false).unwrap();
+    assert_eq!(arm.comparison(&0xf100_141fu32.to_le_bytes()),
+        cmp(0, CmpOperand::Imm(5), 8));
+    assert_eq!(arm.comparison(&0x6b01_001fu32.to_le_bytes()),
+        cmp(0, CmpOperand::Reg(1), 4));
+    let arm = Signatures::new(Architecture::Armv5tel, false).unwrap();
+    assert_eq!(arm.comparison(&0xe350_0c01u32.to_le_bytes()),
+        cmp(0, CmpOperand::Imm(0x100), 4));
+    let mips = Signatures::new(Architecture::Mips, true).unwrap();
+    assert_eq!(mips.comparison(&0x1088_0003u32.to_be_bytes()),
+        cmp(4, CmpOperand::Reg(8), 4));
+    assert_eq!(mips.comparison(&0x1000_0003u32.to_be_bytes()), None);
+    let riscv = Signatures::new(Architecture::Riscv64, false).unwrap();
+    assert_eq!(riscv.comparison(&0x00b5_1463u32.to_le_bytes()),
+        cmp(10, CmpOperand::Reg(11), 8));
+}
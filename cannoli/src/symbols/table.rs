@@ -0,0 +1,313 @@
+//! Sorted symbol tables loaded from map files, ELFs and inferred functions
+
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use crate::analysis::functions::FunctionInference;
+use crate::symbols::{Resolver, Resolved, Symbol};
+
+/// `nm` symbol types which don't name an address in the image
+const SKIPPED_TYPES: &[&str] = &["U", "w", "v", "a", "A", "N"];
+
+/// Prefixes of mangled names: Itanium C++ (and legacy Rust), and Rust v0
+const MANGLED_PREFIXES: &[&str] = &["_Z", "_R", "__Z"];
+
+/// Drop the `::h<hash>` suffix of a demangled legacy Rust name
+fn strip_hash(name: &str) -> &str {
+    name.rsplit_once("::h")
+        .filter(|(_, x)| x.len() == 16 && x.bytes().all(|x| {
+            x.is_ascii_hexdigit()
+        }))
+        .map_or(name, |(x, _)| x)
+}
+
+/// A table of symbols from a single source
+#[derive(Clone, Debug)]
+pub struct SymbolTable {
+    /// Name of the source, reported as the provenance of the symbols
+    name: Arc<str>,
+
+    /// Symbols, sorted by address. Symbols at the same address keep the order
+    /// they were loaded in, and the first one is used
+    symbols: Vec<Symbol>,
+}
+
+impl SymbolTable {
+    /// Create a table named `name` from `symbols`
+    pub fn new(name: impl Into<Arc<str>>, mut symbols: Vec<Symbol>) -> Self {
+        symbols.sort_by_key(|x| x.addr);
+        Self { name: name.into(), symbols }
+    }
+
+    /// Parse `nm` output, or a map file of a similar format, into a table
+    /// named `name`. Each line is one of:
+    ///
+    /// ```text
+    /// <addr> <name>
+    /// <addr> <type> <name>
+    /// <addr> <size> <type> <name>    (nm -S)
+    /// ```
+    ///
+    /// with the address and size in hex. Undefined symbols are skipped
+    pub fn from_nm(name: impl Into<Arc<str>>, text: &str)
+            -> std::io::Result<Self> {
+        let hex = |x: &str| {
+            u64::from_str_radix(x.trim_start_matches("0x"), 16).ok()
+        };
+
+        let mut symbols = Vec::new();
+        for line in text.lines() {
+            // Undefined symbols have no address
+            let line = line.trim();
+            let Some((addr, rest)) = line.split_once(char::is_whitespace)
+                else { continue };
+            let Some(addr) = hex(addr) else { continue };
+
+            // Optional size, only present if followed by a type
+            let mut size = None;
+            let mut rest = rest.trim_start();
+            if let Some((maybe_size, after)) =
+                    rest.split_once(char::is_whitespace) {
+                let after = after.trim_start();
+                let is_type = after.split_once(char::is_whitespace)
+                    .is_some_and(|(x, _)| x.len() == 1);
+                if is_type && maybe_size.len() > 1 {
+                    if let Some(x) = hex(maybe_size) {
+                        size = Some(x);
+                        rest = after;
+                    }
+                }
+            }
+
+            // Optional single character type
+            if let Some((kind, after)) = rest.split_once(char::is_whitespace) {
+                if kind.len() == 1 {
+                    if SKIPPED_TYPES.contains(&kind) {
+                        continue;
+                    }
+                    rest = after.trim_start();
+                }
+            }
+
+            if rest.is_empty() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("Malformed symbol line `{line}`")));
+            }
+
+            symbols.push(Symbol { name: rest.into(), addr, size,
+                mangled: None });
+        }
+
+        Ok(Self::new(name, symbols))
+    }
+
+    /// Load a map file in the format of [`SymbolTable::from_nm`], the table
+    /// is named `map:<path>`
+    pub fn from_map_file(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let path = path.as_ref();
+        Self::from_nm(format!("map:{}", path.display()),
+            &std::fs::read_to_string(path)?)
+    }
+
+    /// Load the symbols of the ELF at `path` using `nm`, the table is named
+    /// `elf:<path>`. Both the static and the dynamic symbol tables are used,
+    /// so stripped binaries still get their exported symbols
+    pub fn from_elf(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let path = path.as_ref();
+
+        let mut text   = String::new();
+        let mut errors = String::new();
+        for dynamic in [false, true] {
+            let mut command = Command::new("nm");
+            command.args(["-S", "--defined-only"]);
+            if dynamic {
+                command.arg("-D");
+            }
+            let output = command.arg(path).output()?;
+
+            // `nm` fails for a stripped binary, the dynamic symbols may still
+            // be there
+            if output.status.success() {
+                text += &String::from_utf8_lossy(&output.stdout);
+            } else {
+                errors += &String::from_utf8_lossy(&output.stderr);
+            }
+        }
+
+        if text.is_empty() && !errors.is_empty() {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData,
+                format!("nm failed for {}: {}", path.display(),
+                    errors.trim())));
+        }
+
+        let mut ret = Self::from_nm(format!("elf:{}", path.display()),
+            &text)?;
+
+        // Symbols are in both tables when the binary isn't stripped
+        let mut seen = HashSet::new();
+        ret.symbols.retain(|x| seen.insert((x.addr, x.name.clone())));
+
+        // Names stay mangled without `c++filt`
+        let _ = ret.demangle();
+        Ok(ret)
+    }
+
+    /// Demangle the C++ (Itanium) and Rust names of the table with
+    /// `c++filt`, keeping the mangled names in [`Symbol::mangled`]. The hash
+    /// of legacy Rust names is dropped. Names which aren't mangled, or which
+    /// `c++filt` doesn't know, stay as they are. [`SymbolTable::from_elf`]
+    /// already demangles
+    pub fn demangle(&mut self) -> std::io::Result<()> {
+        let mangled = self.symbols.iter_mut().filter(|x| {
+            x.mangled.is_none() &&
+                MANGLED_PREFIXES.iter().any(|y| x.name.starts_with(y))
+        }).collect::<Vec<_>>();
+        if mangled.is_empty() {
+            return Ok(());
+        }
+
+        // Names are written from a thread, so `c++filt` can't block on a
+        // full stdout while we block on a full stdin
+        let input = mangled.iter().map(|x| format!("{}\n", x.name))
+            .collect::<String>();
+        let mut child = Command::new("c++filt")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let mut stdin = child.stdin.take().unwrap();
+        let writer = std::thread::spawn(move || {
+            stdin.write_all(input.as_bytes())
+        });
+        let output = child.wait_with_output()?;
+        writer.join().unwrap()?;
+        if !output.status.success() {
+            return Err(std::io::Error::other("c++filt failed"));
+        }
+
+        // One line per name
+        let text = String::from_utf8_lossy(&output.stdout);
+        for (symbol, line) in mangled.into_iter().zip(text.lines()) {
+            let line = strip_hash(line);
+            if line != &*symbol.name {
+                symbol.mangled = Some(std::mem::replace(&mut symbol.name,
+                    line.into()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Go back to the mangled names, for output which has to match the
+    /// binary. Undoes [`SymbolTable::demangle`]
+    pub fn raw_names(&mut self) {
+        for symbol in &mut self.symbols {
+            if let Some(mangled) = symbol.mangled.take() {
+                symbol.name = mangled;
+            }
+        }
+    }
+
+    /// Create a table named `inferred` from the functions discovered by
+    /// `inference`, naming each function `sub_<entry>`
+    pub fn from_inferred(inference: &FunctionInference) -> Self {
+        Self::new("inferred", inference.functions().map(|func| Symbol {
+            name: format!("sub_{:x}", func.entry).into(),
+            addr: func.entry,
+            size: Some(func.last_pc - func.entry + 1),
+            mangled: None,
+        }).collect())
+    }
+
+    /// Shift every symbol by `bias`, for images which are loaded somewhere
+    /// other than the addresses in their symbols (eg. PIEs and shared
+    /// libraries)
+    pub fn rebase(mut self, bias: u64) -> Self {
+        for symbol in &mut self.symbols {
+            symbol.addr = symbol.addr.wrapping_add(bias);
+        }
+        self.symbols.sort_by_key(|x| x.addr);
+        self
+    }
+
+    /// Get the symbols in the table, sorted by address
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+}
+
+impl Resolver for SymbolTable {
+    fn name(&self) -> &Arc<str> {
+        &self.name
+    }
+
+    fn resolve(&self, addr: u64) -> Option<Resolved> {
+        // Find the highest address at or below `addr`, then the first symbol
+        // at that address
+        let idx = self.symbols.partition_point(|x| x.addr <= addr);
+        let base = self.symbols.get(idx.checked_sub(1)?)?.addr;
+        let symbol = &self.symbols[self.symbols.partition_point(|x| {
+            x.addr < base
+        })];
+
+        let offset = addr - symbol.addr;
+        Some(Resolved {
+            symbol: symbol.clone(),
+            exact:  symbol.size.is_some_and(|x| offset < x),
+            source: self.name.clone(),
+            offset,
+        })
+    }
+}
+
+#[test]
+fn parse_nm() {
+    let table = SymbolTable::from_nm("test", "\
+                         U printf
+        0000000000002000 0000000000000010 T main
+        0000000000001000 t _start
+        0000000000003000 data_table
+    ").unwrap();
+
+    let names = table.symbols().iter()
+        .map(|x| (x.addr, &*x.name, x.size))
+        .collect::<Vec<_>>();
+    assert_eq!(names, [
+        (0x1000, "_start",     None),
+        (0x2000, "main",       Some(0x10)),
+        (0x3000, "data_table", None),
+    ]);
+
+    let resolved = table.resolve(0x2010).unwrap();
+    assert_eq!((&*resolved.symbol.name, resolved.offset, resolved.exact),
+        ("main", 0x10, false));
+    assert!(table.resolve(0xfff).is_none());
+}
+
+#[test]
+#[ignore = "needs c++filt, run with --ignored"]
+fn demangle_names() {
+    let mut table = SymbolTable::from_nm("test", "\
+        0000000000001000 T main
+        0000000000002000 T _ZN3foo3barEv
+        0000000000003000 T _ZN4core3ptr13drop_in_place17h0123456789abcdefE
+        0000000000004000 T _RNvNtCs1234_7mycrate5parse6header
+    ").unwrap();
+
+    table.demangle().unwrap();
+    let names = table.symbols().iter()
+        .map(|x| (&*x.name, &**x.raw_name()))
+        .collect::<Vec<_>>();
+    assert_eq!(names[..3], [
+        ("main", "main"),
+        ("foo::bar()", "_ZN3foo3barEv"),
+        ("core::ptr::drop_in_place",
+            "_ZN4core3ptr13drop_in_place17h0123456789abcdefE"),
+    ]);
+    assert!(names[3].0.ends_with("::parse::header"));
+
+    table.raw_names();
+    assert_eq!(&*table.symbols()[1].name, "_ZN3foo3barEv");
+    assert!(table.symbols().iter().all(|x| x.mangled.is_none()));
+}
@@ -0,0 +1,343 @@
+//! System call entries and exits, reported to
+//! [`crate::Cannoli::syscall_entry`] and [`crate::Cannoli::syscall_exit`]
+//!
+//! QEMU doesn't tell the jitter about system calls, so they are recognized
+//! by their instructions instead. With [`crate::control::Filters::syscalls`]
+//! set, the jitter checks every instruction it lifts with
+//! [`crate::symbols::signatures::Signatures::syscall_len`]. It hooks system
+//! call instructions and the instructions after them with register hooks,
+//! and both announce their system call site on every execution, right
+//! before their registers and in the same payload, so the site is always
+//! known by the time the registers are checked. The registers at the system
+//! call instruction hold the number and arguments, the registers at the
+//! instruction after it hold the return value. The same sites are how the
+//! jitter pins the results of some system calls, see [`crate::pin`].
+//!
+//! Sites are shared by every thread of a process, like the translated code
+//! is. System calls which don't return (`exit`, `execve`) have no exit,
+//! and on MIPS the error flag in `a3` is not part of the return value. Only
+//! the architectures [`crate::symbols::signatures`] supports are
+//! recognized, and on MIPS only the four register arguments of o32 are
+//! reported.
+//!
+//! `mprotect()` is reported to [`crate::Cannoli::mprotect`] once it
+//! succeeded, which takes the arguments of its entry and the return value
+//! of its exit. They are paired in the sequential phase where the trace is
+//! in order, like edges (see [`crate::edges`]), as the entry and the exit
+//! may be processed on different threads.
+
+use std::sync::{Arc, Mutex, RwLock, LazyLock};
+use std::collections::HashMap;
+use crate::{Architecture, ClientInfo};
+use crate::symbols::signatures::Signatures;
+
+/// Where a system call's number, arguments and return value are, as
+/// indices into the general purpose registers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SyscallAbi {
+    /// Register holding the system call number
+    pub nr: usize,
+
+    /// Registers holding the arguments, in order
+    pub args: &'static [usize],
+
+    /// Register holding the return value
+    pub ret: usize,
+}
+
+impl SyscallAbi {
+    /// Get the Linux system call convention of `arch`, in QEMU's order of
+    /// the general purpose registers. `None` if system calls of `arch`
+    /// aren't recognized
+    pub fn new(arch: Architecture) -> Option<Self> {
+        let (nr, args, ret): (usize, &'static [usize], usize) = match arch {
+            // rax; rdi, rsi, rdx, r10, r8, r9
+            Architecture::X86_64 => (0, &[7, 6, 2, 10, 8, 9], 0),
+
+            // eax; ebx, ecx, edx, esi, edi, ebp
+            Architecture::I386 | Architecture::I686 =>
+                (0, &[3, 1, 2, 6, 7, 5], 0),
+
+            // x8; x0-x5
+            Architecture::Aarch64 | Architecture::Aarch64be =>
+                (8, &[0, 1, 2, 3, 4, 5], 0),
+
+            // r7; r0-r5
+            Architecture::Armv5tel | Architecture::Armv5teb =>
+                (7, &[0, 1, 2, 3, 4, 5], 0),
+
+            // v0; a0-a3
+            Architecture::Mips => (2, &[4, 5, 6, 7], 2),
+
+            // a7; a0-a5
+            Architecture::Riscv32 | Architecture::Riscv64 =>
+                (17, &[10, 11, 12, 13, 14, 15], 10),
+
+            _ => return None,
+        };
+        Some(Self { nr, args, ret })
+    }
+}
+
+/// A system call site known to a process
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Site {
+    /// A system call instruction
+    Entry,
+
+    /// The instruction after the system call instruction at `syscall`
+    Return { syscall: u64 },
+}
+
+/// A system call site reached in the trace
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Call {
+    /// A system call is about to be made
+    Entry { nr: u64, args: [u64; 6] },
+
+    /// The system call at `syscall` returned
+    Exit { syscall: u64, ret: u64 },
+}
+
+/// A system call in the trace which is paired in order, see
+/// [`Protections`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Mark {
+    /// `mprotect(base, len, prot)` at the system call instruction `pc`
+    Mprotect { pc: u64, base: u64, len: u64, prot: u32 },
+
+    /// The system call at `syscall` returned `ret`
+    Exit { syscall: u64, ret: u64 },
+}
+
+/// Pairs the entries of `mprotect()` with their exits, in trace order
+#[derive(Default)]
+pub(crate) struct Protections {
+    /// Entry of the `mprotect()` which didn't return yet
+    pending: Option<Mark>,
+}
+
+impl Protections {
+    /// Add the next mark of the trace, returning the base, length and
+    /// protection of an `mprotect()` which succeeded
+    pub(crate) fn mark(&mut self, mark: &Mark) -> Option<(u64, u64, u32)> {
+        match *mark {
+            Mark::Mprotect { .. } => {
+                self.pending = Some(*mark);
+                None
+            }
+            Mark::Exit { syscall, ret } => match self.pending.take()? {
+                Mark::Mprotect { pc, base, len, prot }
+                    if pc == syscall && ret == 0 => Some((base, len, prot)),
+                _ => None,
+            },
+        }
+    }
+
+    /// Forget the pending entry, when the trace has a gap
+    pub(crate) fn reset(&mut self) {
+        self.pending = None;
+    }
+}
+
+/// System call sites of a process, announced by the jitter
+pub(crate) struct Sites {
+    /// How to find the arguments and return value, `None` if system calls
+    /// of the target aren't recognized
+    abi: Option<SyscallAbi>,
+
+    /// Size of a general purpose register in bytes
+    width: usize,
+
+    /// Known sites, keyed by PC
+    sites: RwLock<HashMap<u64, Site>>,
+
+    /// Names system calls, to recognize those which exit
+    names: Option<Signatures>,
+
+    /// Status of the last `exit_group`, or of the last `exit` if there was
+    /// none
+    exit_code: Mutex<Option<(i32, bool)>>,
+}
+
+/// Sites of every process with a connected thread, keyed by PID
+static SITES: LazyLock<Mutex<HashMap<i32, Arc<Sites>>>> =
+    LazyLock::new(Default::default);
+
+impl Sites {
+    /// Get the sites of the process of `ci`
+    pub(crate) fn get(ci: &ClientInfo) -> Arc<Self> {
+        SITES.lock().unwrap().entry(ci.pid).or_insert_with(|| {
+            Arc::new(Self {
+                abi:   SyscallAbi::new(ci.arch),
+                width: ci.arch.bitness() as usize / 8,
+                sites: Default::default(),
+                names: Signatures::new(ci.arch, ci.big_endian),
+                exit_code: Mutex::new(None),
+            })
+        }).clone()
+    }
+
+    /// Forget the sites of the process `pid`, once its last thread is gone
+    pub(crate) fn remove(pid: i32) {
+        SITES.lock().unwrap().remove(&pid);
+    }
+
+    /// Add the system call instruction at `pc`, followed by the instruction
+    /// at `next`
+    pub(crate) fn add(&self, pc: u64, next: u64) {
+        // Sites are announced on every execution, mostly known already
+        {
+            let sites = self.sites.read().unwrap();
+            if sites.get(&pc) == Some(&Site::Entry) &&
+                    sites.get(&next) == Some(&Site::Return { syscall: pc }) {
+                return;
+            }
+        }
+
+        let mut sites = self.sites.write().unwrap();
+        sites.insert(pc, Site::Entry);
+        sites.insert(next, Site::Return { syscall: pc });
+    }
+
+    /// Read general purpose register `index` from `regs`, which QEMU keeps
+    /// in host byte order
+    fn reg(&self, regs: &[u8], index: usize) -> Option<u64> {
+        let bytes = regs.get(index * self.width..(index + 1) * self.width)?;
+        let mut value = [0u8; 8];
+        value[..bytes.len()].copy_from_slice(bytes);
+        Some(u64::from_le_bytes(value))
+    }
+
+    /// Check if the registers `regs` at `pc` are at a system call site
+    pub(crate) fn check(&self, pc: u64, regs: &[u8]) -> Option<Call> {
+        let abi = self.abi?;
+        let site = *self.sites.read().unwrap().get(&pc)?;
+
+        Some(match site {
+            Site::Entry => {
+                let mut args = [0u64; 6];
+                for (arg, &reg) in args.iter_mut().zip(abi.args) {
+                    *arg = self.reg(regs, reg)?;
+                }
+                let nr = self.reg(regs, abi.nr)?;
+                self.check_exit(nr, args[0]);
+                Call::Entry { nr, args }
+            }
+            Site::Return { syscall } =>
+                Call::Exit { syscall, ret: self.reg(regs, abi.ret)? },
+        })
+    }
+
+    /// Get the mark of the system call site `pc` reached with `call`, for
+    /// the system calls which are paired in order
+    pub(crate) fn mark(&self, pc: u64, call: &Call) -> Option<Mark> {
+        Some(match *call {
+            Call::Entry { nr, args } => {
+                let name = self.names.as_ref()?.syscall_name(nr);
+                if name != Some("mprotect") {
+                    return None;
+                }
+                Mark::Mprotect {
+                    pc,
+                    base: args[0],
+                    len:  args[1],
+                    prot: args[2] as u32,
+                }
+            }
+            Call::Exit { syscall, ret } => Mark::Exit { syscall, ret },
+        })
+    }
+
+    /// Remember the status of system call `nr` with the first argument
+    /// `arg` if it exits
+    fn check_exit(&self, nr: u64, arg: u64) {
+        let name = self.names.as_ref().and_then(|x| x.syscall_name(nr));
+        let group = match name {
+            Some("exit_group") => true,
+            Some("exit") => false,
+            _ => return,
+        };
+        let mut code = self.exit_code.lock().unwrap();
+        if group || !code.is_some_and(|(_, group)| group) {
+            *code = Some((arg as i32, group));
+        }
+    }
+
+    /// Get the exit status of the process, if it made a system call which
+    /// exits
+    pub(crate) fn exit_code(&self) -> Option<i32> {
+        self.exit_code.lock().unwrap().map(|(code, _)| code)
+    }
+
+    /// Get the number of arguments of a system call
+    pub(crate) fn num_args(&self) -> usize {
+        self.abi.map_or(0, |x| x.args.len())
+    }
+}
+
+#[test]
+fn syscall_sites() {
+    let ci = ClientInfo {
+        uid:        0,
+        arch:       Architecture::X86_64,
+        big_endian: false,
+        ppid:       0,
+        pid:        -2,
+        tid:        -2,
+        cpu:        None,
+        pcomm:      None,
+        comm:       None,
+        exe:        None,
+        argv:       Vec::new(),
+        env:        Vec::new(),
+        cwd:        None,
+        tenant:     None,
+    };
+    let sites = Sites::get(&ci);
+    sites.add(0x1000, 0x1002);
+
+    // write(1, 0x2000, 5) returning 5
+    let mut regs = vec![0u8; 16 * 8];
+    let set = |regs: &mut Vec<u8>, index: usize, value: u64| {
+        regs[index * 8..index * 8 + 8].copy_from_slice(&value.to_le_bytes());
+    };
+    set(&mut regs, 0, 1);
+    set(&mut regs, 7, 1);
+    set(&mut regs, 6, 0x2000);
+    set(&mut regs, 2, 5);
+    assert_eq!(sites.check(0x1000, &regs),
+        Some(Call::Entry { nr: 1, args: [1, 0x2000, 5, 0, 0, 0] }));
+    set(&mut regs, 0, 5);
+    assert_eq!(sites.check(0x1002, &regs),
+        Some(Call::Exit { syscall: 0x1000, ret: 5 }));
+    assert_eq!(sites.check(0x1004, &regs), None);
+    assert_eq!(sites.num_args(), 6);
+
+    // exit(3) of a thread, then exit_group(1), which another exit of a
+    // thread doesn't override
+    assert_eq!(sites.exit_code(), None);
+    for (nr, code) in [(60, 3), (231, 1), (60, 2)] {
+        set(&mut regs, 0, nr);
+        set(&mut regs, 7, code);
+        sites.check(0x1000, &regs);
+    }
+    assert_eq!(sites.exit_code(), Some(1));
+
+    // mprotect(0x4000, 0x1000, PROT_READ | PROT_EXEC) succeeding, paired
+    // with its exit
+    let mut protections = Protections::default();
+    for (pc, value) in [(0x1000, 10), (0x1002, 0)] {
+        set(&mut regs, 0, value);
+        set(&mut regs, 7, 0x4000);
+        set(&mut regs, 6, 0x1000);
+        set(&mut regs, 2, 5);
+        let call = sites.check(pc, &regs).unwrap();
+        let changed = protections.mark(&sites.mark(pc, &call).unwrap());
+        assert_eq!(changed, (pc == 0x1002).then_some((0x4000, 0x1000, 5)));
+    }
+    assert_eq!(protections.mark(&Mark::Exit { syscall: 0x1000, ret: 0 }),
+        None);
+
+    Sites::remove(-2);
+}
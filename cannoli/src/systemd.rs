@@ -0,0 +1,183 @@
+//! Running the server as a systemd service
+//!
+//! A long-running server, like `cannoli daemon`, is best left to systemd to
+//! start, supervise and restart. Two parts of systemd's protocols are
+//! supported for that:
+//!
+//! - socket activation: systemd binds the socket from a `.socket` unit and
+//!   passes it in `LISTEN_FDS`, [`listener`] gets it to give to
+//!   [`crate::CannoliOpts::listener`]. The server then starts with the first
+//!   connection, and connections keep queueing up while it restarts
+//! - notifications: with [`crate::CannoliOpts::notify`] the server tells
+//!   systemd through `NOTIFY_SOCKET` when it's ready to accept connections
+//!   (for `Type=notify` services) and when it's stopping, and keeps the
+//!   watchdog of `WatchdogSec=` fed from its accept loop, see [`Notifier`]
+//!
+//! A server which fails (eg. an analysis panics with
+//! [`crate::PanicPolicy::Abort`]) returns an error, and `cannoli daemon`
+//! exits with a non-zero status, so `Restart=on-failure` restarts it. A
+//! server which hangs stops feeding the watchdog and is restarted as well.
+//!
+//! Both are no-ops when the variables aren't set, so the same binary can be
+//! run by hand.
+
+use std::env;
+use std::io;
+use std::net::TcpListener;
+use std::os::fd::{FromRawFd, RawFd};
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::os::linux::net::SocketAddrExt;
+use std::time::{Duration, Instant};
+
+/// First file descriptor passed by systemd, `SD_LISTEN_FDS_START`
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Check if the variable `name`, holding a PID, names our process
+fn is_ours(name: &str) -> bool {
+    env::var(name).ok().and_then(|x| x.parse::<u32>().ok())
+        .is_some_and(|x| x == std::process::id())
+}
+
+/// Get the socket passed by systemd with socket activation, `None` if we
+/// weren't socket activated. The socket has to be a single listening TCP
+/// socket (`ListenStream=` of an address in the `.socket` unit). The
+/// variables are cleared so processes we spawn don't take the socket as
+/// theirs
+pub fn listener() -> io::Result<Option<TcpListener>> {
+    if !is_ours("LISTEN_PID") {
+        return Ok(None);
+    }
+    let count = env::var("LISTEN_FDS").ok()
+        .and_then(|x| x.parse::<usize>().ok());
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+
+    let invalid = |x: &str| io::Error::new(io::ErrorKind::InvalidInput, x);
+    match count {
+        Some(0) => return Ok(None),
+        Some(1) => {}
+        _ => return Err(invalid("expected a single socket in LISTEN_FDS")),
+    }
+
+    // Make sure it's a listening stream socket of an internet address
+    let fd = LISTEN_FDS_START;
+    let option = |level, name| {
+        let mut value: libc::c_int = 0;
+        let mut len = std::mem::size_of_val(&value) as libc::socklen_t;
+        let ret = unsafe {
+            libc::getsockopt(fd, level, name,
+                &mut value as *mut libc::c_int as *mut libc::c_void,
+                &mut len)
+        };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(value)
+    };
+    let domain = option(libc::SOL_SOCKET, libc::SO_DOMAIN)?;
+    if option(libc::SOL_SOCKET, libc::SO_TYPE)? != libc::SOCK_STREAM ||
+            option(libc::SOL_SOCKET, libc::SO_ACCEPTCONN)? == 0 ||
+            !matches!(domain, libc::AF_INET | libc::AF_INET6) {
+        return Err(invalid("LISTEN_FDS socket isn't a listening TCP socket"));
+    }
+
+    // systemd doesn't set close-on-exec on the sockets it passes
+    unsafe {
+        libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+        Ok(Some(TcpListener::from_raw_fd(fd)))
+    }
+}
+
+/// Send `state` to the notification socket at `path`, systemd's format of
+/// `NOTIFY_SOCKET` with a leading `@` for abstract sockets
+fn send(path: &str, state: &str) -> io::Result<()> {
+    let addr = match path.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name)?,
+        None => SocketAddr::from_pathname(path)?,
+    };
+    UnixDatagram::unbound()?.send_to_addr(state.as_bytes(), &addr)?;
+    Ok(())
+}
+
+/// Notifications to the service manager, see the module documentation
+#[derive(Debug)]
+pub struct Notifier {
+    /// Notification socket, `None` if we aren't run by systemd
+    socket: Option<String>,
+
+    /// Interval of the watchdog, `None` if it's disabled
+    watchdog: Option<Duration>,
+
+    /// When the watchdog was last fed
+    fed: Instant,
+}
+
+impl Notifier {
+    /// Create a notifier for the service manager which started us, from
+    /// `NOTIFY_SOCKET` and `WATCHDOG_USEC`
+    pub fn from_env() -> Self {
+        let watchdog = env::var("WATCHDOG_USEC").ok()
+            .and_then(|x| x.parse().ok())
+            .filter(|_| is_ours("WATCHDOG_PID") ||
+                env::var_os("WATCHDOG_PID").is_none())
+            .map(Duration::from_micros);
+        Self::new(env::var("NOTIFY_SOCKET").ok(), watchdog)
+    }
+
+    /// Create a notifier for the notification socket `socket` and a watchdog
+    /// with the interval `watchdog`
+    pub fn new(socket: Option<String>, watchdog: Option<Duration>) -> Self {
+        Self { socket, watchdog, fed: Instant::now() }
+    }
+
+    /// Send the newline separated assignments of `state`, eg. `READY=1`,
+    /// nothing is sent if we aren't run by systemd
+    pub fn notify(&self, state: &str) -> io::Result<()> {
+        self.socket.as_deref().map_or(Ok(()), |x| send(x, state))
+    }
+
+    /// Feed the watchdog if half of its interval passed since it was last fed.
+    /// This is to be called regularly, more often than half of the interval
+    pub fn tick(&mut self) -> io::Result<()> {
+        match self.watchdog {
+            Some(interval) if self.fed.elapsed() >= interval / 2 => {
+                self.fed = Instant::now();
+                self.notify("WATCHDOG=1")
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[test]
+fn notifications() {
+    let dir = std::env::temp_dir().join(format!("cannoli-notify-{}",
+        std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("notify");
+    let _ = std::fs::remove_file(&path);
+    let socket = UnixDatagram::bind(&path).unwrap();
+    socket.set_nonblocking(true).unwrap();
+
+    let mut buf = [0u8; 64];
+    let mut recv = || {
+        socket.recv(&mut buf).ok().map(|x| buf[..x].to_vec())
+    };
+
+    // The watchdog is only fed once half of its interval passed
+    let mut notifier = Notifier::new(
+        Some(path.to_str().unwrap().to_string()),
+        Some(Duration::from_millis(40)));
+    notifier.notify("READY=1").unwrap();
+    assert_eq!(recv().as_deref(), Some(&b"READY=1"[..]));
+    notifier.tick().unwrap();
+    assert_eq!(recv(), None);
+    std::thread::sleep(Duration::from_millis(25));
+    notifier.tick().unwrap();
+    assert_eq!(recv().as_deref(), Some(&b"WATCHDOG=1"[..]));
+
+    // Nothing to notify outside of systemd
+    Notifier::new(None, None).notify("READY=1").unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+}
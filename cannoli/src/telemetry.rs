@@ -0,0 +1,365 @@
+//! Sampling watched guest variables and alerting on their values
+//!
+//! Long soak tests go wrong slowly: a counter creeps up, a queue length
+//! never goes back down, a state variable ends up somewhere it shouldn't.
+//! Tracing every write to those variables for days is wasteful, so a
+//! [`Telemetry`] instead reads a handful of watched addresses every so often
+//! through the control channel (see [`crate::guest`]) and checks the values
+//! against rules:
+//!
+//! ```no_run
+//! # fn f(ci: &cannoli::ClientInfo) {
+//! use std::time::Duration;
+//! use cannoli::telemetry::{Condition, Telemetry, Watch};
+//!
+//! let mut telemetry = Telemetry::new();
+//! let queue = telemetry.watch(Watch::new("queue_len", 0x4a2010, 4));
+//! telemetry.rule(queue, Condition::Above(1000));
+//! telemetry.rule(queue, Condition::Rate {
+//!     max: 100,
+//!     per: Duration::from_secs(1),
+//! });
+//!
+//! telemetry.spawn(ci, Duration::from_millis(500), move |x, alerts| {
+//!     for alert in alerts {
+//!         eprintln!("{alert:?}, now {:?}", x.value(queue));
+//!     }
+//! });
+//! # }
+//! ```
+//!
+//! Alerts are edge triggered, a rule alerts when its condition starts to
+//! hold and again only after it stopped holding for a sample, so a value
+//! stuck above a threshold is reported once rather than on every sample.
+//! Values can also be fed from the trace with [`Telemetry::record`] (eg.
+//! from [`crate::Cannoli::write`] of a watched address), which sees every
+//! change rather than samples of them.
+//!
+//! Samples are memory as it is when it's read, which has nothing to do with
+//! where the trace is being processed, see [`crate::guest::GuestMemory`].
+
+use std::sync::Arc;
+use std::io::{ErrorKind, Result};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use crate::ClientInfo;
+use crate::guest::GuestMemory;
+use crate::history::{self, Block};
+
+/// A watched guest variable
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Watch {
+    /// Name of the variable, reported with its alerts
+    pub name: Arc<str>,
+
+    /// Address of the variable
+    pub addr: u64,
+
+    /// Size of the variable in bytes, 1, 2, 4 or 8
+    pub size: usize,
+
+    /// Whether the variable is a signed integer
+    pub signed: bool,
+}
+
+impl Watch {
+    /// Watch the unsigned integer of `size` bytes at `addr`
+    pub fn new(name: &str, addr: u64, size: usize) -> Self {
+        assert!(matches!(size, 1 | 2 | 4 | 8), "Invalid size {size}");
+        Self { name: name.into(), addr, size, signed: false }
+    }
+
+    /// Treat the variable as a signed integer
+    pub fn signed(mut self) -> Self {
+        self.signed = true;
+        self
+    }
+
+    /// Get the value of the variable from the raw bytes `raw`, zero extended
+    fn value(&self, raw: u64) -> i128 {
+        let bits = self.size as u32 * 8;
+        let raw  = raw & (u64::MAX >> (64 - bits));
+        if self.signed {
+            ((raw << (64 - bits)) as i64 >> (64 - bits)) as i128
+        } else {
+            raw as i128
+        }
+    }
+}
+
+/// When a [`Rule`] alerts
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Condition {
+    /// The value is greater than this
+    Above(i128),
+
+    /// The value is less than this
+    Below(i128),
+
+    /// The value changed by more than `max` over `per`, from the last value
+    Rate {
+        /// Largest change which doesn't alert
+        max: u128,
+
+        /// Time the change is measured over
+        per: Duration,
+    },
+
+    /// The bits of the value in `mask` are `value`
+    Pattern {
+        /// Bits which are compared
+        mask: u64,
+
+        /// Value of the compared bits
+        value: u64,
+    },
+
+    /// The value changed at all
+    Changed,
+}
+
+/// A condition on a watched variable
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rule {
+    /// Index of the watched variable, as returned by [`Telemetry::watch`]
+    pub watch: usize,
+
+    /// When the rule alerts
+    pub condition: Condition,
+}
+
+/// A rule which started to hold
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Alert {
+    /// Index of the rule, as returned by [`Telemetry::rule`]
+    pub rule: usize,
+
+    /// Name of the watched variable
+    pub watch: Arc<str>,
+
+    /// Value of the variable
+    pub value: i128,
+
+    /// Previous value of the variable, if there was one
+    pub previous: Option<i128>,
+
+    /// Time of the value since the telemetry was created
+    pub at: Duration,
+
+    /// Last blocks of every thread of the process by TID, see
+    /// [`crate::history`]. Only alerts of [`Telemetry::spawn`] have them
+    pub history: Vec<(i32, Vec<Block>)>,
+}
+
+/// Watched variables and the rules on them, see the module documentation
+#[derive(Debug)]
+pub struct Telemetry {
+    /// Watched variables
+    watches: Vec<Watch>,
+
+    /// Every rule
+    rules: Vec<Rule>,
+
+    /// Last value of every watched variable and when it was recorded
+    last: Vec<Option<(i128, Duration)>>,
+
+    /// Whether the condition of every rule held for the last value
+    active: Vec<bool>,
+
+    /// When the telemetry was created, the origin of sample times
+    started: Instant,
+}
+
+impl Default for Telemetry {
+    fn default() -> Self {
+        Self {
+            watches: Vec::new(),
+            rules:   Vec::new(),
+            last:    Vec::new(),
+            active:  Vec::new(),
+            started: Instant::now(),
+        }
+    }
+}
+
+impl Telemetry {
+    /// Create a new telemetry, without watched variables
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Watch the variable `watch`, returning its index
+    pub fn watch(&mut self, watch: Watch) -> usize {
+        self.watches.push(watch);
+        self.last.push(None);
+        self.watches.len() - 1
+    }
+
+    /// Alert when `condition` starts to hold for the watched variable with
+    /// the index `watch`, returning the index of the rule
+    pub fn rule(&mut self, watch: usize, condition: Condition) -> usize {
+        assert!(watch < self.watches.len(), "Invalid watch {watch}");
+        self.rules.push(Rule { watch, condition });
+        self.active.push(false);
+        self.rules.len() - 1
+    }
+
+    /// Get the watched variables
+    pub fn watches(&self) -> &[Watch] {
+        &self.watches
+    }
+
+    /// Get the rules
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// Get the last value of the watched variable with the index `watch`
+    pub fn value(&self, watch: usize) -> Option<i128> {
+        self.last[watch].map(|(value, _)| value)
+    }
+
+    /// Record the raw value `raw` of the watched variable with the index
+    /// `watch` at `at`, since the telemetry was created, and return the
+    /// alerts of the rules on it
+    pub fn record(&mut self, watch: usize, raw: u64, at: Duration)
+            -> Vec<Alert> {
+        let value = self.watches[watch].value(raw);
+        let last  = self.last[watch].replace((value, at));
+
+        let mut ret = Vec::new();
+        for (ii, rule) in self.rules.iter().enumerate() {
+            if rule.watch != watch {
+                continue;
+            }
+
+            let holds = match rule.condition {
+                Condition::Above(x) => value > x,
+                Condition::Below(x) => value < x,
+                Condition::Pattern { mask, value: x } => {
+                    value as u64 & mask == x & mask
+                }
+                Condition::Changed => last.is_some_and(|(x, _)| x != value),
+                Condition::Rate { max, per } => last.is_some_and(
+                    |(x, when)| {
+                        // Scale the change to `per`, an instant change is
+                        // infinitely fast
+                        let elapsed = at.saturating_sub(when).as_nanos();
+                        let change  = value.abs_diff(x);
+                        change > 0 && (elapsed == 0 ||
+                            change * per.as_nanos() / elapsed > max)
+                    }),
+            };
+
+            if holds && !self.active[ii] {
+                ret.push(Alert {
+                    rule:     ii,
+                    watch:    self.watches[watch].name.clone(),
+                    previous: last.map(|(x, _)| x),
+                    history:  Vec::new(),
+                    value,
+                    at,
+                });
+            }
+            self.active[ii] = holds;
+        }
+        ret
+    }
+
+    /// Read every watched variable through `mem` and return the alerts of
+    /// the rules on them. Variables which aren't mapped are skipped, other
+    /// errors (eg. a thread which is gone) are returned
+    pub fn sample(&mut self, mem: &mut GuestMemory) -> Result<Vec<Alert>> {
+        mem.invalidate();
+        let mut ret = Vec::new();
+        for watch in 0..self.watches.len() {
+            let Watch { addr, size, .. } = self.watches[watch];
+            let raw = match size {
+                1 => mem.read_u8(addr).map(u64::from),
+                2 => mem.read_u16(addr).map(u64::from),
+                4 => mem.read_u32(addr).map(u64::from),
+                _ => mem.read_u64(addr),
+            };
+            match raw {
+                Ok(raw) => {
+                    let at = self.started.elapsed();
+                    ret.extend(self.record(watch, raw, at));
+                }
+                Err(err) if err.kind() == ErrorKind::InvalidData => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Sample the watched variables of the process of `ci` every `interval`
+    /// on a new thread, through the connection of the thread of `ci`.
+    /// `callback` gets the telemetry and the alerts after every sample, with
+    /// the last blocks of the threads of the process, and sampling stops
+    /// once the thread of `ci` is gone. Samples which time out, eg. because
+    /// the target is stalled on a full pipe, are skipped
+    pub fn spawn(mut self, ci: &ClientInfo, interval: Duration,
+            mut callback: impl FnMut(&Self, &[Alert]) + Send + 'static)
+            -> JoinHandle<()> {
+        let mut mem = GuestMemory::new(ci).timeout(interval.max(
+            Duration::from_millis(100)));
+        let pid = ci.pid;
+        std::thread::spawn(move || loop {
+            match self.sample(&mut mem) {
+                Ok(mut alerts) => {
+                    if !alerts.is_empty() {
+                        let threads = history::process(pid);
+                        alerts.iter_mut()
+                            .for_each(|x| x.history = threads.clone());
+                    }
+                    callback(&self, &alerts)
+                }
+                Err(err) if err.kind() == ErrorKind::TimedOut => {}
+                Err(_) => break,
+            }
+            std::thread::sleep(interval);
+        })
+    }
+}
+
+#[test]
+fn telemetry_rules() {
+    let secs = Duration::from_secs;
+    let mut telemetry = Telemetry::new();
+    let count = telemetry.watch(Watch::new("count", 0x1000, 4));
+    let state = telemetry.watch(Watch::new("state", 0x2000, 1).signed());
+    let above = telemetry.rule(count, Condition::Above(100));
+    let rate  = telemetry.rule(count, Condition::Rate {
+        max: 10,
+        per: secs(1),
+    });
+    let error = telemetry.rule(state, Condition::Below(0));
+    let flag  = telemetry.rule(state, Condition::Pattern {
+        mask: 0x0f, value: 0x02 });
+
+    // Growing slowly, then quickly, then over the threshold
+    let rules = |x: Vec<Alert>| x.iter().map(|x| x.rule).collect::<Vec<_>>();
+    assert!(telemetry.record(count, 10, secs(0)).is_empty());
+    assert!(telemetry.record(count, 20, secs(2)).is_empty());
+    let alerts = telemetry.record(count, 40, secs(3));
+    assert_eq!(alerts, [Alert {
+        rule:     rate,
+        watch:    "count".into(),
+        value:    40,
+        previous: Some(20),
+        at:       secs(3),
+        history:  Vec::new(),
+    }]);
+    assert_eq!(rules(telemetry.record(count, 200, secs(30))), [above]);
+
+    // Only alerting again after the condition stopped holding
+    assert!(telemetry.record(count, 150, secs(40)).is_empty());
+    assert!(telemetry.record(count, 90, secs(50)).is_empty());
+    assert_eq!(rules(telemetry.record(count, 101, secs(60))), [above]);
+    assert_eq!(telemetry.value(count), Some(101));
+
+    // Signed bytes and bit patterns
+    assert_eq!(rules(telemetry.record(state, 0x12, secs(0))), [flag]);
+    let alerts = telemetry.record(state, 0xff, secs(1));
+    assert_eq!((rules(alerts.clone()), alerts[0].value), (vec![error], -1));
+}
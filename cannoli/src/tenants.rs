@@ -0,0 +1,287 @@
+//! Multi-tenant daemon mode, serving unrelated QEMU runs of several users
+//!
+//! By default a Cannoli server belongs to whoever started it: it only
+//! accepts connections from processes of its own user (see [`crate::auth`])
+//! and everything ends up in the same outputs. On a shared analysis server,
+//! one long-running daemon can instead serve every job, with
+//! [`crate::CannoliOpts::tenants`] and a file describing who may use it:
+//!
+//! ```toml
+//! [[tenant]]
+//! name          = "alice-fuzz"
+//! token         = "4f1c7e..."
+//! uids          = [1001]
+//! max_threads   = 64
+//! max_processes = 8
+//! output        = "/srv/cannoli/alice-fuzz"
+//! ```
+//!
+//! The jitter greets the server with the token in [`crate::auth::TOKEN_VAR`]
+//! like it always does, and that token tells which tenant a connection
+//! belongs to. Connections are accepted from any user in this mode, unless
+//! the tenant lists the `uids` it's used from, and connections with a token
+//! of no tenant are rejected.
+//!
+//! Tenants are kept apart:
+//!
+//! - every connection carries its tenant in [`crate::ClientInfo::tenant`],
+//!   and the outputs of the included implementations go to the tenant's
+//!   `output` directory ([`crate::sinks::capture::CaptureSink`] writes a
+//!   `capture.cap` there, [`crate::coverage::DrcovCollector`] its drcov
+//!   files)
+//! - a process belongs to the tenant of its first thread, threads of it
+//!   connecting with the token of another tenant are rejected, so a tenant
+//!   can't mix events into the pipeline of someone else's process
+//! - `max_threads` and `max_processes` cap the connections of a tenant at
+//!   once, anything over the quota is rejected (which stops the QEMU thread,
+//!   see [`crate::auth`]) rather than slowing down the other tenants
+//!
+//! Processes are identified by their PID, so the targets of every tenant
+//! have to run in the PID namespace of the daemon.
+
+use std::fmt;
+#[cfg(feature = "client")]
+use std::io;
+#[cfg(feature = "client")]
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
+use serde::Deserialize;
+use crate::auth;
+
+/// A user of the daemon, see the module documentation
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Tenant {
+    /// Name of the tenant, for logs
+    pub name: String,
+
+    /// Token the jitters of the tenant greet the server with
+    token: String,
+
+    /// UIDs the tenant's QEMU processes may run as, any if empty
+    #[serde(default)]
+    pub uids: Vec<u32>,
+
+    /// Most threads of the tenant connected at once
+    #[serde(default)]
+    pub max_threads: Option<usize>,
+
+    /// Most processes of the tenant connected at once
+    #[serde(default)]
+    pub max_processes: Option<usize>,
+
+    /// Directory the outputs of the tenant are written to
+    pub output: PathBuf,
+}
+
+impl fmt::Debug for Tenant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Without the token, which is a secret
+        f.debug_struct("Tenant")
+            .field("name", &self.name)
+            .field("uids", &self.uids)
+            .field("max_threads", &self.max_threads)
+            .field("max_processes", &self.max_processes)
+            .field("output", &self.output)
+            .finish()
+    }
+}
+
+/// Layout of a tenants file
+#[cfg(feature = "client")]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct File {
+    /// Every tenant
+    tenant: Vec<Tenant>,
+}
+
+/// Connections of a tenant
+#[derive(Debug, Default)]
+struct Usage {
+    /// Number of connected threads
+    threads: usize,
+
+    /// Number of connected threads of every connected process, by PID
+    processes: HashMap<i32, usize>,
+}
+
+/// Every tenant of a daemon and their connections, see the module
+/// documentation
+#[derive(Debug)]
+pub struct Tenants {
+    /// Every tenant
+    tenants: Vec<Arc<Tenant>>,
+
+    /// Connections of every tenant, indexed like `tenants`
+    usage: Mutex<Vec<Usage>>,
+}
+
+impl Tenants {
+    /// Parse the tenants file `contents`
+    #[cfg(feature = "client")]
+    pub fn parse(contents: &str) -> io::Result<Self> {
+        let invalid = |x: String| io::Error::new(io::ErrorKind::InvalidData, x);
+        let file: File = toml::from_str(contents)
+            .map_err(|x| invalid(x.to_string()))?;
+
+        for (ii, tenant) in file.tenant.iter().enumerate() {
+            let other = file.tenant[..ii].iter().find(|x| {
+                x.name == tenant.name || x.token == tenant.token
+            });
+            if tenant.token.is_empty() {
+                return Err(invalid(format!("tenant {} has no token",
+                    tenant.name)));
+            }
+            if let Some(other) = other {
+                return Err(invalid(format!("tenants {} and {} have the same \
+                    name or token", other.name, tenant.name)));
+            }
+        }
+
+        Ok(Self {
+            usage:   Mutex::new(file.tenant.iter()
+                .map(|_| Usage::default()).collect()),
+            tenants: file.tenant.into_iter().map(Arc::new).collect(),
+        })
+    }
+
+    /// Load the tenants file at `path`
+    #[cfg(feature = "client")]
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// Get every tenant
+    pub fn tenants(&self) -> &[Arc<Tenant>] {
+        &self.tenants
+    }
+
+    /// Admit a thread of the process `pid` greeting with `token`, from a
+    /// process of `uid` if it's known. The connection counts against the
+    /// quotas of its tenant until the returned admission is dropped
+    pub(crate) fn admit(&self, token: &[u8], uid: Option<u32>, pid: i32)
+            -> Result<Admission<'_>, String> {
+        // Every token is compared, so the time taken doesn't tell which
+        // tenant was close
+        let index = self.tenants.iter()
+            .map(|x| auth::check_token(Some(x.token.as_bytes()), token))
+            .enumerate()
+            .fold(None, |found, (ii, ok)| if ok { Some(ii) } else { found })
+            .ok_or("invalid token")?;
+        let tenant = &self.tenants[index];
+        if !tenant.uids.is_empty() &&
+                !uid.is_some_and(|x| tenant.uids.contains(&x)) {
+            return Err(format!("UID {uid:?} isn't allowed for tenant {}",
+                tenant.name));
+        }
+
+        let mut usage = self.usage.lock().unwrap();
+        if let Some(owner) = usage.iter().position(|x| {
+            x.processes.contains_key(&pid)
+        }).filter(|&x| x != index) {
+            return Err(format!("process belongs to tenant {}",
+                self.tenants[owner].name));
+        }
+
+        let used = &mut usage[index];
+        if tenant.max_threads.is_some_and(|x| used.threads >= x) {
+            return Err(format!("tenant {} is at its quota of threads",
+                tenant.name));
+        }
+        if !used.processes.contains_key(&pid) &&
+                tenant.max_processes
+                    .is_some_and(|x| used.processes.len() >= x) {
+            return Err(format!("tenant {} is at its quota of processes",
+                tenant.name));
+        }
+
+        used.threads += 1;
+        *used.processes.entry(pid).or_default() += 1;
+        Ok(Admission { tenants: self, index, pid })
+    }
+}
+
+/// An admitted connection, which counts against the quotas of its tenant
+/// until it's dropped
+pub(crate) struct Admission<'a> {
+    /// Tenants the connection was admitted by
+    tenants: &'a Tenants,
+
+    /// Index of the tenant
+    index: usize,
+
+    /// Process ID of the connected thread
+    pid: i32,
+}
+
+impl Admission<'_> {
+    /// Get the tenant of the connection
+    pub(crate) fn tenant(&self) -> &Arc<Tenant> {
+        &self.tenants.tenants[self.index]
+    }
+}
+
+impl Drop for Admission<'_> {
+    fn drop(&mut self) {
+        let mut usage = self.tenants.usage.lock().unwrap();
+        let used = &mut usage[self.index];
+        used.threads -= 1;
+        let threads = used.processes.get_mut(&self.pid).unwrap();
+        *threads -= 1;
+        if *threads == 0 {
+            used.processes.remove(&self.pid);
+        }
+    }
+}
+
+#[test]
+#[cfg(feature = "client")]
+fn tenant_quotas() {
+    let tenants = Tenants::parse(r#"
+        [[tenant]]
+        name          = "alice"
+        token         = "a-token"
+        max_threads   = 2
+        max_processes = 1
+        output        = "/tmp/alice"
+
+        [[tenant]]
+        name   = "bob"
+        token  = "b-token"
+        uids   = [1002]
+        output = "/tmp/bob"
+    "#).unwrap();
+
+    // Two threads of one process, and nothing past the quotas
+    let first = tenants.admit(b"a-token", Some(1001), 100).unwrap();
+    assert_eq!(first.tenant().name, "alice");
+    let second = tenants.admit(b"a-token", None, 100).unwrap();
+    assert!(tenants.admit(b"a-token", None, 100).is_err());
+    drop(second);
+    assert!(tenants.admit(b"a-token", None, 101).is_err());
+
+    // Processes and UIDs of other tenants are off limits
+    assert!(tenants.admit(b"b-token", Some(1002), 100).is_err());
+    assert!(tenants.admit(b"b-token", Some(1001), 200).is_err());
+    assert!(tenants.admit(b"c-token", Some(1002), 200).is_err());
+    let bob = tenants.admit(b"b-token", Some(1002), 200).unwrap();
+    assert_eq!(bob.tenant().name, "bob");
+
+    // The process is gone with its last thread
+    drop(first);
+    assert!(tenants.admit(b"a-token", None, 101).is_ok());
+    assert!(Tenants::parse(r#"
+        [[tenant]]
+        name   = "alice"
+        token  = "a-token"
+        output = "/tmp/alice"
+
+        [[tenant]]
+        name   = "eve"
+        token  = "a-token"
+        output = "/tmp/eve"
+    "#).is_err());
+}
@@ -0,0 +1,73 @@
+//! `cannoli annotate`, manage the annotation database shared between runs
+
+use cannoli::symbols::annotations::{Annotation, AnnotationDb};
+use crate::args::Args;
+
+pub const USAGE: &str = "\
+usage: cannoli annotate [options] <module> <offset> <label>
+       cannoli annotate [options] --remove <module> <offset>
+       cannoli annotate [options] --list [module]
+
+Attaches a label and an optional note to <offset> (hex) from the base of
+<module>. Modules are identified by their file name, eg. `libc.so.6`. The
+labels are applied to the symbolized output of later runs.
+
+options:
+    --db <path>        annotation database (default: annotations.db)
+    --source <name>    who the annotation is from (default: user)
+    --note <text>      longer note to attach along with the label
+    --remove           remove the annotation from --source instead
+    --list             list all annotations, or those in [module]";
+
+pub fn run(args: Args) -> Result<(), String> {
+    let path = args.opt("db").unwrap_or("annotations.db");
+    let db = AnnotationDb::open(path)
+        .map_err(|x| format!("failed to open {path}: {x}"))?;
+    let source = args.opt("source").unwrap_or("user");
+
+    if args.switch("list") {
+        let module = match args.positional() {
+            []       => None,
+            [module] => Some(module.as_str()),
+            _ => return Err("expected at most one module".into()),
+        };
+
+        let annotations = db.list(module)
+            .map_err(|x| format!("failed to list annotations: {x}"))?;
+        for x in annotations {
+            let note = x.note.map(|x| format!(" -- {x}")).unwrap_or_default();
+            println!("{}+{:#x} [{}] {}{note}", x.module, x.offset, x.source,
+                x.label);
+        }
+        return Ok(());
+    }
+
+    let parse_offset = |x: &str| {
+        u64::from_str_radix(x.trim_start_matches("0x"), 16)
+            .map_err(|_| format!("invalid offset {x}"))
+    };
+
+    if args.switch("remove") {
+        let [module, offset] = args.positional() else {
+            return Err("expected a module and an offset".into());
+        };
+        let removed = db.remove(module, parse_offset(offset)?, source)
+            .map_err(|x| format!("failed to remove annotation: {x}"))?;
+        if !removed {
+            return Err(format!("no annotation from {source} at \
+                {module}+{offset}"));
+        }
+        return Ok(());
+    }
+
+    let [module, offset, label] = args.positional() else {
+        return Err("expected a module, an offset and a label".into());
+    };
+    db.annotate(&Annotation {
+        module: module.clone(),
+        offset: parse_offset(offset)?,
+        source: source.to_string(),
+        label:  label.clone(),
+        note:   args.opt("note").map(|x| x.to_string()),
+    }).map_err(|x| format!("failed to add annotation: {x}"))
+}
@@ -0,0 +1,85 @@
+//! Minimal command line parsing shared by the subcommands
+
+use std::collections::HashMap;
+
+/// Parsed arguments of a subcommand
+///
+/// Options come first as `--name value`, `--name=value` or `--switch`, and
+/// option parsing stops at the first positional argument or at `--` so the
+/// rest can be passed through to the guest untouched.
+#[derive(Debug, Default)]
+pub struct Args {
+    /// Values of the options, in the order they were given
+    options: HashMap<String, Vec<String>>,
+
+    /// Positional arguments
+    positional: Vec<String>,
+}
+
+impl Args {
+    /// Parse `args`, `switches` are the options which don't take a value
+    pub fn parse(args: impl IntoIterator<Item = String>, switches: &[&str])
+            -> Result<Self, String> {
+        let mut ret  = Self::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            if arg == "--" {
+                break;
+            }
+
+            let Some(name) = arg.strip_prefix("--") else {
+                ret.positional.push(arg);
+                break;
+            };
+
+            let (name, value) = match name.split_once('=') {
+                Some((name, value)) => (name, value.to_string()),
+                None if switches.contains(&name) => (name, String::new()),
+                None => (name, args.next()
+                    .ok_or_else(|| format!("--{name} needs a value"))?),
+            };
+            ret.options.entry(name.to_string()).or_default().push(value);
+        }
+
+        ret.positional.extend(args);
+        Ok(ret)
+    }
+
+    /// Get the last value of the option `name`
+    pub fn opt(&self, name: &str) -> Option<&str> {
+        self.options.get(name).and_then(|x| x.last()).map(|x| x.as_str())
+    }
+
+    /// Get the value of the option `name`, failing if it's missing
+    pub fn required(&self, name: &str) -> Result<&str, String> {
+        self.opt(name).ok_or_else(|| format!("--{name} is required"))
+    }
+
+    /// Get every value of the option `name`
+    pub fn opts(&self, name: &str) -> &[String] {
+        self.options.get(name).map_or(&[], |x| x.as_slice())
+    }
+
+    /// Check if the switch `name` was given
+    pub fn switch(&self, name: &str) -> bool {
+        self.options.contains_key(name)
+    }
+
+    /// Get the positional arguments
+    pub fn positional(&self) -> &[String] {
+        &self.positional
+    }
+}
+
+#[test]
+fn parse_args() {
+    let args = ["--qemu", "qemu-x86_64", "--force", "--arg=a", "--arg", "b",
+                "guest", "--not-ours"].map(String::from);
+    let args = Args::parse(args, &["force"]).unwrap();
+
+    assert_eq!(args.opt("qemu"), Some("qemu-x86_64"));
+    assert!(args.switch("force"));
+    assert_eq!(args.opts("arg"), ["a", "b"]);
+    assert_eq!(args.positional(), ["guest", "--not-ours"]);
+}
@@ -0,0 +1,98 @@
+//! `cannoli batch`, run a target once for every entry of a corpus
+
+use std::time::Duration;
+use cannoli::harness::{Batch, Template, Pool, Limits, Outcome, Summary};
+use crate::args::Args;
+
+pub const USAGE: &str = "\
+usage: cannoli batch --qemu <qemu> --jitter <jitter.so> --corpus <dir>
+                     [options] <guest> [args]
+
+Runs <guest> once for every file in <dir>, writing the manifest of each run
+to the output directory so it can be reproduced with `cannoli repro`. The
+arguments, environment variables and input file path may use placeholders:
+
+    {run}         index of the run
+    {input}       path of the input for this run
+    {input_name}  file name of the corpus entry
+
+options:
+    --out <dir>           where to write manifests [default: cannoli_batch]
+    --env <key=value>     set an environment variable, may be repeated
+    --input-file <path>   copy each input to <path> before running
+    --qemu-arg <arg>      extra argument to pass to QEMU, may be repeated
+    --seed <seed>         give every run the same random numbers, see
+                          `cannoli run --help`
+    --time <secs>         give every run the same time, see
+                          `cannoli run --help`
+    --jobs <n>            runs going at once, 0 for one per CPU [default: 1]
+    --timeout <secs>      kill runs after this much wall-clock time
+    --cpu-limit <secs>    kill runs after this much CPU time
+    --memory-limit <mib>  limit the address space of QEMU, which includes
+                          its 1 GiB translation buffer";
+
+pub fn run(args: Args) -> Result<(), String> {
+    let [guest, argv @ ..] = args.positional() else {
+        return Err("no guest binary given".into());
+    };
+
+    let mut template = Template::new(args.required("qemu")?,
+        args.required("jitter")?, guest, argv)
+        .map_err(|x| format!("failed to create template: {x}"))?
+        .qemu_args(args.opts("qemu-arg"));
+    for var in args.opts("env") {
+        let (key, val) = var.split_once('=')
+            .ok_or_else(|| format!("invalid --env `{var}`"))?;
+        template = template.env(key, val);
+    }
+    if let Some(seed) = args.opt("seed") {
+        template = template.seed(seed.parse()
+            .map_err(|_| format!("invalid --seed `{seed}`"))?);
+    }
+    if let Some(time) = args.opt("time") {
+        let nanos = time.parse::<u64>().ok().and_then(|x| {
+            x.checked_mul(1_000_000_000)
+        }).ok_or_else(|| format!("invalid --time `{time}`"))?;
+        template = template.time(nanos);
+    }
+    if let Some(path) = args.opt("input-file") {
+        template = template.input_file(path);
+    }
+
+    let corpus = args.required("corpus")?;
+    let out    = args.opt("out").unwrap_or("cannoli_batch");
+    let batch  = Batch::from_dir(template, corpus, out)
+        .map_err(|x| format!("failed to read corpus {corpus}: {x}"))?;
+
+    let number = |name: &str| args.opt(name).map(|x| x.parse::<u64>()
+        .map_err(|_| format!("invalid --{name} `{x}`"))).transpose();
+    let secs = |name: &str| Ok::<_, String>(
+        number(name)?.map(Duration::from_secs));
+    let limits = Limits {
+        cpu:    secs("cpu-limit")?,
+        memory: number("memory-limit")?.map(|x| x << 20),
+        wall:   secs("timeout")?,
+    };
+    let pool = match number("jobs")?.unwrap_or(1) {
+        0 => Pool::per_cpu(),
+        x => Pool::new(x as usize),
+    }.limits(limits);
+
+    let mut done = 0;
+    let results = batch.run_pool(&pool, |result| {
+        done += 1;
+        let outcome = match &result.outcome {
+            Outcome::Exited(status) => status.to_string(),
+            Outcome::TimedOut       => "timed out".into(),
+            Outcome::Failed(err)    => err.clone(),
+        };
+        println!("[{done:6}/{:6}] {} {outcome}", batch.len(),
+            batch.input(result.index).display());
+    }).map_err(|x| format!("batch failed: {x}"))?;
+
+    let summary = Summary::new(&results);
+    println!("{} runs, {} failed, {} crashed, {} timed out, {} errors, \
+        manifests in {out}", batch.len(), summary.failed, summary.crashed,
+        summary.timed_out, summary.errors);
+    Ok(())
+}
@@ -0,0 +1,122 @@
+//! `cannoli conformance`, check QEMU builds against the client
+
+use std::net::TcpListener;
+use std::time::Duration;
+use cannoli::{CannoliOpts, create_cannoli_with};
+use cannoli::conformance::{self, ConformanceSink, Matrix};
+use cannoli::harness::{Limits, Outcome, Pool, RunManifest};
+use cannoli::sinks::Recorder;
+use crate::args::Args;
+
+pub const USAGE: &str = "\
+usage: cannoli conformance [options] <matrix.toml>
+
+Runs the guest of every target of <matrix.toml> under its QEMU build once
+for every hook configuration, and checks that the events the client gets
+follow the protocol: only hooked kinds of events and all of them, legal
+memory access sizes and values, registers of a consistent size, executed
+code in executable mappings. Run it after bumping the QEMU submodule or
+changing the patch. See `cannoli::conformance` for the matrix format.
+
+Exits with an error if any check fails. No Cannoli server may be running,
+this starts its own.
+
+options:
+    --config <name>    only run the hook configuration <name>, can be given
+                       multiple times: once, always, registers, branches,
+                       exec-regs, memory, addresses, everything
+    --timeout <secs>   kill runs after this long [default: 60]
+    --threads <count>  processing threads for every connection [default: 4]";
+
+pub fn run(args: Args) -> Result<(), String> {
+    let [path] = args.positional() else {
+        return Err("expected a matrix file".into());
+    };
+    let matrix = Matrix::load(path)
+        .map_err(|x| format!("failed to load {path}: {x}"))?;
+    let timeout = args.opt("timeout").map_or(Ok(60), |x| x.parse())
+        .map_err(|_| "invalid --timeout")?;
+    let timeout = Duration::from_secs(timeout);
+    let threads = args.opt("threads").map_or(Ok(4), |x| x.parse())
+        .map_err(|_| "invalid --threads")?;
+
+    let only = args.opts("config");
+    let configs = conformance::configs().into_iter()
+        .filter(|x| only.is_empty() || only.iter().any(|y| y == x.name))
+        .collect::<Vec<_>>();
+    if let Some(name) = only.iter().find(|x| {
+        !configs.iter().any(|y| y.name == x.as_str())
+    }) {
+        return Err(format!("unknown configuration `{name}`"));
+    }
+
+    // Bind before the server thread starts, so the first run can't beat it
+    let listener = TcpListener::bind("127.0.0.1:11458")
+        .map_err(|x| format!("failed to listen, is a server running? {x}"))?;
+    let opts = CannoliOpts::new(threads).listener(listener);
+    std::thread::spawn(move || {
+        if let Err(err) = create_cannoli_with::<
+                Recorder<ConformanceSink>>(opts) {
+            eprintln!("error: server failed: {err:?}");
+            std::process::exit(1);
+        }
+    });
+
+    let pool = Pool::new(1).limits(Limits {
+        wall: Some(timeout),
+        ..Limits::default()
+    });
+    let mut failed = 0;
+    for target in &matrix.target {
+        let manifest = RunManifest::new(&target.qemu, &target.jitter,
+            &target.guest, &target.args)
+            .map_err(|x| format!("failed to create manifest for {}: {x}",
+                target.name))?;
+        if let Some(version) = target.version.as_deref()
+                .filter(|x| *x != manifest.qemu_version) {
+            println!("{:<12} FAIL QEMU is `{}`, not the pinned `{version}`",
+                target.name, manifest.qemu_version);
+            failed += 1;
+            continue;
+        }
+
+        for config in &configs {
+            conformance::start(config.filters.clone())
+                .map_err(|x| format!("failed to set the filters: {x}"))?;
+            let result = pool.run(std::slice::from_ref(&manifest), |_| {})
+                .map_err(|x| format!("failed to run {}: {x}", target.name))?
+                .remove(0);
+            let report = conformance::finish(&target.guest, timeout);
+
+            let outcome = match result.outcome {
+                Outcome::Exited(status) if status.success() => None,
+                Outcome::Exited(status) => Some(status.to_string()),
+                Outcome::TimedOut => Some("timed out".into()),
+                Outcome::Failed(err) => Some(err),
+            };
+            let counts = report.counts;
+            let status = if report.passed() && outcome.is_none() {
+                "ok"
+            } else {
+                failed += 1;
+                "FAIL"
+            };
+            println!("{:<12} {:<10} {status:<4} {} exec, {} regs, {} \
+                branches, {} reads, {} writes, {} mappings", target.name,
+                config.name, counts.exec, counts.regs, counts.branches,
+                counts.reads, counts.writes, counts.maps);
+            if let Some(outcome) = outcome {
+                println!("    guest failed: {outcome}");
+            }
+            for x in &report.violations {
+                println!("    {} ({}x), first {}", x.rule, x.count,
+                    x.example);
+            }
+        }
+    }
+
+    match failed {
+        0 => Ok(()),
+        _ => Err(format!("{failed} runs failed")),
+    }
+}
@@ -0,0 +1,156 @@
+//! `cannoli coredump`, align a core dump of a traced process with its trace
+
+use std::collections::{BTreeMap, BTreeSet};
+use cannoli::capture::{CaptureReader, Record};
+use cannoli::coredump::{Aligner, CoreDump, CoreFile, Origin};
+use crate::args::Args;
+
+pub const USAGE: &str = "\
+usage: cannoli coredump [options] <capture> <core>
+
+Aligns the core dump <core> with the trace of the process which crashed in
+<capture>, and lists what the trace says about the dump: how the modules of
+the dump line up with the trace, whether the registers of the crashed
+thread are those of the last instruction traced on it, the last blocks
+traced on it, and for every writable or written region of the dump, the
+mapping it came from, the instructions which wrote to it the most and how
+many of the last values written are still in the dump. Values which
+aren't were changed by something the trace doesn't show.
+
+The process is the one with a thread of the dump, or the only one of the
+capture. Addresses are those of the dump, in modules when the dump lists
+its files.
+
+options:
+    --pid <pid>      process of the capture the dump is of
+    --output <file>  also save the alignment to <file> as JSON";
+
+/// Format `addr` of the dump as an offset in the file of `files` mapped
+/// there
+fn location(files: &[CoreFile], addr: u64) -> String {
+    files.iter().find(|x| (x.start..x.end).contains(&addr))
+        .map_or(format!("{addr:#x}"), |x| {
+            format!("{}+{:#x}", x.path, x.offset + (addr - x.start))
+        })
+}
+
+pub fn run(args: Args) -> Result<(), String> {
+    let [capture, core] = args.positional() else {
+        return Err("expected a capture and a core dump".into());
+    };
+    let pid = args.opt("pid").map(|x| x.parse::<i32>()).transpose()
+        .map_err(|_| "invalid --pid")?;
+    let core = CoreDump::load(core)
+        .map_err(|x| format!("failed to load {core}: {x}"))?;
+    let failed = |x: std::io::Error| format!("failed to read {capture}: {x}");
+
+    // Find the process first, by its PID or the threads of the dump, the
+    // last one if it's in several segments
+    let mut processes: BTreeMap<(u32, i32), BTreeSet<i32>> = BTreeMap::new();
+    let mut reader = CaptureReader::open(capture).map_err(failed)?;
+    let mut segment = 0;
+    while let Some(record) = reader.next_record().map_err(failed)? {
+        match record {
+            Record::Segment(x) => segment = x.index,
+            Record::Events { pid, tid, .. } => {
+                processes.entry((segment, pid)).or_default().insert(tid);
+            }
+        }
+    }
+    let key = match pid {
+        Some(pid) => processes.keys().rev().find(|x| x.1 == pid).copied()
+            .ok_or_else(|| format!("no process {pid} in {capture}"))?,
+        None => processes.iter().rev().find(|(_, tids)| {
+            core.threads.iter().any(|x| tids.contains(&x.tid))
+        }).map(|x| *x.0).or_else(|| {
+            let mut pids = processes.keys();
+            pids.next().filter(|_| pids.next().is_none()).copied()
+        }).ok_or("no process of the capture has threads of the dump, \
+            give it with --pid")?,
+    };
+
+    let mut aligner = Aligner::new(core);
+    let mut reader = CaptureReader::open(capture).map_err(failed)?;
+    let mut segment = 0;
+    while let Some(record) = reader.next_record().map_err(failed)? {
+        match record {
+            Record::Segment(x) => segment = x.index,
+            Record::Events { pid, tid, events } if (segment, pid) == key => {
+                for event in &events {
+                    aligner.event(tid, event);
+                }
+            }
+            Record::Events { .. } => {}
+        }
+    }
+    let files = aligner.core().files.clone();
+    let alignment = aligner.finish();
+    let location = |x| location(&files, x);
+
+    println!("process {} of segment {}", key.1, key.0);
+    for slide in &alignment.slides {
+        println!("module {} at {:#x}, {:#x} in the trace", slide.module,
+            slide.core, slide.trace);
+    }
+    if files.is_empty() {
+        println!("the dump doesn't list its files, assuming it's from the \
+            traced run");
+    }
+
+    if let Some(regs) = &alignment.registers {
+        println!("thread {} crashed with signal {}", regs.tid, regs.signal);
+        match regs.last_pc {
+            Some(pc) => println!("last traced instruction {}, {}",
+                location(pc), if regs.pc_in_core {
+                    "in the registers of the dump"
+                } else {
+                    "not in the registers of the dump"
+                }),
+            None => println!("nothing traced on the thread"),
+        }
+        if regs.traced != 0 {
+            println!("{} of {} values of the last traced registers are in \
+                the dump", regs.matching, regs.traced);
+        }
+        if !regs.history.is_empty() {
+            println!("last traced blocks, the oldest first:");
+            for block in &regs.history {
+                println!("    {}", location(block.pc));
+            }
+        }
+    }
+
+    for region in &alignment.regions {
+        let segment = &region.segment;
+        let origin = match &region.origin {
+            Origin::File { path, offset } => format!("{path}+{offset:#x}"),
+            Origin::Heap { heap } => format!("{heap:?} heap"),
+            Origin::Anonymous => "anonymous".into(),
+            Origin::Untraced => "not mapped in the trace".into(),
+        };
+        println!("\n{:#x}-{:#x} {}{}{} {origin}{}", segment.base,
+            segment.base + segment.len,
+            if segment.read  { "r" } else { "-" },
+            if segment.write { "w" } else { "-" },
+            if segment.exec  { "x" } else { "-" },
+            if region.same_mapping { "" } else { ", mapped differently" });
+        println!("    {} writes, {} last values in the dump, {} changed \
+            since", region.writes, region.agreeing, region.disagreeing);
+        for (pc, count) in &region.writers {
+            println!("    {count:>8} {}", location(*pc));
+        }
+        if let Some((pc, addr)) = region.last_write {
+            println!("    last written at {addr:#x} by {}", location(pc));
+        }
+    }
+    if alignment.missing != 0 {
+        println!("\n{} mappings of the trace aren't in the dump",
+            alignment.missing);
+    }
+
+    if let Some(path) = args.opt("output") {
+        alignment.save(path)
+            .map_err(|x| format!("failed to save {path}: {x}"))?;
+    }
+    Ok(())
+}
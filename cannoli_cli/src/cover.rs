@@ -0,0 +1,149 @@
+//! `cannoli cover`, list the code covered by captures
+
+use std::sync::Arc;
+use std::collections::{BTreeSet, HashMap};
+use cannoli::address_space::AddressSpace;
+use cannoli::analysis::source_coverage::SourceCoverage;
+use cannoli::capture::{CaptureReader, Record};
+use cannoli::event::Event;
+use crate::args::Args;
+use crate::overlay;
+
+pub const USAGE: &str = "\
+usage: cannoli cover [options] <capture>...
+
+Lists the instructions executed in the captures, by module and offset, or
+by address outside of files.
+
+With --lines, lists the source lines executed instead, from the DWARF line
+tables of the modules. Lines stay the same when the target is rebuilt where
+addresses don't, so with --db the lines are added to a coverage file which
+accumulates over captures of different builds. Modules have to be at the
+path they were traced from, and instructions without a line are only
+counted.
+
+With overlays, instructions are listed by the label of the region of the
+overlay they're in, with the number of instructions covered in each label,
+so coverage can be read by component. See `cannoli::symbols::overlay` for
+the format of overlay files.
+
+options:
+    --lines           key coverage by source file and line
+    --db <file>       add the covered lines to <file> and list every line
+                      in it, implies --lines
+    --overlay <file>  label code with the regions of the overlay file
+                      <file>, can be given multiple times";
+
+pub fn run(args: Args) -> Result<(), String> {
+    if args.positional().is_empty() {
+        return Err("no capture given".into());
+    }
+    let db      = args.opt("db");
+    let by_line = args.switch("lines") || db.is_some();
+    let overlay = overlay::load(&args)?;
+
+    let mut lines = match db {
+        Some(db) => SourceCoverage::load(db)
+            .map_err(|x| format!("failed to load {db}: {x}"))?,
+        None => SourceCoverage::new(),
+    };
+    let before = lines.len();
+
+    // Keyed by module and offset, or by address outside of files
+    let mut covered: BTreeSet<(Option<Arc<str>>, u64)> = BTreeSet::new();
+    for path in args.positional() {
+        let failed = |x: std::io::Error| format!("failed to read {path}: {x}");
+        let mut reader = CaptureReader::open(path).map_err(failed)?;
+        let mut spaces: HashMap<(u32, i32), AddressSpace> = HashMap::new();
+        let mut segment = 0;
+        while let Some(record) = reader.next_record().map_err(failed)? {
+            let (pid, events) = match record {
+                Record::Segment(x) => {
+                    segment = x.index;
+                    continue;
+                }
+                Record::Events { pid, events, .. } => (pid, events),
+            };
+
+            let space = spaces.entry((segment, pid)).or_default();
+            for event in &events {
+                let pc = match event {
+                    Event::Mmap { base, len, anon, read, write, exec, path,
+                            offset } => {
+                        space.mmap(*base, *len, *anon, *read, *write, *exec,
+                            path, *offset);
+                        continue;
+                    }
+                    Event::Munmap { base, len } => {
+                        space.munmap(*base, *len);
+                        continue;
+                    }
+                    Event::Exec { pc } | Event::Regs { pc, .. } |
+                        Event::Branch { pc, .. } => *pc,
+                    _ => continue,
+                };
+
+                let key = match space.module_offset(pc) {
+                    Some((module, offset)) => (Some(module), offset),
+                    None => (None, pc),
+                };
+                covered.insert(key);
+            }
+        }
+    }
+
+    if !by_line && !overlay.is_empty() {
+        // Grouped by label, unlabelled code last
+        let mut labelled = covered.iter().map(|(module, offset)| {
+            (overlay.lookup(module.as_deref(), *offset), module, offset)
+        }).collect::<Vec<_>>();
+        labelled.sort_by_key(|(x, ..)| (x.is_none(), x.map(|x| &x.label)));
+
+        let mut counts: Vec<(&str, u64)> = Vec::new();
+        for (region, module, offset) in labelled {
+            let name = region.map_or("-".into(), overlay::label);
+            match module {
+                Some(module) => println!("{name} {module}+{offset:#x}"),
+                None => println!("{name} {offset:#x}"),
+            }
+
+            let label = region.map_or("(unlabelled)", |x| &x.label);
+            match counts.last_mut() {
+                Some((last, count)) if *last == label => *count += 1,
+                _ => counts.push((label, 1)),
+            }
+        }
+        for (label, count) in counts {
+            eprintln!("{count:>10} {label}");
+        }
+        return Ok(());
+    }
+    if !by_line {
+        for (module, offset) in &covered {
+            match module {
+                Some(module) => println!("{module}+{offset:#x}"),
+                None => println!("{offset:#x}"),
+            }
+        }
+        return Ok(());
+    }
+
+    // Each instruction only once, lines are looked up per instruction
+    let mut outside = 0u64;
+    for (module, offset) in &covered {
+        match module {
+            Some(module) => { lines.add(module, *offset); }
+            None => outside += 1,
+        }
+    }
+    for line in lines.lines() {
+        println!("{}:{}", line.file, line.line);
+    }
+    eprintln!("{} lines ({} new), {} instructions without a line",
+        lines.len(), lines.len() - before, lines.unmapped() + outside);
+
+    if let Some(db) = db {
+        lines.save(db).map_err(|x| format!("failed to save {db}: {x}"))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,58 @@
+//! `cannoli daemon`, record the runs of several tenants on a shared server
+
+use cannoli::{CannoliOpts, create_cannoli_with};
+use cannoli::profile::Profile;
+use cannoli::sinks::Recorder;
+use cannoli::sinks::capture::CaptureSink;
+use cannoli::systemd;
+use cannoli::tenants::Tenants;
+use crate::args::Args;
+
+pub const USAGE: &str = "\
+usage: cannoli daemon [options] <tenants>
+
+Runs a Cannoli server which records the QEMU runs of every tenant listed in
+the file <tenants>, each to a capture in the output directory of its
+tenant. Runs of any user are accepted as long as they greet the server with
+the token of a tenant, and each tenant's connections are limited by its
+quotas. See `cannoli::tenants` for the format of <tenants>.
+
+The daemon can run as a systemd service: it accepts connections on the
+socket passed with socket activation if there is one, notifies systemd once
+it's ready (`Type=notify`), feeds the watchdog of `WatchdogSec=`, and exits
+with a non-zero status when it fails so `Restart=on-failure` applies.
+
+options:
+    --profile <name>   hook and record with a capture profile, see `cannoli
+                       record`, rather than recording what the jitter hooks
+    --threads <count>  processing threads for every connection [default: 4]";
+
+pub fn run(args: Args) -> Result<(), String> {
+    let [path] = args.positional() else {
+        return Err("expected a tenants file".into());
+    };
+    let threads = args.opt("threads").map_or(Ok(4), |x| x.parse())
+        .map_err(|_| "invalid --threads")?;
+    let profile = args.opt("profile").map(|name| {
+        Profile::parse(name).ok_or_else(|| format!("unknown profile `{name}`"))
+    }).transpose()?;
+
+    let tenants = Tenants::load(path)
+        .map_err(|x| format!("failed to load {path}: {x}"))?;
+    for tenant in tenants.tenants() {
+        eprintln!("Serving {} into {}", tenant.name, tenant.output.display());
+    }
+
+    let mut opts = CannoliOpts::new(threads).tenants(tenants).notify();
+    if let Some(listener) = systemd::listener()
+            .map_err(|x| format!("invalid socket activation: {x}"))? {
+        eprintln!("Listening on {:?} from systemd",
+            listener.local_addr().map_err(|x| x.to_string())?);
+        opts = opts.listener(listener);
+    }
+
+    match profile {
+        Some(profile) => profile.serve(opts),
+        None => create_cannoli_with::<Recorder<CaptureSink>>(opts),
+    }.map_err(|x| format!("server failed: {x:?}"))
+}
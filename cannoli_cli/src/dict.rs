@@ -0,0 +1,72 @@
+//! `cannoli dict`, generate a fuzzing dictionary from a capture
+
+use std::collections::HashMap;
+use cannoli::address_space::AddressSpace;
+use cannoli::analysis::dictionary::Dictionary;
+use cannoli::capture::{CaptureReader, Record};
+use cannoli::event::Event;
+use crate::args::Args;
+
+pub const USAGE: &str = "\
+usage: cannoli dict [options] <capture>...
+
+Writes an AFL/libFuzzer dictionary for the target of the captures: the
+constants its comparisons were made against (needs `cmp` events), and the
+strings it read from read-only data of the files it mapped (needs memory
+events). The mapped files have to be where they were during the runs.
+
+options:
+    --out <path>       where to write the dictionary [default: stdout]
+    --big-endian       encode constants for a big-endian target
+    --min-len <count>  shortest string to keep [default: 3]";
+
+pub fn run(args: Args) -> Result<(), String> {
+    if args.positional().is_empty() {
+        return Err("no capture given".into());
+    }
+
+    let mut dictionary = Dictionary::new();
+    dictionary.big_endian = args.switch("big-endian");
+    if let Some(len) = args.opt("min-len") {
+        dictionary.min_len = len.parse()
+            .map_err(|_| format!("invalid --min-len `{len}`"))?;
+    }
+
+    for path in args.positional() {
+        let failed = |x: std::io::Error| format!("failed to read {path}: {x}");
+        let mut reader = CaptureReader::open(path).map_err(failed)?;
+        let mut spaces: HashMap<(u32, i32), AddressSpace> = HashMap::new();
+        let mut segment = 0;
+        while let Some(record) = reader.next_record().map_err(failed)? {
+            let (pid, events) = match record {
+                Record::Segment(x) => {
+                    segment = x.index;
+                    continue;
+                }
+                Record::Events { pid, events, .. } => (pid, events),
+            };
+
+            let space = spaces.entry((segment, pid)).or_default();
+            for event in &events {
+                match event {
+                    Event::Mmap { base, len, anon, read, write, exec, path,
+                            offset } => {
+                        space.mmap(*base, *len, *anon, *read, *write, *exec,
+                            path, *offset);
+                    }
+                    Event::Munmap { base, len } => space.munmap(*base, *len),
+                    _ => dictionary.observe(space, event),
+                }
+            }
+        }
+    }
+
+    match args.opt("out") {
+        Some(out) => {
+            let file = std::fs::File::create(out)
+                .map_err(|x| format!("failed to create {out}: {x}"))?;
+            dictionary.write(std::io::BufWriter::new(file))
+        }
+        None => dictionary.write(std::io::stdout().lock()),
+    }.map_err(|x| format!("failed to write the dictionary: {x}"))
+}
@@ -0,0 +1,49 @@
+//! `cannoli diff`, find where two runs of a target first diverge
+
+use cannoli::harness::Differential;
+use crate::args::Args;
+
+pub const USAGE: &str = "\
+usage: cannoli diff [options] <a.capture> <b.capture>
+
+Compares the main threads of two captures, eg. of a patched and an unpatched
+build run with the same input, and prints the first instruction or store
+where they differ along with the events leading up to it. Addresses are
+compared in canonical form, and the guest binaries of both runs are named
+`guest`, so the captures need manifests.
+
+options:
+    --start <a>:<b>     only compare from when the first run executes offset
+                        <a> of its guest binary, and the second run offset
+                        <b> of its guest binary
+    --context <count>   events to show before the divergence [default: 8]";
+
+/// Parse a hex offset, with or without `0x`
+fn parse_offset(text: &str) -> Option<u64> {
+    u64::from_str_radix(text.trim_start_matches("0x"), 16).ok()
+}
+
+pub fn run(args: Args) -> Result<(), String> {
+    let [a, b] = args.positional() else {
+        return Err("expected exactly two captures".into());
+    };
+
+    let mut diff = Differential::new();
+    if let Some(start) = args.opt("start") {
+        let (x, y) = start.split_once(':')
+            .and_then(|(x, y)| Some((parse_offset(x)?, parse_offset(y)?)))
+            .ok_or_else(|| format!("invalid --start `{start}`"))?;
+        diff = diff.start_at(x, y);
+    }
+    if let Some(count) = args.opt("context") {
+        diff = diff.context(count.parse()
+            .map_err(|_| format!("invalid --context `{count}`"))?);
+    }
+
+    match diff.compare_captures(a, b)
+            .map_err(|x| format!("failed to compare {a} and {b}: {x}"))? {
+        Some(divergence) => println!("{divergence}"),
+        None => println!("runs are identical"),
+    }
+    Ok(())
+}
@@ -0,0 +1,119 @@
+//! `cannoli estimate`, project the overhead of every capture profile
+
+use std::net::TcpListener;
+use std::time::Duration;
+use cannoli::{CannoliOpts, create_cannoli_with};
+use cannoli::control::{self, Filters};
+use cannoli::estimate::{self, Counter, DryRun};
+use cannoli::harness::{Limits, Outcome, Pool, RunManifest};
+use cannoli::profile::Profile;
+use crate::args::Args;
+
+pub const USAGE: &str = "\
+usage: cannoli estimate --qemu <qemu> --jitter <jitter.so> [options] <guest>
+                        [args]
+
+Runs <guest> twice for at most --duration seconds, with the hooks of the
+`syscall-audit` and `full` profiles but only counting events, and reports
+for every profile the events and output it would produce per second and how
+many times slower than without hooks the target would run. Do this before
+a long capture to pick a profile or narrow the filters.
+
+Runs are compared by the system calls they made, or as a whole if the
+target exits in both. Without either there is no slowdown. Output sizes
+are before any summarizing, so they are upper bounds. No Cannoli server
+may be running, this starts its own.
+
+options:
+    --duration <secs>  time given to each run [default: 10]
+    --module <name>    only hook code in the module <name>, see `cannoli
+                       record --help`, can be given multiple times
+    --qemu-arg <arg>   extra argument to pass to QEMU, may be repeated
+    --threads <count>  processing threads for every connection [default: 4]";
+
+/// Format `bytes` with a binary unit
+fn size(bytes: f64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut bytes = bytes;
+    let mut unit = 0;
+    while bytes >= 1024. && unit + 1 < units.len() {
+        bytes /= 1024.;
+        unit += 1;
+    }
+    format!("{bytes:.1} {}", units[unit])
+}
+
+pub fn run(args: Args) -> Result<(), String> {
+    let [guest, argv @ ..] = args.positional() else {
+        return Err("no guest binary given".into());
+    };
+    let duration = args.opt("duration").map_or(Ok(10), |x| x.parse())
+        .map_err(|_| "invalid --duration")?;
+    let threads = args.opt("threads").map_or(Ok(4), |x| x.parse())
+        .map_err(|_| "invalid --threads")?;
+
+    let mut manifest = RunManifest::new(args.required("qemu")?,
+        args.required("jitter")?, guest, argv)
+        .map_err(|x| format!("failed to create manifest: {x}"))?;
+    manifest.qemu_args = args.opts("qemu-arg").to_vec();
+    let filters = |profile: Profile| Filters {
+        modules: args.opts("module").to_vec(),
+        ..profile.filters()
+    };
+
+    // Bind before the server thread starts, so the first run can't beat it
+    let listener = TcpListener::bind("127.0.0.1:11458")
+        .map_err(|x| format!("failed to listen, is a server running? {x}"))?;
+    let opts = CannoliOpts::new(threads).listener(listener)
+        .filters(filters(Profile::SyscallAudit));
+    std::thread::spawn(move || {
+        if let Err(err) = create_cannoli_with::<Counter>(opts) {
+            eprintln!("error: server failed: {err:?}");
+            std::process::exit(1);
+        }
+    });
+
+    let pool = Pool::new(1).limits(Limits {
+        wall: Some(Duration::from_secs(duration)),
+        ..Limits::default()
+    });
+    let mut runs = Vec::new();
+    for profile in [Profile::SyscallAudit, Profile::Full] {
+        control::set_filters(filters(profile))
+            .map_err(|x| format!("failed to set the filters: {x}"))?;
+        estimate::reset();
+        eprintln!("Running with the hooks of {profile}");
+        let result = pool.run(std::slice::from_ref(&manifest), |_| {})
+            .map_err(|x| format!("failed to run the target: {x}"))?
+            .remove(0);
+        let exited = match result.outcome {
+            Outcome::Exited(_)   => true,
+            Outcome::TimedOut    => false,
+            Outcome::Failed(err) => return Err(err),
+        };
+        runs.push(DryRun {
+            counts:  estimate::settle(),
+            elapsed: result.elapsed,
+            exited,
+        });
+    }
+
+    let counts = &runs[1].counts;
+    println!("{} instructions ({} distinct), {} reads, {} writes, {} system \
+        calls in {:.1}s", counts.exec, counts.covered, counts.reads,
+        counts.writes, counts.syscalls, runs[1].elapsed.as_secs_f64());
+    println!("\n{:<14} {:>12} {:>12} {:>12} {:>9}", "profile", "events/s",
+        "output/s", "output/h", "slowdown");
+    let estimates = estimate::estimate(&runs[0], &runs[1]);
+    for x in &estimates {
+        println!("{:<14} {:>12.0} {:>12} {:>12} {:>9}", x.profile.name(),
+            x.events_per_sec, size(x.bytes_per_sec),
+            size(x.bytes_per_sec * 3600.),
+            x.slowdown.map_or("n/a".into(), |x| format!("{x:.1}x")));
+    }
+    if estimates.iter().any(|x| x.slowdown.is_none()) {
+        println!("\nno system calls were made, the slowdown is only known \
+            for targets which exit within --duration");
+    }
+    Ok(())
+}
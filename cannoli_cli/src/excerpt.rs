@@ -0,0 +1,60 @@
+//! `cannoli excerpt`, cut the events around events of interest out of a
+//! capture
+
+use cannoli::excerpt::{self, Mark};
+use cannoli::grep::{Index, Query};
+use crate::args::Args;
+
+pub const USAGE: &str = "\
+usage: cannoli excerpt [options] <capture> <out> [<term>...]
+
+Copies the events around every event of <capture> matching all of the terms
+(see `cannoli grep --help`) into the new capture <out>, so a small capture
+holding just what's interesting can be shared. Events which map or unmap
+memory are always copied.
+
+options:
+    --radius <count>   events to copy before and after every mark, in the
+                       same thread [default: 1000]
+    --marks <file>     also mark the events listed in <file>, one per line as
+                       printed by `cannoli grep`
+    --no-index         don't use or create an index";
+
+pub fn run(args: Args) -> Result<(), String> {
+    let [path, out, terms @ ..] = args.positional() else {
+        return Err("expected a capture and an output path".into());
+    };
+    let radius = args.opt("radius").map_or(Ok(1000), |x| x.parse())
+        .map_err(|_| "invalid --radius")?;
+
+    let mut marks = Vec::new();
+    if let Some(file) = args.opt("marks") {
+        let text = std::fs::read_to_string(file)
+            .map_err(|x| format!("failed to read {file}: {x}"))?;
+        for line in text.lines().filter(|x| !x.trim().is_empty()) {
+            marks.push(line.parse::<Mark>()?);
+        }
+    }
+    if !terms.is_empty() {
+        let query = Query::parse(terms)?;
+        let index = if args.switch("no-index") {
+            None
+        } else {
+            Some(Index::open(path)
+                .map_err(|x| format!("failed to index {path}: {x}"))?)
+        };
+        cannoli::grep::search(path, &query, index.as_ref(), |found| {
+            marks.push(Mark::from(&found));
+            true
+        }).map_err(|x| format!("failed to search {path}: {x}"))?;
+    }
+    if marks.is_empty() {
+        return Err("nothing is marked".into());
+    }
+
+    let stats = excerpt::excerpt(path, out, &marks, radius)
+        .map_err(|x| format!("failed to excerpt {path}: {x}"))?;
+    println!("Copied {} of {} events in {} windows to {out}", stats.events,
+        stats.total, stats.windows);
+    Ok(())
+}
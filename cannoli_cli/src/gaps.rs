@@ -0,0 +1,178 @@
+//! `cannoli gaps`, list the conditional branches which only went one way
+
+use std::sync::Arc;
+use std::collections::HashMap;
+use cannoli::Architecture;
+use cannoli::address_space::AddressSpace;
+use cannoli::analysis::cfg::CfgBuilder;
+use cannoli::analysis::functions::FunctionInference;
+use cannoli::analysis::unreached::{UnreachedBranch, unreached_branches};
+use cannoli::capture::{CaptureReader, Record};
+use cannoli::event::Event;
+use cannoli::symbols::Overlay;
+use cannoli::symbols::signatures::Signatures;
+use crate::args::Args;
+use crate::overlay;
+
+pub const USAGE: &str = "\
+usage: cannoli gaps [options] <capture>...
+
+Lists the conditional branches of the captures which were only ever taken
+or only ever not taken, with where the missing side goes, as a list of what
+to test next. Functions are inferred from the calls which returned, and
+their control flow graphs are built from the instructions which executed.
+
+Branches are decoded from the modules of the target, which have to be at
+the path they were traced from, to find both of their sides. Without them,
+or on architectures which can't be decoded, only branches traced with
+branch hooks are listed, without where the missing side goes.
+
+options:
+    --arch <name>  architecture of the target, as in `qemu-<name>`
+                   [default: from the run's manifest]
+    --big-endian   the target is big endian, implied by the names of QEMU's
+                   big endian emulators
+    --overlay <file>
+                   label branches with the regions of the overlay file
+                   <file>, can be given multiple times";
+
+/// Get the architecture and endianness of QEMU's `qemu-<name>` emulator
+fn architecture(name: &str, big_endian: bool) -> Option<(Architecture, bool)> {
+    Some(match name {
+        "arm"    => (Architecture::Armv5tel, big_endian),
+        "armeb"  => (Architecture::Armv5teb, true),
+        "mips"   => (Architecture::Mips, true),
+        "mipsel" => (Architecture::Mips, false),
+        "aarch64_be" | "armv5teb" => (Architecture::from_name(name)?, true),
+        _ => (Architecture::from_name(name)?, big_endian),
+    })
+}
+
+/// What's known about a traced process
+#[derive(Default)]
+struct Process {
+    /// Every mapping ever made in the process, to read its code from the
+    /// files after it's unmapped
+    space: AddressSpace,
+
+    /// Control flow of every thread
+    threads: HashMap<i32, (CfgBuilder, FunctionInference)>,
+}
+
+/// Read `len` bytes of the code at `addr` from the file mapped there
+fn read_code(space: &AddressSpace, files: &mut HashMap<Arc<str>, Vec<u8>>,
+        addr: u64, len: usize) -> Option<Vec<u8>> {
+    let mapping = space.lookup(addr).filter(|x| !x.anon)?;
+    if !files.contains_key(&mapping.path) {
+        // Files which can't be read are only tried once
+        let data = std::fs::read(&*mapping.path).unwrap_or_default();
+        files.insert(mapping.path.clone(), data);
+    }
+    let file  = &files[&mapping.path];
+    let start = mapping.offset.checked_add(addr - mapping.base)? as usize;
+    let end   = start.saturating_add(len).min(file.len());
+    file.get(start..end).filter(|x| !x.is_empty()).map(|x| x.to_vec())
+}
+
+/// Format `addr` as an offset in its module, or as an address outside of
+/// files, with the label of its region of `overlay`
+fn location(space: &AddressSpace, overlay: &Overlay, addr: u64) -> String {
+    let label = overlay.resolve(space, addr)
+        .map_or(String::new(), |x| format!(" [{}]", overlay::label(x)));
+    match space.module_offset(addr) {
+        Some((module, offset)) => format!("{module}+{offset:#x}{label}"),
+        None => format!("{addr:#x}{label}"),
+    }
+}
+
+pub fn run(args: Args) -> Result<(), String> {
+    if args.positional().is_empty() {
+        return Err("no capture given".into());
+    }
+    let overlay = overlay::load(&args)?;
+
+    let mut processes: HashMap<(usize, u32, i32), Process> = HashMap::new();
+    let mut arch = None;
+    for (index, path) in args.positional().iter().enumerate() {
+        let failed = |x: std::io::Error| format!("failed to read {path}: {x}");
+
+        // QEMU user-mode emulators are named after the architecture
+        let name = match args.opt("arch") {
+            Some(name) => Some(name.to_string()),
+            None => CaptureReader::segments(path).map_err(failed)?.first()
+                .and_then(|x| x.manifest.as_ref())
+                .and_then(|x| x.qemu.path.file_name()?.to_str()?
+                    .strip_prefix("qemu-").map(String::from)),
+        };
+        if let Some(name) = name {
+            arch = Some(architecture(&name, args.switch("big-endian"))
+                .ok_or_else(|| format!("unknown architecture {name}"))?);
+        }
+
+        let mut reader = CaptureReader::open(path).map_err(failed)?;
+        let mut segment = 0;
+        while let Some(record) = reader.next_record().map_err(failed)? {
+            let (pid, tid, events) = match record {
+                Record::Segment(x) => {
+                    segment = x.index;
+                    continue;
+                }
+                Record::Events { pid, tid, events } => (pid, tid, events),
+            };
+
+            let process = processes.entry((index, segment, pid)).or_default();
+            let (cfg, functions) = process.threads.entry(tid).or_default();
+            for event in &events {
+                match event {
+                    Event::Mmap { base, len, anon, read, write, exec, path,
+                            offset } => {
+                        process.space.mmap(*base, *len, *anon, *read, *write,
+                            *exec, path, *offset);
+                    }
+                    Event::Exec { pc } | Event::Regs { pc, .. } |
+                        Event::Branch { pc, .. } => functions.observe(*pc),
+                    _ => {}
+                }
+                cfg.event(event);
+            }
+        }
+    }
+
+    let sigs = arch.and_then(|(arch, big_endian)| {
+        Signatures::new(arch, big_endian)
+    });
+    if sigs.is_none() {
+        eprintln!("branches can't be decoded for this target, only listing \
+            branches traced with branch hooks");
+    }
+
+    let mut files = HashMap::new();
+    let mut count = 0;
+    let mut keys  = processes.keys().copied().collect::<Vec<_>>();
+    keys.sort_unstable();
+    for key in keys {
+        let process = &processes[&key];
+        let mut cfg = CfgBuilder::new();
+        let mut functions = FunctionInference::new();
+        for (thread_cfg, thread_functions) in process.threads.values() {
+            cfg.merge(thread_cfg);
+            functions.merge(thread_functions);
+        }
+
+        for graph in cfg.build_inferred(&functions) {
+            let gaps = unreached_branches(&graph, sigs.as_ref(),
+                |addr, len| read_code(&process.space, &mut files, addr, len));
+            for UnreachedBranch { function, pc, taken, target, .. } in gaps {
+                let never = if taken { "never taken" } else { "always taken" };
+                let location = |x| location(&process.space, &overlay, x);
+                let target = target.map_or(String::new(),
+                    |x| format!(", to {}", location(x)));
+                println!("{} {never}{target} (in {})", location(pc),
+                    location(function));
+                count += 1;
+            }
+        }
+    }
+    eprintln!("{count} branches only went one way");
+    Ok(())
+}
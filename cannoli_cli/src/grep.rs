@@ -0,0 +1,91 @@
+//! `cannoli grep`, search a capture for events
+
+use cannoli::event::Event;
+use cannoli::grep::{Index, Query, Match};
+use crate::args::Args;
+
+pub const USAGE: &str = "\
+usage: cannoli grep [options] <capture> <term>...
+
+Prints every event of <capture> matching all of the terms:
+
+    exec read write mmap munmap heap rep   kinds of events, any of them
+    cmp
+    pc=<start>[-<end>]                     PC in a range, end exclusive
+    addr=<start>[-<end>]                   memory accessed or mapped overlaps
+                                           a range
+    value=<value>                          value loaded, stored or compared
+    pid=<pid> tid=<tid> segment=<index>    where the event comes from
+    module=<name>                          PC in a file whose name starts
+                                           with <name>
+    addr-module=<name>                     same, for the address accessed
+    in=[<module>!]<symbol>                 PC inside a function of a module
+
+Matches are printed as `<segment> <pid> <tid> #<event number> <event>`.
+An index of the capture is saved next to it to speed up later searches.
+
+options:
+    --max <count>   stop after <count> matches
+    --no-index      don't use or create an index";
+
+/// Describe the event of a match on one line
+fn describe(found: &Match) -> String {
+    let pc = |pc: u64| match &found.module {
+        Some((path, offset)) => format!("{pc:#x} ({}+{offset:#x})",
+            path.rsplit('/').next().unwrap_or(path)),
+        None => format!("{pc:#x}"),
+    };
+
+    match &found.event {
+        Event::Exec { pc: x } | Event::Regs { pc: x, .. } =>
+            format!("exec {}", pc(*x)),
+        Event::Branch { pc: x, taken, .. } =>
+            format!("branch {} {}", pc(*x),
+                if *taken { "taken" } else { "not-taken" }),
+        Event::Read { pc: x, addr, val, sz } =>
+            format!("read {} {addr:#x} {sz} {val:#x}", pc(*x)),
+        Event::Write { pc: x, addr, val, sz } =>
+            format!("write {} {addr:#x} {sz} {val:#x}", pc(*x)),
+        Event::ReadAddr { pc: x, addr, sz } =>
+            format!("read {} {addr:#x} {sz}", pc(*x)),
+        Event::WriteAddr { pc: x, addr, sz } =>
+            format!("write {} {addr:#x} {sz}", pc(*x)),
+        Event::Rep { pc: x, count, .. } =>
+            format!("rep {} {count}", pc(*x)),
+        Event::Cmp { pc: x, lhs, rhs, sz } =>
+            format!("cmp {} {sz} {lhs:#x} {rhs:#x}", pc(*x)),
+        Event::RegFile { pc: x, index, regs } =>
+            format!("regfile {} {index} {}", pc(*x), regs.len()),
+        Event::Signal { sig, from, to } =>
+            format!("signal {sig} {} {}", pc(*from), pc(*to)),
+        Event::Mmap { base, len, path, .. } =>
+            format!("mmap {base:#x} {len:#x} {path}"),
+        Event::Munmap { base, len } => format!("munmap {base:#x} {len:#x}"),
+        Event::Brk { old, new } => format!("brk {old:#x} {new:#x}"),
+        Event::Arena { base, len } => format!("arena {base:#x} {len:#x}"),
+    }
+}
+
+pub fn run(args: Args) -> Result<(), String> {
+    let [path, terms @ ..] = args.positional() else {
+        return Err("no capture given".into());
+    };
+    let query = Query::parse(terms)?;
+    let max = args.opt("max").map(|x| x.parse::<u64>())
+        .transpose().map_err(|_| "invalid --max")?;
+
+    let index = if args.switch("no-index") {
+        None
+    } else {
+        Some(Index::open(path)
+            .map_err(|x| format!("failed to index {path}: {x}"))?)
+    };
+
+    let mut count = 0;
+    cannoli::grep::search(path, &query, index.as_ref(), |found| {
+        println!("{} {} {} #{} {}", found.segment, found.pid, found.tid,
+            found.ordinal, describe(&found));
+        count += 1;
+        count < max.unwrap_or(u64::MAX)
+    }).map_err(|x| format!("failed to search {path}: {x}"))
+}
@@ -0,0 +1,129 @@
+//! Command line tool for working with Cannoli runs and captures
+//!
+//! `cannoli <command> [args]`, run `cannoli <command> --help` for the usage of
+//! each command
+
+mod args;
+mod overlay;
+mod run;
+mod repro;
+mod batch;
+mod annotate;
+mod segments;
+mod grep;
+mod diff;
+mod excerpt;
+mod dict;
+mod path;
+mod top;
+mod cover;
+mod migrate;
+mod gaps;
+mod daemon;
+mod coredump;
+mod record;
+mod perfetto;
+mod estimate;
+mod conformance;
+mod modules;
+mod suggest;
+
+use cannoli::harness::RunManifest;
+use args::Args;
+
+/// Entry point of a command
+type Command = fn(Args) -> Result<(), String>;
+
+/// Available commands, with their usage and entry point
+const COMMANDS: &[(&str, &str, &str, Command)] = &[
+    ("run",   "launch a target and record its manifest",
+        run::USAGE,   run::run),
+    ("repro", "re-execute a run from its manifest",
+        repro::USAGE, repro::run),
+    ("batch", "run a target once for every entry of a corpus",
+        batch::USAGE, batch::run),
+    ("annotate", "manage the annotation database shared between runs",
+        annotate::USAGE, annotate::run),
+    ("segments", "list the capture sessions in a capture",
+        segments::USAGE, segments::run),
+    ("grep",  "search a capture for events",
+        grep::USAGE, grep::run),
+    ("diff",  "find where two runs of a target first diverge",
+        diff::USAGE,  diff::run),
+    ("excerpt", "copy the events around events of interest to a new capture",
+        excerpt::USAGE, excerpt::run),
+    ("dict",  "generate a fuzzing dictionary from captures",
+        dict::USAGE,  dict::run),
+    ("path",  "export the path of a run for symbolic execution",
+        path::USAGE,  path::run),
+    ("top",   "list the most executed code of captures",
+        top::USAGE,   top::run),
+    ("cover", "list the code or source lines covered by captures",
+        cover::USAGE, cover::run),
+    ("migrate", "rewrite a capture for another version of the format",
+        migrate::USAGE, migrate::run),
+    ("gaps",  "list the conditional branches which only went one way",
+        gaps::USAGE,  gaps::run),
+    ("record", "record QEMU runs with a capture profile",
+        record::USAGE, record::run),
+    ("daemon", "record the runs of several tenants on a shared server",
+        daemon::USAGE, daemon::run),
+    ("coredump", "align a core dump of a traced process with its trace",
+        coredump::USAGE, coredump::run),
+    ("perfetto", "export the function calls of a capture for Perfetto",
+        perfetto::USAGE, perfetto::run),
+    ("estimate", "project the overhead of every capture profile",
+        estimate::USAGE, estimate::run),
+    ("conformance", "check QEMU builds against the client protocol",
+        conformance::USAGE, conformance::run),
+    ("modules", "report the load order and dependencies of modules",
+        modules::USAGE, modules::run),
+    ("suggest", "suggest filters which drop the hottest code of captures",
+        suggest::USAGE, suggest::run),
+];
+
+/// Switches accepted by any command
+const SWITCHES: &[&str] = &["help", "force", "list", "remove", "no-index",
+    "big-endian", "lines"];
+
+/// Run the target described by `manifest`, exiting with its exit code
+fn execute(manifest: &RunManifest) -> Result<(), String> {
+    let status = manifest.command().status()
+        .map_err(|x| format!("failed to run {}: {x}",
+            manifest.qemu.path.display()))?;
+    std::process::exit(status.code().unwrap_or(1));
+}
+
+/// Print the top-level usage and exit
+fn usage() -> ! {
+    eprintln!("usage: cannoli <command> [args]\n");
+    eprintln!("commands:");
+    for (name, desc, _, _) in COMMANDS {
+        eprintln!("    {name:<11} {desc}");
+    }
+    std::process::exit(1);
+}
+
+fn main() {
+    let mut argv = std::env::args().skip(1);
+    let command = argv.next().unwrap_or_else(|| usage());
+
+    let Some((_, _, help, func)) = COMMANDS.iter()
+            .find(|(name, ..)| *name == command) else {
+        usage();
+    };
+
+    let result = Args::parse(argv, SWITCHES).and_then(|args| {
+        if args.switch("help") {
+            println!("{help}");
+            Ok(())
+        } else {
+            func(args)
+        }
+    });
+
+    if let Err(err) = result {
+        eprintln!("error: {err}\n\n{help}");
+        std::process::exit(1);
+    }
+}
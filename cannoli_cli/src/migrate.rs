@@ -0,0 +1,34 @@
+//! `cannoli migrate`, rewrite a capture for another version of the format
+
+use cannoli::capture::FORMAT_VERSION;
+use cannoli::migrate;
+use crate::args::Args;
+
+pub const USAGE: &str = "\
+usage: cannoli migrate [options] <capture> <out>
+
+Rewrites <capture> as the new capture <out> in another version of the
+capture format, by default the one this build reads. Captures can be
+downgraded for older builds as long as the older format can hold what's in
+them.
+
+options:
+    --to <version>  version of the format to write
+    --force         replace <out> if it exists";
+
+pub fn run(args: Args) -> Result<(), String> {
+    let [path, out] = args.positional() else {
+        return Err("expected a capture and an output path".into());
+    };
+    let to = args.opt("to").map_or(Ok(FORMAT_VERSION), |x| x.parse())
+        .map_err(|_| "invalid --to")?;
+    if std::path::Path::new(out).exists() && !args.switch("force") {
+        return Err(format!("{out} exists, use --force to replace it"));
+    }
+
+    let stats = migrate::migrate(path, out, to)
+        .map_err(|x| format!("failed to migrate {path}: {x}"))?;
+    println!("Migrated {path} from version {} to {}, {} records ({} dropped)",
+        stats.from, stats.to, stats.records, stats.dropped);
+    Ok(())
+}
@@ -0,0 +1,72 @@
+//! `cannoli modules`, report the load order and dependencies of modules
+
+use std::collections::BTreeMap;
+use cannoli::address_space::AddressSpace;
+use cannoli::analysis::load_order::LoadOrder;
+use cannoli::capture::{CaptureReader, Record};
+use cannoli::event::Event;
+use crate::args::Args;
+
+pub const USAGE: &str = "\
+usage: cannoli modules <capture>
+
+Lists the modules of every process of <capture> in the order they were
+mapped, with how many instructions the process executed before each was
+mapped and before its code first executed. Modules mapped after the main
+binary started executing (eg. by `dlopen()`) are marked as runtime loads.
+The modules are followed by the first call from every module into another,
+see `cannoli::analysis::load_order`.";
+
+pub fn run(args: Args) -> Result<(), String> {
+    let [path] = args.positional() else {
+        return Err("expected exactly one capture".into());
+    };
+    let failed = |x: std::io::Error| format!("failed to read {path}: {x}");
+
+    // Processes are keyed by segment and PID
+    let mut processes: BTreeMap<(u32, i32), (AddressSpace, LoadOrder)> =
+        BTreeMap::new();
+    let mut reader  = CaptureReader::open(path).map_err(failed)?;
+    let mut segment = 0;
+    while let Some(record) = reader.next_record().map_err(failed)? {
+        let (pid, events) = match record {
+            Record::Segment(x) => {
+                segment = x.index;
+                continue;
+            }
+            Record::Events { pid, events, .. } => (pid, events),
+        };
+
+        let (space, order) = processes.entry((segment, pid)).or_default();
+        for event in &events {
+            match event {
+                Event::Mmap { base, len, anon, read, write, exec, path,
+                        offset } => {
+                    space.mmap(*base, *len, *anon, *read, *write, *exec,
+                        path, *offset);
+                    order.mmap(path, *exec);
+                }
+                Event::Munmap { base, len } => space.munmap(*base, *len),
+                Event::Exec { pc } | Event::Regs { pc, .. } |
+                    Event::Branch { pc, .. } => order.exec(space, *pc),
+                _ => {}
+            }
+        }
+    }
+
+    for ((segment, pid), (_, order)) in processes {
+        println!("segment {segment}, process {pid}");
+        for module in order.modules() {
+            let first = module.first_exec.map_or("-".into(), |x| x.to_string());
+            let runtime = if module.runtime { " (runtime)" } else { "" };
+            println!("  {:>12} {first:>12} {}{runtime}", module.mapped,
+                module.path);
+        }
+        for dep in order.dependencies() {
+            println!("  {} -> {} at {} ({:#x} -> {:#x})", dep.caller,
+                dep.callee, dep.insns, dep.from, dep.to);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,24 @@
+//! Overlays given with `--overlay`, shared by the commands listing code
+
+use std::io::IsTerminal;
+use cannoli::symbols::Overlay;
+use cannoli::symbols::overlay::Region;
+use crate::args::Args;
+
+/// Load the overlay files given with `--overlay`
+pub fn load(args: &Args) -> Result<Overlay, String> {
+    let mut overlay = Overlay::new();
+    for path in args.opts("overlay") {
+        overlay.load(path)
+            .map_err(|x| format!("failed to load overlay {path}: {x}"))?;
+    }
+    Ok(overlay)
+}
+
+/// Format the label of `region`, in its color when printing to a terminal
+pub fn label(region: &Region) -> String {
+    match region.ansi().filter(|_| std::io::stdout().is_terminal()) {
+        Some(color) => format!("{color}{}\x1b[0m", region.label),
+        None => region.label.to_string(),
+    }
+}
@@ -0,0 +1,72 @@
+//! `cannoli path`, export the path of a run for symbolic execution
+
+use cannoli::analysis::concolic::PathRecorder;
+use cannoli::capture::{CaptureReader, Record};
+use crate::args::Args;
+
+pub const USAGE: &str = "\
+usage: cannoli path [options] <capture>
+
+Exports the path the main thread of the first segment of a capture took, as
+JSON for a symbolic executor to pick up from: the branches it took (needs
+branch hooks), the operands of its comparisons (needs `cmp` events) with
+where they appear in the input, and the files it mapped.
+
+options:
+    --input <path>  the input of the run [default: the first input file of
+                    the run's manifest]
+    --out <path>    where to write the path [default: stdout]";
+
+pub fn run(args: Args) -> Result<(), String> {
+    let [path] = args.positional() else {
+        return Err("expected a single capture".into());
+    };
+    let failed = |x: std::io::Error| format!("failed to read {path}: {x}");
+
+    let segments = CaptureReader::segments(path).map_err(failed)?;
+    let manifest = segments.first().and_then(|x| x.manifest.as_ref());
+    let input = match (args.opt("input"), manifest) {
+        (Some(input), _) => input.into(),
+        (None, Some(manifest)) => manifest.inputs.first()
+            .map(|x| x.file.path.clone())
+            .ok_or("the run has no input file, give one with --input")?,
+        (None, None) => return Err("the capture has no manifest, give the \
+            input with --input".into()),
+    };
+    let input = std::fs::read(&input)
+        .map_err(|x| format!("failed to read {}: {x}", input.display()))?;
+
+    // QEMU user-mode emulators are named after the architecture
+    let arch = manifest.and_then(|x| x.qemu.path.file_name())
+        .and_then(|x| x.to_str()?.strip_prefix("qemu-"))
+        .map(String::from);
+
+    let mut recorder = PathRecorder::new(arch, input);
+    let mut reader   = CaptureReader::open(path).map_err(failed)?;
+    let mut thread   = None;
+    while let Some(record) = reader.next_record().map_err(failed)? {
+        match record {
+            Record::Segment(x) if x.index > 0 => break,
+            Record::Segment(_) => {}
+            Record::Events { pid, tid, events } => {
+                if *thread.get_or_insert((pid, tid)) == (pid, tid) {
+                    events.iter().for_each(|x| recorder.observe(x));
+                }
+            }
+        }
+    }
+
+    let seed = recorder.finish();
+    match args.opt("out") {
+        Some(out) => {
+            let file = std::fs::File::create(out)
+                .map_err(|x| format!("failed to create {out}: {x}"))?;
+            seed.write(std::io::BufWriter::new(file))
+        }
+        None => seed.write(std::io::stdout().lock()),
+    }.map_err(|x| format!("failed to write the path: {x}"))?;
+
+    eprintln!("{} branches, {} comparisons", seed.branches.len(),
+        seed.cmps.len());
+    Ok(())
+}
@@ -0,0 +1,38 @@
+//! `cannoli perfetto`, export a capture for ui.perfetto.dev
+
+use std::fs::File;
+use std::io::BufWriter;
+use cannoli::capture::{CaptureReader, Record};
+use cannoli::perfetto::TraceEventWriter;
+use crate::args::Args;
+
+pub const USAGE: &str = "\
+usage: cannoli perfetto <capture> <output>
+
+Writes the function calls of <capture> to <output> in the Chrome Trace Event
+format, to open in ui.perfetto.dev or chrome://tracing. Every thread is a
+track of its process, and every call a slice named after its function.
+
+Calls are recognized from the executed instructions, so the capture needs
+instruction events. A thread's clock is the number of events it traced,
+durations don't compare between threads.";
+
+pub fn run(args: Args) -> Result<(), String> {
+    let [capture, output] = args.positional() else {
+        return Err("expected a capture and an output file".into());
+    };
+    let failed = |x: std::io::Error| format!("failed to read {capture}: {x}");
+    let written = |x: std::io::Error| format!("failed to write {output}: {x}");
+
+    let file = File::create(output).map_err(written)?;
+    let mut writer = TraceEventWriter::new(BufWriter::new(file))
+        .map_err(written)?;
+    let mut reader = CaptureReader::open(capture).map_err(failed)?;
+    while let Some(record) = reader.next_record().map_err(failed)? {
+        if let Record::Events { pid, tid, events } = record {
+            writer.events(pid, tid, &events).map_err(written)?;
+        }
+    }
+    let mut out = writer.finish().map_err(written)?;
+    std::io::Write::flush(&mut out).map_err(written)
+}
@@ -0,0 +1,49 @@
+//! `cannoli record`, record QEMU runs with a capture profile
+
+use cannoli::CannoliOpts;
+use cannoli::profile::Profile;
+use crate::args::Args;
+
+pub const USAGE: &str = "\
+usage: cannoli record [options]
+
+Runs a Cannoli server which records the QEMU runs connecting to it, with the
+hooks and the output of a capture profile. Start the targets with `cannoli
+run` once it's listening. Profiles, from the least to the most overhead:
+
+    coverage       every instruction once, written as drcov files
+    syscall-audit  the registers at every system call, to a capture
+    memory-audit   reads and writes with their values, to a capture
+    full           everything, including system calls, to a capture
+
+Captures are written to `capture.cap` in the output directory.
+
+options:
+    --profile <name>   capture profile [default: full]
+    --output <dir>     directory to write to [default: .]
+    --module <name>    only hook code in the module <name>, given by its path
+                       or the start of its file name, can be given multiple
+                       times
+    --threads <count>  processing threads for every connection [default: 4]";
+
+pub fn run(args: Args) -> Result<(), String> {
+    if !args.positional().is_empty() {
+        return Err("unexpected arguments".into());
+    }
+    let name = args.opt("profile").unwrap_or("full");
+    let profile = Profile::parse(name)
+        .ok_or_else(|| format!("unknown profile `{name}`"))?;
+    let threads = args.opt("threads").map_or(Ok(4), |x| x.parse())
+        .map_err(|_| "invalid --threads")?;
+
+    let output = args.opt("output").unwrap_or(".");
+    profile.configure(output.as_ref())
+        .map_err(|x| format!("failed to create the output in {output}: {x}"))?;
+
+    let mut opts = CannoliOpts::new(threads);
+    for module in args.opts("module") {
+        opts = opts.module(module);
+    }
+    eprintln!("Recording with the {profile} profile into {output}");
+    profile.serve(opts).map_err(|x| format!("server failed: {x:?}"))
+}
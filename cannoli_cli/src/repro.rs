@@ -0,0 +1,42 @@
+//! `cannoli repro`, re-execute a run from its manifest
+
+use cannoli::harness::RunManifest;
+use crate::args::Args;
+
+pub const USAGE: &str = "\
+usage: cannoli repro [--force] <manifest>
+
+Re-executes the run described by <manifest> with the same QEMU, jitter,
+guest, arguments, environment and working directory. Start the Cannoli client
+before running this.
+
+options:
+    --force    run even if files have changed since the manifest was written";
+
+pub fn run(args: Args) -> Result<(), String> {
+    let [path] = args.positional() else {
+        return Err("expected exactly one manifest".into());
+    };
+
+    let manifest = RunManifest::load(path)
+        .map_err(|x| format!("failed to load {path}: {x}"))?;
+
+    // Make sure we'd actually be running the same thing
+    let problems = manifest.verify();
+    for problem in &problems {
+        eprintln!("warning: {problem}");
+    }
+    if !problems.is_empty() && !args.switch("force") {
+        return Err("the run can't be reproduced exactly, use --force to run \
+            it anyway".into());
+    }
+
+    if manifest.host != cannoli::harness::manifest::HostInfo::current() {
+        eprintln!("note: the run was recorded on a different host ({} {})",
+            manifest.host.hostname, manifest.host.kernel);
+    }
+
+    manifest.stage_inputs()
+        .map_err(|x| format!("failed to copy inputs into place: {x}"))?;
+    crate::execute(&manifest)
+}
@@ -0,0 +1,51 @@
+//! `cannoli run`, launch a target and record its manifest
+
+use cannoli::harness::RunManifest;
+use crate::args::Args;
+
+/// Default path the manifest is written to
+const DEFAULT_MANIFEST: &str = "cannoli.manifest.json";
+
+pub const USAGE: &str = "\
+usage: cannoli run --qemu <qemu> --jitter <jitter.so> [options] <guest> [args]
+
+Runs <guest> under QEMU with the Cannoli hooks in <jitter.so>, and writes a
+manifest describing the run so it can be reproduced with `cannoli repro`.
+Start the Cannoli client before running this.
+
+options:
+    --manifest <path>   where to write the manifest, should be next to the
+                        capture [default: cannoli.manifest.json]
+    --qemu-arg <arg>    extra argument to pass to QEMU, may be repeated
+    --seed <seed>       make the guest's random numbers (getrandom() and
+                        AT_RANDOM) the same in every run with this seed
+    --time <secs>       make the guest's time (time(), gettimeofday() and
+                        clock_gettime()) start at these seconds since the
+                        Unix epoch in every run, advancing 1 ms per read";
+
+pub fn run(args: Args) -> Result<(), String> {
+    let [guest, argv @ ..] = args.positional() else {
+        return Err("no guest binary given".into());
+    };
+
+    let mut manifest = RunManifest::new(args.required("qemu")?,
+        args.required("jitter")?, guest, argv)
+        .map_err(|x| format!("failed to create manifest: {x}"))?;
+    manifest.qemu_args = args.opts("qemu-arg").to_vec();
+    if let Some(seed) = args.opt("seed") {
+        manifest.deterministic_rng(seed.parse()
+            .map_err(|_| format!("invalid --seed `{seed}`"))?);
+    }
+    if let Some(time) = args.opt("time") {
+        let nanos = time.parse::<u64>().ok().and_then(|x| {
+            x.checked_mul(1_000_000_000)
+        }).ok_or_else(|| format!("invalid --time `{time}`"))?;
+        manifest.deterministic_time(nanos);
+    }
+
+    let path = args.opt("manifest").unwrap_or(DEFAULT_MANIFEST);
+    manifest.save(path)
+        .map_err(|x| format!("failed to write {path}: {x}"))?;
+
+    crate::execute(&manifest)
+}
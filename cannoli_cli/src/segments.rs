@@ -0,0 +1,32 @@
+//! `cannoli segments`, list the capture sessions in a capture
+
+use cannoli::capture::CaptureReader;
+use crate::args::Args;
+
+pub const USAGE: &str = "\
+usage: cannoli segments <capture>
+
+Lists the segments of <capture>, one for every capture session that wrote to
+it, with the time each session started and the guest it ran. The offsets can
+be used to jump straight to a segment with `CaptureReader::seek_segment`.";
+
+pub fn run(args: Args) -> Result<(), String> {
+    let [path] = args.positional() else {
+        return Err("expected exactly one capture".into());
+    };
+
+    let segments = CaptureReader::segments(path)
+        .map_err(|x| format!("failed to read {path}: {x}"))?;
+
+    for segment in segments {
+        let guest = segment.manifest.as_ref().map_or("-".into(), |x| {
+            std::iter::once(x.guest.path.display().to_string())
+                .chain(x.argv.iter().cloned())
+                .collect::<Vec<_>>().join(" ")
+        });
+        println!("{:4} {:>12} {:#14x} {guest}", segment.index,
+            segment.started, segment.offset);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,89 @@
+//! `cannoli suggest`, suggest filters which drop the hottest code of
+//! captures
+
+use std::collections::HashMap;
+use cannoli::address_space::AddressSpace;
+use cannoli::analysis::suggest::FilterSuggester;
+use cannoli::capture::{CaptureReader, Record};
+use cannoli::config::Config;
+use cannoli::event::Event;
+use crate::args::Args;
+
+pub const USAGE: &str = "\
+usage: cannoli suggest [options] <capture>...
+
+Counts the events every block of code produced in the captures, and prints
+the `exclude` rule of a config file which drops the fewest blocks
+responsible for --share of the events, hottest first, with their share and
+where they are. Take a short capture with the filters of the long one, and
+paste the rule into its config file (or give the file with --config to
+keep the ranges it already excludes).
+
+Ranges are addresses, so they fit later runs of the same target under QEMU
+as long as it maps its code at the same addresses.
+
+options:
+    --share <percent>  share of the events to drop [default: 80]
+    --max <count>      most blocks to exclude [default: 32]
+    --config <file>    config file the rule is for";
+
+pub fn run(args: Args) -> Result<(), String> {
+    if args.positional().is_empty() {
+        return Err("no capture given".into());
+    }
+    let share = args.opt("share").map_or(Ok(80.), |x| x.parse::<f64>()
+        .ok().filter(|x| (0. ..=100.).contains(x))
+        .ok_or_else(|| format!("invalid --share `{x}`")))?;
+    let max = args.opt("max").map_or(Ok(32), |x| x.parse()
+        .map_err(|_| format!("invalid --max `{x}`")))?;
+    let config = args.opt("config").map_or(Ok(Config::default()), |x| {
+        Config::load(x).map_err(|err| format!("failed to read {x}: {err}"))
+    })?;
+
+    let mut suggester = FilterSuggester::new();
+    let mut processes = Vec::new();
+    for path in args.positional() {
+        let failed = |x: std::io::Error| format!("failed to read {path}: {x}");
+        let mut reader = CaptureReader::open(path).map_err(failed)?;
+        let mut spaces: HashMap<(u32, i32), AddressSpace> = HashMap::new();
+        let mut threads: HashMap<(u32, i32, i32), FilterSuggester> =
+            HashMap::new();
+        let mut segment = 0;
+        while let Some(record) = reader.next_record().map_err(failed)? {
+            let (pid, tid, events) = match record {
+                Record::Segment(x) => {
+                    segment = x.index;
+                    continue;
+                }
+                Record::Events { pid, tid, events } => (pid, tid, events),
+            };
+
+            let space  = spaces.entry((segment, pid)).or_default();
+            let thread = threads.entry((segment, pid, tid)).or_default();
+            for event in &events {
+                match event {
+                    Event::Mmap { base, len, anon, read, write, exec, path,
+                            offset } => {
+                        space.mmap(*base, *len, *anon, *read, *write, *exec,
+                            path, *offset);
+                    }
+                    Event::Munmap { base, len } => space.munmap(*base, *len),
+                    _ => thread.event(event),
+                }
+            }
+        }
+
+        for thread in threads.values() {
+            suggester.merge(thread);
+        }
+        processes.extend(spaces.into_values());
+    }
+
+    // Name blocks by the module they're in, in any process which has it
+    let label = |pc: u64| processes.iter().find_map(|x| x.module_offset(pc))
+        .map(|(module, offset)| format!("{module}+{offset:#x}"));
+
+    let suggestion = suggester.suggest(share / 100., max);
+    suggestion.write(std::io::stdout().lock(), &config.filters, label)
+        .map_err(|x| format!("failed to write the filters: {x}"))
+}
@@ -0,0 +1,122 @@
+//! `cannoli top`, list the most executed code of captures
+
+use std::sync::Arc;
+use std::collections::HashMap;
+use cannoli::address_space::AddressSpace;
+use cannoli::analysis::topk::TopK;
+use cannoli::capture::{CaptureReader, Record};
+use cannoli::event::Event;
+use crate::args::Args;
+use crate::overlay;
+
+pub const USAGE: &str = "\
+usage: cannoli top [options] <capture>...
+
+Lists the most executed instructions of the captures, in memory which
+doesn't grow with the size of the captures. Instructions in files are
+counted by module and offset, so they add up across processes and segments
+regardless of ASLR. Counts are upper bounds, shown with how much they may be
+too high by. Instructions executed more than 1/<capacity> of the time are
+always listed.
+
+With overlays, instructions are labelled with the region of the overlay
+they're in, and the share of every label is listed after them, so the
+time spent can be read by component. See `cannoli::symbols::overlay` for
+the format of overlay files.
+
+options:
+    --count <count>     number of instructions to list [default: 20]
+    --capacity <count>  number of instructions to keep counts for
+                        [default: 4096]
+    --overlay <file>    label code with the regions of the overlay file
+                        <file>, can be given multiple times";
+
+pub fn run(args: Args) -> Result<(), String> {
+    if args.positional().is_empty() {
+        return Err("no capture given".into());
+    }
+    let number = |name: &str, default: usize| {
+        args.opt(name).map_or(Ok(default), |x| x.parse()
+            .map_err(|_| format!("invalid --{name} `{x}`")))
+    };
+    let count    = number("count", 20)?;
+    let capacity = number("capacity", 4096)?;
+    let overlay  = overlay::load(&args)?;
+
+    // Keyed by module and offset, or by address outside of files
+    let mut hot: TopK<(Option<Arc<str>>, u64)> = TopK::new(capacity);
+
+    // Exact counts of every label of the overlay, few enough to keep all
+    let mut labels: HashMap<Option<String>, u64> = HashMap::new();
+    for path in args.positional() {
+        let failed = |x: std::io::Error| format!("failed to read {path}: {x}");
+        let mut reader = CaptureReader::open(path).map_err(failed)?;
+        let mut spaces: HashMap<(u32, i32), AddressSpace> = HashMap::new();
+        let mut segment = 0;
+        while let Some(record) = reader.next_record().map_err(failed)? {
+            let (pid, events) = match record {
+                Record::Segment(x) => {
+                    segment = x.index;
+                    continue;
+                }
+                Record::Events { pid, events, .. } => (pid, events),
+            };
+
+            let space = spaces.entry((segment, pid)).or_default();
+            for event in &events {
+                let pc = match event {
+                    Event::Mmap { base, len, anon, read, write, exec, path,
+                            offset } => {
+                        space.mmap(*base, *len, *anon, *read, *write, *exec,
+                            path, *offset);
+                        continue;
+                    }
+                    Event::Munmap { base, len } => {
+                        space.munmap(*base, *len);
+                        continue;
+                    }
+                    Event::Exec { pc } | Event::Regs { pc, .. } |
+                        Event::Branch { pc, .. } => *pc,
+                    _ => continue,
+                };
+
+                let key = match space.module_offset(pc) {
+                    Some((module, offset)) => (Some(module), offset),
+                    None => (None, pc),
+                };
+                if !overlay.is_empty() {
+                    let region = overlay.lookup(key.0.as_deref(), key.1);
+                    *labels.entry(region.map(|x| x.label.clone()))
+                        .or_default() += 1;
+                }
+                hot.add(key, 1);
+            }
+        }
+    }
+
+    let total = hot.total().max(1);
+    for counter in hot.top(count) {
+        let location = match &counter.key {
+            (Some(module), offset) => format!("{module}+{offset:#x}"),
+            (None, pc) => format!("{pc:#x}"),
+        };
+        let label = overlay.lookup(counter.key.0.as_deref(), counter.key.1)
+            .map_or(String::new(), |x| format!(" [{}]", overlay::label(x)));
+        println!("{:>12} {:>6.2}% ±{:<10} {location}{label}", counter.count,
+            counter.count as f64 * 100. / total as f64, counter.error);
+    }
+
+    // Share of every label, most executed first
+    let mut labels = labels.into_iter().collect::<Vec<_>>();
+    labels.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    if !labels.is_empty() {
+        println!();
+    }
+    for (label, count) in labels {
+        let label = label.as_deref().unwrap_or("(unlabelled)");
+        println!("{count:>12} {:>6.2}% {label}",
+            count as f64 * 100. / total as f64);
+    }
+
+    Ok(())
+}
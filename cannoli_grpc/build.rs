@@ -0,0 +1,41 @@
+//! Generate the gRPC service stubs
+//!
+//! The messages are written by hand in `src/proto.rs` so we don't need
+//! `protoc` to build, `proto/cannoli.proto` is the matching definition for
+//! clients in other languages. Keep the two in sync!
+
+use tonic_build::manual::{Builder, Method, Service};
+
+/// Build a method of the `TraceQuery` service
+fn method(name: &str, route: &str, input: &str, output: &str,
+        streaming: bool) -> Method {
+    let mut method = Method::builder()
+        .name(name)
+        .route_name(route)
+        .input_type(format!("crate::proto::{input}"))
+        .output_type(format!("crate::proto::{output}"))
+        .codec_path("tonic_prost::ProstCodec");
+
+    if streaming {
+        method = method.server_streaming();
+    }
+
+    method.build()
+}
+
+fn main() {
+    let service = Service::builder()
+        .name("TraceQuery")
+        .package("cannoli")
+        .method(method("get_coverage", "GetCoverage",
+            "CoverageRequest", "CoverageResponse", false))
+        .method(method("get_hot_pcs", "GetHotPcs",
+            "HotPcsRequest", "HotPcsResponse", false))
+        .method(method("stream_events", "StreamEvents",
+            "EventFilter", "Event", true))
+        .method(method("read_guest_memory", "ReadGuestMemory",
+            "ReadGuestMemoryRequest", "ReadGuestMemoryResponse", false))
+        .build();
+
+    Builder::new().compile(&[service]);
+}
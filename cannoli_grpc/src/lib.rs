@@ -0,0 +1,373 @@
+//! gRPC server exposing the live trace of a Cannoli client
+//!
+//! This lets external systems (fuzzing clusters, CI, etc) consume the data
+//! cannoli collects without having to link Rust. [`LiveTrace`] is a
+//! [`Cannoli`] implementation which records coverage and publishes events,
+//! and [`spawn_server`] serves them with the `cannoli.TraceQuery` service
+//! described in `proto/cannoli.proto`:
+//!
+//! - `GetCoverage` returns the set of PCs executed so far
+//! - `GetHotPcs` returns the most executed PCs so far, counted in bounded
+//!   memory with [`cannoli::analysis::topk`]
+//! - `StreamEvents` streams events from the live trace matching a filter
+//! - `ReadGuestMemory` reads memory from a guest process
+//!
+//! Events are only published while at least one client is streaming, chunks
+//! are dropped for clients which fall too far behind.
+//!
+//! `ReadGuestMemory` currently always fails with `UNIMPLEMENTED`, the client
+//! only sees what the jitter streams to it and has no way to access guest
+//! memory yet.
+
+use std::thread::JoinHandle;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, LazyLock};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use cannoli::{Cannoli, ClientInfo};
+use cannoli::analysis::topk::TopK;
+use tokio::sync::{broadcast, mpsc};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+use tonic::transport::Server;
+use tonic::transport::server::TcpIncoming;
+
+pub mod proto;
+
+use proto::{Event, EventKind, EventFilter};
+use proto::{CoverageRequest, CoverageResponse};
+use proto::{HotPc, HotPcsRequest, HotPcsResponse};
+use proto::{ReadGuestMemoryRequest, ReadGuestMemoryResponse};
+use proto::trace_query_server::{TraceQuery, TraceQueryServer};
+
+/// Default address for the gRPC server
+pub const DEFAULT_ADDR: &str = "127.0.0.1:50051";
+
+/// Number of trace chunks buffered for streaming clients. Clients which fall
+/// further behind than this miss events
+const EVENT_BACKLOG: usize = 1024;
+
+/// Number of events buffered per streaming client between the filter and the
+/// connection
+const STREAM_BACKLOG: usize = 64 * 1024;
+
+/// Number of counters kept per process for `GetHotPcs`. PCs executed more
+/// than 1/`HOT_PCS` of the time are always counted
+const HOT_PCS: usize = 4096;
+
+/// Number of PCs `GetHotPcs` returns if the request doesn't say
+const DEFAULT_HOT_PCS: u32 = 20;
+
+/// Errors for this crate
+#[derive(Debug)]
+pub enum Error {
+    /// Failed to create the async runtime for the server
+    Runtime(std::io::Error),
+
+    /// Failed to bind the server's address
+    Bind(std::io::Error),
+
+    /// The server failed while running
+    Serve(tonic::transport::Error),
+}
+
+/// State of every process we've traced, keyed by PID
+static PROCESSES: LazyLock<Mutex<HashMap<i32, Arc<Process>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Channel trace chunks are published to for streaming clients
+static EVENTS: LazyLock<broadcast::Sender<Arc<Vec<Event>>>> =
+    LazyLock::new(|| broadcast::channel(EVENT_BACKLOG).0);
+
+/// What we know about a traced process
+pub struct Process {
+    /// Set of PCs executed
+    coverage: Mutex<HashSet<u64>>,
+
+    /// Approximate execution counts of the hottest PCs
+    hot: Mutex<TopK<u64>>,
+}
+
+impl Default for Process {
+    fn default() -> Self {
+        Self {
+            coverage: Default::default(),
+            hot:      Mutex::new(TopK::new(HOT_PCS)),
+        }
+    }
+}
+
+/// Identity of the target thread a [`LiveTrace`] is processing
+pub struct Thread {
+    /// Process ID
+    pid: i32,
+
+    /// Thread ID
+    tid: i32,
+}
+
+/// A [`Cannoli`] implementation which records coverage and publishes events
+/// for [`spawn_server`]
+pub struct LiveTrace;
+
+impl Cannoli for LiveTrace {
+    type Trace = Event;
+
+    type PidContext = Process;
+    type TidContext = Thread;
+
+    fn init_pid(ci: &ClientInfo) -> Arc<Self::PidContext> {
+        // Keep the process around after it exits so it can still be queried
+        PROCESSES.lock().unwrap().entry(ci.pid).or_default().clone()
+    }
+
+    fn init_tid(_pid: &Self::PidContext,
+            ci: &ClientInfo) -> (Self, Self::TidContext) {
+        (Self, Thread { pid: ci.pid, tid: ci.tid })
+    }
+
+    fn exec(_pid: &Self::PidContext, tid: &Self::TidContext,
+            pc: u64, trace: &mut Vec<Self::Trace>) {
+        trace.push(Event {
+            pid:  tid.pid,
+            tid:  tid.tid,
+            kind: EventKind::Exec as i32,
+            pc,
+            ..Default::default()
+        });
+    }
+
+    fn regs(_pid: &Self::PidContext, tid: &Self::TidContext,
+            pc: u64, regs: &[u8], trace: &mut Vec<Self::Trace>) {
+        trace.push(Event {
+            pid:  tid.pid,
+            tid:  tid.tid,
+            kind: EventKind::Regs as i32,
+            regs: regs.to_vec(),
+            pc,
+            ..Default::default()
+        });
+    }
+
+    fn branch(_pid: &Self::PidContext, tid: &Self::TidContext,
+            pc: u64, branch: bool, regs: &[u8],
+            trace: &mut Vec<Self::Trace>) {
+        trace.push(Event {
+            pid:   tid.pid,
+            tid:   tid.tid,
+            kind:  EventKind::Branch as i32,
+            taken: branch,
+            regs:  regs.to_vec(),
+            pc,
+            ..Default::default()
+        });
+    }
+
+    fn read(_pid: &Self::PidContext, tid: &Self::TidContext,
+            pc: u64, addr: u64, val: u64, sz: u8, _helper: bool,
+            trace: &mut Vec<Self::Trace>) {
+        trace.push(Event {
+            pid:   tid.pid,
+            tid:   tid.tid,
+            kind:  EventKind::Read as i32,
+            value: val,
+            size:  sz as u32,
+            pc, addr,
+            ..Default::default()
+        });
+    }
+
+    fn write(_pid: &Self::PidContext, tid: &Self::TidContext,
+            pc: u64, addr: u64, val: u64, sz: u8, _helper: bool,
+            trace: &mut Vec<Self::Trace>) {
+        trace.push(Event {
+            pid:   tid.pid,
+            tid:   tid.tid,
+            kind:  EventKind::Write as i32,
+            value: val,
+            size:  sz as u32,
+            pc, addr,
+            ..Default::default()
+        });
+    }
+
+    fn mmap(_pid: &Self::PidContext, tid: &Self::TidContext,
+            base: u64, len: u64, anon: bool, read: bool, write: bool,
+            exec: bool, path: &str, offset: u64,
+            trace: &mut Vec<Self::Trace>) {
+        let prot = if read  { proto::PROT_READ  } else { 0 } |
+                   if write { proto::PROT_WRITE } else { 0 } |
+                   if exec  { proto::PROT_EXEC  } else { 0 } |
+                   if anon  { proto::PROT_ANON  } else { 0 };
+        trace.push(Event {
+            pid:  tid.pid,
+            tid:  tid.tid,
+            kind: EventKind::Mmap as i32,
+            addr: base,
+            path: path.to_string(),
+            len, offset, prot,
+            ..Default::default()
+        });
+    }
+
+    fn munmap(_pid: &Self::PidContext, tid: &Self::TidContext,
+            base: u64, len: u64, trace: &mut Vec<Self::Trace>) {
+        trace.push(Event {
+            pid:  tid.pid,
+            tid:  tid.tid,
+            kind: EventKind::Munmap as i32,
+            addr: base,
+            len,
+            ..Default::default()
+        });
+    }
+
+    fn trace(&mut self, pid: &Self::PidContext,
+            _tid: &Self::TidContext, trace: &[Self::Trace]) {
+        // Record coverage and execution counts
+        {
+            let mut coverage = pid.coverage.lock().unwrap();
+            let mut hot      = pid.hot.lock().unwrap();
+            for event in trace {
+                if event.kind == EventKind::Exec as i32 {
+                    coverage.insert(event.pc);
+                    hot.add(event.pc, 1);
+                }
+            }
+        }
+
+        // Publish the events if anyone is listening
+        if EVENTS.receiver_count() > 0 {
+            let _ = EVENTS.send(Arc::new(trace.to_vec()));
+        }
+    }
+}
+
+/// Implementation of the `TraceQuery` service
+struct Query;
+
+#[tonic::async_trait]
+impl TraceQuery for Query {
+    type StreamEventsStream = ReceiverStream<Result<Event, Status>>;
+
+    async fn get_coverage(&self, request: Request<CoverageRequest>)
+            -> Result<Response<CoverageResponse>, Status> {
+        let pid = request.into_inner().pid;
+
+        // Merge the coverage of the requested processes
+        let mut pcs = BTreeSet::new();
+        for (_, process) in PROCESSES.lock().unwrap().iter()
+                .filter(|(&x, _)| pid.is_none_or(|pid| pid == x)) {
+            pcs.extend(process.coverage.lock().unwrap().iter().copied());
+        }
+
+        Ok(Response::new(CoverageResponse {
+            pcs: pcs.into_iter().collect(),
+        }))
+    }
+
+    async fn get_hot_pcs(&self, request: Request<HotPcsRequest>)
+            -> Result<Response<HotPcsResponse>, Status> {
+        let request = request.into_inner();
+
+        // Merge the counts of the requested processes
+        let mut hot = TopK::new(HOT_PCS);
+        for (_, process) in PROCESSES.lock().unwrap().iter()
+                .filter(|(&x, _)| request.pid.is_none_or(|pid| pid == x)) {
+            hot.merge(&process.hot.lock().unwrap());
+        }
+
+        let count = request.count.unwrap_or(DEFAULT_HOT_PCS) as usize;
+        Ok(Response::new(HotPcsResponse {
+            pcs: hot.top(count).into_iter().map(|x| HotPc {
+                pc:    x.key,
+                count: x.count,
+                error: x.error,
+            }).collect(),
+            total: hot.total(),
+        }))
+    }
+
+    async fn stream_events(&self, request: Request<EventFilter>)
+            -> Result<Response<Self::StreamEventsStream>, Status> {
+        let filter = request.into_inner();
+        let mut events = EVENTS.subscribe();
+        let (tx, rx) = mpsc::channel(STREAM_BACKLOG);
+
+        // Forward matching events until the client goes away
+        tokio::spawn(async move {
+            loop {
+                let chunk = match events.recv().await {
+                    Ok(chunk) => chunk,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+
+                for event in chunk.iter().filter(|x| filter.matches(x)) {
+                    if tx.send(Ok(event.clone())).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn read_guest_memory(&self,
+            _request: Request<ReadGuestMemoryRequest>)
+            -> Result<Response<ReadGuestMemoryResponse>, Status> {
+        Err(Status::unimplemented(
+            "guest memory is not available to the cannoli client"))
+    }
+}
+
+/// Start the gRPC server on `addr` in a background thread
+///
+/// The address is bound before this returns, errors after that are returned
+/// from the thread
+pub fn spawn_server(addr: SocketAddr)
+        -> Result<JoinHandle<Result<(), Error>>, Error> {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .map_err(Error::Runtime)?;
+
+    // Bind the address up front so the caller learns about failures
+    let incoming = {
+        let _guard = runtime.enter();
+        TcpIncoming::bind(addr).map_err(Error::Bind)?
+    };
+
+    Ok(std::thread::spawn(move || {
+        runtime.block_on(Server::builder()
+            .add_service(TraceQueryServer::new(Query))
+            .serve_with_incoming(incoming))
+            .map_err(Error::Serve)
+    }))
+}
+
+#[test]
+fn event_filter() {
+    let event = Event {
+        pid:  10,
+        kind: EventKind::Read as i32,
+        pc:   0x1000,
+        ..Default::default()
+    };
+
+    assert!(EventFilter::default().matches(&event));
+    assert!(EventFilter {
+        kinds:    vec![EventKind::Read as i32, EventKind::Write as i32],
+        pid:      Some(10),
+        pc_start: Some(0x1000),
+        pc_end:   Some(0x1001),
+    }.matches(&event));
+    assert!(!EventFilter {
+        kinds: vec![EventKind::Exec as i32],
+        ..Default::default()
+    }.matches(&event));
+    assert!(!EventFilter {
+        pc_end: Some(0x1000),
+        ..Default::default()
+    }.matches(&event));
+}
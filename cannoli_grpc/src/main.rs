@@ -0,0 +1,21 @@
+//! Serve the live trace over gRPC
+//!
+//! `cannoli_grpc [addr] [threads]`
+
+use cannoli::create_cannoli;
+use cannoli_grpc::{LiveTrace, DEFAULT_ADDR};
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+
+    let addr = args.next().unwrap_or_else(|| DEFAULT_ADDR.to_string())
+        .parse().expect("Invalid server address");
+    let threads = args.next()
+        .map(|x| x.parse().expect("Invalid thread count"))
+        .unwrap_or(2);
+
+    cannoli_grpc::spawn_server(addr).expect("Failed to start gRPC server");
+    println!("Serving cannoli.TraceQuery on {addr}");
+
+    create_cannoli::<LiveTrace>(threads).unwrap();
+}
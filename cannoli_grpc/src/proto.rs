@@ -0,0 +1,198 @@
+//! Messages of the `TraceQuery` service, matching `proto/cannoli.proto`
+
+/// `prot` bit set for readable mappings
+pub const PROT_READ: u32 = 1 << 0;
+
+/// `prot` bit set for writable mappings
+pub const PROT_WRITE: u32 = 1 << 1;
+
+/// `prot` bit set for executable mappings
+pub const PROT_EXEC: u32 = 1 << 2;
+
+/// `prot` bit set for anonymous mappings
+pub const PROT_ANON: u32 = 1 << 3;
+
+/// Kinds of events in the trace
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[derive(prost::Enumeration)]
+#[repr(i32)]
+pub enum EventKind {
+    Exec   = 0,
+    Read   = 1,
+    Write  = 2,
+    Regs   = 3,
+    Branch = 4,
+    Mmap   = 5,
+    Munmap = 6,
+}
+
+/// A single event from the trace, fields which don't apply to the kind of
+/// event are left as zero
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Event {
+    /// Process ID
+    #[prost(int32, tag = "1")]
+    pub pid: i32,
+
+    /// Thread ID
+    #[prost(int32, tag = "2")]
+    pub tid: i32,
+
+    /// Kind of event, an [`EventKind`]
+    #[prost(enumeration = "EventKind", tag = "3")]
+    pub kind: i32,
+
+    /// PC of the instruction which caused the event
+    #[prost(uint64, tag = "4")]
+    pub pc: u64,
+
+    /// Address accessed, or base of the mapping for `Mmap` and `Munmap`
+    #[prost(uint64, tag = "5")]
+    pub addr: u64,
+
+    /// Length of the mapping for `Mmap` and `Munmap`
+    #[prost(uint64, tag = "6")]
+    pub len: u64,
+
+    /// Value loaded or stored
+    #[prost(uint64, tag = "7")]
+    pub value: u64,
+
+    /// Size of the memory access in bytes
+    #[prost(uint32, tag = "8")]
+    pub size: u32,
+
+    /// Whether the branch was taken
+    #[prost(bool, tag = "9")]
+    pub taken: bool,
+
+    /// Raw register state for `Regs` and `Branch`
+    #[prost(bytes = "vec", tag = "10")]
+    pub regs: Vec<u8>,
+
+    /// Path of the file backing the mapping
+    #[prost(string, tag = "11")]
+    pub path: String,
+
+    /// Offset into the file backing the mapping
+    #[prost(uint64, tag = "12")]
+    pub offset: u64,
+
+    /// Bitmask of the `PROT_*` constants for the mapping
+    #[prost(uint32, tag = "13")]
+    pub prot: u32,
+}
+
+/// Filter for the events returned by `StreamEvents`
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct EventFilter {
+    /// Kinds of events to return, empty returns every kind
+    #[prost(enumeration = "EventKind", repeated, tag = "1")]
+    pub kinds: Vec<i32>,
+
+    /// Only return events from this process
+    #[prost(int32, optional, tag = "2")]
+    pub pid: Option<i32>,
+
+    /// Only return events with a PC at or above this
+    #[prost(uint64, optional, tag = "3")]
+    pub pc_start: Option<u64>,
+
+    /// Only return events with a PC below this
+    #[prost(uint64, optional, tag = "4")]
+    pub pc_end: Option<u64>,
+}
+
+impl EventFilter {
+    /// Check if `event` passes the filter
+    pub fn matches(&self, event: &Event) -> bool {
+        (self.kinds.is_empty() || self.kinds.contains(&event.kind)) &&
+            self.pid.is_none_or(|pid| pid == event.pid) &&
+            self.pc_start.is_none_or(|start| event.pc >= start) &&
+            self.pc_end.is_none_or(|end| event.pc < end)
+    }
+}
+
+/// Request for `GetCoverage`
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct CoverageRequest {
+    /// Only return coverage for this process, otherwise coverage from all
+    /// processes is merged
+    #[prost(int32, optional, tag = "1")]
+    pub pid: Option<i32>,
+}
+
+/// Response for `GetCoverage`
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct CoverageResponse {
+    /// Sorted, unique, PCs which have been executed
+    #[prost(uint64, repeated, tag = "1")]
+    pub pcs: Vec<u64>,
+}
+
+/// Request for `GetHotPcs`
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct HotPcsRequest {
+    /// Only count this process, otherwise the counts of all processes are
+    /// merged
+    #[prost(int32, optional, tag = "1")]
+    pub pid: Option<i32>,
+
+    /// Number of PCs to return, 20 if not set
+    #[prost(uint32, optional, tag = "2")]
+    pub count: Option<u32>,
+}
+
+/// Approximate execution count of a PC
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct HotPc {
+    /// The PC
+    #[prost(uint64, tag = "1")]
+    pub pc: u64,
+
+    /// Number of executions, which is never less than the real number
+    #[prost(uint64, tag = "2")]
+    pub count: u64,
+
+    /// Maximum amount `count` is too high by
+    #[prost(uint64, tag = "3")]
+    pub error: u64,
+}
+
+/// Response for `GetHotPcs`
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct HotPcsResponse {
+    /// The most executed PCs, most executed first
+    #[prost(message, repeated, tag = "1")]
+    pub pcs: Vec<HotPc>,
+
+    /// Number of instructions executed in total
+    #[prost(uint64, tag = "2")]
+    pub total: u64,
+}
+
+/// Request for `ReadGuestMemory`
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ReadGuestMemoryRequest {
+    /// Process to read from
+    #[prost(int32, tag = "1")]
+    pub pid: i32,
+
+    /// Guest virtual address to read from
+    #[prost(uint64, tag = "2")]
+    pub addr: u64,
+
+    /// Number of bytes to read
+    #[prost(uint32, tag = "3")]
+    pub len: u32,
+}
+
+/// Response for `ReadGuestMemory`
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct ReadGuestMemoryResponse {
+    /// Bytes read
+    #[prost(bytes = "vec", tag = "1")]
+    pub data: Vec<u8>,
+}
+
+include!(concat!(env!("OUT_DIR"), "/cannoli.TraceQuery.rs"));
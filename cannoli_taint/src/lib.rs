@@ -0,0 +1,355 @@
+//! Taint tracking over Cannoli traces
+//!
+//! Bytes the target gets from the outside world through selected system
+//! calls (`read()` and `recvfrom()` by default) are tainted, and the taint
+//! follows the data as the trace shows it moving through memory. Whenever
+//! tainted data ends up as the PC, or as an argument of a system call, a
+//! [`Finding`] names the system call the data came from.
+//!
+//! Cannoli only sees memory, not registers, so propagation is inferred from
+//! the values loaded and stored:
+//!
+//! - A store of an instruction which loaded tainted data (eg. `movs`) is
+//!   tainted
+//! - A store of the same value and size as the last load of the thread is
+//!   tainted if that load was, which covers the byte by byte copy loops of
+//!   `memcpy()` and friends
+//! - A store of a value of at least [`MIN_VALUE_SIZE`] bytes which was
+//!   recently loaded from tainted memory is tainted
+//! - Any other store clears the taint of the bytes it writes
+//!
+//! Values computed from tainted data (eg. a checksum) lose their taint, and
+//! unrelated values which happen to be equal to a tainted one gain it, so
+//! this is an approximation meant to point at where to look. Loads need
+//! their values, see `cannoli::control::Filters::read_values`, and system
+//! calls need `cannoli::control::Filters::syscalls`.
+//!
+//! [`TaintMap`] is the taint of the memory of a process, shared by its
+//! threads, and [`Tracker`] follows a single thread, fed its events in
+//! trace order from `cannoli::Cannoli::trace`.
+
+use std::collections::{HashMap, VecDeque};
+use cannoli::Architecture;
+use cannoli::symbols::signatures::Signatures;
+
+/// Size of the pages of the shadow memory
+const PAGE_SIZE: u64 = 4096;
+
+/// Smallest load whose value is matched against later stores and sinks
+/// regardless of what came in between, smaller values are too common
+pub const MIN_VALUE_SIZE: u8 = 4;
+
+/// Number of recently loaded tainted values remembered by a thread
+const RECENT_VALUES: usize = 64;
+
+/// System calls which taint memory by default, with the index of the
+/// argument pointing to the buffer they fill
+const SOURCES: &[(&str, usize)] = &[("read", 1), ("recvfrom", 1)];
+
+/// Identifies where tainted data came from, an index into
+/// [`TaintMap::sources`] plus one. 0 is untainted
+pub type Label = u32;
+
+/// A system call which tainted memory
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Source {
+    /// Name of the system call
+    pub syscall: &'static str,
+
+    /// Address of the system call instruction
+    pub pc: u64,
+
+    /// Address of the buffer the system call filled
+    pub addr: u64,
+
+    /// Number of bytes it filled
+    pub len: u64,
+}
+
+/// Taint of the memory of a process, see the module documentation
+#[derive(Default)]
+pub struct TaintMap {
+    /// Label of every byte of the pages with taint, keyed by page number
+    pages: HashMap<u64, Box<[Label]>>,
+
+    /// Sources of the labels
+    sources: Vec<Source>,
+}
+
+impl TaintMap {
+    /// Create a map where nothing is tainted
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Taint the buffer `source` filled with a new label, and return it
+    pub fn add_source(&mut self, source: Source) -> Label {
+        self.sources.push(source);
+        let label = self.sources.len() as Label;
+        let source = &self.sources[label as usize - 1];
+        self.set(source.addr, source.len, label);
+        label
+    }
+
+    /// Get the source of the data tainted with `label`
+    pub fn source(&self, label: Label) -> Option<&Source> {
+        self.sources.get((label as usize).checked_sub(1)?)
+    }
+
+    /// Get every source, in the order they tainted memory
+    pub fn sources(&self) -> &[Source] {
+        &self.sources
+    }
+
+    /// Set the taint of `len` bytes at `addr` to `label`
+    pub fn set(&mut self, addr: u64, len: u64, label: Label) {
+        for addr in addr..addr.saturating_add(len) {
+            let page = addr / PAGE_SIZE;
+            let index = (addr % PAGE_SIZE) as usize;
+            if label == 0 {
+                if let Some(page) = self.pages.get_mut(&page) {
+                    page[index] = 0;
+                }
+            } else {
+                self.pages.entry(page)
+                    .or_insert_with(|| vec![0; PAGE_SIZE as usize].into())
+                    [index] = label;
+            }
+        }
+    }
+
+    /// Get the label of the first tainted byte of the `len` bytes at `addr`,
+    /// 0 if none of them is tainted
+    pub fn get(&self, addr: u64, len: u64) -> Label {
+        (addr..addr.saturating_add(len)).find_map(|addr| {
+            let label = self.pages.get(&(addr / PAGE_SIZE))?
+                [(addr % PAGE_SIZE) as usize];
+            (label != 0).then_some(label)
+        }).unwrap_or(0)
+    }
+}
+
+/// Where tainted data ended up
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Reach {
+    /// The PC, by jumping to a tainted value or executing tainted bytes
+    Pc,
+
+    /// Argument `index` of system call `nr`, named `name` if known
+    SyscallArg {
+        nr:    u64,
+        name:  Option<&'static str>,
+        index: usize,
+    },
+}
+
+/// Tainted data reaching a sink
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Finding {
+    /// Where the data ended up
+    pub reach: Reach,
+
+    /// Address of the instruction executed with tainted data
+    pub pc: u64,
+
+    /// The tainted value
+    pub value: u64,
+
+    /// Label of the data, see [`TaintMap::source`]
+    pub label: Label,
+}
+
+/// A load seen by a [`Tracker`]
+#[derive(Clone, Copy, Debug)]
+struct Load {
+    /// Value loaded
+    val: u64,
+
+    /// Size of the load in bytes
+    sz: u8,
+
+    /// Taint of the bytes loaded
+    label: Label,
+}
+
+/// Follows the taint of a single thread, see the module documentation
+pub struct Tracker {
+    /// Names of the system calls of the target
+    signatures: Signatures,
+
+    /// Source system calls, with the index of their buffer argument
+    sources: Vec<(&'static str, usize)>,
+
+    /// PC of the current instruction and the taint of what it loaded
+    insn: (u64, Label),
+
+    /// Last load of the thread
+    last: Option<Load>,
+
+    /// Tainted values of at least [`MIN_VALUE_SIZE`] bytes loaded recently,
+    /// newest last
+    recent: VecDeque<Load>,
+
+    /// Source system call in progress, its name, buffer and size
+    pending: Option<(&'static str, u64, u64)>,
+}
+
+impl Tracker {
+    /// Create a tracker for a thread of a target of `arch`, `None` if system
+    /// calls of `arch` aren't recognized
+    pub fn new(arch: Architecture, big_endian: bool) -> Option<Self> {
+        Some(Self {
+            signatures: Signatures::new(arch, big_endian)?,
+            sources:    SOURCES.to_vec(),
+            insn:       (0, 0),
+            last:       None,
+            recent:     VecDeque::new(),
+            pending:    None,
+        })
+    }
+
+    /// Also taint the buffer which argument `buf` of system call `name`
+    /// points to, for as many bytes as it returns
+    pub fn source(mut self, name: &'static str, buf: usize) -> Self {
+        self.sources.push((name, buf));
+        self
+    }
+
+    /// Find a recently loaded tainted value equal to `val`
+    fn recent(&self, val: u64) -> Option<Label> {
+        self.recent.iter().rev().find(|x| x.val == val).map(|x| x.label)
+    }
+
+    /// Observe the execution of the instruction at `pc`, appending what
+    /// reached it to `out`
+    pub fn exec(&mut self, map: &TaintMap, pc: u64, out: &mut Vec<Finding>) {
+        self.insn = (pc, 0);
+        let label = match map.get(pc, 1) {
+            0 => self.recent(pc).unwrap_or(0),
+            x => x,
+        };
+        if label != 0 {
+            out.push(Finding { reach: Reach::Pc, pc, value: pc, label });
+        }
+    }
+
+    /// Observe a load of `sz` bytes of value `val` from `addr` by the
+    /// instruction at `pc`
+    pub fn read(&mut self, map: &TaintMap, pc: u64, addr: u64, val: u64,
+            sz: u8) {
+        let load = Load { val, sz, label: map.get(addr, sz as u64) };
+        self.last = Some(load);
+        if load.label == 0 {
+            return;
+        }
+
+        if self.insn.0 == pc {
+            self.insn.1 = load.label;
+        }
+        if sz >= MIN_VALUE_SIZE {
+            if self.recent.len() == RECENT_VALUES {
+                self.recent.pop_front();
+            }
+            self.recent.push_back(load);
+        }
+    }
+
+    /// Observe a store of `sz` bytes of value `val` to `addr` by the
+    /// instruction at `pc`
+    pub fn write(&mut self, map: &mut TaintMap, pc: u64, addr: u64,
+            val: u64, sz: u8) {
+        let label = if self.insn.0 == pc && self.insn.1 != 0 {
+            self.insn.1
+        } else if let Some(last) = self.last
+                .filter(|x| x.val == val && x.sz == sz && x.label != 0) {
+            last.label
+        } else if sz >= MIN_VALUE_SIZE {
+            self.recent(val).unwrap_or(0)
+        } else {
+            0
+        };
+        map.set(addr, sz as u64, label);
+    }
+
+    /// Observe the system call instruction at `pc` making system call `nr`
+    /// with `args`, appending the tainted arguments to `out`
+    pub fn syscall_entry(&mut self, pc: u64, nr: u64, args: &[u64],
+            out: &mut Vec<Finding>) {
+        let name = self.signatures.syscall_name(nr);
+        for (index, &value) in args.iter().enumerate() {
+            if let Some(label) = self.recent(value) {
+                out.push(Finding {
+                    reach: Reach::SyscallArg { nr, name, index },
+                    pc, value, label,
+                });
+            }
+        }
+
+        self.pending = name.and_then(|name| {
+            let &(name, buf) = self.sources.iter().find(|x| x.0 == name)?;
+            Some((name, *args.get(buf)?, *args.get(buf + 1)?))
+        });
+    }
+
+    /// Observe the system call made at `pc` returning `ret`, tainting what a
+    /// source system call filled
+    pub fn syscall_exit(&mut self, map: &mut TaintMap, pc: u64, ret: u64) {
+        let Some((syscall, addr, size)) = self.pending.take() else {
+            return;
+        };
+
+        // Errors are negative, so way larger than what was asked for
+        if ret != 0 && ret <= size {
+            map.add_source(Source { syscall, pc, addr, len: ret });
+        }
+    }
+}
+
+#[test]
+fn propagate_taint() {
+    let mut map = TaintMap::new();
+    let mut tracker = Tracker::new(Architecture::X86_64, false).unwrap();
+    let mut found = Vec::new();
+
+    // read(0, 0x1000, 16) returns 8 bytes
+    tracker.exec(&map, 0x400000, &mut found);
+    tracker.syscall_entry(0x400000, 0, &[0, 0x1000, 16], &mut found);
+    tracker.syscall_exit(&mut map, 0x400000, 8);
+    assert_eq!(map.sources(), [Source { syscall: "read", pc: 0x400000,
+        addr: 0x1000, len: 8 }]);
+    assert_eq!((map.get(0xfff, 1), map.get(0x1007, 2), map.get(0x1008, 8)),
+        (0, 1, 0));
+
+    // A byte copy, then an untainted store over part of the copy
+    tracker.exec(&map, 0x400010, &mut found);
+    tracker.read(&map, 0x400010, 0x1000, 0x41, 1);
+    tracker.exec(&map, 0x400014, &mut found);
+    tracker.write(&mut map, 0x400014, 0x2000, 0x41, 1);
+    tracker.exec(&map, 0x400018, &mut found);
+    tracker.read(&map, 0x400018, 0x3000, 0x41, 1);
+    tracker.exec(&map, 0x40001c, &mut found);
+    tracker.write(&mut map, 0x40001c, 0x2001, 0x41, 1);
+    assert_eq!((map.get(0x2000, 1), map.get(0x2001, 1)), (1, 0));
+
+    // A tainted pointer is stored far from its load, then returned to and
+    // passed to write()
+    tracker.exec(&map, 0x400020, &mut found);
+    tracker.read(&map, 0x400020, 0x1000, 0x41414141, 4);
+    tracker.exec(&map, 0x400024, &mut found);
+    tracker.read(&map, 0x400024, 0x3000, 0, 8);
+    tracker.exec(&map, 0x400028, &mut found);
+    tracker.write(&mut map, 0x400028, 0x7ff0, 0x41414141, 4);
+    assert_eq!(map.get(0x7ff0, 4), 1);
+    assert!(found.is_empty());
+
+    tracker.exec(&map, 0x41414141, &mut found);
+    tracker.syscall_entry(0x400030, 1, &[1, 0x41414141, 4], &mut found);
+    assert_eq!(found, [
+        Finding { reach: Reach::Pc, pc: 0x41414141, value: 0x41414141,
+            label: 1 },
+        Finding {
+            reach: Reach::SyscallArg { nr: 1, name: Some("write"), index: 1 },
+            pc: 0x400030, value: 0x41414141, label: 1,
+        },
+    ]);
+}
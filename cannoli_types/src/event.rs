@@ -0,0 +1,358 @@
+//! Owned events covering the payload of every `cannoli::Cannoli` callback
+//!
+//! The callbacks hand out borrowed data which only lives for the duration of
+//! the call, [`Event`] is an owned copy which can be stored or shipped
+//! elsewhere. Events have a compact binary encoding, every integer is
+//! little-endian and 64-bit regardless of the target:
+//!
+//! ```text
+//! 0x00 Exec    pc
+//! 0x01 Regs    pc, regs_len: u32, regs
+//! 0x02 Branch  pc, taken: u8, regs_len: u32, regs
+//! 0x03 Read    pc, addr, val, sz: u8
+//! 0x04 Write   pc, addr, val, sz: u8
+//! 0x05 Mmap    base, len, flags: u8, offset, path_len: u32, path
+//! 0x06 Munmap  base, len
+//! 0x07 Brk     old, new
+//! 0x08 Arena   base, len
+//! 0x09 ReadAddr  pc, addr, sz: u8
+//! 0x0a WriteAddr pc, addr, sz: u8
+//! 0x0b Rep     pc, count, backward: u8, accesses_len: u32, accesses
+//! 0x0c Cmp     pc, lhs, rhs, sz: u8
+//! 0x0d RegFile pc, index: u32, regs_len: u32, regs
+//! 0x0e Signal  sig: u32, from, to
+//! ```
+//!
+//! `flags` for `Mmap` has bit 0 set for anonymous mappings, and bits 1, 2 and
+//! 3 set for readable, writable and executable mappings. Each access of `Rep`
+//! is encoded as `write: u8, addr, sz: u8`.
+//!
+//! Events can also be serialized with serde, as an object tagged with the
+//! snake case name of the variant in `event`, eg. `{"event": "exec", "pc":
+//! 4096}`. This is what generic tooling (exporters, filters, JSON lines)
+//! should use, the binary encoding is meant for captures.
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+#[cfg(feature = "serde")]
+use serde::{Serialize, Deserialize};
+
+/// Result of decoding an event
+type Result<T> = core::result::Result<T, DecodeError>;
+
+/// Errors decoding an event
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The bytes ended in the middle of an event
+    BufferTruncated,
+
+    /// An event started with an unknown opcode
+    InvalidOpcode(u8),
+
+    /// The path of a mapping wasn't valid UTF-8
+    PathEncoding(core::str::Utf8Error),
+}
+
+/// A memory access of the first iteration of a summarized block transfer, see
+/// [`Event::Rep`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RepAccess {
+    /// Set for stores, clear for loads
+    pub write: bool,
+
+    /// Address accessed by the first iteration
+    pub addr: u64,
+
+    /// Size of the access in bytes, which is also how far the address moves
+    /// every iteration
+    pub sz: u8,
+}
+
+/// A single event from the trace, covering the payload of every
+/// `cannoli::Cannoli` callback
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde",
+    serde(tag = "event", rename_all = "snake_case"))]
+pub enum Event {
+    /// Executed a PC
+    Exec { pc: u64 },
+
+    /// Executed a PC with register tracing
+    Regs { pc: u64, regs: Vec<u8> },
+
+    /// Executed a PC with branch tracing
+    Branch { pc: u64, taken: bool, regs: Vec<u8> },
+
+    /// Memory load of `sz` bytes
+    Read { pc: u64, addr: u64, val: u64, sz: u8 },
+
+    /// Memory store of `sz` bytes
+    Write { pc: u64, addr: u64, val: u64, sz: u8 },
+
+    /// Memory load of `sz` bytes, with the value suppressed
+    ReadAddr { pc: u64, addr: u64, sz: u8 },
+
+    /// Memory store of `sz` bytes, with the value suppressed
+    WriteAddr { pc: u64, addr: u64, sz: u8 },
+
+    /// `count` iterations of a block-transfer instruction at `pc` (eg. x86
+    /// `rep movsb`) summarized into one event, see
+    /// `cannoli::analysis::rep`. Every iteration repeats `accesses` with the
+    /// addresses moved by the size of the access, downwards if `backward`
+    Rep { pc: u64, count: u64, backward: bool, accesses: Vec<RepAccess> },
+
+    /// Comparison of the `sz` byte operands `lhs` and `rhs`, see
+    /// `cannoli::analysis::cmp`
+    Cmp { pc: u64, lhs: u64, rhs: u64, sz: u8 },
+
+    /// FP or vector register file `index` of
+    /// `cannoli::control::Filters::reg_files`, captured before the `Regs` or
+    /// `Branch` event of the same instruction
+    RegFile { pc: u64, index: u32, regs: Vec<u8> },
+
+    /// The signal `sig` was delivered to the thread at `from`, which
+    /// continued in its handler at `to`, see `cannoli::Cannoli::signal`
+    Signal { sig: i32, from: u64, to: u64 },
+
+    /// Memory was mapped
+    Mmap {
+        base:   u64,
+        len:    u64,
+        anon:   bool,
+        read:   bool,
+        write:  bool,
+        exec:   bool,
+        path:   String,
+        offset: u64,
+    },
+
+    /// Memory was unmapped
+    Munmap { base: u64, len: u64 },
+
+    /// The program break grew, see `cannoli::heap::HeapEvent::Brk`
+    Brk { old: u64, new: u64 },
+
+    /// An allocator arena was reserved, see `cannoli::heap::HeapEvent::Arena`
+    Arena { base: u64, len: u64 },
+}
+
+/// Take `len` bytes from the front of `bytes`
+fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    let head = bytes.get(..len).ok_or(DecodeError::BufferTruncated)?;
+    *bytes = &bytes[len..];
+    Ok(head)
+}
+
+/// Take a `u32` length-prefixed byte slice from the front of `bytes`
+fn take_slice<'a>(bytes: &mut &'a [u8]) -> Result<&'a [u8]> {
+    let len = u32::from_le_bytes(take(bytes, 4)?.try_into().unwrap());
+    take(bytes, len as usize)
+}
+
+impl Event {
+    /// Append the binary encoding of this event to `out`
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Event::Exec { pc } => {
+                out.push(0x00);
+                out.extend_from_slice(&pc.to_le_bytes());
+            }
+            Event::Regs { pc, regs } => {
+                out.push(0x01);
+                out.extend_from_slice(&pc.to_le_bytes());
+                out.extend_from_slice(&(regs.len() as u32).to_le_bytes());
+                out.extend_from_slice(regs);
+            }
+            Event::Branch { pc, taken, regs } => {
+                out.push(0x02);
+                out.extend_from_slice(&pc.to_le_bytes());
+                out.push(*taken as u8);
+                out.extend_from_slice(&(regs.len() as u32).to_le_bytes());
+                out.extend_from_slice(regs);
+            }
+            Event::Read { pc, addr, val, sz } |
+                    Event::Write { pc, addr, val, sz } => {
+                out.push(if matches!(self, Event::Read { .. }) {
+                    0x03
+                } else {
+                    0x04
+                });
+                out.extend_from_slice(&pc.to_le_bytes());
+                out.extend_from_slice(&addr.to_le_bytes());
+                out.extend_from_slice(&val.to_le_bytes());
+                out.push(*sz);
+            }
+            Event::ReadAddr { pc, addr, sz } |
+                    Event::WriteAddr { pc, addr, sz } => {
+                out.push(if matches!(self, Event::ReadAddr { .. }) {
+                    0x09
+                } else {
+                    0x0a
+                });
+                out.extend_from_slice(&pc.to_le_bytes());
+                out.extend_from_slice(&addr.to_le_bytes());
+                out.push(*sz);
+            }
+            Event::Rep { pc, count, backward, accesses } => {
+                out.push(0x0b);
+                out.extend_from_slice(&pc.to_le_bytes());
+                out.extend_from_slice(&count.to_le_bytes());
+                out.push(*backward as u8);
+                out.extend_from_slice(&(accesses.len() as u32).to_le_bytes());
+                for access in accesses {
+                    out.push(access.write as u8);
+                    out.extend_from_slice(&access.addr.to_le_bytes());
+                    out.push(access.sz);
+                }
+            }
+            Event::Cmp { pc, lhs, rhs, sz } => {
+                out.push(0x0c);
+                out.extend_from_slice(&pc.to_le_bytes());
+                out.extend_from_slice(&lhs.to_le_bytes());
+                out.extend_from_slice(&rhs.to_le_bytes());
+                out.push(*sz);
+            }
+            Event::Mmap { base, len, anon, read, write, exec, path,
+                    offset } => {
+                out.push(0x05);
+                out.extend_from_slice(&base.to_le_bytes());
+                out.extend_from_slice(&len.to_le_bytes());
+                out.push((*anon  as u8)      | (*read as u8) << 1 |
+                         (*write as u8) << 2 | (*exec as u8) << 3);
+                out.extend_from_slice(&offset.to_le_bytes());
+                out.extend_from_slice(&(path.len() as u32).to_le_bytes());
+                out.extend_from_slice(path.as_bytes());
+            }
+            Event::Munmap { base, len } => {
+                out.push(0x06);
+                out.extend_from_slice(&base.to_le_bytes());
+                out.extend_from_slice(&len.to_le_bytes());
+            }
+            Event::Brk { old, new } => {
+                out.push(0x07);
+                out.extend_from_slice(&old.to_le_bytes());
+                out.extend_from_slice(&new.to_le_bytes());
+            }
+            Event::Arena { base, len } => {
+                out.push(0x08);
+                out.extend_from_slice(&base.to_le_bytes());
+                out.extend_from_slice(&len.to_le_bytes());
+            }
+            Event::RegFile { pc, index, regs } => {
+                out.push(0x0d);
+                out.extend_from_slice(&pc.to_le_bytes());
+                out.extend_from_slice(&index.to_le_bytes());
+                out.extend_from_slice(&(regs.len() as u32).to_le_bytes());
+                out.extend_from_slice(regs);
+            }
+            Event::Signal { sig, from, to } => {
+                out.push(0x0e);
+                out.extend_from_slice(&sig.to_le_bytes());
+                out.extend_from_slice(&from.to_le_bytes());
+                out.extend_from_slice(&to.to_le_bytes());
+            }
+        }
+    }
+
+    /// Decode the event at the front of `bytes`, advancing `bytes` past it
+    pub fn decode(bytes: &mut &[u8]) -> Result<Self> {
+        let u64 = |bytes: &mut &[u8]| take(bytes, 8)
+            .map(|x| u64::from_le_bytes(x.try_into().unwrap()));
+        let u8  = |bytes: &mut &[u8]| take(bytes, 1).map(|x| x[0]);
+
+        let op = u8(bytes)?;
+        Ok(match op {
+            0x00 => Event::Exec { pc: u64(bytes)? },
+            0x01 => Event::Regs {
+                pc:   u64(bytes)?,
+                regs: take_slice(bytes)?.to_vec(),
+            },
+            0x02 => Event::Branch {
+                pc:    u64(bytes)?,
+                taken: u8(bytes)? != 0,
+                regs:  take_slice(bytes)?.to_vec(),
+            },
+            0x03 => Event::Read {
+                pc:   u64(bytes)?,
+                addr: u64(bytes)?,
+                val:  u64(bytes)?,
+                sz:   u8(bytes)?,
+            },
+            0x04 => Event::Write {
+                pc:   u64(bytes)?,
+                addr: u64(bytes)?,
+                val:  u64(bytes)?,
+                sz:   u8(bytes)?,
+            },
+            0x05 => {
+                let base   = u64(bytes)?;
+                let len    = u64(bytes)?;
+                let flags  = u8(bytes)?;
+                let offset = u64(bytes)?;
+                let path   = core::str::from_utf8(take_slice(bytes)?)
+                    .map_err(DecodeError::PathEncoding)?.to_string();
+                Event::Mmap {
+                    anon:  flags & 1 != 0,
+                    read:  flags & 2 != 0,
+                    write: flags & 4 != 0,
+                    exec:  flags & 8 != 0,
+                    base, len, offset, path,
+                }
+            }
+            0x06 => Event::Munmap {
+                base: u64(bytes)?,
+                len:  u64(bytes)?,
+            },
+            0x07 => Event::Brk {
+                old: u64(bytes)?,
+                new: u64(bytes)?,
+            },
+            0x08 => Event::Arena {
+                base: u64(bytes)?,
+                len:  u64(bytes)?,
+            },
+            0x09 => Event::ReadAddr {
+                pc:   u64(bytes)?,
+                addr: u64(bytes)?,
+                sz:   u8(bytes)?,
+            },
+            0x0a => Event::WriteAddr {
+                pc:   u64(bytes)?,
+                addr: u64(bytes)?,
+                sz:   u8(bytes)?,
+            },
+            0x0b => {
+                let pc       = u64(bytes)?;
+                let count    = u64(bytes)?;
+                let backward = u8(bytes)? != 0;
+                let len      = u32::from_le_bytes(
+                    take(bytes, 4)?.try_into().unwrap());
+                let accesses = (0..len).map(|_| Ok(RepAccess {
+                    write: u8(bytes)? != 0,
+                    addr:  u64(bytes)?,
+                    sz:    u8(bytes)?,
+                })).collect::<Result<_>>()?;
+                Event::Rep { pc, count, backward, accesses }
+            }
+            0x0c => Event::Cmp {
+                pc:  u64(bytes)?,
+                lhs: u64(bytes)?,
+                rhs: u64(bytes)?,
+                sz:  u8(bytes)?,
+            },
+            0x0d => Event::RegFile {
+                pc:    u64(bytes)?,
+                index: u32::from_le_bytes(take(bytes, 4)?.try_into().unwrap()),
+                regs:  take_slice(bytes)?.to_vec(),
+            },
+            0x0e => Event::Signal {
+                sig:  i32::from_le_bytes(take(bytes, 4)?.try_into().unwrap()),
+                from: u64(bytes)?,
+                to:   u64(bytes)?,
+            },
+            _ => return Err(DecodeError::InvalidOpcode(op)),
+        })
+    }
+}
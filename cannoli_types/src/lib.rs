@@ -0,0 +1,240 @@
+//! Wire format of Cannoli, for consumers which can't take the whole client
+//!
+//! The `cannoli` crate is a server with threads, sockets and files, which is
+//! no use to something like a kernel module or the firmware of a capture
+//! card ingesting a stream of events. This crate only has the definitions of
+//! what goes over the wire, and works without `std` (it needs `alloc`):
+//!
+//! - [`ClientConn`], the greeting of a jitter, and [`Architecture`]
+//! - [`Event`] and its binary encoding, which is what captures and sinks
+//!   stream, see [`event`]
+//!
+//! The `serde` feature derives serde's traits for events. Everything here is
+//! re-exported by `cannoli`, so users of the client don't need this crate.
+
+#![no_std]
+
+extern crate alloc;
+
+use core::ffi::CStr;
+
+pub mod event;
+
+pub use event::{Event, RepAccess, DecodeError};
+
+/// Header sent when a client connects
+///
+/// Must only contain plain-old-data otherwise you will break the unsafe
+/// serialization and deserialization we use :)
+///
+/// This also cannot have unaligned fields that cause padding bytes
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct ClientConn {
+    /// UID for the pipe
+    pub uid: u64,
+
+    /// Architecture
+    pub arch: i32,
+
+    /// Big endian flag
+    pub big_endian: i32,
+
+    /// Parent process ID
+    pub ppid: i32,
+
+    /// Process ID
+    pub pid: i32,
+
+    /// Thread ID, as seen by the guest. QEMU user-mode runs every guest
+    /// thread on a host thread of its own and passes `gettid()` through, so
+    /// this is the TID the target itself reports for the thread
+    pub tid: i32,
+
+    /// Index of the vCPU QEMU runs the thread on, its `cpu_index`, `-1` if
+    /// QEMU doesn't export it
+    pub cpu: i32,
+
+    /// Length of the parent comm (in bytes)
+    pub pcomm_len: u32,
+
+    /// Length of the comm (in bytes)
+    pub comm_len: u32,
+
+    /// Length of the authentication token following the comm (in bytes), see
+    /// `cannoli::auth`
+    pub token_len: u32,
+
+    /// Length of the path of the main binary following the token (in bytes)
+    pub exe_len: u32,
+
+    /// Length of the working directory following the main binary (in bytes)
+    pub cwd_len: u32,
+
+    /// Length of the arguments following the working directory (in bytes),
+    /// each of them ends with a NUL byte
+    pub argv_len: u32,
+
+    /// Length of the environment following the arguments (in bytes), each
+    /// `NAME=value` entry ends with a NUL byte
+    pub env_len: u32,
+
+    /// Zero, keeps the header free of padding bytes
+    pub reserved: u32,
+}
+
+/// Most bytes of main binary, working directory, arguments and environment
+/// the server accepts in a greeting, so a bad greeting can't make it allocate
+/// an arbitrary amount of memory. Jitters leave out the arguments and
+/// environment entries which don't fit
+pub const MAX_IMAGE_LEN: u32 = 1024 * 1024;
+
+/// Different QEMU target architectures
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Architecture {
+    Aarch64,
+    Aarch64be,
+    Alpha,
+    Armv5teb,
+    Armv5tel,
+    Cris,
+    Hexagon,
+    I386,
+    I686,
+    M68k,
+    Microblaze,
+    Mips,
+    Mips64,
+    Nios2,
+    Openrisc,
+    Parisc,
+    Ppc,
+    Ppc64,
+    Ppc64le,
+    Riscv32,
+    Riscv64,
+    S390x,
+    Sh4,
+    Sparc,
+    Sparc64,
+    X86_64,
+    Xtensa,
+}
+
+impl From<i32> for Architecture {
+    fn from(val: i32) -> Self {
+        match val {
+             0 => Self::Aarch64,
+             1 => Self::Aarch64be,
+             2 => Self::Alpha,
+             3 => Self::Armv5teb,
+             4 => Self::Armv5tel,
+             5 => Self::Cris,
+             6 => Self::Hexagon,
+             7 => Self::I386,
+             8 => Self::I686,
+             9 => Self::M68k,
+            10 => Self::Microblaze,
+            11 => Self::Mips,
+            12 => Self::Mips64,
+            13 => Self::Nios2,
+            14 => Self::Openrisc,
+            15 => Self::Parisc,
+            16 => Self::Ppc,
+            17 => Self::Ppc64,
+            18 => Self::Ppc64le,
+            19 => Self::Riscv32,
+            20 => Self::Riscv64,
+            21 => Self::S390x,
+            22 => Self::Sh4,
+            23 => Self::Sparc,
+            24 => Self::Sparc64,
+            25 => Self::X86_64,
+            26 => Self::Xtensa,
+            _  => panic!("Cannoli: Unhandled architecture ID {}", val),
+        }
+    }
+}
+
+impl Architecture {
+    /// Convert a `UNAME_MACHINE` C-string to an [`Architecture`]
+    ///
+    /// # Safety
+    ///
+    /// Expects a pointer to a valid null-terminatedf string
+    pub unsafe fn from_cstr(arch: *const i8) -> Self {
+        let arch = CStr::from_ptr(arch).to_str().expect(
+            "Cannoli: Invalid string passed to Architecture::from_cstr()");
+        Self::from_name(arch).unwrap_or_else(|| {
+            panic!("Cannoli: Unhandled architecture name {}", arch)
+        })
+    }
+
+    /// Get the [`Architecture`] named `name` by `UNAME_MACHINE`, which is
+    /// also what QEMU's user-mode emulators are named after
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "aarch64"    => Architecture::Aarch64,
+            "aarch64_be" => Architecture::Aarch64be,
+            "alpha"      => Architecture::Alpha,
+            "armv5teb"   => Architecture::Armv5teb,
+            "armv5tel"   => Architecture::Armv5tel,
+            "cris"       => Architecture::Cris,
+            "hexagon"    => Architecture::Hexagon,
+            "i386"       => Architecture::I386,
+            "i686"       => Architecture::I686,
+            "m68k"       => Architecture::M68k,
+            "microblaze" => Architecture::Microblaze,
+            "mips"       => Architecture::Mips,
+            "mips64"     => Architecture::Mips64,
+            "nios2"      => Architecture::Nios2,
+            "openrisc"   => Architecture::Openrisc,
+            "parisc"     => Architecture::Parisc,
+            "ppc"        => Architecture::Ppc,
+            "ppc64"      => Architecture::Ppc64,
+            "ppc64le"    => Architecture::Ppc64le,
+            "riscv32"    => Architecture::Riscv32,
+            "riscv64"    => Architecture::Riscv64,
+            "s390x"      => Architecture::S390x,
+            "sh4"        => Architecture::Sh4,
+            "sparc"      => Architecture::Sparc,
+            "sparc64"    => Architecture::Sparc64,
+            "x86_64"     => Architecture::X86_64,
+            "xtensa"     => Architecture::Xtensa,
+            _ => return None,
+        })
+    }
+
+    pub fn bitness(&self) -> u8 {
+        match self {
+            Architecture::Aarch64     => 64,
+            Architecture::Aarch64be   => 64,
+            Architecture::Alpha       => 32,
+            Architecture::Armv5teb    => 64,
+            Architecture::Armv5tel    => 64,
+            Architecture::Cris        => 32,
+            Architecture::Hexagon     => 32,
+            Architecture::I386        => 32,
+            Architecture::I686        => 32,
+            Architecture::M68k        => 32,
+            Architecture::Microblaze  => 32,
+            Architecture::Mips        => 32,
+            Architecture::Mips64      => 64,
+            Architecture::Nios2       => 32,
+            Architecture::Openrisc    => 32,
+            Architecture::Parisc      => 32,
+            Architecture::Ppc         => 32,
+            Architecture::Ppc64       => 64,
+            Architecture::Ppc64le     => 64,
+            Architecture::Riscv32     => 32,
+            Architecture::Riscv64     => 64,
+            Architecture::S390x       => 32,
+            Architecture::Sh4         => 32,
+            Architecture::Sparc       => 32,
+            Architecture::Sparc64     => 64,
+            Architecture::X86_64      => 64,
+            Architecture::Xtensa      => 32,
+        }
+    }
+}
@@ -0,0 +1,28 @@
+;; Example plugin which counts instructions and memory accesses per thread
+;;
+;; cargo run --release --bin cannoli_wasm -- plugins/count.wat
+(module
+  (import "env" "log_u64" (func $log_u64 (param i32 i32 i64)))
+
+  (memory (export "memory") 1)
+  (data (i32.const 0)  "instructions")
+  (data (i32.const 16) "memory accesses")
+
+  (global $execs (mut i64) (i64.const 0))
+  (global $mems  (mut i64) (i64.const 0))
+
+  (func (export "exec") (param $pc i64)
+    (global.set $execs (i64.add (global.get $execs) (i64.const 1))))
+
+  (func (export "read") (param $pc i64) (param $addr i64) (param $val i64)
+      (param $sz i32)
+    (global.set $mems (i64.add (global.get $mems) (i64.const 1))))
+
+  (func (export "write") (param $pc i64) (param $addr i64) (param $val i64)
+      (param $sz i32)
+    (global.set $mems (i64.add (global.get $mems) (i64.const 1))))
+
+  (func (export "fini")
+    (call $log_u64 (i32.const 0)  (i32.const 12) (global.get $execs))
+    (call $log_u64 (i32.const 16) (i32.const 15) (global.get $mems)))
+)
@@ -0,0 +1,399 @@
+//! Host for Cannoli analyses compiled to WebAssembly
+//!
+//! Analyses are normally native code linked into the client, which means an
+//! analysis plugin shared by someone else can do anything it wants to the
+//! machine doing the capture. This crate instead runs analyses compiled to
+//! WebAssembly inside of a wasmtime sandbox. Plugins get no WASI and no
+//! imports other than logging, have a bounded memory, and a bounded amount of
+//! fuel per trace chunk so they can't hang the client.
+//!
+//! # Hook ABI
+//!
+//! Plugins may export any of the following functions, hooks which are not
+//! exported are simply not invoked. All hooks are invoked in trace order from
+//! the sequential phase, with one plugin instance per target thread.
+//!
+//! ```text
+//! init(pid: i32, tid: i32, arch: i32, big_endian: i32)
+//! fini()
+//! exec(pc: i64)
+//! read(pc: i64, addr: i64, val: i64, sz: i32)
+//! write(pc: i64, addr: i64, val: i64, sz: i32)
+//! regs(pc: i64, regs_ptr: i32, regs_len: i32)
+//! branch(pc: i64, branch: i32, regs_ptr: i32, regs_len: i32)
+//! mmap(base: i64, len: i64, flags: i32, path_ptr: i32, path_len: i32,
+//!      offset: i64)
+//! munmap(base: i64, len: i64)
+//! ```
+//!
+//! `arch` is the [`cannoli::Architecture`] discriminant. `flags` for `mmap`
+//! is a bitmask of [`MMAP_ANON`], [`MMAP_READ`], [`MMAP_WRITE`] and
+//! [`MMAP_EXEC`].
+//!
+//! To receive byte buffers (registers and paths) a plugin must export its
+//! `memory` and an `alloc(len: i32) -> i32` function, the host allocates
+//! through it and copies the bytes in before invoking the hook. Without
+//! `alloc`, buffers are passed as a null pointer and zero length.
+//!
+//! Plugins can import `env.log(ptr: i32, len: i32)` to print a string, and
+//! `env.log_u64(ptr: i32, len: i32, val: i64)` to print a labeled value.
+
+use std::sync::{Arc, OnceLock};
+use std::path::Path;
+use cannoli::{Cannoli, ClientInfo};
+use wasmtime::{Caller, Config, Engine, Instance, Linker, Memory, Module};
+use wasmtime::{Store, StoreLimits, StoreLimitsBuilder, TypedFunc};
+
+/// `mmap` flag set for anonymous mappings
+pub const MMAP_ANON: u32 = 1 << 0;
+
+/// `mmap` flag set for readable mappings
+pub const MMAP_READ: u32 = 1 << 1;
+
+/// `mmap` flag set for writable mappings
+pub const MMAP_WRITE: u32 = 1 << 2;
+
+/// `mmap` flag set for executable mappings
+pub const MMAP_EXEC: u32 = 1 << 3;
+
+/// Maximum size of a plugin's linear memory, in bytes
+const MAX_MEMORY: usize = 256 * 1024 * 1024;
+
+/// Fuel given to a plugin for each chunk of the trace. This is roughly the
+/// number of wasm instructions the plugin may execute per chunk
+const FUEL_PER_CHUNK: u64 = 1_000_000_000;
+
+/// Errors for this crate
+#[derive(Debug)]
+pub enum Error {
+    /// Failed to create the wasmtime engine
+    Engine(wasmtime::Error),
+
+    /// Failed to load or compile the plugin
+    LoadModule(wasmtime::Error),
+
+    /// A plugin was already loaded
+    AlreadyLoaded,
+}
+
+/// The compiled plugin, shared by every instance
+struct Plugin {
+    /// Engine the module was compiled with
+    engine: Engine,
+
+    /// Compiled plugin
+    module: Module,
+}
+
+/// The loaded plugin
+static PLUGIN: OnceLock<Plugin> = OnceLock::new();
+
+/// Load and compile the plugin at `path`, this must be called before
+/// [`cannoli::create_cannoli`] is used with [`WasmAnalysis`]. Both `.wasm`
+/// and `.wat` files are accepted
+pub fn load(path: impl AsRef<Path>) -> Result<(), Error> {
+    // Configure the engine, fuel is what lets us stop runaway plugins
+    let mut config = Config::new();
+    config.consume_fuel(true);
+
+    let engine = Engine::new(&config).map_err(Error::Engine)?;
+    let module = Module::from_file(&engine, path)
+        .map_err(Error::LoadModule)?;
+
+    PLUGIN.set(Plugin { engine, module }).map_err(|_| Error::AlreadyLoaded)
+}
+
+/// State available to host functions invoked by the plugin
+struct HostState {
+    /// Resource limits for the plugin
+    limits: StoreLimits,
+
+    /// Target thread ID, used when logging
+    tid: i32,
+}
+
+/// Read a string out of the plugin's memory for the logging imports
+fn plugin_str(caller: &mut Caller<'_, HostState>, ptr: u32, len: u32)
+        -> String {
+    let memory = caller.get_export("memory").and_then(|x| x.into_memory());
+    memory.and_then(|memory| {
+        memory.data(&caller)
+            .get(ptr as usize..(ptr as usize).checked_add(len as usize)?)
+            .map(|x| String::from_utf8_lossy(x).into_owned())
+    }).unwrap_or_else(|| "<invalid string>".into())
+}
+
+/// `mmap(base, len, flags, path_ptr, path_len, offset)` hook of the plugin
+type MmapHook = TypedFunc<(u64, u64, u32, u32, u32, u64), ()>;
+
+/// Hooks exported by the plugin, see the crate documentation for the
+/// signatures
+#[derive(Default)]
+struct Hooks {
+    exec:   Option<TypedFunc<u64, ()>>,
+    read:   Option<TypedFunc<(u64, u64, u64, u32), ()>>,
+    write:  Option<TypedFunc<(u64, u64, u64, u32), ()>>,
+    regs:   Option<TypedFunc<(u64, u32, u32), ()>>,
+    branch: Option<TypedFunc<(u64, u32, u32, u32), ()>>,
+    mmap:   Option<MmapHook>,
+    munmap: Option<TypedFunc<(u64, u64), ()>>,
+    fini:   Option<TypedFunc<(), ()>>,
+    alloc:  Option<TypedFunc<u32, u32>>,
+}
+
+/// Events we sequence from the trace to hand to the plugin in order
+pub enum Event {
+    /// Executed a PC
+    Exec(u64),
+
+    /// Memory load
+    Read   { pc: u64, addr: u64, val: u64, sz: u8 },
+
+    /// Memory store
+    Write  { pc: u64, addr: u64, val: u64, sz: u8 },
+
+    /// Executed a PC with register tracing
+    Regs   { pc: u64, regs: Vec<u8> },
+
+    /// Executed a PC with branch tracing
+    Branch { pc: u64, branch: bool, regs: Vec<u8> },
+
+    /// Memory was mapped, `flags` is a bitmask of the `MMAP_*` constants
+    Mmap   { base: u64, len: u64, flags: u32, path: String, offset: u64 },
+
+    /// Memory was unmapped
+    Munmap { base: u64, len: u64 },
+}
+
+/// A [`Cannoli`] implementation which forwards the trace to the loaded wasm
+/// plugin, one plugin instance per target thread
+pub struct WasmAnalysis {
+    /// Store for the plugin instance
+    store: Store<HostState>,
+
+    /// Plugin's memory, if exported
+    memory: Option<Memory>,
+
+    /// Hooks exported by the plugin, these are all cleared if the plugin
+    /// traps
+    hooks: Hooks,
+}
+
+impl WasmAnalysis {
+    /// Copy `bytes` into the plugin's memory, returning the pointer and length
+    /// to pass to the plugin
+    fn pass_bytes(&mut self, bytes: &[u8]) -> wasmtime::Result<(u32, u32)> {
+        let (Some(alloc), Some(memory)) = (&self.hooks.alloc, &self.memory)
+        else {
+            return Ok((0, 0));
+        };
+
+        let ptr = alloc.call(&mut self.store, bytes.len() as u32)?;
+        memory.write(&mut self.store, ptr as usize, bytes)?;
+        Ok((ptr, bytes.len() as u32))
+    }
+
+    /// Invoke the plugin hook for `event`
+    fn dispatch(&mut self, event: &Event) -> wasmtime::Result<()> {
+        match event {
+            Event::Exec(pc) => {
+                if let Some(hook) = &self.hooks.exec {
+                    hook.call(&mut self.store, *pc)?;
+                }
+            }
+            Event::Read { pc, addr, val, sz } => {
+                if let Some(hook) = &self.hooks.read {
+                    hook.call(&mut self.store, (*pc, *addr, *val, *sz as u32))?;
+                }
+            }
+            Event::Write { pc, addr, val, sz } => {
+                if let Some(hook) = &self.hooks.write {
+                    hook.call(&mut self.store, (*pc, *addr, *val, *sz as u32))?;
+                }
+            }
+            Event::Regs { pc, regs } => {
+                if let Some(hook) = self.hooks.regs.clone() {
+                    let (ptr, len) = self.pass_bytes(regs)?;
+                    hook.call(&mut self.store, (*pc, ptr, len))?;
+                }
+            }
+            Event::Branch { pc, branch, regs } => {
+                if let Some(hook) = self.hooks.branch.clone() {
+                    let (ptr, len) = self.pass_bytes(regs)?;
+                    hook.call(&mut self.store, (*pc, *branch as u32, ptr, len))?;
+                }
+            }
+            Event::Mmap { base, len, flags, path, offset } => {
+                if let Some(hook) = self.hooks.mmap.clone() {
+                    let (ptr, plen) = self.pass_bytes(path.as_bytes())?;
+                    hook.call(&mut self.store,
+                        (*base, *len, *flags, ptr, plen, *offset))?;
+                }
+            }
+            Event::Munmap { base, len } => {
+                if let Some(hook) = &self.hooks.munmap {
+                    hook.call(&mut self.store, (*base, *len))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Report a plugin failure and disable the plugin for this thread
+    fn disable(&mut self, err: wasmtime::Error) {
+        eprintln!("[tid {}] wasm plugin failed, disabling it: {err:?}",
+            self.store.data().tid);
+        self.hooks = Hooks::default();
+    }
+}
+
+impl Cannoli for WasmAnalysis {
+    type Trace = Event;
+
+    type PidContext = ();
+    type TidContext = ();
+
+    fn init_pid(_ci: &ClientInfo) -> Arc<Self::PidContext> {
+        Arc::new(())
+    }
+
+    fn init_tid(_pid: &Self::PidContext,
+            ci: &ClientInfo) -> (Self, Self::TidContext) {
+        let plugin = PLUGIN.get()
+            .expect("cannoli_wasm::load() must be called before tracing");
+
+        // Create the sandbox for this thread
+        let mut store = Store::new(&plugin.engine, HostState {
+            limits: StoreLimitsBuilder::new().memory_size(MAX_MEMORY).build(),
+            tid:    ci.tid,
+        });
+        store.limiter(|state| &mut state.limits);
+        store.set_fuel(FUEL_PER_CHUNK).unwrap();
+
+        // The only things the plugin can import are the logging functions
+        let mut linker = Linker::new(&plugin.engine);
+        linker.func_wrap("env", "log",
+            |mut caller: Caller<'_, HostState>, ptr: u32, len: u32| {
+                let msg = plugin_str(&mut caller, ptr, len);
+                println!("[tid {}] {msg}", caller.data().tid);
+            }).unwrap();
+        linker.func_wrap("env", "log_u64",
+            |mut caller: Caller<'_, HostState>, ptr: u32, len: u32,
+                    val: u64| {
+                let msg = plugin_str(&mut caller, ptr, len);
+                println!("[tid {}] {msg}: {val}", caller.data().tid);
+            }).unwrap();
+
+        let mut ret = Self {
+            store,
+            memory: None,
+            hooks:  Hooks::default(),
+        };
+
+        // Instantiate the plugin and call its initializer
+        let result = (|| -> wasmtime::Result<()> {
+            let instance: Instance =
+                linker.instantiate(&mut ret.store, &plugin.module)?;
+            let store = &mut ret.store;
+
+            ret.memory = instance.get_memory(&mut *store, "memory");
+            ret.hooks = Hooks {
+                exec:   instance.get_typed_func(&mut *store, "exec").ok(),
+                read:   instance.get_typed_func(&mut *store, "read").ok(),
+                write:  instance.get_typed_func(&mut *store, "write").ok(),
+                regs:   instance.get_typed_func(&mut *store, "regs").ok(),
+                branch: instance.get_typed_func(&mut *store, "branch").ok(),
+                mmap:   instance.get_typed_func(&mut *store, "mmap").ok(),
+                munmap: instance.get_typed_func(&mut *store, "munmap").ok(),
+                fini:   instance.get_typed_func(&mut *store, "fini").ok(),
+                alloc:  instance.get_typed_func(&mut *store, "alloc").ok(),
+            };
+
+            if let Ok(init) = instance.get_typed_func::<(i32, i32, i32, i32), ()>(
+                    &mut *store, "init") {
+                init.call(&mut *store,
+                    (ci.pid, ci.tid, ci.arch as i32, ci.big_endian as i32))?;
+            }
+
+            Ok(())
+        })();
+
+        if let Err(err) = result {
+            ret.disable(err);
+        }
+
+        (ret, ())
+    }
+
+    fn exec(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            pc: u64, trace: &mut Vec<Self::Trace>) {
+        trace.push(Event::Exec(pc));
+    }
+
+    fn regs(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            pc: u64, regs: &[u8], trace: &mut Vec<Self::Trace>) {
+        trace.push(Event::Regs { pc, regs: regs.to_vec() });
+    }
+
+    fn branch(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            pc: u64, branch: bool, regs: &[u8],
+            trace: &mut Vec<Self::Trace>) {
+        trace.push(Event::Branch { pc, branch, regs: regs.to_vec() });
+    }
+
+    fn read(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            pc: u64, addr: u64, val: u64, sz: u8, _helper: bool,
+            trace: &mut Vec<Self::Trace>) {
+        trace.push(Event::Read { pc, addr, val, sz });
+    }
+
+    fn write(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            pc: u64, addr: u64, val: u64, sz: u8, _helper: bool,
+            trace: &mut Vec<Self::Trace>) {
+        trace.push(Event::Write { pc, addr, val, sz });
+    }
+
+    fn mmap(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            base: u64, len: u64, anon: bool, read: bool, write: bool,
+            exec: bool, path: &str, offset: u64,
+            trace: &mut Vec<Self::Trace>) {
+        let flags = if anon  { MMAP_ANON  } else { 0 } |
+                    if read  { MMAP_READ  } else { 0 } |
+                    if write { MMAP_WRITE } else { 0 } |
+                    if exec  { MMAP_EXEC  } else { 0 };
+        trace.push(Event::Mmap {
+            path: path.to_string(),
+            base, len, flags, offset,
+        });
+    }
+
+    fn munmap(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            base: u64, len: u64, trace: &mut Vec<Self::Trace>) {
+        trace.push(Event::Munmap { base, len });
+    }
+
+    fn trace(&mut self, _pid: &Self::PidContext,
+            _tid: &Self::TidContext, trace: &[Self::Trace]) {
+        // Refill the fuel for this chunk
+        self.store.set_fuel(FUEL_PER_CHUNK).unwrap();
+
+        for event in trace {
+            if let Err(err) = self.dispatch(event) {
+                self.disable(err);
+                break;
+            }
+        }
+    }
+}
+
+impl Drop for WasmAnalysis {
+    fn drop(&mut self) {
+        if let Some(fini) = self.hooks.fini.take() {
+            self.store.set_fuel(FUEL_PER_CHUNK).unwrap();
+            if let Err(err) = fini.call(&mut self.store, ()) {
+                self.disable(err);
+            }
+        }
+    }
+}
@@ -0,0 +1,21 @@
+//! Run a Cannoli analysis compiled to WebAssembly
+//!
+//! `cannoli_wasm <plugin.wasm> [threads]`
+
+use cannoli::create_cannoli;
+use cannoli_wasm::WasmAnalysis;
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+
+    let Some(plugin) = args.next() else {
+        eprintln!("usage: cannoli_wasm <plugin.wasm|plugin.wat> [threads]");
+        std::process::exit(1);
+    };
+    let threads = args.next()
+        .map(|x| x.parse().expect("Invalid thread count"))
+        .unwrap_or(2);
+
+    cannoli_wasm::load(&plugin).expect("Failed to load wasm plugin");
+    create_cannoli::<WasmAnalysis>(threads).unwrap();
+}
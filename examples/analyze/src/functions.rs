@@ -0,0 +1,201 @@
+//! Infer functions in a (potentially stripped) target, and write them out as
+//! a symbol map. Functions of statically linked targets which wrap a single
+//! system call are named after it
+//!
+//! Set `ANALYZE_PLT=suppress` to keep the dynamic loader's lazy symbol
+//! resolution out of the inferred functions, or `ANALYZE_PLT=report` to also
+//! print every resolution
+
+use std::io::Write;
+use std::sync::{Arc, Mutex, LazyLock};
+use std::collections::HashMap;
+use cannoli::{Architecture, Cannoli, ClientInfo};
+use cannoli::address_space::AddressSpace;
+use cannoli::analysis::functions::FunctionInference;
+use cannoli::analysis::lazy_binding::{LazyBinding, Step};
+use cannoli::symbols::signatures::Signatures;
+
+/// Functions discovered by all exited threads, keyed by PID
+static FUNCTIONS_BY_PID: LazyLock<Mutex<HashMap<i32, FunctionInference>>> =
+    LazyLock::new(Default::default);
+
+/// Mappings of the target processes, keyed by PID
+static SPACES_BY_PID:
+        LazyLock<Mutex<HashMap<i32, Arc<Mutex<AddressSpace>>>>> =
+    LazyLock::new(Default::default);
+
+/// Events we sequence from the trace
+pub enum Trace {
+    /// An instruction was executed
+    Exec(u64),
+
+    /// Memory was mapped
+    Mmap {
+        base:   u64,
+        len:    u64,
+        anon:   bool,
+        read:   bool,
+        write:  bool,
+        exec:   bool,
+        path:   String,
+        offset: u64,
+    },
+
+    /// Memory was unmapped
+    Munmap {
+        base: u64,
+        len:  u64,
+    },
+}
+
+/// The structure we implement [`Cannoli`] for! One per target thread
+pub struct Functions {
+    /// Process ID of the target
+    pid: i32,
+
+    /// Architecture of the target
+    arch: Architecture,
+
+    /// Is the target big endian?
+    big_endian: bool,
+
+    /// Inference state for this thread
+    inference: FunctionInference,
+
+    /// Mappings of the process
+    space: Arc<Mutex<AddressSpace>>,
+
+    /// Lazy binding tracking, if `ANALYZE_PLT` is set
+    binding: Option<LazyBinding>,
+
+    /// Set if resolutions are printed
+    report: bool,
+}
+
+impl Functions {
+    /// Write the symbol map of the process to `out`. Statically linked
+    /// targets have their system call wrappers named, these come first so
+    /// they are preferred over the `sub_<entry>` names
+    fn write_symbol_map(&self, inference: &FunctionInference,
+            mut out: impl Write) -> std::io::Result<()> {
+        let space = self.space.lock().unwrap();
+        if space.is_statically_linked() {
+            if let Some(signatures) =
+                    Signatures::new(self.arch, self.big_endian) {
+                let mut sites = Vec::new();
+                for mapping in space.mappings().filter(|x| x.exec && !x.anon) {
+                    sites.extend(signatures.scan_mapping(mapping)?);
+                }
+
+                let entries = inference.functions().map(|x| x.entry)
+                    .collect::<Vec<_>>();
+                for symbol in signatures.identify(&sites, &entries).symbols() {
+                    writeln!(out, "{:016x} T {}", symbol.addr, symbol.name)?;
+                }
+            }
+        }
+
+        inference.write_symbol_map(out)
+    }
+}
+
+impl Cannoli for Functions {
+    /// We need the PCs, in order, and the mappings to find the code of
+    /// statically linked targets
+    type Trace = Trace;
+
+    type PidContext = ();
+    type TidContext = ();
+
+    fn init_pid(_ci: &ClientInfo) -> Arc<Self::PidContext> {
+        Arc::new(())
+    }
+
+    fn init_tid(_pid: &Self::PidContext,
+            ci: &ClientInfo) -> (Self, Self::TidContext) {
+        let space = SPACES_BY_PID.lock().unwrap()
+            .entry(ci.pid).or_default().clone();
+        let plt = std::env::var("ANALYZE_PLT").ok();
+
+        (Self {
+            pid:        ci.pid,
+            arch:       ci.arch,
+            big_endian: ci.big_endian,
+            inference:  FunctionInference::new(),
+            binding:    plt.as_ref().map(|_| LazyBinding::new()),
+            report:     plt.as_deref() == Some("report"),
+            space,
+        }, ())
+    }
+
+    fn exec(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            pc: u64, trace: &mut Vec<Self::Trace>) {
+        trace.push(Trace::Exec(pc));
+    }
+
+    fn mmap(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            base: u64, len: u64, anon: bool, read: bool, write: bool,
+            exec: bool, path: &str, offset: u64,
+            trace: &mut Vec<Self::Trace>) {
+        trace.push(Trace::Mmap {
+            path: path.to_string(),
+            base, len, anon, read, write, exec, offset,
+        });
+    }
+
+    fn munmap(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            base: u64, len: u64, trace: &mut Vec<Self::Trace>) {
+        trace.push(Trace::Munmap { base, len });
+    }
+
+    fn trace(&mut self, _pid: &Self::PidContext,
+            _tid: &Self::TidContext, trace: &[Self::Trace]) {
+        let mut space = self.space.lock().unwrap();
+        for event in trace {
+            match event {
+                Trace::Exec(pc) => {
+                    // The loader's code is dropped while it resolves a
+                    // symbol, the PLT stub appears to jump straight to the
+                    // function
+                    let step = self.binding.as_mut()
+                        .map(|x| x.observe(&space, *pc));
+                    match step {
+                        Some(Step::Loader) => continue,
+                        Some(Step::Resolved(resolved)) if self.report => {
+                            println!("[pid {}] {resolved} from {:#x}, {} \
+                                instructions", self.pid, resolved.from,
+                                resolved.insns);
+                        }
+                        _ => {}
+                    }
+                    self.inference.observe(*pc);
+                }
+                Trace::Mmap { base, len, anon, read, write, exec, path,
+                        offset } => {
+                    space.mmap(*base, *len, *anon, *read, *write, *exec, path,
+                        *offset);
+                }
+                Trace::Munmap { base, len } => space.munmap(*base, *len),
+            }
+        }
+    }
+}
+
+impl Drop for Functions {
+    fn drop(&mut self) {
+        // Merge in what this thread found
+        let mut functions = FUNCTIONS_BY_PID.lock().unwrap();
+        let merged = functions.entry(self.pid).or_default();
+        merged.merge(&self.inference);
+
+        // Rewrite the symbol map for the process, we don't know which thread
+        // is the last one to exit, so just do it every time
+        let path = format!("inferred_symbols_{}.txt", self.pid);
+        let file = std::fs::File::create(&path)
+            .expect("Failed to create symbol map");
+        self.write_symbol_map(merged, std::io::BufWriter::new(file))
+            .expect("Failed to write symbol map");
+
+        println!("Wrote {} functions to {path}", merged.functions().count());
+    }
+}
@@ -0,0 +1,150 @@
+//! Report modifications of the target's code, along with the PCs which made
+//! them
+
+use std::sync::{Arc, Mutex, LazyLock};
+use std::collections::HashMap;
+use cannoli::{Cannoli, ClientInfo};
+use cannoli::analysis::integrity::IntegrityWatch;
+
+/// Number of trace batches between checks of the code, a power of two
+const CHECK_INTERVAL: u64 = 64;
+
+/// Code of the target processes, keyed by PID. Code is shared between
+/// threads so this is per-process rather than per-thread
+static WATCHES_BY_PID:
+        LazyLock<Mutex<HashMap<i32, Arc<Mutex<IntegrityWatch>>>>> =
+    LazyLock::new(Default::default);
+
+/// Events we sequence from the trace
+pub enum Trace {
+    /// A write to memory, `val` is `None` if it was logged without its value
+    Write {
+        pc:   u64,
+        addr: u64,
+        val:  Option<u64>,
+        sz:   u8,
+    },
+
+    /// Memory was mapped
+    Mmap {
+        base:   u64,
+        len:    u64,
+        anon:   bool,
+        exec:   bool,
+        path:   String,
+        offset: u64,
+    },
+
+    /// Memory was unmapped
+    Munmap {
+        base: u64,
+        len:  u64,
+    },
+}
+
+/// The structure we implement [`Cannoli`] for! One per target thread
+pub struct Integrity {
+    /// Thread ID of the target
+    tid: i32,
+
+    /// Code of the process
+    watch: Arc<Mutex<IntegrityWatch>>,
+
+    /// Number of trace batches handled
+    batches: u64,
+}
+
+impl Integrity {
+    /// Check the code of the process and print what was modified
+    fn check(&self) {
+        for modified in self.watch.lock().unwrap().check() {
+            let after = modified.after
+                .map_or("unknown".to_string(), |x| format!("{x:016x}"));
+            println!("[tid {}] code modified at {:#x} ({}+{:#x}) \
+                {:016x} -> {after}", self.tid, modified.page,
+                if modified.path.is_empty() { "anon" } else { &modified.path },
+                modified.offset, modified.before);
+            for writer in &modified.writers {
+                println!("    {:#x} wrote {} bytes at {:#x}", writer.pc,
+                    writer.sz, writer.addr);
+            }
+        }
+    }
+}
+
+impl Cannoli for Integrity {
+    type Trace = Trace;
+
+    type PidContext = ();
+    type TidContext = ();
+
+    fn init_pid(_ci: &ClientInfo) -> Arc<Self::PidContext> {
+        Arc::new(())
+    }
+
+    fn init_tid(_pid: &Self::PidContext,
+            ci: &ClientInfo) -> (Self, Self::TidContext) {
+        let watch = WATCHES_BY_PID.lock().unwrap()
+            .entry(ci.pid).or_default().clone();
+
+        (Self { tid: ci.tid, batches: 0, watch }, ())
+    }
+
+    fn write(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            pc: u64, addr: u64, val: u64, sz: u8, _helper: bool,
+            trace: &mut Vec<Self::Trace>) {
+        trace.push(Trace::Write { pc, addr, val: Some(val), sz });
+    }
+
+    fn write_addr(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            pc: u64, addr: u64, sz: u8, _helper: bool,
+            trace: &mut Vec<Self::Trace>) {
+        trace.push(Trace::Write { pc, addr, val: None, sz });
+    }
+
+    fn mmap(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            base: u64, len: u64, anon: bool, _read: bool, _write: bool,
+            exec: bool, path: &str, offset: u64,
+            trace: &mut Vec<Self::Trace>) {
+        trace.push(Trace::Mmap {
+            path: path.to_string(),
+            base, len, anon, exec, offset,
+        });
+    }
+
+    fn munmap(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            base: u64, len: u64, trace: &mut Vec<Self::Trace>) {
+        trace.push(Trace::Munmap { base, len });
+    }
+
+    fn trace(&mut self, _pid: &Self::PidContext,
+            _tid: &Self::TidContext, trace: &[Self::Trace]) {
+        {
+            let mut watch = self.watch.lock().unwrap();
+            for event in trace {
+                match event {
+                    Trace::Write { pc, addr, val, sz } => {
+                        watch.write(*pc, *addr, *val, *sz);
+                    }
+                    Trace::Mmap { base, len, anon, exec, path, offset } => {
+                        watch.mmap(*base, *len, *anon, *exec, path, *offset);
+                    }
+                    Trace::Munmap { base, len } => {
+                        watch.munmap(*base, *len);
+                    }
+                }
+            }
+        }
+
+        self.batches += 1;
+        if self.batches & (CHECK_INTERVAL - 1) == 0 {
+            self.check();
+        }
+    }
+}
+
+impl Drop for Integrity {
+    fn drop(&mut self) {
+        self.check();
+    }
+}
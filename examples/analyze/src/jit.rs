@@ -0,0 +1,173 @@
+//! Separate execution of dynamically generated code (eg. from a JIT in the
+//! target) from execution of static code
+
+use std::sync::{Arc, Mutex, LazyLock};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use cannoli::{Cannoli, ClientInfo};
+use cannoli::address_space::{AddressSpace, CodeOrigin};
+
+/// Address spaces of the target processes, keyed by PID
+static SPACES_BY_PID: LazyLock<Mutex<HashMap<i32, Arc<Mutex<AddressSpace>>>>> =
+    LazyLock::new(Default::default);
+
+/// Events we sequence from the trace
+pub enum Trace {
+    /// Executed a PC
+    Exec(u64),
+
+    /// Executed a PC of dynamic code, tagged by the jitter, and whether its
+    /// code was captured
+    Dynamic(u64, bool),
+
+    /// Memory was mapped
+    Mmap {
+        base:  u64,
+        len:   u64,
+        anon:  bool,
+        read:  bool,
+        write: bool,
+        exec:  bool,
+        path:  String,
+        offset: u64,
+    },
+
+    /// Memory was unmapped
+    Munmap {
+        base: u64,
+        len:  u64,
+    },
+}
+
+/// The structure we implement [`Cannoli`] for! One per target thread
+pub struct Jit {
+    /// Thread ID of the target
+    tid: i32,
+
+    /// Address space of the process
+    space: Arc<Mutex<AddressSpace>>,
+
+    /// Instructions executed in static code, keyed by module path
+    static_execs: BTreeMap<Arc<str>, u64>,
+
+    /// Instructions executed in dynamic code, keyed by base of the mapping
+    dynamic_execs: BTreeMap<u64, u64>,
+
+    /// Instructions executed with no known mapping
+    unknown_execs: u64,
+
+    /// Instructions of dynamic code whose code was captured
+    captured: BTreeSet<u64>,
+}
+
+impl Cannoli for Jit {
+    type Trace = Trace;
+
+    type PidContext = ();
+    type TidContext = ();
+
+    const CAPTURE_DYNAMIC_CODE: bool = true;
+
+    fn init_pid(_ci: &ClientInfo) -> Arc<Self::PidContext> {
+        Arc::new(())
+    }
+
+    fn init_tid(_pid: &Self::PidContext,
+            ci: &ClientInfo) -> (Self, Self::TidContext) {
+        let space = SPACES_BY_PID.lock().unwrap()
+            .entry(ci.pid).or_default().clone();
+
+        (Self {
+            tid:           ci.tid,
+            static_execs:  BTreeMap::new(),
+            dynamic_execs: BTreeMap::new(),
+            unknown_execs: 0,
+            captured:      BTreeSet::new(),
+            space,
+        }, ())
+    }
+
+    fn exec(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            pc: u64, trace: &mut Vec<Self::Trace>) {
+        trace.push(Trace::Exec(pc));
+    }
+
+    fn exec_dynamic(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            pc: u64, bytes: &[u8], trace: &mut Vec<Self::Trace>) {
+        trace.push(Trace::Dynamic(pc, !bytes.is_empty()));
+    }
+
+    fn mmap(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            base: u64, len: u64, anon: bool, read: bool, write: bool,
+            exec: bool, path: &str, offset: u64,
+            trace: &mut Vec<Self::Trace>) {
+        trace.push(Trace::Mmap {
+            path: path.to_string(),
+            base, len, anon, read, write, exec, offset,
+        });
+    }
+
+    fn munmap(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            base: u64, len: u64, trace: &mut Vec<Self::Trace>) {
+        trace.push(Trace::Munmap { base, len });
+    }
+
+    fn trace(&mut self, _pid: &Self::PidContext,
+            _tid: &Self::TidContext, trace: &[Self::Trace]) {
+        let mut space = self.space.lock().unwrap();
+        for event in trace {
+            match event {
+                Trace::Exec(pc) => {
+                    match space.classify(*pc) {
+                        CodeOrigin::Static(path) => {
+                            *self.static_execs.entry(path).or_default() += 1;
+                        }
+                        CodeOrigin::Dynamic => {
+                            let base = space.lookup(*pc).unwrap().base;
+                            *self.dynamic_execs.entry(base).or_default() += 1;
+                        }
+                        CodeOrigin::Unknown => {
+                            self.unknown_execs += 1;
+                        }
+                    }
+                }
+                Trace::Dynamic(pc, captured) => {
+                    // The mapping may be gone already, keep its page then
+                    let base = space.lookup(*pc)
+                        .map_or(*pc & !0xfff, |x| x.base);
+                    *self.dynamic_execs.entry(base).or_default() += 1;
+                    if *captured {
+                        self.captured.insert(*pc);
+                    }
+                }
+                Trace::Mmap { base, len, anon, read, write, exec, path,
+                        offset } => {
+                    space.mmap(*base, *len, *anon, *read, *write, *exec,
+                        path, *offset);
+                }
+                Trace::Munmap { base, len } => {
+                    space.munmap(*base, *len);
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Jit {
+    fn drop(&mut self) {
+        println!("[tid {}] static code:", self.tid);
+        for (path, count) in &self.static_execs {
+            println!("    {count:12} {path}");
+        }
+
+        println!("[tid {}] dynamic code:", self.tid);
+        for (base, count) in &self.dynamic_execs {
+            println!("    {count:12} {base:#x}");
+        }
+
+        println!("[tid {}] {} instructions of dynamic code captured",
+            self.tid, self.captured.len());
+
+        println!("[tid {}] {} instructions outside of known mappings",
+            self.tid, self.unknown_execs);
+    }
+}
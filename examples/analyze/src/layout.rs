@@ -0,0 +1,111 @@
+//! Collect structure layout hints from memory accesses, and write them out as
+//! a C header which can be imported into Ghidra
+
+use std::sync::{Arc, Mutex, LazyLock};
+use std::collections::HashMap;
+use cannoli::{Cannoli, ClientInfo};
+use cannoli::analysis::layout::LayoutInference;
+
+/// Layout inference state, keyed by PID. Pointers are shared between threads
+/// so this is per-process rather than per-thread
+static LAYOUTS_BY_PID:
+        LazyLock<Mutex<HashMap<i32, Arc<Mutex<LayoutInference>>>>> =
+    LazyLock::new(Default::default);
+
+/// Events we sequence from the trace
+pub enum Trace {
+    /// A memory access
+    Access {
+        pc:    u64,
+        addr:  u64,
+        val:   u64,
+        sz:    u8,
+        write: bool,
+    },
+
+    /// Memory was unmapped
+    Unmap {
+        base: u64,
+        len:  u64,
+    },
+}
+
+/// The structure we implement [`Cannoli`] for! One per target thread
+pub struct Layout {
+    /// Process ID of the target
+    pid: i32,
+
+    /// Inference state for the process
+    inference: Arc<Mutex<LayoutInference>>,
+}
+
+impl Cannoli for Layout {
+    type Trace = Trace;
+
+    type PidContext = ();
+    type TidContext = ();
+
+    fn init_pid(_ci: &ClientInfo) -> Arc<Self::PidContext> {
+        Arc::new(())
+    }
+
+    fn init_tid(_pid: &Self::PidContext,
+            ci: &ClientInfo) -> (Self, Self::TidContext) {
+        let inference = LAYOUTS_BY_PID.lock().unwrap()
+            .entry(ci.pid).or_insert_with(|| {
+                Arc::new(Mutex::new(
+                    LayoutInference::new(ci.arch.bitness() / 8)))
+            }).clone();
+
+        (Self { pid: ci.pid, inference }, ())
+    }
+
+    fn read(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            pc: u64, addr: u64, val: u64, sz: u8, _helper: bool,
+            trace: &mut Vec<Self::Trace>) {
+        trace.push(Trace::Access { pc, addr, val, sz, write: false });
+    }
+
+    fn write(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            pc: u64, addr: u64, val: u64, sz: u8, _helper: bool,
+            trace: &mut Vec<Self::Trace>) {
+        trace.push(Trace::Access { pc, addr, val, sz, write: true });
+    }
+
+    fn munmap(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            base: u64, len: u64, trace: &mut Vec<Self::Trace>) {
+        trace.push(Trace::Unmap { base, len });
+    }
+
+    fn trace(&mut self, _pid: &Self::PidContext,
+            _tid: &Self::TidContext, trace: &[Self::Trace]) {
+        let mut inference = self.inference.lock().unwrap();
+        for event in trace {
+            match *event {
+                Trace::Access { pc, addr, val, sz, write } => {
+                    inference.access(pc, addr, val, sz, write);
+                }
+                Trace::Unmap { base, len } => {
+                    inference.unmap(base, len);
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Layout {
+    fn drop(&mut self) {
+        let inference = self.inference.lock().unwrap();
+
+        // Rewrite the header for the process, we don't know which thread is
+        // the last one to exit, so just do it every time
+        let path = format!("layout_{}.h", self.pid);
+        let file = std::fs::File::create(&path)
+            .expect("Failed to create layout header");
+        inference.write_c_header(std::io::BufWriter::new(file))
+            .expect("Failed to write layout header");
+
+        println!("Wrote {} structures to {path}",
+            inference.layouts().count());
+    }
+}
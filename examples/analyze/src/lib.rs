@@ -0,0 +1,43 @@
+//! Jitter hooks for the analysis examples
+
+#![feature(once_cell)]
+
+use std::sync::LazyLock;
+use jitter::HookType;
+
+/// Set if `ANALYZE_MEM` is in the environment, memory accesses are only
+/// hooked for the analyses that need them
+static HOOK_MEM: LazyLock<bool> = LazyLock::new(|| {
+    std::env::var_os("ANALYZE_MEM").is_some()
+});
+
+/// Set if `ANALYZE_BRANCH` is in the environment, branch instructions are
+/// then hooked with their branch flag and registers
+static HOOK_BRANCH: LazyLock<bool> = LazyLock::new(|| {
+    std::env::var_os("ANALYZE_BRANCH").is_some()
+});
+
+/// Called before an instruction is lifted in QEMU.
+///
+/// The `HookType` dictates the type of hook used for the instruction, and may
+/// be `Never`, `Always`, and `Once`
+///
+/// This may be called from multiple threads
+#[no_mangle]
+fn hook_inst(_pc: u64, branch: bool) -> HookType {
+    // The analyses need every PC in order
+    if branch && *HOOK_BRANCH {
+        HookType::Branch
+    } else {
+        HookType::Always
+    }
+}
+
+/// Called when a memory access is being lifted in QEMU. Returning `true` will
+/// cause the memory access to generate events in the trace buffer.
+///
+/// This may be called from multiple threads
+#[no_mangle]
+fn hook_mem(_pc: u64, _write: bool, _size: usize) -> bool {
+    *HOOK_MEM
+}
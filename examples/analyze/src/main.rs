@@ -0,0 +1,58 @@
+//! Example analyses built on top of [`cannoli::analysis`]
+//!
+//! Run with the name of the analysis to perform, for example
+//! `cargo run --release --bin analyze -- functions`
+
+#![feature(once_cell)]
+
+use cannoli::create_cannoli;
+
+mod functions;
+mod integrity;
+mod jit;
+mod layout;
+mod mix;
+mod uart;
+
+fn main() {
+    let analysis = std::env::args().nth(1);
+
+    match analysis.as_deref() {
+        Some("functions") => {
+            create_cannoli::<functions::Functions>(2).unwrap();
+        }
+        Some("integrity") => {
+            create_cannoli::<integrity::Integrity>(2).unwrap();
+        }
+        Some("jit") => {
+            create_cannoli::<jit::Jit>(2).unwrap();
+        }
+        Some("layout") => {
+            create_cannoli::<layout::Layout>(2).unwrap();
+        }
+        Some("mix") => {
+            create_cannoli::<mix::Mix>(2).unwrap();
+        }
+        Some("uart") => {
+            create_cannoli::<uart::Uart>(2).unwrap();
+        }
+        _ => {
+            eprintln!("usage: analyze <analysis>\n");
+            eprintln!("analyses:");
+            eprintln!("    functions  infer functions and write a symbol \
+                map for each process");
+            eprintln!("    integrity  report writes which modify code, \
+                with the PCs which made them (needs ANALYZE_MEM=1)");
+            eprintln!("    jit        split instructions executed in \
+                static code from dynamically generated code");
+            eprintln!("    layout     structure layout hints from memory \
+                accesses, written as a C header (needs ANALYZE_MEM=1)");
+            eprintln!("    mix        instruction mix of every function, \
+                loads and stores need ANALYZE_MEM=1, untaken branches need \
+                ANALYZE_BRANCH=1");
+            eprintln!("    uart       reconstruct UART output and the \
+                functions which wrote it (needs ANALYZE_MEM=1)");
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,127 @@
+//! Report the instruction mix of every function, and write it out as a table
+
+use std::sync::{Arc, Mutex, LazyLock};
+use std::collections::HashMap;
+use cannoli::{Cannoli, ClientInfo};
+use cannoli::analysis::functions::FunctionInference;
+use cannoli::analysis::mix::{self, InsnMix};
+use cannoli::symbols::{ResolverChain, SymbolTable};
+
+/// Counts and functions of all exited threads, keyed by PID
+static MIXES_BY_PID:
+        LazyLock<Mutex<HashMap<i32, (InsnMix, FunctionInference)>>> =
+    LazyLock::new(Default::default);
+
+/// Events we sequence from the trace
+pub enum Trace {
+    /// An instruction was executed, `branch` is set if it's a branch
+    Exec {
+        pc:     u64,
+        branch: bool,
+    },
+
+    /// A memory access
+    Access {
+        pc:    u64,
+        write: bool,
+    },
+}
+
+/// The structure we implement [`Cannoli`] for! One per target thread
+pub struct Mix {
+    /// Process ID of the target
+    pid: i32,
+
+    /// Counts of this thread
+    mix: InsnMix,
+
+    /// Functions found by this thread, to group the counts by
+    inference: FunctionInference,
+}
+
+impl Cannoli for Mix {
+    type Trace = Trace;
+
+    type PidContext = ();
+    type TidContext = ();
+
+    fn init_pid(_ci: &ClientInfo) -> Arc<Self::PidContext> {
+        Arc::new(())
+    }
+
+    fn init_tid(_pid: &Self::PidContext,
+            ci: &ClientInfo) -> (Self, Self::TidContext) {
+        (Self {
+            pid:       ci.pid,
+            mix:       InsnMix::new(),
+            inference: FunctionInference::new(),
+        }, ())
+    }
+
+    fn exec(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            pc: u64, trace: &mut Vec<Self::Trace>) {
+        trace.push(Trace::Exec { pc, branch: false });
+    }
+
+    fn branch(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            pc: u64, branch: bool, _regs: &[u8],
+            trace: &mut Vec<Self::Trace>) {
+        trace.push(Trace::Exec { pc, branch });
+    }
+
+    fn read(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            pc: u64, _addr: u64, _val: u64, _sz: u8, _helper: bool,
+            trace: &mut Vec<Self::Trace>) {
+        trace.push(Trace::Access { pc, write: false });
+    }
+
+    fn write(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            pc: u64, _addr: u64, _val: u64, _sz: u8, _helper: bool,
+            trace: &mut Vec<Self::Trace>) {
+        trace.push(Trace::Access { pc, write: true });
+    }
+
+    fn trace(&mut self, _pid: &Self::PidContext,
+            _tid: &Self::TidContext, trace: &[Self::Trace]) {
+        for event in trace {
+            match *event {
+                Trace::Exec { pc, branch } => {
+                    self.mix.exec(pc, branch);
+                    self.inference.observe(pc);
+                }
+                Trace::Access { pc, write: false } => self.mix.read(pc),
+                Trace::Access { pc, write: true  } => self.mix.write(pc),
+            }
+        }
+    }
+}
+
+impl Drop for Mix {
+    fn drop(&mut self) {
+        // Merge in what this thread found
+        let mut mixes = MIXES_BY_PID.lock().unwrap();
+        let (merged, functions) = mixes.entry(self.pid).or_default();
+        merged.merge(&self.mix);
+        functions.merge(&self.inference);
+
+        // A symbol map is preferred, functions we inferred are used for
+        // anything it doesn't name
+        let mut symbols = ResolverChain::new();
+        if let Ok(table) = SymbolTable::from_map_file("symbols.txt") {
+            symbols.add(10, table);
+        }
+        symbols.add(0, SymbolTable::from_inferred(functions));
+
+        // Rewrite the report for the process, we don't know which thread is
+        // the last one to exit, so just do it every time
+        let report = merged.report(|x| symbols.resolve(x));
+        let path = format!("mix_{}.txt", self.pid);
+        let file = std::fs::File::create(&path)
+            .expect("Failed to create instruction mix report");
+        mix::write_table(&report, std::io::BufWriter::new(file))
+            .expect("Failed to write instruction mix report");
+
+        println!("Wrote the instruction mix of {} functions to {path}",
+            report.len());
+    }
+}
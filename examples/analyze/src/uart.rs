@@ -0,0 +1,229 @@
+//! Reconstruct the output written to a UART, and correlate every line with
+//! the function which wrote it
+//!
+//! The device memory map is loaded from `devices.toml` (see
+//! [`cannoli::devices`]), and the UART is the device named by `ANALYZE_UART`,
+//! `uart0` if it's not set. Bytes written to offset 0 of the device, the data
+//! register of the common UARTs (PL011, 16550), are the output.
+
+use std::io::Write;
+use std::sync::{Arc, Mutex, LazyLock};
+use std::collections::HashMap;
+use cannoli::{Cannoli, ClientInfo};
+use cannoli::address_space::AddressSpace;
+use cannoli::analysis::functions::FunctionInference;
+use cannoli::devices::DeviceMap;
+use cannoli::symbols::{ResolverChain, SymbolTable};
+
+/// Device map of the machine
+static DEVICES: LazyLock<DeviceMap> = LazyLock::new(|| {
+    DeviceMap::load("devices.toml").unwrap_or_else(|err| {
+        eprintln!("Not using devices.toml: {err}");
+        DeviceMap::new()
+    })
+});
+
+/// Name of the UART device
+static UART: LazyLock<String> = LazyLock::new(|| {
+    std::env::var("ANALYZE_UART").unwrap_or_else(|_| "uart0".to_string())
+});
+
+/// Output of the target processes, keyed by PID. The UART is shared between
+/// threads so this is per-process rather than per-thread
+static SERIAL_BY_PID: LazyLock<Mutex<HashMap<i32, Arc<Mutex<Serial>>>>> =
+    LazyLock::new(Default::default);
+
+/// Events we sequence from the trace
+pub enum Trace {
+    /// An instruction was executed
+    Exec(u64),
+
+    /// A byte was written to the UART
+    Output {
+        pc:   u64,
+        byte: u8,
+    },
+
+    /// A write which may be to a mapping of the UART's device file
+    Write {
+        pc:   u64,
+        addr: u64,
+        val:  u64,
+    },
+
+    /// Memory was mapped
+    Mmap {
+        base:   u64,
+        len:    u64,
+        anon:   bool,
+        read:   bool,
+        write:  bool,
+        exec:   bool,
+        path:   String,
+        offset: u64,
+    },
+
+    /// Memory was unmapped
+    Munmap {
+        base: u64,
+        len:  u64,
+    },
+}
+
+/// Serial output of a process
+#[derive(Default)]
+struct Serial {
+    /// Mappings of the process, to find mappings of device files
+    space: AddressSpace,
+
+    /// Line being written, and the PC which wrote its first byte
+    line: Option<(Vec<u8>, u64)>,
+
+    /// Lines written, with the PC which wrote their first byte
+    lines: Vec<(String, u64)>,
+
+    /// Functions found by the exited threads
+    functions: FunctionInference,
+}
+
+impl Serial {
+    /// Add a byte written by `pc` to the output
+    fn output(&mut self, pc: u64, byte: u8) {
+        let (line, _) = self.line.get_or_insert_with(|| (Vec::new(), pc));
+        match byte {
+            b'\n' => {
+                let (line, pc) = self.line.take().unwrap();
+                let line = String::from_utf8_lossy(&line);
+                self.lines.push((line.trim_end_matches('\r').into(), pc));
+            }
+            _ => line.push(byte),
+        }
+    }
+}
+
+/// The structure we implement [`Cannoli`] for! One per target thread
+pub struct Uart {
+    /// Process ID of the target
+    pid: i32,
+
+    /// Output of the process
+    serial: Arc<Mutex<Serial>>,
+
+    /// Functions found by this thread, to name the writers of the output
+    inference: FunctionInference,
+}
+
+impl Cannoli for Uart {
+    type Trace = Trace;
+
+    type PidContext = ();
+    type TidContext = ();
+
+    fn init_pid(_ci: &ClientInfo) -> Arc<Self::PidContext> {
+        Arc::new(())
+    }
+
+    fn init_tid(_pid: &Self::PidContext,
+            ci: &ClientInfo) -> (Self, Self::TidContext) {
+        let serial = SERIAL_BY_PID.lock().unwrap()
+            .entry(ci.pid).or_default().clone();
+
+        (Self {
+            pid:       ci.pid,
+            inference: FunctionInference::new(),
+            serial,
+        }, ())
+    }
+
+    fn exec(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            pc: u64, trace: &mut Vec<Self::Trace>) {
+        trace.push(Trace::Exec(pc));
+    }
+
+    fn write(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            pc: u64, addr: u64, val: u64, _sz: u8, _helper: bool,
+            trace: &mut Vec<Self::Trace>) {
+        // Devices from the map are checked here in parallel, mappings of
+        // device files need the address space
+        match DEVICES.lookup(None, addr) {
+            Some((device, 0)) if *device == **UART => {
+                trace.push(Trace::Output { pc, byte: val as u8 });
+            }
+            Some(_) => {}
+            None => trace.push(Trace::Write { pc, addr, val }),
+        }
+    }
+
+    fn mmap(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            base: u64, len: u64, anon: bool, read: bool, write: bool,
+            exec: bool, path: &str, offset: u64,
+            trace: &mut Vec<Self::Trace>) {
+        trace.push(Trace::Mmap {
+            path: path.to_string(),
+            base, len, anon, read, write, exec, offset,
+        });
+    }
+
+    fn munmap(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            base: u64, len: u64, trace: &mut Vec<Self::Trace>) {
+        trace.push(Trace::Munmap { base, len });
+    }
+
+    fn trace(&mut self, _pid: &Self::PidContext,
+            _tid: &Self::TidContext, trace: &[Self::Trace]) {
+        let mut serial = self.serial.lock().unwrap();
+        for event in trace {
+            match event {
+                Trace::Exec(pc) => self.inference.observe(*pc),
+                Trace::Output { pc, byte } => serial.output(*pc, *byte),
+                Trace::Write { pc, addr, val } => {
+                    let device = DEVICES.lookup(Some(&serial.space), *addr);
+                    let is_uart = device
+                        .is_some_and(|(x, off)| *x == **UART && off == 0);
+                    if is_uart {
+                        serial.output(*pc, *val as u8);
+                    }
+                }
+                Trace::Mmap { base, len, anon, read, write, exec, path,
+                        offset } => {
+                    serial.space.mmap(*base, *len, *anon, *read, *write,
+                        *exec, path, *offset);
+                }
+                Trace::Munmap { base, len } => {
+                    serial.space.munmap(*base, *len);
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Uart {
+    fn drop(&mut self) {
+        let mut serial = self.serial.lock().unwrap();
+        serial.functions.merge(&self.inference);
+
+        // A symbol map is preferred, functions we inferred are used for
+        // anything it doesn't name
+        let mut symbols = ResolverChain::new();
+        if let Ok(table) = SymbolTable::from_map_file("symbols.txt") {
+            symbols.add(10, table);
+        }
+        symbols.add(0, SymbolTable::from_inferred(&serial.functions));
+
+        // Rewrite the output for the process, we don't know which thread is
+        // the last one to exit, so just do it every time
+        let path = format!("uart_{}.txt", self.pid);
+        let file = std::fs::File::create(&path)
+            .expect("Failed to create UART output");
+        let mut out = std::io::BufWriter::new(file);
+        for (line, pc) in &serial.lines {
+            let function = symbols.resolve(*pc)
+                .map_or(format!("{pc:#x}"), |x| x.symbol.name.to_string());
+            writeln!(out, "{function:>24} | {line}")
+                .expect("Failed to write UART output");
+        }
+
+        println!("Wrote {} lines of UART output to {path}",
+            serial.lines.len());
+    }
+}
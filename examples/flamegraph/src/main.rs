@@ -0,0 +1,247 @@
+//! An example user of Cannoli which profiles the target, printing where its
+//! instructions were executed as folded stacks for `inferno-flamegraph` or
+//! `flamegraph.pl`
+//!
+//! ```text
+//! flamegraph > target.folded
+//! inferno-flamegraph target.folded > target.svg
+//! ```
+//!
+//! Every instruction is hooked with its code, calls and returns are told
+//! apart from other branches by decoding it (see
+//! [`Signatures::call_kind`]), and a [`ShadowStack`] of every thread follows
+//! them. Every instruction counts towards the stack it executed with, so
+//! the widths of the flamegraph are instruction counts. Stacks start with the
+//! name of the process, and are printed once it exits.
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use cannoli::{Cannoli, CannoliOpts, ClientInfo};
+use cannoli::calls::ShadowStack;
+use cannoli::control::Filters;
+use cannoli::event::Event;
+use cannoli::symbols::{ModuleSymbols, Resolver};
+use cannoli::symbols::signatures::{CallKind, Signatures};
+
+/// Deepest stack followed, deeper recursion loses its outermost frames
+const MAX_DEPTH: usize = 256;
+
+/// Events we sequence from the trace
+enum Trace {
+    Exec { pc: u64, kind: Option<CallKind> },
+    Map(Event),
+}
+
+/// The profile of a process
+#[derive(Default)]
+struct Profile {
+    /// Symbols of the process, to name functions with
+    symbols: ModuleSymbols,
+
+    /// Names of the functions called so far, by address
+    names: HashMap<u64, String>,
+
+    /// Instructions executed with every stack, given by the addresses of
+    /// its functions, the outermost first
+    stacks: HashMap<Vec<u64>, u64>,
+}
+
+impl Profile {
+    /// Name the function at `addr`, while it's mapped
+    fn name(&mut self, addr: u64) {
+        if self.names.contains_key(&addr) {
+            return;
+        }
+
+        let name = match self.symbols.resolve(addr) {
+            Some(x) => x.to_string(),
+            None => match self.symbols.space().module_offset(addr) {
+                Some((path, offset)) => format!("{}+{offset:#x}",
+                    path.rsplit('/').next().unwrap_or(&path)),
+                None => format!("{addr:#x}"),
+            },
+        };
+        self.names.insert(addr, name);
+    }
+}
+
+/// A process of the target
+struct Process {
+    /// Name of the process, the root of its stacks
+    name: String,
+
+    /// Decodes calls and returns, `None` if the architecture isn't
+    /// supported
+    signatures: Option<Signatures>,
+
+    /// The profile, shared by every thread
+    profile: Mutex<Profile>,
+}
+
+/// The structure we implement [`Cannoli`] for! One of these exists per target
+/// thread
+struct Flamegraph {
+    /// Calls of the thread which didn't return yet
+    stack: ShadowStack,
+
+    /// Call or return the thread is in, with its address. It goes somewhere
+    /// at the first instruction executed after it and its delay slots
+    branch: Option<(u64, CallKind)>,
+
+    /// Number of instructions after a branch which execute before it goes
+    /// anywhere
+    delay_slots: u64,
+
+    /// Instructions executed with the current stack, not yet added to
+    /// `stacks`
+    count: u64,
+
+    /// Instructions executed with every stack by this thread
+    stacks: HashMap<Vec<u64>, u64>,
+}
+
+impl Flamegraph {
+    /// Add the instructions executed with the current stack
+    fn flush(&mut self) {
+        if self.count == 0 {
+            return;
+        }
+        let key = self.stack.frames().iter().map(|x| x.target).collect();
+        *self.stacks.entry(key).or_default() += std::mem::take(&mut self.count);
+    }
+}
+
+impl Cannoli for Flamegraph {
+    /// The type emit in the serialized trace
+    type Trace = Trace;
+
+    type PidContext = Process;
+
+    type TidContext = ();
+
+    fn init_pid(ci: &ClientInfo) -> Arc<Self::PidContext> {
+        let name = ci.comm.as_deref().map(str::trim)
+            .filter(|x| !x.is_empty())
+            .map_or_else(|| format!("pid {}", ci.pid), str::to_string);
+        let signatures = Signatures::new(ci.arch, ci.big_endian);
+        if signatures.is_none() {
+            eprintln!("[pid {}] Calls of {:?} aren't supported", ci.pid,
+                ci.arch);
+        }
+        Arc::new(Process { name, signatures, profile: Mutex::default() })
+    }
+
+    fn init_tid(pid: &Self::PidContext,
+            _ci: &ClientInfo) -> (Self, Self::TidContext) {
+        let delay_slots = pid.signatures.as_ref()
+            .map_or(0, |x| x.delay_slots() as u64);
+        (Self {
+            stack:  ShadowStack::new().max_depth(MAX_DEPTH),
+            branch: None,
+            count:  0,
+            stacks: HashMap::new(),
+            delay_slots,
+        }, ())
+    }
+
+    fn exec_with_bytes(pid: &Self::PidContext, _tid: &Self::TidContext,
+            pc: u64, bytes: &[u8], trace: &mut Vec<Self::Trace>) {
+        let kind = pid.signatures.as_ref().and_then(|x| x.call_kind(bytes));
+        trace.push(Trace::Exec { pc, kind });
+    }
+
+    fn mmap(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            base: u64, len: u64, anon: bool, read: bool, write: bool,
+            exec: bool, path: &str, offset: u64,
+            trace: &mut Vec<Self::Trace>) {
+        trace.push(Trace::Map(Event::Mmap {
+            base, len, anon, read, write, exec, offset,
+            path: path.into(),
+        }));
+    }
+
+    fn munmap(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            base: u64, len: u64, trace: &mut Vec<Self::Trace>) {
+        trace.push(Trace::Map(Event::Munmap { base, len }));
+    }
+
+    fn trace(&mut self, pid: &Self::PidContext,
+            _tid: &Self::TidContext, trace: &[Self::Trace]) {
+        let mut profile = pid.profile.lock().unwrap();
+        for event in trace {
+            let (pc, kind) = match event {
+                Trace::Map(event) => {
+                    profile.symbols.event(event);
+                    continue;
+                }
+                Trace::Exec { pc, kind } => (*pc, *kind),
+            };
+
+            // The last call or return went to `pc`, unless this is its delay
+            // slot
+            if let Some((site, branch)) = self.branch {
+                let slot = pc.wrapping_sub(site).wrapping_sub(1) <
+                    self.delay_slots * 4;
+                if !slot {
+                    self.flush();
+                    match branch {
+                        CallKind::Call => {
+                            self.stack.call(site, pc);
+                            profile.name(pc);
+                        }
+                        CallKind::Return => {
+                            self.stack.ret(pc);
+                        }
+                    }
+                    self.branch = None;
+                }
+            }
+
+            self.count += 1;
+            if let Some(kind) = kind {
+                self.branch = Some((pc, kind));
+            }
+        }
+    }
+
+    fn thread_exit(&mut self, pid: &Self::PidContext,
+            _ctx: &Self::TidContext, _tid: i32) {
+        self.flush();
+        let mut profile = pid.profile.lock().unwrap();
+        for (stack, count) in self.stacks.drain() {
+            *profile.stacks.entry(stack).or_default() += count;
+        }
+    }
+
+    fn exit(pid: &Self::PidContext, _code: Option<i32>) {
+        let profile = pid.profile.lock().unwrap();
+        let mut lines = profile.stacks.iter().map(|(stack, count)| {
+            let mut line = pid.name.clone();
+            for addr in stack {
+                line.push(';');
+                line.push_str(&profile.names[addr]);
+            }
+            (line, *count)
+        }).collect::<Vec<_>>();
+        lines.sort();
+
+        // One process at a time, so their lines don't interleave
+        let mut out = std::io::stdout().lock();
+        for (line, count) in lines {
+            let _ = writeln!(out, "{line} {count}");
+        }
+    }
+}
+
+fn main() {
+    // Every instruction, with its code to find the calls and returns
+    let filters = Filters {
+        reads:      false,
+        writes:     false,
+        exec_bytes: true,
+        ..Filters::default()
+    };
+    cannoli::create_cannoli_with::<Flamegraph>(
+        CannoliOpts::new(2).filters(filters)).unwrap();
+}
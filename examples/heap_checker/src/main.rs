@@ -0,0 +1,430 @@
+//! An example user of Cannoli which checks the heap of the target, like
+//! AddressSanitizer but without rebuilding the target
+//!
+//! `malloc()`, `calloc()`, `realloc()` and `free()` are found by their
+//! symbols, a call is the PC reaching the start of one of them and its return
+//! is the stack pointer popping the return address. The loads and stores of
+//! the target are checked against the chunks handed out so far, reporting
+//! use-after-free, double and invalid frees, and accesses just past either
+//! end of a live chunk. Accesses made by the allocator itself aren't checked,
+//! it reads and writes the metadata around the chunks.
+//!
+//! Only x86-64 targets are supported, the arguments and return values are
+//! taken from the registers of the System V ABI.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use cannoli::{Architecture, Cannoli, CannoliOpts, ClientInfo};
+use cannoli::control::Filters;
+use cannoli::event::Event;
+use cannoli::symbols::{ModuleSymbols, Resolver};
+
+/// Bytes past either end of a live chunk where accesses are reported as
+/// overflows, rather than left alone as somebody else's memory
+const REDZONE: u64 = 16;
+
+/// Number of recent instructions kept to find the call of an allocator
+/// function
+const HISTORY: usize = 8;
+
+/// Indices of the registers we use, in QEMU's order for x86-64
+const RAX: usize = 0;
+const RSP: usize = 4;
+const RSI: usize = 6;
+const RDI: usize = 7;
+
+/// Allocator functions we hook
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Func {
+    Malloc,
+    Calloc,
+    Realloc,
+    Free,
+}
+
+impl Func {
+    /// Recognize the allocator function named `name`, glibc gives them
+    /// `__libc_` aliases at the same address
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name.strip_prefix("__libc_").unwrap_or(name) {
+            "malloc"         => Self::Malloc,
+            "calloc"         => Self::Calloc,
+            "realloc"        => Self::Realloc,
+            "free" | "cfree" => Self::Free,
+            _ => return None,
+        })
+    }
+}
+
+/// Events we sequence from the trace
+enum Trace {
+    Exec { pc: u64, rax: u64, rsp: u64, rdi: u64, rsi: u64 },
+    Access { pc: u64, addr: u64, sz: u8, write: bool },
+    Map(Event),
+}
+
+/// A chunk handed out by the allocator
+#[derive(Clone, Copy)]
+struct Chunk {
+    /// Size that was asked for
+    size: u64,
+
+    /// Call which allocated the chunk
+    allocated: u64,
+
+    /// Call which freed the chunk, `None` while it's live
+    freed: Option<u64>,
+}
+
+/// A call to the allocator waiting for its return
+struct Call {
+    /// Function called
+    func: Func,
+
+    /// The first two arguments
+    args: [u64; 2],
+
+    /// Stack pointer at the entry of the function, pointing at the return
+    /// address
+    sp: u64,
+
+    /// Address of the call instruction
+    site: u64,
+}
+
+/// The heap of a process
+#[derive(Default)]
+struct Heap {
+    /// Symbols of the process, to find the allocator and for reports
+    symbols: ModuleSymbols,
+
+    /// Allocator functions starting at the PCs executed so far, `None` for
+    /// any other code
+    funcs: HashMap<u64, Option<Func>>,
+
+    /// Module which contains the allocator
+    allocator: Option<Arc<str>>,
+
+    /// Chunks by their address
+    chunks: BTreeMap<u64, Chunk>,
+
+    /// Kinds and PCs of the errors reported so far, to report every bug once
+    reported: HashSet<(&'static str, u64)>,
+}
+
+impl Heap {
+    /// Get the allocator function starting at `pc`, if any
+    fn func(&mut self, pc: u64) -> Option<Func> {
+        if let Some(&func) = self.funcs.get(&pc) {
+            return func;
+        }
+
+        let func = self.symbols.resolve(pc).filter(|x| x.offset == 0)
+            .and_then(|x| Func::from_name(&x.symbol.name));
+        if func.is_some() && self.allocator.is_none() {
+            self.allocator = self.symbols.space().module_offset(pc)
+                .map(|(path, _)| path);
+        }
+        self.funcs.insert(pc, func);
+        func
+    }
+
+    /// Symbolize `pc` for a report
+    fn symbolize(&self, pc: u64) -> String {
+        self.symbols.resolve(pc)
+            .map_or_else(|| format!("{pc:#x}"), |x| format!("{pc:#x} {x}"))
+    }
+
+    /// Report an error of `kind` at `pc`, unless it already was
+    fn report(&mut self, pid: i32, kind: &'static str, pc: u64,
+            detail: String) {
+        if self.reported.insert((kind, pc)) {
+            println!("[pid {pid}] {kind} at {}\n    {detail}",
+                self.symbolize(pc));
+        }
+    }
+
+    /// Describe `chunk` at `base` for a report
+    fn describe(&self, base: u64, chunk: &Chunk) -> String {
+        let mut out = format!("{base:#x} is a {} byte chunk allocated at {}",
+            chunk.size, self.symbolize(chunk.allocated));
+        if let Some(freed) = chunk.freed {
+            out += &format!(", freed at {}", self.symbolize(freed));
+        }
+        out
+    }
+
+    /// Check that `ptr`, passed to `free()` or `realloc()` at `site`, is a
+    /// live chunk
+    fn check_free(&mut self, pid: i32, ptr: u64, site: u64) -> bool {
+        match self.chunks.get(&ptr).copied() {
+            Some(Chunk { freed: None, .. }) => true,
+            Some(chunk) => {
+                let detail = self.describe(ptr, &chunk);
+                self.report(pid, "double free", site, detail);
+                false
+            }
+            None => {
+                self.report(pid, "invalid free", site,
+                    format!("{ptr:#x} isn't the start of a chunk"));
+                false
+            }
+        }
+    }
+
+    /// Record a chunk of `size` bytes at `base`, allocated at `site`. Chunks
+    /// it overlaps were freed and their memory was reused
+    fn alloc(&mut self, base: u64, size: u64, site: u64) {
+        let end = base.saturating_add(size.max(1));
+        let reused = self.chunks.range(..end).rev()
+            .take_while(|x| x.0 + x.1.size.max(1) > base)
+            .map(|(&x, _)| x)
+            .collect::<Vec<_>>();
+        for x in reused {
+            self.chunks.remove(&x);
+        }
+        self.chunks.insert(base, Chunk { size, allocated: site, freed: None });
+    }
+
+    /// Handle the entry of `call`
+    fn entry(&mut self, pid: i32, call: &Call) {
+        let [ptr, _] = call.args;
+        match call.func {
+            // Checked before the call, glibc aborts on the errors it notices
+            Func::Free if ptr != 0 => {
+                if self.check_free(pid, ptr, call.site) {
+                    let chunk = self.chunks.get_mut(&ptr).unwrap();
+                    chunk.freed = Some(call.site);
+                }
+            }
+            Func::Realloc if ptr != 0 => {
+                self.check_free(pid, ptr, call.site);
+            }
+            _ => {}
+        }
+    }
+
+    /// Handle the return of `call`, which returned `ret`
+    fn exit(&mut self, call: &Call, ret: u64) {
+        let [a, b] = call.args;
+        if ret == 0 {
+            // Either failed, or `realloc(ptr, 0)` freed the chunk
+            if call.func == Func::Realloc && b == 0 {
+                if let Some(chunk) = self.chunks.get_mut(&a) {
+                    chunk.freed.get_or_insert(call.site);
+                }
+            }
+            return;
+        }
+
+        match call.func {
+            Func::Malloc  => self.alloc(ret, a, call.site),
+            Func::Calloc  => self.alloc(ret, a.saturating_mul(b), call.site),
+            Func::Realloc => {
+                if let Some(chunk) = self.chunks.get_mut(&a) {
+                    chunk.freed.get_or_insert(call.site);
+                }
+                self.alloc(ret, b, call.site);
+            }
+            Func::Free => {}
+        }
+    }
+
+    /// Check a load or store of `sz` bytes at `addr` by the instruction at
+    /// `pc`
+    fn access(&mut self, pid: i32, pc: u64, addr: u64, sz: u8, write: bool) {
+        let kind = if write { "write" } else { "read" };
+        let end  = addr.saturating_add(sz as u64);
+
+        // The chunk at or below the access, which it's inside of or past
+        if let Some((&base, &chunk)) = self.chunks.range(..=addr).next_back() {
+            let chunk_end = base + chunk.size;
+            if chunk.freed.is_some() && addr < chunk_end {
+                let detail = self.describe(base, &chunk);
+                self.report(pid, "use-after-free", pc, format!(
+                    "{sz} byte {kind} of {addr:#x}, {} bytes into the \
+                    chunk\n    {detail}", addr - base));
+                return;
+            }
+            if chunk.freed.is_none() && end > chunk_end &&
+                    addr < chunk_end + REDZONE && self.checked(pc) {
+                let detail = self.describe(base, &chunk);
+                self.report(pid, "heap overflow", pc, format!(
+                    "{sz} byte {kind} of {addr:#x}, {} bytes past the end \
+                    of the chunk\n    {detail}", end - chunk_end));
+                return;
+            }
+        }
+
+        // The chunk above the access, which it's in front of
+        if let Some((&base, &chunk)) = self.chunks.range(addr + 1..).next() {
+            if chunk.freed.is_none() && end > base.saturating_sub(REDZONE) &&
+                    self.checked(pc) {
+                let detail = self.describe(base, &chunk);
+                self.report(pid, "heap underflow", pc, format!(
+                    "{sz} byte {kind} of {addr:#x}, {} bytes before the \
+                    chunk\n    {detail}", base - addr));
+            }
+        }
+    }
+
+    /// Whether overflows by the instruction at `pc` are reported. The string
+    /// functions of the C library read whole aligned words, which may go past
+    /// the end of a chunk without it being a bug
+    fn checked(&self, pc: u64) -> bool {
+        let module = self.symbols.space().module_offset(pc)
+            .map(|(path, _)| path);
+        self.allocator.is_none() || module != self.allocator
+    }
+}
+
+/// A process of the target
+struct Process {
+    /// Architecture of the target
+    arch: Architecture,
+
+    /// The heap, shared by every thread
+    heap: Mutex<Heap>,
+}
+
+/// The structure we implement [`Cannoli`] for! One of these exists per target
+/// thread
+struct HeapChecker {
+    /// Process ID of the target, for reporting
+    pid: i32,
+
+    /// Call to the allocator the thread is in
+    call: Option<Call>,
+
+    /// PCs and stack pointers of the last instructions executed outside of
+    /// the allocator, the oldest first
+    history: Vec<(u64, u64)>,
+}
+
+impl Cannoli for HeapChecker {
+    /// The type emit in the serialized trace
+    type Trace = Trace;
+
+    type PidContext = Process;
+
+    type TidContext = ();
+
+    fn init_pid(ci: &ClientInfo) -> Arc<Self::PidContext> {
+        if ci.arch != Architecture::X86_64 {
+            eprintln!("[pid {}] Only x86-64 is supported, not {:?}", ci.pid,
+                ci.arch);
+        }
+        Arc::new(Process { arch: ci.arch, heap: Mutex::default() })
+    }
+
+    fn init_tid(_pid: &Self::PidContext,
+            ci: &ClientInfo) -> (Self, Self::TidContext) {
+        (Self { pid: ci.pid, call: None, history: Vec::new() }, ())
+    }
+
+    fn exec_with_regs(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            pc: u64, regs: &[u8], trace: &mut Vec<Self::Trace>) {
+        let reg = |index: usize| regs.get(index * 8..index * 8 + 8)
+            .map_or(0, |x| u64::from_ne_bytes(x.try_into().unwrap()));
+        trace.push(Trace::Exec {
+            pc,
+            rax: reg(RAX),
+            rsp: reg(RSP),
+            rdi: reg(RDI),
+            rsi: reg(RSI),
+        });
+    }
+
+    fn read_addr(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            pc: u64, addr: u64, sz: u8, _helper: bool,
+            trace: &mut Vec<Self::Trace>) {
+        trace.push(Trace::Access { pc, addr, sz, write: false });
+    }
+
+    fn write_addr(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            pc: u64, addr: u64, sz: u8, _helper: bool,
+            trace: &mut Vec<Self::Trace>) {
+        trace.push(Trace::Access { pc, addr, sz, write: true });
+    }
+
+    fn mmap(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            base: u64, len: u64, anon: bool, read: bool, write: bool,
+            exec: bool, path: &str, offset: u64,
+            trace: &mut Vec<Self::Trace>) {
+        trace.push(Trace::Map(Event::Mmap {
+            base, len, anon, read, write, exec, offset,
+            path: path.into(),
+        }));
+    }
+
+    fn munmap(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            base: u64, len: u64, trace: &mut Vec<Self::Trace>) {
+        trace.push(Trace::Map(Event::Munmap { base, len }));
+    }
+
+    fn trace(&mut self, pid: &Self::PidContext,
+            _tid: &Self::TidContext, trace: &[Self::Trace]) {
+        if pid.arch != Architecture::X86_64 {
+            return;
+        }
+
+        let mut heap = pid.heap.lock().unwrap();
+        for event in trace {
+            match event {
+                Trace::Map(event) => heap.symbols.event(event),
+                Trace::Exec { pc, rax, rsp, rdi, rsi } => {
+                    // The allocator returns once the return address popped
+                    if let Some(call) = &self.call {
+                        if *rsp == call.sp.wrapping_add(8) {
+                            heap.exit(call, *rax);
+                            self.call = None;
+                        }
+                        continue;
+                    }
+
+                    if let Some(func) = heap.func(*pc) {
+                        // The call instruction ran with the return address
+                        // not pushed yet, a PLT stub in between didn't
+                        let site = self.history.iter().rev()
+                            .find(|x| x.1 == rsp.wrapping_add(8))
+                            .map_or(*pc, |x| x.0);
+                        let call = Call {
+                            func,
+                            args: [*rdi, *rsi],
+                            sp:   *rsp,
+                            site,
+                        };
+                        heap.entry(self.pid, &call);
+                        self.call = Some(call);
+                        self.history.clear();
+                        continue;
+                    }
+
+                    if self.history.len() == HISTORY {
+                        self.history.remove(0);
+                    }
+                    self.history.push((*pc, *rsp));
+                }
+                Trace::Access { pc, addr, sz, write } => {
+                    if self.call.is_none() {
+                        heap.access(self.pid, *pc, *addr, *sz, *write);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn main() {
+    // Every instruction sends the registers up to `rdi`, and accesses only
+    // need their address
+    let filters = Filters {
+        read_values:    false,
+        write_values:   false,
+        exec_regs:      true,
+        exec_reg_count: Some(RDI + 1),
+        ..Filters::default()
+    };
+    cannoli::create_cannoli_with::<HeapChecker>(
+        CannoliOpts::new(2).filters(filters)).unwrap();
+}
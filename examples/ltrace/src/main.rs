@@ -0,0 +1,157 @@
+//! An example user of Cannoli which logs calls to functions like `ltrace`,
+//! with their arguments and return values decoded from prototypes
+//!
+//! ```text
+//! ltrace <prototypes file>
+//! ```
+//!
+//! See [`cannoli::prototypes`] for the format of the prototypes. Every
+//! instruction is hooked with its registers, and those at the entry of a
+//! function with a prototype, as named by the symbols of the module it's
+//! in, are decoded into a call. Calls are printed as they're entered and
+//! returns as they're reached, indented by the number of calls the thread
+//! is in, so calls made by other functions with prototypes show up nested.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use cannoli::{Cannoli, CannoliOpts, ClientInfo};
+use cannoli::control::{Filters, HookKind};
+use cannoli::event::Event;
+use cannoli::prototypes::{CallTracer, Prototype, Prototypes};
+use cannoli::symbols::{ModuleSymbols, Resolver};
+
+/// Prototypes of the functions to log
+static PROTOTYPES: OnceLock<Prototypes> = OnceLock::new();
+
+/// Events we sequence from the trace
+enum Trace {
+    Regs { pc: u64, regs: Box<[u8]> },
+    Map(Event),
+}
+
+/// Finds the functions with prototypes in a process
+#[derive(Default)]
+struct Functions {
+    /// Symbols of the process
+    symbols: ModuleSymbols,
+
+    /// Prototype of the function every PC seen so far is the entry of, if
+    /// any. Cleared when the mappings change
+    entries: HashMap<u64, Option<Arc<Prototype>>>,
+}
+
+impl Functions {
+    /// Get the prototype of the function `pc` is the entry of
+    fn entry(&mut self, pc: u64) -> Option<Arc<Prototype>> {
+        if let Some(entry) = self.entries.get(&pc) {
+            return entry.clone();
+        }
+
+        let entry = self.symbols.resolve(pc)
+            .filter(|x| x.offset == 0)
+            .and_then(|x| PROTOTYPES.get().unwrap().get(&x.symbol.name))
+            .cloned();
+        self.entries.insert(pc, entry.clone());
+        entry
+    }
+}
+
+/// The structure we implement [`Cannoli`] for! One of these exists per target
+/// thread
+struct Ltrace {
+    /// Process and thread ID of the target thread, for printing
+    ids: (i32, i32),
+
+    /// Decodes calls and returns, `None` if the architecture isn't
+    /// supported
+    tracer: Option<CallTracer>,
+}
+
+impl Cannoli for Ltrace {
+    /// The type emit in the serialized trace
+    type Trace = Trace;
+
+    type PidContext = Mutex<Functions>;
+
+    type TidContext = ();
+
+    fn init_pid(_ci: &ClientInfo) -> Arc<Self::PidContext> {
+        Arc::new(Mutex::default())
+    }
+
+    fn init_tid(_pid: &Self::PidContext,
+            ci: &ClientInfo) -> (Self, Self::TidContext) {
+        let tracer = CallTracer::new(ci);
+        if tracer.is_none() {
+            eprintln!("[pid {} tid {}] Calls of {:?} aren't supported",
+                ci.pid, ci.tid, ci.arch);
+        }
+        (Self { ids: (ci.pid, ci.tid), tracer }, ())
+    }
+
+    fn regs(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            pc: u64, regs: &[u8], trace: &mut Vec<Self::Trace>) {
+        trace.push(Trace::Regs { pc, regs: regs.into() });
+    }
+
+    fn mmap(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            base: u64, len: u64, anon: bool, read: bool, write: bool,
+            exec: bool, path: &str, offset: u64,
+            trace: &mut Vec<Self::Trace>) {
+        trace.push(Trace::Map(Event::Mmap {
+            base, len, anon, read, write, exec, offset,
+            path: path.into(),
+        }));
+    }
+
+    fn munmap(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            base: u64, len: u64, trace: &mut Vec<Self::Trace>) {
+        trace.push(Trace::Map(Event::Munmap { base, len }));
+    }
+
+    fn trace(&mut self, pid: &Self::PidContext,
+            _tid: &Self::TidContext, trace: &[Self::Trace]) {
+        let mut functions = pid.lock().unwrap();
+        for event in trace {
+            let (pc, regs) = match event {
+                Trace::Map(event) => {
+                    functions.symbols.event(event);
+                    functions.entries.clear();
+                    continue;
+                }
+                Trace::Regs { pc, regs } => (*pc, regs),
+            };
+            let Some(tracer) = &mut self.tracer else { continue };
+
+            let (pid, tid) = self.ids;
+            if let Some(ret) = tracer.returned(pc, regs) {
+                println!("[pid {pid} tid {tid}] {:width$}{ret}", "",
+                    width = ret.depth * 2);
+            }
+            let Some(prototype) = functions.entry(pc) else { continue };
+            if let Some(call) = tracer.entered(&prototype, regs) {
+                println!("[pid {pid} tid {tid}] {:width$}{call}", "",
+                    width = call.depth * 2);
+            }
+        }
+    }
+}
+
+fn main() {
+    let path = std::env::args().nth(1)
+        .expect("Expected a file of prototypes");
+    let prototypes = Prototypes::load(&path).expect("Invalid prototypes");
+    eprintln!("Logging calls to {} functions", prototypes.len());
+    PROTOTYPES.set(prototypes).unwrap();
+
+    // The registers of every instruction, to find the entries of functions
+    // and where they return to
+    let filters = Filters {
+        hook:   HookKind::Register,
+        reads:  false,
+        writes: false,
+        ..Filters::default()
+    };
+    cannoli::create_cannoli_with::<Ltrace>(
+        CannoliOpts::new(2).filters(filters)).unwrap();
+}
@@ -0,0 +1,30 @@
+//! Publish the trace to a NATS server
+//!
+//! `nats_client [server] [run_id] [zstd_level] [threads]`
+
+use cannoli::create_cannoli;
+use cannoli::sinks::Recorder;
+use cannoli::sinks::nats::{self, NatsConfig, NatsSink};
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let mut config = NatsConfig::default();
+
+    if let Some(server) = args.next() {
+        config.server = server;
+    }
+    if let Some(run_id) = args.next() {
+        config.run_id = run_id;
+    }
+    config.compression = args.next()
+        .map(|x| x.parse().expect("Invalid zstd level"));
+    let threads = args.next()
+        .map(|x| x.parse().expect("Invalid thread count"))
+        .unwrap_or(2);
+
+    println!("Publishing to {} as {}.{}.<pid>",
+        config.server, config.prefix, config.run_id);
+    nats::configure(config).unwrap();
+
+    create_cannoli::<Recorder<NatsSink>>(threads).unwrap();
+}
@@ -0,0 +1,334 @@
+//! An example user of Cannoli which audits the paths a target running under
+//! QEMU user-mode passes to the kernel, for accesses which leave the root
+//! it's supposed to stay in
+//!
+//! `path_audit <root>` takes the directory the guest's files are in, as
+//! given to QEMU with `-L`. QEMU only prefixes absolute paths with it, and
+//! only when the prefixed file exists, otherwise the guest gets the host's
+//! file. Symlinks and `..` are left to the host kernel, so an absolute
+//! symlink of an unpacked firmware image points into the host. Every path of
+//! `open()`, `openat()`, `stat()`, `unlink()`, `unlinkat()`, `execve()` and
+//! `chdir()` is resolved the way the host sees it, and those which end up
+//! outside of the root are reported once, with why. A summary is printed
+//! when the process exits.
+//!
+//! Paths are read from guest memory when the trace gets to the system call,
+//! which is usually after the target made it, see [`cannoli::guest`]. Paths
+//! relative to a directory file descriptor which wasn't opened in the trace
+//! can't be resolved, they're counted.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use cannoli::{Cannoli, CannoliOpts, ClientInfo};
+use cannoli::control::Filters;
+use cannoli::guest::GuestMemory;
+use cannoli::symbols::signatures::Signatures;
+
+/// Longest path read from guest memory
+const MAX_PATH: usize = 4096;
+
+/// `AT_FDCWD`, the directory file descriptor of the working directory
+const AT_FDCWD: i32 = -100;
+
+/// Root the target is supposed to stay in, canonicalized
+static ROOT: OnceLock<PathBuf> = OnceLock::new();
+
+/// Events we sequence from the trace
+enum Trace {
+    Entry { nr: u64, args: Vec<u64> },
+    Exit { ret: u64 },
+}
+
+/// Why a path left the root
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Escape {
+    /// The path isn't in the root, QEMU passed it to the host as is
+    Host,
+
+    /// `..` climbs above the root
+    DotDot,
+
+    /// A symlink points out of the root
+    Symlink,
+
+    /// A relative path, from a working directory or directory outside of
+    /// the root
+    Relative,
+}
+
+impl Escape {
+    /// Describe the escape for a report
+    fn describe(&self) -> &'static str {
+        match self {
+            Escape::Host     => "isn't in the root, the host's file is used",
+            Escape::DotDot   => "climbs out of the root with `..`",
+            Escape::Symlink  => "follows a symlink out of the root",
+            Escape::Relative => "is relative to a directory outside the root",
+        }
+    }
+}
+
+/// Check if the lexically normalized `path` climbs above its start
+fn climbs(path: &Path) -> bool {
+    let mut depth = 0i32;
+    for component in path.components() {
+        match component {
+            Component::ParentDir => depth -= 1,
+            Component::Normal(_) => depth += 1,
+            _ => {}
+        }
+        if depth < 0 {
+            return true;
+        }
+    }
+    false
+}
+
+/// Resolve `path` as the host kernel does, following symlinks and `..`.
+/// Files which don't exist (yet) are resolved through their directory
+fn canonical(path: &Path) -> PathBuf {
+    if let Ok(x) = path.canonicalize() {
+        return x;
+    }
+    match (path.parent(), path.file_name()) {
+        (Some(dir), Some(name)) if dir != path => canonical(dir).join(name),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// A system call waiting for its return
+struct Pending {
+    /// Name of the system call
+    name: &'static str,
+
+    /// Path the host resolved it to
+    resolved: PathBuf,
+}
+
+/// What we know of a process
+struct Process {
+    /// Names system calls, `None` if the architecture isn't supported
+    signatures: Option<Signatures>,
+
+    /// Bits of the target's registers
+    bits: u8,
+
+    /// Working directory on the host
+    cwd: Mutex<PathBuf>,
+
+    /// Paths of the open file descriptors, as the host resolved them
+    fds: Mutex<HashMap<i32, PathBuf>>,
+
+    /// Audit of the process
+    audit: Mutex<Audit>,
+}
+
+/// Findings of a process
+#[derive(Default)]
+struct Audit {
+    /// Paths checked
+    checked: u64,
+
+    /// Paths relative to a directory file descriptor we don't know
+    unresolved: u64,
+
+    /// System calls and guest paths reported so far
+    reported: HashSet<(&'static str, PathBuf)>,
+
+    /// Escapes reported by kind
+    escapes: HashMap<&'static str, u64>,
+}
+
+/// The structure we implement [`Cannoli`] for! One of these exists per target
+/// thread
+struct PathAudit {
+    /// Process and thread ID of the target thread, for reporting
+    ids: (i32, i32),
+
+    /// Reads the paths
+    mem: GuestMemory,
+
+    /// System call of the thread waiting for its return
+    pending: Option<Pending>,
+}
+
+impl PathAudit {
+    /// Resolve the guest path `path` passed to `name`, relative to the
+    /// directory file descriptor `dirfd`, and report it if it leaves the
+    /// root. Returns the path the host resolved
+    fn check(&mut self, pid: &Process, name: &'static str, dirfd: i32,
+            path: PathBuf) -> Option<PathBuf> {
+        let root = ROOT.get().unwrap();
+
+        // QEMU prefixes absolute paths which exist in the root, so a file
+        // being created goes to the host. Relative ones are the kernel's
+        // business
+        let (candidate, mut escape) = if path.is_absolute() {
+            let prefixed = root.join(path.strip_prefix("/").unwrap());
+            if prefixed.exists() {
+                let escape = climbs(path.strip_prefix("/").unwrap())
+                    .then_some(Escape::DotDot);
+                (prefixed, escape)
+            } else {
+                (path.clone(), Some(Escape::Host))
+            }
+        } else {
+            let dir = if dirfd == AT_FDCWD {
+                pid.cwd.lock().unwrap().clone()
+            } else if let Some(dir) = pid.fds.lock().unwrap().get(&dirfd) {
+                dir.clone()
+            } else {
+                pid.audit.lock().unwrap().unresolved += 1;
+                return None;
+            };
+            let escape = if !dir.starts_with(root) {
+                Some(Escape::Relative)
+            } else {
+                let inside = dir.strip_prefix(root).unwrap().join(&path);
+                climbs(&inside).then_some(Escape::DotDot)
+            };
+            (dir.join(&path), escape)
+        };
+
+        let resolved = canonical(&candidate);
+        if resolved.starts_with(root) {
+            escape = None;
+        } else if escape.is_none() {
+            escape = Some(Escape::Symlink);
+        }
+
+        let mut audit = pid.audit.lock().unwrap();
+        audit.checked += 1;
+        if let Some(escape) = escape {
+            if audit.reported.insert((name, path.clone())) {
+                *audit.escapes.entry(escape.describe()).or_default() += 1;
+                println!("[pid {} tid {}] {name}({path:?}) resolves to {:?}, \
+                    which {}", self.ids.0, self.ids.1, resolved,
+                    escape.describe());
+            }
+        }
+        Some(resolved)
+    }
+}
+
+impl Cannoli for PathAudit {
+    /// The type emit in the serialized trace
+    type Trace = Trace;
+
+    type PidContext = Process;
+
+    type TidContext = ();
+
+    fn init_pid(ci: &ClientInfo) -> Arc<Self::PidContext> {
+        let signatures = Signatures::new(ci.arch, ci.big_endian);
+        if signatures.is_none() {
+            eprintln!("[pid {}] System calls of {:?} aren't supported",
+                ci.pid, ci.arch);
+        }
+        let cwd = ci.cwd.as_deref()
+            .map_or_else(PathBuf::new, |x| canonical(Path::new(x)));
+        Arc::new(Process {
+            bits:  ci.arch.bitness(),
+            cwd:   Mutex::new(cwd),
+            fds:   Mutex::default(),
+            audit: Mutex::default(),
+            signatures,
+        })
+    }
+
+    fn init_tid(_pid: &Self::PidContext,
+            ci: &ClientInfo) -> (Self, Self::TidContext) {
+        (Self {
+            ids:     (ci.pid, ci.tid),
+            mem:     GuestMemory::new(ci),
+            pending: None,
+        }, ())
+    }
+
+    fn syscall_entry(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            _pc: u64, nr: u64, args: &[u64], trace: &mut Vec<Self::Trace>) {
+        trace.push(Trace::Entry { nr, args: args.to_vec() });
+    }
+
+    fn syscall_exit(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            _pc: u64, ret: u64, trace: &mut Vec<Self::Trace>) {
+        trace.push(Trace::Exit { ret });
+    }
+
+    fn trace(&mut self, pid: &Self::PidContext,
+            _tid: &Self::TidContext, trace: &[Self::Trace]) {
+        let Some(signatures) = &pid.signatures else { return };
+        for event in trace {
+            match event {
+                Trace::Entry { nr, args } => {
+                    self.pending = None;
+                    let Some(name) = signatures.syscall_name(*nr) else {
+                        continue;
+                    };
+                    let arg = |x: usize| args.get(x).copied().unwrap_or(0);
+                    let (dirfd, path) = match name {
+                        "open" | "stat" | "unlink" | "execve" | "chdir" =>
+                            (AT_FDCWD, arg(0)),
+                        "openat" | "unlinkat" => (arg(0) as i32, arg(1)),
+                        _ => continue,
+                    };
+
+                    self.mem.invalidate();
+                    let Ok(path) = self.mem.read_cstr(path, MAX_PATH) else {
+                        continue;
+                    };
+                    let path = PathBuf::from(
+                        String::from_utf8_lossy(&path).into_owned());
+                    if let Some(resolved) = self.check(pid, name, dirfd, path) {
+                        self.pending = Some(Pending { name, resolved });
+                    }
+                }
+                Trace::Exit { ret } => {
+                    let Some(pending) = self.pending.take() else { continue };
+                    let shift = 64 - pid.bits as u32;
+                    let ret = ((*ret << shift) as i64) >> shift;
+                    if ret < 0 {
+                        continue;
+                    }
+                    match pending.name {
+                        "open" | "openat" => {
+                            pid.fds.lock().unwrap()
+                                .insert(ret as i32, pending.resolved);
+                        }
+                        "chdir" => *pid.cwd.lock().unwrap() = pending.resolved,
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+
+    fn exit(pid: &Self::PidContext, _code: Option<i32>) {
+        let audit = pid.audit.lock().unwrap();
+        let mut escapes = audit.escapes.iter().collect::<Vec<_>>();
+        escapes.sort();
+        println!("{} paths checked, {} relative to unknown directories, {} \
+            escapes", audit.checked, audit.unresolved, audit.reported.len());
+        for (kind, count) in escapes {
+            println!("    {count} which {kind}");
+        }
+    }
+}
+
+fn main() {
+    let root = std::env::args().nth(1).expect("Expected the root directory");
+    let root = Path::new(&root).canonicalize().expect("Invalid root");
+    ROOT.set(root).unwrap();
+
+    // Only system calls are needed
+    let filters = Filters {
+        exec:     false,
+        reads:    false,
+        writes:   false,
+        syscalls: true,
+        ..Filters::default()
+    };
+    cannoli::create_cannoli_with::<PathAudit>(
+        CannoliOpts::new(2).filters(filters)).unwrap();
+}
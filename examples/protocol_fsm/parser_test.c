@@ -0,0 +1,39 @@
+#include <stdio.h>
+#include <string.h>
+
+// A toy protocol parser. Every message type is handled by its own function,
+// and the order in which they are invoked is the protocol state machine.
+
+__attribute__((noinline)) void parse_hello(const char *msg) {
+    printf("hello:  %s\n", msg);
+}
+
+__attribute__((noinline)) void parse_header(const char *msg) {
+    printf("header: %s\n", msg);
+}
+
+__attribute__((noinline)) void parse_body(const char *msg) {
+    printf("body:   %s\n", msg);
+}
+
+__attribute__((noinline)) void parse_done(const char *msg) {
+    printf("done:   %s\n", msg);
+}
+
+int main(int argc, char *argv[]) {
+    // Dispatch each message on the command line to its handler, with no
+    // checking of the order. That's what the client is for!
+    for (int ii = 1; ii < argc; ii++) {
+        if (!strcmp(argv[ii], "hello")) {
+            parse_hello(argv[ii]);
+        } else if (!strcmp(argv[ii], "header")) {
+            parse_header(argv[ii]);
+        } else if (!strcmp(argv[ii], "body")) {
+            parse_body(argv[ii]);
+        } else if (!strcmp(argv[ii], "done")) {
+            parse_done(argv[ii]);
+        }
+    }
+
+    return 0;
+}
@@ -0,0 +1,43 @@
+//! Jitter hooks for the protocol state machine example, only the entry points
+//! of the states are instrumented
+
+#![feature(once_cell)]
+
+use std::sync::LazyLock;
+use jitter::HookType;
+
+// Only the entry points are needed here, not the transitions
+#[allow(dead_code)]
+mod spec;
+
+/// The state machine we're validating, loaded the first time QEMU lifts an
+/// instruction
+static SPEC: LazyLock<spec::Spec> = LazyLock::new(|| {
+    spec::Spec::load().expect("Failed to load state machine")
+});
+
+/// Called before an instruction is lifted in QEMU.
+///
+/// The `HookType` dictates the type of hook used for the instruction, and may
+/// be `Never`, `Always`, and `Once`
+///
+/// This may be called from multiple threads
+#[no_mangle]
+fn hook_inst(pc: u64, _branch: bool) -> HookType {
+    // We need every entry into a state, so we can't use oneshot hooks here
+    if SPEC.entries.contains_key(&pc) {
+        HookType::Always
+    } else {
+        HookType::Never
+    }
+}
+
+/// Called when a memory access is being lifted in QEMU. Returning `true` will
+/// cause the memory access to generate events in the trace buffer.
+///
+/// This may be called from multiple threads
+#[no_mangle]
+fn hook_mem(_pc: u64, _write: bool, _size: usize) -> bool {
+    // Memory doesn't matter for control flow validation
+    false
+}
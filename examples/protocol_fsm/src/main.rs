@@ -0,0 +1,107 @@
+//! An example user of Cannoli which validates the order in which a protocol
+//! parser enters its states against a user-provided state machine
+
+use std::sync::Arc;
+use cannoli::{Cannoli, ClientInfo, create_cannoli};
+
+mod spec;
+
+use spec::Spec;
+
+/// Events we sequence from the trace
+enum Trace {
+    /// Entered the state with the given ID at `pc`
+    Enter {
+        state: usize,
+        pc:    u64,
+    },
+}
+
+/// The structure we implement [`Cannoli`] for! One of these exists per target
+/// thread, each thread walks the state machine on its own
+struct ProtocolFsm {
+    /// Thread ID of the target thread, for reporting
+    tid: i32,
+
+    /// Current state, `None` until we observe the first state entry
+    current: Option<usize>,
+
+    /// Number of transitions observed
+    transitions: u64,
+
+    /// Number of transitions which violated the state machine
+    violations: u64,
+}
+
+impl Cannoli for ProtocolFsm {
+    /// The type emit in the serialized trace
+    type Trace = Trace;
+
+    /// The state machine is shared by every thread of a process
+    type PidContext = Spec;
+
+    type TidContext = ();
+
+    fn init_pid(_ci: &ClientInfo) -> Arc<Self::PidContext> {
+        Arc::new(Spec::load().expect("Failed to load state machine"))
+    }
+
+    fn init_tid(_pid: &Self::PidContext,
+            ci: &ClientInfo) -> (Self, Self::TidContext) {
+        (Self {
+            tid:         ci.tid,
+            current:     None,
+            transitions: 0,
+            violations:  0,
+        }, ())
+    }
+
+    fn exec(pid: &Self::PidContext, _tid: &Self::TidContext,
+            pc: u64, trace: &mut Vec<Self::Trace>) {
+        // Only state entry points matter, the jitter should only hook those
+        // but we filter anyways in case another jitter is in use
+        if let Some(&state) = pid.entries.get(&pc) {
+            trace.push(Trace::Enter { state, pc });
+        }
+    }
+
+    fn trace(&mut self, pid: &Self::PidContext,
+            _tid: &Self::TidContext, trace: &[Self::Trace]) {
+        for Trace::Enter { state, pc } in trace {
+            self.transitions += 1;
+
+            // Report transitions which are not part of the state machine
+            if !pid.allowed(self.current, *state) {
+                self.violations += 1;
+
+                let from = self.current.map(|x| pid.names[x].as_str())
+                    .unwrap_or("<start>");
+                println!("[tid {}] VIOLATION #{}: {} -> {} at {:#x} \
+                    (transition {})",
+                    self.tid, self.violations, from, pid.names[*state], pc,
+                    self.transitions);
+            }
+
+            // Always follow the observed transition so that we report every
+            // bad edge rather than everything after the first one
+            self.current = Some(*state);
+        }
+    }
+}
+
+impl Drop for ProtocolFsm {
+    fn drop(&mut self) {
+        println!("[tid {}] {} transitions, {} violations",
+            self.tid, self.transitions, self.violations);
+    }
+}
+
+fn main() {
+    // Validate the description before waiting on QEMU, so mistakes in it are
+    // reported up front
+    let spec = Spec::load().expect("Failed to load state machine");
+    println!("Loaded {} states and {} transitions",
+        spec.names.len(), spec.transitions.len() + spec.from_any.len());
+
+    create_cannoli::<ProtocolFsm>(2).unwrap();
+}
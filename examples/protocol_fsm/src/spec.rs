@@ -0,0 +1,136 @@
+//! Parsing of the expected protocol state machine
+//!
+//! This is shared between the jitter (to only hook state entry points) and
+//! the client (to validate the transitions)
+
+use std::collections::{HashMap, HashSet};
+
+/// File holding the state machine description
+pub const STATES_FILE: &str = "states.txt";
+
+/// File holding the `nm` output for the target, used to resolve state names
+/// to their entry points
+pub const SYMBOLS_FILE: &str = "symbols.txt";
+
+/// A description of the expected states and transitions of a protocol parser
+///
+/// Every state is a function in the target, entering the function is
+/// considered entering the state
+#[derive(Debug, Default)]
+pub struct Spec {
+    /// Names of all states, indexed by state ID
+    pub names: Vec<String>,
+
+    /// Mapping of function entry points to state IDs
+    pub entries: HashMap<u64, usize>,
+
+    /// States which are valid as the first state observed on a thread
+    pub initial: HashSet<usize>,
+
+    /// Allowed `(from, to)` transitions
+    pub transitions: HashSet<(usize, usize)>,
+
+    /// States which may be entered from any other state
+    pub from_any: HashSet<usize>,
+}
+
+impl Spec {
+    /// Load the spec from [`STATES_FILE`], resolving entry points with
+    /// [`SYMBOLS_FILE`]
+    pub fn load() -> Result<Self, String> {
+        let states = std::fs::read_to_string(STATES_FILE)
+            .map_err(|x| format!("Failed to read {STATES_FILE}: {x}"))?;
+        let symbols = std::fs::read_to_string(SYMBOLS_FILE)
+            .map_err(|x| format!("Failed to read {SYMBOLS_FILE}: {x}"))?;
+
+        Self::parse(&states, &symbols)
+    }
+
+    /// Parse a spec from the contents of a states file and `nm` output
+    ///
+    /// The states file is line based, `#` starts a comment:
+    ///
+    /// ```text
+    /// initial <state>
+    /// <state> -> <state>
+    /// *       -> <state>
+    /// ```
+    pub fn parse(states: &str, symbols: &str) -> Result<Self, String> {
+        // Parse the symbols into a lookup of name to address
+        let mut addrs = HashMap::new();
+        for line in symbols.lines() {
+            let chunk = line.splitn(3, ' ').collect::<Vec<_>>();
+            if chunk.len() != 3 {
+                continue;
+            }
+
+            if let Ok(addr) = u64::from_str_radix(chunk[0], 16) {
+                addrs.insert(chunk[2].trim(), addr);
+            }
+        }
+
+        let mut spec = Spec::default();
+
+        for (lineno, line) in states.lines().enumerate() {
+            // Strip comments and skip empty lines
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let tokens = line.split_whitespace().collect::<Vec<_>>();
+            match tokens.as_slice() {
+                ["initial", state] => {
+                    let state = spec.state(state, &addrs)?;
+                    spec.initial.insert(state);
+                }
+                ["*", "->", to] => {
+                    let to = spec.state(to, &addrs)?;
+                    spec.from_any.insert(to);
+                }
+                [from, "->", to] => {
+                    let from = spec.state(from, &addrs)?;
+                    let to   = spec.state(to,   &addrs)?;
+                    spec.transitions.insert((from, to));
+                }
+                _ => {
+                    return Err(format!("{STATES_FILE}:{}: invalid line `{line}`",
+                        lineno + 1));
+                }
+            }
+        }
+
+        Ok(spec)
+    }
+
+    /// Get the state ID for `name`, creating the state if it's the first time
+    /// we've seen it
+    fn state(&mut self, name: &str, addrs: &HashMap<&str, u64>)
+            -> Result<usize, String> {
+        // Check if we already know about this state
+        if let Some(idx) = self.names.iter().position(|x| x == name) {
+            return Ok(idx);
+        }
+
+        // Resolve the entry point for the state
+        let addr = *addrs.get(name).ok_or_else(||
+            format!("State `{name}` not found in {SYMBOLS_FILE}"))?;
+
+        let idx = self.names.len();
+        self.names.push(name.to_string());
+        self.entries.insert(addr, idx);
+        Ok(idx)
+    }
+
+    /// Check if moving from `from` to `to` is an expected transition. `from`
+    /// is `None` when `to` is the first state observed
+    pub fn allowed(&self, from: Option<usize>, to: usize) -> bool {
+        match from {
+            None       => self.initial.contains(&to),
+            Some(from) => {
+                self.from_any.contains(&to) ||
+                    self.transitions.contains(&(from, to))
+            }
+        }
+    }
+}
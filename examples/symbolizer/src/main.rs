@@ -1,33 +1,34 @@
 //! An example user of Cannoli which symbolizes a trace
 
-use cannoli::{create_cannoli, Cannoli};
+use cannoli::address_space::AddressSpace;
+use cannoli::symbols::annotations::{AnnotationDb, Annotations};
+use cannoli::symbols::{FlatResolver, Resolved, ResolverChain, SymbolTable};
+use cannoli::{create_cannoli, Cannoli, Pressure};
 use memfd_exec::MemFdExecutable;
 use qemu::qemu_mipsel;
-use std::{process::exit, sync::Arc, thread};
+use std::{
+    process::exit,
+    sync::atomic::{AtomicBool, Ordering},
+    sync::{Arc, Mutex},
+    thread,
+};
 
-/// An original pointer address, and then a resolved symbol + offset for that
-/// address
+/// An original pointer address, and then the resolved symbol + offset for
+/// that address, if any
 struct SymOff {
     /// The "raw", original address
     addr: u64,
 
-    /// Symbol
-    symbol: &'static str,
-
-    /// Offset from the base of the symbol
-    offset: u64,
+    /// Symbol, offset from the base of the symbol, and where the symbol came
+    /// from
+    resolved: Option<Resolved>,
 }
 
 impl std::fmt::Display for SymOff {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        if self.offset != 0 {
-            write!(
-                f,
-                "{:#018x} ({}+{:#x})",
-                self.addr, self.symbol, self.offset
-            )
-        } else {
-            write!(f, "{:#018x} ({})", self.addr, self.symbol)
+        match &self.resolved {
+            Some(resolved) => write!(f, "{:#018x} ({resolved})", self.addr),
+            None => write!(f, "{:#018x} (<unknown>)", self.addr),
         }
     }
 }
@@ -48,6 +49,20 @@ enum Operation {
         val: u64,
         sz: u8,
     },
+    Mmap {
+        base: u64,
+        len: u64,
+        anon: bool,
+        read: bool,
+        write: bool,
+        exec: bool,
+        path: String,
+        offset: u64,
+    },
+    Munmap {
+        base: u64,
+        len: u64,
+    },
 }
 
 /// The structure we implement [`Cannoli`] for!
@@ -55,44 +70,37 @@ struct Symbolizer;
 
 /// Context shared between threads
 struct Context {
-    /// Lookup from an address to a symbol, stored in sorted order
-    symbols: Vec<(u64, &'static str)>,
+    /// Lookup from an address to a symbol, combining all the symbol sources
+    /// we have. PCs are resolved through flat per-page tables, which are
+    /// filled when code is mapped
+    symbols: FlatResolver,
+
+    /// Annotations from previous sessions, applied to executed PCs
+    annotations: Annotations,
+
+    /// Set while processing is about to stall the target, data addresses
+    /// aren't symbolized then
+    shedding: AtomicBool,
 }
 
 impl Context {
     /// Attempt to resolve a symbol into a symbol and an offset
     fn resolve(&self, addr: u64) -> SymOff {
-        // Get access to the symbols
-        let symbols = &self.symbols;
-
-        // Find the symbol
-        match symbols.binary_search_by_key(&addr, |x| x.0) {
-            Ok(pos) => {
-                // Direct symbol match
-                SymOff {
-                    addr,
-                    symbol: symbols[pos].1,
-                    offset: 0,
-                }
-            }
-            Err(pos) => {
-                // Found location after symbol, find the nearest symbol below
-                if let Some((sa, sym)) = pos.checked_sub(1).and_then(|x| symbols.get(x)) {
-                    // Got symbol below
-                    SymOff {
-                        addr,
-                        symbol: sym,
-                        offset: addr - sa,
-                    }
-                } else {
-                    // No symbols below this address, just emit the PC
-                    SymOff {
-                        addr,
-                        symbol: "<unknown>",
-                        offset: addr,
-                    }
-                }
-            }
+        // Data addresses can't use the pre-resolved tables, so they're the
+        // first thing to go when we're falling behind
+        let resolved = if self.shedding.load(Ordering::Relaxed) {
+            None
+        } else {
+            self.symbols.chain().resolve(addr)
+        };
+        SymOff { addr, resolved }
+    }
+
+    /// Resolve a PC, using the pre-resolved tables
+    fn resolve_pc(&self, pc: u64) -> SymOff {
+        SymOff {
+            addr: pc,
+            resolved: self.symbols.resolve(pc),
         }
     }
 }
@@ -105,34 +113,57 @@ impl Cannoli for Symbolizer {
     /// processing. We stuff our symbol table here.
     type TidContext = Context;
 
-    type PidContext = ();
+    /// Address space of the process, used to find the module-relative
+    /// addresses annotations are stored with
+    type PidContext = Mutex<AddressSpace>;
 
     fn init_pid(_: &cannoli::ClientInfo) -> Arc<Self::PidContext> {
-        Arc::new(())
+        Arc::new(Mutex::new(AddressSpace::new()))
     }
 
     /// Load the symbol table
     fn init_tid(_pid: &Self::PidContext, _: &cannoli::ClientInfo) -> (Self, Self::TidContext) {
-        // Symbols
-        let mut symbols = Vec::new();
+        let mut symbols = ResolverChain::new();
 
-        // Load the symbol file up and leak it so all the lifetimes are static
-        let data = std::fs::read_to_string("symbols.txt").unwrap();
-        let data = Box::leak(data.into_boxed_str());
-
-        // Parse each line into an address and symbol
-        for line in data.lines() {
-            let chunk = line.splitn(3, ' ').collect::<Vec<_>>();
+        // The symbol map is preferred, fall back to the symbols of the
+        // binaries themselves for anything it doesn't name
+        match SymbolTable::from_map_file("symbols.txt") {
+            Ok(table) => symbols.add(10, table),
+            Err(err) => eprintln!("Not using symbols.txt: {err}"),
+        }
 
-            let addr = u64::from_str_radix(chunk[0], 16).unwrap();
-            let sym = chunk[2];
-            symbols.push((addr, sym));
+        // C++ and Rust names are demangled unless `SYMBOLIZER_RAW_SYMBOLS` is
+        // set
+        let raw = std::env::var_os("SYMBOLIZER_RAW_SYMBOLS").is_some();
+        for elf in ["example_app", "example_app64"] {
+            match SymbolTable::from_elf(elf) {
+                Ok(mut table) => {
+                    if raw {
+                        table.raw_names();
+                    }
+                    symbols.add(0, table)
+                }
+                Err(err) => eprintln!("Not using symbols from {elf}: {err}"),
+            }
         }
 
-        // Sort the symbols by address
-        symbols.sort_by_key(|x| x.0);
+        // Labels and notes from previous sessions, see `cannoli annotate`
+        let annotations = AnnotationDb::open("annotations.db")
+            .and_then(|db| db.load())
+            .unwrap_or_else(|err| {
+                eprintln!("Not using annotations.db: {err}");
+                Annotations::default()
+            });
 
-        (Self, Context { symbols })
+        (
+            Self,
+            Context {
+                // Instructions are at least 2-byte aligned on MIPS and RISC-V
+                symbols: FlatResolver::new(symbols, 2),
+                annotations,
+                shedding: AtomicBool::new(false),
+            },
+        )
     }
 
     /// Convert PCs into symbol + offset in parallel
@@ -143,7 +174,7 @@ impl Cannoli for Symbolizer {
         trace: &mut Vec<Self::Trace>,
     ) {
         trace.push(Operation::Exec {
-            pc: tid.resolve(pc),
+            pc: tid.resolve_pc(pc),
         });
     }
 
@@ -155,10 +186,11 @@ impl Cannoli for Symbolizer {
         addr: u64,
         val: u64,
         sz: u8,
+        _helper: bool,
         trace: &mut Vec<Self::Trace>,
     ) {
         trace.push(Operation::Read {
-            pc: tid.resolve(pc),
+            pc: tid.resolve_pc(pc),
             addr: tid.resolve(addr),
             val,
             sz,
@@ -173,22 +205,82 @@ impl Cannoli for Symbolizer {
         addr: u64,
         val: u64,
         sz: u8,
+        _helper: bool,
         trace: &mut Vec<Self::Trace>,
     ) {
         trace.push(Operation::Write {
-            pc: tid.resolve(pc),
+            pc: tid.resolve_pc(pc),
             addr: tid.resolve(addr),
             val,
             sz,
         });
     }
 
+    /// Track mappings so we know which module each PC is in
+    fn mmap(
+        _pid: &Self::PidContext,
+        tid: &Self::TidContext,
+        base: u64,
+        len: u64,
+        anon: bool,
+        read: bool,
+        write: bool,
+        exec: bool,
+        path: &str,
+        offset: u64,
+        trace: &mut Vec<Self::Trace>,
+    ) {
+        // Resolve all the code of modules up front, rather than in the hot
+        // path of `exec`
+        if exec && !anon {
+            tid.symbols.prefill(base, len);
+        }
+
+        trace.push(Operation::Mmap {
+            path: path.to_string(),
+            base,
+            len,
+            anon,
+            read,
+            write,
+            exec,
+            offset,
+        });
+    }
+
+    /// Track unmappings
+    fn munmap(
+        _pid: &Self::PidContext,
+        tid: &Self::TidContext,
+        base: u64,
+        len: u64,
+        trace: &mut Vec<Self::Trace>,
+    ) {
+        tid.symbols.invalidate(base, len);
+        trace.push(Operation::Munmap { base, len });
+    }
+
+    /// Stop symbolizing data addresses when processing is falling behind
+    fn pressure(_pid: &Self::PidContext, tid: &Self::TidContext, level: Pressure) {
+        tid.shedding
+            .store(level == Pressure::Critical, Ordering::Relaxed);
+    }
+
     /// Print the trace we processed!
-    fn trace(&mut self, _pid: &Self::PidContext, _tid: &Self::TidContext, trace: &[Self::Trace]) {
+    fn trace(&mut self, pid: &Self::PidContext, tid: &Self::TidContext, trace: &[Self::Trace]) {
+        let mut space = pid.lock().unwrap();
         for op in trace {
             match op {
                 Operation::Exec { pc } => {
-                    println!("\x1b[0;34mEXEC\x1b[0m   @ {pc}");
+                    print!("\x1b[0;34mEXEC\x1b[0m   @ {pc}");
+                    for annotation in tid.annotations.at(&space, pc.addr) {
+                        print!(" \x1b[0;33m; {}", annotation.label);
+                        if let Some(note) = &annotation.note {
+                            print!(": {note}");
+                        }
+                        print!("\x1b[0m");
+                    }
+                    println!();
                 }
                 Operation::Read { pc, addr, val, sz } => {
                     println!(
@@ -202,6 +294,21 @@ impl Cannoli for Symbolizer {
                         {addr} ={val:#x}"
                     );
                 }
+                Operation::Mmap {
+                    base,
+                    len,
+                    anon,
+                    read,
+                    write,
+                    exec,
+                    path,
+                    offset,
+                } => {
+                    space.mmap(*base, *len, *anon, *read, *write, *exec, path, *offset);
+                }
+                Operation::Munmap { base, len } => {
+                    space.munmap(*base, *len);
+                }
             }
         }
     }
@@ -0,0 +1,154 @@
+//! An example user of Cannoli which reports where data the target read from
+//! files and sockets ends up, see `cannoli_taint`
+//!
+//! `taint [syscall:arg]...` also taints the buffers of the given system
+//! calls, eg. `taint recvmsg:1`
+
+use std::sync::{Arc, Mutex};
+use cannoli::{Architecture, Cannoli, CannoliOpts, ClientInfo};
+use cannoli::control::Filters;
+use cannoli_taint::{Finding, Reach, TaintMap, Tracker};
+
+/// Extra source system calls given on the command line
+static SOURCES: Mutex<Vec<(&'static str, usize)>> = Mutex::new(Vec::new());
+
+/// Events we sequence from the trace
+enum Trace {
+    Exec { pc: u64 },
+    Read { pc: u64, addr: u64, val: u64, sz: u8 },
+    Write { pc: u64, addr: u64, val: u64, sz: u8 },
+    SyscallEntry { pc: u64, nr: u64, args: Vec<u64> },
+    SyscallExit { pc: u64, ret: u64 },
+}
+
+/// The taint of the memory of a process
+struct Process {
+    /// Architecture of the target
+    arch: Architecture,
+
+    /// Whether the target is big endian
+    big_endian: bool,
+
+    /// The taint, shared by every thread
+    map: Mutex<TaintMap>,
+}
+
+/// The structure we implement [`Cannoli`] for! One of these exists per target
+/// thread
+struct Taint {
+    /// Thread ID of the target thread, for reporting
+    tid: i32,
+
+    /// Follows the taint of the thread, `None` if the architecture isn't
+    /// supported
+    tracker: Option<Tracker>,
+
+    /// Scratch buffer for findings
+    found: Vec<Finding>,
+}
+
+impl Cannoli for Taint {
+    /// The type emit in the serialized trace
+    type Trace = Trace;
+
+    type PidContext = Process;
+
+    type TidContext = ();
+
+    fn init_pid(ci: &ClientInfo) -> Arc<Self::PidContext> {
+        Arc::new(Process {
+            arch:       ci.arch,
+            big_endian: ci.big_endian,
+            map:        Mutex::new(TaintMap::new()),
+        })
+    }
+
+    fn init_tid(pid: &Self::PidContext,
+            ci: &ClientInfo) -> (Self, Self::TidContext) {
+        let tracker = Tracker::new(pid.arch, pid.big_endian).map(|x| {
+            SOURCES.lock().unwrap().iter()
+                .fold(x, |x, &(name, buf)| x.source(name, buf))
+        });
+        if tracker.is_none() {
+            eprintln!("[tid {}] System calls of {:?} aren't supported",
+                ci.tid, pid.arch);
+        }
+        (Self { tid: ci.tid, tracker, found: Vec::new() }, ())
+    }
+
+    fn exec(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            pc: u64, trace: &mut Vec<Self::Trace>) {
+        trace.push(Trace::Exec { pc });
+    }
+
+    fn read(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            pc: u64, addr: u64, val: u64, sz: u8, _helper: bool,
+            trace: &mut Vec<Self::Trace>) {
+        trace.push(Trace::Read { pc, addr, val, sz });
+    }
+
+    fn write(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            pc: u64, addr: u64, val: u64, sz: u8, _helper: bool,
+            trace: &mut Vec<Self::Trace>) {
+        trace.push(Trace::Write { pc, addr, val, sz });
+    }
+
+    fn syscall_entry(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            pc: u64, nr: u64, args: &[u64], trace: &mut Vec<Self::Trace>) {
+        trace.push(Trace::SyscallEntry { pc, nr, args: args.to_vec() });
+    }
+
+    fn syscall_exit(_pid: &Self::PidContext, _tid: &Self::TidContext,
+            pc: u64, ret: u64, trace: &mut Vec<Self::Trace>) {
+        trace.push(Trace::SyscallExit { pc, ret });
+    }
+
+    fn trace(&mut self, pid: &Self::PidContext,
+            _tid: &Self::TidContext, trace: &[Self::Trace]) {
+        let Some(tracker) = &mut self.tracker else {
+            return;
+        };
+
+        let mut map = pid.map.lock().unwrap();
+        for event in trace {
+            match event {
+                Trace::Exec { pc } => tracker.exec(&map, *pc, &mut self.found),
+                Trace::Read { pc, addr, val, sz } =>
+                    tracker.read(&map, *pc, *addr, *val, *sz),
+                Trace::Write { pc, addr, val, sz } =>
+                    tracker.write(&mut map, *pc, *addr, *val, *sz),
+                Trace::SyscallEntry { pc, nr, args } =>
+                    tracker.syscall_entry(*pc, *nr, args, &mut self.found),
+                Trace::SyscallExit { pc, ret } =>
+                    tracker.syscall_exit(&mut map, *pc, *ret),
+            }
+        }
+
+        for finding in self.found.drain(..) {
+            let sink = match finding.reach {
+                Reach::Pc => "the PC".to_string(),
+                Reach::SyscallArg { nr, name, index } => format!(
+                    "argument {index} of {}", name.map_or_else(
+                        || format!("system call {nr}"), |x| format!("{x}()"))),
+            };
+            let source = map.source(finding.label).map_or_else(
+                || "?".to_string(), |x| format!("{}() at {:#x} into {:#x}",
+                    x.syscall, x.pc, x.addr));
+            println!("[tid {}] {:#x} from {source} reaches {sink} at {:#x}",
+                self.tid, finding.value, finding.pc);
+        }
+    }
+}
+
+fn main() {
+    for arg in std::env::args().skip(1) {
+        let (name, buf) = arg.split_once(':').expect("Expected syscall:arg");
+        let buf = buf.parse().expect("Invalid argument index");
+        SOURCES.lock().unwrap().push((name.to_string().leak(), buf));
+    }
+
+    // Loads need their values, and system calls have to be reported
+    let filters = Filters { syscalls: true, ..Filters::default() };
+    cannoli::create_cannoli_with::<Taint>(
+        CannoliOpts::new(2).filters(filters)).unwrap();
+}
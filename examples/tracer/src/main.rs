@@ -3,7 +3,7 @@
 use cannoli::{create_cannoli, Cannoli};
 use memfd_exec::MemFdExecutable;
 use qemu::qemu_x86_64;
-use std::{process::exit, sync::Arc, thread};
+use std::{process::{exit, Command}, sync::Arc, thread};
 
 /// An original pointer address, and then a resolved symbol + offset for that
 /// address
@@ -103,22 +103,29 @@ impl Cannoli for Tracer {
     type Trace = Operation;
 
     /// Context, the shared, immutable context shared between all threads doing
-    /// processing. We stuff our symbol table here.
-    type TidContext = Context;
+    /// processing of a process. We stuff its symbol table here.
+    type PidContext = Context;
 
-    type PidContext = ();
+    type TidContext = ();
 
-    fn init_pid(_info: &cannoli::ClientInfo) -> Arc<Self::PidContext> {
-        Arc::new(())
-    }
-
-    /// Load the symbol table
-    fn init_tid(_pid: &Self::PidContext, _info: &cannoli::ClientInfo) -> (Self, Self::TidContext) {
+    /// Load the symbol table of the main binary of the process, or the one in
+    /// `symbols.txt` if `nm` can't get it
+    fn init_pid(info: &cannoli::ClientInfo) -> Arc<Self::PidContext> {
         // Symbols
         let mut symbols = Vec::new();
 
-        // Load the symbol file up and leak it so all the lifetimes are static
-        let data = std::fs::read_to_string("symbols.txt").unwrap();
+        // Get the symbols in `nm` format, and leak them so all the lifetimes
+        // are static. C++ and Rust names are demangled unless
+        // `TRACER_RAW_SYMBOLS` is set
+        let demangle = std::env::var_os("TRACER_RAW_SYMBOLS").is_none();
+        let data = info.exe.as_ref()
+            .and_then(|exe| {
+                Command::new("nm").args(demangle.then_some("--demangle"))
+                    .arg(exe).output().ok()
+            })
+            .filter(|x| x.status.success())
+            .and_then(|x| String::from_utf8(x.stdout).ok())
+            .unwrap_or_else(|| std::fs::read_to_string("symbols.txt").unwrap());
         let data = Box::leak(data.into_boxed_str());
 
         // Parse each line into an address and symbol
@@ -134,7 +141,11 @@ impl Cannoli for Tracer {
         // Sort the symbols by address
         symbols.sort_by_key(|x| x.0);
 
-        (Self, Context { symbols })
+        Arc::new(Context { symbols })
+    }
+
+    fn init_tid(_pid: &Self::PidContext, _info: &cannoli::ClientInfo) -> (Self, Self::TidContext) {
+        (Self, ())
     }
 
     fn mmap(
@@ -157,29 +168,30 @@ impl Cannoli for Tracer {
 
     /// Convert PCs into symbol + offset in parallel
     fn exec(
-        _pid: &Self::PidContext,
-        tid: &Self::TidContext,
+        pid: &Self::PidContext,
+        _tid: &Self::TidContext,
         pc: u64,
         trace: &mut Vec<Self::Trace>,
     ) {
         trace.push(Operation::Exec {
-            pc: tid.resolve(pc),
+            pc: pid.resolve(pc),
         });
     }
 
     /// Symbolize reads
     fn read(
-        _pid: &Self::PidContext,
-        tid: &Self::TidContext,
+        pid: &Self::PidContext,
+        _tid: &Self::TidContext,
         pc: u64,
         addr: u64,
         val: u64,
         sz: u8,
+        _helper: bool,
         trace: &mut Vec<Self::Trace>,
     ) {
         trace.push(Operation::Read {
-            pc: tid.resolve(pc),
-            addr: tid.resolve(addr),
+            pc: pid.resolve(pc),
+            addr: pid.resolve(addr),
             val,
             sz,
         });
@@ -187,17 +199,18 @@ impl Cannoli for Tracer {
 
     /// Symbolize writes
     fn write(
-        _pid: &Self::PidContext,
-        tid: &Self::TidContext,
+        pid: &Self::PidContext,
+        _tid: &Self::TidContext,
         pc: u64,
         addr: u64,
         val: u64,
         sz: u8,
+        _helper: bool,
         trace: &mut Vec<Self::Trace>,
     ) {
         trace.push(Operation::Write {
-            pc: tid.resolve(pc),
-            addr: tid.resolve(addr),
+            pc: pid.resolve(pc),
+            addr: pid.resolve(addr),
             val,
             sz,
         });
@@ -71,13 +71,13 @@ impl Cannoli for Tracer {
     }
 
     fn read(_pid: &Self::PidContext, _tid: &Self::TidContext,
-            _pc: u64, addr: u64, _val: u64, _sz: u8,
+            _pc: u64, addr: u64, _val: u64, _sz: u8, _helper: bool,
             trace: &mut Vec<Self::Trace>) {
         trace.push(Trace::Load(addr as u32));
     }
 
     fn write(_pid: &Self::PidContext, _tid: &Self::TidContext,
-             _pc: u64, addr: u64, _val: u64, _sz: u8,
+             _pc: u64, addr: u64, _val: u64, _sz: u8, _helper: bool,
              trace: &mut Vec<Self::Trace>) {
         trace.push(Trace::Store(addr as u32));
     }
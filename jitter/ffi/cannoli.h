@@ -10,7 +10,7 @@ typedef __UINT64_TYPE__ uint64_t;
 typedef __SIZE_TYPE__   size_t;
 
 /// Random 64-bit integer defining this Cannoli version
-static const uint64_t CANNOLI_VERSION = 0x9ee44dcf4508ae3eULL;
+static const uint64_t CANNOLI_VERSION = 0x9d14b7e6c3a25f08ULL;
 
 /// Poison value to indicate that the trace buffer is not actively set
 static const uint64_t CANNOLI_POISON = 0x5ac91c0a3c7b863eULL;
@@ -86,6 +86,35 @@ struct Cannoli32 {
     /// Invoked when the Linux application invokes munmap() (even if
     /// unsuccessful)
     void (*munmap)(uint32_t start, uint32_t len);
+
+    /// Invoked after a QEMU helper accessed guest memory for the JIT, eg. for
+    /// instructions QEMU doesn't lift to TCG memory operations. These
+    /// accesses never go through `lift_memop`
+    ///
+    /// This function is called with the parameters:
+    ///
+    /// - `state`    - The values of `r12`, `r13`, and `r14` the JIT saved
+    ///                before calling the helper, with the target program
+    ///                counter of the calling instruction in `r14`. The JIT
+    ///                reloads `r12` and `r13` from here when the helper
+    ///                returns
+    /// - `is_write` - `0` if this is a read, `1` if this is a write
+    /// - `addr`     - The emulated guest's address that was accessed
+    /// - `memop`    - One of the QEMU memops from `MemOp` (eg. `MO_8`). This
+    ///                tells us the size of the operation
+    void (*helper_memop)(uint64_t *state, int32_t is_write, uint32_t addr,
+        int32_t memop);
+
+    /// Invoked after QEMU set up the frame of a signal it delivers to the
+    /// guest, before the guest runs the handler
+    ///
+    /// This function is called with the parameters:
+    ///
+    /// - `sig`  - The guest's number of the signal
+    /// - `from` - Target program counter the signal interrupted, where the
+    ///            guest resumes once the handler returns
+    /// - `to`   - Target program counter of the handler
+    void (*guest_signal)(int32_t sig, uint32_t from, uint32_t to);
 };
 
 /// Definition of the bindings defined in Cannoli, passed to QEMU so it knows
@@ -159,6 +188,35 @@ struct Cannoli64 {
     /// Invoked when the Linux application invokes munmap() (even if
     /// unsuccessful)
     void (*munmap)(uint64_t start, uint64_t len);
+
+    /// Invoked after a QEMU helper accessed guest memory for the JIT, eg. for
+    /// instructions QEMU doesn't lift to TCG memory operations. These
+    /// accesses never go through `lift_memop`
+    ///
+    /// This function is called with the parameters:
+    ///
+    /// - `state`    - The values of `r12`, `r13`, and `r14` the JIT saved
+    ///                before calling the helper, with the target program
+    ///                counter of the calling instruction in `r14`. The JIT
+    ///                reloads `r12` and `r13` from here when the helper
+    ///                returns
+    /// - `is_write` - `0` if this is a read, `1` if this is a write
+    /// - `addr`     - The emulated guest's address that was accessed
+    /// - `memop`    - One of the QEMU memops from `MemOp` (eg. `MO_8`). This
+    ///                tells us the size of the operation
+    void (*helper_memop)(uint64_t *state, int32_t is_write, uint64_t addr,
+        int32_t memop);
+
+    /// Invoked after QEMU set up the frame of a signal it delivers to the
+    /// guest, before the guest runs the handler
+    ///
+    /// This function is called with the parameters:
+    ///
+    /// - `sig`  - The guest's number of the signal
+    /// - `from` - Target program counter the signal interrupted, where the
+    ///            guest resumes once the handler returns
+    /// - `to`   - Target program counter of the handler
+    void (*guest_signal)(int32_t sig, uint64_t from, uint64_t to);
 };
 
 // If we're building in QEMU these will be defined and we'll make an alias for
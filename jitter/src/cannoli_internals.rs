@@ -12,14 +12,20 @@ compile_error!("Currently only Linux support is available. This includes \
 compile_error!("This code literally has x86_64 assembly at its core, so uhh \
     x86_64 only right now :)");
 
-use std::io::Write;
+use std::io::{Read, Write};
 use std::ffi::CStr;
-use std::net::TcpStream;
+use std::net::{Shutdown, TcpStream};
 use std::mem::{ManuallyDrop, size_of};
 use std::cell::{RefCell, UnsafeCell, RefMut};
-use std::sync::OnceLock;
-use std::sync::atomic::{AtomicI32, AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicUsize, Ordering};
 use cannoli::{Architecture, ClientConn};
+use cannoli::heap::{HeapClassifier, HeapEvent};
+use cannoli::pin::{PIN_VAR, PinnedCall, Pinner, Pins};
+use cannoli::syscalls::SyscallAbi;
+use cannoli::symbols::signatures::{CallKind, CmpOperand, Comparison};
+use cannoli::symbols::signatures::Signatures;
 use mempipe::{SendPipe, ChunkWriter};
 
 /// Chunk size to use when streaming data over IPC
@@ -64,6 +70,16 @@ pub enum HookType {
     /// target architecture
     Branch,
 
+    /// Hook fires every time an instruction which may be a branch, or which
+    /// starts a translation block, is hit, and reports PC and which of the
+    /// two it is. Other instructions aren't hooked, see [`cannoli::edges`]
+    Edge,
+
+    /// Edge hooks, but only on calls and returns and the starts of
+    /// translation blocks, so the client only sees where calls and returns
+    /// went, see [`cannoli::calls`]
+    Function,
+
     /// Don't hook at all
     Never,
 }
@@ -75,8 +91,9 @@ struct HookState {
     /// Pipe to use to send data out of QEMU to the processing process
     pipe: SendPipe<CHUNK_SIZE, NUM_BUFFERS>,
 
-    /// Connection to the server for sending metadata needed to establish IPC
-    _server: TcpStream,
+    /// Connection to the server for sending metadata needed to establish IPC,
+    /// and receiving control messages
+    server: TcpStream,
 
     /// Currently active buffer. This is set upon JIT entries, and taken on JIT
     /// exits.
@@ -85,6 +102,19 @@ struct HookState {
     /// through the boundaries of the JIT entry and exit
     active_buffer: Option<
         ManuallyDrop<ChunkWriter<'static, CHUNK_SIZE, NUM_BUFFERS>>>,
+
+    /// Latest epoch marked in our trace, see [`crate::control::epoch`]
+    epoch: u64,
+
+    /// PC of the last instruction this thread lifted, and if it may end a
+    /// basic block. QEMU lifts translation blocks an instruction at a time
+    /// in order, so this tells where they start
+    last_lift: Option<(u64, bool)>,
+
+    /// System call with a pinned result this thread is in, as the PC of its
+    /// system call instruction, the call and its arguments, see
+    /// [`cannoli::pin`]
+    pinned: Option<(u64, PinnedCall, [u64; 6])>,
 }
 
 impl Default for HookState {
@@ -112,6 +142,31 @@ impl Default for HookState {
         let comm = std::fs::read(format!("/proc/{pid}/comm"))
             .expect("Cannoli: Failed to read `/proc/<pid>/comm`");
 
+        // Prove we're who the server is waiting for, see `cannoli::auth`
+        let token = cannoli::auth::token().unwrap_or_default();
+
+        // Tell what the guest runs, leaving out the arguments and the
+        // environment entries which don't fit in the greeting
+        let GuestImage { exe, argv, env } = GuestImage::get();
+        let cwd = std::env::current_dir().map_or(String::new(),
+            |x| x.to_string_lossy().into_owned());
+        let mut budget = (cannoli::MAX_IMAGE_LEN as usize)
+            .saturating_sub(exe.len() + cwd.len());
+        let mut list = |x: &[String]| {
+            let mut ret = Vec::new();
+            for entry in x {
+                if entry.len() >= budget {
+                    break;
+                }
+                budget -= entry.len() + 1;
+                ret.extend_from_slice(entry.as_bytes());
+                ret.push(0);
+            }
+            ret
+        };
+        let argv = list(&argv);
+        let env  = list(&env);
+
         // Construct the payload to send to the server
         let header = ClientConn {
             uid:        pipe.uid(),
@@ -119,6 +174,13 @@ impl Default for HookState {
             big_endian: qi.big_endian as i32,
             pcomm_len:  pcomm.len()   as u32,
             comm_len:   comm.len()    as u32,
+            token_len:  token.len()   as u32,
+            exe_len:    exe.len()     as u32,
+            cwd_len:    cwd.len()     as u32,
+            argv_len:   argv.len()    as u32,
+            env_len:    env.len()     as u32,
+            cpu:        cpu_index(),
+            reserved:   0,
             ppid,
             pid,
             tid,
@@ -135,19 +197,51 @@ impl Default for HookState {
         // Add the parent and self comm values
         payload.extend_from_slice(&pcomm);
         payload.extend_from_slice(&comm);
+        payload.extend_from_slice(&token);
+
+        // Add the image
+        payload.extend_from_slice(exe.as_bytes());
+        payload.extend_from_slice(cwd.as_bytes());
+        payload.extend_from_slice(&argv);
+        payload.extend_from_slice(&env);
 
         // Send the data!
         server.write_all(&payload)
             .expect("Cannoli: Failed to send initial greeting");
 
+        // Wait for the server to accept us
+        let mut accepted = [0u8];
+        if server.read_exact(&mut accepted).is_err() ||
+                accepted[0] != cannoli::auth::ACCEPTED {
+            panic!("Cannoli: Rejected by the Cannoli server, check {}",
+                cannoli::auth::TOKEN_VAR);
+        }
+
+        // Receive control messages from the server in the background
+        let control = server.try_clone()
+            .expect("Cannoli: Failed to clone server connection");
+        std::thread::spawn(move || crate::control::receive(control));
+
+        // Epochs requested before we existed have nothing of ours to mark
         Self {
             active_buffer: None,
-            _server: server,
+            epoch:         crate::control::epoch(),
+            last_lift:     None,
+            pinned:        None,
+            server,
             pipe,
         }
     }
 }
 
+impl Drop for HookState {
+    fn drop(&mut self) {
+        // The control thread holds a clone of the connection, shut it down
+        // explicitly so the server sees the thread exit
+        let _ = self.server.shutdown(Shutdown::Both);
+    }
+}
+
 /// Global state about the QEMU process we're in. This can only hold values
 /// which are constant through execution of the target.
 ///
@@ -166,6 +260,27 @@ struct QemuInfo {
 /// Global state holding information about the QEMU being used
 static QEMU_INFO: OnceLock<QemuInfo> = OnceLock::new();
 
+/// Get the index of the vCPU QEMU runs the calling thread on, its
+/// `cpu_index`. The Cannoli patches export `cannoli_cpu_index()` from QEMU
+/// for it, `-1` if QEMU doesn't
+fn cpu_index() -> i32 {
+    static CPU_INDEX: OnceLock<Option<extern "C" fn() -> i32>> =
+        OnceLock::new();
+    let get = CPU_INDEX.get_or_init(|| unsafe {
+        let sym = libc::dlsym(libc::RTLD_DEFAULT,
+            c"cannoli_cpu_index".as_ptr());
+        if sym.is_null() {
+            eprintln!("Cannoli: QEMU doesn't export `cannoli_cpu_index`, \
+                the vCPUs of threads aren't known");
+            None
+        } else {
+            Some(std::mem::transmute::<*mut libc::c_void,
+                extern "C" fn() -> i32>(sym))
+        }
+    });
+    get.map_or(-1, |x| x())
+}
+
 thread_local! {
     /// The thread-local QEMU hook state
     ///
@@ -236,6 +351,409 @@ static REGISTER_OFFSET: AtomicUsize = AtomicUsize::new(0);
 /// Size of the register state for the target architecture
 static REGISTER_SIZE: AtomicUsize = AtomicUsize::new(0);
 
+/// Size of a general purpose register of the target architecture
+static REGISTER_WIDTH: AtomicUsize = AtomicUsize::new(0);
+
+/// Recognizes heap growth in the mappings of this process. QEMU holds its
+/// mmap lock while reporting mappings, so they're seen in the order they're
+/// made
+static HEAP: Mutex<Option<HeapClassifier>> = Mutex::new(None);
+
+/// Set once the first instruction was lifted, at which point the program
+/// loader is done mapping the binary
+static STARTED: AtomicBool = AtomicBool::new(false);
+
+/// Recognizes system call and branch instructions of the target, `None` if
+/// those of the target aren't known
+static SIGNATURES: OnceLock<Option<Signatures>> = OnceLock::new();
+
+/// Longest instruction of any target, instructions further apart than this
+/// aren't in the same translation block
+const MAX_INST_LEN: u64 = 15;
+
+/// PCs of the instructions after system call instructions, which are hooked
+/// to report the return values, with the PC of their system call
+/// instruction
+static SYSCALL_RETURNS: Mutex<BTreeMap<u64, u64>> =
+    Mutex::new(BTreeMap::new());
+
+/// Get the length of the instruction at `pc` if it is a system call
+/// instruction. The code is read like guest memory, so short reads at the
+/// end of a mapping are retried with the shortest system call instruction
+fn syscall_len(pc: u64) -> Option<u64> {
+    let sigs = signatures()?;
+    let code = crate::control::read_memory(pc, 4)
+        .or_else(|| crate::control::read_memory(pc, 2))?;
+    sigs.syscall_len(&code)
+}
+
+/// Get the signatures of the target's code
+fn signatures() -> Option<&'static Signatures> {
+    SIGNATURES.get_or_init(|| {
+        let qi = QEMU_INFO.get()?;
+        Signatures::new(qi.arch, qi.big_endian)
+    }).as_ref()
+}
+
+/// Get the address of the instruction after the branch at `pc`, if the
+/// branch can continue there
+fn fallthrough(pc: u64) -> Option<u64> {
+    let sigs = signatures()?;
+    let code = [16, 4, 2].into_iter()
+        .find_map(|len| crate::control::read_memory(pc, len))?;
+    Some(pc.wrapping_add(sigs.fallthrough_len(&code)?))
+}
+
+/// Get whether the branch at `pc` is a call or a return. On MIPS `pc` is
+/// the delay slot which ends the block, and the branch is before it
+fn call_kind(pc: u64) -> Option<CallKind> {
+    let sigs = signatures()?;
+    let pc = pc.wrapping_sub(4 * sigs.delay_slots() as u64);
+    let code = [16, 4, 2].into_iter()
+        .find_map(|len| crate::control::read_memory(pc, len))?;
+    sigs.call_kind(&code)
+}
+
+/// Get the comparison the instruction at `pc` makes, if it compares
+/// registers or a register with an immediate
+fn comparison(pc: u64) -> Option<Comparison> {
+    let sigs = signatures()?;
+    let code = [16, 4, 2].into_iter()
+        .find_map(|len| crate::control::read_memory(pc, len))?;
+    sigs.comparison(&code)
+}
+
+/// Announce the code of the instruction at `pc` to the client, as the
+/// longest window of [`cannoli::code::MAX_BYTES`] bytes or fewer which can
+/// be read, see [`cannoli::code`]
+fn announce_code(pc: u64) {
+    let max = cannoli::code::MAX_BYTES as u32;
+    let Some(code) = [max, 8, 4, 2, 1].into_iter()
+            .find_map(|len| crate::control::read_memory(pc, len)) else {
+        return;
+    };
+
+    let mut packet = vec![0x37];
+    packet.extend_from_slice(&pc.to_le_bytes());
+    packet.push(code.len() as u8);
+    packet.extend_from_slice(&code);
+    with_hook(|mut hook| {
+        hook.pipe.alloc_buffer(true).send(packet);
+    });
+}
+
+/// Options of QEMU user-mode which take a value, as in `linux-user/main.c`
+/// along with the one added for Cannoli. Options may be given with one dash
+/// or two
+const QEMU_VALUE_OPTS: &[&str] = &[
+    "g", "L", "s", "cpu", "E", "U", "0", "r", "R", "B", "d", "D", "dfilter",
+    "p", "seed", "trace", "plugin", "cannoli",
+];
+
+/// The image the guest runs, from QEMU's command line and environment
+struct GuestImage {
+    /// Path of the main binary
+    exe: String,
+
+    /// Arguments, starting with the one naming the main binary
+    argv: Vec<String>,
+
+    /// Environment as `NAME=value` entries
+    env: Vec<String>,
+}
+
+impl GuestImage {
+    /// Get the image of the guest. The guest's arguments end QEMU's own
+    /// command line, from the argument naming the main binary on. Once the
+    /// main binary is mapped that's the argument naming it, until then it's
+    /// the first one which isn't an option of QEMU or its value
+    fn get() -> Self {
+        let cmdline = std::fs::read("/proc/self/cmdline").unwrap_or_default();
+        let args = cmdline.strip_suffix(&[0]).unwrap_or(&[])
+            .split(|&x| x == 0).skip(1)
+            .map(|x| String::from_utf8_lossy(x).into_owned())
+            .collect::<Vec<_>>();
+
+        // Find the main binary, going through QEMU's options
+        let main = crate::control::main_module();
+        let canonical = main.as_deref().and_then(|x| {
+            std::fs::canonicalize(x).ok()
+        });
+        let mut env_opts = Vec::new();
+        let mut start = None;
+        let mut ii = 0;
+        while ii < args.len() {
+            let arg = &args[ii];
+            let matches = main.as_deref().is_some_and(|x| {
+                *arg == *x || (canonical.is_some() &&
+                    std::fs::canonicalize(arg).ok() == canonical)
+            });
+            let opt = arg.strip_prefix("--")
+                .or_else(|| arg.strip_prefix('-'))
+                .filter(|_| !matches);
+            match opt {
+                Some("") => {
+                    start = Some(ii + 1).filter(|&x| x < args.len());
+                    break;
+                }
+                Some(opt) if QEMU_VALUE_OPTS.contains(&opt) => {
+                    if let Some(value) = args.get(ii + 1)
+                            .filter(|_| opt == "E" || opt == "U") {
+                        env_opts.push((opt, value));
+                    }
+                    ii += 2;
+                }
+                Some(_) => ii += 1,
+                None => {
+                    start = Some(ii);
+                    break;
+                }
+            }
+        }
+        let argv = start.map_or(&[][..], |x| &args[x..]).to_vec();
+        let exe = match main {
+            Some(main) => main.to_string(),
+            None => argv.first().map_or(String::new(), |x| {
+                std::fs::canonicalize(x)
+                    .map_or(x.clone(), |x| x.to_string_lossy().into_owned())
+            }),
+        };
+
+        // The guest starts with QEMU's environment, as changed by `-E` and
+        // `-U`, which take comma separated lists
+        let environ = std::fs::read("/proc/self/environ").unwrap_or_default();
+        let mut env = environ.strip_suffix(&[0]).unwrap_or(&[])
+            .split(|&x| x == 0)
+            .map(|x| String::from_utf8_lossy(x).into_owned())
+            .collect::<Vec<_>>();
+        for (opt, value) in env_opts {
+            for entry in value.split(',') {
+                let name = entry.split('=').next();
+                env.retain(|x| x.split('=').next() != name);
+                if opt == "E" {
+                    env.push(entry.to_string());
+                }
+            }
+        }
+
+        Self { exe, argv, env }
+    }
+}
+
+/// Announce the image the process runs to the client, as the path of its
+/// main binary and its arguments, see [`cannoli::Cannoli::exec_image`]
+fn announce_image() {
+    let GuestImage { exe: path, argv, .. } = GuestImage::get();
+
+    // Arguments which don't fit in a chunk are left out
+    let mut budget = CHUNK_SIZE / 4;
+    let argv = argv.iter()
+        .take_while(|x| {
+            budget = budget.saturating_sub(x.len() + 4);
+            budget > 0
+        }).collect::<Vec<_>>();
+
+    let mut packet = vec![0x38];
+    packet.extend_from_slice(&(path.len() as u32).to_le_bytes());
+    packet.extend_from_slice(path.as_bytes());
+    packet.extend_from_slice(&(argv.len() as u32).to_le_bytes());
+    for arg in argv {
+        packet.extend_from_slice(&(arg.len() as u32).to_le_bytes());
+        packet.extend_from_slice(arg.as_bytes());
+    }
+    with_hook(|mut hook| {
+        hook.pipe.alloc_buffer(true).send(packet);
+    });
+}
+
+/// Get the PC of the system call instruction which returns to `pc`, `None`
+/// if `pc` doesn't follow one. `block_start` is set if `pc` starts a
+/// translation block
+fn syscall_return(pc: u64, block_start: bool) -> Option<u64> {
+    if let Some(&syscall) = SYSCALL_RETURNS.lock().unwrap().get(&pc) {
+        return Some(syscall);
+    }
+
+    // The instruction after a system call may be translated before the
+    // system call is, eg. when something branches to it, so it's looked up
+    // from the code before it too. System calls end their block, so only
+    // the starts of blocks can follow one. System call instructions are 2
+    // or 4 bytes long on every target
+    if !block_start {
+        return None;
+    }
+    let syscall = [2, 4].into_iter().map(|len| pc.wrapping_sub(len))
+        .find(|&x| syscall_len(x) == Some(pc.wrapping_sub(x)))?;
+    SYSCALL_RETURNS.lock().unwrap().insert(pc, syscall);
+    Some(syscall)
+}
+
+/// Get the system call site the instruction at `pc` is part of, as the PCs
+/// of the system call instruction and of the instruction after it, see
+/// [`cannoli::syscalls`]. `None` if `pc` isn't part of a site. A system
+/// call right after another one is part of both sites, and this gets the
+/// site it starts, [`SYSCALL_RETURNS`] has the other one. `block_start` is
+/// set if `pc` starts a translation block
+fn syscall_site(pc: u64, block_start: bool) -> Option<(u64, u64)> {
+    let syscall = syscall_return(pc, block_start);
+    if let Some(len) = syscall_len(pc) {
+        let next = pc.wrapping_add(len);
+        SYSCALL_RETURNS.lock().unwrap().insert(next, pc);
+        return Some((pc, next));
+    }
+    syscall.map(|x| (x, pc))
+}
+
+/// Upgrade `hook_type` of an instruction of a system call site to capture
+/// registers, which every execution of the site announces right before
+/// them
+fn syscall_hook(hook_type: HookType) -> HookType {
+    match hook_type {
+        HookType::Register | HookType::Branch => hook_type,
+        _ => HookType::Register,
+    }
+}
+
+/// Get the clock and random numbers of the system calls whose results are
+/// pinned, `None` if nothing is, see [`cannoli::pin`]
+fn pinner() -> Option<&'static Pinner> {
+    static PINNER: OnceLock<Option<Pinner>> = OnceLock::new();
+    PINNER.get_or_init(|| {
+        let pins = std::env::var(PIN_VAR).ok()?;
+        let Some(pins) = Pins::parse(&pins) else {
+            eprintln!("Cannoli: Invalid {PIN_VAR} `{pins}`, nothing is \
+                pinned");
+            return None;
+        };
+        (!pins.is_empty()).then(|| Pinner::new(pins))
+    }).as_ref()
+}
+
+/// Read the general purpose register `index` of QEMU's CPU state `env`
+unsafe fn read_reg(env: *const u8, index: usize) -> u64 {
+    let width = REGISTER_WIDTH.load(Ordering::Relaxed);
+    let reg = env.add(REGISTER_OFFSET.load(Ordering::Relaxed) + index * width);
+    match width {
+        4 => (reg as *const u32).read_unaligned() as u64,
+        _ => (reg as *const u64).read_unaligned(),
+    }
+}
+
+/// Write the general purpose register `index` of QEMU's CPU state `env`
+unsafe fn write_reg(env: *mut u8, index: usize, val: u64) {
+    let width = REGISTER_WIDTH.load(Ordering::Relaxed);
+    let reg = env.add(REGISTER_OFFSET.load(Ordering::Relaxed) + index * width);
+    match width {
+        4 => (reg as *mut u32).write_unaligned(val as u32),
+        _ => (reg as *mut u64).write_unaligned(val),
+    }
+}
+
+/// Remember the arguments of the system call at `pc` if its result is
+/// pinned. Called from the JIT by [`cannoli_pin_entry`] with QEMU's CPU
+/// state `env`, before the system call
+unsafe extern fn pin_entry(env: *mut u8, pc: u64) {
+    let pinned = QEMU_INFO.get().and_then(|qi| {
+        let abi  = SyscallAbi::new(qi.arch)?;
+        let name = signatures()?.syscall_name(read_reg(env, abi.nr))?;
+        let call = pinner()?.call(name)?;
+
+        let mut args = [0u64; 6];
+        for (arg, &index) in args.iter_mut().zip(abi.args) {
+            *arg = read_reg(env, index);
+        }
+        Some((pc, call, args))
+    });
+    with_hook(|mut hook| hook.pinned = pinned);
+}
+
+/// Overwrite the result of the pinned system call which returned to `pc`.
+/// Called from the JIT by [`cannoli_pin_return`] with QEMU's CPU state
+/// `env`, after the system call
+unsafe extern fn pin_return(env: *mut u8, pc: u64) {
+    let mut pinned = None;
+    with_hook(|mut hook| pinned = hook.pinned.take());
+    let Some((syscall, call, args)) = pinned else { return };
+    if SYSCALL_RETURNS.lock().unwrap().get(&pc) != Some(&syscall) {
+        return;
+    }
+
+    let (Some(qi), Some(pinner)) = (QEMU_INFO.get(), pinner()) else {
+        return;
+    };
+    let Some(abi) = SyscallAbi::new(qi.arch) else { return };
+
+    // MIPS flags failures in `a3` rather than in the return value
+    if qi.arch == Architecture::Mips && read_reg(env, 7) != 0 {
+        return;
+    }
+
+    let width = REGISTER_WIDTH.load(Ordering::Relaxed);
+    let Some(pinned) = pinner.pin(call, &args, read_reg(env, abi.ret),
+        width, qi.big_endian) else { return };
+    for (addr, data) in &pinned.writes {
+        crate::control::write_memory(*addr, data);
+    }
+    write_reg(env, abi.ret, pinned.ret);
+}
+
+/// Create a callout the JIT calls with the PC of a pinned system call site
+/// in `r14`, which calls `$handler` with QEMU's CPU state and the PC. Like
+/// the `$flush` of [`create_bitness`] it preserves every register
+macro_rules! create_pin_callout {
+    ($name:ident, $handler:ident) => {
+
+/// Called _directly_ from the JIT by `cannoli_pinhook` without preserving
+/// any registers
+#[naked]
+unsafe extern fn $name() {
+    std::arch::asm!(r#"
+        // Save all registers that aren't preserved by our callees
+        push rax
+        push rdi
+        push rsi
+        push rdx
+        push rcx
+        push r8
+        push r9
+        push r10
+        push r11
+
+        // QEMU's CPU state is in rbp, the PC in r14
+        mov  rdi, rbp
+        mov  rsi, r14
+
+        // Align the stack
+        push rbp
+        mov  rbp, rsp
+        and  rsp, ~0xf
+
+        call {handler}
+
+        // Restore the stack
+        mov rsp, rbp
+        pop rbp
+
+        // Restore all registers that aren't preserved by our callees
+        pop r11
+        pop r10
+        pop r9
+        pop r8
+        pop rcx
+        pop rdx
+        pop rsi
+        pop rdi
+        pop rax
+        ret
+    "#, handler = sym $handler, options(noreturn));
+}
+
+    };
+}
+
+create_pin_callout!(cannoli_pin_entry, pin_entry);
+create_pin_callout!(cannoli_pin_return, pin_return);
+
 /// Gross macro we use to generate both the 32-bit and 64-bit versions of code
 /// for handling QEMU targets of different bitnesses. Unfortunately we kind of
 /// have to do this as we don't want the user to have to build different
@@ -260,10 +778,12 @@ static REGISTER_SIZE: AtomicUsize = AtomicUsize::new(0);
 ///                a "fake" JIT exit and entry to flush the IPC data and get
 ///                a new buffer.
 /// - `$memop`   - Identifier for the memory access hook
+/// - `$helper`  - Identifier for the hook of memory accesses of QEMU helpers
 macro_rules! create_bitness {
     (
         $tusize:ty, $cannoli:tt, $init:ident, $lift:ident, $entry:ident,
-        $exit:ident, $flush:ident, $memop:ident, $mmap:ident, $munmap:ident
+        $exit:ident, $flush:ident, $memop:ident, $helper:ident, $mmap:ident,
+        $munmap:ident, $signal:ident
     ) => {
 
 /// Called by QEMU to initialize this library, we also return version
@@ -280,11 +800,14 @@ extern fn $init(arch: *const i8, big_endian: i32, gpr_offset: usize,
         jit_exit:         Some($exit),
         mmap:             Some($mmap),
         munmap:           Some($munmap),
+        helper_memop:     Some($helper),
+        guest_signal:     Some($signal),
     };
 
     // Save the register offset and size in the globals.
     REGISTER_OFFSET.store(gpr_offset, Ordering::Relaxed);
     REGISTER_SIZE.store(num_gprs * gpr_width, Ordering::Relaxed);
+    REGISTER_WIDTH.store(gpr_width, Ordering::Relaxed);
 
     // Convert architecture string to enum
     let arch = unsafe { Architecture::from_cstr(arch) };
@@ -312,13 +835,197 @@ extern fn $init(arch: *const i8, big_endian: i32, gpr_offset: usize,
 #[no_mangle]
 unsafe extern fn $lift(pc: $tusize, bb_end: i32,
         buf: *mut u8, buf_size: usize) -> usize {
-    // Get the requested hook type for this instruction
-    let hook_type = hook_inst(pc as u64, bb_end != 0);
+    // The program break starts where the loader left it, and the image it
+    // loaded is announced
+    if !STARTED.load(Ordering::Relaxed) &&
+            !STARTED.swap(true, Ordering::Relaxed) {
+        if let Some(heap) = HEAP.lock().unwrap().as_mut() {
+            heap.start();
+        }
+        announce_image();
+    }
+
+    // Get the requested hook type for this instruction. Exec hooks send the
+    // registers along with `exec_regs`, and system call sites always capture
+    // all of them when they are reported
+    let filters = crate::control::filters();
+    let mut hook_type = hook_inst(pc as u64, bb_end != 0);
+    let exec_regs = filters.exec_regs &&
+        matches!(hook_type, HookType::Once | HookType::Always);
+    if exec_regs {
+        hook_type = HookType::Register;
+    }
+    let mut all_regs = !exec_regs;
+
+    // Find the starts of translation blocks for system call sites,
+    // edge hooks and sampling
+    let mut block_start = false;
+    with_hook(|mut hook| {
+        block_start = hook.last_lift.is_none_or(|(last, bb_end)| {
+            bb_end || pc as u64 <= last || pc as u64 - last > MAX_INST_LEN
+        });
+        hook.last_lift = Some((pc as u64, bb_end != 0));
+    });
+
+    let mut site = None;
+    let pins = pinner().is_some();
+    let syscall_site = if filters.syscalls || pins {
+        syscall_site(pc as u64, block_start)
+    } else {
+        None
+    };
+    if filters.syscalls && syscall_site.is_some() {
+        hook_type = syscall_hook(hook_type);
+        all_regs  = true;
+        site      = syscall_site;
+    }
+
+    // System calls with pinned results call into the jitter before and
+    // after them, before anything else is hooked so nothing sees what the
+    // host returned, see `cannoli::pin`
+    let mut pin_len = 0;
+    if let Some((syscall, _)) = syscall_site.filter(|_| pins) {
+        let returns = SYSCALL_RETURNS.lock().unwrap()
+            .contains_key(&(pc as u64));
+        let callouts = [
+            (returns, cannoli_pin_return as usize),
+            (syscall == pc as u64, cannoli_pin_entry as usize),
+        ];
+        for (_, callout) in callouts.into_iter().filter(|x| x.0) {
+            let shellcode = core::slice::from_raw_parts(
+                core::ptr::addr_of!(cannoli_pinhook) as *const u8,
+                core::ptr::addr_of!(cannoli_pinhook_end) as usize -
+                core::ptr::addr_of!(cannoli_pinhook) as usize);
+            assert!(pin_len + shellcode.len() <= buf_size,
+                "Cannoli: Pin shellcode too large for QEMU buffer");
+            buf.add(pin_len).copy_from_nonoverlapping(shellcode.as_ptr(),
+                shellcode.len());
+            let tmp = std::slice::from_raw_parts_mut(buf.add(pin_len),
+                shellcode.len());
+
+            patch(tmp, REPLACE_WITH_PC.to_le_bytes(),
+                (pc as u64).to_le_bytes());
+            patch(tmp, REPLACE_WITH_PIN.to_le_bytes(), callout.to_le_bytes());
+            pin_len += tmp.len();
+        }
+    }
+    let (buf, buf_size) = (buf.add(pin_len), buf_size - pin_len);
+
+    // Comparisons log their operands with a hook of their own, after
+    // whatever else hooks them, see `cannoli::control::Filters::cmp`
+    let cmp = if crate::control::hook_cmp(pc as u64) {
+        comparison(pc as u64)
+    } else {
+        None
+    };
+    let append_cmp = move |size: usize| -> usize {
+        let Some(cmp) = cmp else { return size };
+        let width  = REGISTER_WIDTH.load(Ordering::Relaxed);
+        let count  = REGISTER_SIZE.load(Ordering::Relaxed) / width.max(1);
+        let offset = |reg: usize| {
+            (REGISTER_OFFSET.load(Ordering::Relaxed) + reg * width) as u32
+        };
+        let rhs_reg = match cmp.rhs {
+            CmpOperand::Reg(reg) => Some(reg),
+            CmpOperand::Imm(_) => None,
+        };
+        if cmp.lhs >= count || rhs_reg.is_some_and(|x| x >= count) ||
+                cmp.sz as usize > width {
+            return size;
+        }
+
+        let (start, end) = match (<$tusize>::BITS, rhs_reg.is_some()) {
+            (32, true) => (
+                core::ptr::addr_of!(cannoli_cmphook32)     as usize,
+                core::ptr::addr_of!(cannoli_cmphook32_end) as usize,
+            ),
+            (64, true) => (
+                core::ptr::addr_of!(cannoli_cmphook64)     as usize,
+                core::ptr::addr_of!(cannoli_cmphook64_end) as usize,
+            ),
+            (32, false) => (
+                core::ptr::addr_of!(cannoli_cmphook32_imm)     as usize,
+                core::ptr::addr_of!(cannoli_cmphook32_imm_end) as usize,
+            ),
+            (64, false) => (
+                core::ptr::addr_of!(cannoli_cmphook64_imm)     as usize,
+                core::ptr::addr_of!(cannoli_cmphook64_imm_end) as usize,
+            ),
+            (_, _) => panic!("Invalid target bitness"),
+        };
+        let shellcode = core::slice::from_raw_parts(
+            start as *const u8, end - start);
+        assert!(size + shellcode.len() <= buf_size,
+            "Cannoli: Comparison shellcode too large for QEMU buffer");
+        buf.add(size).copy_from_nonoverlapping(shellcode.as_ptr(),
+            shellcode.len());
+        let tmp = std::slice::from_raw_parts_mut(buf.add(size),
+            shellcode.len());
+
+        patch(tmp, (REPLACE_WITH_PC as $tusize).to_le_bytes(),
+            pc.to_le_bytes());
+        patch(tmp, REPLACE_WITH_FLUSH.to_le_bytes(),
+            ($flush as usize).to_le_bytes());
+        patch(tmp, REPLACE_WITH_CMP_LHS.to_le_bytes(),
+            offset(cmp.lhs).to_le_bytes());
+        patch(tmp, REPLACE_WITH_CMP_SHIFT.to_le_bytes(),
+            (64 - cmp.sz as u32 * 8).to_le_bytes());
+        match cmp.rhs {
+            CmpOperand::Reg(reg) => patch(tmp,
+                REPLACE_WITH_CMP_RHS.to_le_bytes(), offset(reg).to_le_bytes()),
+            CmpOperand::Imm(imm) => patch(tmp,
+                REPLACE_WITH_CMP_IMM.to_le_bytes(), imm.to_le_bytes()),
+        }
+
+        // The size is the immediate of the `mov byte ptr [r12 + disp8], 0`
+        // which ends the event
+        let disp = 1 + 3 * (<$tusize>::BITS / 8) as u8;
+        patch(tmp, [0x41, 0xc6, 0x44, 0x24, disp, 0],
+            [0x41, 0xc6, 0x44, 0x24, disp, cmp.sz]);
+        size + tmp.len()
+    };
+    let append_cmp = move |size: usize| pin_len + append_cmp(size);
+
+    // Sampled exec hooks only hook the start of every block, see
+    // `cannoli::control::Sample`
+    let sample = filters.sample
+        .filter(|_| matches!(hook_type, HookType::Always));
+    if sample.is_some() && !block_start {
+        hook_type = HookType::Never;
+    }
+
+    // Exec hooks send the code along with `exec_bytes`, which is announced
+    // before it can execute, see `cannoli::code`
+    let exec_bytes = filters.exec_bytes &&
+        matches!(hook_type, HookType::Once | HookType::Always);
+    if exec_bytes {
+        announce_code(pc as u64);
+    }
+
+    // Calls and returns, the only sources of function hooks
+    let edges = matches!(hook_type, HookType::Edge | HookType::Function);
+    let call = if edges && bb_end != 0 { call_kind(pc as u64) } else { None };
+    let source = match hook_type {
+        HookType::Function => call.is_some(),
+        _ => bb_end != 0,
+    };
 
     // Get the start and end address of the shellcode
     //
     // Check the size of `$tusize` to determine the correct shellcode to use
     let (start, end) = match (<$tusize>::BITS, hook_type) {
+        (32, HookType::Always) if sample.is_some() => {
+            (
+                core::ptr::addr_of!(cannoli_insthook32_sample)     as usize,
+                core::ptr::addr_of!(cannoli_insthook32_sample_end) as usize,
+            )
+        }
+        (64, HookType::Always) if sample.is_some() => {
+            (
+                core::ptr::addr_of!(cannoli_insthook64_sample)     as usize,
+                core::ptr::addr_of!(cannoli_insthook64_sample_end) as usize,
+            )
+        }
         (32, HookType::Always) => {
             (
                 core::ptr::addr_of!(cannoli_insthook32)     as usize,
@@ -381,9 +1088,25 @@ unsafe extern fn $lift(pc: $tusize, bb_end: i32,
                 )
             }
         }
+        (_, HookType::Edge | HookType::Function) if !source && !block_start => {
+            // Neither a source nor a target, nothing to see here
+            return append_cmp(0);
+        }
+        (32, HookType::Edge | HookType::Function) => {
+            (
+                core::ptr::addr_of!(cannoli_insthook32)     as usize,
+                core::ptr::addr_of!(cannoli_insthook32_end) as usize,
+            )
+        }
+        (64, HookType::Edge | HookType::Function) => {
+            (
+                core::ptr::addr_of!(cannoli_insthook64)     as usize,
+                core::ptr::addr_of!(cannoli_insthook64_end) as usize,
+            )
+        }
         (_, HookType::Never) => {
             // Don't hook at all
-            return 0;
+            return append_cmp(0);
         }
         (_, _) => {
             // At this point we've covered all the types we support
@@ -391,6 +1114,37 @@ unsafe extern fn $lift(pc: $tusize, bb_end: i32,
         }
     };
 
+    // System call sites are announced on every execution, right before
+    // their registers and in the same payload, so the client knows the site
+    // by the time it parses the registers, see `cannoli::syscalls`
+    let site_len = match site {
+        Some((syscall, next)) => {
+            let shellcode = core::slice::from_raw_parts(
+                core::ptr::addr_of!(cannoli_sitehook) as *const u8,
+                core::ptr::addr_of!(cannoli_sitehook_end) as usize -
+                core::ptr::addr_of!(cannoli_sitehook) as usize);
+            assert!(shellcode.len() <= buf_size,
+                "Cannoli: Site shellcode too large for QEMU buffer");
+            buf.copy_from_nonoverlapping(shellcode.as_ptr(), shellcode.len());
+            let tmp = std::slice::from_raw_parts_mut(buf, shellcode.len());
+
+            patch(tmp, REPLACE_WITH_PC.to_le_bytes(), syscall.to_le_bytes());
+            patch(tmp, REPLACE_WITH_SITE_NEXT.to_le_bytes(),
+                next.to_le_bytes());
+            patch(tmp, REPLACE_WITH_FLUSH.to_le_bytes(),
+                ($flush as usize).to_le_bytes());
+
+            // Leave room for the largest register event after it, a branch
+            // event of every register
+            let regs = 1 + 4 + 8 + 1 + REGISTER_SIZE.load(Ordering::Relaxed);
+            patch(tmp, REPLACE_WITH_SITE_RESERVE.to_le_bytes(),
+                (regs as u32).to_le_bytes());
+            tmp.len()
+        }
+        None => 0,
+    };
+    let (buf, buf_size) = (buf.add(site_len), buf_size - site_len);
+
     // Get a slice to the shellcode
     let shellcode = core::slice::from_raw_parts(
         start as *const u8, end - start);
@@ -406,9 +1160,74 @@ unsafe extern fn $lift(pc: $tusize, bb_end: i32,
     // Create safe, mutable access to the buffer
     let tmp = std::slice::from_raw_parts_mut(buf as *mut u8, shellcode.len());
 
+    // Exec hooks with registers are register hooks with their own opcode,
+    // patched in the `mov byte ptr [r12], <opcode>` which starts the event
+    if exec_regs {
+        let opcode = if <$tusize>::BITS == 32 { 0x01 } else { 0x81 };
+        patch(tmp, [0x41, 0xc6, 0x04, 0x24, opcode],
+            [0x41, 0xc6, 0x04, 0x24, opcode | 0x02]);
+    }
+
+    // So are exec hooks with code, which the client pairs with the code
+    // announced above
+    if exec_bytes {
+        let opcode = if <$tusize>::BITS == 32 { 0x00 } else { 0x80 };
+        patch(tmp, [0x41, 0xc6, 0x04, 0x24, opcode],
+            [0x41, 0xc6, 0x04, 0x24, opcode | 0x08]);
+    }
+
+    // Exec hooks of dynamic code are tagged with their own opcodes, with or
+    // without code, see `cannoli::address_space`
+    if matches!(hook_type, HookType::Once | HookType::Always) &&
+            crate::control::is_dynamic_code(pc as u64) {
+        let (from, to) = if exec_bytes { (0x08, 0x0a) } else { (0x00, 0x09) };
+        let high = if <$tusize>::BITS == 32 { 0x00 } else { 0x80 };
+        patch(tmp, [0x41, 0xc6, 0x04, 0x24, from | high],
+            [0x41, 0xc6, 0x04, 0x24, to | high]);
+    }
+
+    // Edge hooks are exec hooks with their own opcodes, telling if the
+    // instruction is a source (bit 0) or a target (bit 1) of edges. Sources
+    // announce where they fall through to
+    if edges {
+        let opcode = if <$tusize>::BITS == 32 { 0x00 } else { 0x80 };
+        let kind = 0x04 | source as u8 | (block_start as u8) << 1;
+        patch(tmp, [0x41, 0xc6, 0x04, 0x24, opcode],
+            [0x41, 0xc6, 0x04, 0x24, opcode | kind]);
+
+        let next = if source { fallthrough(pc as u64) } else { None };
+        if let Some(next) = next {
+            let mut packet = vec![0x36];
+            packet.extend_from_slice(&(pc as u64).to_le_bytes());
+            packet.extend_from_slice(&next.to_le_bytes());
+            with_hook(|mut hook| {
+                hook.pipe.alloc_buffer(true).send(packet);
+            });
+        }
+
+        // Calls and returns are announced too, so their edges are reported
+        // as such, see `cannoli::calls`
+        if let Some(kind) = call {
+            let mut packet = vec![0x39];
+            packet.extend_from_slice(&(pc as u64).to_le_bytes());
+            packet.push((kind == CallKind::Return) as u8);
+            with_hook(|mut hook| {
+                hook.pipe.alloc_buffer(true).send(packet);
+            });
+        }
+    }
+
     // Patch the PC placeholder with the actual PC
     patch(tmp, (REPLACE_WITH_PC as $tusize).to_le_bytes(), pc.to_le_bytes());
 
+    // Sampled hooks count down on the counter of the process
+    if let Some(sample) = sample {
+        patch(tmp, REPLACE_WITH_SAMPLE_COUNTER.to_le_bytes(),
+            (crate::control::sample_counter() as usize).to_le_bytes());
+        patch(tmp, REPLACE_WITH_SAMPLE_PERIOD.to_le_bytes(),
+            sample.period().to_le_bytes());
+    }
+
     // So, we can't use an address in our shellcode since we don't know that
     // information at compile time. Thus, we replace the `REPLACE_WITH_FLUSH`
     // with the run-time address where that has been loaded
@@ -420,12 +1239,70 @@ unsafe extern fn $lift(pc: $tusize, bb_end: i32,
         // Patch register hook size and offset
         patch(tmp, REPLACE_WITH_REGHOOK_OFFSET.to_le_bytes(),
             (REGISTER_OFFSET.load(Ordering::Relaxed) as u32).to_le_bytes());
+        let mut size = REGISTER_SIZE.load(Ordering::Relaxed);
+        if let (false, Some(count)) = (all_regs, filters.exec_reg_count) {
+            size = size.min(count * REGISTER_WIDTH.load(Ordering::Relaxed));
+        }
         patch(tmp, REPLACE_WITH_REGHOOK_SIZE.to_le_bytes(),
-            (REGISTER_SIZE.load(Ordering::Relaxed) as u32).to_le_bytes());
+            (size as u32).to_le_bytes());
+    } else {
+        return append_cmp(site_len + tmp.len());
+    }
+
+    // Register files are captured right after the general purpose registers
+    let (start, end) = if <$tusize>::BITS == 32 {
+        (
+            core::ptr::addr_of!(cannoli_regfilehook32)     as usize,
+            core::ptr::addr_of!(cannoli_regfilehook32_end) as usize,
+        )
+    } else {
+        (
+            core::ptr::addr_of!(cannoli_regfilehook64)     as usize,
+            core::ptr::addr_of!(cannoli_regfilehook64_end) as usize,
+        )
+    };
+    let regfile = core::slice::from_raw_parts(start as *const u8, end - start);
+
+    let mut size = tmp.len();
+    for (index, file) in filters.reg_files.iter().enumerate() {
+        // Invalid register files are skipped rather than corrupting QEMU's
+        // state
+        if file.validate().is_err() {
+            continue;
+        }
+
+        // Some register files are only wanted for branches
+        if file.branches_only &&
+                !(matches!(hook_type, HookType::Branch) && bb_end != 0) {
+            continue;
+        }
+
+        assert!(size + regfile.len() <= buf_size,
+            "Cannoli: Register file shellcode too large for QEMU buffer");
+        buf.add(size).copy_from_nonoverlapping(regfile.as_ptr(), regfile.len());
+        let tmp = std::slice::from_raw_parts_mut(buf.add(size), regfile.len());
+        size += regfile.len();
+
+        patch(tmp, (REPLACE_WITH_PC as $tusize).to_le_bytes(),
+            pc.to_le_bytes());
+        patch(tmp, REPLACE_WITH_FLUSH.to_le_bytes(),
+            ($flush as usize).to_le_bytes());
+        patch(tmp, REPLACE_WITH_REGFILE_OFFSET.to_le_bytes(),
+            file.offset.to_le_bytes());
+        patch(tmp, REPLACE_WITH_REGFILE_SIZE.to_le_bytes(),
+            file.size().to_le_bytes());
+        patch(tmp, REPLACE_WITH_REGFILE_INDEX.to_le_bytes(),
+            (index as u32).to_le_bytes());
+        patch(tmp, REPLACE_WITH_REGFILE_COUNT.to_le_bytes(),
+            file.count.to_le_bytes());
+        patch(tmp, REPLACE_WITH_REGFILE_WIDTH.to_le_bytes(),
+            file.width.to_le_bytes());
+        patch(tmp, REPLACE_WITH_REGFILE_SKIP.to_le_bytes(),
+            (file.stride - file.width).to_le_bytes());
     }
 
     // Return the size of the shellcode we want to inject
-    tmp.len()
+    append_cmp(site_len + size)
 }
 
 /// Invoked from QEMU when entering the JIT. This provides an opportunity for
@@ -493,6 +1370,16 @@ unsafe extern fn $exit(r12: usize, _r13: usize, _r14: usize) {
         let mut ab = ManuallyDrop::into_inner(ab);
         let to_send = r12 - ab.get_raw() as usize;
         ab.send_raw(to_send);
+
+        // Everything up to here is flushed, mark a newly requested epoch
+        let epoch = crate::control::epoch();
+        if epoch > hook.epoch {
+            hook.epoch = epoch;
+
+            let mut tmp = vec![0x34];
+            tmp.extend_from_slice(&epoch.to_le_bytes());
+            hook.pipe.alloc_buffer(true).send(tmp);
+        }
     });
 }
 
@@ -569,9 +1456,18 @@ unsafe extern fn $memop(pc: $tusize, is_write: i32, data_reg: usize,
     // `access_type`:
     //   0 - read
     //   1 - write
-    let (start, end) = crate::cannoli_memops::MEMHOOK_TABLE
-        [size_of::<$tusize>() / 4 - 1][memop as usize]
-        [is_write as usize][data_reg][addr_reg];
+    //
+    // When the values of this kind of access aren't wanted, use the smaller
+    // address-only hooks instead, which have no `data` index
+    let (start, end) = if crate::control::mem_values(is_write != 0) {
+        crate::cannoli_memops::MEMHOOK_TABLE
+            [size_of::<$tusize>() / 4 - 1][memop as usize]
+            [is_write as usize][data_reg][addr_reg]
+    } else {
+        crate::cannoli_memops::ADDRHOOK_TABLE
+            [size_of::<$tusize>() / 4 - 1][memop as usize]
+            [is_write as usize][addr_reg]
+    };
 
     // Convert the addresses to `usize`s
     let (start, end) =
@@ -604,6 +1500,87 @@ unsafe extern fn $memop(pc: $tusize, is_write: i32, data_reg: usize,
     tmp.len()
 }
 
+/// Invoked after a QEMU helper accessed guest memory for the JIT, which
+/// [`$memop`] never sees. The access is traced like the JIT's, behind a
+/// `0x3a` marker flagging it as a helper's, into the buffer of the JIT which
+/// called the helper
+///
+/// - `state`    - The `r12`, `r13` and `r14` of the JIT, `r14` holding the
+///                PC. The JIT reloads `r12` and `r13` from here
+/// - `is_write` - `0` if this is a read, `1` if this is a write
+/// - `addr`     - The emulated guest's address that was accessed
+/// - `memop`    - One of the QEMU memops from `MemOp` (eg. `MO_8`)
+///
+/// The value is read back from guest memory after the access, so both halves
+/// of an atomic read-modify-write report the value written. 16-byte
+/// accesses are traced as two 8-byte ones
+unsafe extern fn $helper(state: *mut u64, is_write: i32, addr: $tusize,
+        memop: i32) {
+    let write = is_write != 0;
+    let pc    = state.add(2).read() as $tusize;
+    let size  = 1usize << (memop & 7);
+    let width = size.min(8);
+    if !hook_mem(pc as u64, write, width) {
+        return;
+    }
+
+    // Build the events like the memory hooks of the JIT write them
+    let values = crate::control::mem_values(write);
+    let big_endian = QEMU_INFO.get().is_some_and(|x| x.big_endian);
+    let mut packet = Vec::new();
+    for offset in (0..size).step_by(width) {
+        let addr = addr.wrapping_add(offset as $tusize);
+        let mut op = width as u8 | if write { 0x20 } else { 0x10 };
+        if !values {
+            op |= 0x40;
+        }
+        if size_of::<$tusize>() == 8 {
+            op |= 0x80;
+        }
+        packet.extend_from_slice(&[0x3a, op]);
+        packet.extend_from_slice(&addr.to_le_bytes());
+        if values {
+            let Some(mut val) = crate::control::read_memory(addr as u64,
+                width as u32) else { return };
+            if big_endian {
+                val.reverse();
+            }
+            packet.extend_from_slice(&val);
+        }
+        packet.extend_from_slice(&pc.to_le_bytes());
+    }
+
+    // QEMU also uses its accessors outside of the JIT, where the state is
+    // poisoned or left over from an earlier JIT run. Only trace into the
+    // active buffer
+    let (mut cursor, mut end) = (state.read(), state.add(1).read());
+    let mut active = false;
+    with_hook(|mut hook| {
+        if let Some(ab) = hook.active_buffer.as_mut() {
+            let start = ab.get_raw() as u64;
+            active = end == start + CHUNK_SIZE as u64 &&
+                (start..=end).contains(&cursor);
+        }
+    });
+    if !active {
+        return;
+    }
+
+    // Flush the buffer if the events don't fit, like `$flush` does for the
+    // JIT
+    if cursor + packet.len() as u64 > end {
+        $exit(cursor as usize, end as usize, 0);
+        let mut regs = [0usize; 3];
+        $entry(regs.as_mut_ptr());
+        (cursor, end) = (regs[0] as u64, regs[1] as u64);
+        state.add(1).write(end);
+    }
+
+    (cursor as *mut u8).copy_from_nonoverlapping(
+        packet.as_ptr(), packet.len());
+    state.write(cursor + packet.len() as u64);
+}
+
 /// Called _directly_ from the JIT without preserving any registers. We have
 /// to preserve all registers in the JIT, this is not a standard extern FFI!
 ///
@@ -693,6 +1670,11 @@ unsafe extern fn $mmap(start: $tusize, len: $tusize,
             CStr::from_ptr(path).to_bytes()
         };
 
+        // Module filters may cover the new mapping
+        crate::control::mapped(start as u64, len as u64, anon != 0,
+            read != 0, write != 0, exec != 0,
+            &String::from_utf8_lossy(path), offset as u64);
+
         // Temporary vector for building packet
         let mut tmp = Vec::new();
 
@@ -710,6 +1692,21 @@ unsafe extern fn $mmap(start: $tusize, len: $tusize,
         tmp.extend_from_slice(&offset.to_le_bytes());
         tmp.extend_from_slice(path);
 
+        // Follow the mapping with the heap growth it is, if any
+        let heap = HEAP.lock().unwrap()
+            .get_or_insert_with(|| HeapClassifier::new(<$tusize>::BITS as u8))
+            .mmap(start as u64, len as u64, anon != 0, read != 0, write != 0,
+                exec != 0, path);
+        let heap = heap.map(|x| match x {
+            HeapEvent::Brk   { old, new }  => (0x32, old, new),
+            HeapEvent::Arena { base, len } => (0x33, base, len),
+        });
+        if let Some((op, a, b)) = heap {
+            tmp.push(if <$tusize>::BITS == 64 { op | 0x80 } else { op });
+            tmp.extend_from_slice(&(a as $tusize).to_le_bytes());
+            tmp.extend_from_slice(&(b as $tusize).to_le_bytes());
+        }
+
         // Send the payload
         buffer.send(tmp);
     });
@@ -723,6 +1720,9 @@ unsafe extern fn $munmap(start: $tusize, len: $tusize) {
         // Shouldn't have an active buffer
         assert!(hook.active_buffer.is_none(), "munmap from inside the JIT?");
 
+        // Module filters may have covered the mapping
+        crate::control::unmapped(start as u64, len as u64);
+
         // Allocate a new blocking buffer in our pipe
         let buffer = hook.pipe.alloc_buffer(true);
 
@@ -741,6 +1741,33 @@ unsafe extern fn $munmap(start: $tusize, len: $tusize) {
     });
 }
 
+/// Called when QEMU delivers the signal `sig` to the guest, which was at
+/// `from` and continues in the handler at `to`
+#[no_mangle]
+unsafe extern fn $signal(sig: i32, from: $tusize, to: $tusize) {
+    // Nothing is hooked until the start trigger fires
+    if !crate::control::hook_signal() {
+        return;
+    }
+
+    // Make sure the hook state is thread-local
+    with_hook(|mut hook| {
+        // Signals are delivered outside of the JIT
+        assert!(hook.active_buffer.is_none(), "signal from inside the JIT?");
+
+        // Temporary vector for building packet
+        let mut tmp = vec![0x3b];
+
+        // Parameters
+        tmp.extend_from_slice(&sig.to_le_bytes());
+        tmp.extend_from_slice(&(from as u64).to_le_bytes());
+        tmp.extend_from_slice(&(to as u64).to_le_bytes());
+
+        // Send the payload
+        hook.pipe.alloc_buffer(true).send(tmp);
+    });
+}
+
 }} // macro_rules!
 
 // ============================================================================
@@ -755,6 +1782,10 @@ extern {
     static cannoli_insthook32_once_end: u8;
     static cannoli_insthook64_once:     u8;
     static cannoli_insthook64_once_end: u8;
+    static cannoli_insthook32_sample:     u8;
+    static cannoli_insthook32_sample_end: u8;
+    static cannoli_insthook64_sample:     u8;
+    static cannoli_insthook64_sample_end: u8;
     static cannoli_reghook32:           u8;
     static cannoli_reghook32_end:       u8;
     static cannoli_reghook64:           u8;
@@ -767,6 +1798,22 @@ extern {
     static cannoli_branchhook164_end:   u8;
     static cannoli_branchhook064:       u8;
     static cannoli_branchhook064_end:   u8;
+    static cannoli_regfilehook32:       u8;
+    static cannoli_regfilehook32_end:   u8;
+    static cannoli_regfilehook64:       u8;
+    static cannoli_regfilehook64_end:   u8;
+    static cannoli_cmphook32:           u8;
+    static cannoli_cmphook32_end:       u8;
+    static cannoli_cmphook64:           u8;
+    static cannoli_cmphook64_end:       u8;
+    static cannoli_cmphook32_imm:       u8;
+    static cannoli_cmphook32_imm_end:   u8;
+    static cannoli_cmphook64_imm:       u8;
+    static cannoli_cmphook64_imm_end:   u8;
+    static cannoli_sitehook:            u8;
+    static cannoli_sitehook_end:        u8;
+    static cannoli_pinhook:             u8;
+    static cannoli_pinhook_end:         u8;
 }
 
 /// Magic value to replace with the address of the respective `flush_buffer`
@@ -776,12 +1823,65 @@ const REPLACE_WITH_FLUSH: usize = 0xa03e2cd1b94c78fd;
 /// Magic value to replace with the current instructions PC
 const REPLACE_WITH_PC: usize = 0xcc5fe07bf3cfe384;
 
+/// Magic value to replace with the address of the sample counter
+const REPLACE_WITH_SAMPLE_COUNTER: usize = 0xd2a4e5f06b19c387;
+
+/// Magic value to replace with the number of blocks between samples, kept
+/// positive as it's a sign-extended immediate
+const REPLACE_WITH_SAMPLE_PERIOD: u32 = 0x2e71c94b;
+
 /// Magic value to replace with the register byte offset off of rbp
 const REPLACE_WITH_REGHOOK_OFFSET: u32 = 0x3fcc88a3;
 
 /// Magic value to replace with the register state size
 const REPLACE_WITH_REGHOOK_SIZE: u32 = 0x652a1e21;
 
+/// Magic value to replace with the register file byte offset off of rbp
+const REPLACE_WITH_REGFILE_OFFSET: u32 = 0x1e6a93f5;
+
+/// Magic value to replace with the number of bytes captured of a register
+/// file
+const REPLACE_WITH_REGFILE_SIZE: u32 = 0x2b7d14c9;
+
+/// Magic value to replace with the index of the register file in the filters
+const REPLACE_WITH_REGFILE_INDEX: u32 = 0x5c0d27b3;
+
+/// Magic value to replace with the number of registers in a register file
+const REPLACE_WITH_REGFILE_COUNT: u32 = 0x43f1a86d;
+
+/// Magic value to replace with the bytes captured of each register
+const REPLACE_WITH_REGFILE_WIDTH: u32 = 0x37b94e12;
+
+/// Magic value to replace with the bytes skipped between registers
+const REPLACE_WITH_REGFILE_SKIP: u32 = 0x0a5e63d7;
+
+/// Magic value to replace with the byte offset off of rbp of the register
+/// holding the left-hand operand of a comparison
+const REPLACE_WITH_CMP_LHS: u32 = 0x1b3f7a59;
+
+/// Magic value to replace with the byte offset off of rbp of the register
+/// holding the right-hand operand of a comparison
+const REPLACE_WITH_CMP_RHS: u32 = 0x4d82c61e;
+
+/// Magic value to replace with the number of high bits shifted out of the
+/// registers of a comparison
+const REPLACE_WITH_CMP_SHIFT: u32 = 0x26e9d0b7;
+
+/// Magic value to replace with the immediate of a comparison
+const REPLACE_WITH_CMP_IMM: usize = 0xe7c1593a2f84d60b;
+
+/// Magic value to replace with the PC of the instruction after a system
+/// call instruction
+const REPLACE_WITH_SITE_NEXT: usize = 0x5d0e93b7c4a1f628;
+
+/// Magic value to replace with the room a system call site leaves for the
+/// register event after it
+const REPLACE_WITH_SITE_RESERVE: u32 = 0x3a7c05e9;
+
+/// Magic value to replace with the address of the callout of a pinned
+/// system call site
+const REPLACE_WITH_PIN: usize = 0x71d9b2e64c08a35f;
+
 // All of our shellcode is written in this global assembly block, and it is
 // ripped out and placed into the JIT. It's kinda neat. It seems ugly, but I
 // think this is way easier to make tweaks to than some weird assembler at
@@ -795,8 +1895,8 @@ std::arch::global_asm!(r#"
 //
 // bits  - The bitness of the emulated target, either 32 or 64
 // width - The bitness divided by eight (number of bytes per target usize)
-// once  - Determines if this is a single-shot instruction hook, or an always
-//         hook
+// once  - Determines if this is a single-shot instruction hook (`_once`), a
+//         sampled one (`_sample`), or an always hook
 .macro create_insthook bits, width, once
 
 // This code is injected _directly_ into QEMUs JIT, we have to make sure we
@@ -807,7 +1907,7 @@ cannoli_insthook\bits\()\once\():
     // r13 - Pointer to end of trace buffer
     // r14 - Scratch
 
-.ifnb \once
+.ifc \once, _once
     // Clear the zero flag
     xor r14d, r14d
 
@@ -818,6 +1918,17 @@ cannoli_insthook\bits\()\once\():
     mov byte ptr [rip - 9], 0xeb
 .endif
 
+.ifc \once, _sample
+    // Count down to the next sample, the counter is shared by every thread
+    // of the process and racing on it only makes samples a bit irregular
+    mov r14, {REPLACE_WITH_SAMPLE_COUNTER}
+    dec qword ptr [r14]
+    jnz 10f
+
+    // This one is sampled, start counting down to the next one
+    mov qword ptr [r14], {REPLACE_WITH_SAMPLE_PERIOD}
+.endif
+
     // Allocate room in the buffer
     lea r14, [r12 + \width + 1]
 
@@ -870,6 +1981,8 @@ create_insthook 32, 4
 create_insthook 64, 8
 create_insthook 32, 4, _once
 create_insthook 64, 8, _once
+create_insthook 32, 4, _sample
+create_insthook 64, 8, _sample
 
 // ============================================================================
 
@@ -943,6 +2056,54 @@ cannoli_memhook_\access\()_\data\()_\addr\():
 cannoli_memhook_\access\()_\data\()_\addr\()_end:
 .endm // create_memhook
 
+// Same as `create_memhook`, but only logs the address and PC of the access and
+// not the data, for when the values aren't needed. The opcode has 0x40 set to
+// tell these apart
+.macro create_addrhook access, datawidth, width, addr
+.global cannoli_addrhook_\access\()\datawidth\()_\addr\()
+cannoli_addrhook_\access\()\datawidth\()_\addr\():
+    // r12 - Pointer to trace buffer
+    // r13 - Pointer to end of trace buffer
+    // r14 - For reads, this always holds the address, for writes, it's scratch
+
+    // Allocate room in the buffer (we have to preserve r14 here)
+    lea r12, [r12 + (\width * 2 + 1)]
+    cmp r12, r13
+    lea r12, [r12 - (\width * 2 + 1)]
+    jbe 2f
+
+    // Out of space, flush to get a new r12, r13, and r14
+    mov  r13, {REPLACE_WITH_FLUSH}
+    call r13
+
+2:
+.ifc \access, read
+    // Read opcode
+    mov byte ptr [r12], (((\width / 4) - 1) << 7) | 0x50 | \datawidth
+.endif
+.ifc \access, write
+    // Write opcode
+    mov byte ptr [r12], (((\width / 4) - 1) << 7) | 0x60 | \datawidth
+.endif
+
+    // Address
+    mov [r12 + 1], \addr
+
+    // Store the PC
+.if \width == 4
+    mov dword ptr [r12 + \width + 1], {REPLACE_WITH_PC}
+.elseif \width == 8
+    mov r14, {REPLACE_WITH_PC}
+    mov [r12 + \width + 1], r14
+.endif
+
+    // Advance buffer
+    add r12, \width * 2 + 1
+
+.global cannoli_addrhook_\access\()\datawidth\()_\addr\()_end
+cannoli_addrhook_\access\()\datawidth\()_\addr\()_end:
+.endm // create_addrhook
+
 // Macro invoked when creating an register hook.
 //
 // bits  - The bitness of the emulated target, either 32 or 64
@@ -1099,6 +2260,221 @@ create_branchhook 32, 4, 0
 create_branchhook 64, 8, 1
 create_branchhook 64, 8, 0
 
+// Macro invoked when creating a register file hook, which follows a register
+// or branch hook for every register file in the filters. The registers are
+// `width` bytes every `width + skip` bytes, and copied back to back
+//
+// bits  - The bitness of the emulated target, either 32 or 64
+// width - The bitness divided by eight (number of bytes per target usize)
+.macro create_regfilehook bits, width
+
+.global cannoli_regfilehook\bits\()
+cannoli_regfilehook\bits\():
+    // Determine size required for the register file and its metadata
+    lea r14, [r12 + 1 + 4 + \width + 4]
+    add r14, {REPLACE_WITH_REGFILE_SIZE}
+
+    // Make sure we're in bounds
+    cmp r14, r13
+    jbe 2f
+
+    // We're out of space, flush to get a new r12, r13, and r14
+    mov  r13, {REPLACE_WITH_FLUSH}
+    call r13
+
+2:
+.if \bits == 32
+    // Opcode
+    mov byte ptr [r12], 0x02
+
+    // Size of payload
+    mov dword ptr [r12 + 1], {REPLACE_WITH_REGFILE_SIZE}
+
+    // PC, directly put into memory from an immediate
+    mov dword ptr [r12 + 1 + 4], {REPLACE_WITH_PC}
+.elseif \bits == 64
+    // Opcode
+    mov byte ptr [r12], 0x82
+
+    // Size of payload
+    mov dword ptr [r12 + 1], {REPLACE_WITH_REGFILE_SIZE}
+
+    // Move PC into a register so we can use imm64 encoding
+    mov r14, {REPLACE_WITH_PC}
+    mov qword ptr [r12 + 1 + 4], r14
+.else
+.error "Invalid bitness passed to cannoli_regfilehook"
+.endif
+
+    // Index of the register file
+    mov dword ptr [r12 + 1 + 4 + \width], {REPLACE_WITH_REGFILE_INDEX}
+
+    // Copy the registers one at a time
+    push rdi
+    push rsi
+    push rcx
+    push rdx
+    lea rdi, [r12 + 1 + 4 + \width + 4]
+    lea rsi, [rbp + {REPLACE_WITH_REGFILE_OFFSET}]
+    mov edx, {REPLACE_WITH_REGFILE_COUNT}
+3:
+    mov ecx, {REPLACE_WITH_REGFILE_WIDTH}
+    rep movsb
+    add rsi, {REPLACE_WITH_REGFILE_SKIP}
+    dec edx
+    jnz 3b
+    pop rdx
+    pop rcx
+    pop rsi
+    pop rdi
+
+    // Advance buffer
+    add r12, 1 + 4 + \width + 4
+    add r12, {REPLACE_WITH_REGFILE_SIZE}
+
+.global cannoli_regfilehook\bits\()_end
+cannoli_regfilehook\bits\()_end:
+
+.endm // create_regfilehook
+
+create_regfilehook 32, 4
+create_regfilehook 64, 8
+
+// Macro invoked when creating a comparison hook, which follows whatever else
+// hooks a comparison instruction and logs its operands. The left-hand operand
+// is a register, the right-hand one a register or an immediate (`_imm`).
+// Registers are truncated to the size of the comparison by shifting out
+// their high bits, the size itself is patched into the last byte
+//
+// bits  - The bitness of the emulated target, either 32 or 64
+// width - The bitness divided by eight (number of bytes per target usize)
+// imm   - `_imm` if the right-hand operand is an immediate
+.macro create_cmphook bits, width, imm
+
+.global cannoli_cmphook\bits\()\imm\()
+cannoli_cmphook\bits\()\imm\():
+    // Allocate room in the buffer
+    lea r14, [r12 + 1 + 3 * \width + 1]
+
+    // Make sure we're in bounds
+    cmp r14, r13
+    jbe 2f
+
+    // We're out of space, flush to get a new r12, r13, and r14
+    mov  r13, {REPLACE_WITH_FLUSH}
+    call r13
+
+2:
+.if \bits == 32
+    // Opcode
+    mov byte ptr [r12], 0x70
+
+    // PC, directly put into memory from an immediate
+    mov dword ptr [r12 + 1], {REPLACE_WITH_PC}
+.elseif \bits == 64
+    // Opcode
+    mov byte ptr [r12], 0xf0
+
+    // Move PC into a register so we can use imm64 encoding
+    mov r14, {REPLACE_WITH_PC}
+    mov qword ptr [r12 + 1], r14
+.else
+.error "Invalid bitness passed to create_cmphook"
+.endif
+
+    // Left-hand operand
+    push rcx
+    mov ecx, {REPLACE_WITH_CMP_SHIFT}
+    mov r14, qword ptr [rbp + {REPLACE_WITH_CMP_LHS}]
+    shl r14, cl
+    shr r14, cl
+.if \bits == 32
+    mov dword ptr [r12 + 1 + \width], r14d
+.else
+    mov qword ptr [r12 + 1 + \width], r14
+.endif
+
+    // Right-hand operand
+.ifc \imm, _imm
+    mov r14, {REPLACE_WITH_CMP_IMM}
+.else
+    mov r14, qword ptr [rbp + {REPLACE_WITH_CMP_RHS}]
+    shl r14, cl
+    shr r14, cl
+.endif
+.if \bits == 32
+    mov dword ptr [r12 + 1 + 2 * \width], r14d
+.else
+    mov qword ptr [r12 + 1 + 2 * \width], r14
+.endif
+    pop rcx
+
+    // Size of the operands
+    mov byte ptr [r12 + 1 + 3 * \width], 0
+
+    // Advance buffer
+    add r12, 1 + 3 * \width + 1
+
+.global cannoli_cmphook\bits\()\imm\()_end
+cannoli_cmphook\bits\()\imm\()_end:
+
+.endm // create_cmphook
+
+create_cmphook 32, 4
+create_cmphook 64, 8
+create_cmphook 32, 4, _imm
+create_cmphook 64, 8, _imm
+
+// ============================================================================
+
+// System call site, announced before the registers of the system call
+// instruction and of the instruction after it, see `cannoli::syscalls`. The
+// same for every bitness
+.global cannoli_sitehook
+cannoli_sitehook:
+    // Allocate room in the buffer for the site and the register event after
+    // it, so they go to the client in the same payload
+    lea r14, [r12 + 1 + 8 + 8]
+    add r14, {REPLACE_WITH_SITE_RESERVE}
+
+    // Make sure we're in bounds
+    cmp r14, r13
+    jbe 2f
+
+    // We're out of space, flush to get a new r12, r13, and r14
+    mov  r13, {REPLACE_WITH_FLUSH}
+    call r13
+
+2:
+    // Opcode
+    mov byte ptr [r12], 0x35
+
+    // PC of the system call instruction and of the instruction after it
+    mov r14, {REPLACE_WITH_PC}
+    mov qword ptr [r12 + 1], r14
+    mov r14, {REPLACE_WITH_SITE_NEXT}
+    mov qword ptr [r12 + 1 + 8], r14
+
+    // Advance buffer
+    add r12, 1 + 8 + 8
+
+.global cannoli_sitehook_end
+cannoli_sitehook_end:
+
+// System call site with a pinned result, which calls into the jitter with
+// the PC, see `cannoli::pin`. The same for every bitness
+.global cannoli_pinhook
+cannoli_pinhook:
+    // Call the callout through r13, which holds the end of the buffer
+    push r13
+    mov  r14, {REPLACE_WITH_PC}
+    mov  r13, {REPLACE_WITH_PIN}
+    call r13
+    pop  r13
+
+.global cannoli_pinhook_end
+cannoli_pinhook_end:
+
 // ===========================================================================
 // !!! WARNING !!!
 //
@@ -1158,26 +2534,67 @@ multiple_create_memhook64 2, ax, cx, dx, bx, sp, bp, si, di, r8w, r9w, r10w, r11
 multiple_create_memhook64 4, eax, ecx, edx, ebx, esp, ebp, esi, edi, r8d, r9d, r10d, r11d, r12d, r13d, r14d, r15d
 multiple_create_memhook64 8, rax, rcx, rdx, rbx, rsp, rbp, rsi, rdi, r8, r9, r10, r11, r12, r13, r14, r15
 
+// For each `addr` in `regs`, create the read and write address-only hooks
+.macro multiple_create_addrhook datawidth, width, addr, regs:vararg
+    create_addrhook read,  \datawidth, \width, \addr
+    create_addrhook write, \datawidth, \width, \addr
+
+    // Continue creating addrhooks until we're out of regs
+    .ifnb \regs
+        multiple_create_addrhook \datawidth, \width, \regs
+    .endif
+.endm // multiple_create_addrhook
+
+// Create all possible address-only hooks for 32-bit and 64-bit
+multiple_create_addrhook 1, 4, eax, ecx, edx, ebx, esp, ebp, esi, edi, r8d, r9d, r10d, r11d, r12d, r13d, r14d, r15d
+multiple_create_addrhook 2, 4, eax, ecx, edx, ebx, esp, ebp, esi, edi, r8d, r9d, r10d, r11d, r12d, r13d, r14d, r15d
+multiple_create_addrhook 4, 4, eax, ecx, edx, ebx, esp, ebp, esi, edi, r8d, r9d, r10d, r11d, r12d, r13d, r14d, r15d
+multiple_create_addrhook 8, 4, eax, ecx, edx, ebx, esp, ebp, esi, edi, r8d, r9d, r10d, r11d, r12d, r13d, r14d, r15d
+multiple_create_addrhook 1, 8, rax, rcx, rdx, rbx, rsp, rbp, rsi, rdi, r8, r9, r10, r11, r12, r13, r14, r15
+multiple_create_addrhook 2, 8, rax, rcx, rdx, rbx, rsp, rbp, rsi, rdi, r8, r9, r10, r11, r12, r13, r14, r15
+multiple_create_addrhook 4, 8, rax, rcx, rdx, rbx, rsp, rbp, rsi, rdi, r8, r9, r10, r11, r12, r13, r14, r15
+multiple_create_addrhook 8, 8, rax, rcx, rdx, rbx, rsp, rbp, rsi, rdi, r8, r9, r10, r11, r12, r13, r14, r15
+
 "#,
     // Some magic values we use in our assembly for find-and-replace
     REPLACE_WITH_FLUSH = const REPLACE_WITH_FLUSH,
     REPLACE_WITH_PC    = const REPLACE_WITH_PC,
 
+    REPLACE_WITH_SAMPLE_COUNTER = const REPLACE_WITH_SAMPLE_COUNTER,
+    REPLACE_WITH_SAMPLE_PERIOD  = const REPLACE_WITH_SAMPLE_PERIOD,
+
     REPLACE_WITH_REGHOOK_SIZE   = const REPLACE_WITH_REGHOOK_SIZE,
     REPLACE_WITH_REGHOOK_OFFSET = const REPLACE_WITH_REGHOOK_OFFSET,
+
+    REPLACE_WITH_REGFILE_OFFSET = const REPLACE_WITH_REGFILE_OFFSET,
+    REPLACE_WITH_REGFILE_SIZE   = const REPLACE_WITH_REGFILE_SIZE,
+    REPLACE_WITH_REGFILE_INDEX  = const REPLACE_WITH_REGFILE_INDEX,
+    REPLACE_WITH_REGFILE_COUNT  = const REPLACE_WITH_REGFILE_COUNT,
+    REPLACE_WITH_REGFILE_WIDTH  = const REPLACE_WITH_REGFILE_WIDTH,
+    REPLACE_WITH_REGFILE_SKIP   = const REPLACE_WITH_REGFILE_SKIP,
+
+    REPLACE_WITH_CMP_LHS   = const REPLACE_WITH_CMP_LHS,
+    REPLACE_WITH_CMP_RHS   = const REPLACE_WITH_CMP_RHS,
+    REPLACE_WITH_CMP_SHIFT = const REPLACE_WITH_CMP_SHIFT,
+    REPLACE_WITH_CMP_IMM   = const REPLACE_WITH_CMP_IMM,
+
+    REPLACE_WITH_SITE_NEXT    = const REPLACE_WITH_SITE_NEXT,
+    REPLACE_WITH_SITE_RESERVE = const REPLACE_WITH_SITE_RESERVE,
+
+    REPLACE_WITH_PIN = const REPLACE_WITH_PIN,
 );
 
 // Create the 32-bit Cannoli implementation
 create_bitness!(
     u32, Cannoli32, init_cannoli32, lift_instruction32, jit_entry32,
-    jit_exit32, cannoli_flush_buffer32, lift_memop32,
-    cannoli_mmap32, cannoli_munmap32
+    jit_exit32, cannoli_flush_buffer32, lift_memop32, helper_memop32,
+    cannoli_mmap32, cannoli_munmap32, cannoli_signal32
 );
 
 // Create the 64-bit Cannoli implementation
 create_bitness!(
     u64, Cannoli64, init_cannoli64, lift_instruction64, jit_entry64,
-    jit_exit64, cannoli_flush_buffer64, lift_memop64,
-    cannoli_mmap64, cannoli_munmap64
+    jit_exit64, cannoli_flush_buffer64, lift_memop64, helper_memop64,
+    cannoli_mmap64, cannoli_munmap64, cannoli_signal64
 );
 
@@ -12876,3 +12876,833 @@ pub static MEMHOOK_TABLE: [[[[[(&u8, &u8); 16]; 16]; 2]; 4]; 2] = [
     ],
 ];
 
+extern {
+    static cannoli_addrhook_read1_eax: u8;
+    static cannoli_addrhook_read1_eax_end: u8;
+    static cannoli_addrhook_read1_ecx: u8;
+    static cannoli_addrhook_read1_ecx_end: u8;
+    static cannoli_addrhook_read1_edx: u8;
+    static cannoli_addrhook_read1_edx_end: u8;
+    static cannoli_addrhook_read1_ebx: u8;
+    static cannoli_addrhook_read1_ebx_end: u8;
+    static cannoli_addrhook_read1_esp: u8;
+    static cannoli_addrhook_read1_esp_end: u8;
+    static cannoli_addrhook_read1_ebp: u8;
+    static cannoli_addrhook_read1_ebp_end: u8;
+    static cannoli_addrhook_read1_esi: u8;
+    static cannoli_addrhook_read1_esi_end: u8;
+    static cannoli_addrhook_read1_edi: u8;
+    static cannoli_addrhook_read1_edi_end: u8;
+    static cannoli_addrhook_read1_r8d: u8;
+    static cannoli_addrhook_read1_r8d_end: u8;
+    static cannoli_addrhook_read1_r9d: u8;
+    static cannoli_addrhook_read1_r9d_end: u8;
+    static cannoli_addrhook_read1_r10d: u8;
+    static cannoli_addrhook_read1_r10d_end: u8;
+    static cannoli_addrhook_read1_r11d: u8;
+    static cannoli_addrhook_read1_r11d_end: u8;
+    static cannoli_addrhook_read1_r12d: u8;
+    static cannoli_addrhook_read1_r12d_end: u8;
+    static cannoli_addrhook_read1_r13d: u8;
+    static cannoli_addrhook_read1_r13d_end: u8;
+    static cannoli_addrhook_read1_r14d: u8;
+    static cannoli_addrhook_read1_r14d_end: u8;
+    static cannoli_addrhook_read1_r15d: u8;
+    static cannoli_addrhook_read1_r15d_end: u8;
+    static cannoli_addrhook_write1_eax: u8;
+    static cannoli_addrhook_write1_eax_end: u8;
+    static cannoli_addrhook_write1_ecx: u8;
+    static cannoli_addrhook_write1_ecx_end: u8;
+    static cannoli_addrhook_write1_edx: u8;
+    static cannoli_addrhook_write1_edx_end: u8;
+    static cannoli_addrhook_write1_ebx: u8;
+    static cannoli_addrhook_write1_ebx_end: u8;
+    static cannoli_addrhook_write1_esp: u8;
+    static cannoli_addrhook_write1_esp_end: u8;
+    static cannoli_addrhook_write1_ebp: u8;
+    static cannoli_addrhook_write1_ebp_end: u8;
+    static cannoli_addrhook_write1_esi: u8;
+    static cannoli_addrhook_write1_esi_end: u8;
+    static cannoli_addrhook_write1_edi: u8;
+    static cannoli_addrhook_write1_edi_end: u8;
+    static cannoli_addrhook_write1_r8d: u8;
+    static cannoli_addrhook_write1_r8d_end: u8;
+    static cannoli_addrhook_write1_r9d: u8;
+    static cannoli_addrhook_write1_r9d_end: u8;
+    static cannoli_addrhook_write1_r10d: u8;
+    static cannoli_addrhook_write1_r10d_end: u8;
+    static cannoli_addrhook_write1_r11d: u8;
+    static cannoli_addrhook_write1_r11d_end: u8;
+    static cannoli_addrhook_write1_r12d: u8;
+    static cannoli_addrhook_write1_r12d_end: u8;
+    static cannoli_addrhook_write1_r13d: u8;
+    static cannoli_addrhook_write1_r13d_end: u8;
+    static cannoli_addrhook_write1_r14d: u8;
+    static cannoli_addrhook_write1_r14d_end: u8;
+    static cannoli_addrhook_write1_r15d: u8;
+    static cannoli_addrhook_write1_r15d_end: u8;
+    static cannoli_addrhook_read2_eax: u8;
+    static cannoli_addrhook_read2_eax_end: u8;
+    static cannoli_addrhook_read2_ecx: u8;
+    static cannoli_addrhook_read2_ecx_end: u8;
+    static cannoli_addrhook_read2_edx: u8;
+    static cannoli_addrhook_read2_edx_end: u8;
+    static cannoli_addrhook_read2_ebx: u8;
+    static cannoli_addrhook_read2_ebx_end: u8;
+    static cannoli_addrhook_read2_esp: u8;
+    static cannoli_addrhook_read2_esp_end: u8;
+    static cannoli_addrhook_read2_ebp: u8;
+    static cannoli_addrhook_read2_ebp_end: u8;
+    static cannoli_addrhook_read2_esi: u8;
+    static cannoli_addrhook_read2_esi_end: u8;
+    static cannoli_addrhook_read2_edi: u8;
+    static cannoli_addrhook_read2_edi_end: u8;
+    static cannoli_addrhook_read2_r8d: u8;
+    static cannoli_addrhook_read2_r8d_end: u8;
+    static cannoli_addrhook_read2_r9d: u8;
+    static cannoli_addrhook_read2_r9d_end: u8;
+    static cannoli_addrhook_read2_r10d: u8;
+    static cannoli_addrhook_read2_r10d_end: u8;
+    static cannoli_addrhook_read2_r11d: u8;
+    static cannoli_addrhook_read2_r11d_end: u8;
+    static cannoli_addrhook_read2_r12d: u8;
+    static cannoli_addrhook_read2_r12d_end: u8;
+    static cannoli_addrhook_read2_r13d: u8;
+    static cannoli_addrhook_read2_r13d_end: u8;
+    static cannoli_addrhook_read2_r14d: u8;
+    static cannoli_addrhook_read2_r14d_end: u8;
+    static cannoli_addrhook_read2_r15d: u8;
+    static cannoli_addrhook_read2_r15d_end: u8;
+    static cannoli_addrhook_write2_eax: u8;
+    static cannoli_addrhook_write2_eax_end: u8;
+    static cannoli_addrhook_write2_ecx: u8;
+    static cannoli_addrhook_write2_ecx_end: u8;
+    static cannoli_addrhook_write2_edx: u8;
+    static cannoli_addrhook_write2_edx_end: u8;
+    static cannoli_addrhook_write2_ebx: u8;
+    static cannoli_addrhook_write2_ebx_end: u8;
+    static cannoli_addrhook_write2_esp: u8;
+    static cannoli_addrhook_write2_esp_end: u8;
+    static cannoli_addrhook_write2_ebp: u8;
+    static cannoli_addrhook_write2_ebp_end: u8;
+    static cannoli_addrhook_write2_esi: u8;
+    static cannoli_addrhook_write2_esi_end: u8;
+    static cannoli_addrhook_write2_edi: u8;
+    static cannoli_addrhook_write2_edi_end: u8;
+    static cannoli_addrhook_write2_r8d: u8;
+    static cannoli_addrhook_write2_r8d_end: u8;
+    static cannoli_addrhook_write2_r9d: u8;
+    static cannoli_addrhook_write2_r9d_end: u8;
+    static cannoli_addrhook_write2_r10d: u8;
+    static cannoli_addrhook_write2_r10d_end: u8;
+    static cannoli_addrhook_write2_r11d: u8;
+    static cannoli_addrhook_write2_r11d_end: u8;
+    static cannoli_addrhook_write2_r12d: u8;
+    static cannoli_addrhook_write2_r12d_end: u8;
+    static cannoli_addrhook_write2_r13d: u8;
+    static cannoli_addrhook_write2_r13d_end: u8;
+    static cannoli_addrhook_write2_r14d: u8;
+    static cannoli_addrhook_write2_r14d_end: u8;
+    static cannoli_addrhook_write2_r15d: u8;
+    static cannoli_addrhook_write2_r15d_end: u8;
+    static cannoli_addrhook_read4_eax: u8;
+    static cannoli_addrhook_read4_eax_end: u8;
+    static cannoli_addrhook_read4_ecx: u8;
+    static cannoli_addrhook_read4_ecx_end: u8;
+    static cannoli_addrhook_read4_edx: u8;
+    static cannoli_addrhook_read4_edx_end: u8;
+    static cannoli_addrhook_read4_ebx: u8;
+    static cannoli_addrhook_read4_ebx_end: u8;
+    static cannoli_addrhook_read4_esp: u8;
+    static cannoli_addrhook_read4_esp_end: u8;
+    static cannoli_addrhook_read4_ebp: u8;
+    static cannoli_addrhook_read4_ebp_end: u8;
+    static cannoli_addrhook_read4_esi: u8;
+    static cannoli_addrhook_read4_esi_end: u8;
+    static cannoli_addrhook_read4_edi: u8;
+    static cannoli_addrhook_read4_edi_end: u8;
+    static cannoli_addrhook_read4_r8d: u8;
+    static cannoli_addrhook_read4_r8d_end: u8;
+    static cannoli_addrhook_read4_r9d: u8;
+    static cannoli_addrhook_read4_r9d_end: u8;
+    static cannoli_addrhook_read4_r10d: u8;
+    static cannoli_addrhook_read4_r10d_end: u8;
+    static cannoli_addrhook_read4_r11d: u8;
+    static cannoli_addrhook_read4_r11d_end: u8;
+    static cannoli_addrhook_read4_r12d: u8;
+    static cannoli_addrhook_read4_r12d_end: u8;
+    static cannoli_addrhook_read4_r13d: u8;
+    static cannoli_addrhook_read4_r13d_end: u8;
+    static cannoli_addrhook_read4_r14d: u8;
+    static cannoli_addrhook_read4_r14d_end: u8;
+    static cannoli_addrhook_read4_r15d: u8;
+    static cannoli_addrhook_read4_r15d_end: u8;
+    static cannoli_addrhook_write4_eax: u8;
+    static cannoli_addrhook_write4_eax_end: u8;
+    static cannoli_addrhook_write4_ecx: u8;
+    static cannoli_addrhook_write4_ecx_end: u8;
+    static cannoli_addrhook_write4_edx: u8;
+    static cannoli_addrhook_write4_edx_end: u8;
+    static cannoli_addrhook_write4_ebx: u8;
+    static cannoli_addrhook_write4_ebx_end: u8;
+    static cannoli_addrhook_write4_esp: u8;
+    static cannoli_addrhook_write4_esp_end: u8;
+    static cannoli_addrhook_write4_ebp: u8;
+    static cannoli_addrhook_write4_ebp_end: u8;
+    static cannoli_addrhook_write4_esi: u8;
+    static cannoli_addrhook_write4_esi_end: u8;
+    static cannoli_addrhook_write4_edi: u8;
+    static cannoli_addrhook_write4_edi_end: u8;
+    static cannoli_addrhook_write4_r8d: u8;
+    static cannoli_addrhook_write4_r8d_end: u8;
+    static cannoli_addrhook_write4_r9d: u8;
+    static cannoli_addrhook_write4_r9d_end: u8;
+    static cannoli_addrhook_write4_r10d: u8;
+    static cannoli_addrhook_write4_r10d_end: u8;
+    static cannoli_addrhook_write4_r11d: u8;
+    static cannoli_addrhook_write4_r11d_end: u8;
+    static cannoli_addrhook_write4_r12d: u8;
+    static cannoli_addrhook_write4_r12d_end: u8;
+    static cannoli_addrhook_write4_r13d: u8;
+    static cannoli_addrhook_write4_r13d_end: u8;
+    static cannoli_addrhook_write4_r14d: u8;
+    static cannoli_addrhook_write4_r14d_end: u8;
+    static cannoli_addrhook_write4_r15d: u8;
+    static cannoli_addrhook_write4_r15d_end: u8;
+    static cannoli_addrhook_read8_eax: u8;
+    static cannoli_addrhook_read8_eax_end: u8;
+    static cannoli_addrhook_read8_ecx: u8;
+    static cannoli_addrhook_read8_ecx_end: u8;
+    static cannoli_addrhook_read8_edx: u8;
+    static cannoli_addrhook_read8_edx_end: u8;
+    static cannoli_addrhook_read8_ebx: u8;
+    static cannoli_addrhook_read8_ebx_end: u8;
+    static cannoli_addrhook_read8_esp: u8;
+    static cannoli_addrhook_read8_esp_end: u8;
+    static cannoli_addrhook_read8_ebp: u8;
+    static cannoli_addrhook_read8_ebp_end: u8;
+    static cannoli_addrhook_read8_esi: u8;
+    static cannoli_addrhook_read8_esi_end: u8;
+    static cannoli_addrhook_read8_edi: u8;
+    static cannoli_addrhook_read8_edi_end: u8;
+    static cannoli_addrhook_read8_r8d: u8;
+    static cannoli_addrhook_read8_r8d_end: u8;
+    static cannoli_addrhook_read8_r9d: u8;
+    static cannoli_addrhook_read8_r9d_end: u8;
+    static cannoli_addrhook_read8_r10d: u8;
+    static cannoli_addrhook_read8_r10d_end: u8;
+    static cannoli_addrhook_read8_r11d: u8;
+    static cannoli_addrhook_read8_r11d_end: u8;
+    static cannoli_addrhook_read8_r12d: u8;
+    static cannoli_addrhook_read8_r12d_end: u8;
+    static cannoli_addrhook_read8_r13d: u8;
+    static cannoli_addrhook_read8_r13d_end: u8;
+    static cannoli_addrhook_read8_r14d: u8;
+    static cannoli_addrhook_read8_r14d_end: u8;
+    static cannoli_addrhook_read8_r15d: u8;
+    static cannoli_addrhook_read8_r15d_end: u8;
+    static cannoli_addrhook_write8_eax: u8;
+    static cannoli_addrhook_write8_eax_end: u8;
+    static cannoli_addrhook_write8_ecx: u8;
+    static cannoli_addrhook_write8_ecx_end: u8;
+    static cannoli_addrhook_write8_edx: u8;
+    static cannoli_addrhook_write8_edx_end: u8;
+    static cannoli_addrhook_write8_ebx: u8;
+    static cannoli_addrhook_write8_ebx_end: u8;
+    static cannoli_addrhook_write8_esp: u8;
+    static cannoli_addrhook_write8_esp_end: u8;
+    static cannoli_addrhook_write8_ebp: u8;
+    static cannoli_addrhook_write8_ebp_end: u8;
+    static cannoli_addrhook_write8_esi: u8;
+    static cannoli_addrhook_write8_esi_end: u8;
+    static cannoli_addrhook_write8_edi: u8;
+    static cannoli_addrhook_write8_edi_end: u8;
+    static cannoli_addrhook_write8_r8d: u8;
+    static cannoli_addrhook_write8_r8d_end: u8;
+    static cannoli_addrhook_write8_r9d: u8;
+    static cannoli_addrhook_write8_r9d_end: u8;
+    static cannoli_addrhook_write8_r10d: u8;
+    static cannoli_addrhook_write8_r10d_end: u8;
+    static cannoli_addrhook_write8_r11d: u8;
+    static cannoli_addrhook_write8_r11d_end: u8;
+    static cannoli_addrhook_write8_r12d: u8;
+    static cannoli_addrhook_write8_r12d_end: u8;
+    static cannoli_addrhook_write8_r13d: u8;
+    static cannoli_addrhook_write8_r13d_end: u8;
+    static cannoli_addrhook_write8_r14d: u8;
+    static cannoli_addrhook_write8_r14d_end: u8;
+    static cannoli_addrhook_write8_r15d: u8;
+    static cannoli_addrhook_write8_r15d_end: u8;
+    static cannoli_addrhook_read1_rax: u8;
+    static cannoli_addrhook_read1_rax_end: u8;
+    static cannoli_addrhook_read1_rcx: u8;
+    static cannoli_addrhook_read1_rcx_end: u8;
+    static cannoli_addrhook_read1_rdx: u8;
+    static cannoli_addrhook_read1_rdx_end: u8;
+    static cannoli_addrhook_read1_rbx: u8;
+    static cannoli_addrhook_read1_rbx_end: u8;
+    static cannoli_addrhook_read1_rsp: u8;
+    static cannoli_addrhook_read1_rsp_end: u8;
+    static cannoli_addrhook_read1_rbp: u8;
+    static cannoli_addrhook_read1_rbp_end: u8;
+    static cannoli_addrhook_read1_rsi: u8;
+    static cannoli_addrhook_read1_rsi_end: u8;
+    static cannoli_addrhook_read1_rdi: u8;
+    static cannoli_addrhook_read1_rdi_end: u8;
+    static cannoli_addrhook_read1_r8: u8;
+    static cannoli_addrhook_read1_r8_end: u8;
+    static cannoli_addrhook_read1_r9: u8;
+    static cannoli_addrhook_read1_r9_end: u8;
+    static cannoli_addrhook_read1_r10: u8;
+    static cannoli_addrhook_read1_r10_end: u8;
+    static cannoli_addrhook_read1_r11: u8;
+    static cannoli_addrhook_read1_r11_end: u8;
+    static cannoli_addrhook_read1_r12: u8;
+    static cannoli_addrhook_read1_r12_end: u8;
+    static cannoli_addrhook_read1_r13: u8;
+    static cannoli_addrhook_read1_r13_end: u8;
+    static cannoli_addrhook_read1_r14: u8;
+    static cannoli_addrhook_read1_r14_end: u8;
+    static cannoli_addrhook_read1_r15: u8;
+    static cannoli_addrhook_read1_r15_end: u8;
+    static cannoli_addrhook_write1_rax: u8;
+    static cannoli_addrhook_write1_rax_end: u8;
+    static cannoli_addrhook_write1_rcx: u8;
+    static cannoli_addrhook_write1_rcx_end: u8;
+    static cannoli_addrhook_write1_rdx: u8;
+    static cannoli_addrhook_write1_rdx_end: u8;
+    static cannoli_addrhook_write1_rbx: u8;
+    static cannoli_addrhook_write1_rbx_end: u8;
+    static cannoli_addrhook_write1_rsp: u8;
+    static cannoli_addrhook_write1_rsp_end: u8;
+    static cannoli_addrhook_write1_rbp: u8;
+    static cannoli_addrhook_write1_rbp_end: u8;
+    static cannoli_addrhook_write1_rsi: u8;
+    static cannoli_addrhook_write1_rsi_end: u8;
+    static cannoli_addrhook_write1_rdi: u8;
+    static cannoli_addrhook_write1_rdi_end: u8;
+    static cannoli_addrhook_write1_r8: u8;
+    static cannoli_addrhook_write1_r8_end: u8;
+    static cannoli_addrhook_write1_r9: u8;
+    static cannoli_addrhook_write1_r9_end: u8;
+    static cannoli_addrhook_write1_r10: u8;
+    static cannoli_addrhook_write1_r10_end: u8;
+    static cannoli_addrhook_write1_r11: u8;
+    static cannoli_addrhook_write1_r11_end: u8;
+    static cannoli_addrhook_write1_r12: u8;
+    static cannoli_addrhook_write1_r12_end: u8;
+    static cannoli_addrhook_write1_r13: u8;
+    static cannoli_addrhook_write1_r13_end: u8;
+    static cannoli_addrhook_write1_r14: u8;
+    static cannoli_addrhook_write1_r14_end: u8;
+    static cannoli_addrhook_write1_r15: u8;
+    static cannoli_addrhook_write1_r15_end: u8;
+    static cannoli_addrhook_read2_rax: u8;
+    static cannoli_addrhook_read2_rax_end: u8;
+    static cannoli_addrhook_read2_rcx: u8;
+    static cannoli_addrhook_read2_rcx_end: u8;
+    static cannoli_addrhook_read2_rdx: u8;
+    static cannoli_addrhook_read2_rdx_end: u8;
+    static cannoli_addrhook_read2_rbx: u8;
+    static cannoli_addrhook_read2_rbx_end: u8;
+    static cannoli_addrhook_read2_rsp: u8;
+    static cannoli_addrhook_read2_rsp_end: u8;
+    static cannoli_addrhook_read2_rbp: u8;
+    static cannoli_addrhook_read2_rbp_end: u8;
+    static cannoli_addrhook_read2_rsi: u8;
+    static cannoli_addrhook_read2_rsi_end: u8;
+    static cannoli_addrhook_read2_rdi: u8;
+    static cannoli_addrhook_read2_rdi_end: u8;
+    static cannoli_addrhook_read2_r8: u8;
+    static cannoli_addrhook_read2_r8_end: u8;
+    static cannoli_addrhook_read2_r9: u8;
+    static cannoli_addrhook_read2_r9_end: u8;
+    static cannoli_addrhook_read2_r10: u8;
+    static cannoli_addrhook_read2_r10_end: u8;
+    static cannoli_addrhook_read2_r11: u8;
+    static cannoli_addrhook_read2_r11_end: u8;
+    static cannoli_addrhook_read2_r12: u8;
+    static cannoli_addrhook_read2_r12_end: u8;
+    static cannoli_addrhook_read2_r13: u8;
+    static cannoli_addrhook_read2_r13_end: u8;
+    static cannoli_addrhook_read2_r14: u8;
+    static cannoli_addrhook_read2_r14_end: u8;
+    static cannoli_addrhook_read2_r15: u8;
+    static cannoli_addrhook_read2_r15_end: u8;
+    static cannoli_addrhook_write2_rax: u8;
+    static cannoli_addrhook_write2_rax_end: u8;
+    static cannoli_addrhook_write2_rcx: u8;
+    static cannoli_addrhook_write2_rcx_end: u8;
+    static cannoli_addrhook_write2_rdx: u8;
+    static cannoli_addrhook_write2_rdx_end: u8;
+    static cannoli_addrhook_write2_rbx: u8;
+    static cannoli_addrhook_write2_rbx_end: u8;
+    static cannoli_addrhook_write2_rsp: u8;
+    static cannoli_addrhook_write2_rsp_end: u8;
+    static cannoli_addrhook_write2_rbp: u8;
+    static cannoli_addrhook_write2_rbp_end: u8;
+    static cannoli_addrhook_write2_rsi: u8;
+    static cannoli_addrhook_write2_rsi_end: u8;
+    static cannoli_addrhook_write2_rdi: u8;
+    static cannoli_addrhook_write2_rdi_end: u8;
+    static cannoli_addrhook_write2_r8: u8;
+    static cannoli_addrhook_write2_r8_end: u8;
+    static cannoli_addrhook_write2_r9: u8;
+    static cannoli_addrhook_write2_r9_end: u8;
+    static cannoli_addrhook_write2_r10: u8;
+    static cannoli_addrhook_write2_r10_end: u8;
+    static cannoli_addrhook_write2_r11: u8;
+    static cannoli_addrhook_write2_r11_end: u8;
+    static cannoli_addrhook_write2_r12: u8;
+    static cannoli_addrhook_write2_r12_end: u8;
+    static cannoli_addrhook_write2_r13: u8;
+    static cannoli_addrhook_write2_r13_end: u8;
+    static cannoli_addrhook_write2_r14: u8;
+    static cannoli_addrhook_write2_r14_end: u8;
+    static cannoli_addrhook_write2_r15: u8;
+    static cannoli_addrhook_write2_r15_end: u8;
+    static cannoli_addrhook_read4_rax: u8;
+    static cannoli_addrhook_read4_rax_end: u8;
+    static cannoli_addrhook_read4_rcx: u8;
+    static cannoli_addrhook_read4_rcx_end: u8;
+    static cannoli_addrhook_read4_rdx: u8;
+    static cannoli_addrhook_read4_rdx_end: u8;
+    static cannoli_addrhook_read4_rbx: u8;
+    static cannoli_addrhook_read4_rbx_end: u8;
+    static cannoli_addrhook_read4_rsp: u8;
+    static cannoli_addrhook_read4_rsp_end: u8;
+    static cannoli_addrhook_read4_rbp: u8;
+    static cannoli_addrhook_read4_rbp_end: u8;
+    static cannoli_addrhook_read4_rsi: u8;
+    static cannoli_addrhook_read4_rsi_end: u8;
+    static cannoli_addrhook_read4_rdi: u8;
+    static cannoli_addrhook_read4_rdi_end: u8;
+    static cannoli_addrhook_read4_r8: u8;
+    static cannoli_addrhook_read4_r8_end: u8;
+    static cannoli_addrhook_read4_r9: u8;
+    static cannoli_addrhook_read4_r9_end: u8;
+    static cannoli_addrhook_read4_r10: u8;
+    static cannoli_addrhook_read4_r10_end: u8;
+    static cannoli_addrhook_read4_r11: u8;
+    static cannoli_addrhook_read4_r11_end: u8;
+    static cannoli_addrhook_read4_r12: u8;
+    static cannoli_addrhook_read4_r12_end: u8;
+    static cannoli_addrhook_read4_r13: u8;
+    static cannoli_addrhook_read4_r13_end: u8;
+    static cannoli_addrhook_read4_r14: u8;
+    static cannoli_addrhook_read4_r14_end: u8;
+    static cannoli_addrhook_read4_r15: u8;
+    static cannoli_addrhook_read4_r15_end: u8;
+    static cannoli_addrhook_write4_rax: u8;
+    static cannoli_addrhook_write4_rax_end: u8;
+    static cannoli_addrhook_write4_rcx: u8;
+    static cannoli_addrhook_write4_rcx_end: u8;
+    static cannoli_addrhook_write4_rdx: u8;
+    static cannoli_addrhook_write4_rdx_end: u8;
+    static cannoli_addrhook_write4_rbx: u8;
+    static cannoli_addrhook_write4_rbx_end: u8;
+    static cannoli_addrhook_write4_rsp: u8;
+    static cannoli_addrhook_write4_rsp_end: u8;
+    static cannoli_addrhook_write4_rbp: u8;
+    static cannoli_addrhook_write4_rbp_end: u8;
+    static cannoli_addrhook_write4_rsi: u8;
+    static cannoli_addrhook_write4_rsi_end: u8;
+    static cannoli_addrhook_write4_rdi: u8;
+    static cannoli_addrhook_write4_rdi_end: u8;
+    static cannoli_addrhook_write4_r8: u8;
+    static cannoli_addrhook_write4_r8_end: u8;
+    static cannoli_addrhook_write4_r9: u8;
+    static cannoli_addrhook_write4_r9_end: u8;
+    static cannoli_addrhook_write4_r10: u8;
+    static cannoli_addrhook_write4_r10_end: u8;
+    static cannoli_addrhook_write4_r11: u8;
+    static cannoli_addrhook_write4_r11_end: u8;
+    static cannoli_addrhook_write4_r12: u8;
+    static cannoli_addrhook_write4_r12_end: u8;
+    static cannoli_addrhook_write4_r13: u8;
+    static cannoli_addrhook_write4_r13_end: u8;
+    static cannoli_addrhook_write4_r14: u8;
+    static cannoli_addrhook_write4_r14_end: u8;
+    static cannoli_addrhook_write4_r15: u8;
+    static cannoli_addrhook_write4_r15_end: u8;
+    static cannoli_addrhook_read8_rax: u8;
+    static cannoli_addrhook_read8_rax_end: u8;
+    static cannoli_addrhook_read8_rcx: u8;
+    static cannoli_addrhook_read8_rcx_end: u8;
+    static cannoli_addrhook_read8_rdx: u8;
+    static cannoli_addrhook_read8_rdx_end: u8;
+    static cannoli_addrhook_read8_rbx: u8;
+    static cannoli_addrhook_read8_rbx_end: u8;
+    static cannoli_addrhook_read8_rsp: u8;
+    static cannoli_addrhook_read8_rsp_end: u8;
+    static cannoli_addrhook_read8_rbp: u8;
+    static cannoli_addrhook_read8_rbp_end: u8;
+    static cannoli_addrhook_read8_rsi: u8;
+    static cannoli_addrhook_read8_rsi_end: u8;
+    static cannoli_addrhook_read8_rdi: u8;
+    static cannoli_addrhook_read8_rdi_end: u8;
+    static cannoli_addrhook_read8_r8: u8;
+    static cannoli_addrhook_read8_r8_end: u8;
+    static cannoli_addrhook_read8_r9: u8;
+    static cannoli_addrhook_read8_r9_end: u8;
+    static cannoli_addrhook_read8_r10: u8;
+    static cannoli_addrhook_read8_r10_end: u8;
+    static cannoli_addrhook_read8_r11: u8;
+    static cannoli_addrhook_read8_r11_end: u8;
+    static cannoli_addrhook_read8_r12: u8;
+    static cannoli_addrhook_read8_r12_end: u8;
+    static cannoli_addrhook_read8_r13: u8;
+    static cannoli_addrhook_read8_r13_end: u8;
+    static cannoli_addrhook_read8_r14: u8;
+    static cannoli_addrhook_read8_r14_end: u8;
+    static cannoli_addrhook_read8_r15: u8;
+    static cannoli_addrhook_read8_r15_end: u8;
+    static cannoli_addrhook_write8_rax: u8;
+    static cannoli_addrhook_write8_rax_end: u8;
+    static cannoli_addrhook_write8_rcx: u8;
+    static cannoli_addrhook_write8_rcx_end: u8;
+    static cannoli_addrhook_write8_rdx: u8;
+    static cannoli_addrhook_write8_rdx_end: u8;
+    static cannoli_addrhook_write8_rbx: u8;
+    static cannoli_addrhook_write8_rbx_end: u8;
+    static cannoli_addrhook_write8_rsp: u8;
+    static cannoli_addrhook_write8_rsp_end: u8;
+    static cannoli_addrhook_write8_rbp: u8;
+    static cannoli_addrhook_write8_rbp_end: u8;
+    static cannoli_addrhook_write8_rsi: u8;
+    static cannoli_addrhook_write8_rsi_end: u8;
+    static cannoli_addrhook_write8_rdi: u8;
+    static cannoli_addrhook_write8_rdi_end: u8;
+    static cannoli_addrhook_write8_r8: u8;
+    static cannoli_addrhook_write8_r8_end: u8;
+    static cannoli_addrhook_write8_r9: u8;
+    static cannoli_addrhook_write8_r9_end: u8;
+    static cannoli_addrhook_write8_r10: u8;
+    static cannoli_addrhook_write8_r10_end: u8;
+    static cannoli_addrhook_write8_r11: u8;
+    static cannoli_addrhook_write8_r11_end: u8;
+    static cannoli_addrhook_write8_r12: u8;
+    static cannoli_addrhook_write8_r12_end: u8;
+    static cannoli_addrhook_write8_r13: u8;
+    static cannoli_addrhook_write8_r13_end: u8;
+    static cannoli_addrhook_write8_r14: u8;
+    static cannoli_addrhook_write8_r14_end: u8;
+    static cannoli_addrhook_write8_r15: u8;
+    static cannoli_addrhook_write8_r15_end: u8;
+}
+
+/// Address-only memory hook table, indexed by
+///     `ADDRHOOK_TABLE[bitness][access_width][access_type][addr]`
+/// with the same values as [`MEMHOOK_TABLE`]. These hooks log the address
+/// and PC of an access but not the data, so there is no data register.
+#[allow(clippy::type_complexity)]
+pub static ADDRHOOK_TABLE: [[[[(&u8, &u8); 16]; 2]; 4]; 2] = [
+    [
+        [
+            [
+                unsafe { (&cannoli_addrhook_read1_eax, &cannoli_addrhook_read1_eax_end) },
+                unsafe { (&cannoli_addrhook_read1_ecx, &cannoli_addrhook_read1_ecx_end) },
+                unsafe { (&cannoli_addrhook_read1_edx, &cannoli_addrhook_read1_edx_end) },
+                unsafe { (&cannoli_addrhook_read1_ebx, &cannoli_addrhook_read1_ebx_end) },
+                unsafe { (&cannoli_addrhook_read1_esp, &cannoli_addrhook_read1_esp_end) },
+                unsafe { (&cannoli_addrhook_read1_ebp, &cannoli_addrhook_read1_ebp_end) },
+                unsafe { (&cannoli_addrhook_read1_esi, &cannoli_addrhook_read1_esi_end) },
+                unsafe { (&cannoli_addrhook_read1_edi, &cannoli_addrhook_read1_edi_end) },
+                unsafe { (&cannoli_addrhook_read1_r8d, &cannoli_addrhook_read1_r8d_end) },
+                unsafe { (&cannoli_addrhook_read1_r9d, &cannoli_addrhook_read1_r9d_end) },
+                unsafe { (&cannoli_addrhook_read1_r10d, &cannoli_addrhook_read1_r10d_end) },
+                unsafe { (&cannoli_addrhook_read1_r11d, &cannoli_addrhook_read1_r11d_end) },
+                unsafe { (&cannoli_addrhook_read1_r12d, &cannoli_addrhook_read1_r12d_end) },
+                unsafe { (&cannoli_addrhook_read1_r13d, &cannoli_addrhook_read1_r13d_end) },
+                unsafe { (&cannoli_addrhook_read1_r14d, &cannoli_addrhook_read1_r14d_end) },
+                unsafe { (&cannoli_addrhook_read1_r15d, &cannoli_addrhook_read1_r15d_end) },
+            ],
+            [
+                unsafe { (&cannoli_addrhook_write1_eax, &cannoli_addrhook_write1_eax_end) },
+                unsafe { (&cannoli_addrhook_write1_ecx, &cannoli_addrhook_write1_ecx_end) },
+                unsafe { (&cannoli_addrhook_write1_edx, &cannoli_addrhook_write1_edx_end) },
+                unsafe { (&cannoli_addrhook_write1_ebx, &cannoli_addrhook_write1_ebx_end) },
+                unsafe { (&cannoli_addrhook_write1_esp, &cannoli_addrhook_write1_esp_end) },
+                unsafe { (&cannoli_addrhook_write1_ebp, &cannoli_addrhook_write1_ebp_end) },
+                unsafe { (&cannoli_addrhook_write1_esi, &cannoli_addrhook_write1_esi_end) },
+                unsafe { (&cannoli_addrhook_write1_edi, &cannoli_addrhook_write1_edi_end) },
+                unsafe { (&cannoli_addrhook_write1_r8d, &cannoli_addrhook_write1_r8d_end) },
+                unsafe { (&cannoli_addrhook_write1_r9d, &cannoli_addrhook_write1_r9d_end) },
+                unsafe { (&cannoli_addrhook_write1_r10d, &cannoli_addrhook_write1_r10d_end) },
+                unsafe { (&cannoli_addrhook_write1_r11d, &cannoli_addrhook_write1_r11d_end) },
+                unsafe { (&cannoli_addrhook_write1_r12d, &cannoli_addrhook_write1_r12d_end) },
+                unsafe { (&cannoli_addrhook_write1_r13d, &cannoli_addrhook_write1_r13d_end) },
+                unsafe { (&cannoli_addrhook_write1_r14d, &cannoli_addrhook_write1_r14d_end) },
+                unsafe { (&cannoli_addrhook_write1_r15d, &cannoli_addrhook_write1_r15d_end) },
+            ],
+        ],
+        [
+            [
+                unsafe { (&cannoli_addrhook_read2_eax, &cannoli_addrhook_read2_eax_end) },
+                unsafe { (&cannoli_addrhook_read2_ecx, &cannoli_addrhook_read2_ecx_end) },
+                unsafe { (&cannoli_addrhook_read2_edx, &cannoli_addrhook_read2_edx_end) },
+                unsafe { (&cannoli_addrhook_read2_ebx, &cannoli_addrhook_read2_ebx_end) },
+                unsafe { (&cannoli_addrhook_read2_esp, &cannoli_addrhook_read2_esp_end) },
+                unsafe { (&cannoli_addrhook_read2_ebp, &cannoli_addrhook_read2_ebp_end) },
+                unsafe { (&cannoli_addrhook_read2_esi, &cannoli_addrhook_read2_esi_end) },
+                unsafe { (&cannoli_addrhook_read2_edi, &cannoli_addrhook_read2_edi_end) },
+                unsafe { (&cannoli_addrhook_read2_r8d, &cannoli_addrhook_read2_r8d_end) },
+                unsafe { (&cannoli_addrhook_read2_r9d, &cannoli_addrhook_read2_r9d_end) },
+                unsafe { (&cannoli_addrhook_read2_r10d, &cannoli_addrhook_read2_r10d_end) },
+                unsafe { (&cannoli_addrhook_read2_r11d, &cannoli_addrhook_read2_r11d_end) },
+                unsafe { (&cannoli_addrhook_read2_r12d, &cannoli_addrhook_read2_r12d_end) },
+                unsafe { (&cannoli_addrhook_read2_r13d, &cannoli_addrhook_read2_r13d_end) },
+                unsafe { (&cannoli_addrhook_read2_r14d, &cannoli_addrhook_read2_r14d_end) },
+                unsafe { (&cannoli_addrhook_read2_r15d, &cannoli_addrhook_read2_r15d_end) },
+            ],
+            [
+                unsafe { (&cannoli_addrhook_write2_eax, &cannoli_addrhook_write2_eax_end) },
+                unsafe { (&cannoli_addrhook_write2_ecx, &cannoli_addrhook_write2_ecx_end) },
+                unsafe { (&cannoli_addrhook_write2_edx, &cannoli_addrhook_write2_edx_end) },
+                unsafe { (&cannoli_addrhook_write2_ebx, &cannoli_addrhook_write2_ebx_end) },
+                unsafe { (&cannoli_addrhook_write2_esp, &cannoli_addrhook_write2_esp_end) },
+                unsafe { (&cannoli_addrhook_write2_ebp, &cannoli_addrhook_write2_ebp_end) },
+                unsafe { (&cannoli_addrhook_write2_esi, &cannoli_addrhook_write2_esi_end) },
+                unsafe { (&cannoli_addrhook_write2_edi, &cannoli_addrhook_write2_edi_end) },
+                unsafe { (&cannoli_addrhook_write2_r8d, &cannoli_addrhook_write2_r8d_end) },
+                unsafe { (&cannoli_addrhook_write2_r9d, &cannoli_addrhook_write2_r9d_end) },
+                unsafe { (&cannoli_addrhook_write2_r10d, &cannoli_addrhook_write2_r10d_end) },
+                unsafe { (&cannoli_addrhook_write2_r11d, &cannoli_addrhook_write2_r11d_end) },
+                unsafe { (&cannoli_addrhook_write2_r12d, &cannoli_addrhook_write2_r12d_end) },
+                unsafe { (&cannoli_addrhook_write2_r13d, &cannoli_addrhook_write2_r13d_end) },
+                unsafe { (&cannoli_addrhook_write2_r14d, &cannoli_addrhook_write2_r14d_end) },
+                unsafe { (&cannoli_addrhook_write2_r15d, &cannoli_addrhook_write2_r15d_end) },
+            ],
+        ],
+        [
+            [
+                unsafe { (&cannoli_addrhook_read4_eax, &cannoli_addrhook_read4_eax_end) },
+                unsafe { (&cannoli_addrhook_read4_ecx, &cannoli_addrhook_read4_ecx_end) },
+                unsafe { (&cannoli_addrhook_read4_edx, &cannoli_addrhook_read4_edx_end) },
+                unsafe { (&cannoli_addrhook_read4_ebx, &cannoli_addrhook_read4_ebx_end) },
+                unsafe { (&cannoli_addrhook_read4_esp, &cannoli_addrhook_read4_esp_end) },
+                unsafe { (&cannoli_addrhook_read4_ebp, &cannoli_addrhook_read4_ebp_end) },
+                unsafe { (&cannoli_addrhook_read4_esi, &cannoli_addrhook_read4_esi_end) },
+                unsafe { (&cannoli_addrhook_read4_edi, &cannoli_addrhook_read4_edi_end) },
+                unsafe { (&cannoli_addrhook_read4_r8d, &cannoli_addrhook_read4_r8d_end) },
+                unsafe { (&cannoli_addrhook_read4_r9d, &cannoli_addrhook_read4_r9d_end) },
+                unsafe { (&cannoli_addrhook_read4_r10d, &cannoli_addrhook_read4_r10d_end) },
+                unsafe { (&cannoli_addrhook_read4_r11d, &cannoli_addrhook_read4_r11d_end) },
+                unsafe { (&cannoli_addrhook_read4_r12d, &cannoli_addrhook_read4_r12d_end) },
+                unsafe { (&cannoli_addrhook_read4_r13d, &cannoli_addrhook_read4_r13d_end) },
+                unsafe { (&cannoli_addrhook_read4_r14d, &cannoli_addrhook_read4_r14d_end) },
+                unsafe { (&cannoli_addrhook_read4_r15d, &cannoli_addrhook_read4_r15d_end) },
+            ],
+            [
+                unsafe { (&cannoli_addrhook_write4_eax, &cannoli_addrhook_write4_eax_end) },
+                unsafe { (&cannoli_addrhook_write4_ecx, &cannoli_addrhook_write4_ecx_end) },
+                unsafe { (&cannoli_addrhook_write4_edx, &cannoli_addrhook_write4_edx_end) },
+                unsafe { (&cannoli_addrhook_write4_ebx, &cannoli_addrhook_write4_ebx_end) },
+                unsafe { (&cannoli_addrhook_write4_esp, &cannoli_addrhook_write4_esp_end) },
+                unsafe { (&cannoli_addrhook_write4_ebp, &cannoli_addrhook_write4_ebp_end) },
+                unsafe { (&cannoli_addrhook_write4_esi, &cannoli_addrhook_write4_esi_end) },
+                unsafe { (&cannoli_addrhook_write4_edi, &cannoli_addrhook_write4_edi_end) },
+                unsafe { (&cannoli_addrhook_write4_r8d, &cannoli_addrhook_write4_r8d_end) },
+                unsafe { (&cannoli_addrhook_write4_r9d, &cannoli_addrhook_write4_r9d_end) },
+                unsafe { (&cannoli_addrhook_write4_r10d, &cannoli_addrhook_write4_r10d_end) },
+                unsafe { (&cannoli_addrhook_write4_r11d, &cannoli_addrhook_write4_r11d_end) },
+                unsafe { (&cannoli_addrhook_write4_r12d, &cannoli_addrhook_write4_r12d_end) },
+                unsafe { (&cannoli_addrhook_write4_r13d, &cannoli_addrhook_write4_r13d_end) },
+                unsafe { (&cannoli_addrhook_write4_r14d, &cannoli_addrhook_write4_r14d_end) },
+                unsafe { (&cannoli_addrhook_write4_r15d, &cannoli_addrhook_write4_r15d_end) },
+            ],
+        ],
+        [
+            [
+                unsafe { (&cannoli_addrhook_read8_eax, &cannoli_addrhook_read8_eax_end) },
+                unsafe { (&cannoli_addrhook_read8_ecx, &cannoli_addrhook_read8_ecx_end) },
+                unsafe { (&cannoli_addrhook_read8_edx, &cannoli_addrhook_read8_edx_end) },
+                unsafe { (&cannoli_addrhook_read8_ebx, &cannoli_addrhook_read8_ebx_end) },
+                unsafe { (&cannoli_addrhook_read8_esp, &cannoli_addrhook_read8_esp_end) },
+                unsafe { (&cannoli_addrhook_read8_ebp, &cannoli_addrhook_read8_ebp_end) },
+                unsafe { (&cannoli_addrhook_read8_esi, &cannoli_addrhook_read8_esi_end) },
+                unsafe { (&cannoli_addrhook_read8_edi, &cannoli_addrhook_read8_edi_end) },
+                unsafe { (&cannoli_addrhook_read8_r8d, &cannoli_addrhook_read8_r8d_end) },
+                unsafe { (&cannoli_addrhook_read8_r9d, &cannoli_addrhook_read8_r9d_end) },
+                unsafe { (&cannoli_addrhook_read8_r10d, &cannoli_addrhook_read8_r10d_end) },
+                unsafe { (&cannoli_addrhook_read8_r11d, &cannoli_addrhook_read8_r11d_end) },
+                unsafe { (&cannoli_addrhook_read8_r12d, &cannoli_addrhook_read8_r12d_end) },
+                unsafe { (&cannoli_addrhook_read8_r13d, &cannoli_addrhook_read8_r13d_end) },
+                unsafe { (&cannoli_addrhook_read8_r14d, &cannoli_addrhook_read8_r14d_end) },
+                unsafe { (&cannoli_addrhook_read8_r15d, &cannoli_addrhook_read8_r15d_end) },
+            ],
+            [
+                unsafe { (&cannoli_addrhook_write8_eax, &cannoli_addrhook_write8_eax_end) },
+                unsafe { (&cannoli_addrhook_write8_ecx, &cannoli_addrhook_write8_ecx_end) },
+                unsafe { (&cannoli_addrhook_write8_edx, &cannoli_addrhook_write8_edx_end) },
+                unsafe { (&cannoli_addrhook_write8_ebx, &cannoli_addrhook_write8_ebx_end) },
+                unsafe { (&cannoli_addrhook_write8_esp, &cannoli_addrhook_write8_esp_end) },
+                unsafe { (&cannoli_addrhook_write8_ebp, &cannoli_addrhook_write8_ebp_end) },
+                unsafe { (&cannoli_addrhook_write8_esi, &cannoli_addrhook_write8_esi_end) },
+                unsafe { (&cannoli_addrhook_write8_edi, &cannoli_addrhook_write8_edi_end) },
+                unsafe { (&cannoli_addrhook_write8_r8d, &cannoli_addrhook_write8_r8d_end) },
+                unsafe { (&cannoli_addrhook_write8_r9d, &cannoli_addrhook_write8_r9d_end) },
+                unsafe { (&cannoli_addrhook_write8_r10d, &cannoli_addrhook_write8_r10d_end) },
+                unsafe { (&cannoli_addrhook_write8_r11d, &cannoli_addrhook_write8_r11d_end) },
+                unsafe { (&cannoli_addrhook_write8_r12d, &cannoli_addrhook_write8_r12d_end) },
+                unsafe { (&cannoli_addrhook_write8_r13d, &cannoli_addrhook_write8_r13d_end) },
+                unsafe { (&cannoli_addrhook_write8_r14d, &cannoli_addrhook_write8_r14d_end) },
+                unsafe { (&cannoli_addrhook_write8_r15d, &cannoli_addrhook_write8_r15d_end) },
+            ],
+        ],
+    ],
+    [
+        [
+            [
+                unsafe { (&cannoli_addrhook_read1_rax, &cannoli_addrhook_read1_rax_end) },
+                unsafe { (&cannoli_addrhook_read1_rcx, &cannoli_addrhook_read1_rcx_end) },
+                unsafe { (&cannoli_addrhook_read1_rdx, &cannoli_addrhook_read1_rdx_end) },
+                unsafe { (&cannoli_addrhook_read1_rbx, &cannoli_addrhook_read1_rbx_end) },
+                unsafe { (&cannoli_addrhook_read1_rsp, &cannoli_addrhook_read1_rsp_end) },
+                unsafe { (&cannoli_addrhook_read1_rbp, &cannoli_addrhook_read1_rbp_end) },
+                unsafe { (&cannoli_addrhook_read1_rsi, &cannoli_addrhook_read1_rsi_end) },
+                unsafe { (&cannoli_addrhook_read1_rdi, &cannoli_addrhook_read1_rdi_end) },
+                unsafe { (&cannoli_addrhook_read1_r8, &cannoli_addrhook_read1_r8_end) },
+                unsafe { (&cannoli_addrhook_read1_r9, &cannoli_addrhook_read1_r9_end) },
+                unsafe { (&cannoli_addrhook_read1_r10, &cannoli_addrhook_read1_r10_end) },
+                unsafe { (&cannoli_addrhook_read1_r11, &cannoli_addrhook_read1_r11_end) },
+                unsafe { (&cannoli_addrhook_read1_r12, &cannoli_addrhook_read1_r12_end) },
+                unsafe { (&cannoli_addrhook_read1_r13, &cannoli_addrhook_read1_r13_end) },
+                unsafe { (&cannoli_addrhook_read1_r14, &cannoli_addrhook_read1_r14_end) },
+                unsafe { (&cannoli_addrhook_read1_r15, &cannoli_addrhook_read1_r15_end) },
+            ],
+            [
+                unsafe { (&cannoli_addrhook_write1_rax, &cannoli_addrhook_write1_rax_end) },
+                unsafe { (&cannoli_addrhook_write1_rcx, &cannoli_addrhook_write1_rcx_end) },
+                unsafe { (&cannoli_addrhook_write1_rdx, &cannoli_addrhook_write1_rdx_end) },
+                unsafe { (&cannoli_addrhook_write1_rbx, &cannoli_addrhook_write1_rbx_end) },
+                unsafe { (&cannoli_addrhook_write1_rsp, &cannoli_addrhook_write1_rsp_end) },
+                unsafe { (&cannoli_addrhook_write1_rbp, &cannoli_addrhook_write1_rbp_end) },
+                unsafe { (&cannoli_addrhook_write1_rsi, &cannoli_addrhook_write1_rsi_end) },
+                unsafe { (&cannoli_addrhook_write1_rdi, &cannoli_addrhook_write1_rdi_end) },
+                unsafe { (&cannoli_addrhook_write1_r8, &cannoli_addrhook_write1_r8_end) },
+                unsafe { (&cannoli_addrhook_write1_r9, &cannoli_addrhook_write1_r9_end) },
+                unsafe { (&cannoli_addrhook_write1_r10, &cannoli_addrhook_write1_r10_end) },
+                unsafe { (&cannoli_addrhook_write1_r11, &cannoli_addrhook_write1_r11_end) },
+                unsafe { (&cannoli_addrhook_write1_r12, &cannoli_addrhook_write1_r12_end) },
+                unsafe { (&cannoli_addrhook_write1_r13, &cannoli_addrhook_write1_r13_end) },
+                unsafe { (&cannoli_addrhook_write1_r14, &cannoli_addrhook_write1_r14_end) },
+                unsafe { (&cannoli_addrhook_write1_r15, &cannoli_addrhook_write1_r15_end) },
+            ],
+        ],
+        [
+            [
+                unsafe { (&cannoli_addrhook_read2_rax, &cannoli_addrhook_read2_rax_end) },
+                unsafe { (&cannoli_addrhook_read2_rcx, &cannoli_addrhook_read2_rcx_end) },
+                unsafe { (&cannoli_addrhook_read2_rdx, &cannoli_addrhook_read2_rdx_end) },
+                unsafe { (&cannoli_addrhook_read2_rbx, &cannoli_addrhook_read2_rbx_end) },
+                unsafe { (&cannoli_addrhook_read2_rsp, &cannoli_addrhook_read2_rsp_end) },
+                unsafe { (&cannoli_addrhook_read2_rbp, &cannoli_addrhook_read2_rbp_end) },
+                unsafe { (&cannoli_addrhook_read2_rsi, &cannoli_addrhook_read2_rsi_end) },
+                unsafe { (&cannoli_addrhook_read2_rdi, &cannoli_addrhook_read2_rdi_end) },
+                unsafe { (&cannoli_addrhook_read2_r8, &cannoli_addrhook_read2_r8_end) },
+                unsafe { (&cannoli_addrhook_read2_r9, &cannoli_addrhook_read2_r9_end) },
+                unsafe { (&cannoli_addrhook_read2_r10, &cannoli_addrhook_read2_r10_end) },
+                unsafe { (&cannoli_addrhook_read2_r11, &cannoli_addrhook_read2_r11_end) },
+                unsafe { (&cannoli_addrhook_read2_r12, &cannoli_addrhook_read2_r12_end) },
+                unsafe { (&cannoli_addrhook_read2_r13, &cannoli_addrhook_read2_r13_end) },
+                unsafe { (&cannoli_addrhook_read2_r14, &cannoli_addrhook_read2_r14_end) },
+                unsafe { (&cannoli_addrhook_read2_r15, &cannoli_addrhook_read2_r15_end) },
+            ],
+            [
+                unsafe { (&cannoli_addrhook_write2_rax, &cannoli_addrhook_write2_rax_end) },
+                unsafe { (&cannoli_addrhook_write2_rcx, &cannoli_addrhook_write2_rcx_end) },
+                unsafe { (&cannoli_addrhook_write2_rdx, &cannoli_addrhook_write2_rdx_end) },
+                unsafe { (&cannoli_addrhook_write2_rbx, &cannoli_addrhook_write2_rbx_end) },
+                unsafe { (&cannoli_addrhook_write2_rsp, &cannoli_addrhook_write2_rsp_end) },
+                unsafe { (&cannoli_addrhook_write2_rbp, &cannoli_addrhook_write2_rbp_end) },
+                unsafe { (&cannoli_addrhook_write2_rsi, &cannoli_addrhook_write2_rsi_end) },
+                unsafe { (&cannoli_addrhook_write2_rdi, &cannoli_addrhook_write2_rdi_end) },
+                unsafe { (&cannoli_addrhook_write2_r8, &cannoli_addrhook_write2_r8_end) },
+                unsafe { (&cannoli_addrhook_write2_r9, &cannoli_addrhook_write2_r9_end) },
+                unsafe { (&cannoli_addrhook_write2_r10, &cannoli_addrhook_write2_r10_end) },
+                unsafe { (&cannoli_addrhook_write2_r11, &cannoli_addrhook_write2_r11_end) },
+                unsafe { (&cannoli_addrhook_write2_r12, &cannoli_addrhook_write2_r12_end) },
+                unsafe { (&cannoli_addrhook_write2_r13, &cannoli_addrhook_write2_r13_end) },
+                unsafe { (&cannoli_addrhook_write2_r14, &cannoli_addrhook_write2_r14_end) },
+                unsafe { (&cannoli_addrhook_write2_r15, &cannoli_addrhook_write2_r15_end) },
+            ],
+        ],
+        [
+            [
+                unsafe { (&cannoli_addrhook_read4_rax, &cannoli_addrhook_read4_rax_end) },
+                unsafe { (&cannoli_addrhook_read4_rcx, &cannoli_addrhook_read4_rcx_end) },
+                unsafe { (&cannoli_addrhook_read4_rdx, &cannoli_addrhook_read4_rdx_end) },
+                unsafe { (&cannoli_addrhook_read4_rbx, &cannoli_addrhook_read4_rbx_end) },
+                unsafe { (&cannoli_addrhook_read4_rsp, &cannoli_addrhook_read4_rsp_end) },
+                unsafe { (&cannoli_addrhook_read4_rbp, &cannoli_addrhook_read4_rbp_end) },
+                unsafe { (&cannoli_addrhook_read4_rsi, &cannoli_addrhook_read4_rsi_end) },
+                unsafe { (&cannoli_addrhook_read4_rdi, &cannoli_addrhook_read4_rdi_end) },
+                unsafe { (&cannoli_addrhook_read4_r8, &cannoli_addrhook_read4_r8_end) },
+                unsafe { (&cannoli_addrhook_read4_r9, &cannoli_addrhook_read4_r9_end) },
+                unsafe { (&cannoli_addrhook_read4_r10, &cannoli_addrhook_read4_r10_end) },
+                unsafe { (&cannoli_addrhook_read4_r11, &cannoli_addrhook_read4_r11_end) },
+                unsafe { (&cannoli_addrhook_read4_r12, &cannoli_addrhook_read4_r12_end) },
+                unsafe { (&cannoli_addrhook_read4_r13, &cannoli_addrhook_read4_r13_end) },
+                unsafe { (&cannoli_addrhook_read4_r14, &cannoli_addrhook_read4_r14_end) },
+                unsafe { (&cannoli_addrhook_read4_r15, &cannoli_addrhook_read4_r15_end) },
+            ],
+            [
+                unsafe { (&cannoli_addrhook_write4_rax, &cannoli_addrhook_write4_rax_end) },
+                unsafe { (&cannoli_addrhook_write4_rcx, &cannoli_addrhook_write4_rcx_end) },
+                unsafe { (&cannoli_addrhook_write4_rdx, &cannoli_addrhook_write4_rdx_end) },
+                unsafe { (&cannoli_addrhook_write4_rbx, &cannoli_addrhook_write4_rbx_end) },
+                unsafe { (&cannoli_addrhook_write4_rsp, &cannoli_addrhook_write4_rsp_end) },
+                unsafe { (&cannoli_addrhook_write4_rbp, &cannoli_addrhook_write4_rbp_end) },
+                unsafe { (&cannoli_addrhook_write4_rsi, &cannoli_addrhook_write4_rsi_end) },
+                unsafe { (&cannoli_addrhook_write4_rdi, &cannoli_addrhook_write4_rdi_end) },
+                unsafe { (&cannoli_addrhook_write4_r8, &cannoli_addrhook_write4_r8_end) },
+                unsafe { (&cannoli_addrhook_write4_r9, &cannoli_addrhook_write4_r9_end) },
+                unsafe { (&cannoli_addrhook_write4_r10, &cannoli_addrhook_write4_r10_end) },
+                unsafe { (&cannoli_addrhook_write4_r11, &cannoli_addrhook_write4_r11_end) },
+                unsafe { (&cannoli_addrhook_write4_r12, &cannoli_addrhook_write4_r12_end) },
+                unsafe { (&cannoli_addrhook_write4_r13, &cannoli_addrhook_write4_r13_end) },
+                unsafe { (&cannoli_addrhook_write4_r14, &cannoli_addrhook_write4_r14_end) },
+                unsafe { (&cannoli_addrhook_write4_r15, &cannoli_addrhook_write4_r15_end) },
+            ],
+        ],
+        [
+            [
+                unsafe { (&cannoli_addrhook_read8_rax, &cannoli_addrhook_read8_rax_end) },
+                unsafe { (&cannoli_addrhook_read8_rcx, &cannoli_addrhook_read8_rcx_end) },
+                unsafe { (&cannoli_addrhook_read8_rdx, &cannoli_addrhook_read8_rdx_end) },
+                unsafe { (&cannoli_addrhook_read8_rbx, &cannoli_addrhook_read8_rbx_end) },
+                unsafe { (&cannoli_addrhook_read8_rsp, &cannoli_addrhook_read8_rsp_end) },
+                unsafe { (&cannoli_addrhook_read8_rbp, &cannoli_addrhook_read8_rbp_end) },
+                unsafe { (&cannoli_addrhook_read8_rsi, &cannoli_addrhook_read8_rsi_end) },
+                unsafe { (&cannoli_addrhook_read8_rdi, &cannoli_addrhook_read8_rdi_end) },
+                unsafe { (&cannoli_addrhook_read8_r8, &cannoli_addrhook_read8_r8_end) },
+                unsafe { (&cannoli_addrhook_read8_r9, &cannoli_addrhook_read8_r9_end) },
+                unsafe { (&cannoli_addrhook_read8_r10, &cannoli_addrhook_read8_r10_end) },
+                unsafe { (&cannoli_addrhook_read8_r11, &cannoli_addrhook_read8_r11_end) },
+                unsafe { (&cannoli_addrhook_read8_r12, &cannoli_addrhook_read8_r12_end) },
+                unsafe { (&cannoli_addrhook_read8_r13, &cannoli_addrhook_read8_r13_end) },
+                unsafe { (&cannoli_addrhook_read8_r14, &cannoli_addrhook_read8_r14_end) },
+                unsafe { (&cannoli_addrhook_read8_r15, &cannoli_addrhook_read8_r15_end) },
+            ],
+            [
+                unsafe { (&cannoli_addrhook_write8_rax, &cannoli_addrhook_write8_rax_end) },
+                unsafe { (&cannoli_addrhook_write8_rcx, &cannoli_addrhook_write8_rcx_end) },
+                unsafe { (&cannoli_addrhook_write8_rdx, &cannoli_addrhook_write8_rdx_end) },
+                unsafe { (&cannoli_addrhook_write8_rbx, &cannoli_addrhook_write8_rbx_end) },
+                unsafe { (&cannoli_addrhook_write8_rsp, &cannoli_addrhook_write8_rsp_end) },
+                unsafe { (&cannoli_addrhook_write8_rbp, &cannoli_addrhook_write8_rbp_end) },
+                unsafe { (&cannoli_addrhook_write8_rsi, &cannoli_addrhook_write8_rsi_end) },
+                unsafe { (&cannoli_addrhook_write8_rdi, &cannoli_addrhook_write8_rdi_end) },
+                unsafe { (&cannoli_addrhook_write8_r8, &cannoli_addrhook_write8_r8_end) },
+                unsafe { (&cannoli_addrhook_write8_r9, &cannoli_addrhook_write8_r9_end) },
+                unsafe { (&cannoli_addrhook_write8_r10, &cannoli_addrhook_write8_r10_end) },
+                unsafe { (&cannoli_addrhook_write8_r11, &cannoli_addrhook_write8_r11_end) },
+                unsafe { (&cannoli_addrhook_write8_r12, &cannoli_addrhook_write8_r12_end) },
+                unsafe { (&cannoli_addrhook_write8_r13, &cannoli_addrhook_write8_r13_end) },
+                unsafe { (&cannoli_addrhook_write8_r14, &cannoli_addrhook_write8_r14_end) },
+                unsafe { (&cannoli_addrhook_write8_r15, &cannoli_addrhook_write8_r15_end) },
+            ],
+        ],
+    ],
+];
@@ -0,0 +1,322 @@
+//! Jitter side of the control channel, see [`cannoli::control`]
+//!
+//! Control messages from the client are received on a background thread per
+//! connection and applied to process-wide state, which the hooks consult when
+//! QEMU lifts code. Every thread of the target gets the same messages, so
+//! applying them is idempotent. Requests are answered on the same thread and
+//! connection, so they don't wait for the target thread, which may be
+//! stalled on a full pipe. Guest memory is read while the target runs, see
+//! [`cannoli::guest`] for what that means for consistency.
+//!
+//! Module filters are resolved to address ranges here, with the mappings of
+//! the process as the hooks report them, so deciding how to hook an
+//! instruction stays a range check. Start triggers are checked here too, as
+//! mappings are made and instructions are translated.
+//!
+//! Sampled exec hooks (see [`cannoli::control::Sample`]) count down on a
+//! counter of the process, which a timer thread of ours sets to sample the
+//! next block when sampling by time.
+
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex, OnceLock, RwLock, LazyLock};
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU64, Ordering};
+use std::time::Duration;
+use cannoli::address_space::AddressSpace;
+use cannoli::control::{ControlMessage, ControlReply, Filters, HookKind};
+use cannoli::control::{Sample, Trigger, is_module};
+use cannoli::control::MAX_READ_MEMORY;
+use crate::HookType;
+
+/// Current filters of this process with their modules resolved, `None`
+/// until the client sends some
+static FILTERS: RwLock<Option<Arc<Filters>>> = RwLock::new(None);
+
+/// What the filters are resolved from
+#[derive(Default)]
+struct State {
+    /// Filters as the client sent them
+    filters: Option<Filters>,
+
+    /// Mappings of this process, to resolve modules with
+    space: AddressSpace,
+}
+
+/// Filters as the client sent them and the mappings of this process
+static STATE: LazyLock<Mutex<State>> = LazyLock::new(Default::default);
+
+/// Set while the start trigger of the filters hasn't fired, nothing is
+/// hooked until it does
+static WAITING: AtomicBool = AtomicBool::new(false);
+
+/// Address of the instruction of an exec trigger, `u64::MAX` while it
+/// isn't known
+static TRIGGER_PC: AtomicU64 = AtomicU64::new(u64::MAX);
+
+/// Resolve the modules of the filters the client sent, and the address of
+/// their exec trigger, with the current mappings
+fn resolve(state: &State) {
+    let Some(filters) = &state.filters else { return };
+    *FILTERS.write().unwrap() = Some(Arc::new(
+        filters.resolve(state.space.mappings())));
+
+    let pc = match &filters.start {
+        Some(Trigger::Exec { module: None, offset }) => Some(*offset),
+        Some(Trigger::Exec { module: Some(name), offset }) => {
+            state.space.mappings().find(|x| {
+                !x.anon && x.offset == 0 && is_module(&x.path, name)
+            }).map(|x| x.base.wrapping_add(*offset))
+        }
+        _ => None,
+    };
+    TRIGGER_PC.store(pc.unwrap_or(u64::MAX), Ordering::Release);
+}
+
+/// Blocks left until the next sample of sampled exec hooks, see
+/// [`sample_counter`]
+static SAMPLE_COUNTER: AtomicU64 = AtomicU64::new(1);
+
+/// PID of the process the sampling timer runs in, threads don't survive
+/// `fork()`
+static SAMPLE_TIMER: AtomicI32 = AtomicI32::new(0);
+
+/// Get the address of the counter sampled exec hooks count down on, it
+/// has to stay at least 1 for them to sample again
+pub(crate) fn sample_counter() -> *mut u64 {
+    SAMPLE_COUNTER.as_ptr()
+}
+
+/// Start the timer which samples the next block every so often when
+/// sampling by time, unless it's already running in this process
+fn start_sample_timer() {
+    let pid = std::process::id() as i32;
+    if SAMPLE_TIMER.swap(pid, Ordering::AcqRel) == pid {
+        return;
+    }
+    std::thread::spawn(|| loop {
+        let interval = match filters().sample {
+            Some(Sample::Time { micros }) => {
+                SAMPLE_COUNTER.store(1, Ordering::Relaxed);
+                Duration::from_micros(micros.max(1))
+            }
+            _ => Duration::from_millis(100),
+        };
+        std::thread::sleep(interval);
+    });
+}
+
+/// Apply filters sent by the client
+fn set_filters(filters: Filters) {
+    let mut state = STATE.lock().unwrap();
+    let armed = state.filters.as_ref().and_then(|x| x.start.as_ref());
+    if armed != filters.start.as_ref() {
+        WAITING.store(filters.start.is_some(), Ordering::Release);
+    }
+    SAMPLE_COUNTER.store(1, Ordering::Relaxed);
+    if let Some(Sample::Time { .. }) = filters.sample {
+        start_sample_timer();
+    }
+    state.filters = Some(filters);
+    resolve(&state);
+}
+
+/// Check if the filters need the mappings of the process resolved again
+fn needs_resolve(state: &State) -> bool {
+    state.filters.as_ref().is_some_and(|x| !x.modules.is_empty()) ||
+        WAITING.load(Ordering::Acquire)
+}
+
+/// Track a new mapping of the process, for module filters and triggers
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn mapped(base: u64, len: u64, anon: bool, read: bool,
+        write: bool, exec: bool, path: &str, offset: u64) {
+    let mut state = STATE.lock().unwrap();
+    state.space.mmap(base, len, anon, read, write, exec, path, offset);
+    if let Some(Trigger::Mmap { module }) =
+            state.filters.as_ref().and_then(|x| x.start.as_ref()) {
+        if !anon && is_module(path, module) {
+            WAITING.store(false, Ordering::Release);
+        }
+    }
+    if needs_resolve(&state) {
+        resolve(&state);
+    }
+}
+
+/// Track an unmapping in the process, for module filters and triggers
+pub(crate) fn unmapped(base: u64, len: u64) {
+    let mut state = STATE.lock().unwrap();
+    state.space.munmap(base, len);
+    if needs_resolve(&state) {
+        resolve(&state);
+    }
+}
+
+/// Check if `pc` is in dynamic code, memory mapped anonymous and executable
+pub(crate) fn is_dynamic_code(pc: u64) -> bool {
+    STATE.lock().unwrap().space.is_dynamic_code(pc)
+}
+
+/// Get the path of the main binary of the process, the first file mapped
+/// executable
+pub(crate) fn main_module() -> Option<Arc<str>> {
+    STATE.lock().unwrap().space.main_module().cloned()
+}
+
+/// Latest epoch requested by the client, 0 before the first
+static EPOCH: AtomicU64 = AtomicU64::new(0);
+
+/// Get the latest epoch requested by the client. Target threads mark it in
+/// their trace when they exit translated code, see
+/// [`cannoli::control::epoch`]
+pub(crate) fn epoch() -> u64 {
+    EPOCH.load(Ordering::Acquire)
+}
+
+/// Get the current filters, which hook everything until the client sets
+/// them
+pub fn filters() -> Arc<Filters> {
+    FILTERS.read().unwrap().clone().unwrap_or_default()
+}
+
+/// Decide how to hook the instruction at `pc` based on the current filters.
+/// This is a ready-made `hook_inst()` for jitters which don't need anything
+/// more specific
+pub fn hook_inst(pc: u64, _branch: bool) -> HookType {
+    // The exec trigger fires when its instruction is translated, and that
+    // instruction is the first one hooked
+    if WAITING.load(Ordering::Acquire) {
+        if pc != TRIGGER_PC.load(Ordering::Acquire) {
+            return HookType::Never;
+        }
+        WAITING.store(false, Ordering::Release);
+    }
+
+    match filters().hook_inst(pc) {
+        Some(HookKind::Once)     => HookType::Once,
+        Some(HookKind::Always)   => HookType::Always,
+        Some(HookKind::Register) => HookType::Register,
+        Some(HookKind::Branch)   => HookType::Branch,
+        Some(HookKind::Edge)     => HookType::Edge,
+        Some(HookKind::Function) => HookType::Function,
+        None                     => HookType::Never,
+    }
+}
+
+/// Decide if a memory access by the instruction at `pc` is hooked based on
+/// the current filters. This is a ready-made `hook_mem()` for jitters which
+/// don't need anything more specific
+pub fn hook_mem(pc: u64, write: bool, _size: usize) -> bool {
+    !WAITING.load(Ordering::Acquire) && filters().hook_mem(pc, write)
+}
+
+/// Decide if the operands of a comparison at `pc` are reported based on the
+/// current filters
+pub fn hook_cmp(pc: u64) -> bool {
+    !WAITING.load(Ordering::Acquire) && filters().hook_cmp(pc)
+}
+
+/// Decide if signals delivered to the guest are reported, they are once
+/// the start trigger fired
+pub fn hook_signal() -> bool {
+    !WAITING.load(Ordering::Acquire)
+}
+
+/// Check if the values of memory accesses of a kind are logged, or only
+/// their addresses
+pub fn mem_values(write: bool) -> bool {
+    filters().mem_values(write)
+}
+
+/// Get QEMU's `guest_base`, the offset of guest addresses in our address
+/// space. QEMU exports it as it's linked with `-rdynamic` (see
+/// `qemu-rs/build.rs`), `None` if it doesn't, as guest addresses can't be
+/// translated then
+fn guest_base() -> Option<u64> {
+    static GUEST_BASE: OnceLock<Option<u64>> = OnceLock::new();
+    *GUEST_BASE.get_or_init(|| unsafe {
+        let sym = libc::dlsym(libc::RTLD_DEFAULT, c"guest_base".as_ptr());
+        if sym.is_null() {
+            eprintln!("Cannoli: QEMU doesn't export `guest_base`, guest \
+                memory can't be read");
+            None
+        } else {
+            Some(*(sym as *const usize) as u64)
+        }
+    })
+}
+
+/// Read `len` bytes of guest memory at `addr`, `None` if any of it isn't
+/// mapped readable or if QEMU's `guest_base` isn't known. This goes through
+/// `process_vm_readv()` on ourselves, which fails rather than faulting on
+/// bad addresses
+pub(crate) fn read_memory(addr: u64, len: u32) -> Option<Vec<u8>> {
+    if len > MAX_READ_MEMORY {
+        return None;
+    }
+    let base = guest_base()?;
+
+    let mut data = vec![0u8; len as usize];
+    let local = libc::iovec {
+        iov_base: data.as_mut_ptr() as *mut libc::c_void,
+        iov_len:  data.len(),
+    };
+    let remote = libc::iovec {
+        iov_base: base.wrapping_add(addr) as usize as *mut libc::c_void,
+        iov_len:  data.len(),
+    };
+    let read = unsafe {
+        libc::process_vm_readv(libc::getpid(), &local, 1, &remote, 1, 0)
+    };
+    (read == data.len() as isize).then_some(data)
+}
+
+/// Write `data` to guest memory at `addr`, like [`read_memory`] reads it.
+/// Returns if all of it was written
+pub(crate) fn write_memory(addr: u64, data: &[u8]) -> bool {
+    let Some(base) = guest_base() else { return false };
+
+    let local = libc::iovec {
+        iov_base: data.as_ptr() as *mut libc::c_void,
+        iov_len:  data.len(),
+    };
+    let remote = libc::iovec {
+        iov_base: base.wrapping_add(addr) as usize as *mut libc::c_void,
+        iov_len:  data.len(),
+    };
+    let written = unsafe {
+        libc::process_vm_writev(libc::getpid(), &local, 1, &remote, 1, 0)
+    };
+    written == data.len() as isize
+}
+
+/// Receive control messages from `stream` until the connection is closed
+pub(crate) fn receive(stream: TcpStream) {
+    while let Ok(msg) = ControlMessage::read_from(&stream) {
+        match msg {
+            ControlMessage::SetFilters(filters) => set_filters(filters),
+            ControlMessage::Ping { id } => {
+                let reply = ControlReply::Pong { id };
+                if reply.write_to(&stream).is_err() {
+                    break;
+                }
+            }
+            ControlMessage::Epoch { id } => {
+                EPOCH.fetch_max(id, Ordering::AcqRel);
+            }
+            ControlMessage::ReadMemory { id, addr, len } => {
+                let reply = ControlReply::Memory {
+                    id, data: read_memory(addr, len) };
+                if reply.write_to(&stream).is_err() {
+                    break;
+                }
+            }
+            ControlMessage::GuestBase { id } => {
+                let reply = ControlReply::GuestBase {
+                    id, base: guest_base() };
+                if reply.write_to(&stream).is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
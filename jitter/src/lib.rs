@@ -6,6 +6,7 @@
 
 mod cannoli_memops;
 mod cannoli_internals;
+pub mod control;
 
 // Re-export `HookType`
 pub use cannoli_internals::HookType;
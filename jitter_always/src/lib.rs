@@ -5,10 +5,13 @@ use jitter::HookType;
 /// The `HookType` dictates the type of hook used for the instruction, and may
 /// be `Never`, `Always`, and `Once`
 ///
+/// Everything is hooked unless the client pushed filters over the control
+/// channel, see `cannoli::control`
+///
 /// This may be called from multiple threads
 #[no_mangle]
-fn hook_inst(_pc: u64, _branch: bool) -> HookType {
-    HookType::Always
+fn hook_inst(pc: u64, branch: bool) -> HookType {
+    jitter::control::hook_inst(pc, branch)
 }
 
 /// Called when a memory access is being lifted in QEMU. Returning `true` will
@@ -16,7 +19,6 @@ fn hook_inst(_pc: u64, _branch: bool) -> HookType {
 ///
 /// This may be called from multiple threads
 #[no_mangle]
-fn hook_mem(_pc: u64, _write: bool, _size: usize) -> bool {
-    true
+fn hook_mem(pc: u64, write: bool, size: usize) -> bool {
+    jitter::control::hook_mem(pc, write, size)
 }
-
@@ -551,6 +551,17 @@ impl<const CHUNK_SIZE: usize, const NUM_BUFFERS: usize>
         Ticket(self.seq.fetch_add(1, Ordering::Relaxed))
     }
 
+    /// Get the number of buffers which are filled and waiting to be received.
+    /// Once all `NUM_BUFFERS` are, the sender blocks until one is received
+    pub fn pending(&self) -> usize {
+        // Get the pipe
+        let pipe = unsafe { &*self.mem_pipe };
+
+        pipe.client_owned.iter()
+            .filter(|x| x.load(Ordering::Relaxed))
+            .count()
+    }
+
     /// Attempt to receive data from the pipe, invoking the closure only if
     /// data was ready
     ///
@@ -725,6 +725,10 @@ fn main() {
     cannoli_path.pop();
     configure_args.push(format!("--with-cannoli={}", cannoli_path.to_str().unwrap()));
 
+    // Export QEMU's symbols to the jitter, which looks up `guest_base` to
+    // translate guest addresses when it reads guest memory
+    configure_args.push("--extra-ldflags=-rdynamic".to_string());
+
     let configure_prog = qemu_repo_path
         .join("configure")
         .canonicalize()
@@ -0,0 +1,141 @@
+//! Cached extraction of the QEMU binaries
+//!
+//! Running a binary from memory with `memfd-exec` writes the whole binary to a
+//! new memfd every run, which adds up for harnesses executing thousands of
+//! short runs. [`extract`] writes a binary to the user's cache directory once,
+//! under a name including a hash of its contents, and returns the path to
+//! execute instead. Later runs, in this process or any other, reuse the file as
+//! long as the binary is the same, and the kernel shares its pages between all
+//! of them.
+//!
+//! The cache directory is `$XDG_CACHE_HOME/qemu-rs`, or `~/.cache/qemu-rs` if
+//! `XDG_CACHE_HOME` isn't set. Binaries of older builds are left behind and can
+//! be removed at any time, as long as no run is starting from them.
+//!
+//! ```ignore
+//! let path = qemu::extract("qemu-x86_64", &qemu::qemu_x86_64())?;
+//! std::process::Command::new(path).args(args).status()?;
+//! ```
+
+use std::collections::HashMap;
+use std::env::var_os;
+use std::fs::{
+    create_dir_all, metadata, remove_file, rename, set_permissions,
+    OpenOptions, Permissions,
+};
+use std::io::{Error, ErrorKind, Result, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// Paths extracted by this process, keyed by name and hash, so the cache
+/// directory is only checked once
+static EXTRACTED: Mutex<Option<HashMap<(String, u64), PathBuf>>> =
+    Mutex::new(None);
+
+/// Extractions started by this process, to name their temporary files
+static STARTED: AtomicU64 = AtomicU64::new(0);
+
+/// Get the directory extracted binaries are cached in
+pub fn cache_dir() -> Result<PathBuf> {
+    let base = match (var_os("XDG_CACHE_HOME"), var_os("HOME")) {
+        (Some(cache), _) if !cache.is_empty() => PathBuf::from(cache),
+        (_, Some(home)) => PathBuf::from(home).join(".cache"),
+        _ => {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                "Neither XDG_CACHE_HOME nor HOME is set",
+            ))
+        }
+    };
+    Ok(base.join("qemu-rs"))
+}
+
+/// 64-bit FNV-1a hash of `bytes`, a word at a time, which is stable across
+/// builds and platforms
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let words = bytes.chunks(8).map(|x| {
+        let mut word = [0u8; 8];
+        word[..x.len()].copy_from_slice(x);
+        u64::from_le_bytes(word)
+    });
+    words.fold(0xcbf29ce484222325 ^ bytes.len() as u64, |hash, word| {
+        (hash ^ word).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Write `program` to the cache directory as an executable named after `name`
+/// and the hash of `program`, unless it's there already, and return its path.
+/// See the module documentation
+pub fn extract(name: &str, program: &[u8]) -> Result<PathBuf> {
+    let key = (name.to_string(), fnv1a(program));
+    if let Some(path) = EXTRACTED
+        .lock()
+        .unwrap()
+        .get_or_insert_with(HashMap::new)
+        .get(&key)
+    {
+        return Ok(path.clone());
+    }
+
+    let dir = cache_dir()?;
+    let path = dir.join(format!("{}-{:016x}", name, key.1));
+
+    // A file of the wrong size is left over from an extraction which didn't
+    // finish
+    let cached = metadata(&path)
+        .is_ok_and(|x| x.is_file() && x.len() == program.len() as u64);
+    if !cached {
+        // Write under a new name of our own and move it in place, so concurrent
+        // runs, in this process or any other, never see (or execute) a partial
+        // file. The file is created exclusively, which never follows symlinks
+        create_dir_all(&dir)?;
+        let tmp = dir.join(format!(
+            ".{}-{:016x}.{}.{}",
+            name,
+            key.1,
+            std::process::id(),
+            STARTED.fetch_add(1, Ordering::Relaxed)
+        ));
+        let written = (|| {
+            let mut file = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(0o700)
+                .open(&tmp)?;
+            file.write_all(program)?;
+            drop(file);
+            set_permissions(&tmp, Permissions::from_mode(0o755))?;
+            rename(&tmp, &path)
+        })();
+        if written.is_err() {
+            let _ = remove_file(&tmp);
+        }
+        written?;
+    }
+
+    EXTRACTED
+        .lock()
+        .unwrap()
+        .get_or_insert_with(HashMap::new)
+        .insert(key, path.clone());
+    Ok(path)
+}
+
+#[test]
+fn extract_reuses() {
+    let dir = std::env::temp_dir()
+        .join(format!("qemu_rs_cache_{}", std::process::id()));
+    std::env::set_var("XDG_CACHE_HOME", &dir);
+
+    let path = extract("qemu-test", b"\x7fELF one").unwrap();
+    assert!(path.starts_with(dir.join("qemu-rs")));
+    assert_eq!(metadata(&path).unwrap().permissions().mode() & 0o777, 0o755);
+    assert_eq!(extract("qemu-test", b"\x7fELF one").unwrap(), path);
+    let other = extract("qemu-test", b"\x7fELF two").unwrap();
+    assert_ne!(other, path);
+    assert_eq!(std::fs::read(other).unwrap(), b"\x7fELF two");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
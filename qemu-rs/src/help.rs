@@ -0,0 +1,262 @@
+//! Listing the machines and devices of the system-mode emulators
+//!
+//! Launching a long job only to have QEMU reject the `-machine` or `-device`
+//! it was given is a waste of time. [`machines`] and [`devices`] run the
+//! bundled `qemu-system-<arch>` binary with `-machine help` and `-device help`
+//! and parse the output, so harnesses can validate their configuration up
+//! front. Only the architectures whose `qemu-system-<arch>` feature is enabled
+//! are available. The binary is run from the cache of [`crate::extract`].
+
+use std::io::{Error, ErrorKind, Result};
+use std::process::Command;
+
+/// A machine supported by a system-mode emulator, from `-machine help`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Machine {
+    /// Name of the machine, as passed to `-machine`
+    pub name: String,
+
+    /// Description of the machine
+    pub description: String,
+
+    /// Name of the machine this one is an alias of
+    pub alias_of: Option<String>,
+
+    /// Set if this is the machine used when `-machine` isn't given
+    pub default: bool,
+
+    /// Set if the machine is deprecated
+    pub deprecated: bool,
+}
+
+/// A device supported by a system-mode emulator, from `-device help`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Device {
+    /// Name of the device, as passed to `-device`
+    pub name: String,
+
+    /// Category the device is listed under, eg. `Network devices`
+    pub category: String,
+
+    /// Bus the device plugs into
+    pub bus: Option<String>,
+
+    /// Alternative name of the device
+    pub alias: Option<String>,
+
+    /// Description of the device
+    pub description: Option<String>,
+
+    /// Set if the device can't be created with `-device`
+    pub no_user: bool,
+}
+
+/// Get the `qemu-system-<arch>` binary, if its feature is enabled
+fn qemu_system(arch: &str) -> Option<Vec<u8>> {
+    match arch {
+        #[cfg(feature = "qemu-system-aarch64")]
+        "aarch64" => Some(crate::qemu_system_aarch64()),
+        #[cfg(feature = "qemu-system-alpha")]
+        "alpha" => Some(crate::qemu_system_alpha()),
+        #[cfg(feature = "qemu-system-arm")]
+        "arm" => Some(crate::qemu_system_arm()),
+        #[cfg(feature = "qemu-system-avr")]
+        "avr" => Some(crate::qemu_system_avr()),
+        #[cfg(feature = "qemu-system-cris")]
+        "cris" => Some(crate::qemu_system_cris()),
+        #[cfg(feature = "qemu-system-hppa")]
+        "hppa" => Some(crate::qemu_system_hppa()),
+        #[cfg(feature = "qemu-system-i386")]
+        "i386" => Some(crate::qemu_system_i386()),
+        #[cfg(feature = "qemu-system-loongarch64")]
+        "loongarch64" => Some(crate::qemu_system_loongarch64()),
+        #[cfg(feature = "qemu-system-m68k")]
+        "m68k" => Some(crate::qemu_system_m68k()),
+        #[cfg(feature = "qemu-system-microblazeel")]
+        "microblazeel" => Some(crate::qemu_system_microblazeel()),
+        #[cfg(feature = "qemu-system-microblaze")]
+        "microblaze" => Some(crate::qemu_system_microblaze()),
+        #[cfg(feature = "qemu-system-mips64el")]
+        "mips64el" => Some(crate::qemu_system_mips64el()),
+        #[cfg(feature = "qemu-system-mips64")]
+        "mips64" => Some(crate::qemu_system_mips64()),
+        #[cfg(feature = "qemu-system-mipsel")]
+        "mipsel" => Some(crate::qemu_system_mipsel()),
+        #[cfg(feature = "qemu-system-mips")]
+        "mips" => Some(crate::qemu_system_mips()),
+        #[cfg(feature = "qemu-system-nios2")]
+        "nios2" => Some(crate::qemu_system_nios2()),
+        #[cfg(feature = "qemu-system-or1k")]
+        "or1k" => Some(crate::qemu_system_or1k()),
+        #[cfg(feature = "qemu-system-ppc64")]
+        "ppc64" => Some(crate::qemu_system_ppc64()),
+        #[cfg(feature = "qemu-system-ppc")]
+        "ppc" => Some(crate::qemu_system_ppc()),
+        #[cfg(feature = "qemu-system-riscv32")]
+        "riscv32" => Some(crate::qemu_system_riscv32()),
+        #[cfg(feature = "qemu-system-riscv64")]
+        "riscv64" => Some(crate::qemu_system_riscv64()),
+        #[cfg(feature = "qemu-system-rx")]
+        "rx" => Some(crate::qemu_system_rx()),
+        #[cfg(feature = "qemu-system-s390x")]
+        "s390x" => Some(crate::qemu_system_s390x()),
+        #[cfg(feature = "qemu-system-sh4eb")]
+        "sh4eb" => Some(crate::qemu_system_sh4eb()),
+        #[cfg(feature = "qemu-system-sh4")]
+        "sh4" => Some(crate::qemu_system_sh4()),
+        #[cfg(feature = "qemu-system-sparc64")]
+        "sparc64" => Some(crate::qemu_system_sparc64()),
+        #[cfg(feature = "qemu-system-sparc")]
+        "sparc" => Some(crate::qemu_system_sparc()),
+        #[cfg(feature = "qemu-system-tricore")]
+        "tricore" => Some(crate::qemu_system_tricore()),
+        #[cfg(feature = "qemu-system-x86_64")]
+        "x86_64" => Some(crate::qemu_system_x86_64()),
+        #[cfg(feature = "qemu-system-xtensaeb")]
+        "xtensaeb" => Some(crate::qemu_system_xtensaeb()),
+        #[cfg(feature = "qemu-system-xtensa")]
+        "xtensa" => Some(crate::qemu_system_xtensa()),
+        _ => None,
+    }
+}
+
+/// Run `qemu-system-<arch>` with `args` and return its standard output
+fn run(arch: &str, args: &[&str]) -> Result<String> {
+    let program = qemu_system(arch).ok_or_else(|| {
+        Error::new(
+            ErrorKind::NotFound,
+            format!("The qemu-system-{arch} feature is not enabled"),
+        )
+    })?;
+
+    let path = crate::cache::extract(&format!("qemu-system-{arch}"), &program)?;
+    let output = Command::new(&path).args(args).output()?;
+
+    if !output.status.success() {
+        return Err(Error::other(format!(
+            "qemu-system-{arch} {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Get the machines supported by `qemu-system-<arch>`, eg. `machines("arm")`
+pub fn machines(arch: &str) -> Result<Vec<Machine>> {
+    Ok(parse_machines(&run(arch, &["-machine", "help"])?))
+}
+
+/// Get the devices supported by `qemu-system-<arch>`, eg. `devices("arm")`
+pub fn devices(arch: &str) -> Result<Vec<Device>> {
+    Ok(parse_devices(&run(arch, &["-device", "help"])?))
+}
+
+/// Parse the output of `-machine help`
+pub fn parse_machines(text: &str) -> Vec<Machine> {
+    let mut ret = Vec::new();
+    for line in text.lines().filter(|x| !x.ends_with(':')) {
+        let Some((name, description)) =
+            line.trim().split_once(char::is_whitespace)
+        else {
+            continue;
+        };
+
+        // Flags are appended to the description in parentheses
+        let mut description = description.trim();
+        let mut machine = Machine {
+            name: name.to_string(),
+            description: String::new(),
+            alias_of: None,
+            default: false,
+            deprecated: false,
+        };
+        loop {
+            if let Some(rest) = description.strip_suffix(" (default)") {
+                machine.default = true;
+                description = rest;
+            } else if let Some(rest) =
+                description.strip_suffix(" (deprecated)")
+            {
+                machine.deprecated = true;
+                description = rest;
+            } else if let Some((rest, alias)) = description
+                .strip_suffix(')')
+                .and_then(|x| x.rsplit_once(" (alias of "))
+            {
+                machine.alias_of = Some(alias.to_string());
+                description = rest;
+            } else {
+                break;
+            }
+        }
+
+        machine.description = description.to_string();
+        ret.push(machine);
+    }
+    ret
+}
+
+/// Parse the `key "value", key "value", flag` fields of a line of
+/// `-device help`
+fn fields(line: &str) -> Vec<(&str, Option<&str>)> {
+    let mut ret = Vec::new();
+    let mut rest = line.trim();
+    while !rest.is_empty() {
+        let key_end = rest.find([' ', ',']).unwrap_or(rest.len());
+        let key = &rest[..key_end];
+        rest = rest[key_end..].trim_start();
+
+        // Values are quoted, except for the bus
+        let mut value = None;
+        if let Some(quoted) = rest.strip_prefix('"') {
+            let end = quoted.find('"').unwrap_or(quoted.len());
+            value = Some(&quoted[..end]);
+            rest = quoted.get(end + 1..).unwrap_or("").trim_start();
+        } else if !rest.is_empty() && !rest.starts_with(',') {
+            let end = rest.find(',').unwrap_or(rest.len());
+            value = Some(rest[..end].trim_end());
+            rest = &rest[end..];
+        }
+        ret.push((key, value));
+        rest = rest.trim_start_matches(',').trim_start();
+    }
+    ret
+}
+
+/// Parse the output of `-device help`
+pub fn parse_devices(text: &str) -> Vec<Device> {
+    let mut ret = Vec::new();
+    let mut category = String::new();
+    for line in text.lines().map(str::trim).filter(|x| !x.is_empty()) {
+        if let Some(x) = line.strip_suffix(':') {
+            category = x.to_string();
+            continue;
+        }
+
+        let mut device = Device {
+            name: String::new(),
+            category: category.clone(),
+            bus: None,
+            alias: None,
+            description: None,
+            no_user: false,
+        };
+        for (key, value) in fields(line) {
+            let value = value.map(str::to_string);
+            match key {
+                "name" => device.name = value.unwrap_or_default(),
+                "bus" => device.bus = value,
+                "alias" => device.alias = value,
+                "desc" => device.description = value,
+                "no-user" => device.no_user = true,
+                _ => {}
+            }
+        }
+
+        if !device.name.is_empty() {
+            ret.push(device);
+        }
+    }
+    ret
+}
@@ -25,6 +25,17 @@
 //! write it to disk and run it, or you can be very efficient and use something like
 //! [memfd-exec](https://crates.io/crates/memfd-exec) to run it from memory directly, or on
 //! a separate thread, whatever!
+//!
+//! The machines and devices of the system-mode emulators can be listed with [`machines`] and
+//! [`devices`], to check a configuration before launching a long job.
+//!
+//! Harnesses which start many short runs can [`extract`] the binary to a file in the
+//! cache directory once and execute that, instead of materializing it every run.
+
+mod cache;
+mod help;
+pub use cache::{cache_dir, extract};
+pub use help::{devices, machines, parse_devices, parse_machines, Device, Machine};
 
 #[cfg(feature = "qemu-system-aarch64")]
 /// Returns the qemu-system-aarch64 binary